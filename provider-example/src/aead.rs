@@ -83,12 +83,22 @@ impl cipher::MessageEncrypter for Tls13Cipher {
         m: cipher::BorrowedPlainMessage,
         seq: u64,
     ) -> Result<cipher::OpaqueMessage, rustls::Error> {
-        let total_len = m.payload.len() + 1 + CHACHAPOLY1305_OVERHEAD;
+        self.encrypt_padded(m, seq, 0)
+    }
+
+    fn encrypt_padded(
+        &self,
+        m: cipher::BorrowedPlainMessage,
+        seq: u64,
+        padding: usize,
+    ) -> Result<cipher::OpaqueMessage, rustls::Error> {
+        let total_len = m.payload.len() + 1 + padding + CHACHAPOLY1305_OVERHEAD;
 
         // construct a TLSInnerPlaintext
         let mut payload = Vec::with_capacity(total_len);
         payload.extend_from_slice(m.payload);
         payload.push(m.typ.get_u8());
+        payload.resize(payload.len() + padding, 0);
 
         let nonce = chacha20poly1305::Nonce::from(cipher::Nonce::new(&self.1, seq).0);
         let aad = cipher::make_tls13_aad(total_len);
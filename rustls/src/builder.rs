@@ -1,4 +1,5 @@
 use crate::crypto::{CryptoProvider, SupportedKxGroup};
+use crate::enums::CipherSuite;
 use crate::error::Error;
 use crate::suites::SupportedCipherSuite;
 use crate::versions;
@@ -78,6 +79,9 @@ use core::marker::PhantomData;
 /// - [`ConfigBuilder::with_client_auth_cert`] - to always send a specific certificate
 /// - [`ConfigBuilder::with_client_cert_resolver`] - to send a certificate chosen dynamically
 ///
+/// Before then, [`ConfigBuilder::with_alpn_protocols`] may set the ALPN protocols to offer,
+/// and [`ConfigBuilder::enable_early_data`] may enable TLS1.3 early data.
+///
 /// For example:
 ///
 /// ```
@@ -222,6 +226,13 @@ impl<S: ConfigSide> ConfigBuilder<S, WantsCipherSuites> {
         self,
         cipher_suites: &[SupportedCipherSuite],
     ) -> ConfigBuilder<S, WantsKxGroups> {
+        for scs in cipher_suites {
+            #[allow(irrefutable_let_patterns)]
+            if let SupportedCipherSuite::Tls13(suite) = scs {
+                debug_assert_eq!(suite.validate(), Ok(()));
+            }
+        }
+
         ConfigBuilder {
             state: WantsKxGroups {
                 cipher_suites: cipher_suites.to_vec(),
@@ -241,6 +252,35 @@ impl<S: ConfigSide> ConfigBuilder<S, WantsCipherSuites> {
         let default = self.state.0.default_cipher_suites();
         self.with_cipher_suites(default)
     }
+
+    /// Choose the default set of cipher suites as specified by the `CryptoProvider`,
+    /// less any whose IANA codepoint appears in `denied`.
+    ///
+    /// This suits security guidance published as a list of suites to disable.  Codepoints
+    /// in `denied` which are not in the default set are ignored.
+    ///
+    /// Returns an error if no cipher suites would remain.
+    pub fn with_safe_default_cipher_suites_except(
+        self,
+        denied: &[CipherSuite],
+    ) -> Result<ConfigBuilder<S, WantsKxGroups>, Error> {
+        let cipher_suites: Vec<_> = self
+            .state
+            .0
+            .default_cipher_suites()
+            .iter()
+            .filter(|scs| !denied.contains(&scs.suite()))
+            .copied()
+            .collect();
+
+        if cipher_suites.is_empty() {
+            return Err(Error::General(
+                "all default cipher suites were denied".into(),
+            ));
+        }
+
+        Ok(self.with_cipher_suites(&cipher_suites))
+    }
 }
 
 /// Config builder state where the caller must supply key exchange groups.
@@ -1,11 +1,16 @@
 use crate::builder::{ConfigBuilder, WantsVerifier};
 use crate::client::handy;
 use crate::client::{ClientConfig, ResolvesClientCert};
+use crate::common_state::PrematureDataPolicy;
 use crate::crypto::{CryptoProvider, SupportedKxGroup};
+use crate::enums::ProtocolVersion;
+use crate::error::Error;
 use crate::key_log::NoKeyLog;
+use crate::record_layer::{AeadLimitPolicy, RecordPaddingPolicy};
+use crate::sign::SigningRetryPolicy;
 use crate::suites::SupportedCipherSuite;
 #[cfg(feature = "ring")]
-use crate::{error::Error, webpki};
+use crate::webpki;
 use crate::{verify, versions};
 
 use super::client_conn::Resumption;
@@ -14,7 +19,6 @@ use super::client_conn::Resumption;
 use pki_types::{CertificateDer, PrivateKeyDer};
 
 use alloc::sync::Arc;
-#[cfg(any(feature = "dangerous_configuration", feature = "ring"))]
 use core::marker::PhantomData;
 
 impl ConfigBuilder<ClientConfig, WantsVerifier> {
@@ -31,6 +35,38 @@ impl ConfigBuilder<ClientConfig, WantsVerifier> {
                 provider: self.state.provider,
                 versions: self.state.versions,
                 verifier: Arc::new(webpki::WebPkiServerVerifier::new(root_store)),
+                raw_public_keys: false,
+                alpn_protocols: None,
+                enable_early_data: false,
+                base: None,
+            },
+            side: PhantomData,
+        }
+    }
+
+    /// Require the server to identify itself with a raw public key ([RFC 7250]),
+    /// rather than a certificate chain.
+    ///
+    /// The client then offers only the `RawPublicKey` server certificate type,
+    /// and only TLS1.3.  `verifier` is given the server's public key through
+    /// [`verify::ServerCertVerifier::verify_raw_public_key`].
+    ///
+    /// [RFC 7250]: https://www.rfc-editor.org/rfc/rfc7250
+    pub fn with_raw_public_key_verifier(
+        self,
+        verifier: Arc<dyn verify::ServerCertVerifier>,
+    ) -> ConfigBuilder<ClientConfig, WantsClientCert> {
+        ConfigBuilder {
+            state: WantsClientCert {
+                cipher_suites: self.state.cipher_suites,
+                kx_groups: self.state.kx_groups,
+                provider: self.state.provider,
+                versions: self.state.versions,
+                verifier,
+                raw_public_keys: true,
+                alpn_protocols: None,
+                enable_early_data: false,
+                base: None,
             },
             side: PhantomData,
         }
@@ -71,6 +107,10 @@ pub(super) mod danger {
                     provider: self.cfg.state.provider,
                     versions: self.cfg.state.versions,
                     verifier,
+                    raw_public_keys: false,
+                    alpn_protocols: None,
+                    enable_early_data: false,
+                    base: None,
                 },
                 side: PhantomData,
             }
@@ -89,9 +129,75 @@ pub struct WantsClientCert {
     provider: &'static dyn CryptoProvider,
     versions: versions::EnabledVersions,
     verifier: Arc<dyn verify::ServerCertVerifier>,
+    raw_public_keys: bool,
+    alpn_protocols: Option<Vec<Vec<u8>>>,
+    enable_early_data: bool,
+    base: Option<Box<ClientConfig>>,
+}
+
+impl ConfigBuilder<ClientConfig, WantsClientCert> {
+    /// Start a builder from the existing `config`.
+    ///
+    /// This keeps the protocol primitives and certificate verifier of `config`.
+    /// Once the client certificate is chosen, the resulting config has
+    /// every other setting of `config`, ready for any changes.
+    pub(super) fn from_config(config: &ClientConfig) -> Self {
+        Self {
+            state: WantsClientCert {
+                cipher_suites: config.cipher_suites.clone(),
+                kx_groups: config.kx_groups.clone(),
+                provider: config.provider,
+                versions: config.versions,
+                verifier: Arc::clone(&config.verifier),
+                raw_public_keys: config.raw_public_keys,
+                alpn_protocols: None,
+                enable_early_data: false,
+                base: Some(Box::new(config.clone())),
+            },
+            side: PhantomData,
+        }
+    }
 }
 
 impl ConfigBuilder<ClientConfig, WantsClientCert> {
+    /// Sets the ALPN protocols to offer, in decreasing order of preference.
+    ///
+    /// These become [`ClientConfig::alpn_protocols`] of the resulting config.
+    ///
+    /// This function fails if any protocol ID is empty or longer than 255
+    /// bytes, as these cannot be offered.
+    pub fn with_alpn_protocols(mut self, protocols: Vec<Vec<u8>>) -> Result<Self, Error> {
+        if protocols
+            .iter()
+            .any(|protocol| protocol.is_empty() || protocol.len() > 255)
+        {
+            return Err(Error::General(
+                "ALPN protocol IDs must be between 1 and 255 bytes".into(),
+            ));
+        }
+
+        self.state.alpn_protocols = Some(protocols);
+        Ok(self)
+    }
+
+    /// Send data on the first flight ("early data" or "0-RTT") of TLS1.3
+    /// resumptions, as [`ClientConfig::enable_early_data`] does.
+    ///
+    /// Early data is only possible when resuming a session whose ticket
+    /// allowed it, so it needs [`ClientConfig::resumption`] to store
+    /// TLS1.3 tickets, as the default does.  With resumption disabled this
+    /// has no effect.  Use [`ClientConnection::early_data`] to write early data.
+    ///
+    /// Early data is not forward secret, and an attacker can replay it to the
+    /// server, possibly many times.  Only send requests in it that are safe to
+    /// repeat.
+    ///
+    /// [`ClientConnection::early_data`]: crate::ClientConnection::early_data
+    pub fn enable_early_data(mut self) -> Self {
+        self.state.enable_early_data = true;
+        self
+    }
+
     #[cfg(feature = "ring")]
     /// Sets a single certificate chain and matching private key for use
     /// in client authentication.
@@ -109,6 +215,25 @@ impl ConfigBuilder<ClientConfig, WantsClientCert> {
         Ok(self.with_client_cert_resolver(Arc::new(resolver)))
     }
 
+    #[cfg(feature = "ring")]
+    /// Sets several certificate chains and matching private keys for use
+    /// in client authentication.
+    ///
+    /// Each entry is a vector of DER-encoded certificates and a DER-encoded
+    /// RSA, ECDSA, or Ed25519 private key.  For each handshake, the first
+    /// chain acceptable to the server is used: see [`MultiClientCert`].
+    ///
+    /// This function fails if any key is invalid or any chain is empty.
+    ///
+    /// [`MultiClientCert`]: super::MultiClientCert
+    pub fn with_client_auth_certs(
+        self,
+        certs: Vec<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)>,
+    ) -> Result<ClientConfig, Error> {
+        let resolver = handy::MultiClientCert::from_chains_and_keys(certs)?;
+        Ok(self.with_client_cert_resolver(Arc::new(resolver)))
+    }
+
     #[cfg(feature = "ring")]
     /// Sets a single certificate chain and matching private key for use
     /// in client authentication.
@@ -127,29 +252,91 @@ impl ConfigBuilder<ClientConfig, WantsClientCert> {
     }
 
     /// Do not support client auth.
+    ///
+    /// For a builder from [`ClientConfig::to_builder`], this replaces any
+    /// client certificate of the existing configuration: use
+    /// [`Self::with_existing_client_cert_resolver`] to keep it.
     pub fn with_no_client_auth(self) -> ClientConfig {
         self.with_client_cert_resolver(Arc::new(handy::FailResolveClientCert {}))
     }
 
+    /// Keep the client certificate resolver of the configuration given to
+    /// [`ClientConfig::to_builder`].
+    ///
+    /// Use this to change other settings of an existing configuration,
+    /// without re-supplying its client certificate.  A builder that did not
+    /// come from `to_builder` has no existing resolver, so this is the same as
+    /// [`Self::with_no_client_auth`].
+    pub fn with_existing_client_cert_resolver(self) -> ClientConfig {
+        let resolver = match &self.state.base {
+            Some(base) => Arc::clone(&base.client_auth_cert_resolver),
+            None => return self.with_no_client_auth(),
+        };
+        self.with_client_cert_resolver(resolver)
+    }
+
     /// Sets a custom [`ResolvesClientCert`].
     pub fn with_client_cert_resolver(
         self,
         client_auth_cert_resolver: Arc<dyn ResolvesClientCert>,
     ) -> ClientConfig {
+        if let Some(base) = self.state.base {
+            return ClientConfig {
+                cipher_suites: self.state.cipher_suites,
+                kx_groups: self.state.kx_groups,
+                provider: self.state.provider,
+                client_auth_cert_resolver,
+                versions: self.state.versions,
+                verifier: self.state.verifier,
+                raw_public_keys: self.state.raw_public_keys,
+                alpn_protocols: self
+                    .state
+                    .alpn_protocols
+                    .unwrap_or(base.alpn_protocols),
+                enable_early_data: self.state.enable_early_data || base.enable_early_data,
+                ..*base
+            };
+        }
+
         ClientConfig {
             cipher_suites: self.state.cipher_suites,
             kx_groups: self.state.kx_groups,
             provider: self.state.provider,
-            alpn_protocols: Vec::new(),
+            alpn_protocols: self
+                .state
+                .alpn_protocols
+                .unwrap_or_default(),
             resumption: Resumption::default(),
             max_fragment_size: None,
+            record_padding_policy: RecordPaddingPolicy::None,
+            aead_limit_policy: AeadLimitPolicy::KeyUpdate,
+            premature_data_policy: PrematureDataPolicy::Fail,
+            aead_limit_callback: None,
+            max_connection_age: None,
+            max_bytes_transferred: None,
+            incoming_plaintext_limit: None,
+            outgoing_plaintext_limit: None,
+            fixed_time: None,
+            key_share_reuse_detector: None,
             client_auth_cert_resolver,
+            signing_retry_policy: SigningRetryPolicy::NONE,
+            certificate_extension_handler: None,
             versions: self.state.versions,
             enable_sni: true,
             verifier: self.state.verifier,
+            enforce_advertised_signature_schemes: true,
+            raw_public_keys: self.state.raw_public_keys,
+            server_name_normalizer: Arc::new(handy::LowercaseServerName),
             key_log: Arc::new(NoKeyLog {}),
             enable_secret_extraction: false,
-            enable_early_data: false,
+            enable_early_data: self.state.enable_early_data,
+            enable_grease_ech: false,
+            enable_grease_supported_versions: false,
+            min_ffdhe_bits: 2048,
+            probe_only: false,
+            send_ec_point_formats: true,
+            client_hello_record_version: ProtocolVersion::TLSv1_0,
+            trusted_ca_keys: Vec::new(),
         }
     }
 }
@@ -1,27 +1,33 @@
 use crate::builder::{ConfigBuilder, WantsCipherSuites};
-use crate::common_state::{CommonState, Protocol, Side};
+use crate::common_state::{CommonState, PrematureDataPolicy, Protocol, Side};
 use crate::conn::{ConnectionCommon, ConnectionCore};
 use crate::crypto::{CryptoProvider, SupportedKxGroup};
 use crate::dns_name::{DnsName, DnsNameRef, InvalidDnsNameError};
 use crate::enums::{CipherSuite, ProtocolVersion, SignatureScheme};
 use crate::error::Error;
+use crate::key_share_reuse::KeyShareReuseDetector;
 #[cfg(feature = "logging")]
 use crate::log::trace;
-use crate::msgs::enums::NamedGroup;
-use crate::msgs::handshake::ClientExtension;
+use crate::msgs::enums::{NamedGroup, PSKKeyExchangeMode};
+use crate::msgs::handshake::{ClientExtension, TrustedAuthority};
 use crate::msgs::persist;
+use crate::record_layer::{AeadLimitCallback, AeadLimitPolicy, RecordPaddingPolicy};
 use crate::sign;
 use crate::suites::{ExtractedSecrets, SupportedCipherSuite};
 use crate::verify;
 use crate::versions;
 use crate::KeyLog;
 
+use super::builder::WantsClientCert;
 use super::handy::{ClientSessionMemoryCache, NoClientSessionStorage};
 use super::hs;
 
+use pki_types::{CertificateDer, UnixTime};
+
 use alloc::sync::Arc;
 use core::marker::PhantomData;
 use core::ops::{Deref, DerefMut};
+use core::time::Duration;
 use core::{fmt, mem};
 use std::io;
 use std::net::IpAddr;
@@ -37,7 +43,11 @@ use std::net::IpAddr;
 /// expressed in the type system to allow implementations freedom in
 /// how to achieve interior mutability.  `Mutex` is a common choice.
 pub trait ClientSessionStore: Send + Sync {
-    /// Remember what `NamedGroup` the given server chose.
+    /// Remember what `NamedGroup` the given server chose, or would prefer.
+    ///
+    /// A preference is learnt from the `supported_groups` a TLS1.3 server
+    /// may send in its `EncryptedExtensions`: the first of those the client
+    /// supports is remembered in place of the group the server chose.
     fn set_kx_hint(&self, server_name: &ServerName, group: NamedGroup);
 
     /// This should return the value most recently passed to `set_kx_hint`
@@ -92,6 +102,13 @@ pub trait ResolvesClientCert: Send + Sync {
     /// library, but it should be expected to contain a DER encodings
     /// of X501 NAMEs.
     ///
+    /// `acceptable_issuers` is empty when the server named no issuers: in
+    /// TLS1.3 by omitting the `certificate_authorities` extension or sending
+    /// it empty, and in TLS1.2 by sending an empty `certificate_authorities`
+    /// list.  These all mean any issuer is acceptable to the server, so an
+    /// empty `acceptable_issuers` should not by itself stop a certificate
+    /// being returned.
+    ///
     /// Return None to continue the handshake without any client
     /// authentication.  The server may reject the handshake later
     /// if it requires authentication.
@@ -105,6 +122,40 @@ pub trait ResolvesClientCert: Send + Sync {
     fn has_certs(&self) -> bool;
 }
 
+/// A trait for the ability to customize how the [`ServerName`] is
+/// normalized before the server's certificate is matched against it.
+///
+/// The default lowercases DNS names, and leaves IP addresses unchanged.
+pub trait NormalizesServerName: Send + Sync {
+    /// Given the `server_name` passed to [`ClientConnection::new`],
+    /// return the name the server's certificate must be valid for.
+    ///
+    /// This is the name passed to [`ServerCertVerifier::verify_server_cert`].
+    /// It does not affect the name sent in the SNI extension, nor the name
+    /// used to look up stored sessions.
+    ///
+    /// [`ServerCertVerifier::verify_server_cert`]: crate::client::danger::ServerCertVerifier::verify_server_cert
+    fn normalize(&self, server_name: &ServerName) -> ServerName;
+}
+
+/// A trait for handling extensions on the entries of a TLS1.3 server
+/// `Certificate` message that rustls does not itself understand.
+///
+/// rustls only understands the `status_request` extension (a stapled OCSP
+/// response) there.  Without a [`ClientConfig::certificate_extension_handler`]
+/// any other extension fails the handshake.
+pub trait HandlesCertificateExtensions: Send + Sync {
+    /// Called once for each `CertificateEntry` carrying unknown extensions.
+    ///
+    /// `entry_index` is the position of the entry in the server's chain:
+    /// zero is the end-entity certificate.  `extensions` holds the type and
+    /// payload of each unknown extension, in the order the server sent them.
+    ///
+    /// Return an error to fail the handshake; it is reported to the server
+    /// with an `unsupported_extension` alert.
+    fn handle(&self, entry_index: usize, extensions: &[(u16, &[u8])]) -> Result<(), Error>;
+}
+
 /// Common configuration for (typically) all connections made by a program.
 ///
 /// Making one of these is cheap, though one of the inputs may be expensive: gathering trust roots
@@ -117,10 +168,32 @@ pub trait ResolvesClientCert: Send + Sync {
 /// # Defaults
 ///
 /// * [`ClientConfig::max_fragment_size`]: the default is `None` (meaning 16kB).
+/// * [`ClientConfig::record_padding_policy`]: records are not padded.
+/// * [`ClientConfig::aead_limit_policy`]: TLS1.3 write keys are updated when they
+///   reach their confidentiality limit, with no [`ClientConfig::aead_limit_callback`].
+/// * [`ClientConfig::premature_data_policy`]: application data received before the
+///   handshake completes is an error.
+/// * [`ClientConfig::max_connection_age`] and [`ClientConfig::max_bytes_transferred`]:
+///   connections are not limited.
+/// * [`ClientConfig::fixed_time`]: `None`; the system clock is used.
+/// * [`ClientConfig::key_share_reuse_detector`]: `None`; key share reuse is not looked for.
+/// * [`ClientConfig::signing_retry_policy`]: a failed signature is not retried.
+/// * [`ClientConfig::incoming_plaintext_limit`] and [`ClientConfig::outgoing_plaintext_limit`]:
+///   `None`, leaving [`CommonState::set_buffer_limit`] and the 16kB received
+///   plaintext limit in charge.
 /// * [`ClientConfig::resumption`]: supports resumption with up to 256 server names, using session
-///    ids or tickets, with a max of eight tickets per server.
+///   ids or tickets, with a max of eight tickets per server.
 /// * [`ClientConfig::alpn_protocols`]: the default is empty -- no ALPN protocol is negotiated.
 /// * [`ClientConfig::key_log`]: key material is not logged.
+/// * [`ClientConfig::enable_grease_ech`] and [`ClientConfig::enable_grease_supported_versions`]:
+///   no GREASE values are sent.
+/// * [`ClientConfig::min_ffdhe_bits`]: the default is 2048 bits.
+/// * [`ClientConfig::enforce_advertised_signature_schemes`]: the default is `true`.
+/// * [`ClientConfig::send_ec_point_formats`]: the default is `true`.
+/// * [`ClientConfig::client_hello_record_version`]: the default is TLS1.0 (0x0301).
+/// * [`ClientConfig::trusted_ca_keys`]: empty; the extension is not sent.
+/// * [`ClientConfig::certificate_extension_handler`]: `None`; unknown TLS1.3
+///   certificate entry extensions fail the handshake.
 ///
 /// [`RootCertStore`]: crate::RootCertStore
 pub struct ClientConfig {
@@ -157,9 +230,88 @@ pub struct ClientConfig {
     /// [ClientConnection::new]: crate::client::ClientConnection::new
     pub max_fragment_size: Option<usize>,
 
+    /// How to pad outgoing TLS1.3 application data records, to obscure
+    /// the length of the data they carry.
+    pub record_padding_policy: RecordPaddingPolicy,
+
+    /// What to do when a TLS1.3 write key reaches the confidentiality
+    /// limit of its AEAD algorithm.
+    pub aead_limit_policy: AeadLimitPolicy,
+
+    /// Told whenever a TLS1.3 write key reaches the confidentiality
+    /// limit of its AEAD algorithm.
+    pub aead_limit_callback: Option<Arc<dyn AeadLimitCallback>>,
+
+    /// What to do with application data the server sends before the
+    /// handshake completes.
+    pub premature_data_policy: PrematureDataPolicy,
+
+    /// The longest a connection may live before [`ClientConnection::process_new_packets`]
+    /// fails with [`Error::ConnectionAgeLimitExceeded`].
+    ///
+    /// Time is supplied by the application via [`CommonState::set_current_time`];
+    /// rustls does not consult the clock for this check.
+    ///
+    /// [`ClientConnection::process_new_packets`]: crate::ConnectionCommon::process_new_packets
+    pub max_connection_age: Option<Duration>,
+
+    /// The most TLS bytes a connection may read and write in total before
+    /// [`ClientConnection::process_new_packets`] fails with
+    /// [`Error::BytesTransferredLimitExceeded`].
+    ///
+    /// This lets applications force a fresh handshake (and fresh keys) by
+    /// reconnecting.
+    ///
+    /// [`ClientConnection::process_new_packets`]: crate::ConnectionCommon::process_new_packets
+    pub max_bytes_transferred: Option<u64>,
+
+    /// The most received plaintext to hold for the application.
+    ///
+    /// Once this much is waiting to be read, [`ClientConnection::process_new_packets`]
+    /// stops decrypting further records (leaving them buffered) and `read_tls`
+    /// refuses more data, until the application drains the [`Reader`].  Call
+    /// `process_new_packets` again after that: `wants_read` stays false while
+    /// records are left buffered.  `complete_io` and [`Stream`] do this.
+    ///
+    /// [`ClientConnection::process_new_packets`]: crate::ConnectionCommon::process_new_packets
+    /// [`Reader`]: crate::Reader
+    /// [`Stream`]: crate::Stream
+    pub incoming_plaintext_limit: Option<usize>,
+
+    /// The most data to queue for sending, independently of
+    /// `incoming_plaintext_limit`.
+    ///
+    /// Queued plaintext and TLS records both count.  Once this is reached,
+    /// writes to the [`Writer`] fail with [`std::io::ErrorKind::WouldBlock`]
+    /// until [`ClientConnection::write_tls`] makes space.
+    ///
+    /// [`Writer`]: crate::Writer
+    /// [`ClientConnection::write_tls`]: crate::ConnectionCommon::write_tls
+    pub outgoing_plaintext_limit: Option<usize>,
+
+    /// A fixed time to use instead of the system clock.
+    ///
+    /// When set, every connection made from this config uses this time when
+    /// validating the server's certificate, and for ticket ages and session timestamps.
+    /// This is intended for reproducible tests and replay.
+    pub fixed_time: Option<UnixTime>,
+
+    /// Watches for the server sending a key share it has used before.
+    ///
+    /// Sharing one detector between several configs looks for reuse across
+    /// all of their connections.
+    pub key_share_reuse_detector: Option<Arc<KeyShareReuseDetector>>,
+
     /// How to decide what client auth certificate/keys to use.
     pub client_auth_cert_resolver: Arc<dyn ResolvesClientCert>,
 
+    /// How many times to attempt signing with the client auth key.
+    pub signing_retry_policy: sign::SigningRetryPolicy,
+
+    /// Receives the extensions on the server's TLS1.3 certificate entries
+    /// that rustls does not understand, instead of failing the handshake.
+    pub certificate_extension_handler: Option<Arc<dyn HandlesCertificateExtensions>>,
+
     /// Supported versions, in no particular order.  The default
     /// is all supported versions.
     pub(super) versions: versions::EnabledVersions,
@@ -173,6 +325,29 @@ pub struct ClientConfig {
     /// How to verify the server certificate chain.
     pub(super) verifier: Arc<dyn verify::ServerCertVerifier>,
 
+    /// Whether to refuse a server that signs the handshake with a signature scheme
+    /// it was not offered.
+    ///
+    /// The client offers the schemes from [`verify::ServerCertVerifier::supported_verify_schemes`]
+    /// in its `signature_algorithms` extension, and a compliant server must sign its
+    /// `CertificateVerify` (or TLS1.2 `ServerKeyExchange`) with one of them.  When this is
+    /// true the handshake fails with [`PeerMisbehaved::SignedHandshakeWithUnadvertisedSigScheme`]
+    /// otherwise.  Disable it only to interoperate with servers known to get this wrong;
+    /// the signature is then left to the verifier alone.
+    ///
+    /// [`PeerMisbehaved::SignedHandshakeWithUnadvertisedSigScheme`]: crate::PeerMisbehaved::SignedHandshakeWithUnadvertisedSigScheme
+    pub enforce_advertised_signature_schemes: bool,
+
+    /// Whether the server must be identified by a raw public key ([RFC 7250]),
+    /// and `verifier` is used through [`verify::ServerCertVerifier::verify_raw_public_key`].
+    ///
+    /// [RFC 7250]: https://www.rfc-editor.org/rfc/rfc7250
+    pub(super) raw_public_keys: bool,
+
+    /// How to normalize the server name before verifying the server
+    /// certificate chain.
+    pub(super) server_name_normalizer: Arc<dyn NormalizesServerName>,
+
     /// How to output key material for debugging.  The default
     /// does nothing.
     pub key_log: Arc<dyn KeyLog>,
@@ -186,6 +361,86 @@ pub struct ClientConfig {
     ///
     /// The default is false.
     pub enable_early_data: bool,
+
+    /// Whether to send a GREASE ("generate random extensions and sustain
+    /// extensibility", [RFC 8701]) encrypted_client_hello extension in
+    /// TLS 1.3 handshakes.
+    ///
+    /// The extension is a random placeholder in the shape described by the
+    /// [ECH draft], and does not actually encrypt anything.  It helps keep
+    /// servers and middleboxes tolerant of real ECH.
+    ///
+    /// The default is false.
+    ///
+    /// [RFC 8701]: https://datatracker.ietf.org/doc/html/rfc8701
+    /// [ECH draft]: https://datatracker.ietf.org/doc/html/draft-ietf-tls-esni-18#section-6.2
+    pub enable_grease_ech: bool,
+
+    /// Whether to include a reserved GREASE value ([RFC 8701]) at the start
+    /// of the supported_versions extension.
+    ///
+    /// This is independent of [`ClientConfig::enable_grease_ech`].
+    ///
+    /// The default is false.
+    ///
+    /// [RFC 8701]: https://datatracker.ietf.org/doc/html/rfc8701
+    pub enable_grease_supported_versions: bool,
+
+    /// The smallest finite field Diffie-Hellman group, in bits, that the
+    /// server may select.
+    ///
+    /// This applies to the [RFC 7919] groups, which rustls itself does not
+    /// implement but a [`CryptoProvider`] may.  A server selecting a smaller
+    /// group is rejected with [`PeerIncompatible::ServerSelectedWeakFfdheGroup`].
+    ///
+    /// The default is 2048.
+    ///
+    /// [RFC 7919]: https://datatracker.ietf.org/doc/html/rfc7919
+    /// [`PeerIncompatible::ServerSelectedWeakFfdheGroup`]: crate::PeerIncompatible::ServerSelectedWeakFfdheGroup
+    pub min_ffdhe_bits: u16,
+
+    /// Only probe the server: see [`danger::DangerousClientConfig::set_probe_only`].
+    pub(super) probe_only: bool,
+
+    /// Whether to send the `ec_point_formats` extension ([RFC 8422]), listing
+    /// only the uncompressed format.
+    ///
+    /// This only matters to TLS1.2 servers, some of which (including rustls)
+    /// refuse ECDHE handshakes without it.  Turning it off is therefore only
+    /// sensible for TLS1.3-only clients.
+    ///
+    /// The default is true.
+    ///
+    /// [RFC 8422]: https://datatracker.ietf.org/doc/html/rfc8422#section-5.1.2
+    pub send_ec_point_formats: bool,
+
+    /// The record layer version of the record carrying the initial ClientHello.
+    ///
+    /// Servers are meant to ignore this, and the default of TLS1.0 (0x0301) is
+    /// what most clients send.  Some old middleboxes insist on a particular
+    /// value, so it can be changed here.  A ClientHello sent after a
+    /// HelloRetryRequest always uses TLS1.2 (0x0303), as RFC 8446 requires.
+    ///
+    /// This does not change which protocol versions are offered.
+    pub client_hello_record_version: ProtocolVersion,
+
+    /// Certificate authorities to name in the [RFC 6066] `trusted_ca_keys`
+    /// extension, which some servers use to pick a certificate chain.
+    ///
+    /// The extension is only sent if this is non-empty.  To name the
+    /// roots of a [`RootCertStore`] by their distinguished names, use:
+    ///
+    /// ```ignore
+    /// config.trusted_ca_keys = roots
+    ///     .subjects()
+    ///     .into_iter()
+    ///     .map(TrustedAuthority::X509Name)
+    ///     .collect();
+    /// ```
+    ///
+    /// [RFC 6066]: https://datatracker.ietf.org/doc/html/rfc6066#section-6
+    /// [`RootCertStore`]: crate::RootCertStore
+    pub trusted_ca_keys: Vec<TrustedAuthority>,
 }
 
 /// What mechanisms to support for resuming a TLS 1.2 session.
@@ -204,6 +459,41 @@ pub enum Tls12Resumption {
     SessionIdOrTickets,
 }
 
+/// Which key exchange modes to allow when resuming a TLS 1.3 session.
+///
+/// These are advertised in the `psk_key_exchange_modes` extension.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Tls13PskKeyExchangeModes {
+    /// Only `psk_dhe_ke`: a resumed handshake includes an (EC)DHE key
+    /// exchange, so it has forward secrecy.  This is the default.
+    PskDheKeOnly,
+    /// Only `psk_ke`: a resumed handshake sends no key share, and its keys
+    /// are derived from the resumption secret alone.
+    ///
+    /// This saves the cost of a key exchange, but the resumed connection
+    /// loses forward secrecy: anyone who later learns the ticket's secret,
+    /// or the server's ticket encryption key, can decrypt it.  A key share
+    /// is still sent when there is no TLS 1.3 session to resume.
+    PskKeOnly,
+    /// Both `psk_ke` and `psk_dhe_ke`, letting the server choose.  A key
+    /// share is sent, so the server can still choose forward secrecy.
+    Both,
+}
+
+impl Tls13PskKeyExchangeModes {
+    pub(super) fn modes(self) -> Vec<PSKKeyExchangeMode> {
+        match self {
+            Self::PskDheKeOnly => vec![PSKKeyExchangeMode::PSK_DHE_KE],
+            Self::PskKeOnly => vec![PSKKeyExchangeMode::PSK_KE],
+            Self::Both => vec![PSKKeyExchangeMode::PSK_DHE_KE, PSKKeyExchangeMode::PSK_KE],
+        }
+    }
+
+    pub(super) fn allows_psk_ke(self) -> bool {
+        self != Self::PskDheKeOnly
+    }
+}
+
 impl Clone for ClientConfig {
     fn clone(&self) -> Self {
         Self {
@@ -213,13 +503,37 @@ impl Clone for ClientConfig {
             resumption: self.resumption.clone(),
             alpn_protocols: self.alpn_protocols.clone(),
             max_fragment_size: self.max_fragment_size,
+            record_padding_policy: self.record_padding_policy,
+            aead_limit_policy: self.aead_limit_policy,
+            premature_data_policy: self.premature_data_policy,
+            aead_limit_callback: self.aead_limit_callback.clone(),
+            max_connection_age: self.max_connection_age,
+            max_bytes_transferred: self.max_bytes_transferred,
+            incoming_plaintext_limit: self.incoming_plaintext_limit,
+            outgoing_plaintext_limit: self.outgoing_plaintext_limit,
+            fixed_time: self.fixed_time,
+            key_share_reuse_detector: self.key_share_reuse_detector.clone(),
             client_auth_cert_resolver: Arc::clone(&self.client_auth_cert_resolver),
+            signing_retry_policy: self.signing_retry_policy,
+            certificate_extension_handler: self
+                .certificate_extension_handler
+                .clone(),
             versions: self.versions,
             enable_sni: self.enable_sni,
             verifier: Arc::clone(&self.verifier),
+            enforce_advertised_signature_schemes: self.enforce_advertised_signature_schemes,
+            raw_public_keys: self.raw_public_keys,
+            server_name_normalizer: Arc::clone(&self.server_name_normalizer),
             key_log: Arc::clone(&self.key_log),
             enable_secret_extraction: self.enable_secret_extraction,
             enable_early_data: self.enable_early_data,
+            enable_grease_ech: self.enable_grease_ech,
+            enable_grease_supported_versions: self.enable_grease_supported_versions,
+            min_ffdhe_bits: self.min_ffdhe_bits,
+            probe_only: self.probe_only,
+            send_ec_point_formats: self.send_ec_point_formats,
+            client_hello_record_version: self.client_hello_record_version,
+            trusted_ca_keys: self.trusted_ca_keys.clone(),
         }
     }
 }
@@ -230,8 +544,36 @@ impl fmt::Debug for ClientConfig {
             .field("alpn_protocols", &self.alpn_protocols)
             .field("resumption", &self.resumption)
             .field("max_fragment_size", &self.max_fragment_size)
+            .field("record_padding_policy", &self.record_padding_policy)
+            .field("aead_limit_policy", &self.aead_limit_policy)
+            .field("premature_data_policy", &self.premature_data_policy)
+            .field("max_connection_age", &self.max_connection_age)
+            .field("max_bytes_transferred", &self.max_bytes_transferred)
+            .field("incoming_plaintext_limit", &self.incoming_plaintext_limit)
+            .field("outgoing_plaintext_limit", &self.outgoing_plaintext_limit)
+            .field("fixed_time", &self.fixed_time)
+            .field("key_share_reuse_detector", &self.key_share_reuse_detector)
+            .field("signing_retry_policy", &self.signing_retry_policy)
             .field("enable_sni", &self.enable_sni)
+            .field(
+                "enforce_advertised_signature_schemes",
+                &self.enforce_advertised_signature_schemes,
+            )
+            .field("raw_public_keys", &self.raw_public_keys)
             .field("enable_early_data", &self.enable_early_data)
+            .field("min_ffdhe_bits", &self.min_ffdhe_bits)
+            .field("probe_only", &self.probe_only)
+            .field("send_ec_point_formats", &self.send_ec_point_formats)
+            .field(
+                "client_hello_record_version",
+                &self.client_hello_record_version,
+            )
+            .field("trusted_ca_keys", &self.trusted_ca_keys)
+            .field("enable_grease_ech", &self.enable_grease_ech)
+            .field(
+                "enable_grease_supported_versions",
+                &self.enable_grease_supported_versions,
+            )
             .finish_non_exhaustive()
     }
 }
@@ -259,6 +601,35 @@ impl ClientConfig {
         }
     }
 
+    /// Create a builder that starts from this configuration.
+    ///
+    /// The builder resumes at the point of choosing a client certificate:
+    /// everything else, from cipher suites to [`ClientConfig::alpn_protocols`],
+    /// is carried over into the new configuration.  This is useful to
+    /// reload a client certificate, or to vary a few fields of an existing
+    /// configuration: `with_existing_client_cert_resolver` keeps the client
+    /// certificate this configuration has.
+    pub fn to_builder(&self) -> ConfigBuilder<Self, WantsClientCert> {
+        ConfigBuilder::from_config(self)
+    }
+
+    /// Return `true` if this configuration only enables FIPS-approved cryptography.
+    ///
+    /// This requires the [`CryptoProvider`] itself, every enabled cipher suite, and
+    /// every enabled key exchange group to report being FIPS-approved.  Use this
+    /// to check at startup that nothing non-FIPS has slipped into the configuration.
+    pub fn fips(&self) -> bool {
+        self.provider.fips()
+            && self
+                .cipher_suites
+                .iter()
+                .all(|cs| cs.fips())
+            && self
+                .kx_groups
+                .iter()
+                .all(|kx| kx.fips())
+    }
+
     /// We support a given TLS version if it's quoted in the configured
     /// versions *and* at least one ciphersuite for this version is
     /// also configured.
@@ -300,8 +671,24 @@ pub struct Resumption {
 
     /// What mechanism is used for resuming a TLS 1.2 session.
     pub(super) tls12_resumption: Tls12Resumption,
+
+    /// Whether TLS 1.3 sessions are resumed with tickets.
+    pub(super) tls13_resumption: bool,
+
+    /// Whether to leave out the TLS 1.3 pre-shared key from a
+    /// ClientHello sent in response to a HelloRetryRequest.
+    pub(super) omit_psk_after_hello_retry: bool,
+
+    /// Which key exchange modes are allowed when resuming a TLS 1.3 session.
+    pub(super) tls13_psk_modes: Tls13PskKeyExchangeModes,
+
+    /// Decides, per server, whether a stored session is offered at all.
+    pub(super) offer_predicate: Option<Arc<OfferPredicate>>,
 }
 
+/// Decides whether a stored session for a server is offered: see [`Resumption::offer_resumption_if`].
+type OfferPredicate = dyn Fn(&ServerName) -> bool + Send + Sync;
+
 impl Resumption {
     /// Create a new `Resumption` that stores data for the given number of sessions in memory.
     ///
@@ -311,6 +698,10 @@ impl Resumption {
         Self {
             store: Arc::new(ClientSessionMemoryCache::new(num)),
             tls12_resumption: Tls12Resumption::SessionIdOrTickets,
+            tls13_resumption: true,
+            omit_psk_after_hello_retry: false,
+            tls13_psk_modes: Tls13PskKeyExchangeModes::PskDheKeOnly,
+            offer_predicate: None,
         }
     }
 
@@ -321,6 +712,10 @@ impl Resumption {
         Self {
             store,
             tls12_resumption: Tls12Resumption::SessionIdOrTickets,
+            tls13_resumption: true,
+            omit_psk_after_hello_retry: false,
+            tls13_psk_modes: Tls13PskKeyExchangeModes::PskDheKeOnly,
+            offer_predicate: None,
         }
     }
 
@@ -329,6 +724,10 @@ impl Resumption {
         Self {
             store: Arc::new(NoClientSessionStorage),
             tls12_resumption: Tls12Resumption::Disabled,
+            tls13_resumption: false,
+            omit_psk_after_hello_retry: false,
+            tls13_psk_modes: Tls13PskKeyExchangeModes::PskDheKeOnly,
+            offer_predicate: None,
         }
     }
 
@@ -339,13 +738,77 @@ impl Resumption {
         self.tls12_resumption = tls12;
         self
     }
+
+    /// Configure whether TLS 1.3 sessions may be resumed with tickets.
+    ///
+    /// The default is `true`.  Setting this to `false` stops the client
+    /// storing tickets the server sends and leaves the `pre_shared_key` and
+    /// `psk_key_exchange_modes` extensions out of its ClientHello, so every
+    /// TLS 1.3 handshake is a full one.  TLS 1.2 resumption is unaffected;
+    /// see [`Resumption::tls12_resumption`].
+    ///
+    /// This is meaningless if you've disabled resumption entirely.
+    pub fn tls13_resumption(mut self, enabled: bool) -> Self {
+        self.tls13_resumption = enabled;
+        self
+    }
+
+    /// Configure whether a TLS 1.3 session should still be offered for
+    /// resumption in the second ClientHello, sent after the server
+    /// replies with a HelloRetryRequest.
+    ///
+    /// By default the session is offered in both ClientHellos.  Setting
+    /// this to `true` offers it only in the first, which works around
+    /// servers that mishandle a pre-shared key after a HelloRetryRequest.
+    /// If the server asks for a retry, the handshake then falls back to
+    /// a full handshake.
+    ///
+    /// This is meaningless if you've disabled resumption entirely.
+    pub fn omit_psk_after_hello_retry(mut self, omit: bool) -> Self {
+        self.omit_psk_after_hello_retry = omit;
+        self
+    }
+
+    /// Configure which key exchange modes are allowed when resuming a TLS 1.3 session.
+    ///
+    /// The default is [`Tls13PskKeyExchangeModes::PskDheKeOnly`].  See
+    /// [`Tls13PskKeyExchangeModes::PskKeOnly`] for the forward secrecy cost
+    /// of allowing `psk_ke`.
+    pub fn tls13_psk_key_exchange_modes(mut self, modes: Tls13PskKeyExchangeModes) -> Self {
+        self.tls13_psk_modes = modes;
+        self
+    }
+
+    /// Only offer to resume a stored session with servers for which
+    /// `predicate` returns `true`.
+    ///
+    /// By default a stored session is always offered.  A server for which
+    /// `predicate` returns `false` gets a full handshake, and its stored
+    /// sessions are left in the store.  New sessions from it are still
+    /// stored, so it can be resumed with again if `predicate` later
+    /// changes its mind.
+    ///
+    /// This is meaningless if you've disabled resumption entirely.
+    pub fn offer_resumption_if(
+        mut self,
+        predicate: impl Fn(&ServerName) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.offer_predicate = Some(Arc::new(predicate));
+        self
+    }
 }
 
 impl fmt::Debug for Resumption {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Resumption")
             .field("tls12_resumption", &self.tls12_resumption)
-            .finish()
+            .field("tls13_resumption", &self.tls13_resumption)
+            .field(
+                "omit_psk_after_hello_retry",
+                &self.omit_psk_after_hello_retry,
+            )
+            .field("tls13_psk_modes", &self.tls13_psk_modes)
+            .finish_non_exhaustive()
     }
 }
 
@@ -437,7 +900,7 @@ pub(super) mod danger {
     use alloc::sync::Arc;
 
     use super::verify::ServerCertVerifier;
-    use super::ClientConfig;
+    use super::{ClientConfig, NormalizesServerName};
 
     /// Accessor for dangerous configuration options.
     #[derive(Debug)]
@@ -451,6 +914,32 @@ pub(super) mod danger {
         pub fn set_certificate_verifier(&mut self, verifier: Arc<dyn ServerCertVerifier>) {
             self.cfg.verifier = verifier;
         }
+
+        /// Overrides how the server name is normalized before the server's
+        /// certificate is verified against it.
+        ///
+        /// The default lowercases DNS names.
+        pub fn set_server_name_normalizer(&mut self, normalizer: Arc<dyn NormalizesServerName>) {
+            self.cfg.server_name_normalizer = normalizer;
+        }
+
+        /// Only probe the server: stop the handshake as soon as its parameters
+        /// and certificate chain are known.
+        ///
+        /// The connection then records a [`ServerProbe`] (see
+        /// [`ClientConnection::server_probe`]) and sends a `close_notify` alert.
+        /// The rest of the server's flight is discarded.  The handshake never
+        /// completes, so no application data is exchanged and the server's
+        /// certificate and handshake signature are never verified.  Resumption
+        /// is not attempted.
+        ///
+        /// This is intended for scanners.  The default is false.
+        ///
+        /// [`ServerProbe`]: super::ServerProbe
+        /// [`ClientConnection::server_probe`]: super::ClientConnection::server_probe
+        pub fn set_probe_only(&mut self, probe_only: bool) {
+            self.cfg.probe_only = probe_only;
+        }
     }
 }
 
@@ -631,6 +1120,27 @@ impl ClientConnection {
         self.inner.core.is_early_data_accepted()
     }
 
+    /// Returns true if the server sent a HelloRetryRequest during the handshake.
+    ///
+    /// This usually means none of the key shares in our ClientHello were
+    /// for a group the server was willing to use, costing an extra round trip.
+    pub fn did_hello_retry_request(&self) -> bool {
+        self.inner
+            .core
+            .data
+            .did_hello_retry_request
+    }
+
+    /// Returns what a [probe-only] connection learned about
+    /// the server, once it has done so.
+    ///
+    /// This is always `None` for normal connections.
+    ///
+    /// [probe-only]: danger::DangerousClientConfig::set_probe_only
+    pub fn server_probe(&self) -> Option<&ServerProbe> {
+        self.inner.core.data.probe.as_ref()
+    }
+
     /// Extract secrets, so they can be used when configuring kTLS, for example.
     /// Should be used with care as it exposes secret key material.
     pub fn dangerous_extract_secrets(self) -> Result<ExtractedSecrets, Error> {
@@ -692,6 +1202,17 @@ impl ConnectionCore<ClientConnectionData> {
     ) -> Result<Self, Error> {
         let mut common_state = CommonState::new(Side::Client);
         common_state.set_max_fragment_size(config.max_fragment_size)?;
+        common_state.set_record_padding_policy(config.record_padding_policy, config.provider);
+        common_state
+            .set_aead_limit_policy(config.aead_limit_policy, config.aead_limit_callback.clone());
+        common_state.set_premature_data_policy(config.premature_data_policy);
+        common_state.set_connection_limits(config.max_connection_age, config.max_bytes_transferred);
+        common_state.set_plaintext_limits(
+            config.incoming_plaintext_limit,
+            config.outgoing_plaintext_limit,
+        );
+        common_state.fixed_time = config.fixed_time;
+        common_state.fips_provider = config.provider.fips();
         common_state.protocol = proto;
         common_state.enable_secret_extraction = config.enable_secret_extraction;
         let mut data = ClientConnectionData::new();
@@ -714,6 +1235,9 @@ impl ConnectionCore<ClientConnectionData> {
 pub struct ClientConnectionData {
     pub(super) early_data: EarlyData,
     pub(super) resumption_ciphersuite: Option<SupportedCipherSuite>,
+    pub(super) kx_group: Option<NamedGroup>,
+    pub(super) probe: Option<ServerProbe>,
+    pub(super) did_hello_retry_request: bool,
 }
 
 impl ClientConnectionData {
@@ -721,8 +1245,28 @@ impl ClientConnectionData {
         Self {
             early_data: EarlyData::new(),
             resumption_ciphersuite: None,
+            kx_group: None,
+            probe: None,
+            did_hello_retry_request: false,
         }
     }
 }
 
+/// The server parameters observed by a [probe-only] connection.
+///
+/// None of this has been verified.
+///
+/// [probe-only]: danger::DangerousClientConfig::set_probe_only
+#[derive(Clone, Debug)]
+pub struct ServerProbe {
+    /// The protocol version the server selected.
+    pub version: ProtocolVersion,
+    /// The cipher suite the server selected.
+    pub suite: SupportedCipherSuite,
+    /// The key exchange group the server selected.
+    pub kx_group: NamedGroup,
+    /// The certificate chain the server presented, end-entity first.
+    pub cert_chain: Vec<CertificateDer<'static>>,
+}
+
 impl crate::conn::SideData for ClientConnectionData {}
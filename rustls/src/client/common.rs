@@ -70,6 +70,7 @@ impl ClientAuthDetails {
         sigschemes: &[SignatureScheme],
         auth_context_tls13: Option<Vec<u8>>,
     ) -> Self {
+        // No certificate_authorities, or an empty one, means no constraint.
         let acceptable_issuers = canames
             .unwrap_or_default()
             .iter()
@@ -77,13 +78,19 @@ impl ClientAuthDetails {
             .collect::<Vec<&[u8]>>();
 
         if let Some(certkey) = resolver.resolve(&acceptable_issuers, sigschemes) {
-            if let Some(signer) = certkey.key.choose_scheme(sigschemes) {
-                debug!("Attempting client auth");
-                return Self::Verify {
-                    certkey,
-                    signer,
-                    auth_context_tls13,
-                };
+            match certkey
+                .key
+                .choose_scheme_checked(sigschemes)
+            {
+                Ok(signer) => {
+                    debug!("Attempting client auth");
+                    return Self::Verify {
+                        certkey,
+                        signer,
+                        auth_context_tls13,
+                    };
+                }
+                Err(_mismatch) => debug!("Client auth key unusable: {}", _mismatch),
             }
         }
 
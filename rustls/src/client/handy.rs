@@ -1,18 +1,27 @@
 use crate::client;
+#[cfg(feature = "ring")]
+use crate::crypto::ring;
+use crate::dns_name::DnsName;
 use crate::enums::SignatureScheme;
+use crate::error::{Error, InvalidMessage};
 use crate::limited_cache;
+use crate::msgs::base::{PayloadU24, PayloadU8};
+use crate::msgs::codec::{Codec, Reader};
 use crate::msgs::persist;
 use crate::sign;
+use crate::suites::SupportedCipherSuite;
+use crate::x509;
 use crate::NamedGroup;
 use crate::ServerName;
-#[cfg(feature = "ring")]
-use crate::{crypto::ring, error::Error};
 
 #[cfg(feature = "ring")]
 use pki_types::{CertificateDer, PrivateKeyDer};
 
 use alloc::collections::VecDeque;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::iter;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::sync::Mutex;
 
 /// An implementer of `ClientSessionStore` which does nothing.
@@ -42,6 +51,7 @@ impl client::ClientSessionStore for NoClientSessionStorage {
 
 const MAX_TLS13_TICKETS_PER_SERVER: usize = 8;
 
+#[derive(Default)]
 struct ServerData {
     kx_hint: Option<NamedGroup>,
 
@@ -49,17 +59,62 @@ struct ServerData {
     #[cfg(feature = "tls12")]
     tls12: Option<persist::Tls12ClientSessionValue>,
 
-    // Up to MAX_TLS13_TICKETS_PER_SERVER TLS1.3 tickets, oldest first.
+    // Up to `max_tls13_tickets_per_server` TLS1.3 tickets, oldest first.
     tls13: VecDeque<persist::Tls13ClientSessionValue>,
 }
 
-impl Default for ServerData {
-    fn default() -> Self {
-        Self {
-            kx_hint: None,
-            #[cfg(feature = "tls12")]
-            tls12: None,
-            tls13: VecDeque::with_capacity(MAX_TLS13_TICKETS_PER_SERVER),
+struct ServerCache {
+    by_name: limited_cache::LimitedCache<ServerName, ServerData>,
+
+    // One entry per stored TLS1.3 ticket, naming the server it belongs
+    // to.  Least recently used server first.
+    tls13_order: VecDeque<ServerName>,
+}
+
+impl ServerCache {
+    fn edit(&mut self, server_name: &ServerName, edit: impl FnOnce(&mut ServerData)) {
+        if let Some((evicted, _)) = self
+            .by_name
+            .get_or_insert_default_and_edit(server_name.clone(), edit)
+        {
+            self.tls13_order
+                .retain(|name| name != &evicted);
+        }
+    }
+
+    /// Mark `server_name` as the most recently used, so its tickets and
+    /// its entry are evicted last.
+    fn touch(&mut self, server_name: &ServerName) {
+        self.by_name.touch(server_name);
+
+        let before = self.tls13_order.len();
+        self.tls13_order
+            .retain(|name| name != server_name);
+        let tickets = before - self.tls13_order.len();
+        self.tls13_order.extend(
+            iter::repeat(server_name)
+                .take(tickets)
+                .cloned(),
+        );
+    }
+
+    fn forget_oldest_tls13_ticket(&mut self, server_name: &ServerName) {
+        if let Some(index) = self
+            .tls13_order
+            .iter()
+            .position(|name| name == server_name)
+        {
+            self.tls13_order.remove(index);
+        }
+    }
+
+    fn forget_newest_tls13_ticket(&mut self, server_name: &ServerName) {
+        if let Some(index) = self
+            .tls13_order
+            .iter()
+            .rposition(|name| name == server_name)
+        {
+            self.tls13_order.remove(index);
         }
     }
 }
@@ -69,33 +124,255 @@ impl Default for ServerData {
 ///
 /// It enforces a limit on the number of entries to bound memory usage.
 pub struct ClientSessionMemoryCache {
-    servers: Mutex<limited_cache::LimitedCache<ServerName, ServerData>>,
+    servers: Mutex<ServerCache>,
+    max_tls13_tickets: usize,
+    max_tls13_tickets_per_server: usize,
 }
 
 impl ClientSessionMemoryCache {
     /// Make a new ClientSessionMemoryCache.  `size` is the
     /// maximum number of stored sessions.
     pub fn new(size: usize) -> Self {
-        let max_servers =
-            size.saturating_add(MAX_TLS13_TICKETS_PER_SERVER - 1) / MAX_TLS13_TICKETS_PER_SERVER;
+        Self::new_with_limits(size, MAX_TLS13_TICKETS_PER_SERVER)
+    }
+
+    /// Make a new ClientSessionMemoryCache with explicit limits on
+    /// the number of TLS1.3 tickets stored.
+    ///
+    /// At most `max_tickets_per_server` tickets are kept for any one
+    /// server name, and at most `max_tickets` across all servers.
+    /// When the per-server limit is reached, that server's oldest ticket
+    /// is evicted.  When the overall limit is reached, the oldest ticket of
+    /// the least recently used server is evicted: a server is used when a
+    /// ticket is inserted for it or taken to resume a session with it.
+    ///
+    /// At most `max_tickets` server names are remembered, and the least
+    /// recently used is forgotten first.
+    pub fn new_with_limits(max_tickets: usize, max_tickets_per_server: usize) -> Self {
         Self {
-            servers: Mutex::new(limited_cache::LimitedCache::new(max_servers)),
+            servers: Mutex::new(ServerCache {
+                by_name: limited_cache::LimitedCache::new(max_tickets),
+                tls13_order: VecDeque::with_capacity(max_tickets),
+            }),
+            max_tls13_tickets: max_tickets,
+            max_tls13_tickets_per_server: max_tickets_per_server,
+        }
+    }
+
+    /// Serialize every stored session, so that another `ClientSessionMemoryCache`
+    /// can be loaded with them using [`ClientSessionMemoryCache::import()`].
+    ///
+    /// This lets a client resume sessions across a process restart.
+    ///
+    /// The output contains the secrets of each session, with which its
+    /// tickets can be used to resume it: anyone who has them can impersonate
+    /// this client to the servers concerned, and decrypt traffic protected
+    /// by keys derived from them.  Protect it as you would a private key.
+    pub fn export(&self) -> Vec<u8> {
+        let servers = self.servers.lock().unwrap();
+        let mut bytes = Vec::new();
+        EXPORT_VERSION.encode(&mut bytes);
+
+        let names = servers
+            .by_name
+            .iter()
+            .map(|(name, _)| name)
+            .collect::<Vec<_>>();
+        (names.len() as u32).encode(&mut bytes);
+
+        for (name, data) in servers.by_name.iter() {
+            encode_server_name(name, &mut bytes);
+
+            match data.kx_hint {
+                Some(group) => {
+                    1u8.encode(&mut bytes);
+                    group.encode(&mut bytes);
+                }
+                None => 0u8.encode(&mut bytes),
+            }
+
+            #[cfg(feature = "tls12")]
+            match &data.tls12 {
+                Some(value) => {
+                    1u8.encode(&mut bytes);
+                    let mut encoded = Vec::new();
+                    value.encode(&mut encoded);
+                    PayloadU24::new(encoded).encode(&mut bytes);
+                }
+                None => 0u8.encode(&mut bytes),
+            }
+            #[cfg(not(feature = "tls12"))]
+            0u8.encode(&mut bytes);
+
+            (data.tls13.len() as u32).encode(&mut bytes);
+            for value in &data.tls13 {
+                let mut encoded = Vec::new();
+                value.encode(&mut encoded);
+                PayloadU24::new(encoded).encode(&mut bytes);
+            }
+        }
+
+        // every name here should have an entry; skip any that does not,
+        // rather than attribute its ticket to another server
+        let order = servers
+            .tls13_order
+            .iter()
+            .filter_map(|name| names.iter().position(|n| *n == name))
+            .collect::<Vec<_>>();
+        (order.len() as u32).encode(&mut bytes);
+        for index in order {
+            (index as u32).encode(&mut bytes);
+        }
+
+        bytes
+    }
+
+    /// Add the sessions in `data`, as produced by [`ClientSessionMemoryCache::export()`],
+    /// to this cache.
+    ///
+    /// Sessions are stored as if they had just been inserted in the order
+    /// they were stored in the exporting cache, so this cache's limits
+    /// apply.  Sessions using a cipher suite not in `cipher_suites` are
+    /// dropped: you should pass the suites of the `ClientConfig` this cache
+    /// will be used with.  Expired sessions are kept, and ignored when the
+    /// time comes to use them.
+    ///
+    /// This fails with [`Error::InvalidMessage`] if `data` is malformed, in
+    /// which case nothing is stored.
+    pub fn import(&self, data: &[u8], cipher_suites: &[SupportedCipherSuite]) -> Result<(), Error> {
+        let mut r = Reader::init(data);
+        if u8::read(&mut r)? != EXPORT_VERSION {
+            return Err(InvalidMessage::UnknownProtocolVersion.into());
+        }
+
+        let mut servers = Vec::new();
+        for _ in 0..u32::read(&mut r)? {
+            let name = read_server_name(&mut r)?;
+
+            let kx_hint = match u8::read(&mut r)? {
+                0 => None,
+                _ => Some(NamedGroup::read(&mut r)?),
+            };
+
+            // without TLS1.2 support, a TLS1.2 session is skipped
+            let tls12 = match u8::read(&mut r)? {
+                0 => None,
+                _ => read_tls12_session(&PayloadU24::read(&mut r)?.0, cipher_suites)?,
+            };
+
+            let mut tls13 = VecDeque::new();
+            for _ in 0..u32::read(&mut r)? {
+                let encoded = PayloadU24::read(&mut r)?;
+                let mut sub = Reader::init(&encoded.0);
+                tls13.push_back(persist::Tls13ClientSessionValue::read(
+                    &mut sub,
+                    cipher_suites,
+                )?);
+                sub.expect_empty("Tls13ClientSessionValue")?;
+            }
+
+            servers.push((name, kx_hint, tls12, tls13));
+        }
+
+        let mut order = Vec::new();
+        for _ in 0..u32::read(&mut r)? {
+            let index = u32::read(&mut r)? as usize;
+            if index >= servers.len() {
+                return Err(InvalidMessage::MissingData("ClientSessionMemoryCache").into());
+            }
+            order.push(index);
+        }
+        r.expect_empty("ClientSessionMemoryCache")?;
+
+        for (name, kx_hint, tls12, _) in &mut servers {
+            if let Some(group) = kx_hint {
+                client::ClientSessionStore::set_kx_hint(self, name, *group);
+            }
+            if let Some(value) = tls12.take() {
+                client::ClientSessionStore::set_tls12_session(self, name, value);
+            }
+        }
+
+        for index in order {
+            let (name, _, _, tls13) = &mut servers[index];
+            if let Some(Some(value)) = tls13.pop_front() {
+                client::ClientSessionStore::insert_tls13_ticket(self, name, value);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Identifies the format written by `ClientSessionMemoryCache::export`.
+const EXPORT_VERSION: u8 = 1;
+
+#[cfg(feature = "tls12")]
+fn read_tls12_session(
+    encoded: &[u8],
+    cipher_suites: &[SupportedCipherSuite],
+) -> Result<Option<persist::Tls12ClientSessionValue>, InvalidMessage> {
+    let mut r = Reader::init(encoded);
+    let value = persist::Tls12ClientSessionValue::read(&mut r, cipher_suites)?;
+    r.expect_empty("Tls12ClientSessionValue")?;
+    Ok(value)
+}
+
+#[cfg(not(feature = "tls12"))]
+fn read_tls12_session(
+    _: &[u8],
+    _: &[SupportedCipherSuite],
+) -> Result<Option<persist::Tls12ClientSessionValue>, InvalidMessage> {
+    Ok(None)
+}
+
+fn encode_server_name(name: &ServerName, bytes: &mut Vec<u8>) {
+    match name {
+        ServerName::DnsName(dns_name) => {
+            0u8.encode(bytes);
+            PayloadU8::new(dns_name.as_ref().as_bytes().to_vec()).encode(bytes);
+        }
+        ServerName::IpAddress(IpAddr::V4(addr)) => {
+            4u8.encode(bytes);
+            bytes.extend_from_slice(&addr.octets());
+        }
+        ServerName::IpAddress(IpAddr::V6(addr)) => {
+            6u8.encode(bytes);
+            bytes.extend_from_slice(&addr.octets());
         }
     }
 }
 
+fn read_server_name(r: &mut Reader) -> Result<ServerName, InvalidMessage> {
+    match u8::read(r)? {
+        0 => DnsName::try_from_ascii(&PayloadU8::read(r)?.0)
+            .map(ServerName::DnsName)
+            .map_err(|_| InvalidMessage::InvalidServerName),
+        4 => match r.take(4) {
+            Some(&[a, b, c, d]) => Ok(ServerName::IpAddress(Ipv4Addr::new(a, b, c, d).into())),
+            _ => Err(InvalidMessage::MissingData("Ipv4Addr")),
+        },
+        6 => match r.take(16).map(<[u8; 16]>::try_from) {
+            Some(Ok(octets)) => Ok(ServerName::IpAddress(Ipv6Addr::from(octets).into())),
+            _ => Err(InvalidMessage::MissingData("Ipv6Addr")),
+        },
+        _ => Err(InvalidMessage::InvalidServerName),
+    }
+}
+
 impl client::ClientSessionStore for ClientSessionMemoryCache {
     fn set_kx_hint(&self, server_name: &ServerName, group: NamedGroup) {
         self.servers
             .lock()
             .unwrap()
-            .get_or_insert_default_and_edit(server_name.clone(), |data| data.kx_hint = Some(group));
+            .edit(server_name, |data| data.kx_hint = Some(group));
     }
 
     fn kx_hint(&self, server_name: &ServerName) -> Option<NamedGroup> {
         self.servers
             .lock()
             .unwrap()
+            .by_name
             .get(server_name)
             .and_then(|sd| sd.kx_hint)
     }
@@ -109,7 +386,7 @@ impl client::ClientSessionStore for ClientSessionMemoryCache {
         self.servers
             .lock()
             .unwrap()
-            .get_or_insert_default_and_edit(_server_name.clone(), |data| data.tls12 = Some(_value));
+            .edit(_server_name, |data| data.tls12 = Some(_value));
     }
 
     fn tls12_session(&self, _server_name: &ServerName) -> Option<persist::Tls12ClientSessionValue> {
@@ -120,6 +397,7 @@ impl client::ClientSessionStore for ClientSessionMemoryCache {
         self.servers
             .lock()
             .unwrap()
+            .by_name
             .get(_server_name)
             .and_then(|sd| sd.tls12.as_ref().cloned())
     }
@@ -129,6 +407,7 @@ impl client::ClientSessionStore for ClientSessionMemoryCache {
         self.servers
             .lock()
             .unwrap()
+            .by_name
             .get_mut(_server_name)
             .and_then(|data| data.tls12.take());
     }
@@ -138,26 +417,64 @@ impl client::ClientSessionStore for ClientSessionMemoryCache {
         server_name: &ServerName,
         value: persist::Tls13ClientSessionValue,
     ) {
-        self.servers
-            .lock()
-            .unwrap()
-            .get_or_insert_default_and_edit(server_name.clone(), |data| {
-                if data.tls13.len() == data.tls13.capacity() {
+        if self.max_tls13_tickets == 0 || self.max_tls13_tickets_per_server == 0 {
+            return;
+        }
+
+        let mut servers = self.servers.lock().unwrap();
+
+        let mut evicted_for_server = false;
+        servers.edit(server_name, |data| {
+            if data.tls13.len() >= self.max_tls13_tickets_per_server {
+                data.tls13.pop_front();
+                evicted_for_server = true;
+            }
+            data.tls13.push_back(value);
+        });
+
+        if evicted_for_server {
+            servers.forget_oldest_tls13_ticket(server_name);
+        }
+        servers
+            .tls13_order
+            .push_back(server_name.clone());
+        servers.touch(server_name);
+
+        while servers.tls13_order.len() > self.max_tls13_tickets {
+            if let Some(oldest) = servers.tls13_order.pop_front() {
+                if let Some(data) = servers.by_name.get_mut(&oldest) {
                     data.tls13.pop_front();
                 }
-                data.tls13.push_back(value);
-            });
+            }
+        }
     }
 
     fn take_tls13_ticket(
         &self,
         server_name: &ServerName,
     ) -> Option<persist::Tls13ClientSessionValue> {
-        self.servers
-            .lock()
-            .unwrap()
+        let mut servers = self.servers.lock().unwrap();
+        let value = servers
+            .by_name
             .get_mut(server_name)
-            .and_then(|data| data.tls13.pop_back())
+            .and_then(|data| data.tls13.pop_back())?;
+        servers.forget_newest_tls13_ticket(server_name);
+        servers.touch(server_name);
+        Some(value)
+    }
+}
+
+/// The default `NormalizesServerName`, which lowercases DNS names.
+pub(super) struct LowercaseServerName;
+
+impl client::danger::NormalizesServerName for LowercaseServerName {
+    fn normalize(&self, server_name: &ServerName) -> ServerName {
+        match server_name {
+            ServerName::DnsName(dns_name) => {
+                ServerName::DnsName(dns_name.borrow().to_lowercase_owned())
+            }
+            ServerName::IpAddress(_) => server_name.clone(),
+        }
     }
 }
 
@@ -205,15 +522,91 @@ impl client::ResolvesClientCert for AlwaysResolvesClientCert {
     }
 }
 
+/// A `ResolvesClientCert` that chooses among several certificate chains,
+/// using the issuers and signature schemes acceptable to the server.
+///
+/// A chain is chosen if one of its certificates was issued by an issuer the
+/// server named in its `CertificateRequest` (or the server named none), and
+/// its key can sign with one of the server's signature schemes.  Chains are
+/// tried in the order they were added.  If none is suitable, the handshake
+/// continues without client authentication.
+pub struct MultiClientCert {
+    keys: Vec<Arc<sign::CertifiedKey>>,
+}
+
+impl MultiClientCert {
+    /// Create a new and empty (i.e., knows no certificates) resolver.
+    pub fn new() -> Self {
+        Self { keys: Vec::new() }
+    }
+
+    /// Add a certificate chain and key.
+    ///
+    /// This function fails if the certificate chain is empty.
+    pub fn add(&mut self, ck: sign::CertifiedKey) -> Result<(), Error> {
+        ck.end_entity_cert()?;
+        self.keys.push(Arc::new(ck));
+        Ok(())
+    }
+
+    #[cfg(feature = "ring")]
+    pub(super) fn from_chains_and_keys(
+        certs: Vec<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)>,
+    ) -> Result<Self, Error> {
+        let mut resolver = Self::new();
+        for (chain, priv_key) in certs {
+            let key = ring::sign::any_supported_type(&priv_key)
+                .map_err(|_| Error::General("invalid private key".into()))?;
+            resolver.add(sign::CertifiedKey::new(chain, key))?;
+        }
+        Ok(resolver)
+    }
+}
+
+impl Default for MultiClientCert {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl client::ResolvesClientCert for MultiClientCert {
+    fn resolve(
+        &self,
+        acceptable_issuers: &[&[u8]],
+        sigschemes: &[SignatureScheme],
+    ) -> Option<Arc<sign::CertifiedKey>> {
+        self.keys
+            .iter()
+            .find(|ck| {
+                let issuer_acceptable = acceptable_issuers.is_empty()
+                    || ck.cert.iter().any(|cert| {
+                        x509::certificate_issuer(cert)
+                            .map_or(false, |issuer| acceptable_issuers.contains(&issuer))
+                    });
+                issuer_acceptable
+                    && ck
+                        .key
+                        .choose_scheme(sigschemes)
+                        .is_some()
+            })
+            .map(Arc::clone)
+    }
+
+    fn has_certs(&self) -> bool {
+        !self.keys.is_empty()
+    }
+}
+
 #[cfg(all(test, feature = "ring"))]
 mod tests {
-    use super::NoClientSessionStorage;
+    use super::{ClientSessionMemoryCache, NoClientSessionStorage};
     use crate::client::ClientSessionStore;
     use crate::msgs::enums::NamedGroup;
     #[cfg(feature = "tls12")]
     use crate::msgs::handshake::SessionId;
     use crate::msgs::persist::Tls13ClientSessionValue;
     use crate::suites::SupportedCipherSuite;
+    use crate::ServerName;
 
     use pki_types::UnixTime;
 
@@ -275,4 +668,128 @@ mod tests {
         );
         assert!(c.take_tls13_ticket(&name).is_none());
     }
+
+    #[test]
+    fn test_clientsessionmemorycache_enforces_ticket_limits() {
+        let c = ClientSessionMemoryCache::new_with_limits(10, 4);
+        let now = UnixTime::now();
+        let names = (0..5)
+            .map(|i| {
+                format!("server{}.example.com", i)
+                    .as_str()
+                    .try_into()
+                    .unwrap()
+            })
+            .collect::<Vec<ServerName>>();
+
+        let tls13_suite = match crate::cipher_suite::TLS13_AES_256_GCM_SHA384 {
+            SupportedCipherSuite::Tls13(inner) => inner,
+            #[cfg(feature = "tls12")]
+            _ => unreachable!(),
+        };
+
+        for (i, name) in names.iter().enumerate() {
+            for j in 0..6u8 {
+                c.insert_tls13_ticket(
+                    name,
+                    Tls13ClientSessionValue::new(
+                        tls13_suite,
+                        vec![i as u8, j],
+                        Vec::new(),
+                        Vec::new(),
+                        now,
+                        0,
+                        0,
+                        0,
+                    ),
+                );
+            }
+        }
+
+        let mut remaining = Vec::new();
+        for name in &names {
+            let mut tickets = Vec::new();
+            while let Some(ticket) = c.take_tls13_ticket(name) {
+                tickets.push(ticket.ticket().to_vec());
+            }
+            assert!(tickets.len() <= 4);
+            remaining.push(tickets);
+        }
+
+        // globally, only the ten newest tickets survive; and for each
+        // server only the four newest, returned newest first.
+        assert_eq!(
+            remaining,
+            vec![
+                vec![],
+                vec![],
+                vec![vec![2, 5], vec![2, 4]],
+                vec![vec![3, 5], vec![3, 4], vec![3, 3], vec![3, 2]],
+                vec![vec![4, 5], vec![4, 4], vec![4, 3], vec![4, 2]],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_clientsessionmemorycache_evicts_least_recently_used_server() {
+        let c = ClientSessionMemoryCache::new_with_limits(4, 4);
+        let now = UnixTime::now();
+        let names = ["a.example.com", "b.example.com", "c.example.com"]
+            .map(|name| ServerName::try_from(name).unwrap());
+
+        let tls13_suite = match crate::cipher_suite::TLS13_AES_256_GCM_SHA384 {
+            SupportedCipherSuite::Tls13(inner) => inner,
+            #[cfg(feature = "tls12")]
+            _ => unreachable!(),
+        };
+        let insert = |i: usize, j: u8| {
+            c.insert_tls13_ticket(
+                &names[i],
+                Tls13ClientSessionValue::new(
+                    tls13_suite,
+                    vec![i as u8, j],
+                    Vec::new(),
+                    Vec::new(),
+                    now,
+                    0,
+                    0,
+                    0,
+                ),
+            )
+        };
+
+        insert(0, 0);
+        insert(0, 1);
+        insert(1, 0);
+        insert(1, 1);
+
+        // resuming with the first server makes it the most recently used
+        assert_eq!(
+            c.take_tls13_ticket(&names[0])
+                .unwrap()
+                .ticket(),
+            &[0, 1]
+        );
+
+        // so the second server loses its oldest ticket to make room
+        insert(2, 0);
+        insert(2, 1);
+
+        let mut remaining = Vec::new();
+        for name in &names {
+            let mut tickets = Vec::new();
+            while let Some(ticket) = c.take_tls13_ticket(name) {
+                tickets.push(ticket.ticket().to_vec());
+            }
+            remaining.push(tickets);
+        }
+        assert_eq!(
+            remaining,
+            vec![
+                vec![vec![0, 0]],
+                vec![vec![1, 1]],
+                vec![vec![2, 1], vec![2, 0]],
+            ]
+        );
+    }
 }
@@ -1,22 +1,26 @@
 #[cfg(feature = "logging")]
 use crate::bs_debug;
 use crate::check::inappropriate_handshake_message;
+#[cfg(feature = "dangerous_transcript")]
+use crate::common_state::TranscriptPoint;
 use crate::common_state::{CommonState, State};
 use crate::conn::ConnectionRandoms;
-use crate::crypto::ActiveKeyExchange;
-use crate::enums::{AlertDescription, CipherSuite, ContentType, HandshakeType, ProtocolVersion};
+use crate::enums::{
+    AlertDescription, CipherSuite, ContentType, HandshakeType, ProtocolVersion, SignatureScheme,
+};
 use crate::error::{Error, PeerIncompatible, PeerMisbehaved};
 use crate::hash_hs::HandshakeHashBuffer;
 #[cfg(feature = "logging")]
 use crate::log::{debug, trace};
-use crate::msgs::base::Payload;
-use crate::msgs::enums::{Compression, ExtensionType};
-use crate::msgs::enums::{ECPointFormat, PSKKeyExchangeMode};
+use crate::msgs::base::{Payload, PayloadU16};
+use crate::msgs::codec::Codec;
+use crate::msgs::enums::{CertificateType, Compression, ExtensionType};
+use crate::msgs::enums::{ECPointFormat, NamedGroup};
 use crate::msgs::handshake::ConvertProtocolNameList;
 use crate::msgs::handshake::{CertificateStatusRequest, ClientSessionTicket};
 use crate::msgs::handshake::{ClientExtension, HasServerExtensions};
 use crate::msgs::handshake::{ClientHelloPayload, HandshakeMessagePayload, HandshakePayload};
-use crate::msgs::handshake::{HelloRetryRequest, KeyShareEntry};
+use crate::msgs::handshake::{HelloRetryRequest, KeyShareEntry, UnknownExtension};
 use crate::msgs::handshake::{Random, SessionId};
 use crate::msgs::message::{Message, MessagePayload};
 use crate::msgs::persist;
@@ -25,8 +29,8 @@ use crate::SupportedCipherSuite;
 
 #[cfg(feature = "tls12")]
 use super::tls12;
-use super::Tls12Resumption;
-use crate::client::client_conn::ClientConnectionData;
+use super::{Tls12Resumption, Tls13PskKeyExchangeModes};
+use crate::client::client_conn::{ClientConnectionData, ServerProbe};
 use crate::client::common::ClientHelloDetails;
 use crate::client::{tls13, ClientConfig, ServerName};
 
@@ -42,13 +46,27 @@ pub(super) type ClientContext<'a> = crate::common_state::Context<'a, ClientConne
 fn find_session(
     server_name: &ServerName,
     config: &ClientConfig,
+    now: UnixTime,
     #[cfg(feature = "quic")] cx: &mut ClientContext<'_>,
 ) -> Option<persist::Retrieved<ClientSessionValue>> {
+    if let Some(offer) = &config.resumption.offer_predicate {
+        if !offer(server_name) {
+            debug!("Not offering resumption to {:?}", server_name);
+            return None;
+        }
+    }
+
     #[allow(clippy::let_and_return, clippy::unnecessary_lazy_evaluations)]
     let found = config
         .resumption
-        .store
-        .take_tls13_ticket(server_name)
+        .tls13_resumption
+        .then(|| {
+            config
+                .resumption
+                .store
+                .take_tls13_ticket(server_name)
+        })
+        .flatten()
         .map(ClientSessionValue::Tls13)
         .or_else(|| {
             #[cfg(feature = "tls12")]
@@ -64,7 +82,7 @@ fn find_session(
             None
         })
         .and_then(|resuming| {
-            let retrieved = persist::Retrieved::new(resuming, UnixTime::now());
+            let retrieved = persist::Retrieved::new(resuming, now);
             match retrieved.has_expired() {
                 false => Some(retrieved),
                 true => None,
@@ -93,6 +111,12 @@ pub(super) fn start_handshake(
     config: Arc<ClientConfig>,
     cx: &mut ClientContext<'_>,
 ) -> NextStateOrError {
+    if config.raw_public_keys && !config.supports_version(ProtocolVersion::TLSv1_3) {
+        return Err(Error::General(
+            "TLS 1.3 support is required for raw public keys".into(),
+        ));
+    }
+
     let mut transcript_buffer = HandshakeHashBuffer::new();
     if config
         .client_auth_cert_resolver
@@ -101,14 +125,25 @@ pub(super) fn start_handshake(
         transcript_buffer.set_client_auth_enabled();
     }
 
-    let mut resuming = find_session(
-        &server_name,
-        &config,
-        #[cfg(feature = "quic")]
-        cx,
-    );
+    let mut resuming = if config.probe_only {
+        None
+    } else {
+        find_session(
+            &server_name,
+            &config,
+            cx.common.now(),
+            #[cfg(feature = "quic")]
+            cx,
+        )
+    };
+
+    // With only psk_ke allowed, a TLS1.3 resumption needs no key share.
+    let psk_ke_resumption = config.resumption.tls13_psk_modes
+        == Tls13PskKeyExchangeModes::PskKeOnly
+        && matches!(&resuming, Some(resuming)
+            if resuming.tls13().is_some() && !resuming.ticket().is_empty());
 
-    let key_share = if config.supports_version(ProtocolVersion::TLSv1_3) {
+    let key_share = if config.supports_version(ProtocolVersion::TLSv1_3) && !psk_ke_resumption {
         Some(tls13::initial_key_share(&config, &server_name)?)
     } else {
         None
@@ -143,6 +178,7 @@ pub(super) fn start_handshake(
     };
 
     let random = Random::new(config.provider)?;
+    let grease = Grease::new(&config)?;
 
     Ok(emit_client_hello_for_retry(
         transcript_buffer,
@@ -160,6 +196,7 @@ pub(super) fn start_handshake(
             hello: ClientHelloDetails::new(),
             session_id,
             server_name,
+            grease,
         },
         cx,
     ))
@@ -169,7 +206,7 @@ struct ExpectServerHello {
     input: ClientHelloInput,
     transcript_buffer: HandshakeHashBuffer,
     early_key_schedule: Option<KeyScheduleEarly>,
-    offered_key_share: Option<Box<dyn ActiveKeyExchange>>,
+    offered_key_share: Option<tls13::OfferedKeyShare>,
     suite: Option<SupportedCipherSuite>,
 }
 
@@ -188,19 +225,94 @@ struct ClientHelloInput {
     hello: ClientHelloDetails,
     session_id: SessionId,
     server_name: ServerName,
+    grease: Grease,
+}
+
+/// GREASE values chosen once per connection, so that a ClientHello sent
+/// in response to a HelloRetryRequest repeats them.
+struct Grease {
+    supported_version: Option<ProtocolVersion>,
+    ech: Option<ClientExtension>,
+}
+
+impl Grease {
+    fn new(config: &ClientConfig) -> Result<Self, Error> {
+        let supported_version = if config.enable_grease_supported_versions {
+            let mut random = [0u8; 1];
+            config
+                .provider
+                .fill_random(&mut random)?;
+            Some(ProtocolVersion::Unknown(grease_value(random[0])))
+        } else {
+            None
+        };
+
+        let ech = if config.enable_grease_ech && config.supports_version(ProtocolVersion::TLSv1_3) {
+            Some(grease_ech_extension(config)?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            supported_version,
+            ech,
+        })
+    }
+}
+
+/// Returns one of the reserved GREASE values `0x0a0a`, `0x1a1a` .. `0xfafa`.
+fn grease_value(random: u8) -> u16 {
+    let byte = (random & 0xf0) | 0x0a;
+    u16::from_be_bytes([byte, byte])
+}
+
+/// Builds an outer `ECHClientHello` with random contents, as described in
+/// draft-ietf-tls-esni-18 section 6.2 for clients not offering real ECH.
+fn grease_ech_extension(config: &ClientConfig) -> Result<ClientExtension, Error> {
+    const OUTER: u8 = 0;
+    const HKDF_SHA256: u16 = 0x0001;
+    const AES_128_GCM: u16 = 0x0001;
+    const ENC_LEN: usize = 32;
+
+    // vary the payload length like a real, padded, inner ClientHello would
+    let mut random = [0u8; 1];
+    config
+        .provider
+        .fill_random(&mut random)?;
+    let payload_len = 144 + 32 * usize::from(random[0] & 0x03);
+    let mut rand = vec![0u8; 1 + ENC_LEN + payload_len];
+    config.provider.fill_random(&mut rand)?;
+    let (config_id, rand) = rand.split_at(1);
+    let (enc, payload) = rand.split_at(ENC_LEN);
+
+    let mut body = Vec::new();
+    OUTER.encode(&mut body);
+    HKDF_SHA256.encode(&mut body);
+    AES_128_GCM.encode(&mut body);
+    config_id[0].encode(&mut body);
+    PayloadU16::new(enc.to_vec()).encode(&mut body);
+    PayloadU16::new(payload.to_vec()).encode(&mut body);
+
+    Ok(ClientExtension::Unknown(UnknownExtension {
+        typ: ExtensionType::EncryptedClientHello,
+        payload: Payload::new(body),
+    }))
 }
 
 fn emit_client_hello_for_retry(
     mut transcript_buffer: HandshakeHashBuffer,
     retryreq: Option<&HelloRetryRequest>,
-    key_share: Option<Box<dyn ActiveKeyExchange>>,
+    key_share: Option<tls13::OfferedKeyShare>,
     extra_exts: Vec<ClientExtension>,
     suite: Option<SupportedCipherSuite>,
     mut input: ClientHelloInput,
     cx: &mut ClientContext<'_>,
 ) -> NextState {
     let config = &input.config;
-    let support_tls12 = config.supports_version(ProtocolVersion::TLSv1_2) && !cx.common.is_quic();
+    // Raw public keys are only supported in TLS1.3.
+    let support_tls12 = config.supports_version(ProtocolVersion::TLSv1_2)
+        && !cx.common.is_quic()
+        && !config.raw_public_keys;
     let support_tls13 = config.supports_version(ProtocolVersion::TLSv1_3);
 
     let mut supported_versions = Vec::new();
@@ -215,9 +327,17 @@ fn emit_client_hello_for_retry(
     // should be unreachable thanks to config builder
     assert!(!supported_versions.is_empty());
 
-    let mut exts = vec![
-        ClientExtension::SupportedVersions(supported_versions),
-        ClientExtension::ECPointFormats(ECPointFormat::SUPPORTED.to_vec()),
+    if let Some(grease) = input.grease.supported_version {
+        supported_versions.insert(0, grease);
+    }
+
+    let mut exts = vec![ClientExtension::SupportedVersions(supported_versions)];
+    if config.send_ec_point_formats {
+        exts.push(ClientExtension::ECPointFormats(
+            ECPointFormat::SUPPORTED.to_vec(),
+        ));
+    }
+    exts.extend([
         ClientExtension::NamedGroups(
             config
                 .kx_groups
@@ -232,15 +352,27 @@ fn emit_client_hello_for_retry(
         ),
         ClientExtension::ExtendedMasterSecretRequest,
         ClientExtension::CertificateStatusRequest(CertificateStatusRequest::build_ocsp()),
-    ];
+    ]);
 
     if let (Some(sni_name), true) = (input.server_name.for_sni(), config.enable_sni) {
         exts.push(ClientExtension::make_sni(sni_name));
     }
 
+    if !config.trusted_ca_keys.is_empty() {
+        exts.push(ClientExtension::TrustedCaKeys(
+            config.trusted_ca_keys.clone(),
+        ));
+    }
+
+    if config.raw_public_keys {
+        exts.push(ClientExtension::ServerCertificateTypes(vec![
+            CertificateType::RawPublicKey,
+        ]));
+    }
+
     if let Some(key_share) = &key_share {
         debug_assert!(support_tls13);
-        let key_share = KeyShareEntry::new(key_share.group(), key_share.pub_key());
+        let key_share = KeyShareEntry::new(key_share.kx.group(), key_share.kx.pub_key());
         exts.push(ClientExtension::KeyShare(vec![key_share]));
     }
 
@@ -248,10 +380,11 @@ fn emit_client_hello_for_retry(
         exts.push(ClientExtension::Cookie(cookie.clone()));
     }
 
-    if support_tls13 {
-        // We could support PSK_KE here too. Such connections don't
-        // have forward secrecy, and are similar to TLS1.2 resumption.
-        let psk_modes = vec![PSKKeyExchangeMode::PSK_DHE_KE];
+    if support_tls13 && config.resumption.tls13_resumption {
+        let psk_modes = config
+            .resumption
+            .tls13_psk_modes
+            .modes();
         exts.push(ClientExtension::PresharedKeyModes(psk_modes));
     }
 
@@ -265,6 +398,10 @@ fn emit_client_hello_for_retry(
         )));
     }
 
+    if let Some(grease_ech) = &input.grease.ech {
+        exts.push(grease_ech.clone());
+    }
+
     // Extra extensions must be placed before the PSK extension
     exts.extend(extra_exts.iter().cloned());
 
@@ -311,7 +448,7 @@ fn emit_client_hello_for_retry(
         version: if retryreq.is_some() {
             ProtocolVersion::TLSv1_2
         } else {
-            ProtocolVersion::TLSv1_0
+            config.client_hello_record_version
         },
         payload: MessagePayload::handshake(chp),
     };
@@ -424,12 +561,81 @@ fn prepare_resumption<'a>(
     // If the selected cipher suite can't select from the session's, we can't resume.
     if let Some(suite) = suite {
         suite.can_resume_from(tls13.suite())?;
+
+        // Nor do we offer to resume into a weaker suite than the session's.
+        if !suite.is_at_least_as_strong_as(tls13.suite()) {
+            debug!("Not offering TLS1.3 session to resume into a weaker suite");
+            return None;
+        }
+
+        // A suite is only known here after a HelloRetryRequest.
+        if config
+            .resumption
+            .omit_psk_after_hello_retry
+        {
+            debug!("Not offering TLS1.3 session after HelloRetryRequest");
+            return None;
+        }
     }
 
     tls13::prepare_resumption(config, cx, &tls13, exts, suite.is_some());
     Some(tls13)
 }
 
+/// End a probe-only handshake, having learned `probe`.
+pub(super) fn finish_probe(cx: &mut ClientContext<'_>, probe: ServerProbe) -> NextState {
+    debug!("Probe complete: {:?}", probe);
+    cx.data.probe = Some(probe);
+    cx.common.send_close_notify();
+    Box::new(ProbeComplete)
+}
+
+/// Where a probe-only connection ends up: having closed the connection,
+/// the rest of the server's flight is discarded.
+struct ProbeComplete;
+
+impl State<ClientConnectionData> for ProbeComplete {
+    fn handle(self: Box<Self>, _cx: &mut ClientContext<'_>, _m: Message) -> NextStateOrError {
+        Ok(self)
+    }
+}
+
+/// Refuse finite field groups smaller than `ClientConfig::min_ffdhe_bits`.
+pub(super) fn check_server_kx_group(
+    common: &mut CommonState,
+    config: &ClientConfig,
+    group: NamedGroup,
+) -> Result<(), Error> {
+    match group.ffdhe_bits() {
+        Some(bits) if bits < config.min_ffdhe_bits => Err(common.send_fatal_alert(
+            AlertDescription::InsufficientSecurity,
+            PeerIncompatible::ServerSelectedWeakFfdheGroup,
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Refuse a server signature made with a scheme we did not offer in `signature_algorithms`,
+/// unless `ClientConfig::enforce_advertised_signature_schemes` is disabled.
+pub(super) fn check_server_signature_scheme(
+    common: &mut CommonState,
+    config: &ClientConfig,
+    scheme: SignatureScheme,
+) -> Result<(), Error> {
+    if config.enforce_advertised_signature_schemes
+        && !config
+            .verifier
+            .supported_verify_schemes()
+            .contains(&scheme)
+    {
+        return Err(common.send_fatal_alert(
+            AlertDescription::IllegalParameter,
+            PeerMisbehaved::SignedHandshakeWithUnadvertisedSigScheme,
+        ));
+    }
+    Ok(())
+}
+
 pub(super) fn process_alpn_protocol(
     common: &mut CommonState,
     config: &ClientConfig,
@@ -449,6 +655,12 @@ pub(super) fn process_alpn_protocol(
         }
     }
 
+    common.alpn_common_protocols = common
+        .alpn_protocol
+        .iter()
+        .cloned()
+        .collect();
+
     #[cfg(feature = "quic")]
     {
         // RFC 9001 says: "While ALPN only specifies that servers use this alert, QUIC clients MUST
@@ -495,7 +707,7 @@ impl State<ClientConnectionData> for ExpectServerHello {
 
         let version = match server_version {
             TLSv1_3 if tls13_supported => TLSv1_3,
-            TLSv1_2 if config.supports_version(TLSv1_2) => {
+            TLSv1_2 if config.supports_version(TLSv1_2) && !config.raw_public_keys => {
                 if cx.data.early_data.is_enabled() && cx.common.early_traffic {
                     // The client must fail with a dedicated error code if the server
                     // responds with TLS 1.2 when offering 0-RTT.
@@ -527,6 +739,7 @@ impl State<ClientConnectionData> for ExpectServerHello {
             }
         };
 
+        // We only ever offer the null compression method, in both TLS1.2 and TLS1.3.
         if server_hello.compression_method != Compression::Null {
             return Err({
                 cx.common.send_fatal_alert(
@@ -612,6 +825,9 @@ impl State<ClientConnectionData> for ExpectServerHello {
             .transcript_buffer
             .start_hash(suite.hash_provider());
         transcript.add_message(&m);
+        #[cfg(feature = "dangerous_transcript")]
+        cx.common
+            .record_transcript(TranscriptPoint::ServerHello, &transcript);
 
         let randoms = ConnectionRandoms::new(self.input.random, server_hello.random);
         // For TLS1.3, start message encryption using
@@ -628,6 +844,9 @@ impl State<ClientConnectionData> for ExpectServerHello {
                         ClientSessionValue::Tls12(_) => None,
                     });
 
+                // TLS1.3 client auth signs the transcript hash only.
+                transcript.abandon_client_auth();
+
                 tls13::handle_server_hello(
                     self.input.config,
                     cx,
@@ -639,8 +858,7 @@ impl State<ClientConnectionData> for ExpectServerHello {
                     transcript,
                     self.early_key_schedule,
                     self.input.hello,
-                    // We always send a key share when TLS 1.3 is enabled.
-                    self.offered_key_share.unwrap(),
+                    self.offered_key_share,
                     self.input.sent_tls13_fake_ccs,
                 )
             }
@@ -686,16 +904,23 @@ impl ExpectServerHelloOrHelloRetryRequest {
         trace!("Got HRR {:?}", hrr);
 
         cx.common.check_aligned_handshake()?;
+        cx.data.did_hello_retry_request = true;
 
         let cookie = hrr.get_cookie();
         let req_group = hrr.get_requested_key_share_group();
+        if let Some(group) = req_group {
+            check_server_kx_group(cx.common, &self.next.input.config, group)?;
+        }
 
-        // We always send a key share when TLS 1.3 is enabled.
-        let offered_key_share = self.next.offered_key_share.unwrap();
+        // We send a key share when TLS 1.3 is enabled, unless resuming with psk_ke.
+        let offered_key_share = self.next.offered_key_share;
+        let offered_group = offered_key_share
+            .as_ref()
+            .map(|share| share.kx.group());
 
         // A retry request is illegal if it contains no cookie and asks for
         // retry of a group we already sent.
-        if cookie.is_none() && req_group == Some(offered_key_share.group()) {
+        if cookie.is_none() && req_group.is_some() && req_group == offered_group {
             return Err({
                 cx.common.send_fatal_alert(
                     AlertDescription::IllegalParameter,
@@ -811,8 +1036,9 @@ impl ExpectServerHelloOrHelloRetryRequest {
             cx.data.early_data.rejected();
         }
 
-        let key_share = match req_group {
-            Some(group) if group != offered_key_share.group() => {
+        let key_share = match (req_group, offered_key_share) {
+            (Some(group), Some(offered)) if group == offered.kx.group() => offered,
+            (Some(group), _) => {
                 let skxg = match config.find_kx_group(group) {
                     Some(skxg) => skxg,
                     None => {
@@ -823,10 +1049,10 @@ impl ExpectServerHelloOrHelloRetryRequest {
                     }
                 };
 
-                skxg.start()
-                    .map_err(|_| Error::FailedToGetRandomBytes)?
+                tls13::OfferedKeyShare::start(skxg)?
             }
-            _ => offered_key_share,
+            (None, Some(offered)) => offered,
+            (None, None) => tls13::initial_key_share(config, &self.next.input.server_name)?,
         };
 
         Ok(emit_client_hello_for_retry(
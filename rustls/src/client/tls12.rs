@@ -1,9 +1,11 @@
 use crate::check::{inappropriate_handshake_message, inappropriate_message};
+#[cfg(feature = "dangerous_transcript")]
+use crate::common_state::TranscriptPoint;
 use crate::common_state::{CommonState, Side, State};
 use crate::conn::ConnectionRandoms;
 use crate::enums::ProtocolVersion;
 use crate::enums::{AlertDescription, ContentType, HandshakeType};
-use crate::error::{Error, InvalidMessage, PeerMisbehaved};
+use crate::error::{Error, InvalidMessage, PeerIncompatible, PeerMisbehaved};
 use crate::hash_hs::HandshakeHash;
 #[cfg(feature = "logging")]
 use crate::log::{debug, trace, warn};
@@ -16,18 +18,17 @@ use crate::msgs::handshake::{
 };
 use crate::msgs::message::{Message, MessagePayload};
 use crate::msgs::persist;
-use crate::sign::Signer;
+use crate::sign::{Signer, SigningRetryPolicy};
 use crate::suites::{PartiallyExtractedSecrets, SupportedCipherSuite};
 use crate::tls12::{self, ConnectionSecrets, Tls12CipherSuite};
 use crate::verify::{self, DigitallySignedStruct};
 
-use super::client_conn::ClientConnectionData;
+use super::client_conn::{ClientConnectionData, ServerProbe};
 use super::hs::ClientContext;
 use crate::client::common::ClientAuthDetails;
 use crate::client::common::ServerCertDetails;
 use crate::client::{hs, ClientConfig, ServerName};
 
-use pki_types::UnixTime;
 use subtle::ConstantTimeEq;
 
 use alloc::sync::Arc;
@@ -382,6 +383,23 @@ impl State<ClientConnectionData> for ExpectServerKx {
                 )
             })?;
 
+        hs::check_server_kx_group(
+            cx.common,
+            &self.config,
+            ecdhe.params.curve_params.named_group,
+        )?;
+        cx.data.kx_group = Some(ecdhe.params.curve_params.named_group);
+
+        if self.config.probe_only {
+            let probe = ServerProbe {
+                version: ProtocolVersion::TLSv1_2,
+                suite: self.suite.into(),
+                kx_group: ecdhe.params.curve_params.named_group,
+                cert_chain: self.server_cert.cert_chain,
+            };
+            return Ok(hs::finish_probe(cx, probe));
+        }
+
         // Save the signature and signed parameters for later verification.
         let mut kx_params = Vec::new();
         ecdhe.params.encode(&mut kx_params);
@@ -413,6 +431,10 @@ fn emit_certificate(
     cert_chain: CertificatePayload,
     common: &mut CommonState,
 ) {
+    if !cert_chain.is_empty() {
+        common.sent_certificates = Some(cert_chain.clone());
+    }
+
     let cert = Message {
         version: ProtocolVersion::TLSv1_2,
         payload: MessagePayload::handshake(HandshakeMessagePayload {
@@ -446,14 +468,22 @@ fn emit_clientkx(transcript: &mut HandshakeHash, common: &mut CommonState, pub_k
 fn emit_certverify(
     transcript: &mut HandshakeHash,
     signer: &dyn Signer,
+    retry_policy: SigningRetryPolicy,
     common: &mut CommonState,
 ) -> Result<(), Error> {
+    // The transcript is only discarded if it grew too large to keep.
     let message = transcript
         .take_handshake_buf()
-        .ok_or_else(|| Error::General("Expected transcript".to_owned()))?;
+        .ok_or_else(|| {
+            common.send_fatal_alert(
+                AlertDescription::HandshakeFailure,
+                PeerIncompatible::HandshakeTooLargeForClientAuth,
+            )
+        })?;
 
     let scheme = signer.scheme();
-    let sig = signer.sign(&message)?;
+    common.record_signature_scheme(scheme);
+    let sig = retry_policy.sign(signer, &message)?;
     let body = DigitallySignedStruct::new(scheme, sig);
 
     let m = Message {
@@ -496,6 +526,8 @@ fn emit_finished(
     };
 
     transcript.add_message(&f);
+    #[cfg(feature = "dangerous_transcript")]
+    common.record_transcript(TranscriptPoint::ClientFinished, transcript);
     common.send_msg(f, true);
 }
 
@@ -705,12 +737,15 @@ impl State<ClientConnectionData> for ExpectServerDone {
         let cert_verified = st
             .config
             .verifier
-            .verify_server_cert(
+            .verify_server_cert_with_alpn(
                 end_entity,
                 intermediates,
-                &st.server_name,
+                &st.config
+                    .server_name_normalizer
+                    .normalize(&st.server_name),
                 &st.server_cert.ocsp_response,
-                UnixTime::now(),
+                cx.common.alpn_protocol.as_deref(),
+                cx.common.now(),
             )
             .map_err(|err| {
                 cx.common
@@ -738,6 +773,9 @@ impl State<ClientConnectionData> for ExpectServerDone {
                 return Err(PeerMisbehaved::SignedKxWithWrongAlgorithm.into());
             }
 
+            hs::check_server_signature_scheme(cx.common, &st.config, sig.scheme)?;
+            cx.common
+                .record_signature_scheme(sig.scheme);
             st.config
                 .verifier
                 .verify_tls12_signature(&message, &st.server_cert.cert_chain[0], sig)
@@ -767,6 +805,9 @@ impl State<ClientConnectionData> for ExpectServerDone {
                 return Err(PeerMisbehaved::SelectedUnofferedKxGroup.into());
             }
         };
+        if let Some(detector) = &st.config.key_share_reuse_detector {
+            detector.check(cx.common, named_group, &ecdh_params.public.0)?;
+        }
         let kx = skxg
             .start()
             .map_err(|_| Error::FailedToGetRandomBytes)?;
@@ -781,13 +822,20 @@ impl State<ClientConnectionData> for ExpectServerDone {
 
         // 5c.
         if let Some(ClientAuthDetails::Verify { signer, .. }) = &st.client_auth {
-            emit_certverify(&mut transcript, signer.as_ref(), cx.common)?;
+            emit_certverify(
+                &mut transcript,
+                signer.as_ref(),
+                st.config.signing_retry_policy,
+                cx.common,
+            )?;
         }
 
         // 5d.
         emit_ccs(cx.common);
 
         // 5e. Now commit secrets.
+        cx.common
+            .record_key_shares(skxg, kx.pub_key(), &ecdh_params.public.0);
         let secrets = ConnectionSecrets::from_key_exchange(
             kx,
             &ecdh_params.public.0,
@@ -979,7 +1027,7 @@ impl ExpectFinished {
                 .peer_certificates
                 .clone()
                 .unwrap_or_default(),
-            UnixTime::now(),
+            cx.common.now(),
             lifetime,
             self.using_ems,
         );
@@ -1014,9 +1062,15 @@ impl State<ClientConnectionData> for ExpectFinished {
                     .send_fatal_alert(AlertDescription::DecryptError, Error::DecryptError));
             }
         };
+        #[cfg(feature = "dangerous_transcript")]
+        cx.common
+            .record_peer_finished(&finished.0);
 
         // Hash this message too.
         st.transcript.add_message(&m);
+        #[cfg(feature = "dangerous_transcript")]
+        cx.common
+            .record_transcript(TranscriptPoint::ServerFinished, &st.transcript);
 
         st.save_session(cx);
 
@@ -1028,6 +1082,8 @@ impl State<ClientConnectionData> for ExpectFinished {
             emit_finished(&st.secrets, &mut st.transcript, cx.common);
         }
 
+        cx.common
+            .record_handshake_hash(st.transcript.get_current_hash());
         cx.common.start_traffic();
         Ok(Box::new(ExpectTraffic {
             secrets: st.secrets,
@@ -1069,8 +1125,7 @@ impl State<ClientConnectionData> for ExpectTraffic {
         context: Option<&[u8]>,
     ) -> Result<(), Error> {
         self.secrets
-            .export_keying_material(output, label, context);
-        Ok(())
+            .export_keying_material(output, label, context)
     }
 
     fn extract_secrets(&self) -> Result<PartiallyExtractedSecrets, Error> {
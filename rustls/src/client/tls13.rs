@@ -1,21 +1,23 @@
 use crate::check::inappropriate_handshake_message;
 #[cfg(feature = "quic")]
 use crate::common_state::Protocol;
+#[cfg(feature = "dangerous_transcript")]
+use crate::common_state::TranscriptPoint;
 use crate::common_state::{CommonState, Side, State};
 use crate::conn::ConnectionRandoms;
 use crate::crypto;
-use crate::crypto::ActiveKeyExchange;
+use crate::crypto::{ActiveKeyExchange, SupportedKxGroup};
 use crate::enums::{
     AlertDescription, ContentType, HandshakeType, ProtocolVersion, SignatureScheme,
 };
-use crate::error::{Error, InvalidMessage, PeerIncompatible, PeerMisbehaved};
+use crate::error::{CertificateError, Error, InvalidMessage, PeerIncompatible, PeerMisbehaved};
 use crate::hash_hs::{HandshakeHash, HandshakeHashBuffer};
 #[cfg(feature = "logging")]
 use crate::log::{debug, trace, warn};
 use crate::msgs::base::{Payload, PayloadU8};
 use crate::msgs::ccs::ChangeCipherSpecPayload;
-use crate::msgs::enums::ExtensionType;
 use crate::msgs::enums::KeyUpdateRequest;
+use crate::msgs::enums::{CertificateType, ExtensionType};
 use crate::msgs::handshake::NewSessionTicketPayloadTLS13;
 use crate::msgs::handshake::{CertificateEntry, CertificatePayloadTLS13};
 use crate::msgs::handshake::{ClientExtension, ServerExtension};
@@ -24,24 +26,22 @@ use crate::msgs::handshake::{HasServerExtensions, ServerHelloPayload};
 use crate::msgs::handshake::{PresharedKeyIdentity, PresharedKeyOffer};
 use crate::msgs::message::{Message, MessagePayload};
 use crate::msgs::persist;
-use crate::sign::{CertifiedKey, Signer};
+use crate::sign::{CertifiedKey, Signer, SigningRetryPolicy};
 use crate::suites::PartiallyExtractedSecrets;
-use crate::tls13::construct_client_verify_message;
-use crate::tls13::construct_server_verify_message;
 use crate::tls13::key_schedule::{
     KeyScheduleEarly, KeyScheduleHandshake, KeySchedulePreHandshake, KeyScheduleTraffic,
 };
 use crate::tls13::Tls13CipherSuite;
+use crate::tls13::{client_verify_message, server_verify_message};
 use crate::verify::{self, DigitallySignedStruct};
 use crate::KeyLog;
 
-use super::client_conn::ClientConnectionData;
+use super::client_conn::{ClientConnectionData, ServerProbe};
 use super::hs::ClientContext;
 use crate::client::common::ServerCertDetails;
 use crate::client::common::{ClientAuthDetails, ClientHelloDetails};
 use crate::client::{hs, ClientConfig, ClientSessionStore, ServerName};
 
-use pki_types::UnixTime;
 use subtle::ConstantTimeEq;
 
 use alloc::sync::Arc;
@@ -73,27 +73,34 @@ pub(super) fn handle_server_hello(
     transcript: HandshakeHash,
     early_key_schedule: Option<KeyScheduleEarly>,
     hello: ClientHelloDetails,
-    our_key_share: Box<dyn ActiveKeyExchange>,
+    our_key_share: Option<OfferedKeyShare>,
     mut sent_tls13_fake_ccs: bool,
 ) -> hs::NextStateOrError {
     validate_server_hello(cx.common, server_hello)?;
 
-    let their_key_share = server_hello
-        .get_key_share()
-        .ok_or_else(|| {
-            cx.common.send_fatal_alert(
-                AlertDescription::MissingExtension,
-                PeerMisbehaved::MissingKeyShare,
-            )
-        })?;
+    // The server's key share is absent only in the psk_ke mode, which
+    // is checked once we know whether it resumed.
+    let their_key_share = server_hello.get_key_share();
+    if let Some(their_key_share) = their_key_share {
+        hs::check_server_kx_group(cx.common, &config, their_key_share.group)?;
 
-    if our_key_share.group() != their_key_share.group {
-        return Err({
-            cx.common.send_fatal_alert(
-                AlertDescription::IllegalParameter,
-                PeerMisbehaved::WrongGroupForKeyShare,
-            )
-        });
+        if our_key_share
+            .as_ref()
+            .map(|share| share.kx.group())
+            != Some(their_key_share.group)
+        {
+            return Err({
+                cx.common.send_fatal_alert(
+                    AlertDescription::IllegalParameter,
+                    PeerMisbehaved::WrongGroupForKeyShare,
+                )
+            });
+        }
+        cx.data.kx_group = Some(their_key_share.group);
+
+        if let Some(detector) = &config.key_share_reuse_detector {
+            detector.check(cx.common, their_key_share.group, &their_key_share.payload.0)?;
+        }
     }
 
     let key_schedule_pre_handshake = if let (Some(selected_psk), Some(early_key_schedule)) =
@@ -147,14 +154,42 @@ pub(super) fn handle_server_hello(
         KeySchedulePreHandshake::new(suite)
     };
 
-    let shared_secret = our_key_share.complete(&their_key_share.payload.0)?;
-    let key_schedule = key_schedule_pre_handshake.into_handshake(shared_secret);
+    let key_schedule = match (our_key_share, their_key_share) {
+        (Some(our_key_share), Some(their_key_share)) => {
+            cx.common.record_key_shares(
+                our_key_share.group,
+                our_key_share.kx.pub_key(),
+                &their_key_share.payload.0,
+            );
+            let shared_secret = our_key_share
+                .kx
+                .complete(&their_key_share.payload.0)?;
 
-    // Remember what KX group the server liked for next time.
-    config
-        .resumption
-        .store
-        .set_kx_hint(&server_name, their_key_share.group);
+            // Remember what KX group the server liked for next time.
+            config
+                .resumption
+                .store
+                .set_kx_hint(&server_name, their_key_share.group);
+
+            key_schedule_pre_handshake.into_handshake(shared_secret)
+        }
+        (_, None)
+            if resuming_session.is_some()
+                && config
+                    .resumption
+                    .tls13_psk_modes
+                    .allows_psk_ke() =>
+        {
+            debug!("Resuming without (EC)DHE");
+            key_schedule_pre_handshake.into_handshake_without_dhe()
+        }
+        _ => {
+            return Err(cx.common.send_fatal_alert(
+                AlertDescription::MissingExtension,
+                PeerMisbehaved::MissingKeyShare,
+            ));
+        }
+    };
 
     // If we change keying when a subsequent handshake message is being joined,
     // the two halves will have different record layer protections.  Disallow this.
@@ -200,10 +235,25 @@ fn validate_server_hello(
     Ok(())
 }
 
+/// A key share offered in a `ClientHello`, with the group it belongs to.
+pub(super) struct OfferedKeyShare {
+    pub(super) group: &'static dyn SupportedKxGroup,
+    pub(super) kx: Box<dyn ActiveKeyExchange>,
+}
+
+impl OfferedKeyShare {
+    pub(super) fn start(group: &'static dyn SupportedKxGroup) -> Result<Self, Error> {
+        let kx = group
+            .start()
+            .map_err(|_| Error::FailedToGetRandomBytes)?;
+        Ok(Self { group, kx })
+    }
+}
+
 pub(super) fn initial_key_share(
     config: &ClientConfig,
     server_name: &ServerName,
-) -> Result<Box<dyn ActiveKeyExchange>, Error> {
+) -> Result<OfferedKeyShare, Error> {
     let group = config
         .resumption
         .store
@@ -218,9 +268,7 @@ pub(super) fn initial_key_share(
                 .expect("No kx groups configured")
         });
 
-    group
-        .start()
-        .map_err(|_| Error::FailedToGetRandomBytes)
+    OfferedKeyShare::start(group)
 }
 
 /// This implements the horrifying TLS1.3 hack where PSK binders have a
@@ -389,6 +437,41 @@ impl State<ClientConnectionData> for ExpectEncryptedExtensions {
         validate_encrypted_extensions(cx.common, &self.hello, exts)?;
         hs::process_alpn_protocol(cx.common, &self.config, exts.get_alpn_protocol())?;
 
+        // Remember the server's preferred group, if it said, so the key
+        // share we next offer is one it would choose.
+        if let Some(group) = exts
+            .get_supported_groups()
+            .and_then(|groups| {
+                groups
+                    .iter()
+                    .find_map(|group| self.config.find_kx_group(*group))
+            })
+        {
+            self.config
+                .resumption
+                .store
+                .set_kx_hint(&self.server_name, group.name());
+        }
+
+        // We only offered raw public keys, if we offered a type at all.
+        if self.config.raw_public_keys && self.resuming_session.is_none() {
+            match exts.get_server_certificate_type() {
+                Some(CertificateType::RawPublicKey) => {}
+                Some(_) => {
+                    return Err(cx.common.send_fatal_alert(
+                        AlertDescription::IllegalParameter,
+                        PeerMisbehaved::SelectedUnofferedServerCertificateType,
+                    ));
+                }
+                None => {
+                    return Err(cx.common.send_fatal_alert(
+                        AlertDescription::UnsupportedCertificate,
+                        PeerIncompatible::ServerCertificateTypeExtensionRequired,
+                    ));
+                }
+            }
+        }
+
         #[cfg(feature = "quic")]
         {
             // QUIC transport parameters
@@ -613,8 +696,11 @@ impl State<ClientConnectionData> for ExpectCertificate {
             ));
         }
 
+        let handler = &self
+            .config
+            .certificate_extension_handler;
         if cert_chain.any_entry_has_duplicate_extension()
-            || cert_chain.any_entry_has_unknown_extension()
+            || (handler.is_none() && cert_chain.any_entry_has_unknown_extension())
         {
             return Err(cx.common.send_fatal_alert(
                 AlertDescription::UnsupportedExtension,
@@ -622,9 +708,41 @@ impl State<ClientConnectionData> for ExpectCertificate {
             ));
         }
 
+        if let Some(handler) = handler {
+            for (index, entry) in cert_chain.entries.iter().enumerate() {
+                let unknown = entry.unknown_extensions();
+                if unknown.is_empty() {
+                    continue;
+                }
+
+                handler
+                    .handle(index, &unknown)
+                    .map_err(|err| {
+                        cx.common
+                            .send_fatal_alert(AlertDescription::UnsupportedExtension, err)
+                    })?;
+            }
+        }
+
         let server_cert =
             ServerCertDetails::new(cert_chain.convert(), cert_chain.get_end_entity_ocsp());
 
+        if self.config.probe_only {
+            let kx_group = cx.data.kx_group.ok_or_else(|| {
+                cx.common.send_fatal_alert(
+                    AlertDescription::InternalError,
+                    Error::General("probe has no key exchange group".into()),
+                )
+            })?;
+            let probe = ServerProbe {
+                version: ProtocolVersion::TLSv1_3,
+                suite: self.suite.into(),
+                kx_group,
+                cert_chain: server_cert.cert_chain,
+            };
+            return Ok(hs::finish_probe(cx, probe));
+        }
+
         Ok(Box::new(ExpectCertificateVerify {
             config: self.config,
             server_name: self.server_name,
@@ -666,28 +784,46 @@ impl State<ClientConnectionData> for ExpectCertificateVerify {
             .cert_chain
             .split_first()
             .ok_or(Error::NoCertificatesPresented)?;
-        let cert_verified = self
+        let server_name = self
             .config
-            .verifier
-            .verify_server_cert(
-                end_entity,
-                intermediates,
-                &self.server_name,
-                &self.server_cert.ocsp_response,
-                UnixTime::now(),
-            )
-            .map_err(|err| {
-                cx.common
-                    .send_cert_verify_error_alert(err)
-            })?;
+            .server_name_normalizer
+            .normalize(&self.server_name);
+        let cert_verified = if self.config.raw_public_keys {
+            // A raw public key is sent alone, in place of the whole chain.
+            match intermediates.is_empty() {
+                true => self
+                    .config
+                    .verifier
+                    .verify_raw_public_key(end_entity, &server_name, cx.common.now()),
+                false => Err(CertificateError::BadEncoding.into()),
+            }
+        } else {
+            self.config
+                .verifier
+                .verify_server_cert_with_alpn(
+                    end_entity,
+                    intermediates,
+                    &server_name,
+                    &self.server_cert.ocsp_response,
+                    cx.common.alpn_protocol.as_deref(),
+                    cx.common.now(),
+                )
+        }
+        .map_err(|err| {
+            cx.common
+                .send_cert_verify_error_alert(err)
+        })?;
 
         // 2. Verify their signature on the handshake.
         let handshake_hash = self.transcript.get_current_hash();
+        hs::check_server_signature_scheme(cx.common, &self.config, cert_verify.scheme)?;
+        cx.common
+            .record_signature_scheme(cert_verify.scheme);
         let sig_verified = self
             .config
             .verifier
             .verify_tls13_signature(
-                &construct_server_verify_message(&handshake_hash),
+                server_verify_message(&handshake_hash).as_ref(),
                 &self.server_cert.cert_chain[0],
                 cert_verify,
             )
@@ -732,6 +868,7 @@ fn emit_certificate_tls13(
                 .entries
                 .push(CertificateEntry::new(cert.clone()));
         }
+        common.sent_certificates = Some(certkey.cert.clone());
     }
 
     let m = Message {
@@ -748,12 +885,14 @@ fn emit_certificate_tls13(
 fn emit_certverify_tls13(
     transcript: &mut HandshakeHash,
     signer: &dyn Signer,
+    retry_policy: SigningRetryPolicy,
     common: &mut CommonState,
 ) -> Result<(), Error> {
-    let message = construct_client_verify_message(&transcript.get_current_hash());
+    let message = client_verify_message(&transcript.get_current_hash());
 
     let scheme = signer.scheme();
-    let sig = signer.sign(&message)?;
+    common.record_signature_scheme(scheme);
+    let sig = retry_policy.sign(signer, message.as_ref())?;
     let dss = DigitallySignedStruct::new(scheme, sig);
 
     let m = Message {
@@ -785,6 +924,8 @@ fn emit_finished_tls13(
     };
 
     transcript.add_message(&m);
+    #[cfg(feature = "dangerous_transcript")]
+    common.record_transcript(TranscriptPoint::ClientFinished, transcript);
     common.send_msg(m, true);
 }
 
@@ -836,8 +977,14 @@ impl State<ClientConnectionData> for ExpectFinished {
                     .send_fatal_alert(AlertDescription::DecryptError, Error::DecryptError));
             }
         };
+        #[cfg(feature = "dangerous_transcript")]
+        cx.common
+            .record_peer_finished(&finished.0);
 
         st.transcript.add_message(&m);
+        #[cfg(feature = "dangerous_transcript")]
+        cx.common
+            .record_transcript(TranscriptPoint::ServerFinished, &st.transcript);
 
         let hash_after_handshake = st.transcript.get_current_hash();
         /* The EndOfEarlyData message to server is still encrypted with early data keys,
@@ -870,7 +1017,12 @@ impl State<ClientConnectionData> for ExpectFinished {
                         auth_context,
                         cx.common,
                     );
-                    emit_certverify_tls13(&mut st.transcript, signer.as_ref(), cx.common)?;
+                    emit_certverify_tls13(
+                        &mut st.transcript,
+                        signer.as_ref(),
+                        st.config.signing_retry_policy,
+                        cx.common,
+                    )?;
                 }
             }
         }
@@ -896,10 +1048,13 @@ impl State<ClientConnectionData> for ExpectFinished {
         /* Now move to our application traffic keys. */
         cx.common.check_aligned_handshake()?;
         let key_schedule_traffic = key_schedule_pre_finished.into_traffic(cx.common);
+        cx.common
+            .record_handshake_hash(st.transcript.get_current_hash());
         cx.common.start_traffic();
 
         let st = ExpectTraffic {
             session_storage: Arc::clone(&st.config.resumption.store),
+            tls13_resumption: st.config.resumption.tls13_resumption,
             server_name: st.server_name,
             suite: st.suite,
             transcript: st.transcript,
@@ -923,6 +1078,7 @@ impl State<ClientConnectionData> for ExpectFinished {
 // and application data.
 struct ExpectTraffic {
     session_storage: Arc<dyn ClientSessionStore>,
+    tls13_resumption: bool,
     server_name: ServerName,
     suite: &'static Tls13CipherSuite,
     transcript: HandshakeHash,
@@ -946,6 +1102,11 @@ impl ExpectTraffic {
             ));
         }
 
+        if !self.tls13_resumption {
+            debug!("Ignoring ticket: TLS 1.3 resumption is disabled");
+            return Ok(());
+        }
+
         let handshake_hash = self.transcript.get_current_hash();
         let secret = self
             .key_schedule
@@ -960,7 +1121,7 @@ impl ExpectTraffic {
                 .peer_certificates
                 .clone()
                 .unwrap_or_default(),
-            UnixTime::now(),
+            cx.common.now(),
             nst.lifetime,
             nst.age_add,
             nst.get_max_early_data_size()
@@ -1063,6 +1224,15 @@ impl State<ClientConnectionData> for ExpectTraffic {
         self.key_schedule
             .extract_secrets(Side::Client)
     }
+
+    fn send_key_update(&mut self, common: &mut CommonState) -> Result<(), Error> {
+        // Any KeyUpdate we already owe the peer goes first.
+        common.perhaps_write_key_update();
+        self.key_schedule
+            .update_encrypter_and_notify(common);
+        common.perhaps_write_key_update();
+        Ok(())
+    }
 }
 
 #[cfg(feature = "quic")]
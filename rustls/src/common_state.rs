@@ -1,24 +1,36 @@
-use crate::enums::{AlertDescription, ContentType, HandshakeType, ProtocolVersion};
+use crate::crypto::hash;
+use crate::crypto::{CryptoProvider, SupportedKxGroup};
+use crate::enums::{
+    AlertDescription, ContentType, HandshakeType, ProtocolVersion, SignatureScheme,
+};
 use crate::error::{Error, InvalidMessage, PeerMisbehaved};
+#[cfg(feature = "dangerous_transcript")]
+use crate::hash_hs::HandshakeHash;
 #[cfg(feature = "logging")]
 use crate::log::{debug, warn};
-use crate::msgs::alert::AlertMessagePayload;
+use crate::msgs::alert::{Alert, AlertMessagePayload};
 use crate::msgs::base::Payload;
 use crate::msgs::enums::{AlertLevel, KeyUpdateRequest};
-use crate::msgs::fragmenter::MessageFragmenter;
-#[cfg(feature = "quic")]
-use crate::msgs::message::MessagePayload;
-use crate::msgs::message::{BorrowedPlainMessage, Message, OpaqueMessage, PlainMessage};
+use crate::msgs::fragmenter::{MessageFragmenter, PACKET_OVERHEAD};
+use crate::msgs::message::{
+    BorrowedPlainMessage, Message, MessagePayload, OpaqueMessage, PlainMessage,
+};
 #[cfg(feature = "quic")]
 use crate::quic;
-use crate::record_layer;
+use crate::record_layer::{self, AeadLimitCallback, AeadLimitPolicy, RecordPaddingPolicy};
 use crate::suites::PartiallyExtractedSecrets;
 use crate::suites::SupportedCipherSuite;
 #[cfg(feature = "tls12")]
 use crate::tls12::ConnectionSecrets;
 use crate::vecbuf::ChunkVecBuffer;
+use crate::x509;
+
+use pki_types::{CertificateDer, UnixTime};
 
-use pki_types::CertificateDer;
+use alloc::sync::Arc;
+use core::cmp;
+use core::time::Duration;
+use std::io;
 
 /// Connection state common to both client and server connections.
 pub struct CommonState {
@@ -27,6 +39,7 @@ pub struct CommonState {
     pub(crate) record_layer: record_layer::RecordLayer,
     pub(crate) suite: Option<SupportedCipherSuite>,
     pub(crate) alpn_protocol: Option<Vec<u8>>,
+    pub(crate) alpn_common_protocols: Vec<Vec<u8>>,
     pub(crate) aligned_handshake: bool,
     pub(crate) may_send_application_data: bool,
     pub(crate) may_receive_application_data: bool,
@@ -34,14 +47,44 @@ pub struct CommonState {
     sent_fatal_alert: bool,
     /// If the peer has signaled end of stream.
     pub(crate) has_received_close_notify: bool,
+    details: Option<Box<NegotiatedDetails>>,
     pub(crate) has_seen_eof: bool,
     pub(crate) received_middlebox_ccs: u8,
     pub(crate) peer_certificates: Option<Vec<CertificateDer<'static>>>,
+    pub(crate) sent_certificates: Option<Vec<CertificateDer<'static>>>,
+    pub(crate) fips_provider: bool,
     message_fragmenter: MessageFragmenter,
+    record_mtu: Option<usize>,
     pub(crate) received_plaintext: ChunkVecBuffer,
+    premature_plaintext: ChunkVecBuffer,
+    premature_data_policy: PrematureDataPolicy,
     sendable_plaintext: ChunkVecBuffer,
+    incoming_plaintext_limit: Option<usize>,
+    outgoing_plaintext_limit: Option<usize>,
+    /// If the last `process_new_packets` left complete records undecrypted
+    /// because `incoming_plaintext_limit` was reached.
+    ///
+    /// This is only recomputed by `process_new_packets`.  Until then it
+    /// describes the records that call left behind: bytes from `read_tls`
+    /// are not counted, and reading plaintext does not clear it, since those
+    /// records still need processing.
+    pub(crate) has_deferred_records: bool,
+    corked: bool,
     pub(crate) sendable_tls: ChunkVecBuffer,
     queued_key_update_message: Option<Vec<u8>>,
+    record_padding: Option<(RecordPaddingPolicy, &'static dyn CryptoProvider)>,
+    aead_limit_policy: AeadLimitPolicy,
+    aead_limit_callback: Option<Arc<dyn AeadLimitCallback>>,
+    aead_limit_failed: bool,
+    bytes_sent: u64,
+    bytes_received: u64,
+    max_bytes_transferred: Option<u64>,
+    max_connection_age: Option<Duration>,
+    connection_start: Option<UnixTime>,
+    current_time: Option<UnixTime>,
+    pub(crate) fixed_time: Option<UnixTime>,
+    #[cfg(feature = "dangerous_transcript")]
+    transcripts: Vec<(TranscriptPoint, Vec<u8>)>,
 
     #[allow(dead_code)] // only read for QUIC
     /// Protocol whose key schedule should be used. Unused for TLS < 1.3.
@@ -59,20 +102,44 @@ impl CommonState {
             record_layer: record_layer::RecordLayer::new(),
             suite: None,
             alpn_protocol: None,
+            alpn_common_protocols: Vec::new(),
             aligned_handshake: true,
             may_send_application_data: false,
             may_receive_application_data: false,
             early_traffic: false,
             sent_fatal_alert: false,
             has_received_close_notify: false,
+            details: None,
             has_seen_eof: false,
             received_middlebox_ccs: 0,
             peer_certificates: None,
+            sent_certificates: None,
+            fips_provider: false,
             message_fragmenter: MessageFragmenter::default(),
+            record_mtu: None,
             received_plaintext: ChunkVecBuffer::new(Some(DEFAULT_RECEIVED_PLAINTEXT_LIMIT)),
+            premature_plaintext: ChunkVecBuffer::new(Some(DEFAULT_RECEIVED_PLAINTEXT_LIMIT)),
+            premature_data_policy: PrematureDataPolicy::Fail,
             sendable_plaintext: ChunkVecBuffer::new(Some(DEFAULT_BUFFER_LIMIT)),
+            incoming_plaintext_limit: None,
+            outgoing_plaintext_limit: None,
+            has_deferred_records: false,
+            corked: false,
             sendable_tls: ChunkVecBuffer::new(Some(DEFAULT_BUFFER_LIMIT)),
             queued_key_update_message: None,
+            record_padding: None,
+            aead_limit_policy: AeadLimitPolicy::KeyUpdate,
+            aead_limit_callback: None,
+            aead_limit_failed: false,
+            bytes_sent: 0,
+            bytes_received: 0,
+            max_bytes_transferred: None,
+            max_connection_age: None,
+            connection_start: None,
+            current_time: None,
+            fixed_time: None,
+            #[cfg(feature = "dangerous_transcript")]
+            transcripts: Vec::new(),
 
             protocol: Protocol::Tcp,
             #[cfg(feature = "quic")]
@@ -88,6 +155,17 @@ impl CommonState {
         !self.sendable_tls.is_empty()
     }
 
+    /// How many bytes of TLS records are queued for [`Connection::write_tls`] to send.
+    ///
+    /// This is the encrypted (or, early in the handshake, plaintext) wire
+    /// format, so unlike [`crate::IoState::tls_bytes_to_write`] it is always
+    /// current.  Nothing is consumed by calling this.
+    ///
+    /// [`Connection::write_tls`]: crate::Connection::write_tls
+    pub fn pending_tls_bytes(&self) -> usize {
+        self.sendable_tls.len()
+    }
+
     /// Returns true if the connection is currently performing the TLS handshake.
     ///
     /// During this time plaintext written to the connection is buffered in memory. After
@@ -118,6 +196,80 @@ impl CommonState {
         self.peer_certificates.as_deref()
     }
 
+    /// Returns the alerts received from the peer, in the order they arrived.
+    ///
+    /// This includes warning alerts such as `close_notify`, and the fatal
+    /// alert, if any, that ended the connection.  It remains available after
+    /// the connection has closed or failed.  Only the first 32 alerts are kept.
+    pub fn received_alerts(&self) -> &[Alert] {
+        self.details
+            .as_ref()
+            .map_or(&[], |details| &details.received_alerts)
+    }
+
+    /// Returns the `verify_data` of the peer's `Finished` message.
+    ///
+    /// This is the content of the `Finished` message the peer sent, which
+    /// was checked against the handshake transcript.  It is available once
+    /// the peer's `Finished` has been verified; until then this is `None`.
+    ///
+    /// This is only available with the `dangerous_transcript` feature, for
+    /// comparing handshakes against known vectors.
+    #[cfg(feature = "dangerous_transcript")]
+    pub fn peer_finished_verify_data(&self) -> Option<&[u8]> {
+        self.details
+            .as_ref()?
+            .peer_finished_verify_data
+            .as_deref()
+    }
+
+    /// Returns the transcript hash of the completed handshake.
+    ///
+    /// This covers every handshake message, up to and including both
+    /// `Finished` messages, using the hash function of the negotiated
+    /// cipher suite.  Both peers observe the same value, which makes it
+    /// suitable for channel binding and audit logging.
+    ///
+    /// This is `None` until the handshake completes.
+    pub fn handshake_hash(&self) -> Option<&[u8]> {
+        self.details
+            .as_ref()?
+            .handshake_hash
+            .as_ref()
+            .map(|hash| hash.as_ref())
+    }
+
+    /// Returns the expiry (`notAfter`) time of the peer's end-entity certificate.
+    ///
+    /// This is available once [`CommonState::peer_certificates`] is, and
+    /// is useful for monitoring peers whose certificates are close to
+    /// expiry.  Returns `None` if there is no peer certificate, or if its
+    /// validity period cannot be parsed.
+    pub fn peer_certificate_not_after(&self) -> Option<UnixTime> {
+        let end_entity = self
+            .peer_certificates
+            .as_ref()?
+            .first()?;
+        x509::certificate_not_after(end_entity)
+    }
+
+    /// Retrieves the certificate chain we sent to the peer to authenticate.
+    ///
+    /// The order of the certificate chain is the same as for
+    /// [`CommonState::peer_certificates`].
+    ///
+    /// For servers, this is the certificate chain chosen by the configured
+    /// resolver.  It is not available for resumed handshakes, where no
+    /// certificate chain is sent.
+    ///
+    /// For clients, this is the certificate chain sent for client
+    /// authentication, if the server asked for it and a chain was available.
+    ///
+    /// The return value is None until this value is available.
+    pub fn sent_certificate_chain(&self) -> Option<&[CertificateDer<'_>]> {
+        self.sent_certificates.as_deref()
+    }
+
     /// Retrieves the protocol agreed with the peer via ALPN.
     ///
     /// A return value of `None` after handshake completion
@@ -127,6 +279,22 @@ impl CommonState {
         self.get_alpn_protocol()
     }
 
+    /// Retrieves all the protocols supported by both peers via ALPN,
+    /// in the server's order of preference.
+    ///
+    /// The first item (if any) is the protocol returned by
+    /// [`CommonState::alpn_protocol`].
+    ///
+    /// For servers, this is the intersection of the protocols offered by
+    /// the client and those in [`ServerConfig::alpn_protocols`].  For clients,
+    /// the server only reveals the protocol it selected, so this contains
+    /// at most that protocol.
+    ///
+    /// [`ServerConfig::alpn_protocols`]: crate::ServerConfig::alpn_protocols
+    pub fn alpn_common_protocols(&self) -> Vec<Vec<u8>> {
+        self.alpn_common_protocols.clone()
+    }
+
     /// Retrieves the ciphersuite agreed with the peer.
     ///
     /// This returns None until the ciphersuite is agreed.
@@ -141,6 +309,123 @@ impl CommonState {
         self.negotiated_version
     }
 
+    /// Retrieves the public key share this side sent in the key exchange.
+    ///
+    /// This is the encoded public value for the negotiated group, for
+    /// example 32 bytes for X25519.  It returns `None` until the key
+    /// exchange completes, and for TLS1.2 resumptions, which have none.
+    pub fn local_key_share(&self) -> Option<&[u8]> {
+        self.details
+            .as_ref()?
+            .key_shares
+            .as_ref()
+            .map(|(local, _)| local.as_slice())
+    }
+
+    /// Retrieves the public key share the peer sent in the key exchange.
+    ///
+    /// See [`CommonState::local_key_share`] for when this is available.
+    pub fn peer_key_share(&self) -> Option<&[u8]> {
+        self.details
+            .as_ref()?
+            .key_shares
+            .as_ref()
+            .map(|(_, peer)| peer.as_slice())
+    }
+
+    pub(crate) fn record_key_shares(
+        &mut self,
+        group: &'static dyn SupportedKxGroup,
+        local: &[u8],
+        peer: &[u8],
+    ) {
+        let details = self.details_mut();
+        details.kx_group = Some(group);
+        details.key_shares = Some((local.to_vec(), peer.to_vec()));
+    }
+
+    /// Record a signature scheme used in the handshake, by either side.
+    pub(crate) fn record_signature_scheme(&mut self, scheme: SignatureScheme) {
+        self.details_mut()
+            .signature_schemes
+            .push(scheme);
+    }
+
+    #[cfg(feature = "dangerous_transcript")]
+    pub(crate) fn record_peer_finished(&mut self, verify_data: &[u8]) {
+        self.details_mut()
+            .peer_finished_verify_data = Some(verify_data.to_vec());
+    }
+
+    pub(crate) fn record_handshake_hash(&mut self, hash: hash::Output) {
+        self.details_mut().handshake_hash = Some(hash);
+    }
+
+    fn details_mut(&mut self) -> &mut NegotiatedDetails {
+        self.details
+            .get_or_insert_with(Box::default)
+    }
+
+    /// Returns `true` if this connection completed its handshake using only
+    /// FIPS-approved algorithms.
+    ///
+    /// Unlike [`crate::ClientConfig::fips`] and [`crate::ServerConfig::fips`],
+    /// this checks what was actually negotiated: the cipher suite, the key
+    /// exchange group, and every signature scheme used by either side, as well
+    /// as that the `CryptoProvider` itself claims FIPS compliance.
+    ///
+    /// This returns `false` until the handshake completes.  For resumptions
+    /// that perform no key exchange (TLS1.2, and TLS1.3 in the `psk_ke` mode),
+    /// there is no group to check; the handshake which established the
+    /// resumed session is not considered.
+    pub fn is_fips_compliant(&self) -> bool {
+        if self.is_handshaking() || !self.fips_provider {
+            return false;
+        }
+
+        let (kx_group, signature_schemes) = match &self.details {
+            Some(details) => (details.kx_group, details.signature_schemes.as_slice()),
+            None => (None, &[][..]),
+        };
+
+        self.suite
+            .map_or(false, |suite| suite.fips())
+            && kx_group.map_or(true, |group| group.fips())
+            && signature_schemes
+                .iter()
+                .all(SignatureScheme::fips)
+    }
+
+    /// Returns the raw handshake transcript, up to and including the message at `point`.
+    ///
+    /// This is the concatenation of the encoded handshake messages that the
+    /// transcript hash of the negotiated cipher suite covers, so hashing it
+    /// gives the transcript hash at that point.  After a `HelloRetryRequest`
+    /// the first `ClientHello` is replaced by the synthetic `message_hash`
+    /// message, as described in [RFC 8446 section 4.4.1].
+    ///
+    /// This returns `None` until the connection has passed `point`.
+    ///
+    /// Every connection keeps a copy of its transcript at each point while this
+    /// feature is enabled, which is a few kilobytes more per connection and more
+    /// for large certificate chains.  These copies are kept for the lifetime of
+    /// the connection.
+    ///
+    /// [RFC 8446 section 4.4.1]: https://datatracker.ietf.org/doc/html/rfc8446#section-4.4.1
+    #[cfg(feature = "dangerous_transcript")]
+    pub fn transcript_up_to(&self, point: TranscriptPoint) -> Option<Vec<u8>> {
+        self.transcripts
+            .iter()
+            .find(|(recorded, _)| *recorded == point)
+            .map(|(_, transcript)| transcript.clone())
+    }
+
+    #[cfg(feature = "dangerous_transcript")]
+    pub(crate) fn record_transcript(&mut self, point: TranscriptPoint, transcript: &HandshakeHash) {
+        self.transcripts
+            .push((point, transcript.raw_transcript().to_vec()));
+    }
+
     pub(crate) fn is_tls13(&self) -> bool {
         matches!(self.negotiated_version, Some(ProtocolVersion::TLSv1_3))
     }
@@ -164,6 +449,23 @@ impl CommonState {
             }
         }
 
+        // Application data before the handshake completes is only kept if
+        // it was encrypted, and is only released by `start_traffic()`.
+        match msg.payload {
+            MessagePayload::ApplicationData(payload)
+                if !state.handles_early_data() && self.may_buffer_premature_data(&payload) =>
+            {
+                debug!(
+                    "Buffering {} bytes of application data until the handshake completes",
+                    payload.0.len()
+                );
+                self.premature_plaintext
+                    .append(payload.0);
+                return Ok(state);
+            }
+            _ => {}
+        }
+
         let mut cx = Context { common: self, data };
         match state.handle(&mut cx, msg) {
             Ok(next) => {
@@ -219,10 +521,10 @@ impl CommonState {
     /// the encrypted fragments for sending.
     pub(crate) fn send_msg_encrypt(&mut self, m: PlainMessage) {
         let iter = self
-            .message_fragmenter
+            .fragmenter(true)
             .fragment_message(&m);
         for m in iter {
-            self.send_single_fragment(m);
+            self.send_single_fragment(m, 0);
         }
     }
 
@@ -232,26 +534,90 @@ impl CommonState {
         // but we're respecting it for plaintext data -- so we'll
         // be out by whatever the cipher+record overhead is.  That's a
         // constant and predictable amount, so it's not a terrible issue.
+        let fragmenter = self.fragmenter(true);
+        let records_left = self
+            .record_layer
+            .records_before_confidentiality_limit();
         let len = match limit {
-            Limit::Yes => self
-                .sendable_tls
-                .apply_limit(payload.len()),
+            // Likewise, never encrypt past the write key's confidentiality
+            // limit: the rest may be written once the key has been updated.
+            Limit::Yes => cmp::min(
+                self.sendable_tls
+                    .apply_limit(payload.len()) as u64,
+                records_left.saturating_mul(fragmenter.max_fragment_len() as u64),
+            ) as usize,
             Limit::No => payload.len(),
         };
 
-        let iter = self.message_fragmenter.fragment_slice(
+        let iter = fragmenter.fragment_slice(
             ContentType::ApplicationData,
             ProtocolVersion::TLSv1_2,
             &payload[..len],
         );
         for m in iter {
-            self.send_single_fragment(m);
+            let padding = self.record_padding_len(m.payload.len());
+            self.send_single_fragment(m, padding);
+        }
+
+        if records_left > 0
+            && self
+                .record_layer
+                .records_before_confidentiality_limit()
+                == 0
+        {
+            self.confidentiality_limit_reached();
         }
 
         len
     }
 
-    fn send_single_fragment(&mut self, m: BorrowedPlainMessage) {
+    /// Apply the `AeadLimitPolicy`, once the write key can encrypt no
+    /// more application data.
+    fn confidentiality_limit_reached(&mut self) {
+        if let (Some(callback), Some(suite)) = (&self.aead_limit_callback, self.suite) {
+            callback.limit_reached(suite.suite(), self.record_layer.write_seq());
+        }
+
+        match self.aead_limit_policy {
+            // `ConnectionCore` owns the key schedule, so it sends the
+            // KeyUpdate once this write is done.
+            AeadLimitPolicy::KeyUpdate => {
+                debug!("Write key reached its confidentiality limit, updating it");
+            }
+            AeadLimitPolicy::Fail => {
+                warn!("Write key reached its confidentiality limit, closing connection");
+                self.send_warning_alert_no_log(AlertDescription::CloseNotify);
+                self.aead_limit_failed = true;
+            }
+        }
+    }
+
+    /// True if the write key should be replaced with a KeyUpdate.
+    pub(crate) fn wants_traffic_key_update(&self) -> bool {
+        self.aead_limit_policy == AeadLimitPolicy::KeyUpdate
+            && self.is_tls13()
+            && self.record_layer.is_encrypting()
+            && self
+                .record_layer
+                .records_before_confidentiality_limit()
+                == 0
+    }
+
+    /// How much padding to add to an application data record
+    /// carrying `len` bytes.
+    fn record_padding_len(&self, len: usize) -> usize {
+        match self.record_padding {
+            Some((policy, provider)) if self.is_tls13() => policy.padding_len(len, provider),
+            _ => 0,
+        }
+    }
+
+    fn send_single_fragment(&mut self, m: BorrowedPlainMessage, padding: usize) {
+        // The write key is used up, and `AeadLimitPolicy::Fail` applies.
+        if self.aead_limit_failed {
+            return;
+        }
+
         // Close connection once we start to run out of
         // sequence space.
         if self
@@ -267,7 +633,9 @@ impl CommonState {
             return;
         }
 
-        let em = self.record_layer.encrypt_outgoing(m);
+        let em = self
+            .record_layer
+            .encrypt_outgoing_padded(m, padding);
         self.queue_tls_message(em);
     }
 
@@ -277,9 +645,10 @@ impl CommonState {
     /// Returns the number of bytes written from `data`: this might
     /// be less than `data.len()` if buffer limits were exceeded.
     fn send_plain(&mut self, data: &[u8], limit: Limit) -> usize {
-        if !self.may_send_application_data {
+        if !self.may_send_application_data || self.corked {
             // If we haven't completed handshaking, buffer
-            // plaintext to send once we do.
+            // plaintext to send once we do.  Likewise if corked,
+            // until there is enough to fill a record.
             let len = match limit {
                 Limit::Yes => self
                     .sendable_plaintext
@@ -288,6 +657,9 @@ impl CommonState {
                     .sendable_plaintext
                     .append(data.to_vec()),
             };
+            if self.may_send_application_data {
+                self.send_corked_plaintext(true);
+            }
             return len;
         }
 
@@ -303,11 +675,75 @@ impl CommonState {
 
     pub(crate) fn start_outgoing_traffic(&mut self) {
         self.may_send_application_data = true;
-        self.flush_plaintext();
+        if self.corked {
+            self.send_corked_plaintext(true);
+        } else {
+            self.flush_plaintext();
+        }
+    }
+
+    /// Stops each write to [`Connection::writer`] from producing its own TLS record.
+    ///
+    /// While corked, written plaintext is buffered and only encrypted once
+    /// there is enough to fill a whole record, when the [`Writer`] is
+    /// flushed, or on [`CommonState::uncork`].  This reduces overhead for
+    /// protocols that make many small writes.
+    ///
+    /// Buffered plaintext counts towards the limit set by
+    /// [`CommonState::set_buffer_limit`].  Connections are not corked by default.
+    ///
+    /// [`Connection::writer`]: crate::Connection::writer
+    /// [`Writer`]: crate::Writer
+    pub fn cork(&mut self) {
+        self.corked = true;
+    }
+
+    /// Undoes [`CommonState::cork`], encrypting any buffered plaintext.
+    ///
+    /// Subsequent writes once again produce a TLS record each.
+    pub fn uncork(&mut self) {
+        self.corked = false;
+        self.flush_corked_plaintext();
+    }
+
+    /// Encrypt all plaintext buffered while corked, coalescing it into as few
+    /// records as possible.  The connection stays corked.
+    pub(crate) fn flush_corked_plaintext(&mut self) {
+        if self.may_send_application_data {
+            self.send_corked_plaintext(false);
+        }
+    }
+
+    fn send_corked_plaintext(&mut self, full_records_only: bool) {
+        let max_frag = self.fragmenter(true).max_fragment_len();
+        if full_records_only && self.sendable_plaintext.len() < max_frag {
+            return;
+        }
+
+        let mut data = Vec::with_capacity(self.sendable_plaintext.len());
+        while let Some(buf) = self.sendable_plaintext.pop() {
+            data.extend_from_slice(&buf);
+        }
+
+        let len = if full_records_only {
+            data.len() - data.len() % max_frag
+        } else {
+            data.len()
+        };
+
+        self.perhaps_write_key_update();
+        self.send_appdata_encrypt(&data[..len], Limit::No);
+        if len < data.len() {
+            self.sendable_plaintext
+                .append(data[len..].to_vec());
+        }
     }
 
     pub(crate) fn start_traffic(&mut self) {
         self.may_receive_application_data = true;
+        while let Some(buf) = self.premature_plaintext.pop() {
+            self.received_plaintext.append(buf);
+        }
         self.start_outgoing_traffic();
     }
 
@@ -400,7 +836,7 @@ impl CommonState {
         if !must_encrypt {
             let msg = &m.into();
             let iter = self
-                .message_fragmenter
+                .fragmenter(false)
                 .fragment_message(msg);
             for m in iter {
                 self.queue_tls_message(m.to_unencrypted_opaque());
@@ -434,6 +870,14 @@ impl CommonState {
     }
 
     pub(crate) fn process_alert(&mut self, alert: &AlertMessagePayload) -> Result<(), Error> {
+        let received_alerts = &mut self.details_mut().received_alerts;
+        if received_alerts.len() < MAX_RECORDED_ALERTS {
+            received_alerts.push(Alert {
+                level: alert.level,
+                description: alert.description,
+            });
+        }
+
         // Reject unknown AlertLevels.
         if let AlertLevel::Unknown(_) = alert.level {
             return Err(self.send_fatal_alert(
@@ -493,6 +937,7 @@ impl CommonState {
     ///
     /// [`Connection::write_tls`]: crate::Connection::write_tls
     pub fn send_close_notify(&mut self) {
+        self.flush_corked_plaintext();
         debug!("Sending warning alert {:?}", AlertDescription::CloseNotify);
         self.send_warning_alert_no_log(AlertDescription::CloseNotify);
     }
@@ -507,6 +952,244 @@ impl CommonState {
             .set_max_fragment_size(new)
     }
 
+    /// Limit the size of each record sent, so it fits in a datagram of `mtu` bytes.
+    ///
+    /// The largest plaintext per record is derived from `mtu` by taking off the
+    /// record header and the expansion caused by the negotiated cipher suite:
+    /// its AEAD tag, plus the content type byte in TLS1.3 or the explicit
+    /// nonce in TLS1.2.  This is reworked each time a record is sent, so it
+    /// follows the connection from plaintext handshake records through to
+    /// traffic keys.  This also applies alongside the `max_fragment_size`
+    /// configuration option, with the smaller limit winning.
+    ///
+    /// Padding added by a [`RecordPaddingPolicy`] is not accounted for, and
+    /// records that are already queued are not changed.  That includes the
+    /// ClientHello, which a client queues on creation: the `max_fragment_size`
+    /// option of [`crate::ClientConfig`] can be used to bound that.
+    ///
+    /// Returns [`Error::BadMaxFragmentSize`] if `mtu` is smaller than 32.
+    pub fn set_record_plaintext_limit_for_mtu(&mut self, mtu: usize) -> Result<(), Error> {
+        if mtu < 32 {
+            return Err(Error::BadMaxFragmentSize);
+        }
+        self.record_mtu = Some(mtu);
+        Ok(())
+    }
+
+    /// The fragmenter to use for the next records, which are `encrypted` or not.
+    fn fragmenter(&self, encrypted: bool) -> MessageFragmenter {
+        let mtu = match self.record_mtu {
+            Some(mtu) => mtu,
+            None => {
+                return self
+                    .message_fragmenter
+                    .limited_to(usize::MAX)
+            }
+        };
+
+        let expansion = match (encrypted, self.suite) {
+            (false, _) | (true, None) => 0,
+            (true, Some(SupportedCipherSuite::Tls13(suite))) => 1 + suite.aead_alg.tag_len(),
+            #[cfg(feature = "tls12")]
+            (true, Some(SupportedCipherSuite::Tls12(suite))) => {
+                suite
+                    .aead_alg
+                    .key_block_shape()
+                    .explicit_nonce_len
+                    + suite.aead_alg.tag_len()
+            }
+        };
+
+        self.message_fragmenter.limited_to(
+            mtu.saturating_sub(PACKET_OVERHEAD + expansion)
+                .max(1),
+        )
+    }
+
+    pub(crate) fn set_record_padding_policy(
+        &mut self,
+        policy: RecordPaddingPolicy,
+        provider: &'static dyn CryptoProvider,
+    ) {
+        self.record_padding = match policy {
+            RecordPaddingPolicy::None => None,
+            _ => Some((policy, provider)),
+        };
+    }
+
+    pub(crate) fn set_aead_limit_policy(
+        &mut self,
+        policy: AeadLimitPolicy,
+        callback: Option<Arc<dyn AeadLimitCallback>>,
+    ) {
+        self.aead_limit_policy = policy;
+        self.aead_limit_callback = callback;
+    }
+
+    fn may_buffer_premature_data(&self, payload: &Payload) -> bool {
+        self.premature_data_policy == PrematureDataPolicy::BufferUntilHandshakeComplete
+            && !self.may_receive_application_data
+            && self.record_layer.is_decrypting()
+            && self
+                .premature_plaintext
+                .apply_limit(payload.0.len())
+                == payload.0.len()
+    }
+
+    pub(crate) fn set_premature_data_policy(&mut self, policy: PrematureDataPolicy) {
+        self.premature_data_policy = policy;
+    }
+
+    pub(crate) fn set_connection_limits(
+        &mut self,
+        max_age: Option<Duration>,
+        max_bytes: Option<u64>,
+    ) {
+        self.max_connection_age = max_age;
+        self.max_bytes_transferred = max_bytes;
+    }
+
+    pub(crate) fn set_plaintext_limits(
+        &mut self,
+        incoming: Option<usize>,
+        outgoing: Option<usize>,
+    ) {
+        if incoming.is_some() {
+            self.received_plaintext
+                .set_limit(incoming);
+        }
+        self.incoming_plaintext_limit = incoming;
+        self.outgoing_plaintext_limit = outgoing;
+    }
+
+    /// True if received records should be left undecrypted until the
+    /// application reads some plaintext.
+    pub(crate) fn incoming_plaintext_full(&self) -> bool {
+        match self.incoming_plaintext_limit {
+            Some(limit) => self.received_plaintext.len() >= limit,
+            None => false,
+        }
+    }
+
+    /// How many bytes of a `len`-byte write may be accepted under
+    /// `outgoing_plaintext_limit`.
+    ///
+    /// Data queued for sending counts towards the limit whether it is
+    /// still plaintext or already encrypted.  Fails with `WouldBlock`
+    /// if there is no space at all.
+    pub(crate) fn outgoing_plaintext_space(&self, len: usize) -> io::Result<usize> {
+        if self.aead_limit_failed {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                Error::AeadLimitReached,
+            ));
+        }
+
+        let limit = match self.outgoing_plaintext_limit {
+            Some(limit) => limit,
+            None => return Ok(len),
+        };
+
+        let queued = self.sendable_plaintext.len() + self.sendable_tls.len();
+        match limit.saturating_sub(queued) {
+            0 if len > 0 => Err(io::ErrorKind::WouldBlock.into()),
+            space => Ok(cmp::min(space, len)),
+        }
+    }
+
+    /// The time to use for certificate validation and session timestamps:
+    /// the configured fixed time, if any, otherwise the system clock.
+    pub(crate) fn now(&self) -> UnixTime {
+        self.fixed_time
+            .unwrap_or_else(UnixTime::now)
+    }
+
+    /// Tells the connection the current time, for enforcing `max_connection_age`.
+    ///
+    /// rustls does not read the clock itself for this purpose.  The first time
+    /// supplied is taken as the start of the connection; later calls advance
+    /// the connection's notion of "now".  The age limit is checked during
+    /// the next call to [`Connection::process_new_packets`].
+    ///
+    /// [`Connection::process_new_packets`]: crate::Connection::process_new_packets
+    pub fn set_current_time(&mut self, now: UnixTime) {
+        if self.connection_start.is_none() {
+            self.connection_start = Some(now);
+        }
+        self.current_time = Some(now);
+    }
+
+    /// Returns the total number of TLS bytes read and written by this connection.
+    ///
+    /// This counts bytes passed through [`Connection::read_tls`] and
+    /// [`Connection::write_tls`], and is compared against `max_bytes_transferred`.
+    ///
+    /// [`Connection::read_tls`]: crate::Connection::read_tls
+    /// [`Connection::write_tls`]: crate::Connection::write_tls
+    pub fn bytes_transferred(&self) -> u64 {
+        self.bytes_sent
+            .saturating_add(self.bytes_received)
+    }
+
+    /// Returns the number of TLS bytes written by [`Connection::write_tls`].
+    ///
+    /// This is at the record layer, so includes record headers, encryption
+    /// overhead and padding, as well as handshake and alert records.
+    ///
+    /// [`Connection::write_tls`]: crate::Connection::write_tls
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes_sent
+    }
+
+    /// Returns the number of TLS bytes read by [`Connection::read_tls`].
+    ///
+    /// Like [`CommonState::bytes_sent`], this counts whole records, whether or
+    /// not they have been processed yet.
+    ///
+    /// [`Connection::read_tls`]: crate::Connection::read_tls
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes_received
+    }
+
+    pub(crate) fn record_bytes_sent(&mut self, len: usize) {
+        self.bytes_sent = self
+            .bytes_sent
+            .saturating_add(len as u64);
+    }
+
+    pub(crate) fn record_bytes_received(&mut self, len: usize) {
+        self.bytes_received = self
+            .bytes_received
+            .saturating_add(len as u64);
+    }
+
+    pub(crate) fn check_connection_limits(&self) -> Result<(), Error> {
+        if self.aead_limit_failed {
+            return Err(Error::AeadLimitReached);
+        }
+
+        if let Some(max) = self.max_bytes_transferred {
+            if self.bytes_transferred() > max {
+                return Err(Error::BytesTransferredLimitExceeded);
+            }
+        }
+
+        if let (Some(max), Some(start), Some(now)) = (
+            self.max_connection_age,
+            self.connection_start,
+            self.current_time,
+        ) {
+            let age = now
+                .as_secs()
+                .saturating_sub(start.as_secs());
+            if age > max.as_secs() {
+                return Err(Error::ConnectionAgeLimitExceeded);
+            }
+        }
+
+        Ok(())
+    }
+
     pub(crate) fn get_alpn_protocol(&self) -> Option<&[u8]> {
         self.alpn_protocol
             .as_ref()
@@ -520,8 +1203,13 @@ impl CommonState {
     /// this returns false.  If your application respects this mechanism,
     /// only one full TLS message will be buffered by rustls.
     ///
+    /// This also returns false if [`Connection::process_new_packets`] left
+    /// received records undecrypted under `incoming_plaintext_limit`.  Call
+    /// it again once the plaintext has been read.
+    ///
     /// [`Connection::reader`]: crate::Connection::reader
     /// [`Connection::read_tls`]: crate::Connection::read_tls
+    /// [`Connection::process_new_packets`]: crate::Connection::process_new_packets
     pub fn wants_read(&self) -> bool {
         // We want to read more data all the time, except when we have unprocessed plaintext.
         // This provides back-pressure to the TCP buffers. We also don't want to read more after
@@ -530,6 +1218,7 @@ impl CommonState {
         // In the handshake case we don't have readable plaintext before the handshake has
         // completed, but also don't want to read if we still have sendable tls.
         self.received_plaintext.is_empty()
+            && !self.has_deferred_records
             && !self.has_received_close_notify
             && (self.may_send_application_data || self.sendable_tls.is_empty())
     }
@@ -639,6 +1328,32 @@ pub(crate) trait State<Data>: Send + Sync {
     fn extract_secrets(&self) -> Result<PartiallyExtractedSecrets, Error> {
         Err(Error::HandshakeNotComplete)
     }
+
+    /// Replace our write key, telling the peer with a KeyUpdate message.
+    fn send_key_update(&mut self, _common: &mut CommonState) -> Result<(), Error> {
+        Err(Error::HandshakeNotComplete)
+    }
+
+    /// Whether this state accepts application data before the handshake
+    /// completes, as TLS1.3 early data.
+    fn handles_early_data(&self) -> bool {
+        false
+    }
+}
+
+/// Details of the handshake that are recorded as they become known.
+///
+/// These are only read by the application, if at all, so they are kept
+/// behind a `Box` to avoid inflating the size of every connection.
+#[derive(Default)]
+struct NegotiatedDetails {
+    received_alerts: Vec<Alert>,
+    #[cfg(feature = "dangerous_transcript")]
+    peer_finished_verify_data: Option<Vec<u8>>,
+    handshake_hash: Option<hash::Output>,
+    key_shares: Option<(Vec<u8>, Vec<u8>)>,
+    kx_group: Option<&'static dyn SupportedKxGroup>,
+    signature_schemes: Vec<SignatureScheme>,
 }
 
 pub(crate) struct Context<'a, Data> {
@@ -664,6 +1379,49 @@ impl Side {
     }
 }
 
+/// What to do with application data the peer sends before the handshake
+/// completes, other than accepted TLS1.3 early data.
+///
+/// This is a protocol violation, normally answered with an
+/// `unexpected_message` alert.  Some peers send their first application data
+/// a little too early, though, and [`PrematureDataPolicy::BufferUntilHandshakeComplete`]
+/// tolerates them.
+///
+/// Unencrypted application data is always refused.
+///
+/// The default is [`PrematureDataPolicy::Fail`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PrematureDataPolicy {
+    /// Fail the connection.
+    Fail,
+    /// Hold the data back, and make it readable only once the handshake
+    /// completes and the peer is authenticated.
+    ///
+    /// Up to 16kB is buffered; the connection fails if the peer sends more.
+    BufferUntilHandshakeComplete,
+}
+
+impl Default for PrematureDataPolicy {
+    fn default() -> Self {
+        Self::Fail
+    }
+}
+
+/// A point in the handshake, for [`CommonState::transcript_up_to`].
+///
+/// Each point is just after the named message.
+#[cfg(feature = "dangerous_transcript")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TranscriptPoint {
+    /// The `ServerHello` which agreed the cipher suite.
+    ServerHello,
+    /// The server's `Finished`.
+    ServerFinished,
+    /// The client's `Finished`.  This is the whole handshake.
+    ClientFinished,
+}
+
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub(crate) enum Protocol {
     Tcp,
@@ -678,3 +1436,170 @@ enum Limit {
 
 const DEFAULT_RECEIVED_PLAINTEXT_LIMIT: usize = 16 * 1024;
 const DEFAULT_BUFFER_LIMIT: usize = 64 * 1024;
+/// Bounds the memory a peer sending many warning alerts can make us use.
+const MAX_RECORDED_ALERTS: usize = 32;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::check::inappropriate_message;
+    use crate::crypto::cipher::MessageDecrypter;
+    #[cfg(feature = "ring")]
+    use crate::crypto::cipher::MessageEncrypter;
+    #[cfg(feature = "ring")]
+    use crate::enums::CipherSuite;
+
+    #[cfg(feature = "ring")]
+    use core::sync::atomic::{AtomicU64, Ordering};
+
+    /// A handshake state that accepts nothing, standing in for one awaiting
+    /// the peer's Finished message.
+    struct ExpectFinished;
+
+    impl State<()> for ExpectFinished {
+        fn handle(
+            self: Box<Self>,
+            _cx: &mut Context<'_, ()>,
+            m: Message,
+        ) -> Result<Box<dyn State<()>>, Error> {
+            Err(inappropriate_message(&m.payload, &[ContentType::Handshake]))
+        }
+    }
+
+    fn handshaking(policy: PrematureDataPolicy) -> CommonState {
+        let mut common = CommonState::new(Side::Client);
+        common.set_premature_data_policy(policy);
+        common
+            .record_layer
+            .set_message_decrypter(<dyn MessageDecrypter>::invalid());
+        common
+    }
+
+    fn app_data(data: &[u8]) -> Message {
+        Message {
+            version: ProtocolVersion::TLSv1_3,
+            payload: MessagePayload::ApplicationData(Payload::new(data)),
+        }
+    }
+
+    fn received(common: &mut CommonState) -> Vec<u8> {
+        let mut data = Vec::new();
+        while let Some(buf) = common.received_plaintext.pop() {
+            data.extend_from_slice(&buf);
+        }
+        data
+    }
+
+    #[test]
+    fn premature_data_fails_by_default() {
+        let mut common = handshaking(PrematureDataPolicy::default());
+        let err = common
+            .process_main_protocol(app_data(b"early"), Box::new(ExpectFinished), &mut ())
+            .err()
+            .unwrap();
+        assert!(matches!(err, Error::InappropriateMessage { .. }));
+
+        common.start_traffic();
+        assert_eq!(received(&mut common), b"");
+    }
+
+    #[test]
+    fn premature_data_is_buffered_until_handshake_completes() {
+        let mut common = handshaking(PrematureDataPolicy::BufferUntilHandshakeComplete);
+        let mut state: Box<dyn State<()>> = Box::new(ExpectFinished);
+        for data in [&b"hello "[..], b"world"] {
+            state = common
+                .process_main_protocol(app_data(data), state, &mut ())
+                .unwrap();
+            assert_eq!(received(&mut common), b"");
+        }
+
+        common.start_traffic();
+        assert_eq!(received(&mut common), b"hello world");
+    }
+
+    #[test]
+    fn premature_data_buffer_is_limited() {
+        let mut common = handshaking(PrematureDataPolicy::BufferUntilHandshakeComplete);
+        let state = common
+            .process_main_protocol(
+                app_data(&[0u8; DEFAULT_RECEIVED_PLAINTEXT_LIMIT]),
+                Box::new(ExpectFinished),
+                &mut (),
+            )
+            .unwrap();
+        assert!(common
+            .process_main_protocol(app_data(b"more"), state, &mut ())
+            .is_err());
+    }
+
+    #[test]
+    fn unencrypted_premature_data_is_never_buffered() {
+        let mut common = CommonState::new(Side::Server);
+        common.set_premature_data_policy(PrematureDataPolicy::BufferUntilHandshakeComplete);
+        assert!(common
+            .process_main_protocol(app_data(b"plaintext"), Box::new(ExpectFinished), &mut ())
+            .is_err());
+
+        common.start_traffic();
+        assert_eq!(received(&mut common), b"");
+    }
+
+    #[cfg(feature = "ring")]
+    struct PassThroughEncrypter;
+
+    #[cfg(feature = "ring")]
+    impl MessageEncrypter for PassThroughEncrypter {
+        fn encrypt(&self, m: BorrowedPlainMessage, _: u64) -> Result<OpaqueMessage, Error> {
+            Ok(m.to_unencrypted_opaque())
+        }
+    }
+
+    #[cfg(feature = "ring")]
+    #[derive(Default)]
+    struct RecordsAtLimit(AtomicU64);
+
+    #[cfg(feature = "ring")]
+    impl AeadLimitCallback for RecordsAtLimit {
+        fn limit_reached(&self, suite: CipherSuite, records: u64) {
+            assert_eq!(suite, CipherSuite::TLS13_AES_128_GCM_SHA256);
+            self.0.store(records, Ordering::SeqCst);
+        }
+    }
+
+    #[cfg(feature = "ring")]
+    #[test]
+    fn connection_fails_at_confidentiality_limit() {
+        let callback = Arc::new(RecordsAtLimit::default());
+        let mut common = CommonState::new(Side::Client);
+        common.suite = Some(crate::crypto::ring::tls13::TLS13_AES_128_GCM_SHA256);
+        common.set_aead_limit_policy(
+            AeadLimitPolicy::Fail,
+            Some(Arc::<RecordsAtLimit>::clone(&callback)),
+        );
+        common
+            .record_layer
+            .set_message_encrypter(Box::new(PassThroughEncrypter));
+        common
+            .record_layer
+            .set_confidentiality_limit(8);
+        common.start_outgoing_traffic();
+
+        // the last record the limit allows carries the close_notify
+        for _ in 0..7 {
+            assert_eq!(common.send_some_plaintext(b"hello"), 5);
+        }
+        assert_eq!(callback.0.load(Ordering::SeqCst), 7);
+        assert_eq!(common.record_layer.write_seq(), 8);
+
+        assert_eq!(common.send_some_plaintext(b"hello"), 0);
+        assert_eq!(common.record_layer.write_seq(), 8);
+        assert_eq!(
+            common.check_connection_limits(),
+            Err(Error::AeadLimitReached)
+        );
+        assert!(common
+            .outgoing_plaintext_space(5)
+            .is_err());
+    }
+}
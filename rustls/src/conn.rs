@@ -2,13 +2,14 @@ use crate::common_state::{CommonState, Context, IoState, State};
 use crate::enums::{AlertDescription, ContentType};
 use crate::error::{Error, PeerMisbehaved};
 #[cfg(feature = "logging")]
-use crate::log::trace;
+use crate::log::{debug, trace};
 use crate::msgs::deframer::{Deframed, MessageDeframer};
 use crate::msgs::handshake::Random;
 use crate::msgs::message::{Message, MessagePayload, PlainMessage};
 use crate::suites::{ExtractedSecrets, PartiallyExtractedSecrets};
 use crate::vecbuf::ChunkVecBuffer;
 
+use core::cmp;
 use core::fmt::Debug;
 use core::mem;
 use core::ops::{Deref, DerefMut};
@@ -38,7 +39,10 @@ impl Connection {
     ///
     /// See [`ConnectionCommon::write_tls()`] for more information.
     pub fn write_tls(&mut self, wr: &mut dyn io::Write) -> Result<usize, io::Error> {
-        self.sendable_tls.write_to(wr)
+        match self {
+            Self::Client(conn) => conn.write_tls(wr),
+            Self::Server(conn) => conn.write_tls(wr),
+        }
     }
 
     /// Returns an object that allows reading plaintext.
@@ -67,6 +71,16 @@ impl Connection {
         }
     }
 
+    /// Queues a fatal alert and marks the connection as failed.
+    ///
+    /// See [`ConnectionCommon::send_fatal_alert()`] for more information.
+    pub fn send_fatal_alert(&mut self, desc: AlertDescription) {
+        match self {
+            Self::Client(conn) => conn.send_fatal_alert(desc),
+            Self::Server(conn) => conn.send_fatal_alert(desc),
+        }
+    }
+
     /// Derives key material from the agreed connection secrets.
     ///
     /// See [`ConnectionCommon::export_keying_material()`] for more information.
@@ -231,18 +245,28 @@ pub(crate) trait PlaintextSink {
 
 impl<T> PlaintextSink for ConnectionCommon<T> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        Ok(self.send_some_plaintext(buf))
+        let len = self.outgoing_plaintext_space(buf.len())?;
+        let len = self.send_some_plaintext(&buf[..len]);
+        self.core.maybe_update_traffic_keys();
+        Ok(len)
     }
 
     fn write_vectored(&mut self, bufs: &[io::IoSlice<'_>]) -> io::Result<usize> {
+        let total = bufs.iter().map(|buf| buf.len()).sum();
+        let mut space = self.outgoing_plaintext_space(total)?;
         let mut sz = 0;
         for buf in bufs {
-            sz += self.send_some_plaintext(buf);
+            let len = cmp::min(space, buf.len());
+            sz += self.send_some_plaintext(&buf[..len]);
+            space -= len;
         }
+        self.core.maybe_update_traffic_keys();
         Ok(sz)
     }
 
     fn flush(&mut self) -> io::Result<()> {
+        self.flush_corked_plaintext();
+        self.core.maybe_update_traffic_keys();
         Ok(())
     }
 }
@@ -387,6 +411,10 @@ impl<Data> ConnectionCommon<Data> {
                 return Ok((rdlen, wrlen));
             }
 
+            // Records left undecrypted under `incoming_plaintext_limit` are
+            // processed before reading more: the peer may have nothing more to send.
+            let had_deferred_records = self.has_deferred_records;
+
             while !eof && self.wants_read() {
                 let read_size = match self.read_tls(io) {
                     Ok(0) => {
@@ -417,6 +445,11 @@ impl<Data> ConnectionCommon<Data> {
                 }
             };
 
+            // the deferred bytes were only part of a record, so read the rest.
+            if had_deferred_records && self.wants_read() {
+                continue;
+            }
+
             // if we're doing IO until handshaked, and we believe we've finished handshaking,
             // but process_new_packets() has queued TLS data to send, loop around again to write
             // the queued messages.
@@ -444,7 +477,10 @@ impl<Data> ConnectionCommon<Data> {
             .map(Message::try_from)
         {
             Some(Ok(msg)) => Ok(Some(msg)),
-            Some(Err(err)) => Err(self.send_fatal_alert(AlertDescription::DecodeError, err)),
+            Some(Err(err)) => Err(self
+                .core
+                .common_state
+                .send_fatal_alert(AlertDescription::DecodeError, err)),
             None => Ok(None),
         }
     }
@@ -504,8 +540,10 @@ impl<Data> ConnectionCommon<Data> {
         }
 
         let res = self.core.message_deframer.read(rd);
-        if let Ok(0) = res {
-            self.has_seen_eof = true;
+        match res {
+            Ok(0) => self.has_seen_eof = true,
+            Ok(n) => self.record_bytes_received(n),
+            Err(_) => {}
         }
         res
     }
@@ -518,7 +556,28 @@ impl<Data> ConnectionCommon<Data> {
     /// After this function returns, the connection buffer may not yet be fully flushed. The
     /// [`CommonState::wants_write`] function can be used to check if the output buffer is empty.
     pub fn write_tls(&mut self, wr: &mut dyn io::Write) -> Result<usize, io::Error> {
-        self.sendable_tls.write_to(wr)
+        let res = self.sendable_tls.write_to(wr);
+        if let Ok(n) = res {
+            self.record_bytes_sent(n);
+        }
+        res
+    }
+
+    /// Queues a fatal alert with description `desc` to be sent in the
+    /// next [`write_tls`] call, and marks the connection as failed.
+    ///
+    /// This allows the application to abort the connection with a
+    /// meaningful reason -- for example, [`AlertDescription::AccessDenied`]
+    /// after its own authorization checks have failed.
+    ///
+    /// Afterwards, [`process_new_packets`] returns [`Error::AlertSent`]
+    /// and no further data is processed.  If the connection has already
+    /// failed, this does nothing.
+    ///
+    /// [`write_tls`]: ConnectionCommon::write_tls
+    /// [`process_new_packets`]: ConnectionCommon::process_new_packets
+    pub fn send_fatal_alert(&mut self, desc: AlertDescription) {
+        self.core.send_fatal_alert(desc);
     }
 
     /// Derives key material from the agreed connection secrets.
@@ -620,7 +679,23 @@ impl<Data> ConnectionCore<Data> {
             }
         };
 
-        while let Some(msg) = self.deframe()? {
+        if let Err(e) = self
+            .common_state
+            .check_connection_limits()
+        {
+            self.state = Err(e.clone());
+            return Err(e);
+        }
+
+        while !self
+            .common_state
+            .incoming_plaintext_full()
+        {
+            let msg = match self.deframe()? {
+                Some(msg) => msg,
+                None => break,
+            };
+
             match self.process_msg(msg, state) {
                 Ok(new) => state = new,
                 Err(e) => {
@@ -630,10 +705,37 @@ impl<Data> ConnectionCore<Data> {
             }
         }
 
+        // Records stay in the deframer while the plaintext limit is full.
+        self.common_state.has_deferred_records = self
+            .common_state
+            .incoming_plaintext_full()
+            && self.message_deframer.has_pending();
+
         self.state = Ok(state);
+        self.maybe_update_traffic_keys();
         Ok(self.common_state.current_io_state())
     }
 
+    /// Replace the write key if it has reached its confidentiality limit.
+    ///
+    /// `CommonState` notices this as it encrypts, but only the state
+    /// machine holds the key schedule needed to derive the next key.
+    pub(crate) fn maybe_update_traffic_keys(&mut self) {
+        if !self
+            .common_state
+            .wants_traffic_key_update()
+        {
+            return;
+        }
+
+        if let Ok(state) = &mut self.state {
+            #[cfg_attr(not(feature = "logging"), allow(unused_variables))]
+            if let Err(err) = state.send_key_update(&mut self.common_state) {
+                debug!("Cannot update write key yet: {:?}", err);
+            }
+        }
+    }
+
     /// Pull a message out of the deframer and send any messages that need to be sent as a result.
     fn deframe(&mut self) -> Result<Option<PlainMessage>, Error> {
         match self.message_deframer.pop(
@@ -732,6 +834,17 @@ impl<Data> ConnectionCore<Data> {
             .process_main_protocol(msg, state, &mut self.data)
     }
 
+    pub(crate) fn send_fatal_alert(&mut self, desc: AlertDescription) {
+        if self.state.is_err() {
+            return;
+        }
+
+        let err = self
+            .common_state
+            .send_fatal_alert(desc, Error::AlertSent(desc));
+        self.state = Err(err);
+    }
+
     pub(crate) fn export_keying_material<T: AsMut<[u8]>>(
         &self,
         mut output: T,
@@ -18,6 +18,13 @@ pub trait Tls13AeadAlgorithm: Send + Sync {
     /// The length of key in bytes required by `encrypter()` and `decrypter()`.
     fn key_len(&self) -> usize;
 
+    /// The length in bytes of the authentication tag added to each record.
+    ///
+    /// Defaults to 16.
+    fn tag_len(&self) -> usize {
+        16
+    }
+
     /// Convert the key material from `key`/`iv`, into a `ConnectionTrafficSecrets` item.
     ///
     /// May return [`UnsupportedOperationError`] if the AEAD algorithm is not a supported
@@ -27,6 +34,13 @@ pub trait Tls13AeadAlgorithm: Send + Sync {
         key: AeadKey,
         iv: Iv,
     ) -> Result<ConnectionTrafficSecrets, UnsupportedOperationError>;
+
+    /// Whether this AEAD is FIPS-approved, and implemented by a FIPS-validated module.
+    ///
+    /// Defaults to `false`.
+    fn fips(&self) -> bool {
+        false
+    }
 }
 
 /// Factory trait for building `MessageEncrypter` and `MessageDecrypter` for a TLS1.2 cipher suite.
@@ -52,6 +66,14 @@ pub trait Tls12AeadAlgorithm: Send + Sync + 'static {
     /// is split up prior to calling `encrypter()`, `decrypter()` and/or `extract_keys()`.
     fn key_block_shape(&self) -> KeyBlockShape;
 
+    /// The length in bytes of the authentication tag added to each record.
+    ///
+    /// Each record also carries [`KeyBlockShape::explicit_nonce_len`] bytes
+    /// of explicit nonce.  Defaults to 16.
+    fn tag_len(&self) -> usize {
+        16
+    }
+
     /// Convert the key material from `key`/`iv`, into a `ConnectionTrafficSecrets` item.
     ///
     /// The length of `key` is set by [`KeyBlockShape::enc_key_len`].
@@ -68,6 +90,13 @@ pub trait Tls12AeadAlgorithm: Send + Sync + 'static {
         iv: &[u8],
         explicit: &[u8],
     ) -> Result<ConnectionTrafficSecrets, UnsupportedOperationError>;
+
+    /// Whether this AEAD is FIPS-approved, and implemented by a FIPS-validated module.
+    ///
+    /// Defaults to `false`.
+    fn fips(&self) -> bool {
+        false
+    }
 }
 
 /// An error indicating that the AEAD algorithm does not support the requested operation.
@@ -128,6 +157,26 @@ pub trait MessageEncrypter: Send + Sync {
     /// Encrypt the given TLS message `msg`, using the sequence number
     /// `seq which can be used to derive a unique [`Nonce`].
     fn encrypt(&self, msg: BorrowedPlainMessage, seq: u64) -> Result<OpaqueMessage, Error>;
+
+    /// Encrypt the given TLS message `msg` like [`MessageEncrypter::encrypt`],
+    /// additionally appending `padding` zero bytes to the TLS1.3 inner
+    /// plaintext ([RFC8446 5.4]).
+    ///
+    /// The default implementation ignores `padding` and calls `encrypt`.
+    /// That is right for TLS1.2, which has no equivalent mechanism, but a
+    /// TLS1.3 encrypter must override it for a [`crate::RecordPaddingPolicy`]
+    /// to have any effect.
+    ///
+    /// [RFC8446 5.4]: <https://www.rfc-editor.org/rfc/rfc8446#section-5.4>
+    fn encrypt_padded(
+        &self,
+        msg: BorrowedPlainMessage,
+        seq: u64,
+        padding: usize,
+    ) -> Result<OpaqueMessage, Error> {
+        let _ = padding;
+        self.encrypt(msg, seq)
+    }
 }
 
 impl dyn MessageEncrypter {
@@ -16,6 +16,11 @@ pub trait Hash: Send + Sync {
 
     /// Which hash function this is, eg, `HashAlgorithm::SHA256`.
     fn algorithm(&self) -> HashAlgorithm;
+
+    /// Whether this hash is implemented by a FIPS-validated module.  Defaults to `false`.
+    fn fips(&self) -> bool {
+        false
+    }
 }
 
 /// A hash output, stored as a value.
@@ -8,6 +8,11 @@ pub trait Hmac: Send + Sync {
 
     /// Give the length of the underlying hash function.  In RFC2104 terminology this is `L`.
     fn hash_output_len(&self) -> usize;
+
+    /// Whether this HMAC is implemented by a FIPS-validated module.  Defaults to `false`.
+    fn fips(&self) -> bool {
+        false
+    }
 }
 
 /// A HMAC tag, stored as a value.
@@ -1,5 +1,5 @@
 use crate::suites;
-use crate::{Error, NamedGroup};
+use crate::{Error, NamedGroup, SignatureScheme};
 
 use core::fmt::Debug;
 
@@ -33,6 +33,147 @@ pub trait CryptoProvider: Send + Sync + Debug + 'static {
 
     /// Return a safe set of supported key exchange groups to be used as the defaults.
     fn default_kx_groups(&self) -> &'static [&'static dyn SupportedKxGroup];
+
+    /// Return the signature schemes this provider can verify, in preference order.
+    ///
+    /// This is useful for filtering a preference list before building a config.
+    ///
+    /// The default is empty.
+    fn supported_signature_schemes(&self) -> Vec<SignatureScheme> {
+        Vec::new()
+    }
+
+    /// Return this provider's implementation of the hash function `algorithm`, if any.
+    ///
+    /// rustls uses this for hashing outside of a cipher suite, such as matching
+    /// the `CertID` of a stapled OCSP response.
+    ///
+    /// The default looks for `algorithm` among the hash functions of
+    /// [`Self::default_cipher_suites()`].
+    fn hash_provider(&self, algorithm: hash::HashAlgorithm) -> Option<&'static dyn hash::Hash> {
+        self.default_cipher_suites()
+            .iter()
+            .map(|suite| suite.hash_provider())
+            .find(|hash| hash.algorithm() == algorithm)
+    }
+
+    /// Return `true` if this provider is operating in a FIPS-approved mode.
+    ///
+    /// This only covers the provider itself: see [`ClientConfig::fips()`] and
+    /// [`ServerConfig::fips()`] for a check over everything a config enables.
+    ///
+    /// The default is `false`.
+    ///
+    /// [`ClientConfig::fips()`]: crate::ClientConfig::fips
+    /// [`ServerConfig::fips()`]: crate::ServerConfig::fips
+    fn fips(&self) -> bool {
+        false
+    }
+}
+
+/// A [`CryptoProvider`] taking its cipher suites from one provider and its
+/// key exchange groups from another.
+///
+/// Randomness comes from the provider supplying the cipher suites.  The
+/// result is only FIPS-approved if both providers are.
+#[derive(Debug)]
+pub struct CompositeCryptoProvider {
+    suites: &'static dyn CryptoProvider,
+    kx_groups: &'static dyn CryptoProvider,
+}
+
+impl CompositeCryptoProvider {
+    /// Combine the cipher suites of `suites` with the key exchange groups of `kx_groups`.
+    ///
+    /// This fails if either list is empty, or if any suite's hash, HMAC and
+    /// AEAD implementations don't fit together (see [`Tls13CipherSuite::validate`]).
+    ///
+    /// If both providers claim to be FIPS-approved, so would the result: then
+    /// every suite and key exchange group must also report being FIPS-approved.
+    ///
+    /// [`Tls13CipherSuite::validate`]: crate::Tls13CipherSuite::validate
+    pub fn new(
+        suites: &'static dyn CryptoProvider,
+        kx_groups: &'static dyn CryptoProvider,
+    ) -> Result<Self, Error> {
+        if suites
+            .default_cipher_suites()
+            .is_empty()
+        {
+            return Err(Error::General("no cipher suites to compose".into()));
+        }
+
+        if kx_groups.default_kx_groups().is_empty() {
+            return Err(Error::General("no kx groups to compose".into()));
+        }
+
+        for suite in suites.default_cipher_suites() {
+            match suite {
+                #[cfg(feature = "tls12")]
+                suites::SupportedCipherSuite::Tls12(s) => {
+                    if s.hmac_provider.hash_output_len() != s.common.hash_provider.output_len() {
+                        return Err(Error::General(format!(
+                            "{:?} has mismatched hash and hmac providers",
+                            suite.suite()
+                        )));
+                    }
+                }
+                suites::SupportedCipherSuite::Tls13(s) => s.validate()?,
+            }
+        }
+
+        if suites.fips() && kx_groups.fips() {
+            if let Some(suite) = suites
+                .default_cipher_suites()
+                .iter()
+                .find(|suite| !suite.fips())
+            {
+                return Err(Error::General(format!(
+                    "{:?} is not FIPS-approved, but the composed provider is",
+                    suite.suite()
+                )));
+            }
+            if let Some(kx) = kx_groups
+                .default_kx_groups()
+                .iter()
+                .find(|kx| !kx.fips())
+            {
+                return Err(Error::General(format!(
+                    "{:?} is not FIPS-approved, but the composed provider is",
+                    kx.name()
+                )));
+            }
+        }
+
+        Ok(Self { suites, kx_groups })
+    }
+}
+
+impl CryptoProvider for CompositeCryptoProvider {
+    fn fill_random(&self, buf: &mut [u8]) -> Result<(), GetRandomFailed> {
+        self.suites.fill_random(buf)
+    }
+
+    fn default_cipher_suites(&self) -> &'static [suites::SupportedCipherSuite] {
+        self.suites.default_cipher_suites()
+    }
+
+    fn default_kx_groups(&self) -> &'static [&'static dyn SupportedKxGroup] {
+        self.kx_groups.default_kx_groups()
+    }
+
+    fn supported_signature_schemes(&self) -> Vec<SignatureScheme> {
+        self.suites
+            .supported_signature_schemes()
+    }
+
+    fn hash_provider(&self, algorithm: hash::HashAlgorithm) -> Option<&'static dyn hash::Hash> {
+        self.suites.hash_provider(algorithm)
+    }
+
+    fn fips(&self) -> bool {
+        self.suites.fips() && self.kx_groups.fips()
+    }
 }
 
 /// A supported key exchange group.
@@ -53,6 +194,13 @@ pub trait SupportedKxGroup: Send + Sync + Debug {
 
     /// Named group the SupportedKxGroup operates in.
     fn name(&self) -> NamedGroup;
+
+    /// Whether this group is FIPS-approved, and implemented by a FIPS-validated module.
+    ///
+    /// Defaults to `false`.
+    fn fips(&self) -> bool {
+        false
+    }
 }
 
 /// An in-progress key exchange originating from a `SupportedKxGroup`.
@@ -87,3 +235,115 @@ impl From<&[u8]> for SharedSecret {
         Self(source.to_vec())
     }
 }
+
+#[cfg(all(test, feature = "ring"))]
+mod tests {
+    use super::*;
+    use crate::crypto::ring::{kx_group, tls13, RING};
+    use crate::suites::{CipherSuiteCommon, SupportedCipherSuite};
+    use crate::tls13::Tls13CipherSuite;
+    use crate::{ClientConfig, RootCertStore};
+
+    /// A provider offering only the given suites, and no key exchange groups.
+    #[derive(Debug)]
+    struct SuitesOnly(&'static [SupportedCipherSuite]);
+
+    impl CryptoProvider for SuitesOnly {
+        fn fill_random(&self, buf: &mut [u8]) -> Result<(), GetRandomFailed> {
+            RING.fill_random(buf)
+        }
+
+        fn default_cipher_suites(&self) -> &'static [SupportedCipherSuite] {
+            self.0
+        }
+
+        fn default_kx_groups(&self) -> &'static [&'static dyn SupportedKxGroup] {
+            &[]
+        }
+    }
+
+    #[test]
+    fn ring_is_not_fips() {
+        assert!(!RING.fips());
+        assert!(!tls13::TLS13_AES_128_GCM_SHA256.fips());
+        assert!(!kx_group::X25519.fips());
+    }
+
+    #[test]
+    fn composite_provider_builds_client_config() {
+        let suite = tls13::TLS13_AES_128_GCM_SHA256;
+        let suites = Box::leak(Box::new(SuitesOnly(Box::leak(Box::new([suite])))));
+        let composite = CompositeCryptoProvider::new(suites, RING).unwrap();
+        assert_eq!(composite.default_cipher_suites(), &[suite]);
+        assert_eq!(
+            composite.default_kx_groups().len(),
+            RING.default_kx_groups().len()
+        );
+        assert!(!composite.fips());
+
+        let config = ClientConfig::builder_with_provider(Box::leak(Box::new(composite)))
+            .with_safe_defaults()
+            .with_root_certificates(RootCertStore::empty())
+            .with_no_client_auth();
+        assert!(!config.fips());
+    }
+
+    #[test]
+    fn ring_signature_schemes() {
+        assert_eq!(
+            RING.supported_signature_schemes(),
+            &[
+                SignatureScheme::ECDSA_NISTP384_SHA384,
+                SignatureScheme::ECDSA_NISTP256_SHA256,
+                SignatureScheme::ED25519,
+                SignatureScheme::RSA_PSS_SHA512,
+                SignatureScheme::RSA_PSS_SHA384,
+                SignatureScheme::RSA_PSS_SHA256,
+                SignatureScheme::RSA_PKCS1_SHA512,
+                SignatureScheme::RSA_PKCS1_SHA384,
+                SignatureScheme::RSA_PKCS1_SHA256,
+            ]
+        );
+
+        assert_eq!(
+            SuitesOnly(RING.default_cipher_suites()).supported_signature_schemes(),
+            &[]
+        );
+        assert_eq!(
+            CompositeCryptoProvider::new(RING, RING)
+                .unwrap()
+                .supported_signature_schemes(),
+            RING.supported_signature_schemes()
+        );
+    }
+
+    #[test]
+    fn composite_provider_rejects_unsatisfiable_suites() {
+        let base = tls13::TLS13_AES_128_GCM_SHA256_INTERNAL;
+        let mismatched = Tls13CipherSuite {
+            common: CipherSuiteCommon {
+                suite: base.common.suite,
+                hash_provider: base.common.hash_provider,
+            },
+            hmac_provider: tls13::TLS13_AES_256_GCM_SHA384
+                .tls13()
+                .unwrap()
+                .hmac_provider,
+            aead_alg: base.aead_alg,
+            confidentiality_limit: base.confidentiality_limit,
+            integrity_limit: base.integrity_limit,
+            #[cfg(feature = "quic")]
+            quic: base.quic,
+        };
+        let mismatched = SupportedCipherSuite::Tls13(Box::leak(Box::new(mismatched)));
+        let suites = Box::leak(Box::new(SuitesOnly(Box::leak(Box::new([mismatched])))));
+        assert!(CompositeCryptoProvider::new(suites, RING).is_err());
+
+        // nor can the key exchange groups come from a provider without any
+        let suites = Box::leak(Box::new(SuitesOnly(Box::leak(Box::new([
+            tls13::TLS13_AES_128_GCM_SHA256,
+        ])))));
+        assert!(CompositeCryptoProvider::new(RING, suites).is_err());
+        assert!(CompositeCryptoProvider::new(suites, RING).is_ok());
+    }
+}
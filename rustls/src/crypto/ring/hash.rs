@@ -2,8 +2,10 @@ use crate::crypto;
 use crate::msgs::enums::HashAlgorithm;
 use ring;
 
+pub(crate) static SHA1: Hash = Hash(&ring::digest::SHA1_FOR_LEGACY_USE_ONLY, HashAlgorithm::SHA1);
 pub(crate) static SHA256: Hash = Hash(&ring::digest::SHA256, HashAlgorithm::SHA256);
 pub(crate) static SHA384: Hash = Hash(&ring::digest::SHA384, HashAlgorithm::SHA384);
+pub(crate) static SHA512: Hash = Hash(&ring::digest::SHA512, HashAlgorithm::SHA512);
 
 pub(crate) struct Hash(&'static ring::digest::Algorithm, HashAlgorithm);
 
@@ -1,12 +1,16 @@
-use crate::crypto::{CryptoProvider, SupportedKxGroup};
+use crate::crypto::hash::HashAlgorithm;
+use crate::crypto::{self, CryptoProvider, SupportedKxGroup};
+use crate::enums::SignatureScheme;
 use crate::rand::GetRandomFailed;
 use crate::suites::SupportedCipherSuite;
+use crate::webpki::WebPkiServerVerifier;
 
 use ring::rand::{SecureRandom, SystemRandom};
 
 pub(crate) mod hash;
 pub(crate) mod hmac;
 pub(crate) mod kx;
+mod pbes2;
 #[cfg(feature = "quic")]
 pub(crate) mod quic;
 pub(crate) mod ticketer;
@@ -41,6 +45,23 @@ impl CryptoProvider for Ring {
     fn default_kx_groups(&self) -> &'static [&'static dyn SupportedKxGroup] {
         ALL_KX_GROUPS
     }
+
+    /// The signature schemes webpki's verifiers offer when built with *ring*.
+    fn supported_signature_schemes(&self) -> Vec<SignatureScheme> {
+        WebPkiServerVerifier::default_supported_verify_schemes()
+    }
+
+    /// *ring*'s SHA-256 and SHA-384, and also the SHA-1 and SHA-512 that no
+    /// cipher suite uses.
+    fn hash_provider(&self, algorithm: HashAlgorithm) -> Option<&'static dyn crypto::hash::Hash> {
+        match algorithm {
+            HashAlgorithm::SHA1 => Some(&hash::SHA1),
+            HashAlgorithm::SHA256 => Some(&hash::SHA256),
+            HashAlgorithm::SHA384 => Some(&hash::SHA384),
+            HashAlgorithm::SHA512 => Some(&hash::SHA512),
+            _ => None,
+        }
+    }
 }
 
 /// The cipher suite configuration that an application should use by default.
@@ -0,0 +1,354 @@
+use super::sign::SignErrorReason;
+use crate::x509::{
+    split_tlv, DER_INTEGER_TAG, DER_OCTET_STRING_TAG, DER_OID_TAG, DER_SEQUENCE_TAG,
+};
+
+use ring::aead::{self, Aad, LessSafeKey, Nonce, UnboundKey};
+use ring::pbkdf2;
+
+use alloc::vec::Vec;
+use core::num::NonZeroU32;
+use zeroize::Zeroizing;
+
+/// Decrypt a PBES2-encrypted PKCS#8 private key with `passphrase`, returning
+/// the DER-encoded `PrivateKeyInfo` within.
+///
+/// `EncryptedPrivateKeyInfo ::= SEQUENCE { encryptionAlgorithm, encryptedData }`
+///
+/// See [RFC5958 section 3](https://www.rfc-editor.org/rfc/rfc5958#section-3) and
+/// [RFC8018 section 6.2](https://www.rfc-editor.org/rfc/rfc8018#section-6.2).
+pub(crate) fn decrypt(
+    der: &[u8],
+    passphrase: &[u8],
+) -> Result<Zeroizing<Vec<u8>>, SignErrorReason> {
+    let info = expect_only_tlv(der, DER_SEQUENCE_TAG)?;
+    let (algorithm, info) = expect_tlv(info, DER_SEQUENCE_TAG)?;
+    let encrypted = expect_only_tlv(info, DER_OCTET_STRING_TAG)?;
+
+    let (oid, parameters) = expect_tlv(algorithm, DER_OID_TAG)?;
+    if oid != OID_PBES2 {
+        return Err(SignErrorReason::UnsupportedEncryption);
+    }
+
+    // PBES2-params ::= SEQUENCE { keyDerivationFunc, encryptionScheme }
+    let parameters = expect_only_tlv(parameters, DER_SEQUENCE_TAG)?;
+    let (kdf, parameters) = expect_tlv(parameters, DER_SEQUENCE_TAG)?;
+    let scheme = expect_only_tlv(parameters, DER_SEQUENCE_TAG)?;
+
+    let (cipher, nonce) = parse_scheme(scheme)?;
+    let mut key = Zeroizing::new([0u8; 32]);
+    let key = &mut key[..cipher.key_len()];
+    derive_key(kdf, passphrase, key)?;
+
+    let key = LessSafeKey::new(
+        UnboundKey::new(cipher, key).map_err(|_| SignErrorReason::BadDerEncoding)?,
+    );
+    let mut plaintext = Zeroizing::new(encrypted.to_vec());
+    let len = key
+        .open_in_place(nonce, Aad::empty(), &mut plaintext)
+        .map_err(|_| SignErrorReason::DecryptionFailed)?
+        .len();
+    plaintext.truncate(len);
+    Ok(plaintext)
+}
+
+/// `PBKDF2-params ::= SEQUENCE { salt, iterationCount, keyLength OPTIONAL, prf DEFAULT hmacWithSHA1 }`
+///
+/// Only a specified (`OCTET STRING`) salt is supported, and at most
+/// [`MAX_PBKDF2_ITERATIONS`].
+fn derive_key(kdf: &[u8], passphrase: &[u8], key: &mut [u8]) -> Result<(), SignErrorReason> {
+    let (oid, parameters) = expect_tlv(kdf, DER_OID_TAG)?;
+    if oid != OID_PBKDF2 {
+        return Err(SignErrorReason::UnsupportedEncryption);
+    }
+    derive_pbkdf2(
+        expect_only_tlv(parameters, DER_SEQUENCE_TAG)?,
+        passphrase,
+        key,
+    )
+}
+
+fn derive_pbkdf2(
+    parameters: &[u8],
+    passphrase: &[u8],
+    key: &mut [u8],
+) -> Result<(), SignErrorReason> {
+    let (salt, parameters) = match split_tlv(parameters) {
+        Some((DER_OCTET_STRING_TAG, salt, rest)) => (salt, rest),
+        Some(_) => return Err(SignErrorReason::UnsupportedEncryption),
+        None => return Err(SignErrorReason::BadDerEncoding),
+    };
+    let (iterations, mut parameters) = expect_tlv(parameters, DER_INTEGER_TAG)?;
+    let iterations = parse_u32(iterations)
+        .and_then(NonZeroU32::new)
+        .ok_or(SignErrorReason::BadDerEncoding)?;
+    if iterations.get() > MAX_PBKDF2_ITERATIONS {
+        return Err(SignErrorReason::UnsupportedEncryption);
+    }
+    parameters = skip_key_length(parameters, key)?;
+
+    let prf = match parameters {
+        [] => pbkdf2::PBKDF2_HMAC_SHA1,
+        _ => {
+            let prf = expect_only_tlv(parameters, DER_SEQUENCE_TAG)?;
+            let (oid, parameters) = expect_tlv(prf, DER_OID_TAG)?;
+            if !matches!(parameters, [] | DER_NULL) {
+                return Err(SignErrorReason::BadDerEncoding);
+            }
+            match oid {
+                OID_HMAC_WITH_SHA1 => pbkdf2::PBKDF2_HMAC_SHA1,
+                OID_HMAC_WITH_SHA256 => pbkdf2::PBKDF2_HMAC_SHA256,
+                OID_HMAC_WITH_SHA384 => pbkdf2::PBKDF2_HMAC_SHA384,
+                OID_HMAC_WITH_SHA512 => pbkdf2::PBKDF2_HMAC_SHA512,
+                _ => return Err(SignErrorReason::UnsupportedEncryption),
+            }
+        }
+    };
+
+    pbkdf2::derive(prf, iterations, salt, passphrase, key);
+    Ok(())
+}
+
+/// Skip the optional `keyLength` of the KDF parameters, which must be the length of `key`.
+fn skip_key_length<'a>(parameters: &'a [u8], key: &[u8]) -> Result<&'a [u8], SignErrorReason> {
+    match split_tlv(parameters) {
+        Some((DER_INTEGER_TAG, key_len, rest)) => match parse_u32(key_len) {
+            Some(key_len) if key_len as usize == key.len() => Ok(rest),
+            _ => Err(SignErrorReason::UnsupportedEncryption),
+        },
+        _ => Ok(parameters),
+    }
+}
+
+/// Parse an AES-GCM `encryptionScheme`, returning the cipher and nonce.
+///
+/// `GCMParameters ::= SEQUENCE { aes-nonce OCTET STRING, aes-ICVlen INTEGER DEFAULT 12 }`
+///
+/// See [RFC5084 section 3.2](https://www.rfc-editor.org/rfc/rfc5084#section-3.2).  *ring*
+/// supports only 12-byte nonces and 16-byte tags.
+fn parse_scheme(scheme: &[u8]) -> Result<(&'static aead::Algorithm, Nonce), SignErrorReason> {
+    let (oid, parameters) = expect_tlv(scheme, DER_OID_TAG)?;
+    let cipher = match oid {
+        OID_AES128_GCM => &aead::AES_128_GCM,
+        OID_AES256_GCM => &aead::AES_256_GCM,
+        _ => return Err(SignErrorReason::UnsupportedEncryption),
+    };
+
+    let parameters = expect_only_tlv(parameters, DER_SEQUENCE_TAG)?;
+    let (nonce, parameters) = expect_tlv(parameters, DER_OCTET_STRING_TAG)?;
+    let tag_len = match parameters {
+        [] => 12,
+        _ => parse_u32(expect_only_tlv(parameters, DER_INTEGER_TAG)?)
+            .ok_or(SignErrorReason::BadDerEncoding)?,
+    };
+    if tag_len as usize != cipher.tag_len() {
+        return Err(SignErrorReason::UnsupportedEncryption);
+    }
+
+    let nonce = Nonce::try_assume_unique_for_key(nonce)
+        .map_err(|_| SignErrorReason::UnsupportedEncryption)?;
+    Ok((cipher, nonce))
+}
+
+/// Parse the contents of a non-negative DER `INTEGER` that fits in a `u32`.
+fn parse_u32(bytes: &[u8]) -> Option<u32> {
+    let bytes = match bytes {
+        [0, rest @ ..] if !rest.is_empty() => rest,
+        [first, ..] if first & 0x80 == 0 => bytes,
+        _ => return None,
+    };
+    if bytes.len() > 4 {
+        return None;
+    }
+    Some(
+        bytes
+            .iter()
+            .fold(0, |value, &byte| (value << 8) | byte as u32),
+    )
+}
+
+/// Like `expect_tlv`, but there must be nothing after the TLV.
+fn expect_only_tlv(input: &[u8], expected: u8) -> Result<&[u8], SignErrorReason> {
+    match expect_tlv(input, expected)? {
+        (contents, []) => Ok(contents),
+        _ => Err(SignErrorReason::BadDerEncoding),
+    }
+}
+
+/// Like `split_tlv`, but only succeeds if the tag is `expected`.
+fn expect_tlv(input: &[u8], expected: u8) -> Result<(&[u8], &[u8]), SignErrorReason> {
+    match split_tlv(input) {
+        Some((tag, contents, rest)) if tag == expected => Ok((contents, rest)),
+        _ => Err(SignErrorReason::BadDerEncoding),
+    }
+}
+
+const DER_NULL: &[u8] = &[0x05, 0x00];
+
+/// The largest PBKDF2 iteration count accepted.
+///
+/// This bounds the work a key file can demand, while being well above the
+/// 600,000 iterations of PBKDF2-HMAC-SHA256 that OWASP currently recommends.
+const MAX_PBKDF2_ITERATIONS: u32 = 10_000_000;
+
+/// 1.2.840.113549.1.5.13
+const OID_PBES2: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x05, 0x0d];
+/// 1.2.840.113549.1.5.12
+const OID_PBKDF2: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x05, 0x0c];
+/// 1.2.840.113549.2.7
+const OID_HMAC_WITH_SHA1: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x02, 0x07];
+/// 1.2.840.113549.2.9
+const OID_HMAC_WITH_SHA256: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x02, 0x09];
+/// 1.2.840.113549.2.10
+const OID_HMAC_WITH_SHA384: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x02, 0x0a];
+/// 1.2.840.113549.2.11
+const OID_HMAC_WITH_SHA512: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x02, 0x0b];
+/// 2.16.840.1.101.3.4.1.6
+const OID_AES128_GCM: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x01, 0x06];
+/// 2.16.840.1.101.3.4.1.46
+const OID_AES256_GCM: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x01, 0x2e];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::x509::{wrap_in_asn1_len, wrap_in_sequence};
+
+    /// Encode a DER TLV with the given tag and contents.
+    fn tlv(tag: u8, contents: &[u8]) -> Vec<u8> {
+        let mut tlv = contents.to_vec();
+        wrap_in_asn1_len(&mut tlv);
+        tlv.insert(0, tag);
+        tlv
+    }
+
+    fn sequence(items: &[&[u8]]) -> Vec<u8> {
+        let mut sequence = items.concat();
+        wrap_in_sequence(&mut sequence);
+        sequence
+    }
+
+    /// An `EncryptedPrivateKeyInfo` using PBES2 with the given KDF and scheme.
+    fn encrypted_private_key_info(kdf: &[u8], scheme: &[u8]) -> Vec<u8> {
+        sequence(&[
+            &sequence(&[&tlv(DER_OID_TAG, OID_PBES2), &sequence(&[kdf, scheme])]),
+            &tlv(DER_OCTET_STRING_TAG, &[0u8; 64]),
+        ])
+    }
+
+    fn pbkdf2() -> Vec<u8> {
+        sequence(&[
+            &tlv(DER_OID_TAG, OID_PBKDF2),
+            &sequence(&[
+                &tlv(DER_OCTET_STRING_TAG, &[0u8; 8]),
+                &tlv(DER_INTEGER_TAG, &[0x08, 0x00]),
+            ]),
+        ])
+    }
+
+    fn aes256_gcm() -> Vec<u8> {
+        sequence(&[
+            &tlv(DER_OID_TAG, OID_AES256_GCM),
+            &sequence(&[
+                &tlv(DER_OCTET_STRING_TAG, &[0u8; 12]),
+                &tlv(DER_INTEGER_TAG, &[16]),
+            ]),
+        ])
+    }
+
+    #[test]
+    fn aes_cbc_is_unsupported() {
+        // 2.16.840.1.101.3.4.1.42, aes256-CBC, with a 16-byte IV.
+        let aes256_cbc = sequence(&[
+            &tlv(
+                DER_OID_TAG,
+                &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x01, 0x2a],
+            ),
+            &tlv(DER_OCTET_STRING_TAG, &[0u8; 16]),
+        ]);
+        assert_eq!(
+            decrypt(
+                &encrypted_private_key_info(&pbkdf2(), &aes256_cbc),
+                b"rustls"
+            ),
+            Err(SignErrorReason::UnsupportedEncryption)
+        );
+    }
+
+    #[test]
+    fn scrypt_is_unsupported() {
+        // 1.3.6.1.4.1.11591.4.11, scrypt, with N = 16384, r = 8 and p = 1.
+        let scrypt = sequence(&[
+            &tlv(
+                DER_OID_TAG,
+                &[0x2b, 0x06, 0x01, 0x04, 0x01, 0xda, 0x47, 0x04, 0x0b],
+            ),
+            &sequence(&[
+                &tlv(DER_OCTET_STRING_TAG, &[0u8; 8]),
+                &tlv(DER_INTEGER_TAG, &[0x40, 0x00]),
+                &tlv(DER_INTEGER_TAG, &[0x08]),
+                &tlv(DER_INTEGER_TAG, &[0x01]),
+            ]),
+        ]);
+        assert_eq!(
+            decrypt(
+                &encrypted_private_key_info(&scrypt, &aes256_gcm()),
+                b"rustls"
+            ),
+            Err(SignErrorReason::UnsupportedEncryption)
+        );
+    }
+
+    #[test]
+    fn pbkdf2_and_aes_gcm_are_attempted() {
+        // The all-zero ciphertext does not authenticate under any passphrase.
+        assert_eq!(
+            decrypt(
+                &encrypted_private_key_info(&pbkdf2(), &aes256_gcm()),
+                b"rustls"
+            ),
+            Err(SignErrorReason::DecryptionFailed)
+        );
+    }
+
+    #[test]
+    fn trailing_data_is_rejected() {
+        let encrypted = tlv(DER_OCTET_STRING_TAG, &[0u8; 64]);
+
+        // after PBES2-params
+        let info = sequence(&[
+            &sequence(&[
+                &tlv(DER_OID_TAG, OID_PBES2),
+                &sequence(&[&pbkdf2(), &aes256_gcm()]),
+                DER_NULL,
+            ]),
+            &encrypted,
+        ]);
+        assert_eq!(
+            decrypt(&info, b"rustls"),
+            Err(SignErrorReason::BadDerEncoding)
+        );
+
+        // after encryptionScheme
+        let info = sequence(&[
+            &sequence(&[
+                &tlv(DER_OID_TAG, OID_PBES2),
+                &sequence(&[&pbkdf2(), &aes256_gcm(), DER_NULL]),
+            ]),
+            &encrypted,
+        ]);
+        assert_eq!(
+            decrypt(&info, b"rustls"),
+            Err(SignErrorReason::BadDerEncoding)
+        );
+    }
+
+    #[test]
+    fn pbkdf2_iterations_are_capped() {
+        // PBKDF2-params with an empty salt and 2^31 - 1 iterations.
+        let parameters = [0x04, 0x00, 0x02, 0x04, 0x7f, 0xff, 0xff, 0xff];
+        assert_eq!(
+            derive_pbkdf2(&parameters, b"", &mut [0u8; 16]),
+            Err(SignErrorReason::UnsupportedEncryption)
+        );
+    }
+}
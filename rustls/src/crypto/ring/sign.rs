@@ -1,12 +1,14 @@
+use super::pbes2;
+use crate::crypto::signer::{try_classify, Unclassified};
 use crate::enums::{SignatureAlgorithm, SignatureScheme};
 use crate::error::Error;
-use crate::sign::{Signer, SigningKey};
+use crate::sign::{KeyKind, Signer, SigningKey};
 use crate::x509::{wrap_in_asn1_len, wrap_in_sequence};
 
 use pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer};
 use ring::io::der;
-use ring::rand::{SecureRandom, SystemRandom};
-use ring::signature::{self, EcdsaKeyPair, Ed25519KeyPair, RsaKeyPair};
+use ring::rand::SystemRandom;
+use ring::signature::{self, EcdsaKeyPair, Ed25519KeyPair, KeyPair, RsaKeyPair};
 
 use alloc::sync::Arc;
 use core::fmt;
@@ -20,45 +22,68 @@ pub fn any_supported_type(der: &PrivateKeyDer<'_>) -> Result<Arc<dyn SigningKey>
     } else if let Ok(ecdsa) = any_ecdsa_type(der) {
         Ok(ecdsa)
     } else if let PrivateKeyDer::Pkcs8(pkcs8) = der {
-        any_eddsa_type(pkcs8)
+        any_eddsa_type(pkcs8).map_err(|_| SignError::rejected(der, SUPPORTED_KEY_KINDS))
     } else {
-        Err(SignError(()))
+        Err(SignError::rejected(der, SUPPORTED_KEY_KINDS))
     }
 }
 
+/// Decrypt `der`, an encrypted PKCS#8 private key (PEM section starting with
+/// 'BEGIN ENCRYPTED PRIVATE KEY'), using `passphrase`, and then parse it as
+/// [`any_supported_type`] does.
+///
+/// Only [PBES2] encryption is supported, using PBKDF2 with HMAC-SHA1, -SHA256,
+/// -SHA384 or -SHA512 to derive a key for AES-128-GCM or AES-256-GCM (with a
+/// 12-byte nonce and a 16-byte tag).  Other schemes fail with
+/// [`SignErrorReason::UnsupportedEncryption`], as do PBKDF2 iteration counts
+/// over ten million; notably, *ring* does not implement the AES-CBC or scrypt
+/// that `openssl pkcs8` uses.
+///
+/// A wrong `passphrase` fails with [`SignErrorReason::DecryptionFailed`].  The
+/// decrypted key is zeroized once it has been parsed.
+///
+/// [PBES2]: https://www.rfc-editor.org/rfc/rfc8018#section-6.2
+pub fn any_supported_type_encrypted(
+    der: &[u8],
+    passphrase: &[u8],
+) -> Result<Arc<dyn SigningKey>, SignError> {
+    let pkcs8 = pbes2::decrypt(der, passphrase).map_err(SignError::new)?;
+    any_supported_type(&PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(
+        pkcs8.as_slice(),
+    )))
+}
+
+static SUPPORTED_KEY_KINDS: &[KeyKind] = &[
+    KeyKind::Rsa,
+    KeyKind::EcdsaP256,
+    KeyKind::EcdsaP384,
+    KeyKind::Ed25519,
+];
+
 /// Parse `der` as any ECDSA key type, returning the first which works.
 ///
 /// Both SEC1 (PEM section starting with 'BEGIN EC PRIVATE KEY') and PKCS8
 /// (PEM section starting with 'BEGIN PRIVATE KEY') encodings are supported.
+///
+/// Keys on the NIST P-256 and P-384 curves are supported.  P-521 keys are
+/// refused with [`SignErrorReason::UnsupportedCurve`], because *ring* cannot
+/// sign with that curve.
 pub fn any_ecdsa_type(der: &PrivateKeyDer<'_>) -> Result<Arc<dyn SigningKey>, SignError> {
-    if let Ok(ecdsa_p256) = EcdsaSigningKey::new(
-        der,
-        SignatureScheme::ECDSA_NISTP256_SHA256,
-        &signature::ECDSA_P256_SHA256_ASN1_SIGNING,
-    ) {
-        return Ok(Arc::new(ecdsa_p256));
-    }
-
-    if let Ok(ecdsa_p384) = EcdsaSigningKey::new(
-        der,
-        SignatureScheme::ECDSA_NISTP384_SHA384,
-        &signature::ECDSA_P384_SHA384_ASN1_SIGNING,
-    ) {
-        return Ok(Arc::new(ecdsa_p384));
-    }
-
-    Err(SignError(()))
+    Ok(Arc::new(EcdsaSigningKey::new(der)?))
 }
 
 /// Parse `der` as any EdDSA key type, returning the first which works.
 pub fn any_eddsa_type(der: &PrivatePkcs8KeyDer<'_>) -> Result<Arc<dyn SigningKey>, SignError> {
-    if let Ok(ed25519) = Ed25519SigningKey::new(der, SignatureScheme::ED25519) {
+    if let Ok(ed25519) = Ed25519SigningKey::new(der) {
         return Ok(Arc::new(ed25519));
     }
 
     // TODO: Add support for Ed448
 
-    Err(SignError(()))
+    Err(SignError::rejected(
+        &PrivateKeyDer::Pkcs8(der.secret_pkcs8_der().into()),
+        &[KeyKind::Ed25519],
+    ))
 }
 
 /// A `SigningKey` for RSA-PKCS1 or RSA-PSS.
@@ -68,6 +93,7 @@ pub fn any_eddsa_type(der: &PrivatePkcs8KeyDer<'_>) -> Result<Arc<dyn SigningKey
 #[doc(hidden)]
 pub struct RsaSigningKey {
     key: Arc<RsaKeyPair>,
+    schemes: Vec<SignatureScheme>,
 }
 
 static ALL_RSA_SCHEMES: &[SignatureScheme] = &[
@@ -83,27 +109,64 @@ impl RsaSigningKey {
     /// Make a new `RsaSigningKey` from a DER encoding, in either
     /// PKCS#1 or PKCS#8 format.
     pub fn new(der: &PrivateKeyDer<'_>) -> Result<Self, SignError> {
+        Self::new_with_schemes(der, ALL_RSA_SCHEMES)
+    }
+
+    /// Make a new `RsaSigningKey` from a DER encoding, in either
+    /// PKCS#1 or PKCS#8 format, that only signs with the given schemes.
+    ///
+    /// For example, passing only the `RSA_PSS_*` schemes forbids PKCS#1v1.5
+    /// signatures.  Schemes in `schemes` that are not RSA schemes are ignored;
+    /// it is an error if no RSA scheme remains.
+    pub fn new_with_schemes(
+        der: &PrivateKeyDer<'_>,
+        schemes: &[SignatureScheme],
+    ) -> Result<Self, SignError> {
+        let schemes = ALL_RSA_SCHEMES
+            .iter()
+            .copied()
+            .filter(|scheme| schemes.contains(scheme))
+            .collect::<Vec<_>>();
+        if schemes.is_empty() {
+            return Err(SignError::new(SignErrorReason::NoSupportedSchemes));
+        }
+
         let key_pair = match der {
             PrivateKeyDer::Pkcs1(pkcs1) => RsaKeyPair::from_der(pkcs1.secret_pkcs1_der()),
             PrivateKeyDer::Pkcs8(pkcs8) => RsaKeyPair::from_pkcs8(pkcs8.secret_pkcs8_der()),
-            _ => return Err(SignError(())),
+            _ => return Err(SignError::rejected(der, &[KeyKind::Rsa])),
         }
-        .map_err(|_| SignError(()))?;
+        .map_err(|_| SignError::rejected(der, &[KeyKind::Rsa]))?;
 
         Ok(Self {
             key: Arc::new(key_pair),
+            schemes,
         })
     }
+
+    /// The public modulus, as big-endian bytes without leading zeros.
+    pub fn public_modulus(&self) -> Vec<u8> {
+        signature::RsaPublicKeyComponents::<Vec<u8>>::from(self.key.public()).n
+    }
+
+    /// The public exponent, as big-endian bytes without leading zeros.
+    pub fn public_exponent(&self) -> Vec<u8> {
+        signature::RsaPublicKeyComponents::<Vec<u8>>::from(self.key.public()).e
+    }
 }
 
 impl SigningKey for RsaSigningKey {
     fn choose_scheme(&self, offered: &[SignatureScheme]) -> Option<Box<dyn Signer>> {
-        ALL_RSA_SCHEMES
+        self.schemes
             .iter()
             .find(|scheme| offered.contains(scheme))
             .map(|scheme| RsaSigner::new(Arc::clone(&self.key), *scheme))
     }
 
+    fn supported_schemes(&self) -> &[SignatureScheme] {
+        &self.schemes
+    }
+
     fn algorithm(&self) -> SignatureAlgorithm {
         SignatureAlgorithm::RSA
     }
@@ -162,30 +225,59 @@ impl Signer for RsaSigner {
 /// different protocol versions.
 ///
 /// Currently this is only implemented for ECDSA keys.
-struct EcdsaSigningKey {
+///
+/// Parsing a key is much more expensive than cloning one: a server that
+/// rebuilds its configuration often can keep an `EcdsaSigningKey` and
+/// use a clone of it each time, rather than loading the key again.
+#[derive(Clone)]
+pub struct EcdsaSigningKey {
     key: Arc<EcdsaKeyPair>,
     scheme: SignatureScheme,
 }
 
 impl EcdsaSigningKey {
-    /// Make a new `ECDSASigningKey` from a DER encoding in PKCS#8 or SEC1
+    /// Make a new `EcdsaSigningKey` from a DER encoding in PKCS#8 or SEC1
+    /// format, for a key on either the NIST P-256 or P-384 curve.
+    pub fn new(der: &PrivateKeyDer<'_>) -> Result<Self, SignError> {
+        Self::new_for_scheme(
+            der,
+            SignatureScheme::ECDSA_NISTP256_SHA256,
+            &signature::ECDSA_P256_SHA256_ASN1_SIGNING,
+        )
+        .or_else(|_| {
+            Self::new_for_scheme(
+                der,
+                SignatureScheme::ECDSA_NISTP384_SHA384,
+                &signature::ECDSA_P384_SHA384_ASN1_SIGNING,
+            )
+        })
+        .map_err(|_| SignError::rejected(der, &[KeyKind::EcdsaP256, KeyKind::EcdsaP384]))
+    }
+
+    /// The public key, as an uncompressed SEC1 elliptic curve point.
+    ///
+    /// This is `0x04` followed by the big-endian x and y coordinates.
+    pub fn public_key_sec1(&self) -> Vec<u8> {
+        self.key.public_key().as_ref().to_vec()
+    }
+
+    /// Make a new `EcdsaSigningKey` from a DER encoding in PKCS#8 or SEC1
     /// format, expecting a key usable with precisely the given signature
     /// scheme.
-    fn new(
+    fn new_for_scheme(
         der: &PrivateKeyDer<'_>,
         scheme: SignatureScheme,
         sigalg: &'static signature::EcdsaSigningAlgorithm,
     ) -> Result<Self, ()> {
-        let rng = SystemRandom::new();
-        let key_pair = match der {
+        let pkcs8 = match der {
             PrivateKeyDer::Sec1(sec1) => {
-                Self::convert_sec1_to_pkcs8(scheme, sigalg, sec1.secret_sec1_der(), &rng)?
-            }
-            PrivateKeyDer::Pkcs8(pkcs8) => {
-                EcdsaKeyPair::from_pkcs8(sigalg, pkcs8.secret_pkcs8_der(), &rng).map_err(|_| ())?
+                Self::convert_sec1_to_pkcs8(scheme, sec1.secret_sec1_der())
             }
+            PrivateKeyDer::Pkcs8(pkcs8) => pkcs8.secret_pkcs8_der().to_vec(),
             _ => return Err(()),
         };
+        let key_pair =
+            EcdsaKeyPair::from_pkcs8(sigalg, &pkcs8, &SystemRandom::new()).map_err(|_| ())?;
 
         Ok(Self {
             key: Arc::new(key_pair),
@@ -193,15 +285,10 @@ impl EcdsaSigningKey {
         })
     }
 
-    /// Convert a SEC1 encoding to PKCS8, and ask ring to parse it.  This
+    /// Convert a SEC1 encoding to PKCS8, so ring can parse it.  This
     /// can be removed once <https://github.com/briansmith/ring/pull/1456>
     /// (or equivalent) is landed.
-    fn convert_sec1_to_pkcs8(
-        scheme: SignatureScheme,
-        sigalg: &'static signature::EcdsaSigningAlgorithm,
-        maybe_sec1_der: &[u8],
-        rng: &dyn SecureRandom,
-    ) -> Result<EcdsaKeyPair, ()> {
+    fn convert_sec1_to_pkcs8(scheme: SignatureScheme, maybe_sec1_der: &[u8]) -> Vec<u8> {
         let pkcs8_prefix = match scheme {
             SignatureScheme::ECDSA_NISTP256_SHA256 => &PKCS8_PREFIX_ECDSA_NISTP256,
             SignatureScheme::ECDSA_NISTP384_SHA384 => &PKCS8_PREFIX_ECDSA_NISTP384,
@@ -218,8 +305,7 @@ impl EcdsaSigningKey {
         pkcs8.extend_from_slice(pkcs8_prefix);
         pkcs8.extend_from_slice(&sec1_wrap);
         wrap_in_sequence(&mut pkcs8);
-
-        EcdsaKeyPair::from_pkcs8(sigalg, &pkcs8, rng).map_err(|_| ())
+        pkcs8
     }
 }
 
@@ -258,6 +344,10 @@ impl SigningKey for EcdsaSigningKey {
     fn algorithm(&self) -> SignatureAlgorithm {
         self.scheme.sign()
     }
+
+    fn supported_schemes(&self) -> &[SignatureScheme] {
+        core::slice::from_ref(&self.scheme)
+    }
 }
 
 struct EcdsaSigner {
@@ -290,23 +380,32 @@ impl Signer for EcdsaSigner {
 /// different protocol versions.
 ///
 /// Currently this is only implemented for Ed25519 keys.
-struct Ed25519SigningKey {
+pub struct Ed25519SigningKey {
     key: Arc<Ed25519KeyPair>,
     scheme: SignatureScheme,
 }
 
 impl Ed25519SigningKey {
-    /// Make a new `Ed25519SigningKey` from a DER encoding in PKCS#8 format,
-    /// expecting a key usable with precisely the given signature scheme.
-    fn new(der: &PrivatePkcs8KeyDer<'_>, scheme: SignatureScheme) -> Result<Self, SignError> {
+    /// Make a new `Ed25519SigningKey` from a DER encoding in PKCS#8 format.
+    pub fn new(der: &PrivatePkcs8KeyDer<'_>) -> Result<Self, SignError> {
         match Ed25519KeyPair::from_pkcs8_maybe_unchecked(der.secret_pkcs8_der()) {
             Ok(key_pair) => Ok(Self {
                 key: Arc::new(key_pair),
-                scheme,
+                scheme: SignatureScheme::ED25519,
             }),
-            Err(_) => Err(SignError(())),
+            Err(_) => Err(SignError::rejected(
+                &PrivateKeyDer::Pkcs8(der.secret_pkcs8_der().into()),
+                &[KeyKind::Ed25519],
+            )),
         }
     }
+
+    /// The raw 32-byte public key, as described in [RFC8032 section 5.1.5].
+    ///
+    /// [RFC8032 section 5.1.5]: <https://www.rfc-editor.org/rfc/rfc8032#section-5.1.5>
+    pub fn public_key_raw(&self) -> Vec<u8> {
+        self.key.public_key().as_ref().to_vec()
+    }
 }
 
 impl SigningKey for Ed25519SigningKey {
@@ -324,6 +423,10 @@ impl SigningKey for Ed25519SigningKey {
     fn algorithm(&self) -> SignatureAlgorithm {
         self.scheme.sign()
     }
+
+    fn supported_schemes(&self) -> &[SignatureScheme] {
+        core::slice::from_ref(&self.scheme)
+    }
 }
 
 struct Ed25519Signer {
@@ -343,19 +446,70 @@ impl Signer for Ed25519Signer {
 
 /// Errors while signing
 #[derive(Debug)]
-pub struct SignError(());
+pub struct SignError(SignErrorReason);
+
+impl SignError {
+    fn new(reason: SignErrorReason) -> Self {
+        Self(reason)
+    }
+
+    /// The error for a key that could not be loaded, when only keys of the
+    /// `expected` kinds are supported.
+    fn rejected(der: &PrivateKeyDer<'_>, expected: &[KeyKind]) -> Self {
+        Self::new(match try_classify(der) {
+            Ok(kind) if expected.contains(&kind) => SignErrorReason::BadDerEncoding,
+            Ok(KeyKind::EcdsaP521) => SignErrorReason::UnsupportedCurve,
+            Ok(_) | Err(Unclassified::UnknownAlgorithm) => SignErrorReason::UnsupportedKeyType,
+            Err(Unclassified::BadEncoding) => SignErrorReason::BadDerEncoding,
+            Err(Unclassified::UnknownCurve) => SignErrorReason::UnsupportedCurve,
+        })
+    }
+
+    /// Why the error occurred.
+    pub fn reason(&self) -> SignErrorReason {
+        self.0
+    }
+}
 
 impl fmt::Display for SignError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.write_str("sign error")
+        f.write_str("sign error: ")?;
+        f.write_str(match self.0 {
+            SignErrorReason::UnsupportedKeyType => "unsupported key type",
+            SignErrorReason::BadDerEncoding => "invalid key encoding",
+            SignErrorReason::UnsupportedCurve => "unsupported elliptic curve",
+            SignErrorReason::NoSupportedSchemes => "no supported signature schemes",
+            SignErrorReason::UnsupportedEncryption => "unsupported key encryption",
+            SignErrorReason::DecryptionFailed => "key decryption failed",
+        })
     }
 }
 
+/// Why a [`SignError`] occurred.
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SignErrorReason {
+    /// The key is of a type that is not supported.
+    UnsupportedKeyType,
+    /// The key encoding, or the key material within it, is invalid.
+    BadDerEncoding,
+    /// The key is an elliptic curve key on an unsupported curve.
+    UnsupportedCurve,
+    /// None of the requested signature schemes can be used with the key.
+    NoSupportedSchemes,
+    /// The key is encrypted with an unsupported scheme.
+    UnsupportedEncryption,
+    /// The key could not be decrypted: the passphrase is wrong, or the
+    /// encrypted key is corrupt.
+    DecryptionFailed,
+}
+
 impl StdError for SignError {}
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::sign::classify;
     use pki_types::{PrivatePkcs1KeyDer, PrivateSec1KeyDer};
 
     #[test]
@@ -405,6 +559,26 @@ mod tests {
         assert!(any_ecdsa_type(&key).is_err());
     }
 
+    #[test]
+    fn cannot_load_ecdsa_nistp521() {
+        for key in [
+            PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(
+                &include_bytes!("../../testdata/nistp521key.pkcs8.der")[..],
+            )),
+            PrivateKeyDer::Sec1(PrivateSec1KeyDer::from(
+                &include_bytes!("../../testdata/nistp521key.der")[..],
+            )),
+        ] {
+            assert_eq!(classify(&key), KeyKind::EcdsaP521);
+            for result in [any_ecdsa_type(&key), any_supported_type(&key)] {
+                assert_eq!(
+                    result.err().map(|err| err.reason()),
+                    Some(SignErrorReason::UnsupportedCurve)
+                );
+            }
+        }
+    }
+
     #[test]
     fn can_load_rsa2048_pkcs8() {
         let key =
@@ -415,6 +589,254 @@ mod tests {
         assert!(any_ecdsa_type(&key).is_err());
     }
 
+    #[test]
+    fn can_load_encrypted_pkcs8() {
+        let p256 = any_supported_type_encrypted(
+            include_bytes!("../../testdata/nistp256key.pkcs8.aes256gcm.der"),
+            b"rustls",
+        )
+        .unwrap();
+        assert_eq!(p256.algorithm(), SignatureAlgorithm::ECDSA);
+
+        let rsa = any_supported_type_encrypted(
+            include_bytes!("../../testdata/rsa2048key.pkcs8.aes128gcm.der"),
+            b"rustls",
+        )
+        .unwrap();
+        assert_eq!(rsa.algorithm(), SignatureAlgorithm::RSA);
+    }
+
+    #[test]
+    fn encrypted_pkcs8_errors_give_a_reason() {
+        fn reason(der: &[u8], passphrase: &[u8]) -> Option<SignErrorReason> {
+            any_supported_type_encrypted(der, passphrase)
+                .err()
+                .map(|err| err.reason())
+        }
+
+        let p256 = include_bytes!("../../testdata/nistp256key.pkcs8.aes256gcm.der");
+        assert_eq!(
+            reason(p256, b"wrong"),
+            Some(SignErrorReason::DecryptionFailed)
+        );
+        assert_eq!(
+            reason(&p256[..100], b"rustls"),
+            Some(SignErrorReason::BadDerEncoding)
+        );
+
+        let mut trailing = p256.to_vec();
+        trailing.push(0);
+        assert_eq!(
+            reason(&trailing, b"rustls"),
+            Some(SignErrorReason::BadDerEncoding)
+        );
+
+        // An unencrypted key is not an `EncryptedPrivateKeyInfo`.
+        assert_eq!(
+            reason(
+                include_bytes!("../../testdata/nistp256key.pkcs8.der"),
+                b"rustls"
+            ),
+            Some(SignErrorReason::BadDerEncoding)
+        );
+    }
+
+    #[test]
+    fn ecdsa_public_keys_are_uncompressed_points() {
+        let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(
+            &include_bytes!("../../testdata/nistp256key.pkcs8.der")[..],
+        ));
+        let public = EcdsaSigningKey::new(&key)
+            .unwrap()
+            .public_key_sec1();
+        assert_eq!(public.len(), 65);
+        assert_eq!(public[0], 0x04);
+
+        // the same key in SEC1 form
+        let key = PrivateKeyDer::Sec1(PrivateSec1KeyDer::from(
+            &include_bytes!("../../testdata/nistp256key.der")[..],
+        ));
+        assert_eq!(
+            EcdsaSigningKey::new(&key)
+                .unwrap()
+                .public_key_sec1(),
+            public
+        );
+
+        let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(
+            &include_bytes!("../../testdata/nistp384key.pkcs8.der")[..],
+        ));
+        let public = EcdsaSigningKey::new(&key)
+            .unwrap()
+            .public_key_sec1();
+        assert_eq!(public.len(), 97);
+        assert_eq!(public[0], 0x04);
+
+        let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(
+            &include_bytes!("../../testdata/rsa2048key.pkcs8.der")[..],
+        ));
+        assert!(EcdsaSigningKey::new(&key).is_err());
+    }
+
+    #[test]
+    fn ecdsa_keys_clone_without_reparsing() {
+        let sec1 = EcdsaSigningKey::new(&PrivateKeyDer::Sec1(PrivateSec1KeyDer::from(
+            &include_bytes!("../../testdata/nistp384key.der")[..],
+        )))
+        .unwrap();
+
+        let cloned = sec1.clone();
+        assert!(Arc::ptr_eq(&cloned.key, &sec1.key));
+        assert_eq!(cloned.public_key_sec1(), sec1.public_key_sec1());
+        assert_eq!(cloned.supported_schemes(), sec1.supported_schemes());
+    }
+
+    #[test]
+    fn ed25519_public_key_is_raw() {
+        let key = PrivatePkcs8KeyDer::from(&include_bytes!("../../testdata/eddsakey.der")[..]);
+        let public = Ed25519SigningKey::new(&key)
+            .unwrap()
+            .public_key_raw();
+        assert_eq!(public.len(), 32);
+    }
+
+    #[test]
+    fn rsa_public_key_components() {
+        let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(
+            &include_bytes!("../../testdata/rsa2048key.pkcs8.der")[..],
+        ));
+        let key = RsaSigningKey::new(&key).unwrap();
+        assert_eq!(key.public_modulus().len(), 256);
+        assert_ne!(key.public_modulus()[0], 0);
+        assert_eq!(key.public_exponent(), vec![0x01, 0x00, 0x01]);
+    }
+
+    #[test]
+    fn rsa_key_restricted_to_pss() {
+        let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(
+            &include_bytes!("../../testdata/rsa2048key.pkcs8.der")[..],
+        ));
+        let key = RsaSigningKey::new_with_schemes(
+            &key,
+            &[
+                SignatureScheme::RSA_PSS_SHA256,
+                SignatureScheme::RSA_PSS_SHA384,
+                SignatureScheme::RSA_PSS_SHA512,
+            ],
+        )
+        .unwrap();
+
+        assert!(key
+            .choose_scheme(&[SignatureScheme::RSA_PKCS1_SHA256])
+            .is_none());
+        assert_eq!(
+            key.choose_scheme(&[
+                SignatureScheme::RSA_PKCS1_SHA512,
+                SignatureScheme::RSA_PSS_SHA256
+            ])
+            .unwrap()
+            .scheme(),
+            SignatureScheme::RSA_PSS_SHA256
+        );
+    }
+
+    #[test]
+    fn rsa_key_needs_an_rsa_scheme() {
+        let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(
+            &include_bytes!("../../testdata/rsa2048key.pkcs8.der")[..],
+        ));
+        assert_eq!(
+            RsaSigningKey::new_with_schemes(&key, &[SignatureScheme::ECDSA_NISTP256_SHA256])
+                .err()
+                .map(|err| err.reason()),
+            Some(SignErrorReason::NoSupportedSchemes)
+        );
+    }
+
+    #[test]
+    fn keys_report_supported_schemes() {
+        let rsa = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(
+            &include_bytes!("../../testdata/rsa2048key.pkcs8.der")[..],
+        ));
+        assert_eq!(
+            any_supported_type(&rsa)
+                .unwrap()
+                .supported_schemes(),
+            ALL_RSA_SCHEMES
+        );
+        assert_eq!(
+            RsaSigningKey::new_with_schemes(&rsa, &[SignatureScheme::RSA_PSS_SHA256])
+                .unwrap()
+                .supported_schemes(),
+            &[SignatureScheme::RSA_PSS_SHA256]
+        );
+
+        let ecdsa = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(
+            &include_bytes!("../../testdata/nistp384key.pkcs8.der")[..],
+        ));
+        assert_eq!(
+            any_supported_type(&ecdsa)
+                .unwrap()
+                .supported_schemes(),
+            &[SignatureScheme::ECDSA_NISTP384_SHA384]
+        );
+
+        let ed25519 = PrivatePkcs8KeyDer::from(&include_bytes!("../../testdata/eddsakey.der")[..]);
+        assert_eq!(
+            any_eddsa_type(&ed25519)
+                .unwrap()
+                .supported_schemes(),
+            &[SignatureScheme::ED25519]
+        );
+    }
+
+    #[test]
+    fn sign_errors_give_a_reason() {
+        fn reason(result: Result<Arc<dyn SigningKey>, SignError>) -> Option<SignErrorReason> {
+            result.err().map(|err| err.reason())
+        }
+
+        let truncated = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(
+            &include_bytes!("../../testdata/rsa2048key.pkcs8.der")[..100],
+        ));
+        assert_eq!(
+            reason(any_supported_type(&truncated)),
+            Some(SignErrorReason::BadDerEncoding)
+        );
+
+        let ed448 = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(
+            &include_bytes!("../../testdata/ed448key.der")[..],
+        ));
+        assert_eq!(
+            reason(any_supported_type(&ed448)),
+            Some(SignErrorReason::UnsupportedKeyType)
+        );
+
+        // A SEC1 key naming secp521r1 as its curve.
+        let p521 = PrivateKeyDer::Sec1(PrivateSec1KeyDer::from(
+            &[
+                0x30, 0x0f, 0x02, 0x01, 0x01, 0x04, 0x01, 0x01, 0xa0, 0x07, 0x06, 0x05, 0x2b, 0x81,
+                0x04, 0x00, 0x23,
+            ][..],
+        ));
+        assert_eq!(
+            reason(any_supported_type(&p521)),
+            Some(SignErrorReason::UnsupportedCurve)
+        );
+        assert_eq!(
+            reason(any_ecdsa_type(&p521)),
+            Some(SignErrorReason::UnsupportedCurve)
+        );
+
+        let rsa = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(
+            &include_bytes!("../../testdata/rsa2048key.pkcs8.der")[..],
+        ));
+        assert_eq!(
+            reason(any_ecdsa_type(&rsa)),
+            Some(SignErrorReason::UnsupportedKeyType)
+        );
+    }
+
     #[test]
     fn can_load_rsa2048_pkcs1() {
         let key = PrivateKeyDer::Pkcs1(PrivatePkcs1KeyDer::from(
@@ -423,11 +845,45 @@ mod tests {
         assert!(any_supported_type(&key).is_ok());
         assert!(any_ecdsa_type(&key).is_err());
     }
+
+    #[test]
+    fn rsa_key_reports_mismatch_with_ecdsa_offer() {
+        let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(
+            &include_bytes!("../../testdata/rsa2048key.pkcs8.der")[..],
+        ));
+        let key = any_supported_type(&key).unwrap();
+        let offered = [
+            SignatureScheme::ECDSA_NISTP256_SHA256,
+            SignatureScheme::ECDSA_NISTP384_SHA384,
+        ];
+        assert!(key.choose_scheme(&offered).is_none());
+
+        let mismatch = match key.choose_scheme_checked(&offered) {
+            Err(mismatch) => mismatch,
+            Ok(_) => panic!("RSA key accepted ECDSA schemes"),
+        };
+        assert_eq!(mismatch.algorithm, SignatureAlgorithm::RSA);
+        assert_eq!(mismatch.offered, offered);
+        assert_eq!(mismatch.supported, ALL_RSA_SCHEMES);
+        assert_eq!(
+            mismatch.to_string(),
+            "no signature scheme in common: RSA key supports [RSA_PSS_SHA512, RSA_PSS_SHA384, \
+             RSA_PSS_SHA256, RSA_PKCS1_SHA512, RSA_PKCS1_SHA384, RSA_PKCS1_SHA256], \
+             peer offered [ECDSA_NISTP256_SHA256, ECDSA_NISTP384_SHA384]"
+        );
+
+        assert_eq!(
+            key.choose_scheme_checked(&[SignatureScheme::RSA_PSS_SHA256])
+                .map(|signer| signer.scheme()),
+            Ok(SignatureScheme::RSA_PSS_SHA256)
+        );
+    }
 }
 
 #[cfg(bench)]
 mod benchmarks {
     use super::{PrivateKeyDer, PrivatePkcs8KeyDer, SignatureScheme};
+    use pki_types::PrivateSec1KeyDer;
 
     #[bench]
     fn bench_rsa2048_pkcs1_sha256(b: &mut test::Bencher) {
@@ -557,6 +1013,29 @@ mod benchmarks {
         });
     }
 
+    #[bench]
+    fn bench_load_and_validate_p256_sec1(b: &mut test::Bencher) {
+        let key = PrivateKeyDer::Sec1(PrivateSec1KeyDer::from(
+            &include_bytes!("../../testdata/nistp256key.der")[..],
+        ));
+
+        b.iter(|| {
+            test::black_box(super::any_ecdsa_type(&key).unwrap());
+        });
+    }
+
+    #[bench]
+    fn bench_clone_p256(b: &mut test::Bencher) {
+        let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(
+            &include_bytes!("../../testdata/nistp256key.pkcs8.der")[..],
+        ));
+        let key = super::EcdsaSigningKey::new(&key).unwrap();
+
+        b.iter(|| {
+            test::black_box(key.clone());
+        });
+    }
+
     #[bench]
     fn bench_load_and_validate_p384(b: &mut test::Bencher) {
         let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(
@@ -15,6 +15,16 @@ impl Ticketer {
     ///
     /// The encryption mechanism used is Chacha20Poly1305.
     pub fn new() -> Result<Arc<dyn ProducesTickets>, Error> {
+        Ok(Self::new_switcher()?)
+    }
+
+    /// Make the recommended Ticketer, like [`Ticketer::new`], but return
+    /// the [`TicketSwitcher`] itself so its keys can also be rotated on
+    /// demand with [`TicketSwitcher::rotate`].
+    ///
+    /// [`TicketSwitcher`]: crate::TicketSwitcher
+    /// [`TicketSwitcher::rotate`]: crate::TicketSwitcher::rotate
+    pub fn new_switcher() -> Result<Arc<crate::ticketer::TicketSwitcher>, Error> {
         Ok(Arc::new(crate::ticketer::TicketSwitcher::new(
             6 * 60 * 60,
             make_ticket_generator,
@@ -142,6 +152,43 @@ mod tests {
         assert_eq!(t.decrypt(&cipher3).unwrap(), b"ticket 3");
     }
 
+    #[test]
+    fn ticketswitcher_rotate_test() {
+        let t = Ticketer::new_switcher().unwrap();
+        let cipher1 = t.encrypt(b"ticket 1").unwrap();
+        t.rotate().unwrap();
+        let cipher2 = t.encrypt(b"ticket 2").unwrap();
+        assert_eq!(t.decrypt(&cipher1).unwrap(), b"ticket 1");
+        assert_eq!(t.decrypt(&cipher2).unwrap(), b"ticket 2");
+
+        t.rotate().unwrap();
+        let cipher3 = t.encrypt(b"ticket 3").unwrap();
+        assert!(t.decrypt(&cipher1).is_none());
+        assert_eq!(t.decrypt(&cipher2).unwrap(), b"ticket 2");
+        assert_eq!(t.decrypt(&cipher3).unwrap(), b"ticket 3");
+    }
+
+    #[test]
+    fn ticketswitcher_rotate_recovers_missing_next() {
+        let mut t = crate::ticketer::TicketSwitcher::new(1, make_ticket_generator).unwrap();
+        let now = UnixTime::now();
+        let cipher1 = t.encrypt(b"ticket 1").unwrap();
+        t.generator = fail_generator;
+        {
+            // Failed new ticketer, leaving no `next`
+            t.maybe_roll(UnixTime::since_unix_epoch(Duration::from_secs(
+                now.as_secs() + 10,
+            )));
+        }
+        assert!(t.rotate().is_err());
+
+        t.generator = make_ticket_generator;
+        t.rotate().unwrap();
+        let cipher2 = t.encrypt(b"ticket 2").unwrap();
+        assert!(t.decrypt(&cipher1).is_none());
+        assert_eq!(t.decrypt(&cipher2).unwrap(), b"ticket 2");
+    }
+
     #[cfg(test)]
     fn fail_generator() -> Result<Box<dyn ProducesTickets>, GetRandomFailed> {
         Err(GetRandomFailed)
@@ -22,9 +22,7 @@ pub(crate) static TLS13_CHACHA20_POLY1305_SHA256_INTERNAL: &Tls13CipherSuite = &
     },
     hmac_provider: &super::hmac::HMAC_SHA256,
     aead_alg: &Chacha20Poly1305Aead(AeadAlgorithm(&ring::aead::CHACHA20_POLY1305)),
-    #[cfg(feature = "quic")]
     confidentiality_limit: u64::MAX,
-    #[cfg(feature = "quic")]
     integrity_limit: 1 << 36,
     #[cfg(feature = "quic")]
     quic: &super::quic::KeyBuilder(&ring::aead::CHACHA20_POLY1305, &ring::aead::quic::CHACHA20),
@@ -39,9 +37,7 @@ pub static TLS13_AES_256_GCM_SHA384: SupportedCipherSuite =
         },
         hmac_provider: &super::hmac::HMAC_SHA384,
         aead_alg: &Aes256GcmAead(AeadAlgorithm(&ring::aead::AES_256_GCM)),
-        #[cfg(feature = "quic")]
         confidentiality_limit: 1 << 23,
-        #[cfg(feature = "quic")]
         integrity_limit: 1 << 52,
         #[cfg(feature = "quic")]
         quic: &super::quic::KeyBuilder(&ring::aead::AES_256_GCM, &aead::quic::AES_256),
@@ -58,9 +54,7 @@ pub(crate) static TLS13_AES_128_GCM_SHA256_INTERNAL: &Tls13CipherSuite = &Tls13C
     },
     hmac_provider: &super::hmac::HMAC_SHA256,
     aead_alg: &Aes128GcmAead(AeadAlgorithm(&ring::aead::AES_128_GCM)),
-    #[cfg(feature = "quic")]
     confidentiality_limit: 1 << 23,
-    #[cfg(feature = "quic")]
     integrity_limit: 1 << 52,
     #[cfg(feature = "quic")]
     quic: &super::quic::KeyBuilder(&ring::aead::AES_128_GCM, &aead::quic::AES_128),
@@ -175,10 +169,20 @@ struct Tls13MessageDecrypter {
 
 impl MessageEncrypter for Tls13MessageEncrypter {
     fn encrypt(&self, msg: BorrowedPlainMessage, seq: u64) -> Result<OpaqueMessage, Error> {
-        let total_len = msg.payload.len() + 1 + self.enc_key.algorithm().tag_len();
+        self.encrypt_padded(msg, seq, 0)
+    }
+
+    fn encrypt_padded(
+        &self,
+        msg: BorrowedPlainMessage,
+        seq: u64,
+        padding: usize,
+    ) -> Result<OpaqueMessage, Error> {
+        let total_len = msg.payload.len() + 1 + padding + self.enc_key.algorithm().tag_len();
         let mut payload = Vec::with_capacity(total_len);
         payload.extend_from_slice(msg.payload);
         msg.typ.encode(&mut payload);
+        payload.resize(payload.len() + padding, 0);
 
         let nonce = aead::Nonce::assume_unique_for_key(Nonce::new(&self.iv, seq).0);
         let aad = aead::Aad::from(make_tls13_aad(total_len));
@@ -1,9 +1,16 @@
 use crate::enums::{SignatureAlgorithm, SignatureScheme};
 use crate::error::Error;
+#[cfg(feature = "logging")]
+use crate::log::debug;
+use crate::x509::{split_tlv, DER_OID_TAG, DER_SEQUENCE_TAG};
 
-use pki_types::CertificateDer;
+use pki_types::{CertificateDer, PrivateKeyDer};
 
+use alloc::string::String;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+use std::error::Error as StdError;
 
 /// An abstract signing key.
 pub trait SigningKey: Send + Sync {
@@ -11,12 +18,67 @@ pub trait SigningKey: Send + Sync {
     ///
     /// Expresses the choice by returning something that implements `Signer`,
     /// using the chosen scheme.
+    ///
+    /// `offered` only contains schemes usable with the negotiated protocol
+    /// version: in TLS1.3 that excludes the `RSA_PKCS1_*` schemes, so an RSA
+    /// key signs with RSA-PSS.  A `Signer` for a scheme that was not offered
+    /// is treated as if this returned `None`.
     fn choose_scheme(&self, offered: &[SignatureScheme]) -> Option<Box<dyn Signer>>;
 
+    /// Like `choose_scheme`, but says why no scheme could be chosen.
+    ///
+    /// This is for diagnostics: the default implementation calls `choose_scheme`
+    /// (applying the same treatment of unoffered schemes) and, if that fails,
+    /// reports `offered` alongside the key's `algorithm` and `supported_schemes`.
+    fn choose_scheme_checked(
+        &self,
+        offered: &[SignatureScheme],
+    ) -> Result<Box<dyn Signer>, SchemeMismatch> {
+        self.choose_scheme(offered)
+            .filter(|signer| offered.contains(&signer.scheme()))
+            .ok_or_else(|| SchemeMismatch {
+                algorithm: self.algorithm(),
+                offered: offered.to_vec(),
+                supported: self.supported_schemes().to_vec(),
+            })
+    }
+
     /// What kind of key we have.
     fn algorithm(&self) -> SignatureAlgorithm;
+
+    /// The schemes this key could sign with, most preferred first.
+    ///
+    /// This is informational: `choose_scheme` decides which scheme is used.
+    /// The default implementation returns an empty slice, meaning the
+    /// schemes are not known.
+    fn supported_schemes(&self) -> &[SignatureScheme] {
+        &[]
+    }
+}
+
+/// Why [`SigningKey::choose_scheme_checked`] found no usable scheme.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SchemeMismatch {
+    /// What kind of key was asked to sign.
+    pub algorithm: SignatureAlgorithm,
+    /// The schemes the peer offered.
+    pub offered: Vec<SignatureScheme>,
+    /// The schemes the key reports it can sign with; empty if it does not say.
+    pub supported: Vec<SignatureScheme>,
 }
 
+impl fmt::Display for SchemeMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "no signature scheme in common: {:?} key supports {:?}, peer offered {:?}",
+            self.algorithm, self.supported, self.offered
+        )
+    }
+}
+
+impl StdError for SchemeMismatch {}
+
 /// A thing that can sign a message.
 pub trait Signer: Send + Sync {
     /// Signs `message` using the selected scheme.
@@ -26,6 +88,54 @@ pub trait Signer: Send + Sync {
     fn scheme(&self) -> SignatureScheme;
 }
 
+/// How many times the handshake attempts a signature, should
+/// [`Signer::sign`] fail transiently.
+///
+/// This is for signers that may fail transiently, such as those backed by
+/// a remote service or an HSM.  Only failures the signer reports as
+/// [`Error::TransientSigningFailure`] are retried, and they are retried
+/// immediately: rustls is synchronous, so any wait before retrying is up to
+/// the signer.  Only the last error is returned, once all attempts fail.
+///
+/// The default is [`SigningRetryPolicy::NONE`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SigningRetryPolicy {
+    /// The total number of attempts, including the first.  Zero is treated
+    /// as one, and more than [`SigningRetryPolicy::MAX_ATTEMPTS`] as that
+    /// many.
+    pub attempts: u32,
+}
+
+impl SigningRetryPolicy {
+    /// Sign once, and never retry.
+    pub const NONE: Self = Self { attempts: 1 };
+
+    /// The most attempts made, whatever `attempts` says.
+    pub const MAX_ATTEMPTS: u32 = 8;
+
+    /// Sign `message` with `signer`, retrying failures as this policy allows.
+    pub(crate) fn sign(&self, signer: &dyn Signer, message: &[u8]) -> Result<Vec<u8>, Error> {
+        let attempts = self.attempts.min(Self::MAX_ATTEMPTS);
+        let mut attempt = 1;
+        loop {
+            match signer.sign(message) {
+                #[cfg_attr(not(feature = "logging"), allow(unused_variables))]
+                Err(err @ Error::TransientSigningFailure(_)) if attempt < attempts => {
+                    debug!("signing attempt {} failed: {:?}", attempt, err);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+impl Default for SigningRetryPolicy {
+    fn default() -> Self {
+        Self::NONE
+    }
+}
+
 /// A packaged-together certificate chain, matching `SigningKey` and
 /// optional stapled OCSP response and/or SCT list.
 #[derive(Clone)]
@@ -39,6 +149,8 @@ pub struct CertifiedKey {
     /// An optional OCSP response from the certificate issuer,
     /// attesting to its continued validity.
     pub ocsp: Option<Vec<u8>>,
+
+    tls13_certificate_extensions: Vec<(u16, Vec<u8>)>,
 }
 
 impl CertifiedKey {
@@ -51,9 +163,25 @@ impl CertifiedKey {
             cert,
             key,
             ocsp: None,
+            tls13_certificate_extensions: Vec::new(),
         }
     }
 
+    /// Send `extensions` in the end-entity certificate's entry of a TLS1.3
+    /// server `Certificate` message, as (type, payload) pairs.
+    ///
+    /// These are sent as given, after any OCSP response.  Clients only
+    /// accept them with a [`crate::client::HandlesCertificateExtensions`].
+    pub fn with_tls13_certificate_extensions(mut self, extensions: Vec<(u16, Vec<u8>)>) -> Self {
+        self.tls13_certificate_extensions = extensions;
+        self
+    }
+
+    /// The extensions set by [`CertifiedKey::with_tls13_certificate_extensions`].
+    pub fn tls13_certificate_extensions(&self) -> &[(u16, Vec<u8>)] {
+        &self.tls13_certificate_extensions
+    }
+
     /// The end-entity certificate.
     pub fn end_entity_cert(&self) -> Result<&CertificateDer<'_>, Error> {
         self.cert
@@ -61,3 +189,495 @@ impl CertifiedKey {
             .ok_or(Error::NoCertificatesPresented)
     }
 }
+
+/// A [`SigningKey`] whose signatures are made by calling a function.
+///
+/// This is for keys that rustls cannot see, like those held in an HSM
+/// reached over the network: the function is given the chosen scheme and
+/// the message, and returns the signature or an error, which ends the
+/// handshake.  An [`Error::TransientSigningFailure`] may instead be retried,
+/// as the config's [`SigningRetryPolicy`] allows.
+///
+/// # Where the function is called
+///
+/// It is called synchronously, from within `process_new_packets()`, while
+/// the connection deals with the peer's handshake messages:
+///
+/// - a server signs when it processes the client's `ClientHello`, for the
+///   TLS1.3 `CertificateVerify` or TLS1.2 `ServerKeyExchange` message.
+/// - a client signs when it processes the server's final flight of the
+///   handshake, for its `CertificateVerify` message, if the server asked
+///   for client authentication.
+///
+/// The function may block, and nothing happens on the connection until it
+/// returns, so there is no need to keep state between calls.  It must not
+/// use the connection itself.  With an async runtime, run
+/// `process_new_packets()` somewhere blocking is allowed, such as a
+/// blocking task, and have the function wait for the remote signer there.
+///
+/// # Example
+///
+/// A server whose key is used by `remote_sign`:
+///
+/// ```
+/// # #[cfg(feature = "ring")] {
+/// use std::sync::Arc;
+///
+/// use rustls::server::{ClientHello, ResolvesServerCert};
+/// use rustls::sign::{CallbackSigningKey, CertifiedKey};
+/// use rustls::{Error, ServerConfig, SignatureAlgorithm, SignatureScheme};
+///
+/// fn remote_sign(scheme: SignatureScheme, message: &[u8]) -> Result<Vec<u8>, Error> {
+///     // talk to the HSM here
+/// #   let _ = (scheme, message);
+///     Err(Error::General("HSM unreachable".into()))
+/// }
+///
+/// struct RemoteKeyResolver(Arc<CertifiedKey>);
+///
+/// impl ResolvesServerCert for RemoteKeyResolver {
+///     fn resolve(&self, _: ClientHello) -> Option<Arc<CertifiedKey>> {
+///         Some(Arc::clone(&self.0))
+///     }
+/// }
+///
+/// let key = CallbackSigningKey::new(
+///     SignatureAlgorithm::ECDSA,
+///     &[SignatureScheme::ECDSA_NISTP256_SHA256],
+///     remote_sign,
+/// );
+/// # let certs = Vec::new();
+/// let certified = CertifiedKey::new(certs, Arc::new(key));
+///
+/// let config = ServerConfig::builder()
+///     .with_safe_defaults()
+///     .with_no_client_auth()
+///     .with_cert_resolver(Arc::new(RemoteKeyResolver(Arc::new(certified))));
+/// # }
+/// ```
+pub struct CallbackSigningKey {
+    algorithm: SignatureAlgorithm,
+    schemes: Vec<SignatureScheme>,
+    sign: Arc<SignCallback>,
+}
+
+type SignCallback = dyn Fn(SignatureScheme, &[u8]) -> Result<Vec<u8>, Error> + Send + Sync;
+
+impl CallbackSigningKey {
+    /// Make a key of kind `algorithm` that signs with `sign`.
+    ///
+    /// `schemes` are those the key can sign with, most preferred first: the
+    /// first that the peer offers is chosen and passed to `sign`.
+    pub fn new(
+        algorithm: SignatureAlgorithm,
+        schemes: &[SignatureScheme],
+        sign: impl Fn(SignatureScheme, &[u8]) -> Result<Vec<u8>, Error> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            algorithm,
+            schemes: schemes.to_vec(),
+            sign: Arc::new(sign),
+        }
+    }
+}
+
+impl SigningKey for CallbackSigningKey {
+    fn choose_scheme(&self, offered: &[SignatureScheme]) -> Option<Box<dyn Signer>> {
+        let scheme = self
+            .schemes
+            .iter()
+            .find(|scheme| offered.contains(scheme))?;
+        Some(Box::new(CallbackSigner {
+            scheme: *scheme,
+            sign: Arc::clone(&self.sign),
+        }))
+    }
+
+    fn algorithm(&self) -> SignatureAlgorithm {
+        self.algorithm
+    }
+
+    fn supported_schemes(&self) -> &[SignatureScheme] {
+        &self.schemes
+    }
+}
+
+impl fmt::Debug for CallbackSigningKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CallbackSigningKey")
+            .field("algorithm", &self.algorithm)
+            .field("schemes", &self.schemes)
+            .finish_non_exhaustive()
+    }
+}
+
+struct CallbackSigner {
+    scheme: SignatureScheme,
+    sign: Arc<SignCallback>,
+}
+
+impl Signer for CallbackSigner {
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, Error> {
+        (self.sign)(self.scheme, message)
+    }
+
+    fn scheme(&self) -> SignatureScheme {
+        self.scheme
+    }
+}
+
+/// Loads signing keys that are named by a URI, rather than given as DER.
+///
+/// This is the extension point for keys held in hardware tokens: for example,
+/// a crate supporting PKCS#11 implements this for [RFC 7512] `pkcs11:` URIs,
+/// returning a [`SigningKey`] which has the token do the signing.  Handlers
+/// are registered on a [`KeyUriHandlers`].
+///
+/// [RFC 7512]: https://www.rfc-editor.org/rfc/rfc7512
+pub trait KeyUriHandler: Send + Sync {
+    /// Load the key named by `uri`.
+    ///
+    /// `uri` is the complete URI, including the scheme this handler was
+    /// registered for.
+    fn load_key(&self, uri: &str) -> Result<Arc<dyn SigningKey>, Error>;
+}
+
+/// A set of [`KeyUriHandler`]s, each for a URI scheme.
+///
+/// rustls itself provides no handlers: the built-in key loading functions,
+/// like `any_supported_type`, only accept DER-encoded keys.
+#[derive(Clone, Default)]
+pub struct KeyUriHandlers {
+    // Pairs of lowercase scheme and its handler.
+    handlers: Vec<(String, Arc<dyn KeyUriHandler>)>,
+}
+
+impl KeyUriHandlers {
+    /// Make an empty set of handlers.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use `handler` for URIs with the given `scheme`, such as `"pkcs11"`.
+    ///
+    /// Schemes are matched case-insensitively.  This replaces any handler
+    /// previously registered for `scheme`.
+    pub fn register_key_uri_handler(&mut self, scheme: &str, handler: Arc<dyn KeyUriHandler>) {
+        let scheme = scheme.to_ascii_lowercase();
+        self.handlers
+            .retain(|(existing, _)| *existing != scheme);
+        self.handlers.push((scheme, handler));
+    }
+
+    /// Load the key named by `uri`, using the handler registered for its scheme.
+    ///
+    /// This fails if `uri` has no scheme, or there is no handler for it.
+    pub fn load_key_uri(&self, uri: &str) -> Result<Arc<dyn SigningKey>, Error> {
+        let scheme = match uri.split_once(':') {
+            Some((scheme, _)) if !scheme.is_empty() => scheme.to_ascii_lowercase(),
+            _ => return Err(Error::General(format!("key URI {:?} has no scheme", uri))),
+        };
+
+        match self
+            .handlers
+            .iter()
+            .find(|(registered, _)| *registered == scheme)
+        {
+            Some((_, handler)) => handler.load_key(uri),
+            None => Err(Error::General(format!(
+                "no handler for key URI scheme {:?}",
+                scheme
+            ))),
+        }
+    }
+}
+
+impl fmt::Debug for KeyUriHandlers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyUriHandlers")
+            .field(
+                "schemes",
+                &self
+                    .handlers
+                    .iter()
+                    .map(|(scheme, _)| scheme)
+                    .collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+/// The kind of a private key, as determined by [`classify`].
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyKind {
+    /// An RSA key.
+    Rsa,
+    /// An ECDSA key on the NIST P-256 curve.
+    EcdsaP256,
+    /// An ECDSA key on the NIST P-384 curve.
+    EcdsaP384,
+    /// An ECDSA key on the NIST P-521 curve.
+    EcdsaP521,
+    /// An Ed25519 key.
+    Ed25519,
+    /// A key of some other type, or one that could not be classified.
+    Unknown,
+}
+
+/// Determine what kind of key `der` contains, without loading it.
+///
+/// This only peeks at the algorithm identifiers in the key encoding: it
+/// does not validate the key material, so a key classified here may
+/// still fail to load.  For SEC1-encoded keys, the curve is only known if
+/// the optional `parameters` field is present.
+pub fn classify(der: &PrivateKeyDer<'_>) -> KeyKind {
+    try_classify(der).unwrap_or(KeyKind::Unknown)
+}
+
+/// Why [`try_classify`] could not determine the kind of a key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Unclassified {
+    /// The key encoding could not be parsed.
+    BadEncoding,
+    /// The key is for an algorithm we do not recognise.
+    UnknownAlgorithm,
+    /// The key is an elliptic curve key, but on an unrecognised curve
+    /// (or, for SEC1, on an unnamed one).
+    UnknownCurve,
+}
+
+/// Like [`classify`], but saying why a key was not classified.
+///
+/// This never returns `Ok(KeyKind::Unknown)`.
+pub(crate) fn try_classify(der: &PrivateKeyDer<'_>) -> Result<KeyKind, Unclassified> {
+    match der {
+        PrivateKeyDer::Pkcs1(_) => Ok(KeyKind::Rsa),
+        PrivateKeyDer::Sec1(sec1) => classify_sec1(sec1.secret_sec1_der()),
+        PrivateKeyDer::Pkcs8(pkcs8) => classify_pkcs8(pkcs8.secret_pkcs8_der()),
+        _ => Err(Unclassified::UnknownAlgorithm),
+    }
+}
+
+/// `ECPrivateKey ::= SEQUENCE { version, privateKey, [0] parameters OPTIONAL, .. }`
+///
+/// See [RFC5915 section 3](https://www.rfc-editor.org/rfc/rfc5915#section-3).
+fn classify_sec1(der: &[u8]) -> Result<KeyKind, Unclassified> {
+    let (key, _) = expect_tlv(der, DER_SEQUENCE_TAG)?;
+    let (_version, _, key) = split_tlv(key).ok_or(Unclassified::BadEncoding)?;
+    let (_private_key, _, mut key) = split_tlv(key).ok_or(Unclassified::BadEncoding)?;
+
+    while !key.is_empty() {
+        let (tag, contents, rest) = split_tlv(key).ok_or(Unclassified::BadEncoding)?;
+        if tag == EC_PARAMETERS_TAG {
+            let (curve, _) = expect_tlv(contents, DER_OID_TAG)?;
+            return curve_kind(curve);
+        }
+        key = rest;
+    }
+
+    Err(Unclassified::UnknownCurve)
+}
+
+/// `PrivateKeyInfo ::= SEQUENCE { version, privateKeyAlgorithm, privateKey, .. }`
+///
+/// See [RFC5208 section 5](https://www.rfc-editor.org/rfc/rfc5208#section-5).
+fn classify_pkcs8(der: &[u8]) -> Result<KeyKind, Unclassified> {
+    let (key, _) = expect_tlv(der, DER_SEQUENCE_TAG)?;
+    let (_version, _, key) = split_tlv(key).ok_or(Unclassified::BadEncoding)?;
+    let (algorithm, _) = expect_tlv(key, DER_SEQUENCE_TAG)?;
+    let (oid, parameters) = expect_tlv(algorithm, DER_OID_TAG)?;
+
+    match oid {
+        OID_RSA_ENCRYPTION => Ok(KeyKind::Rsa),
+        OID_ED25519 => Ok(KeyKind::Ed25519),
+        OID_EC_PUBLIC_KEY => curve_kind(expect_tlv(parameters, DER_OID_TAG)?.0),
+        _ => Err(Unclassified::UnknownAlgorithm),
+    }
+}
+
+/// Like `split_tlv`, but only succeeds if the tag is `expected`.
+fn expect_tlv(input: &[u8], expected: u8) -> Result<(&[u8], &[u8]), Unclassified> {
+    match split_tlv(input) {
+        Some((tag, contents, rest)) if tag == expected => Ok((contents, rest)),
+        _ => Err(Unclassified::BadEncoding),
+    }
+}
+
+fn curve_kind(curve: &[u8]) -> Result<KeyKind, Unclassified> {
+    match curve {
+        OID_SECP256R1 => Ok(KeyKind::EcdsaP256),
+        OID_SECP384R1 => Ok(KeyKind::EcdsaP384),
+        OID_SECP521R1 => Ok(KeyKind::EcdsaP521),
+        _ => Err(Unclassified::UnknownCurve),
+    }
+}
+
+const EC_PARAMETERS_TAG: u8 = 0xa0;
+
+/// 1.2.840.113549.1.1.1
+const OID_RSA_ENCRYPTION: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01];
+/// 1.2.840.10045.2.1
+const OID_EC_PUBLIC_KEY: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+/// 1.3.101.112
+const OID_ED25519: &[u8] = &[0x2b, 0x65, 0x70];
+/// 1.2.840.10045.3.1.7
+const OID_SECP256R1: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+/// 1.3.132.0.34
+const OID_SECP384R1: &[u8] = &[0x2b, 0x81, 0x04, 0x00, 0x22];
+/// 1.3.132.0.35
+const OID_SECP521R1: &[u8] = &[0x2b, 0x81, 0x04, 0x00, 0x23];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pki_types::{PrivatePkcs1KeyDer, PrivatePkcs8KeyDer, PrivateSec1KeyDer};
+
+    fn pkcs8(der: &'static [u8]) -> PrivateKeyDer<'static> {
+        PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(der))
+    }
+
+    fn sec1(der: &'static [u8]) -> PrivateKeyDer<'static> {
+        PrivateKeyDer::Sec1(PrivateSec1KeyDer::from(der))
+    }
+
+    #[test]
+    fn classifies_test_keys() {
+        for (key, kind) in [
+            (
+                pkcs8(include_bytes!("../testdata/rsa2048key.pkcs8.der")),
+                KeyKind::Rsa,
+            ),
+            (
+                pkcs8(include_bytes!("../testdata/rsa4096key.pkcs8.der")),
+                KeyKind::Rsa,
+            ),
+            (
+                PrivateKeyDer::Pkcs1(PrivatePkcs1KeyDer::from(
+                    &include_bytes!("../testdata/rsa2048key.pkcs1.der")[..],
+                )),
+                KeyKind::Rsa,
+            ),
+            (
+                pkcs8(include_bytes!("../testdata/nistp256key.pkcs8.der")),
+                KeyKind::EcdsaP256,
+            ),
+            (
+                sec1(include_bytes!("../testdata/nistp256key.der")),
+                KeyKind::EcdsaP256,
+            ),
+            (
+                pkcs8(include_bytes!("../testdata/nistp384key.pkcs8.der")),
+                KeyKind::EcdsaP384,
+            ),
+            (
+                sec1(include_bytes!("../testdata/nistp384key.der")),
+                KeyKind::EcdsaP384,
+            ),
+            (
+                pkcs8(include_bytes!("../testdata/nistp521key.pkcs8.der")),
+                KeyKind::EcdsaP521,
+            ),
+            (
+                sec1(include_bytes!("../testdata/nistp521key.der")),
+                KeyKind::EcdsaP521,
+            ),
+            (
+                pkcs8(include_bytes!("../testdata/eddsakey.der")),
+                KeyKind::Ed25519,
+            ),
+        ] {
+            assert_eq!(classify(&key), kind);
+        }
+    }
+
+    struct FakeTokenKey;
+
+    impl SigningKey for FakeTokenKey {
+        fn choose_scheme(&self, offered: &[SignatureScheme]) -> Option<Box<dyn Signer>> {
+            match offered.contains(&SignatureScheme::ED25519) {
+                true => Some(Box::new(Self)),
+                false => None,
+            }
+        }
+
+        fn algorithm(&self) -> SignatureAlgorithm {
+            SignatureAlgorithm::ED25519
+        }
+    }
+
+    impl Signer for FakeTokenKey {
+        fn sign(&self, message: &[u8]) -> Result<Vec<u8>, Error> {
+            Ok(message.iter().rev().copied().collect())
+        }
+
+        fn scheme(&self) -> SignatureScheme {
+            SignatureScheme::ED25519
+        }
+    }
+
+    struct FakePkcs11;
+
+    impl KeyUriHandler for FakePkcs11 {
+        fn load_key(&self, uri: &str) -> Result<Arc<dyn SigningKey>, Error> {
+            match uri {
+                "pkcs11:token=fake;object=server-key" | "PKCS11:token=fake;object=server-key" => {
+                    Ok(Arc::new(FakeTokenKey))
+                }
+                _ => Err(Error::General("no such object".into())),
+            }
+        }
+    }
+
+    #[test]
+    fn key_uri_handlers() {
+        let mut handlers = KeyUriHandlers::new();
+        assert!(handlers
+            .load_key_uri("pkcs11:token=fake;object=server-key")
+            .is_err());
+
+        handlers.register_key_uri_handler("PKCS11", Arc::new(FakePkcs11));
+        assert_eq!(
+            format!("{:?}", handlers),
+            "KeyUriHandlers { schemes: [\"pkcs11\"] }"
+        );
+
+        let key = handlers
+            .load_key_uri("pkcs11:token=fake;object=server-key")
+            .unwrap();
+        assert_eq!(key.algorithm(), SignatureAlgorithm::ED25519);
+        let signer = key
+            .choose_scheme(&[SignatureScheme::ED25519])
+            .unwrap();
+        assert_eq!(signer.sign(b"abc").unwrap(), b"cba");
+
+        assert!(handlers
+            .load_key_uri("PKCS11:token=fake;object=server-key")
+            .is_ok());
+        assert!(handlers
+            .load_key_uri("pkcs11:token=fake;object=missing")
+            .is_err());
+        assert!(handlers
+            .load_key_uri("file:server.key")
+            .is_err());
+        assert!(handlers
+            .load_key_uri("no-scheme")
+            .is_err());
+    }
+
+    #[test]
+    fn unclassifiable_keys() {
+        assert_eq!(classify(&pkcs8(&[])), KeyKind::Unknown);
+        assert_eq!(classify(&sec1(&[0x30, 0x00])), KeyKind::Unknown);
+        // a certificate is not a key
+        assert_eq!(
+            classify(&pkcs8(include_bytes!("../testdata/cert-github.0.der"))),
+            KeyKind::Unknown
+        );
+        // a P-256 key wrongly labelled as SEC1
+        assert_eq!(
+            classify(&sec1(include_bytes!("../testdata/nistp256key.pkcs8.der"))),
+            KeyKind::Unknown
+        );
+    }
+}
@@ -531,6 +531,8 @@ impl SignatureScheme {
             Self::ECDSA_NISTP256_SHA256
             | Self::ECDSA_NISTP384_SHA384
             | Self::ECDSA_NISTP521_SHA512 => SignatureAlgorithm::ECDSA,
+            Self::ED25519 => SignatureAlgorithm::ED25519,
+            Self::ED448 => SignatureAlgorithm::ED448,
             _ => SignatureAlgorithm::Unknown(0),
         }
     }
@@ -553,6 +555,24 @@ impl SignatureScheme {
                 | Self::ED25519
         )
     }
+
+    /// Whether a particular `SignatureScheme` is approved by FIPS 186-4.
+    ///
+    /// This excludes schemes using SHA-1, as well as EdDSA.
+    pub(crate) fn fips(&self) -> bool {
+        matches!(
+            *self,
+            Self::ECDSA_NISTP256_SHA256
+                | Self::ECDSA_NISTP384_SHA384
+                | Self::ECDSA_NISTP521_SHA512
+                | Self::RSA_PKCS1_SHA256
+                | Self::RSA_PKCS1_SHA384
+                | Self::RSA_PKCS1_SHA512
+                | Self::RSA_PSS_SHA256
+                | Self::RSA_PSS_SHA384
+                | Self::RSA_PSS_SHA512
+        )
+    }
 }
 
 enum_builder! {
@@ -1,4 +1,5 @@
 use crate::enums::{AlertDescription, ContentType, HandshakeType};
+use crate::msgs::enums::ExtensionType;
 use crate::msgs::handshake::KeyExchangeAlgorithm;
 use crate::rand;
 
@@ -60,6 +61,13 @@ pub enum Error {
     /// We received a fatal alert.  This means the peer is unhappy.
     AlertReceived(AlertDescription),
 
+    /// The application aborted the connection by sending this fatal alert.
+    ///
+    /// See [`ConnectionCommon::send_fatal_alert`].
+    ///
+    /// [`ConnectionCommon::send_fatal_alert`]: crate::ConnectionCommon::send_fatal_alert
+    AlertSent(AlertDescription),
+
     /// We saw an invalid certificate.
     ///
     /// The contained error is from the certificate validation trait
@@ -91,11 +99,39 @@ pub enum Error {
     /// The `max_fragment_size` value supplied in configuration was too small,
     /// or too large.
     BadMaxFragmentSize,
+
+    /// The connection has transferred more than the configured
+    /// `max_bytes_transferred`, and should be torn down.
+    BytesTransferredLimitExceeded,
+
+    /// The connection is older than the configured `max_connection_age`,
+    /// and should be torn down.
+    ConnectionAgeLimitExceeded,
+
+    /// The write key reached the confidentiality limit of its AEAD algorithm,
+    /// and [`AeadLimitPolicy::Fail`] was configured.
+    ///
+    /// [`AeadLimitPolicy::Fail`]: crate::AeadLimitPolicy::Fail
+    AeadLimitReached,
+
+    /// A [`Signer`] failed in a way that might not recur, such as a remote
+    /// signing service timing out.
+    ///
+    /// Only these failures are retried by a [`SigningRetryPolicy`].
+    ///
+    /// [`Signer`]: crate::sign::Signer
+    /// [`SigningRetryPolicy`]: crate::sign::SigningRetryPolicy
+    TransientSigningFailure(String),
+
+    /// A PEM file was malformed, or could not be read.
+    ///
+    /// The string describes the problem.  This is returned by `pem::parse_combined_pem`.
+    InvalidPem(String),
 }
 
 /// A corrupt TLS message payload that resulted in an error.
 #[non_exhaustive]
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 
 pub enum InvalidMessage {
     /// An advertised message was larger then expected.
@@ -114,6 +150,17 @@ pub enum InvalidMessage {
     InvalidEmptyPayload,
     /// A peer sent an unexpected key update request.
     InvalidKeyUpdate,
+    /// A handshake extension could not be decoded.
+    InvalidExtension {
+        /// The codepoint of the offending extension.
+        typ: ExtensionType,
+        /// How far into the extension body decoding got before failing.
+        offset: usize,
+        /// Which kind of extension list was being decoded.
+        context: &'static str,
+        /// The error encountered while decoding the extension body.
+        error: Box<Self>,
+    },
     /// A peer's server name could not be decoded
     InvalidServerName,
     /// A TLS message payload was larger then allowed by the specification.
@@ -205,6 +252,7 @@ pub enum PeerMisbehaved {
     ResumptionOfferedWithVariedCipherSuite,
     ResumptionOfferedWithVariedEms,
     ResumptionOfferedWithIncompatibleCipherSuite,
+    ReusedKeyShare,
     SelectedDifferentCipherSuiteAfterRetry,
     SelectedInvalidPsk,
     SelectedTls12UsingTls13VersionExtension,
@@ -213,12 +261,14 @@ pub enum PeerMisbehaved {
     SelectedUnofferedCompression,
     SelectedUnofferedKxGroup,
     SelectedUnofferedPsk,
+    SelectedUnofferedServerCertificateType,
     SelectedUnusableCipherSuiteForVersion,
     ServerHelloMustOfferUncompressedEcPoints,
     ServerNameDifferedOnRetry,
     ServerNameMustContainOneHostName,
     SignedKxWithWrongAlgorithm,
     SignedHandshakeWithUnadvertisedSigScheme,
+    TooManyClientHelloEntries,
     TooMuchEarlyDataReceived,
     UnexpectedCleartextExtension,
     UnsolicitedCertExtension,
@@ -245,16 +295,20 @@ impl From<PeerMisbehaved> for Error {
 /// versions.
 pub enum PeerIncompatible {
     EcPointsExtensionRequired,
+    HandshakeTooLargeForClientAuth,
     KeyShareExtensionRequired,
     NamedGroupsExtensionRequired,
     NoCertificateRequestSignatureSchemesInCommon,
     NoCipherSuitesInCommon,
     NoEcPointFormatsInCommon,
     NoKxGroupsInCommon,
+    NoServerCertificateTypesInCommon,
     NoSignatureSchemesInCommon,
     NullCompressionRequired,
+    ServerCertificateTypeExtensionRequired,
     ServerDoesNotSupportTls12Or13,
     ServerSentHelloRetryRequestWithUnknownExtension,
+    ServerSelectedWeakFfdheGroup,
     ServerTlsVersionIsDisabledByOurConfig,
     SignatureAlgorithmsExtensionRequired,
     SupportedVersionsExtensionRequired,
@@ -304,6 +358,10 @@ pub enum CertificateError {
     /// issuer.
     BadSignature,
 
+    /// A certificate is signed using a SHA-1 based signature algorithm,
+    /// which the verifier was configured to reject.
+    Sha1Signature,
+
     /// The subject names in an end-entity certificate do not include
     /// the expected name.
     NotValidForName,
@@ -311,6 +369,24 @@ pub enum CertificateError {
     /// The certificate is being used for a different purpose than allowed.
     InvalidPurpose,
 
+    /// The end-entity certificate does not assert any of the certificate
+    /// policies the verifier was configured to require.
+    RequiredPolicyNotAsserted,
+
+    /// A certificate has a serial number that is not a positive integer
+    /// of at most 20 octets, and the verifier was not configured to allow
+    /// such certificates.
+    NonConformingSerialNumber,
+
+    /// The stapled OCSP response could not be parsed, does not say the
+    /// certificate is good, or is not signed by the certificate's issuer
+    /// or a responder it authorised.
+    InvalidOcspResponse,
+
+    /// The current time is outside the validity period of the stapled
+    /// OCSP response.
+    StaleOcspResponse,
+
     /// The certificate is valid, but the handshake is rejected for other
     /// reasons.
     ApplicationVerificationFailure,
@@ -340,8 +416,13 @@ impl PartialEq<Self> for CertificateError {
             (UnhandledCriticalExtension, UnhandledCriticalExtension) => true,
             (UnknownIssuer, UnknownIssuer) => true,
             (BadSignature, BadSignature) => true,
+            (Sha1Signature, Sha1Signature) => true,
             (NotValidForName, NotValidForName) => true,
             (InvalidPurpose, InvalidPurpose) => true,
+            (RequiredPolicyNotAsserted, RequiredPolicyNotAsserted) => true,
+            (NonConformingSerialNumber, NonConformingSerialNumber) => true,
+            (InvalidOcspResponse, InvalidOcspResponse) => true,
+            (StaleOcspResponse, StaleOcspResponse) => true,
             (ApplicationVerificationFailure, ApplicationVerificationFailure) => true,
             _ => false,
         }
@@ -363,7 +444,9 @@ impl From<CertificateError> for AlertDescription {
             Revoked => Self::CertificateRevoked,
             UnknownIssuer => Self::UnknownCA,
             BadSignature => Self::DecryptError,
-            InvalidPurpose => Self::UnsupportedCertificate,
+            Sha1Signature | NonConformingSerialNumber => Self::BadCertificate,
+            InvalidPurpose | RequiredPolicyNotAsserted => Self::UnsupportedCertificate,
+            InvalidOcspResponse | StaleOcspResponse => Self::BadCertificateStatusResponse,
             ApplicationVerificationFailure => Self::AccessDenied,
             // RFC 5246/RFC 8446
             // certificate_unknown
@@ -487,6 +570,7 @@ impl fmt::Display for Error {
             Self::PeerIncompatible(ref why) => write!(f, "peer is incompatible: {:?}", why),
             Self::PeerMisbehaved(ref why) => write!(f, "peer misbehaved: {:?}", why),
             Self::AlertReceived(ref alert) => write!(f, "received fatal alert: {:?}", alert),
+            Self::AlertSent(ref alert) => write!(f, "sent fatal alert: {:?}", alert),
             Self::InvalidCertificate(ref err) => {
                 write!(f, "invalid peer certificate: {:?}", err)
             }
@@ -505,6 +589,19 @@ impl fmt::Display for Error {
             Self::BadMaxFragmentSize => {
                 write!(f, "the supplied max_fragment_size was too small or large")
             }
+            Self::BytesTransferredLimitExceeded => {
+                write!(f, "connection exceeded its maximum bytes transferred")
+            }
+            Self::ConnectionAgeLimitExceeded => {
+                write!(f, "connection exceeded its maximum age")
+            }
+            Self::AeadLimitReached => {
+                write!(f, "write key reached its AEAD confidentiality limit")
+            }
+            Self::TransientSigningFailure(ref err) => {
+                write!(f, "transient signing failure: {}", err)
+            }
+            Self::InvalidPem(ref err) => write!(f, "invalid PEM: {}", err),
             Self::General(ref err) => write!(f, "unexpected error: {}", err),
         }
     }
@@ -540,8 +637,13 @@ mod tests {
         assert_eq!(UnhandledCriticalExtension, UnhandledCriticalExtension);
         assert_eq!(UnknownIssuer, UnknownIssuer);
         assert_eq!(BadSignature, BadSignature);
+        assert_eq!(Sha1Signature, Sha1Signature);
         assert_eq!(NotValidForName, NotValidForName);
         assert_eq!(InvalidPurpose, InvalidPurpose);
+        assert_eq!(RequiredPolicyNotAsserted, RequiredPolicyNotAsserted);
+        assert_eq!(NonConformingSerialNumber, NonConformingSerialNumber);
+        assert_eq!(InvalidOcspResponse, InvalidOcspResponse);
+        assert_eq!(StaleOcspResponse, StaleOcspResponse);
         assert_eq!(
             ApplicationVerificationFailure,
             ApplicationVerificationFailure
@@ -591,6 +693,7 @@ mod tests {
             super::PeerIncompatible::Tls12NotOffered.into(),
             super::PeerMisbehaved::UnsolicitedCertExtension.into(),
             Error::AlertReceived(AlertDescription::ExportRestriction),
+            Error::AlertSent(AlertDescription::AccessDenied),
             super::CertificateError::Expired.into(),
             Error::General("undocumented error".to_string()),
             Error::FailedToGetCurrentTime,
@@ -599,6 +702,11 @@ mod tests {
             Error::PeerSentOversizedRecord,
             Error::NoApplicationProtocol,
             Error::BadMaxFragmentSize,
+            Error::BytesTransferredLimitExceeded,
+            Error::ConnectionAgeLimitExceeded,
+            Error::AeadLimitReached,
+            Error::TransientSigningFailure("timed out".to_string()),
+            Error::InvalidPem("section end missing".to_string()),
             Error::InvalidCertRevocationList(CertRevocationListError::BadSignature),
         ];
 
@@ -1,10 +1,18 @@
 use crate::crypto::hash;
+#[cfg(feature = "logging")]
+use crate::log::debug;
 use crate::msgs::codec::Codec;
 use crate::msgs::enums::HashAlgorithm;
 use crate::msgs::handshake::HandshakeMessagePayload;
 use crate::msgs::message::{Message, MessagePayload};
 use core::mem;
 
+/// Upper bound on the transcript we keep for TLS1.2 client authentication.
+///
+/// This allows for several maximum-size handshake messages.  If a handshake
+/// grows beyond this, the buffer is discarded and client authentication fails.
+const MAX_CLIENT_AUTH_BUFFER: usize = 0x4_0000;
+
 /// Early stage buffering of handshake payloads.
 ///
 /// Before we know the hash algorithm to use to verify the handshake, we just buffer the messages.
@@ -62,6 +70,8 @@ impl HandshakeHashBuffer {
         HandshakeHash {
             provider,
             ctx,
+            #[cfg(feature = "dangerous_transcript")]
+            raw: self.buffer.clone(),
             client_auth: match self.client_auth_enabled {
                 true => Some(self.buffer),
                 false => None,
@@ -75,14 +85,20 @@ impl HandshakeHashBuffer {
 /// we know what hash function we need to use we switch to
 /// incremental hashing.
 ///
-/// For client auth, we also need to buffer all the messages.
-/// This is disabled in cases where client auth is not possible.
+/// For client auth in TLS1.2, we also need to buffer all the messages.
+/// This is disabled in cases where client auth is not possible, and
+/// for TLS1.3 (which signs the transcript hash instead).  The buffer
+/// is limited to `MAX_CLIENT_AUTH_BUFFER` bytes.
 pub(crate) struct HandshakeHash {
     provider: &'static dyn hash::Hash,
     ctx: Box<dyn hash::Context>,
 
     /// buffer for client-auth.
     client_auth: Option<Vec<u8>>,
+
+    /// Everything hashed so far, for `CommonState::transcript_up_to`.
+    #[cfg(feature = "dangerous_transcript")]
+    raw: Vec<u8>,
 }
 
 impl HandshakeHash {
@@ -103,9 +119,16 @@ impl HandshakeHash {
     /// Hash or buffer a byte slice.
     fn update_raw(&mut self, buf: &[u8]) -> &mut Self {
         self.ctx.update(buf);
+        #[cfg(feature = "dangerous_transcript")]
+        self.raw.extend_from_slice(buf);
 
         if let Some(buffer) = &mut self.client_auth {
-            buffer.extend_from_slice(buf);
+            if buffer.len() + buf.len() > MAX_CLIENT_AUTH_BUFFER {
+                debug!("handshake too large to keep for client auth; discarding");
+                self.client_auth = None;
+            } else {
+                buffer.extend_from_slice(buf);
+            }
         }
 
         self
@@ -141,6 +164,8 @@ impl HandshakeHash {
         let old_handshake_hash_msg =
             HandshakeMessagePayload::build_handshake_hash(old_hash.as_ref());
 
+        #[cfg(feature = "dangerous_transcript")]
+        self.raw.clear();
         self.update_raw(&old_handshake_hash_msg.get_encoding());
     }
 
@@ -157,6 +182,12 @@ impl HandshakeHash {
         self.client_auth.take()
     }
 
+    /// Every byte hashed so far.
+    #[cfg(feature = "dangerous_transcript")]
+    pub(crate) fn raw_transcript(&self) -> &[u8] {
+        &self.raw
+    }
+
     /// The hashing algorithm
     pub(crate) fn algorithm(&self) -> HashAlgorithm {
         self.provider.algorithm()
@@ -215,6 +246,52 @@ mod tests {
         assert_eq!(Some(b"helloworld".to_vec()), buf);
     }
 
+    #[cfg(feature = "tls12")]
+    #[test]
+    fn client_auth_buffer_is_capped() {
+        let mut hhb = HandshakeHashBuffer::new();
+        hhb.set_client_auth_enabled();
+        let mut hh = hhb.start_hash(&ring::hash::SHA256);
+        let message = [0u8; 0xffff];
+        for _ in 0..(super::MAX_CLIENT_AUTH_BUFFER / message.len()) {
+            hh.update_raw(&message);
+        }
+        assert!(hh.client_auth.is_some());
+
+        hh.update_raw(&message);
+        assert_eq!(hh.client_auth, None);
+        hh.update_raw(&message);
+        assert_eq!(hh.client_auth, None);
+        assert_eq!(hh.take_handshake_buf(), None);
+    }
+
+    #[cfg(feature = "dangerous_transcript")]
+    #[test]
+    fn raw_transcript_matches_hash() {
+        use crate::crypto::hash::Hash;
+
+        let mut hhb = HandshakeHashBuffer::new();
+        hhb.update_raw(b"hello");
+        let mut hh = hhb.start_hash(&ring::hash::SHA256);
+        hh.update_raw(b"world");
+        assert_eq!(hh.raw_transcript(), b"helloworld");
+        assert_eq!(
+            ring::hash::SHA256
+                .hash(hh.raw_transcript())
+                .as_ref(),
+            hh.get_current_hash().as_ref()
+        );
+
+        hh.rollup_for_hrr();
+        assert_ne!(hh.raw_transcript(), b"helloworld");
+        assert_eq!(
+            ring::hash::SHA256
+                .hash(hh.raw_transcript())
+                .as_ref(),
+            hh.get_current_hash().as_ref()
+        );
+    }
+
     #[test]
     fn abandon() {
         let mut hhb = HandshakeHashBuffer::new();
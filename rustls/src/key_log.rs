@@ -9,7 +9,9 @@
 /// implementation to make this useful.
 ///
 /// See [`KeyLogFile`](crate::KeyLogFile) that implements the standard
-/// `SSLKEYLOGFILE` environment variable behaviour.
+/// `SSLKEYLOGFILE` environment variable behaviour, and
+/// [`WriteKeyLog`](crate::WriteKeyLog) that writes the same format to
+/// any `Write` sink.
 pub trait KeyLog: Send + Sync {
     /// Log the given `secret`.  `client_random` is provided for
     /// session identification.  `label` describes precisely what
@@ -54,20 +54,30 @@ impl KeyLogFileInner {
             Some(ref f) => f,
         };
 
-        self.buf.truncate(0);
-        write!(self.buf, "{} ", label)?;
-        for b in client_random.iter() {
-            write!(self.buf, "{:02x}", b)?;
-        }
-        write!(self.buf, " ")?;
-        for b in secret.iter() {
-            write!(self.buf, "{:02x}", b)?;
-        }
-        writeln!(self.buf)?;
+        encode_line(&mut self.buf, label, client_random, secret)?;
         file.write_all(&self.buf)
     }
 }
 
+/// Encode one line of the NSS key log format into `buf`, replacing its contents.
+fn encode_line(
+    buf: &mut Vec<u8>,
+    label: &str,
+    client_random: &[u8],
+    secret: &[u8],
+) -> io::Result<()> {
+    buf.truncate(0);
+    write!(buf, "{} ", label)?;
+    for b in client_random.iter() {
+        write!(buf, "{:02x}", b)?;
+    }
+    write!(buf, " ")?;
+    for b in secret.iter() {
+        write!(buf, "{:02x}", b)?;
+    }
+    writeln!(buf)
+}
+
 /// [`KeyLog`] implementation that opens a file whose name is
 /// given by the `SSLKEYLOGFILE` environment variable, and writes
 /// keys into it.
@@ -104,6 +114,63 @@ impl KeyLog for KeyLogFile {
     }
 }
 
+// Internal mutable state for WriteKeyLog
+struct WriteKeyLogInner<W> {
+    writer: W,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> WriteKeyLogInner<W> {
+    fn try_write(&mut self, label: &str, client_random: &[u8], secret: &[u8]) -> io::Result<()> {
+        encode_line(&mut self.buf, label, client_random, secret)?;
+        self.writer.write_all(&self.buf)?;
+        self.writer.flush()
+    }
+}
+
+/// [`KeyLog`] implementation that writes keys to any [`Write`] sink,
+/// in the same format as [`KeyLogFile`].
+///
+/// This is useful where the process cannot open files: keys can
+/// instead be captured into an in-memory buffer, or sent down a socket.
+/// Each line is written (and the writer flushed) while holding a lock,
+/// so lines from concurrent connections do not interleave.
+///
+/// If the writer fails, this does nothing but logs errors at warning-level.
+pub struct WriteKeyLog<W>(Mutex<WriteKeyLogInner<W>>);
+
+impl<W: Write + Send> WriteKeyLog<W> {
+    /// Makes a new `WriteKeyLog` which writes keys to `writer`.
+    pub fn new(writer: W) -> Self {
+        Self(Mutex::new(WriteKeyLogInner {
+            writer,
+            buf: Vec::new(),
+        }))
+    }
+
+    /// Consumes this `WriteKeyLog`, returning the writer.
+    pub fn into_inner(self) -> W {
+        self.0.into_inner().unwrap().writer
+    }
+}
+
+impl<W: Write + Send> KeyLog for WriteKeyLog<W> {
+    fn log(&self, label: &str, client_random: &[u8], secret: &[u8]) {
+        #[cfg_attr(not(feature = "logging"), allow(unused_variables))]
+        match self
+            .0
+            .lock()
+            .unwrap()
+            .try_write(label, client_random, secret)
+        {
+            Ok(()) => {}
+            Err(e) => {
+                warn!("error writing to key log: {}", e);
+            }
+        }
+    }
+}
+
 #[cfg(all(test, target_os = "linux"))]
 mod tests {
     use super::*;
@@ -151,4 +218,16 @@ mod tests {
             .try_write("label", b"random", b"secret")
             .is_err());
     }
+
+    #[test]
+    fn test_write_key_log() {
+        init();
+        let key_log = WriteKeyLog::new(Vec::new());
+        key_log.log("label", b"\x01\x02", b"\xab\xcd\xef");
+        key_log.log("other", b"", b"\x00");
+        assert_eq!(
+            key_log.into_inner(),
+            b"label 0102 abcdef\nother  00\n".to_vec()
+        );
+    }
 }
@@ -0,0 +1,145 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+use std::sync::Mutex;
+
+use crate::common_state::CommonState;
+use crate::enums::AlertDescription;
+use crate::error::{Error, PeerMisbehaved};
+use crate::limited_cache::LimitedCache;
+#[cfg(feature = "logging")]
+use crate::log::warn;
+use crate::msgs::enums::NamedGroup;
+
+/// What a [`KeyShareReuseDetector`] does when a peer repeats a key share.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyShareReusePolicy {
+    /// Log a warning, and carry on with the handshake.
+    Log,
+    /// Log a warning, and fail the handshake with
+    /// [`PeerMisbehaved::ReusedKeyShare`].
+    Reject,
+}
+
+/// Remembers the ephemeral key shares recently sent by peers, and spots
+/// when one is used again.
+///
+/// A peer that reuses a key share across handshakes has broken (or no)
+/// forward secrecy: every connection with that share is exposed if its
+/// private key ever is.
+///
+/// This is shared between connections by putting it in the
+/// `key_share_reuse_detector` field of a [`crate::ClientConfig`] or
+/// [`crate::ServerConfig`].  It keeps a bounded number of shares, evicting
+/// the oldest first, so only reuse within that window is detected.
+pub struct KeyShareReuseDetector {
+    policy: KeyShareReusePolicy,
+    state: Mutex<SeenShares>,
+}
+
+struct SeenShares {
+    // keyed by group code point and encoded share
+    cache: LimitedCache<(u16, Vec<u8>), ()>,
+    reuses: u64,
+}
+
+impl KeyShareReuseDetector {
+    /// Make a new detector with the given `policy`.
+    ///
+    /// `size` is the maximum number of key shares remembered, and may be
+    /// rounded-up for efficiency.
+    pub fn new(policy: KeyShareReusePolicy, size: usize) -> Arc<Self> {
+        Arc::new(Self {
+            policy,
+            state: Mutex::new(SeenShares {
+                cache: LimitedCache::new(size),
+                reuses: 0,
+            }),
+        })
+    }
+
+    /// How many reused key shares have been seen so far.
+    pub fn reuses_detected(&self) -> u64 {
+        self.state.lock().unwrap().reuses
+    }
+
+    /// Record the peer's key share for `group`, applying the policy if it
+    /// was seen before.
+    pub(crate) fn check(
+        &self,
+        common: &mut CommonState,
+        group: NamedGroup,
+        share: &[u8],
+    ) -> Result<(), Error> {
+        let key = (group.get_u16(), share.to_vec());
+        {
+            let mut state = self.state.lock().unwrap();
+            if state.cache.get(&key).is_none() {
+                state.cache.insert(key, ());
+                return Ok(());
+            }
+            state.reuses += 1;
+        }
+
+        warn!("peer reused a {:?} key share", group);
+        match self.policy {
+            KeyShareReusePolicy::Log => Ok(()),
+            KeyShareReusePolicy::Reject => Err(common.send_fatal_alert(
+                AlertDescription::IllegalParameter,
+                PeerMisbehaved::ReusedKeyShare,
+            )),
+        }
+    }
+}
+
+impl fmt::Debug for KeyShareReuseDetector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyShareReuseDetector")
+            .field("policy", &self.policy)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{KeyShareReuseDetector, KeyShareReusePolicy};
+    use crate::common_state::{CommonState, Side};
+    use crate::msgs::enums::NamedGroup;
+
+    #[test]
+    fn flags_reuse_within_group() {
+        let detector = KeyShareReuseDetector::new(KeyShareReusePolicy::Reject, 16);
+        let mut common = CommonState::new(Side::Server);
+
+        detector
+            .check(&mut common, NamedGroup::X25519, b"share")
+            .unwrap();
+        detector
+            .check(&mut common, NamedGroup::secp256r1, b"share")
+            .unwrap();
+        assert_eq!(detector.reuses_detected(), 0);
+
+        assert!(detector
+            .check(&mut common, NamedGroup::X25519, b"share")
+            .is_err());
+        assert_eq!(detector.reuses_detected(), 1);
+    }
+
+    #[test]
+    fn forgets_oldest_shares() {
+        let detector = KeyShareReuseDetector::new(KeyShareReusePolicy::Reject, 4);
+        let mut common = CommonState::new(Side::Server);
+
+        for i in 0..64u8 {
+            detector
+                .check(&mut common, NamedGroup::X25519, &[i])
+                .unwrap();
+        }
+
+        // the first share was evicted long ago
+        detector
+            .check(&mut common, NamedGroup::X25519, &[0])
+            .unwrap();
+        assert_eq!(detector.reuses_detected(), 0);
+    }
+}
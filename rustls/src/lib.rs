@@ -254,6 +254,15 @@
 //!   which is used for cryptography.
 //!   Without this feature, these items must be provided externally to the core
 //!   rustls crate.
+//!
+//! - `pem`: this makes the rustls crate depend on the `rustls-pemfile` crate,
+//!   and adds the `pem` module for loading certificates and keys from PEM files.
+//!
+//! - `dangerous_transcript`: keeps a copy of the raw handshake transcript in
+//!   every connection, and adds `CommonState::transcript_up_to` to retrieve it.
+//!   It also adds `CommonState::peer_finished_verify_data`.
+//!   This costs memory, and exposes protocol internals that are easily misused;
+//!   you will only need it for experimental bindings to the handshake.
 
 // Require docs for public APIs, deny unsafe code, etc.
 #![forbid(unsafe_code, unused_must_use)]
@@ -349,6 +358,7 @@ mod builder;
 mod enums;
 mod key_log;
 mod key_log_file;
+mod key_share_reuse;
 mod suites;
 mod ticketer;
 mod versions;
@@ -375,7 +385,9 @@ pub mod internal {
 pub use crate::builder::{
     ConfigBuilder, ConfigSide, WantsCipherSuites, WantsKxGroups, WantsVerifier, WantsVersions,
 };
-pub use crate::common_state::{CommonState, IoState, Side};
+#[cfg(feature = "dangerous_transcript")]
+pub use crate::common_state::TranscriptPoint;
+pub use crate::common_state::{CommonState, IoState, PrematureDataPolicy, Side};
 pub use crate::conn::{Connection, ConnectionCommon, Reader, SideData, Writer};
 pub use crate::enums::{
     AlertDescription, CipherSuite, ContentType, HandshakeType, ProtocolVersion, SignatureAlgorithm,
@@ -386,9 +398,12 @@ pub use crate::error::{
     PeerMisbehaved,
 };
 pub use crate::key_log::{KeyLog, NoKeyLog};
-pub use crate::key_log_file::KeyLogFile;
-pub use crate::msgs::enums::NamedGroup;
-pub use crate::msgs::handshake::DistinguishedName;
+pub use crate::key_log_file::{KeyLogFile, WriteKeyLog};
+pub use crate::key_share_reuse::{KeyShareReuseDetector, KeyShareReusePolicy};
+pub use crate::msgs::alert::Alert;
+pub use crate::msgs::enums::{AlertLevel, NamedGroup};
+pub use crate::msgs::handshake::{DistinguishedName, TrustedAuthority};
+pub use crate::record_layer::{AeadLimitCallback, AeadLimitPolicy, RecordPaddingPolicy};
 pub use crate::stream::{Stream, StreamOwned};
 pub use crate::suites::{ConnectionTrafficSecrets, ExtractedSecrets, SupportedCipherSuite};
 pub use crate::ticketer::TicketSwitcher;
@@ -397,7 +412,10 @@ pub use crate::tls12::Tls12CipherSuite;
 pub use crate::tls13::Tls13CipherSuite;
 pub use crate::verify::DigitallySignedStruct;
 pub use crate::versions::{SupportedProtocolVersion, ALL_VERSIONS, DEFAULT_VERSIONS};
-pub use crate::webpki::{RootCertStore, WebPkiSupportedAlgorithms};
+pub use crate::webpki::{
+    verify_tls13_certificate_verify, verify_tls13_signature_with_raw_key, NameConstraintVerifier,
+    NameSubtrees, RootCertStore, WebPkiSupportedAlgorithms,
+};
 
 /// Items for use in a client.
 pub mod client {
@@ -414,14 +432,16 @@ pub mod client {
     pub use builder::WantsClientCert;
     pub use client_conn::{
         ClientConfig, ClientConnection, ClientConnectionData, ClientSessionStore,
-        ResolvesClientCert, Resumption, ServerName, Tls12Resumption, WriteEarlyData,
+        HandlesCertificateExtensions, ResolvesClientCert, Resumption, ServerName, ServerProbe,
+        Tls12Resumption, Tls13PskKeyExchangeModes, WriteEarlyData,
     };
-    pub use handy::ClientSessionMemoryCache;
+    pub use handy::{ClientSessionMemoryCache, MultiClientCert};
 
     /// Dangerous configuration that should be audited and used with extreme care.
     pub mod danger {
         pub use super::builder::danger::DangerousClientConfigBuilder;
         pub use super::client_conn::danger::DangerousClientConfig;
+        pub use super::client_conn::NormalizesServerName;
         pub use crate::verify::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
     }
 
@@ -451,12 +471,13 @@ pub mod server {
     pub use crate::webpki::{ClientCertVerifierBuilder, ClientCertVerifierBuilderError};
     pub use builder::WantsServerCert;
     pub use handy::ResolvesServerCertUsingSni;
-    pub use handy::{NoServerSessionStorage, ServerSessionMemoryCache};
-    pub use server_conn::StoresServerSessions;
+    pub use handy::{AntiReplayCache, NoServerSessionStorage, ServerSessionMemoryCache};
+    pub use server_conn::ResolvesClientCertVerifier;
     pub use server_conn::{
         Accepted, Acceptor, ReadEarlyData, ServerConfig, ServerConnection, ServerConnectionData,
     };
-    pub use server_conn::{ClientHello, ProducesTickets, ResolvesServerCert};
+    pub use server_conn::{AntiReplay, StoresServerSessions};
+    pub use server_conn::{CipherSuitePolicy, ClientHello, ProducesTickets, ResolvesServerCert};
 
     /// Dangerous configuration that should be audited and used with extreme care.
     pub mod danger {
@@ -499,14 +520,22 @@ pub mod version {
 pub mod sign {
     #[cfg(feature = "ring")]
     pub use crate::crypto::ring::sign::{
-        any_ecdsa_type, any_eddsa_type, any_supported_type, RsaSigningKey,
+        any_ecdsa_type, any_eddsa_type, any_supported_type, any_supported_type_encrypted,
+        EcdsaSigningKey, Ed25519SigningKey, RsaSigningKey, SignError, SignErrorReason,
+    };
+    pub use crate::crypto::signer::{
+        classify, CallbackSigningKey, CertifiedKey, KeyKind, KeyUriHandler, KeyUriHandlers,
+        SchemeMismatch, Signer, SigningKey, SigningRetryPolicy,
     };
-    pub use crate::crypto::signer::{CertifiedKey, Signer, SigningKey};
 }
 
 #[cfg(feature = "quic")]
 /// APIs for implementing QUIC TLS
 pub mod quic;
 
+#[cfg(feature = "pem")]
+/// Helpers for loading certificates and private keys from PEM files.
+pub mod pem;
+
 /// This is the rustls manual.
 pub mod manual;
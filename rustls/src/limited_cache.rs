@@ -6,6 +6,7 @@ use std::collections::HashMap;
 
 /// A HashMap-alike, which never gets larger than a specified
 /// capacity, and evicts the oldest insertion to maintain this.
+/// An entry can be made to count as the newest again with `touch`.
 ///
 /// The requested capacity may be rounded up by the underlying
 /// collections.  This implementation uses all the allocated
@@ -32,7 +33,15 @@ where
         }
     }
 
-    pub(crate) fn get_or_insert_default_and_edit(&mut self, k: K, edit: impl FnOnce(&mut V)) {
+    /// Edit the value for `k`, inserting a default value first if
+    /// it is not present.
+    ///
+    /// Returns the oldest entry if it was evicted to meet capacity.
+    pub(crate) fn get_or_insert_default_and_edit(
+        &mut self,
+        k: K,
+        edit: impl FnOnce(&mut V),
+    ) -> Option<(K, V)> {
         let inserted_new_item = match self.map.entry(k) {
             Entry::Occupied(value) => {
                 edit(value.into_mut());
@@ -49,9 +58,14 @@ where
         // ensure next insertion does not require a realloc
         if inserted_new_item && self.oldest.capacity() == self.oldest.len() {
             if let Some(oldest_key) = self.oldest.pop_front() {
-                self.map.remove(&oldest_key);
+                return self
+                    .map
+                    .remove(&oldest_key)
+                    .map(|value| (oldest_key, value));
             }
         }
+
+        None
     }
 
     pub(crate) fn insert(&mut self, k: K, v: V) {
@@ -94,6 +108,30 @@ where
         self.map.get_mut(k)
     }
 
+    /// Treat `k`, if present, as the newest entry: it will be evicted last.
+    pub(crate) fn touch<Q: ?Sized>(&mut self, k: &Q)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        if let Some(index) = self
+            .oldest
+            .iter()
+            .position(|item| item.borrow() == k)
+        {
+            if let Some(key) = self.oldest.remove(index) {
+                self.oldest.push_back(key);
+            }
+        }
+    }
+
+    /// Iterate over the entries, oldest insertion first.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&K, &V)> + '_ {
+        self.oldest
+            .iter()
+            .filter_map(|k| self.map.get_key_value(k))
+    }
+
     pub(crate) fn remove<Q: ?Sized>(&mut self, k: &Q) -> Option<V>
     where
         K: Borrow<Q>,
@@ -139,6 +177,20 @@ mod tests {
         assert_eq!(t.get("ghi"), Some(&3));
     }
 
+    #[test]
+    fn test_evicts_oldest_item_after_touch() {
+        let mut t = Test::new(3);
+        t.insert("abc".into(), 1);
+        t.insert("def".into(), 2);
+        t.touch("abc");
+        t.touch("xyz");
+        t.insert("ghi".into(), 3);
+
+        assert_eq!(t.get("abc"), Some(&1));
+        assert_eq!(t.get("def"), None);
+        assert_eq!(t.get("ghi"), Some(&3));
+    }
+
     #[test]
     fn test_evicts_second_oldest_item_if_first_removed() {
         let mut t = Test::new(3);
@@ -243,4 +295,21 @@ mod tests {
 
         assert_eq!(t.get("abc"), Some(&6));
     }
+
+    #[test]
+    fn test_iterates_oldest_first() {
+        let mut t = Test::new(4);
+        t.insert("abc".into(), 1);
+        t.insert("def".into(), 2);
+        t.insert("ghi".into(), 3);
+        t.insert("abc".into(), 4);
+        assert_eq!(t.remove("def"), Some(2));
+
+        assert_eq!(
+            t.iter()
+                .map(|(k, v)| (k.as_str(), *v))
+                .collect::<Vec<_>>(),
+            vec![("abc", 4), ("ghi", 3)]
+        );
+    }
 }
@@ -3,6 +3,15 @@ use crate::error::InvalidMessage;
 use crate::msgs::codec::{Codec, Reader};
 use crate::msgs::enums::AlertLevel;
 
+/// A TLS alert, as received from the peer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Alert {
+    /// The alert's level.
+    pub level: AlertLevel,
+    /// What the alert is about.
+    pub description: AlertDescription,
+}
+
 #[derive(Debug)]
 pub struct AlertMessagePayload {
     pub level: AlertLevel,
@@ -102,6 +102,8 @@ enum_builder! {
         Heartbeat => 0x000f,
         ALProtocolNegotiation => 0x0010,
         SCT => 0x0012,
+        ClientCertificateType => 0x0013,
+        ServerCertificateType => 0x0014,
         Padding => 0x0015,
         ExtendedMasterSecret => 0x0017,
         SessionTicket => 0x0023,
@@ -119,6 +121,7 @@ enum_builder! {
         TransportParameters => 0x0039,
         NextProtocolNegotiation => 0x3374,
         ChannelId => 0x754f,
+        EncryptedClientHello => 0xfe0d,
         RenegotiationInfo => 0xff01,
         TransportParametersDraft => 0xffa5
     }
@@ -231,6 +234,20 @@ enum_builder! {
     }
 }
 
+impl NamedGroup {
+    /// The size of the prime modulus, for the RFC 7919 finite field groups.
+    pub(crate) fn ffdhe_bits(&self) -> Option<u16> {
+        match self {
+            Self::FFDHE2048 => Some(2048),
+            Self::FFDHE3072 => Some(3072),
+            Self::FFDHE4096 => Some(4096),
+            Self::FFDHE6144 => Some(6144),
+            Self::FFDHE8192 => Some(8192),
+            _ => None,
+        }
+    }
+}
+
 enum_builder! {
     /// The `ECCurveType` TLS protocol enum.  Values in this enum are taken
     /// from the various RFCs covering TLS, and are listed by IANA.
@@ -279,6 +296,18 @@ enum_builder! {
     }
 }
 
+enum_builder! {
+    /// The `CertificateType` TLS protocol enum.  Values in this enum are taken
+    /// from the various RFCs covering TLS, and are listed by IANA.
+    /// The `Unknown` item is used when processing unrecognised ordinals.
+    @U8
+    EnumName: CertificateType;
+    EnumVal{
+        X509 => 0x00,
+        RawPublicKey => 0x02
+    }
+}
+
 #[cfg(test)]
 pub(crate) mod tests {
     //! These tests are intended to provide coverage and
@@ -328,6 +357,7 @@ pub(crate) mod tests {
             CertificateStatusType::OCSP,
             CertificateStatusType::OCSP,
         );
+        test_enum8::<CertificateType>(CertificateType::X509, CertificateType::RawPublicKey);
     }
 
     pub(crate) fn test_enum8<T: Codec>(first: T, last: T) {
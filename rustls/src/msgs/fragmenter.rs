@@ -47,6 +47,18 @@ impl MessageFragmenter {
             })
     }
 
+    /// The largest payload that will be put in a single fragment.
+    pub fn max_fragment_len(&self) -> usize {
+        self.max_frag
+    }
+
+    /// A fragmenter that also keeps fragments to at most `max_len` bytes.
+    pub fn limited_to(&self, max_len: usize) -> Self {
+        Self {
+            max_frag: self.max_frag.min(max_len),
+        }
+    }
+
     /// Set the maximum fragment size that will be produced.
     ///
     /// This includes overhead. A `max_fragment_size` of 10 will produce TLS fragments
@@ -159,4 +171,16 @@ mod tests {
             b"\x01\x02\x03\x04\x05\x06\x07\x08",
         );
     }
+
+    #[test]
+    fn limited_to_takes_smaller_limit() {
+        let mut frag = MessageFragmenter::default();
+        frag.set_max_fragment_size(Some(64))
+            .unwrap();
+        assert_eq!(
+            frag.limited_to(100).max_fragment_len(),
+            64 - PACKET_OVERHEAD
+        );
+        assert_eq!(frag.limited_to(10).max_fragment_len(), 10);
+    }
 }
@@ -8,8 +8,8 @@ use crate::log::warn;
 use crate::msgs::base::{Payload, PayloadU16, PayloadU24, PayloadU8};
 use crate::msgs::codec::{self, Codec, LengthPrefixedBuffer, ListLength, Reader, TlsListElement};
 use crate::msgs::enums::{
-    CertificateStatusType, ClientCertificateType, Compression, ECCurveType, ECPointFormat,
-    ExtensionType, KeyUpdateRequest, NamedGroup, PSKKeyExchangeMode, ServerNameType,
+    CertificateStatusType, CertificateType, ClientCertificateType, Compression, ECCurveType,
+    ECPointFormat, ExtensionType, KeyUpdateRequest, NamedGroup, PSKKeyExchangeMode, ServerNameType,
 };
 use crate::verify::DigitallySignedStruct;
 use crate::{rand, x509};
@@ -547,6 +547,9 @@ pub enum ClientExtension {
     Cookie(PayloadU16),
     ExtendedMasterSecretRequest,
     CertificateStatusRequest(CertificateStatusRequest),
+    TrustedCaKeys(Vec<TrustedAuthority>),
+    ServerCertificateTypes(Vec<CertificateType>),
+    ClientCertificateTypes(Vec<CertificateType>),
     TransportParameters(Vec<u8>),
     TransportParametersDraft(Vec<u8>),
     EarlyData,
@@ -569,6 +572,9 @@ impl ClientExtension {
             Self::Cookie(_) => ExtensionType::Cookie,
             Self::ExtendedMasterSecretRequest => ExtensionType::ExtendedMasterSecret,
             Self::CertificateStatusRequest(_) => ExtensionType::StatusRequest,
+            Self::TrustedCaKeys(_) => ExtensionType::TrustedCAKeys,
+            Self::ServerCertificateTypes(_) => ExtensionType::ServerCertificateType,
+            Self::ClientCertificateTypes(_) => ExtensionType::ClientCertificateType,
             Self::TransportParameters(_) => ExtensionType::TransportParameters,
             Self::TransportParametersDraft(_) => ExtensionType::TransportParametersDraft,
             Self::EarlyData => ExtensionType::EarlyData,
@@ -598,6 +604,10 @@ impl Codec for ClientExtension {
             Self::PresharedKey(ref r) => r.encode(nested.buf),
             Self::Cookie(ref r) => r.encode(nested.buf),
             Self::CertificateStatusRequest(ref r) => r.encode(nested.buf),
+            Self::TrustedCaKeys(ref r) => r.encode(nested.buf),
+            Self::ServerCertificateTypes(ref r) | Self::ClientCertificateTypes(ref r) => {
+                r.encode(nested.buf)
+            }
             Self::TransportParameters(ref r) | Self::TransportParametersDraft(ref r) => {
                 nested.buf.extend_from_slice(r);
             }
@@ -610,42 +620,72 @@ impl Codec for ClientExtension {
         let len = u16::read(r)? as usize;
         let mut sub = r.sub(len)?;
 
-        let ext = match typ {
-            ExtensionType::ECPointFormats => Self::ECPointFormats(Vec::read(&mut sub)?),
-            ExtensionType::EllipticCurves => Self::NamedGroups(Vec::read(&mut sub)?),
-            ExtensionType::SignatureAlgorithms => Self::SignatureAlgorithms(Vec::read(&mut sub)?),
-            ExtensionType::ServerName => Self::ServerName(Vec::read(&mut sub)?),
+        Self::read_body(typ, &mut sub)
+            .and_then(|ext| {
+                sub.expect_empty("ClientExtension")
+                    .map(|_| ext)
+            })
+            .map_err(|error| InvalidMessage::InvalidExtension {
+                typ,
+                offset: sub.used(),
+                context: "ClientExtension",
+                error: Box::new(error),
+            })
+    }
+}
+
+impl ClientExtension {
+    fn read_body(typ: ExtensionType, sub: &mut Reader) -> Result<Self, InvalidMessage> {
+        Ok(match typ {
+            ExtensionType::ECPointFormats => Self::ECPointFormats(Vec::read(sub)?),
+            ExtensionType::EllipticCurves => Self::NamedGroups(Vec::read(sub)?),
+            ExtensionType::SignatureAlgorithms => Self::SignatureAlgorithms(Vec::read(sub)?),
+            ExtensionType::ServerName => Self::ServerName(Vec::read(sub)?),
             ExtensionType::SessionTicket => {
                 if sub.any_left() {
-                    let contents = Payload::read(&mut sub);
+                    let contents = Payload::read(sub);
                     Self::SessionTicket(ClientSessionTicket::Offer(contents))
                 } else {
                     Self::SessionTicket(ClientSessionTicket::Request)
                 }
             }
-            ExtensionType::ALProtocolNegotiation => Self::Protocols(Vec::read(&mut sub)?),
-            ExtensionType::SupportedVersions => Self::SupportedVersions(Vec::read(&mut sub)?),
-            ExtensionType::KeyShare => Self::KeyShare(Vec::read(&mut sub)?),
-            ExtensionType::PSKKeyExchangeModes => Self::PresharedKeyModes(Vec::read(&mut sub)?),
-            ExtensionType::PreSharedKey => Self::PresharedKey(PresharedKeyOffer::read(&mut sub)?),
-            ExtensionType::Cookie => Self::Cookie(PayloadU16::read(&mut sub)?),
+            ExtensionType::ALProtocolNegotiation => Self::Protocols(Vec::read(sub)?),
+            ExtensionType::SupportedVersions => Self::SupportedVersions(Vec::read(sub)?),
+            ExtensionType::KeyShare => Self::KeyShare(Vec::read(sub)?),
+            ExtensionType::PSKKeyExchangeModes => Self::PresharedKeyModes(Vec::read(sub)?),
+            ExtensionType::PreSharedKey => Self::PresharedKey(PresharedKeyOffer::read(sub)?),
+            ExtensionType::Cookie => Self::Cookie(PayloadU16::read(sub)?),
             ExtensionType::ExtendedMasterSecret if !sub.any_left() => {
                 Self::ExtendedMasterSecretRequest
             }
             ExtensionType::StatusRequest => {
-                let csr = CertificateStatusRequest::read(&mut sub)?;
+                let csr = CertificateStatusRequest::read(sub)?;
                 Self::CertificateStatusRequest(csr)
             }
+            ExtensionType::TrustedCAKeys => {
+                // identifier types we don't know cannot be skipped over,
+                // so keep the whole extension opaque in that case.
+                let body = sub.rest();
+                match Vec::read_bytes(body) {
+                    Ok(authorities) => Self::TrustedCaKeys(authorities),
+                    Err(InvalidMessage::UnexpectedMessage("TrustedAuthority")) => {
+                        Self::Unknown(UnknownExtension {
+                            typ,
+                            payload: Payload::new(body),
+                        })
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+            ExtensionType::ServerCertificateType => Self::ServerCertificateTypes(Vec::read(sub)?),
+            ExtensionType::ClientCertificateType => Self::ClientCertificateTypes(Vec::read(sub)?),
             ExtensionType::TransportParameters => Self::TransportParameters(sub.rest().to_vec()),
             ExtensionType::TransportParametersDraft => {
                 Self::TransportParametersDraft(sub.rest().to_vec())
             }
             ExtensionType::EarlyData if !sub.any_left() => Self::EarlyData,
-            _ => Self::Unknown(UnknownExtension::read(typ, &mut sub)),
-        };
-
-        sub.expect_empty("ClientExtension")
-            .map(|_| ext)
+            _ => Self::Unknown(UnknownExtension::read(typ, sub)),
+        })
     }
 }
 
@@ -694,9 +734,12 @@ pub enum ServerExtension {
     ExtendedMasterSecretAck,
     CertificateStatusAck,
     SupportedVersions(ProtocolVersion),
+    ServerCertificateType(CertificateType),
+    ClientCertificateType(CertificateType),
     TransportParameters(Vec<u8>),
     TransportParametersDraft(Vec<u8>),
     EarlyData,
+    SupportedGroups(Vec<NamedGroup>),
     Unknown(UnknownExtension),
 }
 
@@ -713,9 +756,12 @@ impl ServerExtension {
             Self::ExtendedMasterSecretAck => ExtensionType::ExtendedMasterSecret,
             Self::CertificateStatusAck => ExtensionType::StatusRequest,
             Self::SupportedVersions(_) => ExtensionType::SupportedVersions,
+            Self::ServerCertificateType(_) => ExtensionType::ServerCertificateType,
+            Self::ClientCertificateType(_) => ExtensionType::ClientCertificateType,
             Self::TransportParameters(_) => ExtensionType::TransportParameters,
             Self::TransportParametersDraft(_) => ExtensionType::TransportParametersDraft,
             Self::EarlyData => ExtensionType::EarlyData,
+            Self::SupportedGroups(_) => ExtensionType::EllipticCurves,
             Self::Unknown(ref r) => r.typ,
         }
     }
@@ -738,9 +784,13 @@ impl Codec for ServerExtension {
             Self::KeyShare(ref r) => r.encode(nested.buf),
             Self::PresharedKey(r) => r.encode(nested.buf),
             Self::SupportedVersions(ref r) => r.encode(nested.buf),
+            Self::ServerCertificateType(ref r) | Self::ClientCertificateType(ref r) => {
+                r.encode(nested.buf)
+            }
             Self::TransportParameters(ref r) | Self::TransportParametersDraft(ref r) => {
                 nested.buf.extend_from_slice(r);
             }
+            Self::SupportedGroups(ref r) => r.encode(nested.buf),
             Self::Unknown(ref r) => r.encode(nested.buf),
         }
     }
@@ -750,29 +800,49 @@ impl Codec for ServerExtension {
         let len = u16::read(r)? as usize;
         let mut sub = r.sub(len)?;
 
-        let ext = match typ {
-            ExtensionType::ECPointFormats => Self::ECPointFormats(Vec::read(&mut sub)?),
+        Self::read_body(typ, &mut sub)
+            .and_then(|ext| {
+                sub.expect_empty("ServerExtension")
+                    .map(|_| ext)
+            })
+            .map_err(|error| InvalidMessage::InvalidExtension {
+                typ,
+                offset: sub.used(),
+                context: "ServerExtension",
+                error: Box::new(error),
+            })
+    }
+}
+
+impl ServerExtension {
+    fn read_body(typ: ExtensionType, sub: &mut Reader) -> Result<Self, InvalidMessage> {
+        Ok(match typ {
+            ExtensionType::ECPointFormats => Self::ECPointFormats(Vec::read(sub)?),
             ExtensionType::ServerName => Self::ServerNameAck,
             ExtensionType::SessionTicket => Self::SessionTicketAck,
             ExtensionType::StatusRequest => Self::CertificateStatusAck,
-            ExtensionType::RenegotiationInfo => Self::RenegotiationInfo(PayloadU8::read(&mut sub)?),
-            ExtensionType::ALProtocolNegotiation => Self::Protocols(Vec::read(&mut sub)?),
-            ExtensionType::KeyShare => Self::KeyShare(KeyShareEntry::read(&mut sub)?),
-            ExtensionType::PreSharedKey => Self::PresharedKey(u16::read(&mut sub)?),
+            ExtensionType::RenegotiationInfo => Self::RenegotiationInfo(PayloadU8::read(sub)?),
+            ExtensionType::ALProtocolNegotiation => Self::Protocols(Vec::read(sub)?),
+            ExtensionType::KeyShare => Self::KeyShare(KeyShareEntry::read(sub)?),
+            ExtensionType::PreSharedKey => Self::PresharedKey(u16::read(sub)?),
             ExtensionType::ExtendedMasterSecret => Self::ExtendedMasterSecretAck,
             ExtensionType::SupportedVersions => {
-                Self::SupportedVersions(ProtocolVersion::read(&mut sub)?)
+                Self::SupportedVersions(ProtocolVersion::read(sub)?)
+            }
+            ExtensionType::ServerCertificateType => {
+                Self::ServerCertificateType(CertificateType::read(sub)?)
+            }
+            ExtensionType::ClientCertificateType => {
+                Self::ClientCertificateType(CertificateType::read(sub)?)
             }
             ExtensionType::TransportParameters => Self::TransportParameters(sub.rest().to_vec()),
             ExtensionType::TransportParametersDraft => {
                 Self::TransportParametersDraft(sub.rest().to_vec())
             }
             ExtensionType::EarlyData => Self::EarlyData,
-            _ => Self::Unknown(UnknownExtension::read(typ, &mut sub)),
-        };
-
-        sub.expect_empty("ServerExtension")
-            .map(|_| ext)
+            ExtensionType::EllipticCurves => Self::SupportedGroups(Vec::read(sub)?),
+            _ => Self::Unknown(UnknownExtension::read(typ, sub)),
+        })
     }
 }
 
@@ -900,6 +970,22 @@ impl ClientHelloPayload {
         }
     }
 
+    pub fn get_trusted_ca_keys_extension(&self) -> Option<&[TrustedAuthority]> {
+        let ext = self.find_extension(ExtensionType::TrustedCAKeys)?;
+        match *ext {
+            ClientExtension::TrustedCaKeys(ref req) => Some(req),
+            _ => None,
+        }
+    }
+
+    pub fn get_server_certificate_types_extension(&self) -> Option<&[CertificateType]> {
+        let ext = self.find_extension(ExtensionType::ServerCertificateType)?;
+        match *ext {
+            ClientExtension::ServerCertificateTypes(ref types) => Some(types),
+            _ => None,
+        }
+    }
+
     pub fn get_alpn_extension(&self) -> Option<&Vec<ProtocolName>> {
         let ext = self.find_extension(ExtensionType::ALProtocolNegotiation)?;
         match *ext {
@@ -1358,6 +1444,16 @@ impl CertificateEntry {
             .any(|ext| ext.get_type() != ExtensionType::StatusRequest)
     }
 
+    pub fn unknown_extensions(&self) -> Vec<(u16, &[u8])> {
+        self.exts
+            .iter()
+            .filter_map(|ext| match ext {
+                CertificateExtension::Unknown(unk) => Some((unk.typ.get_u16(), &unk.payload.0[..])),
+                CertificateExtension::CertificateStatus(_) => None,
+            })
+            .collect()
+    }
+
     pub fn get_ocsp_response(&self) -> Option<&Vec<u8>> {
         self.exts
             .iter()
@@ -1631,6 +1727,14 @@ pub trait HasServerExtensions {
         }
     }
 
+    fn get_server_certificate_type(&self) -> Option<CertificateType> {
+        let ext = self.find_extension(ExtensionType::ServerCertificateType)?;
+        match *ext {
+            ServerExtension::ServerCertificateType(typ) => Some(typ),
+            _ => None,
+        }
+    }
+
     fn get_quic_params_extension(&self) -> Option<Vec<u8>> {
         let ext = self
             .find_extension(ExtensionType::TransportParameters)
@@ -1646,6 +1750,14 @@ pub trait HasServerExtensions {
         self.find_extension(ExtensionType::EarlyData)
             .is_some()
     }
+
+    fn get_supported_groups(&self) -> Option<&[NamedGroup]> {
+        let ext = self.find_extension(ExtensionType::EllipticCurves)?;
+        match *ext {
+            ServerExtension::SupportedGroups(ref groups) => Some(groups),
+            _ => None,
+        }
+    }
 }
 
 impl HasServerExtensions for Vec<ServerExtension> {
@@ -1658,6 +1770,10 @@ impl TlsListElement for ClientCertificateType {
     const SIZE_LEN: ListLength = ListLength::U8;
 }
 
+impl TlsListElement for CertificateType {
+    const SIZE_LEN: ListLength = ListLength::U8;
+}
+
 wrapped_payload!(
     /// A `DistinguishedName` is a `Vec<u8>` wrapped in internal types.
     ///
@@ -1695,6 +1811,67 @@ impl TlsListElement for DistinguishedName {
     const SIZE_LEN: ListLength = ListLength::U16;
 }
 
+/// One certificate authority named in the [RFC 6066] `trusted_ca_keys` extension.
+///
+/// [RFC 6066]: https://datatracker.ietf.org/doc/html/rfc6066#section-6
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub enum TrustedAuthority {
+    /// The client has a CA in mind that was agreed out of band.
+    PreAgreed,
+    /// The SHA-1 hash of the CA's public key.
+    KeySha1Hash([u8; 20]),
+    /// The CA's distinguished name.
+    X509Name(DistinguishedName),
+    /// The SHA-1 hash of the CA's DER-encoded certificate.
+    CertSha1Hash([u8; 20]),
+}
+
+impl TrustedAuthority {
+    fn read_sha1(r: &mut Reader) -> Result<[u8; 20], InvalidMessage> {
+        let mut hash = [0u8; 20];
+        hash.copy_from_slice(
+            r.take(20)
+                .ok_or(InvalidMessage::MissingData("TrustedAuthority"))?,
+        );
+        Ok(hash)
+    }
+}
+
+impl Codec for TrustedAuthority {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        match self {
+            Self::PreAgreed => 0u8.encode(bytes),
+            Self::KeySha1Hash(hash) => {
+                1u8.encode(bytes);
+                bytes.extend_from_slice(hash);
+            }
+            Self::X509Name(name) => {
+                2u8.encode(bytes);
+                name.encode(bytes);
+            }
+            Self::CertSha1Hash(hash) => {
+                3u8.encode(bytes);
+                bytes.extend_from_slice(hash);
+            }
+        }
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, InvalidMessage> {
+        match u8::read(r)? {
+            0 => Ok(Self::PreAgreed),
+            1 => Ok(Self::KeySha1Hash(Self::read_sha1(r)?)),
+            2 => Ok(Self::X509Name(DistinguishedName::read(r)?)),
+            3 => Ok(Self::CertSha1Hash(Self::read_sha1(r)?)),
+            _ => Err(InvalidMessage::UnexpectedMessage("TrustedAuthority")),
+        }
+    }
+}
+
+impl TlsListElement for TrustedAuthority {
+    const SIZE_LEN: ListLength = ListLength::U16;
+}
+
 #[derive(Debug)]
 pub struct CertificateRequestPayload {
     pub certtypes: Vec<ClientCertificateType>,
@@ -1,9 +1,10 @@
 use crate::dns_name::DnsNameRef;
 use crate::enums::{CipherSuite, HandshakeType, ProtocolVersion, SignatureScheme};
+use crate::error::InvalidMessage;
 use crate::msgs::base::{Payload, PayloadU16, PayloadU24, PayloadU8};
 use crate::msgs::codec::{put_u16, Codec, Reader};
 use crate::msgs::enums::{
-    ClientCertificateType, Compression, ECCurveType, ECPointFormat, ExtensionType,
+    CertificateType, ClientCertificateType, Compression, ECCurveType, ECPointFormat, ExtensionType,
     KeyUpdateRequest, NamedGroup, PSKKeyExchangeMode, ServerNameType,
 };
 use crate::msgs::handshake::{
@@ -15,7 +16,8 @@ use crate::msgs::handshake::{
     HelloRetryExtension, HelloRetryRequest, KeyShareEntry, NewSessionTicketExtension,
     NewSessionTicketPayload, NewSessionTicketPayloadTLS13, PresharedKeyBinder,
     PresharedKeyIdentity, PresharedKeyOffer, ProtocolName, Random, ServerECDHParams,
-    ServerExtension, ServerHelloPayload, ServerKeyExchangePayload, SessionId, UnknownExtension,
+    ServerExtension, ServerHelloPayload, ServerKeyExchangePayload, SessionId, TrustedAuthority,
+    UnknownExtension,
 };
 use crate::verify::DigitallySignedStruct;
 
@@ -256,6 +258,33 @@ fn rejects_truncated_sni() {
     assert!(ClientExtension::read(&mut Reader::init(&bytes)).is_err());
 }
 
+#[test]
+fn extension_decode_errors_name_the_extension() {
+    // supported_versions, claiming a four byte list but carrying only two
+    let bytes = [0x00, 0x2b, 0x00, 0x03, 0x04, 0x03, 0x04];
+    assert_eq!(
+        ClientExtension::read_bytes(&bytes).unwrap_err(),
+        InvalidMessage::InvalidExtension {
+            typ: ExtensionType::SupportedVersions,
+            offset: 1,
+            context: "ClientExtension",
+            error: Box::new(InvalidMessage::MessageTooShort),
+        }
+    );
+
+    // supported_versions, with a trailing byte after the selected version
+    let bytes = [0x00, 0x2b, 0x00, 0x03, 0x03, 0x04, 0x00];
+    assert_eq!(
+        ServerExtension::read_bytes(&bytes).unwrap_err(),
+        InvalidMessage::InvalidExtension {
+            typ: ExtensionType::SupportedVersions,
+            offset: 2,
+            context: "ServerExtension",
+            error: Box::new(InvalidMessage::TrailingData("ServerExtension")),
+        }
+    );
+}
+
 #[test]
 fn can_roundtrip_psk_identity() {
     let bytes = [0, 0, 0x11, 0x22, 0x33, 0x44];
@@ -386,6 +415,17 @@ fn get_sample_clienthellopayload() -> ClientHelloPayload {
             ClientExtension::Cookie(PayloadU16(vec![1, 2, 3])),
             ClientExtension::ExtendedMasterSecretRequest,
             ClientExtension::CertificateStatusRequest(CertificateStatusRequest::build_ocsp()),
+            ClientExtension::TrustedCaKeys(vec![
+                TrustedAuthority::PreAgreed,
+                TrustedAuthority::KeySha1Hash([1; 20]),
+                TrustedAuthority::X509Name(DistinguishedName::from(vec![2, 3])),
+                TrustedAuthority::CertSha1Hash([4; 20]),
+            ]),
+            ClientExtension::ServerCertificateTypes(vec![
+                CertificateType::RawPublicKey,
+                CertificateType::X509,
+            ]),
+            ClientExtension::ClientCertificateTypes(vec![CertificateType::X509]),
             ClientExtension::TransportParameters(vec![1, 2, 3]),
             ClientExtension::Unknown(UnknownExtension {
                 typ: ExtensionType::Unknown(12345),
@@ -536,6 +576,30 @@ fn client_get_alpn_extension() {
     });
 }
 
+#[test]
+fn client_get_trusted_ca_keys_extension() {
+    test_client_extension_getter(ExtensionType::TrustedCAKeys, |chp| {
+        chp.get_trusted_ca_keys_extension()
+            .is_some()
+    });
+}
+
+#[test]
+fn unknown_trusted_authority_type_is_kept_opaque() {
+    let enc = [0x00, 0x03, 0x00, 0x05, 0x00, 0x03, 0x00, 0xff, 0x01];
+    let ext = ClientExtension::read_bytes(&enc).unwrap();
+    assert!(matches!(
+        ext,
+        ClientExtension::Unknown(UnknownExtension {
+            typ: ExtensionType::TrustedCAKeys,
+            ..
+        })
+    ));
+
+    // truncation is still an error
+    assert!(ClientExtension::read_bytes(&[0x00, 0x03, 0x00, 0x03, 0x00, 0x03, 0x01]).is_err());
+}
+
 #[test]
 fn client_get_quic_params_extension() {
     test_client_extension_getter(ExtensionType::TransportParameters, |chp| {
@@ -755,7 +819,10 @@ fn get_sample_serverhellopayload() -> ServerHelloPayload {
             ServerExtension::ExtendedMasterSecretAck,
             ServerExtension::CertificateStatusAck,
             ServerExtension::SupportedVersions(ProtocolVersion::TLSv1_2),
+            ServerExtension::ServerCertificateType(CertificateType::RawPublicKey),
+            ServerExtension::ClientCertificateType(CertificateType::X509),
             ServerExtension::TransportParameters(vec![1, 2, 3]),
+            ServerExtension::SupportedGroups(vec![NamedGroup::X25519]),
             ServerExtension::Unknown(UnknownExtension {
                 typ: ExtensionType::Unknown(12345),
                 payload: Payload(vec![1, 2, 3]),
@@ -1197,6 +1264,28 @@ fn can_decode_server_hello_from_api_devicecheck_apple_com() {
     println!("msg: {:?}", hm);
 }
 
+#[test]
+fn certificate_request_tls13_with_empty_authorities() {
+    let bytes = [
+        0x00, // empty context
+        0x00, 0x0e, // extensions
+        0x00, 0x0d, 0x00, 0x04, 0x00, 0x02, 0x08, 0x07, // signature_algorithms: ed25519
+        0x00, 0x2f, 0x00, 0x02, 0x00, 0x00, // certificate_authorities: empty
+    ];
+    let cr = CertificateRequestPayloadTLS13::read_bytes(&bytes).unwrap();
+    assert_eq!(
+        cr.get_authorities_extension()
+            .map(|names| names.len()),
+        Some(0)
+    );
+
+    let bytes = [
+        0x00, 0x00, 0x08, 0x00, 0x0d, 0x00, 0x04, 0x00, 0x02, 0x08, 0x07,
+    ];
+    let cr = CertificateRequestPayloadTLS13::read_bytes(&bytes).unwrap();
+    assert!(cr.get_authorities_extension().is_none());
+}
+
 #[test]
 fn wrapped_dn_encoding() {
     let subject = b"subject";
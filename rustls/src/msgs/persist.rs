@@ -5,6 +5,7 @@ use crate::msgs::base::{PayloadU16, PayloadU8};
 use crate::msgs::codec::{Codec, Reader};
 use crate::msgs::handshake::CertificatePayload;
 use crate::msgs::handshake::SessionId;
+use crate::suites::SupportedCipherSuite;
 #[cfg(feature = "tls12")]
 use crate::tls12::Tls12CipherSuite;
 use crate::tls13::Tls13CipherSuite;
@@ -38,12 +39,8 @@ impl<T> Retrieved<T> {
 
 impl Retrieved<&Tls13ClientSessionValue> {
     pub fn obfuscated_ticket_age(&self) -> u32 {
-        let age_secs = self
-            .retrieved_at
-            .as_secs()
-            .saturating_sub(self.value.common.epoch);
-        let age_millis = age_secs as u32 * 1000;
-        age_millis.wrapping_add(self.value.age_add)
+        self.value
+            .obfuscated_ticket_age(self.retrieved_at)
     }
 }
 
@@ -66,7 +63,7 @@ impl<T> core::ops::Deref for Retrieved<T> {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Tls13ClientSessionValue {
     suite: &'static Tls13CipherSuite,
     age_add: u32,
@@ -107,6 +104,21 @@ impl Tls13ClientSessionValue {
         self.max_early_data_size
     }
 
+    /// The `obfuscated_ticket_age` to send with this ticket at time `now`.
+    ///
+    /// This is the age of the ticket in milliseconds, plus the server's
+    /// `ticket_age_add`, modulo 2^32.  Ages are only measured in whole
+    /// seconds.  See [RFC 8446 section 4.2.11.1].
+    ///
+    /// [RFC 8446 section 4.2.11.1]: https://www.rfc-editor.org/rfc/rfc8446#section-4.2.11.1
+    pub fn obfuscated_ticket_age(&self, now: UnixTime) -> u32 {
+        let age_secs = now
+            .as_secs()
+            .saturating_sub(self.common.epoch);
+        let age_millis = age_secs.wrapping_mul(1000) as u32;
+        age_millis.wrapping_add(self.age_add)
+    }
+
     pub fn suite(&self) -> &'static Tls13CipherSuite {
         self.suite
     }
@@ -128,6 +140,50 @@ impl Tls13ClientSessionValue {
     }
 }
 
+impl Tls13ClientSessionValue {
+    pub(crate) fn encode(&self, bytes: &mut Vec<u8>) {
+        self.suite.common.suite.encode(bytes);
+        self.age_add.encode(bytes);
+        self.max_early_data_size.encode(bytes);
+        self.common.encode(bytes);
+        #[cfg(feature = "quic")]
+        self.quic_params.encode(bytes);
+        #[cfg(not(feature = "quic"))]
+        PayloadU16::empty().encode(bytes);
+    }
+
+    /// Read a value written by `encode`.
+    ///
+    /// This gives `None` if its suite is not among `suites`.
+    pub(crate) fn read(
+        r: &mut Reader,
+        suites: &[SupportedCipherSuite],
+    ) -> Result<Option<Self>, InvalidMessage> {
+        let suite = CipherSuite::read(r)?;
+        let age_add = u32::read(r)?;
+        let max_early_data_size = u32::read(r)?;
+        let common = ClientSessionCommon::read(r)?;
+        #[cfg_attr(not(feature = "quic"), allow(unused_variables))]
+        let quic_params = PayloadU16::read(r)?;
+
+        Ok(suites
+            .iter()
+            .find_map(|s| match s {
+                SupportedCipherSuite::Tls13(s) => Some(*s).filter(|s| s.common.suite == suite),
+                #[cfg(feature = "tls12")]
+                SupportedCipherSuite::Tls12(_) => None,
+            })
+            .map(|suite| Self {
+                suite,
+                age_add,
+                max_early_data_size,
+                common,
+                #[cfg(feature = "quic")]
+                quic_params,
+            }))
+    }
+}
+
 impl core::ops::Deref for Tls13ClientSessionValue {
     type Target = ClientSessionCommon;
 
@@ -194,6 +250,42 @@ impl Tls12ClientSessionValue {
     }
 }
 
+#[cfg(feature = "tls12")]
+impl Tls12ClientSessionValue {
+    pub(crate) fn encode(&self, bytes: &mut Vec<u8>) {
+        self.suite.common.suite.encode(bytes);
+        self.session_id.encode(bytes);
+        u8::from(self.extended_ms).encode(bytes);
+        self.common.encode(bytes);
+    }
+
+    /// Read a value written by `encode`.
+    ///
+    /// This gives `None` if its suite is not among `suites`.
+    pub(crate) fn read(
+        r: &mut Reader,
+        suites: &[SupportedCipherSuite],
+    ) -> Result<Option<Self>, InvalidMessage> {
+        let suite = CipherSuite::read(r)?;
+        let session_id = SessionId::read(r)?;
+        let extended_ms = u8::read(r)? == 1;
+        let common = ClientSessionCommon::read(r)?;
+
+        Ok(suites
+            .iter()
+            .find_map(|s| match s {
+                SupportedCipherSuite::Tls12(s) if s.common.suite == suite => Some(*s),
+                _ => None,
+            })
+            .map(|suite| Self {
+                suite,
+                session_id,
+                extended_ms,
+                common,
+            }))
+    }
+}
+
 #[cfg(feature = "tls12")]
 impl core::ops::Deref for Tls12ClientSessionValue {
     type Target = ClientSessionCommon;
@@ -242,6 +334,26 @@ impl ClientSessionCommon {
     }
 }
 
+impl Codec for ClientSessionCommon {
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        self.ticket.encode(bytes);
+        self.secret.encode(bytes);
+        self.epoch.encode(bytes);
+        self.lifetime_secs.encode(bytes);
+        self.server_cert_chain.encode(bytes);
+    }
+
+    fn read(r: &mut Reader) -> Result<Self, InvalidMessage> {
+        Ok(Self {
+            ticket: PayloadU16::read(r)?,
+            secret: PayloadU8::read(r)?,
+            epoch: u64::read(r)?,
+            lifetime_secs: cmp::min(u32::read(r)?, MAX_TICKET_LIFETIME),
+            server_cert_chain: CertificatePayload::read(r)?,
+        })
+    }
+}
+
 static MAX_TICKET_LIFETIME: u32 = 7 * 24 * 60 * 60;
 
 /// This is the maximum allowed skew between server and client clocks, over
@@ -424,6 +536,39 @@ mod tests {
         println!("{:?}", ssv);
     }
 
+    #[cfg(feature = "ring")]
+    #[test]
+    fn tls13_obfuscated_ticket_age() {
+        use core::time::Duration;
+
+        let issued = UnixTime::since_unix_epoch(Duration::from_secs(1_000_000));
+        let at = |secs: u64| UnixTime::since_unix_epoch(Duration::from_secs(1_000_000 + secs));
+        let value = Tls13ClientSessionValue::new(
+            crate::crypto::ring::tls13::TLS13_AES_128_GCM_SHA256_INTERNAL,
+            vec![1, 2, 3],
+            vec![4, 5, 6],
+            Vec::new(),
+            issued,
+            7200,
+            0xffff_f000,
+            0,
+        );
+
+        assert_eq!(value.obfuscated_ticket_age(issued), 0xffff_f000);
+        assert_eq!(value.obfuscated_ticket_age(at(3)), 0xffff_fbb8);
+        // wraps around
+        assert_eq!(value.obfuscated_ticket_age(at(5)), 5000 - 0x1000);
+        // going back in time counts as age zero
+        assert_eq!(
+            value.obfuscated_ticket_age(UnixTime::since_unix_epoch(Duration::from_secs(0))),
+            0xffff_f000
+        );
+        assert_eq!(
+            Retrieved::new(&value, at(5)).obfuscated_ticket_age(),
+            value.obfuscated_ticket_age(at(5))
+        );
+    }
+
     #[test]
     fn serversessionvalue_no_sni() {
         let bytes = [
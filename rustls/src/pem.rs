@@ -0,0 +1,91 @@
+use crate::error::Error;
+
+use pki_types::{CertificateDer, PrivateKeyDer};
+use rustls_pemfile::Item;
+
+use std::io;
+
+/// Splits a PEM file holding both a certificate chain and private keys, in any order.
+///
+/// Sections are separated by their PEM label in a single pass over `rd`.
+/// Certificates are returned in the order they appear, so a file written
+/// end-entity first yields a chain suitable for [`ConfigBuilder::with_single_cert`].
+/// PKCS#1, PKCS#8 and SEC1 private keys are all recognised.  Other sections,
+/// such as CRLs, are skipped.
+///
+/// Failing to read `rd`, or malformed PEM, is reported as [`Error::InvalidPem`].
+///
+/// [`ConfigBuilder::with_single_cert`]: crate::ConfigBuilder::with_single_cert
+pub fn parse_combined_pem(
+    rd: &mut dyn io::BufRead,
+) -> Result<(Vec<CertificateDer<'static>>, Vec<PrivateKeyDer<'static>>), Error> {
+    let mut certs = Vec::new();
+    let mut keys = Vec::new();
+
+    while let Some(item) =
+        rustls_pemfile::read_one(rd).map_err(|err| Error::InvalidPem(err.to_string()))?
+    {
+        match item {
+            Item::X509Certificate(cert) => certs.push(cert),
+            Item::Pkcs1Key(key) => keys.push(key.into()),
+            Item::Pkcs8Key(key) => keys.push(key.into()),
+            Item::Sec1Key(key) => keys.push(key.into()),
+            _ => {}
+        }
+    }
+
+    Ok((certs, keys))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn combined_chain_and_key() {
+        let chain = include_str!("../../test-ca/rsa/end.fullchain");
+        let key = include_str!("../../test-ca/rsa/end.key");
+
+        // put the key between the end-entity and intermediate certificates
+        let combined = [
+            include_str!("../../test-ca/rsa/end.cert"),
+            key,
+            include_str!("../../test-ca/rsa/inter.cert"),
+            include_str!("../../test-ca/rsa/ca.cert"),
+        ]
+        .concat();
+
+        let (certs, keys) = parse_combined_pem(&mut combined.as_bytes()).unwrap();
+        let expected = parse_combined_pem(&mut chain.as_bytes())
+            .unwrap()
+            .0;
+        assert_eq!(certs.len(), 3);
+        assert_eq!(certs, expected);
+        assert_eq!(
+            certs[2].as_ref(),
+            include_bytes!("../../test-ca/rsa/ca.der")
+        );
+
+        assert_eq!(keys.len(), 1);
+        assert!(matches!(keys[0], PrivateKeyDer::Pkcs8(_)));
+        let expected = parse_combined_pem(&mut key.as_bytes())
+            .unwrap()
+            .1;
+        assert_eq!(keys[0].secret_der(), expected[0].secret_der());
+    }
+
+    #[test]
+    fn malformed_pem() {
+        let pem = "-----BEGIN CERTIFICATE-----\n!!!!\n-----END CERTIFICATE-----\n";
+        assert!(matches!(
+            parse_combined_pem(&mut pem.as_bytes()),
+            Err(Error::InvalidPem(_))
+        ));
+
+        let unterminated = "-----BEGIN CERTIFICATE-----\nAAAA\n";
+        assert!(matches!(
+            parse_combined_pem(&mut unterminated.as_bytes()),
+            Err(Error::InvalidPem(_))
+        ));
+    }
+}
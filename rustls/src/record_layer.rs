@@ -1,5 +1,8 @@
 use crate::crypto::cipher::{MessageDecrypter, MessageEncrypter};
+use crate::crypto::CryptoProvider;
+use crate::enums::CipherSuite;
 use crate::error::Error;
+use crate::msgs::fragmenter::MAX_FRAGMENT_LEN;
 use crate::msgs::message::{BorrowedPlainMessage, OpaqueMessage, PlainMessage};
 
 #[cfg(feature = "logging")]
@@ -26,6 +29,7 @@ pub struct RecordLayer {
     message_decrypter: Box<dyn MessageDecrypter>,
     write_seq: u64,
     read_seq: u64,
+    confidentiality_limit: u64,
     has_decrypted: bool,
     encrypt_state: DirectionState,
     decrypt_state: DirectionState,
@@ -44,6 +48,7 @@ impl RecordLayer {
             message_decrypter: <dyn MessageDecrypter>::invalid(),
             write_seq: 0,
             read_seq: 0,
+            confidentiality_limit: u64::MAX,
             has_decrypted: false,
             encrypt_state: DirectionState::Invalid,
             decrypt_state: DirectionState::Invalid,
@@ -105,12 +110,24 @@ impl RecordLayer {
     /// `plain` is a TLS message we'd like to send.  This function
     /// panics if the requisite keying material hasn't been established yet.
     pub(crate) fn encrypt_outgoing(&mut self, plain: BorrowedPlainMessage) -> OpaqueMessage {
+        self.encrypt_outgoing_padded(plain, 0)
+    }
+
+    /// Encrypt a TLS message, adding `padding` zero bytes to the
+    /// TLS1.3 inner plaintext.
+    ///
+    /// This otherwise behaves like `encrypt_outgoing`.
+    pub(crate) fn encrypt_outgoing_padded(
+        &mut self,
+        plain: BorrowedPlainMessage,
+        padding: usize,
+    ) -> OpaqueMessage {
         debug_assert!(self.encrypt_state == DirectionState::Active);
         assert!(!self.encrypt_exhausted());
         let seq = self.write_seq;
         self.write_seq += 1;
         self.message_encrypter
-            .encrypt(plain, seq)
+            .encrypt_padded(plain, seq, padding)
             .unwrap()
     }
 
@@ -188,10 +205,30 @@ impl RecordLayer {
         self.write_seq >= SEQ_HARD_LIMIT
     }
 
+    /// Set how many records may be encrypted under each write key.
+    pub(crate) fn set_confidentiality_limit(&mut self, limit: u64) {
+        self.confidentiality_limit = limit;
+    }
+
+    /// Return how many more application data records may be encrypted
+    /// before the write key reaches its confidentiality limit.
+    ///
+    /// The last record allowed by the limit is held back for the
+    /// KeyUpdate or alert sent once it is reached.
+    pub(crate) fn records_before_confidentiality_limit(&self) -> u64 {
+        self.confidentiality_limit
+            .saturating_sub(1)
+            .saturating_sub(self.write_seq)
+    }
+
     pub(crate) fn is_encrypting(&self) -> bool {
         self.encrypt_state == DirectionState::Active
     }
 
+    pub(crate) fn is_decrypting(&self) -> bool {
+        self.decrypt_state == DirectionState::Active
+    }
+
     /// Return true if we have ever decrypted a message. This is used in place
     /// of checking the read_seq since that will be reset on key updates.
     pub(crate) fn has_decrypted(&self) -> bool {
@@ -220,6 +257,105 @@ impl RecordLayer {
     }
 }
 
+/// How much padding to add to outgoing TLS1.3 application data records.
+///
+/// Padding obscures the length of the plaintext carried in each record,
+/// which makes traffic analysis harder ([RFC8446 5.4]).  It costs
+/// bandwidth, and does nothing for TLS1.2 connections.  It also needs the
+/// TLS1.3 encrypter to implement [`MessageEncrypter::encrypt_padded`], as
+/// the built-in ring provider does.
+///
+/// Records are never padded beyond the TLS maximum plaintext length
+/// of 16 kB.
+///
+/// The default is [`RecordPaddingPolicy::None`].
+///
+/// [RFC8446 5.4]: <https://www.rfc-editor.org/rfc/rfc8446#section-5.4>
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecordPaddingPolicy {
+    /// Do not pad records.
+    None,
+    /// Pad the plaintext of each record up to the next multiple of
+    /// this many bytes.
+    BlockSize(usize),
+    /// Pad each record with a random number of bytes, up to and
+    /// including `max_len`.
+    Random {
+        /// The largest amount of padding to add to a record.
+        max_len: usize,
+    },
+}
+
+impl RecordPaddingPolicy {
+    /// Return the number of padding bytes to add to a record carrying
+    /// `len` bytes of plaintext.
+    pub(crate) fn padding_len(&self, len: usize, provider: &dyn CryptoProvider) -> usize {
+        let padding = match *self {
+            Self::None => 0,
+            Self::BlockSize(block) if block > 1 => (block - len % block) % block,
+            Self::BlockSize(_) => 0,
+            Self::Random { max_len: 0 } => 0,
+            Self::Random { max_len } => {
+                let mut buf = [0u8; 8];
+                match provider.fill_random(&mut buf) {
+                    Ok(()) => (u64::from_be_bytes(buf) % (max_len as u64 + 1)) as usize,
+                    // Err on the side of hiding the length.
+                    Err(_) => max_len,
+                }
+            }
+        };
+
+        padding.min(MAX_FRAGMENT_LEN.saturating_sub(len))
+    }
+}
+
+impl Default for RecordPaddingPolicy {
+    fn default() -> Self {
+        Self::None
+    }
+}
+
+/// What to do when the write key of a TLS1.3 connection reaches the
+/// confidentiality limit of its AEAD algorithm.
+///
+/// Encrypting too many records under one key erodes the security of
+/// AES-GCM ([RFC8446 5.5]), so TLS1.3 suites carry a
+/// [`Tls13CipherSuite::confidentiality_limit`].  Writes of application
+/// data are cut short rather than encrypted beyond it.
+///
+/// TLS1.2 has no way to change keys, and its connections are closed as they
+/// approach the end of the sequence number space, as before.
+///
+/// The default is [`AeadLimitPolicy::KeyUpdate`].
+///
+/// [RFC8446 5.5]: <https://www.rfc-editor.org/rfc/rfc8446#section-5.5>
+/// [`Tls13CipherSuite::confidentiality_limit`]: crate::Tls13CipherSuite::confidentiality_limit
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AeadLimitPolicy {
+    /// Send a KeyUpdate message and carry on with a fresh write key.
+    KeyUpdate,
+    /// Send a `close_notify` alert and refuse to send anything further.
+    ///
+    /// Writes then fail, as does the next call to `process_new_packets`,
+    /// with [`Error::AeadLimitReached`].
+    Fail,
+}
+
+impl Default for AeadLimitPolicy {
+    fn default() -> Self {
+        Self::KeyUpdate
+    }
+}
+
+/// Told when a connection's write key reaches its AEAD confidentiality limit.
+///
+/// This is called once per key, before the configured [`AeadLimitPolicy`]
+/// is applied.
+pub trait AeadLimitCallback: Send + Sync {
+    /// The write key for `suite` has been used to encrypt `records` records.
+    fn limit_reached(&self, suite: CipherSuite, records: u64);
+}
+
 /// Result of decryption.
 #[derive(Debug)]
 pub struct Decrypted {
@@ -233,6 +369,93 @@ pub struct Decrypted {
 mod tests {
     use super::*;
 
+    #[cfg(feature = "ring")]
+    #[test]
+    fn test_record_padding_len() {
+        let provider = crate::crypto::ring::RING;
+
+        assert_eq!(RecordPaddingPolicy::None.padding_len(5, provider), 0);
+
+        let block = RecordPaddingPolicy::BlockSize(256);
+        assert_eq!(block.padding_len(0, provider), 0);
+        assert_eq!(block.padding_len(5, provider), 251);
+        assert_eq!(block.padding_len(256, provider), 0);
+        assert_eq!(block.padding_len(257, provider), 255);
+        assert_eq!(
+            RecordPaddingPolicy::BlockSize(0).padding_len(5, provider),
+            0
+        );
+
+        // never pad beyond the maximum record size
+        let block = RecordPaddingPolicy::BlockSize(10_000);
+        assert_eq!(block.padding_len(MAX_FRAGMENT_LEN - 10, provider), 10);
+
+        let random = RecordPaddingPolicy::Random { max_len: 32 };
+        for _ in 0..100 {
+            assert!(random.padding_len(5, provider) <= 32);
+        }
+        assert_eq!(
+            RecordPaddingPolicy::Random { max_len: 0 }.padding_len(5, provider),
+            0
+        );
+    }
+
+    #[cfg(feature = "ring")]
+    #[test]
+    fn test_confidentiality_limit_after_many_records() {
+        use crate::{ContentType, ProtocolVersion};
+
+        struct PassThroughEncrypter;
+        impl MessageEncrypter for PassThroughEncrypter {
+            fn encrypt(&self, m: BorrowedPlainMessage, _: u64) -> Result<OpaqueMessage, Error> {
+                Ok(m.to_unencrypted_opaque())
+            }
+        }
+
+        let limit =
+            crate::crypto::ring::tls13::TLS13_AES_128_GCM_SHA256_INTERNAL.confidentiality_limit;
+        assert_eq!(limit, 1 << 23);
+
+        // Without a limit (as for TLS1.2), there's no practical end.
+        let mut record_layer = RecordLayer::new();
+        assert_eq!(
+            record_layer.records_before_confidentiality_limit(),
+            u64::MAX - 1
+        );
+
+        record_layer.set_message_encrypter(Box::new(PassThroughEncrypter));
+        record_layer.set_confidentiality_limit(limit);
+        assert_eq!(
+            record_layer.records_before_confidentiality_limit(),
+            limit - 1
+        );
+
+        // Skip ahead, as if nearly 2^23 records had been sent.
+        record_layer.write_seq = limit - 3;
+        let msg = || BorrowedPlainMessage {
+            typ: ContentType::ApplicationData,
+            version: ProtocolVersion::TLSv1_2,
+            payload: b"hello",
+        };
+        record_layer.encrypt_outgoing(msg());
+        assert_eq!(record_layer.records_before_confidentiality_limit(), 1);
+        record_layer.encrypt_outgoing(msg());
+        assert_eq!(record_layer.records_before_confidentiality_limit(), 0);
+
+        // The last record is kept for the KeyUpdate, and going beyond
+        // the limit does not wrap around.
+        record_layer.encrypt_outgoing(msg());
+        record_layer.encrypt_outgoing(msg());
+        assert_eq!(record_layer.records_before_confidentiality_limit(), 0);
+
+        // A new key starts afresh.
+        record_layer.set_message_encrypter(Box::new(PassThroughEncrypter));
+        assert_eq!(
+            record_layer.records_before_confidentiality_limit(),
+            limit - 1
+        );
+    }
+
     #[test]
     fn test_has_decrypted() {
         use crate::{ContentType, ProtocolVersion};
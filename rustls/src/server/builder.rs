@@ -1,9 +1,12 @@
 use crate::builder::{ConfigBuilder, WantsVerifier};
+use crate::common_state::PrematureDataPolicy;
 use crate::crypto::{CryptoProvider, SupportedKxGroup};
 #[cfg(feature = "ring")]
 use crate::error::Error;
+use crate::record_layer::{AeadLimitPolicy, RecordPaddingPolicy};
 use crate::server::handy;
 use crate::server::{ResolvesServerCert, ServerConfig};
+use crate::sign::SigningRetryPolicy;
 use crate::suites::SupportedCipherSuite;
 use crate::verify::{ClientCertVerifier, NoClientAuth};
 use crate::versions;
@@ -13,6 +16,7 @@ use crate::NoKeyLog;
 use pki_types::{CertificateDer, PrivateKeyDer};
 
 use alloc::sync::Arc;
+use core::fmt;
 use core::marker::PhantomData;
 
 impl ConfigBuilder<ServerConfig, WantsVerifier> {
@@ -28,6 +32,7 @@ impl ConfigBuilder<ServerConfig, WantsVerifier> {
                 provider: self.state.provider,
                 versions: self.state.versions,
                 verifier: client_cert_verifier,
+                base: None,
             },
             side: PhantomData,
         }
@@ -43,13 +48,48 @@ impl ConfigBuilder<ServerConfig, WantsVerifier> {
 /// the connecting peer.
 ///
 /// For more information, see the [`ConfigBuilder`] documentation.
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct WantsServerCert {
     cipher_suites: Vec<SupportedCipherSuite>,
     kx_groups: Vec<&'static dyn SupportedKxGroup>,
     provider: &'static dyn CryptoProvider,
     versions: versions::EnabledVersions,
     verifier: Arc<dyn ClientCertVerifier>,
+    base: Option<Box<ServerConfig>>,
+}
+
+impl fmt::Debug for WantsServerCert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // `base` is an implementation detail of `ServerConfig::to_builder`
+        f.debug_struct("WantsServerCert")
+            .field("cipher_suites", &self.cipher_suites)
+            .field("kx_groups", &self.kx_groups)
+            .field("provider", &self.provider)
+            .field("versions", &self.versions)
+            .field("verifier", &self.verifier)
+            .finish()
+    }
+}
+
+impl ConfigBuilder<ServerConfig, WantsServerCert> {
+    /// Start a builder from the existing `config`.
+    ///
+    /// This keeps the protocol primitives and client certificate verifier
+    /// of `config`.  Once the server certificate is chosen, the resulting
+    /// config has every other setting of `config`.
+    pub(super) fn from_config(config: &ServerConfig) -> Self {
+        Self {
+            state: WantsServerCert {
+                cipher_suites: config.cipher_suites.clone(),
+                kx_groups: config.kx_groups.clone(),
+                provider: config.provider,
+                versions: config.versions,
+                verifier: Arc::clone(&config.verifier),
+                base: Some(Box::new(config.clone())),
+            },
+            side: PhantomData,
+        }
+    }
 }
 
 impl ConfigBuilder<ServerConfig, WantsServerCert> {
@@ -96,25 +136,93 @@ impl ConfigBuilder<ServerConfig, WantsServerCert> {
         Ok(self.with_cert_resolver(Arc::new(resolver)))
     }
 
+    #[cfg(feature = "ring")]
+    /// Identify the server with a raw public key ([RFC 7250]) rather than a
+    /// certificate chain.  This key is used for all subsequent connections.
+    ///
+    /// `spki` is the DER-encoded `SubjectPublicKeyInfo` of the public key.
+    /// `key_der` is a DER-encoded RSA, ECDSA, or Ed25519 private key.
+    /// Only TLS1.3 clients that offer raw public keys can connect: see
+    /// [`ServerConfig::raw_public_keys`].
+    ///
+    /// This function fails if `key_der` is invalid.
+    ///
+    /// [RFC 7250]: https://www.rfc-editor.org/rfc/rfc7250
+    pub fn with_raw_public_key(
+        self,
+        spki: Vec<u8>,
+        key_der: PrivateKeyDer<'static>,
+    ) -> Result<ServerConfig, Error> {
+        let mut config = self.with_single_cert(vec![CertificateDer::from(spki)], key_der)?;
+        config.raw_public_keys = true;
+        Ok(config)
+    }
+
+    /// Keep the certificate resolver of the configuration given to
+    /// [`ServerConfig::to_builder`].
+    ///
+    /// Use this to change other settings of an existing configuration,
+    /// without re-supplying its certificates.  A builder that did not come
+    /// from `to_builder` has no existing resolver, so the resulting
+    /// configuration has no certificates and every handshake fails.
+    pub fn with_existing_cert_resolver(self) -> ServerConfig {
+        let cert_resolver = match &self.state.base {
+            Some(base) => Arc::clone(&base.cert_resolver),
+            None => Arc::new(handy::ResolvesServerCertUsingSni::new()),
+        };
+        self.with_cert_resolver(cert_resolver)
+    }
+
     /// Sets a custom [`ResolvesServerCert`].
     pub fn with_cert_resolver(self, cert_resolver: Arc<dyn ResolvesServerCert>) -> ServerConfig {
+        if let Some(base) = self.state.base {
+            return ServerConfig {
+                cipher_suites: self.state.cipher_suites,
+                kx_groups: self.state.kx_groups,
+                provider: self.state.provider,
+                verifier: self.state.verifier,
+                cert_resolver,
+                versions: self.state.versions,
+                ..*base
+            };
+        }
+
         ServerConfig {
             cipher_suites: self.state.cipher_suites,
             kx_groups: self.state.kx_groups,
             provider: self.state.provider,
             verifier: self.state.verifier,
             cert_resolver,
+            signing_retry_policy: SigningRetryPolicy::NONE,
+            raw_public_keys: false,
+            client_cert_verifier_resolver: None,
             ignore_client_order: false,
+            cipher_suite_policy: None,
+            max_client_hello_entries: 512,
             max_fragment_size: None,
+            record_padding_policy: RecordPaddingPolicy::None,
+            aead_limit_policy: AeadLimitPolicy::KeyUpdate,
+            premature_data_policy: PrematureDataPolicy::Fail,
+            aead_limit_callback: None,
+            max_connection_age: None,
+            max_bytes_transferred: None,
+            incoming_plaintext_limit: None,
+            outgoing_plaintext_limit: None,
+            fixed_time: None,
+            key_share_reuse_detector: None,
             session_storage: handy::ServerSessionMemoryCache::new(256),
             ticketer: Arc::new(handy::NeverProducesTickets {}),
             alpn_protocols: Vec::new(),
+            alpn_fallback: None,
             versions: self.state.versions,
             key_log: Arc::new(NoKeyLog {}),
             enable_secret_extraction: false,
             max_early_data_size: 0,
+            anti_replay: None,
             send_half_rtt_data: false,
             send_tls13_tickets: 4,
+            allow_tls13_psk_ke_resumption: false,
+            supported_groups_hint: Vec::new(),
         }
     }
 }
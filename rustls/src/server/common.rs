@@ -33,4 +33,9 @@ impl<'a> ActiveCertifiedKey<'a> {
     pub(super) fn get_ocsp(&self) -> Option<&[u8]> {
         self.ocsp
     }
+
+    #[inline]
+    pub(super) fn get_tls13_certificate_extensions(&self) -> &[(u16, Vec<u8>)] {
+        self.key.tls13_certificate_extensions()
+    }
 }
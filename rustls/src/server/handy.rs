@@ -16,6 +16,38 @@ use alloc::sync::Arc;
 use std::collections;
 use std::sync::Mutex;
 
+/// An implementer of `AntiReplay` that remembers the binders of
+/// ClientHellos in memory.
+///
+/// This holds a bounded number of binders, evicting the oldest first, so
+/// only replays within that window are spotted.  To be effective, it should
+/// be sized to cover every ClientHello offering early data received while
+/// their sessions are still fresh.
+pub struct AntiReplayCache {
+    cache: Mutex<limited_cache::LimitedCache<Vec<u8>, ()>>,
+}
+
+impl AntiReplayCache {
+    /// Make a new AntiReplayCache.  `size` is the maximum number of
+    /// binders remembered, and may be rounded-up for efficiency.
+    pub fn new(size: usize) -> Arc<Self> {
+        Arc::new(Self {
+            cache: Mutex::new(limited_cache::LimitedCache::new(size)),
+        })
+    }
+}
+
+impl server::AntiReplay for AntiReplayCache {
+    fn check_and_record(&self, binder: &[u8], _obfuscated_ticket_age: u32) -> bool {
+        let mut cache = self.cache.lock().unwrap();
+        if cache.get(binder).is_some() {
+            return false;
+        }
+        cache.insert(binder.to_vec(), ());
+        true
+    }
+}
+
 /// Something which never stores sessions.
 pub struct NoServerSessionStorage {}
 
@@ -203,6 +235,7 @@ impl server::ResolvesServerCert for ResolvesServerCertUsingSni {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::server::AntiReplay;
     use crate::server::ProducesTickets;
     use crate::server::ResolvesServerCert;
     use crate::server::StoresServerSessions;
@@ -230,6 +263,15 @@ mod tests {
         assert_eq!(c.take(&[0x02]), None);
     }
 
+    #[test]
+    fn test_antireplaycache_refuses_repeats() {
+        let c = AntiReplayCache::new(4);
+        assert!(c.check_and_record(&[0x01], 100));
+        assert!(c.check_and_record(&[0x02], 100));
+        assert!(!c.check_and_record(&[0x01], 100));
+        assert!(!c.check_and_record(&[0x01], 200));
+    }
+
     #[test]
     fn test_serversessionmemorycache_accepts_put() {
         let c = ServerSessionMemoryCache::new(4);
@@ -283,7 +325,7 @@ mod tests {
     fn test_resolvesservercertusingsni_requires_sni() {
         let rscsni = ResolvesServerCertUsingSni::new();
         assert!(rscsni
-            .resolve(ClientHello::new(&None, &[], None, &[]))
+            .resolve(ClientHello::new(&None, &[], None, &[], None))
             .is_none());
     }
 
@@ -294,7 +336,7 @@ mod tests {
             .unwrap()
             .to_owned();
         assert!(rscsni
-            .resolve(ClientHello::new(&Some(name), &[], None, &[]))
+            .resolve(ClientHello::new(&Some(name), &[], None, &[], None))
             .is_none());
     }
 }
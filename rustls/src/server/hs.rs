@@ -8,7 +8,7 @@ use crate::error::{Error, PeerIncompatible, PeerMisbehaved};
 use crate::hash_hs::{HandshakeHash, HandshakeHashBuffer};
 #[cfg(feature = "logging")]
 use crate::log::{debug, trace};
-use crate::msgs::enums::{Compression, ExtensionType};
+use crate::msgs::enums::{CertificateType, Compression, ExtensionType};
 #[cfg(feature = "tls12")]
 use crate::msgs::handshake::SessionId;
 use crate::msgs::handshake::{ClientHelloPayload, Random, ServerExtension};
@@ -84,14 +84,25 @@ impl ExtensionProcessing {
                 return Err(PeerMisbehaved::OfferedEmptyApplicationProtocol.into());
             }
 
-            cx.common.alpn_protocol = our_protocols
+            cx.common.alpn_common_protocols = our_protocols
                 .iter()
-                .find(|protocol| their_protocols.contains(&protocol.as_slice()))
+                .filter(|protocol| their_protocols.contains(&protocol.as_slice()))
+                .cloned()
+                .collect();
+            cx.common.alpn_protocol = cx
+                .common
+                .alpn_common_protocols
+                .first()
                 .cloned();
             if let Some(ref selected_protocol) = cx.common.alpn_protocol {
                 debug!("Chosen ALPN protocol {:?}", selected_protocol);
                 self.exts
                     .push(ServerExtension::make_alpn(&[selected_protocol]));
+            } else if let (Some(fallback), false) = (&config.alpn_fallback, cx.common.is_quic()) {
+                // The client did not offer this, so it cannot be sent back (RFC7301
+                // section 3.2); the client sees no ALPN protocol.
+                debug!("No ALPN match; falling back to {:?}", fallback);
+                cx.common.alpn_protocol = Some(fallback.clone());
             } else if !our_protocols.is_empty() {
                 return Err(cx.common.send_fatal_alert(
                     AlertDescription::NoApplicationProtocol,
@@ -137,6 +148,21 @@ impl ExtensionProcessing {
                 .push(ServerExtension::ServerNameAck);
         }
 
+        // Confirm the type of our identity, if the client asked.
+        if !for_resume
+            && hello
+                .get_server_certificate_types_extension()
+                .is_some()
+        {
+            self.exts
+                .push(ServerExtension::ServerCertificateType(
+                    match config.raw_public_keys {
+                        true => CertificateType::RawPublicKey,
+                        false => CertificateType::X509,
+                    },
+                ));
+        }
+
         // Send status_request response if we have one.  This is not allowed
         // if we're resuming, and is only triggered if we have an OCSP response
         // to send.
@@ -218,7 +244,11 @@ impl ExpectClientHello {
     pub(super) fn new(config: Arc<ServerConfig>, extra_exts: Vec<ServerExtension>) -> Self {
         let mut transcript_buffer = HandshakeHashBuffer::new();
 
-        if config.verifier.offer_client_auth() {
+        if config.verifier.offer_client_auth()
+            || config
+                .client_cert_verifier_resolver
+                .is_some()
+        {
             transcript_buffer.set_client_auth_enabled();
         }
 
@@ -243,6 +273,19 @@ impl ExpectClientHello {
         m: &Message,
         cx: &mut ServerContext<'_>,
     ) -> NextStateOrError {
+        let max_entries = self.config.max_client_hello_entries;
+        if client_hello.cipher_suites.len() > max_entries
+            || client_hello.extensions.len() > max_entries
+            || client_hello
+                .get_namedgroups_extension()
+                .map_or(false, |groups| groups.len() > max_entries)
+        {
+            return Err(cx.common.send_fatal_alert(
+                AlertDescription::IllegalParameter,
+                PeerMisbehaved::TooManyClientHelloEntries,
+            ));
+        }
+
         let tls13_enabled = self
             .config
             .supports_version(ProtocolVersion::TLSv1_3);
@@ -289,6 +332,25 @@ impl ExpectClientHello {
 
         cx.common.negotiated_version = Some(version);
 
+        // Can we present the kind of identity the client accepts?  Raw
+        // public keys are only supported in TLS1.3.
+        let server_cert_types = client_hello.get_server_certificate_types_extension();
+        let usable_cert_type = match (self.config.raw_public_keys, server_cert_types) {
+            (true, Some(types)) => {
+                version == ProtocolVersion::TLSv1_3
+                    && types.contains(&CertificateType::RawPublicKey)
+            }
+            (true, None) => false,
+            (false, Some(types)) => types.contains(&CertificateType::X509),
+            (false, None) => true,
+        };
+        if !usable_cert_type {
+            return Err(cx.common.send_fatal_alert(
+                AlertDescription::UnsupportedCertificate,
+                PeerIncompatible::NoServerCertificateTypesInCommon,
+            ));
+        }
+
         // We communicate to the upper layer what kind of key they should choose
         // via the sigschemes value.  Clients tend to treat this extension
         // orthogonally to offered ciphersuites (even though, in TLS1.2 it is not).
@@ -316,6 +378,7 @@ impl ExpectClientHello {
                 &sig_schemes,
                 client_hello.get_alpn_extension(),
                 &client_hello.cipher_suites,
+                client_hello.get_trusted_ca_keys_extension(),
             );
 
             let certkey = self
@@ -332,38 +395,75 @@ impl ExpectClientHello {
         };
         let certkey = ActiveCertifiedKey::from_certified_key(&certkey);
 
+        // Choose a client certificate verifier.
+        cx.data.client_cert_verifier = self
+            .config
+            .client_cert_verifier_resolver
+            .as_ref()
+            .and_then(|resolver| {
+                resolver.resolve(ClientHello::new(
+                    &cx.data.sni,
+                    &sig_schemes,
+                    client_hello.get_alpn_extension(),
+                    &client_hello.cipher_suites,
+                    client_hello.get_trusted_ca_keys_extension(),
+                ))
+            });
+
         // Reduce our supported ciphersuites by the certificate.
         // (no-op for TLS1.3)
         let suitable_suites =
             suites::reduce_given_sigalg(&self.config.cipher_suites, certkey.get_key().algorithm());
 
         // And version
-        let suitable_suites = suites::reduce_given_version(&suitable_suites, version);
+        let mut suitable_suites = suites::reduce_given_version(&suitable_suites, version);
 
-        let suite = if self.config.ignore_client_order {
-            suites::choose_ciphersuite_preferring_server(
-                &client_hello.cipher_suites,
-                &suitable_suites,
-            )
-        } else {
-            suites::choose_ciphersuite_preferring_client(
+        let suite = loop {
+            let suite = if self.config.ignore_client_order {
+                suites::choose_ciphersuite_preferring_server(
+                    &client_hello.cipher_suites,
+                    &suitable_suites,
+                )
+            } else {
+                suites::choose_ciphersuite_preferring_client(
+                    &client_hello.cipher_suites,
+                    &suitable_suites,
+                )
+            }
+            .ok_or_else(|| {
+                cx.common.send_fatal_alert(
+                    AlertDescription::HandshakeFailure,
+                    PeerIncompatible::NoCipherSuitesInCommon,
+                )
+            })?;
+
+            let policy = match &self.config.cipher_suite_policy {
+                Some(policy) => policy,
+                None => break suite,
+            };
+
+            let client_hello = ClientHello::new(
+                &cx.data.sni,
+                &sig_schemes,
+                client_hello.get_alpn_extension(),
                 &client_hello.cipher_suites,
-                &suitable_suites,
-            )
-        }
-        .ok_or_else(|| {
-            cx.common.send_fatal_alert(
-                AlertDescription::HandshakeFailure,
-                PeerIncompatible::NoCipherSuitesInCommon,
-            )
-        })?;
+                client_hello.get_trusted_ca_keys_extension(),
+            );
+
+            if policy(&suite, &client_hello) {
+                break suite;
+            }
+
+            debug!("cipher suite policy rejected {:?}", suite);
+            suitable_suites.retain(|scs| *scs != suite);
+        };
 
         debug!("decided upon suite {:?}", suite);
         cx.common.suite = Some(suite);
 
         // Start handshake hash.
         let starting_hash = suite.hash_provider();
-        let transcript = match self.transcript {
+        let mut transcript = match self.transcript {
             HandshakeHashOrBuffer::Buffer(inner) => inner.start_hash(starting_hash),
             HandshakeHashOrBuffer::Hash(inner)
                 if inner.algorithm() == starting_hash.algorithm() =>
@@ -378,20 +478,33 @@ impl ExpectClientHello {
             }
         };
 
+        if !cx
+            .data
+            .client_cert_verifier(&self.config)
+            .offer_client_auth()
+        {
+            transcript.abandon_client_auth();
+        }
+
         // Save their Random.
         let randoms =
             ConnectionRandoms::new(client_hello.random, Random::new(self.config.provider)?);
         match suite {
-            SupportedCipherSuite::Tls13(suite) => tls13::CompleteClientHelloHandling {
-                config: self.config,
-                transcript,
-                suite,
-                randoms,
-                done_retry: self.done_retry,
-                send_tickets: self.send_tickets,
-                extra_exts: self.extra_exts,
+            SupportedCipherSuite::Tls13(suite) => {
+                // TLS1.3 client auth signs the transcript hash only.
+                transcript.abandon_client_auth();
+
+                tls13::CompleteClientHelloHandling {
+                    config: self.config,
+                    transcript,
+                    suite,
+                    randoms,
+                    done_retry: self.done_retry,
+                    send_tickets: self.send_tickets,
+                    extra_exts: self.extra_exts,
+                }
+                .handle_client_hello(cx, certkey, m, client_hello, sig_schemes)
             }
-            .handle_client_hello(cx, certkey, m, client_hello, sig_schemes),
             #[cfg(feature = "tls12")]
             SupportedCipherSuite::Tls12(suite) => tls12::CompleteClientHelloHandling {
                 config: self.config,
@@ -1,27 +1,34 @@
 use crate::builder::{ConfigBuilder, WantsCipherSuites};
-use crate::common_state::{CommonState, Context, Side, State};
+use crate::common_state::{CommonState, Context, PrematureDataPolicy, Side, State};
 use crate::conn::{ConnectionCommon, ConnectionCore};
 use crate::crypto::{CryptoProvider, SupportedKxGroup};
 use crate::dns_name::DnsName;
 use crate::enums::{CipherSuite, ProtocolVersion, SignatureScheme};
 use crate::error::Error;
+use crate::key_share_reuse::KeyShareReuseDetector;
 #[cfg(feature = "logging")]
 use crate::log::trace;
 use crate::msgs::base::Payload;
-use crate::msgs::handshake::{ClientHelloPayload, ProtocolName, ServerExtension};
+use crate::msgs::enums::NamedGroup;
+use crate::msgs::handshake::{ClientHelloPayload, ProtocolName, ServerExtension, TrustedAuthority};
 use crate::msgs::message::Message;
+use crate::record_layer::{AeadLimitCallback, AeadLimitPolicy, RecordPaddingPolicy};
 use crate::sign;
-use crate::suites::{ExtractedSecrets, SupportedCipherSuite};
+use crate::suites::{self, ExtractedSecrets, SupportedCipherSuite};
 use crate::vecbuf::ChunkVecBuffer;
-use crate::verify;
+use crate::verify::ClientCertVerifier;
 use crate::KeyLog;
 
+use super::builder::WantsServerCert;
 use super::hs;
 
+use pki_types::UnixTime;
+
 use alloc::sync::Arc;
 use core::fmt;
 use core::marker::PhantomData;
 use core::ops::{Deref, DerefMut};
+use core::time::Duration;
 use std::io;
 
 /// A trait for the ability to store server session data.
@@ -42,6 +49,29 @@ use std::io;
 /// in the type system to allow implementations freedom in
 /// how to achieve interior mutability.  `Mutex` is a common
 /// choice.
+///
+/// # Sharing sessions between servers
+///
+/// To allow clients to resume sessions on any server in a
+/// load-balanced group, each server can use an implementation of
+/// this trait that is backed by a shared, external cache (such as
+/// Redis or memcached).  [`ServerSessionMemoryCache`] is only
+/// suitable for a single server.
+///
+/// The key is the TLS1.2 session ID, or the TLS1.3 PSK identity.
+/// The value is rustls' own encoding of the secret session state: store
+/// it verbatim, and only share it between servers running compatible
+/// versions of rustls.  Values that cannot be decoded are ignored, leading
+/// to a full handshake.  As noted above, the value contains enough key
+/// material to break the session, so protect it accordingly in transit
+/// and at rest.
+///
+/// TLS1.3 sessions are single-use, and are retrieved with `take`.  In a
+/// shared cache this must remove the value atomically (for example, with
+/// Redis' `GETDEL`), so that two servers cannot both resume the same
+/// session.
+///
+/// [`ServerSessionMemoryCache`]: crate::server::ServerSessionMemoryCache
 pub trait StoresServerSessions: Send + Sync {
     /// Store session secrets encoded in `value` against `key`,
     /// overwrites any existing value against `key`.  Returns `true`
@@ -95,6 +125,32 @@ pub trait ProducesTickets: Send + Sync {
     fn decrypt(&self, cipher: &[u8]) -> Option<Vec<u8>>;
 }
 
+/// Spots replayed ClientHellos, so their early data can be refused.
+///
+/// Early data has no protection against replay by the TLS protocol itself:
+/// an attacker can resend a captured ClientHello, and its early data will be
+/// accepted again if the resumed session is still usable.  Tickets in a
+/// single [`StoresServerSessions`] are single-use, but sessions shared between
+/// servers may not be, so this can act as a "strike register" (see
+/// [RFC8446 section 8.2]).
+///
+/// The binder of the chosen PSK identity is unique to each ClientHello,
+/// since it covers the ClientHello's random value and the identity's
+/// obfuscated ticket age.  [`AntiReplayCache`] is an in-memory
+/// implementation.
+///
+/// [RFC8446 section 8.2]: https://www.rfc-editor.org/rfc/rfc8446#section-8.2
+/// [`AntiReplayCache`]: crate::server::AntiReplayCache
+pub trait AntiReplay: Send + Sync {
+    /// Record the binder and obfuscated ticket age of the PSK identity
+    /// chosen from a ClientHello whose early data is otherwise acceptable.
+    ///
+    /// Return `true` if this ClientHello has not been seen before.  `false`
+    /// means it is a replay: its early data is rejected, and the handshake
+    /// carries on without it.
+    fn check_and_record(&self, binder: &[u8], obfuscated_ticket_age: u32) -> bool;
+}
+
 /// How to choose a certificate chain and signing key for use
 /// in server authentication.
 ///
@@ -112,12 +168,30 @@ pub trait ResolvesServerCert: Send + Sync {
     fn resolve(&self, client_hello: ClientHello) -> Option<Arc<sign::CertifiedKey>>;
 }
 
+/// How to choose a client certificate verifier for a connection.
+///
+/// This allows, for example, trusting a different set of client
+/// certificate issuers for each server name.
+pub trait ResolvesClientCertVerifier: Send + Sync {
+    /// Choose a client certificate verifier given simplified
+    /// ClientHello information.
+    ///
+    /// Return `None` to use the verifier the [`ServerConfig`] was built with.
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<dyn ClientCertVerifier>>;
+}
+
+/// A callback deciding whether the server may select a cipher suite.
+///
+/// See [`ServerConfig::cipher_suite_policy`].
+pub type CipherSuitePolicy = dyn Fn(&SupportedCipherSuite, &ClientHello) -> bool + Send + Sync;
+
 /// A struct representing the received Client Hello
 pub struct ClientHello<'a> {
     server_name: &'a Option<DnsName>,
     signature_schemes: &'a [SignatureScheme],
     alpn: Option<&'a Vec<ProtocolName>>,
     cipher_suites: &'a [CipherSuite],
+    trusted_ca_keys: Option<&'a [TrustedAuthority]>,
 }
 
 impl<'a> ClientHello<'a> {
@@ -127,17 +201,20 @@ impl<'a> ClientHello<'a> {
         signature_schemes: &'a [SignatureScheme],
         alpn: Option<&'a Vec<ProtocolName>>,
         cipher_suites: &'a [CipherSuite],
+        trusted_ca_keys: Option<&'a [TrustedAuthority]>,
     ) -> Self {
         trace!("sni {:?}", server_name);
         trace!("sig schemes {:?}", signature_schemes);
         trace!("alpn protocols {:?}", alpn);
         trace!("cipher suites {:?}", cipher_suites);
+        trace!("trusted ca keys {:?}", trusted_ca_keys);
 
         ClientHello {
             server_name,
             signature_schemes,
             alpn,
             cipher_suites,
+            trusted_ca_keys,
         }
     }
 
@@ -186,6 +263,17 @@ impl<'a> ClientHello<'a> {
     pub fn cipher_suites(&self) -> &[CipherSuite] {
         self.cipher_suites
     }
+
+    /// Get the certificate authorities the client trusts, from the
+    /// [RFC 6066] `trusted_ca_keys` extension.
+    ///
+    /// Returns `None` if the client did not send this extension.  A resolver
+    /// can use it to choose a certificate chain the client is able to verify.
+    ///
+    /// [RFC 6066]: https://datatracker.ietf.org/doc/html/rfc6066#section-6
+    pub fn trusted_ca_keys(&self) -> Option<&'a [TrustedAuthority]> {
+        self.trusted_ca_keys
+    }
 }
 
 /// Common configuration for a set of server sessions.
@@ -198,11 +286,39 @@ impl<'a> ClientHello<'a> {
 ///
 /// # Defaults
 ///
+/// * [`ServerConfig::cipher_suite_policy`]: the default is `None` -- any mutually
+///   supported suite may be chosen.
+/// * [`ServerConfig::max_client_hello_entries`]: 512 entries in each list.
 /// * [`ServerConfig::max_fragment_size`]: the default is `None` (meaning 16kB).
+/// * [`ServerConfig::record_padding_policy`]: records are not padded.
+/// * [`ServerConfig::aead_limit_policy`]: TLS1.3 write keys are updated when they
+///   reach their confidentiality limit.
+/// * [`ServerConfig::aead_limit_callback`]: `None`; nothing is told when a key
+///   reaches its limit.
+/// * [`ServerConfig::premature_data_policy`]: application data received before the
+///   handshake completes is an error.
+/// * [`ServerConfig::max_connection_age`] and [`ServerConfig::max_bytes_transferred`]:
+///   connections are not limited.
+/// * [`ServerConfig::fixed_time`]: `None`; the system clock is used.
+/// * [`ServerConfig::key_share_reuse_detector`]: `None`; key share reuse is not looked for.
+/// * [`ServerConfig::signing_retry_policy`]: a failed signature is not retried.
+/// * [`ServerConfig::incoming_plaintext_limit`] and [`ServerConfig::outgoing_plaintext_limit`]:
+///   `None`, leaving [`CommonState::set_buffer_limit`] and the 16kB received
+///   plaintext limit in charge.
 /// * [`ServerConfig::session_storage`]: the default stores 256 sessions in memory.
 /// * [`ServerConfig::alpn_protocols`]: the default is empty -- no ALPN protocol is negotiated.
+/// * [`ServerConfig::alpn_fallback`]: `None`; a client offering no common protocol is refused.
 /// * [`ServerConfig::key_log`]: key material is not logged.
 /// * [`ServerConfig::send_tls13_tickets`]: 4 tickets are sent.
+/// * [`ServerConfig::allow_tls13_psk_ke_resumption`]: `false`; TLS1.3 sessions
+///   are only resumed with an (EC)DHE key exchange.
+/// * [`ServerConfig::anti_replay`]: `None`; replayed early data is refused only
+///   because TLS1.3 sessions are single-use.
+/// * [`ServerConfig::supported_groups_hint`]: the default is empty -- no
+///   key exchange groups are advertised.
+/// * [`ServerConfig::raw_public_keys`]: `false`; certificate chains are sent.
+/// * [`ServerConfig::client_cert_verifier_resolver`]: `None`; every connection
+///   uses the verifier the config was built with.
 ///
 /// [`RootCertStore`]: crate::RootCertStore
 pub struct ServerConfig {
@@ -223,6 +339,25 @@ pub struct ServerConfig {
     /// which is supported by the client.
     pub ignore_client_order: bool,
 
+    /// Lets the application veto the cipher suite the server is about to choose.
+    ///
+    /// This is called, in preference order, with each acceptable suite that the
+    /// client offered, until it returns `true`; that suite is then used.  If it
+    /// rejects them all, the handshake fails with a `handshake_failure` alert.
+    pub cipher_suite_policy: Option<Arc<CipherSuitePolicy>>,
+
+    /// The most entries accepted in each list of a client's `ClientHello`.
+    ///
+    /// This bounds the offered cipher suites, the offered key exchange
+    /// groups and the extensions, so that a client cannot make the server
+    /// search through thousands of (perhaps duplicated) entries.  A
+    /// `ClientHello` with a longer list is refused with an
+    /// `illegal_parameter` alert before a cipher suite or certificate is
+    /// chosen.
+    ///
+    /// The default is 512.
+    pub max_client_hello_entries: usize,
+
     /// The maximum size of plaintext input to be emitted in a single TLS record.
     /// A value of None is equivalent to the [TLS maximum] of 16 kB.
     ///
@@ -236,6 +371,79 @@ pub struct ServerConfig {
     /// [ServerConnection::new]: crate::server::ServerConnection::new
     pub max_fragment_size: Option<usize>,
 
+    /// How to pad outgoing TLS1.3 application data records, to obscure
+    /// the length of the data they carry.
+    pub record_padding_policy: RecordPaddingPolicy,
+
+    /// What to do when a TLS1.3 write key reaches the confidentiality
+    /// limit of its AEAD algorithm.
+    pub aead_limit_policy: AeadLimitPolicy,
+
+    /// Told whenever a TLS1.3 write key reaches the confidentiality
+    /// limit of its AEAD algorithm.
+    pub aead_limit_callback: Option<Arc<dyn AeadLimitCallback>>,
+
+    /// What to do with application data the client sends before the
+    /// handshake completes, other than accepted
+    /// early data.
+    pub premature_data_policy: PrematureDataPolicy,
+
+    /// The longest a connection may live before [`ServerConnection::process_new_packets`]
+    /// fails with [`Error::ConnectionAgeLimitExceeded`].
+    ///
+    /// Time is supplied by the application via [`CommonState::set_current_time`];
+    /// rustls does not consult the clock for this check.
+    ///
+    /// [`ServerConnection::process_new_packets`]: crate::ConnectionCommon::process_new_packets
+    pub max_connection_age: Option<Duration>,
+
+    /// The most TLS bytes a connection may read and write in total before
+    /// [`ServerConnection::process_new_packets`] fails with
+    /// [`Error::BytesTransferredLimitExceeded`].
+    ///
+    /// This lets applications force a fresh handshake (and fresh keys) by
+    /// reconnecting.
+    ///
+    /// [`ServerConnection::process_new_packets`]: crate::ConnectionCommon::process_new_packets
+    pub max_bytes_transferred: Option<u64>,
+
+    /// The most received plaintext to hold for the application.
+    ///
+    /// Once this much is waiting to be read, [`ServerConnection::process_new_packets`]
+    /// stops decrypting further records (leaving them buffered) and `read_tls`
+    /// refuses more data, until the application drains the [`Reader`].  Call
+    /// `process_new_packets` again after that: `wants_read` stays false while
+    /// records are left buffered.  `complete_io` and [`Stream`] do this.
+    ///
+    /// [`ServerConnection::process_new_packets`]: crate::ConnectionCommon::process_new_packets
+    /// [`Reader`]: crate::Reader
+    /// [`Stream`]: crate::Stream
+    pub incoming_plaintext_limit: Option<usize>,
+
+    /// The most data to queue for sending, independently of
+    /// `incoming_plaintext_limit`.
+    ///
+    /// Queued plaintext and TLS records both count.  Once this is reached,
+    /// writes to the [`Writer`] fail with [`std::io::ErrorKind::WouldBlock`]
+    /// until [`ServerConnection::write_tls`] makes space.
+    ///
+    /// [`Writer`]: crate::Writer
+    /// [`ServerConnection::write_tls`]: crate::ConnectionCommon::write_tls
+    pub outgoing_plaintext_limit: Option<usize>,
+
+    /// A fixed time to use instead of the system clock.
+    ///
+    /// When set, every connection made from this config uses this time when
+    /// validating the client's certificate, and for ticket freshness checks and session timestamps.
+    /// This is intended for reproducible tests and replay.
+    pub fixed_time: Option<UnixTime>,
+
+    /// Watches for the client sending a key share it has used before.
+    ///
+    /// Sharing one detector between several configs looks for reuse across
+    /// all of their connections.
+    pub key_share_reuse_detector: Option<Arc<KeyShareReuseDetector>>,
+
     /// How to store client sessions.
     pub session_storage: Arc<dyn StoresServerSessions + Send + Sync>,
 
@@ -247,16 +455,51 @@ pub struct ServerConfig {
     /// For async applications, see also [Acceptor].
     pub cert_resolver: Arc<dyn ResolvesServerCert>,
 
+    /// How many times to attempt signing with the server's key.
+    pub signing_retry_policy: sign::SigningRetryPolicy,
+
+    /// Whether `cert_resolver` provides raw public keys ([RFC 7250]) rather
+    /// than certificate chains.
+    ///
+    /// If true, the "chain" of each resolved [`CertifiedKey`] is a single
+    /// DER-encoded `SubjectPublicKeyInfo`, and only TLS1.3 clients offering
+    /// the `RawPublicKey` server certificate type can connect.  Otherwise,
+    /// clients that offer only raw public keys are refused.  This is usually
+    /// set by [ConfigBuilder::with_raw_public_key].  The default is false.
+    ///
+    /// [RFC 7250]: https://www.rfc-editor.org/rfc/rfc7250
+    /// [`CertifiedKey`]: crate::sign::CertifiedKey
+    pub raw_public_keys: bool,
+
     /// Protocol names we support, most preferred first.
     /// If empty we don't do ALPN at all.
     pub alpn_protocols: Vec<Vec<u8>>,
 
+    /// The protocol to assume when the client offers ALPN protocols, but none of
+    /// them are in [`ServerConfig::alpn_protocols`].
+    ///
+    /// If `None`, the handshake fails with a `no_application_protocol` alert.
+    ///
+    /// Otherwise the handshake continues and [`ServerConnection::alpn_protocol`]
+    /// returns this protocol.  It is not sent to the client, as only an offered
+    /// protocol may be selected, so the client sees no ALPN protocol.  This is
+    /// not used for QUIC, which requires ALPN to succeed.
+    ///
+    /// [`ServerConnection::alpn_protocol`]: crate::CommonState::alpn_protocol
+    pub alpn_fallback: Option<Vec<u8>>,
+
     /// Supported protocol versions, in no particular order.
     /// The default is all supported versions.
     pub(super) versions: crate::versions::EnabledVersions,
 
     /// How to verify client certificates.
-    pub(super) verifier: Arc<dyn verify::ClientCertVerifier>,
+    pub(super) verifier: Arc<dyn ClientCertVerifier>,
+
+    /// How to choose a client certificate verifier per connection, in
+    /// place of the one this config was built with.
+    ///
+    /// The default is `None`: every connection uses the config's verifier.
+    pub client_cert_verifier_resolver: Option<Arc<dyn ResolvesClientCertVerifier>>,
 
     /// How to output key material for debugging.  The default
     /// does nothing.
@@ -279,6 +522,15 @@ pub struct ServerConfig {
     /// expansion in the latter case.
     pub max_early_data_size: u32,
 
+    /// Consulted before accepting early data, to refuse it from replayed
+    /// ClientHellos.  The default is `None`, relying on sessions being
+    /// single-use.
+    ///
+    /// See [`AntiReplay`] and [`AntiReplayCache`].
+    ///
+    /// [`AntiReplayCache`]: crate::server::AntiReplayCache
+    pub anti_replay: Option<Arc<dyn AntiReplay>>,
+
     /// Whether the server should send "0.5RTT" data.  This means the server
     /// sends data after its first flight of handshake messages, without
     /// waiting for the client to complete the handshake.
@@ -290,6 +542,12 @@ pub struct ServerConfig {
     /// `ClientHello` is indirectly authenticated because it is included
     /// in the transcript used to derive the keys used to encrypt the data.
     ///
+    /// When enabled, data written to [`ConnectionCommon::writer()`][crate::ConnectionCommon::writer] at any
+    /// point after the server's `Finished` is encrypted straight away and
+    /// sent in the same flight.  At that point the server has not yet seen
+    /// the client's `Finished`, so it knows nothing about who it is talking
+    /// to: 0.5-RTT data must be fit to send to any anonymous client.
+    ///
     /// This only applies to TLS1.3 connections.  TLS1.2 connections cannot
     /// do this optimisation and this setting is ignored for them.  It is
     /// also ignored for TLS1.3 connections that even attempt client
@@ -311,6 +569,32 @@ pub struct ServerConfig {
     /// If this is 0, no tickets are sent and clients will not be able to
     /// do any resumption.
     pub send_tls13_tickets: usize,
+
+    /// Whether to resume TLS1.3 sessions in the `psk_ke` mode, for clients
+    /// that offer only that mode.
+    ///
+    /// Such a resumption performs no (EC)DHE key exchange: its keys are derived
+    /// from the resumption secret alone.  Anyone who later learns that secret,
+    /// for example by compromising the [`ServerConfig::ticketer`] keys, can
+    /// decrypt the connection.  There is no forward secrecy.
+    ///
+    /// Clients that also offer `psk_dhe_ke` resume with a key exchange
+    /// regardless of this setting.  If this is `false`, a client offering
+    /// only `psk_ke` gets a full handshake.
+    ///
+    /// The default is `false`.
+    pub allow_tls13_psk_ke_resumption: bool,
+
+    /// Key exchange groups to advertise to TLS1.3 clients, most preferred first.
+    ///
+    /// These are sent in the `supported_groups` extension of the server's
+    /// `EncryptedExtensions`, so that a client can offer a key share for one
+    /// of them when it next connects, avoiding a `HelloRetryRequest`.  They
+    /// do not affect the group chosen for the current connection, and
+    /// clients are free to ignore them.
+    ///
+    /// The default is empty: the extension is not sent.
+    pub supported_groups_hint: Vec<NamedGroup>,
 }
 
 // Avoid a `Clone` bound on `C`.
@@ -321,18 +605,39 @@ impl Clone for ServerConfig {
             kx_groups: self.kx_groups.clone(),
             provider: self.provider,
             ignore_client_order: self.ignore_client_order,
+            cipher_suite_policy: self.cipher_suite_policy.clone(),
+            max_client_hello_entries: self.max_client_hello_entries,
             max_fragment_size: self.max_fragment_size,
+            record_padding_policy: self.record_padding_policy,
+            aead_limit_policy: self.aead_limit_policy,
+            premature_data_policy: self.premature_data_policy,
+            aead_limit_callback: self.aead_limit_callback.clone(),
+            max_connection_age: self.max_connection_age,
+            max_bytes_transferred: self.max_bytes_transferred,
+            incoming_plaintext_limit: self.incoming_plaintext_limit,
+            outgoing_plaintext_limit: self.outgoing_plaintext_limit,
+            fixed_time: self.fixed_time,
+            key_share_reuse_detector: self.key_share_reuse_detector.clone(),
             session_storage: Arc::clone(&self.session_storage),
             ticketer: Arc::clone(&self.ticketer),
             cert_resolver: Arc::clone(&self.cert_resolver),
+            signing_retry_policy: self.signing_retry_policy,
+            raw_public_keys: self.raw_public_keys,
             alpn_protocols: self.alpn_protocols.clone(),
+            alpn_fallback: self.alpn_fallback.clone(),
             versions: self.versions,
             verifier: Arc::clone(&self.verifier),
+            client_cert_verifier_resolver: self
+                .client_cert_verifier_resolver
+                .clone(),
             key_log: Arc::clone(&self.key_log),
             enable_secret_extraction: self.enable_secret_extraction,
             max_early_data_size: self.max_early_data_size,
+            anti_replay: self.anti_replay.clone(),
             send_half_rtt_data: self.send_half_rtt_data,
             send_tls13_tickets: self.send_tls13_tickets,
+            allow_tls13_psk_ke_resumption: self.allow_tls13_psk_ke_resumption,
+            supported_groups_hint: self.supported_groups_hint.clone(),
         }
     }
 }
@@ -341,15 +646,48 @@ impl fmt::Debug for ServerConfig {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("ServerConfig")
             .field("ignore_client_order", &self.ignore_client_order)
+            .field("max_client_hello_entries", &self.max_client_hello_entries)
             .field("max_fragment_size", &self.max_fragment_size)
+            .field("record_padding_policy", &self.record_padding_policy)
+            .field("aead_limit_policy", &self.aead_limit_policy)
+            .field("premature_data_policy", &self.premature_data_policy)
+            .field("max_connection_age", &self.max_connection_age)
+            .field("max_bytes_transferred", &self.max_bytes_transferred)
+            .field("incoming_plaintext_limit", &self.incoming_plaintext_limit)
+            .field("outgoing_plaintext_limit", &self.outgoing_plaintext_limit)
+            .field("fixed_time", &self.fixed_time)
+            .field("key_share_reuse_detector", &self.key_share_reuse_detector)
+            .field("signing_retry_policy", &self.signing_retry_policy)
+            .field("raw_public_keys", &self.raw_public_keys)
             .field("alpn_protocols", &self.alpn_protocols)
+            .field("alpn_fallback", &self.alpn_fallback)
             .field("max_early_data_size", &self.max_early_data_size)
             .field("send_half_rtt_data", &self.send_half_rtt_data)
             .field("send_tls13_tickets", &self.send_tls13_tickets)
+            .field(
+                "allow_tls13_psk_ke_resumption",
+                &self.allow_tls13_psk_ke_resumption,
+            )
+            .field("supported_groups_hint", &self.supported_groups_hint)
             .finish_non_exhaustive()
     }
 }
 
+/// The signature schemes offered by [`ServerConfig::probe_resolver`].
+static PROBE_SIGNATURE_SCHEMES: &[SignatureScheme] = &[
+    SignatureScheme::ECDSA_NISTP384_SHA384,
+    SignatureScheme::ECDSA_NISTP256_SHA256,
+    SignatureScheme::ECDSA_NISTP521_SHA512,
+    SignatureScheme::ED25519,
+    SignatureScheme::ED448,
+    SignatureScheme::RSA_PSS_SHA512,
+    SignatureScheme::RSA_PSS_SHA384,
+    SignatureScheme::RSA_PSS_SHA256,
+    SignatureScheme::RSA_PKCS1_SHA512,
+    SignatureScheme::RSA_PKCS1_SHA384,
+    SignatureScheme::RSA_PKCS1_SHA256,
+];
+
 impl ServerConfig {
     #[cfg(feature = "ring")]
     /// Create builder to build up the server configuration with the default
@@ -373,6 +711,71 @@ impl ServerConfig {
         }
     }
 
+    /// Create a builder that starts from this configuration.
+    ///
+    /// The builder resumes at the point of choosing a server certificate,
+    /// and the new configuration keeps every other setting of this one.
+    /// This suits reloading a certificate without re-stating the rest of
+    /// the configuration.  To vary other settings instead,
+    /// `with_existing_cert_resolver` keeps the certificate this
+    /// configuration has.
+    pub fn to_builder(&self) -> ConfigBuilder<Self, WantsServerCert> {
+        ConfigBuilder::from_config(self)
+    }
+
+    /// Return `true` if this configuration only enables FIPS-approved cryptography.
+    ///
+    /// This requires the [`CryptoProvider`] itself, every enabled cipher suite, and
+    /// every enabled key exchange group to report being FIPS-approved.  Use this
+    /// to check at startup that nothing non-FIPS has slipped into the configuration.
+    pub fn fips(&self) -> bool {
+        self.provider.fips()
+            && self
+                .cipher_suites
+                .iter()
+                .all(|cs| cs.fips())
+            && self
+                .kx_groups
+                .iter()
+                .all(|kx| kx.fips())
+    }
+
+    /// Return `true` if [`ServerConfig::cert_resolver`] produces a certificate
+    /// for a client asking for `server_name`.
+    ///
+    /// The resolver is asked with a synthetic [`ClientHello`] naming
+    /// `server_name`, offering every configured cipher suite and every
+    /// signature scheme usable with them, and no ALPN protocols or trusted CA
+    /// keys.  Use this at startup to check a certificate is available for
+    /// each expected name.  Returns `false` if `server_name` is not a valid
+    /// DNS name.
+    pub fn probe_resolver(&self, server_name: &str) -> bool {
+        let server_name = match DnsName::try_from(server_name.to_string()) {
+            Ok(name) => Some(name),
+            Err(_) => return false,
+        };
+        let signature_schemes = PROBE_SIGNATURE_SCHEMES
+            .iter()
+            .copied()
+            .filter(|scheme| suites::compatible_sigscheme_for_suites(*scheme, &self.cipher_suites))
+            .collect::<Vec<_>>();
+        let cipher_suites = self
+            .cipher_suites
+            .iter()
+            .map(|cs| cs.suite())
+            .collect::<Vec<_>>();
+
+        self.cert_resolver
+            .resolve(ClientHello::new(
+                &server_name,
+                &signature_schemes,
+                None,
+                &cipher_suites,
+                None,
+            ))
+            .is_some()
+    }
+
     /// We support a given TLS version if it's quoted in the configured
     /// versions *and* at least one ciphersuite for this version is
     /// also configured.
@@ -423,9 +826,6 @@ impl ServerConnection {
     /// Make a new ServerConnection.  `config` controls how
     /// we behave in the TLS protocol.
     pub fn new(config: Arc<ServerConfig>) -> Result<Self, Error> {
-        let mut common = CommonState::new(Side::Server);
-        common.set_max_fragment_size(config.max_fragment_size)?;
-        common.enable_secret_extraction = config.enable_secret_extraction;
         Ok(Self {
             inner: ConnectionCommon::from(ConnectionCore::for_server(config, Vec::new())?),
         })
@@ -450,6 +850,23 @@ impl ServerConnection {
         self.inner.core.get_sni_str()
     }
 
+    /// Returns true if the client presented a certificate chain which was
+    /// accepted by the configured [`ClientCertVerifier`], along with a valid
+    /// `CertificateVerify` signature.
+    ///
+    /// This is false for anonymous clients: whether client authentication
+    /// was not offered, or was optional and the client declined it.  For a
+    /// resumed session it reflects the authentication done in the original
+    /// handshake.
+    ///
+    /// [`ClientCertVerifier`]: crate::server::danger::ClientCertVerifier
+    pub fn client_authenticated(&self) -> bool {
+        self.inner
+            .core
+            .data
+            .client_authenticated
+    }
+
     /// Application-controlled portion of the resumption ticket supplied by the client, if any.
     ///
     /// Recovered from the prior session's `set_resumption_data`. Integrity is guaranteed by rustls.
@@ -464,6 +881,25 @@ impl ServerConnection {
             .map(|x| &x[..])
     }
 
+    /// The PSK identity the client resumed with, if any.
+    ///
+    /// For a TLS1.3 resumption this is the identity the client presented in its `pre_shared_key`
+    /// extension: the encrypted ticket when a [`ProducesTickets`] is configured, or the session
+    /// id issued by the [`StoresServerSessions`] otherwise.  rustls does not support externally
+    /// provisioned PSKs, so the identity always names a previous session.
+    ///
+    /// Returns `None` for full handshakes and for TLS1.2 connections.
+    ///
+    /// [`ProducesTickets`]: crate::server::ProducesTickets
+    /// [`StoresServerSessions`]: crate::server::StoresServerSessions
+    pub fn resumed_psk_identity(&self) -> Option<&[u8]> {
+        self.inner
+            .core
+            .data
+            .resumed_psk_identity
+            .as_deref()
+    }
+
     /// Set the resumption data to embed in future resumption tickets supplied to the client.
     ///
     /// Defaults to the empty byte string. Must be less than 2^15 bytes to allow room for other
@@ -676,6 +1112,7 @@ impl Accepted {
             &self.sig_schemes,
             payload.get_alpn_extension(),
             &payload.cipher_suites,
+            payload.get_trusted_ca_keys_extension(),
         )
     }
 
@@ -685,10 +1122,7 @@ impl Accepted {
     /// [`sign::CertifiedKey`] that should be used for the session. Returns an error if
     /// configuration-dependent validation of the received `ClientHello` message fails.
     pub fn into_connection(mut self, config: Arc<ServerConfig>) -> Result<ServerConnection, Error> {
-        self.connection
-            .set_max_fragment_size(config.max_fragment_size)?;
-
-        self.connection.enable_secret_extraction = config.enable_secret_extraction;
+        configure_common_state(&mut self.connection, &config)?;
 
         let state = hs::ExpectClientHello::new(config, Vec::new());
         let mut cx = hs::ServerContext::from(&mut self.connection);
@@ -786,14 +1220,30 @@ impl EarlyDataState {
     }
 }
 
+/// Apply the parts of `config` that are enforced by the connection's [`CommonState`].
+fn configure_common_state(common: &mut CommonState, config: &ServerConfig) -> Result<(), Error> {
+    common.set_max_fragment_size(config.max_fragment_size)?;
+    common.set_record_padding_policy(config.record_padding_policy, config.provider);
+    common.set_aead_limit_policy(config.aead_limit_policy, config.aead_limit_callback.clone());
+    common.set_premature_data_policy(config.premature_data_policy);
+    common.set_connection_limits(config.max_connection_age, config.max_bytes_transferred);
+    common.set_plaintext_limits(
+        config.incoming_plaintext_limit,
+        config.outgoing_plaintext_limit,
+    );
+    common.fixed_time = config.fixed_time;
+    common.fips_provider = config.provider.fips();
+    common.enable_secret_extraction = config.enable_secret_extraction;
+    Ok(())
+}
+
 impl ConnectionCore<ServerConnectionData> {
     pub(crate) fn for_server(
         config: Arc<ServerConfig>,
         extra_exts: Vec<ServerExtension>,
     ) -> Result<Self, Error> {
         let mut common = CommonState::new(Side::Server);
-        common.set_max_fragment_size(config.max_fragment_size)?;
-        common.enable_secret_extraction = config.enable_secret_extraction;
+        configure_common_state(&mut common, &config)?;
         Ok(Self::new(
             Box::new(hs::ExpectClientHello::new(config, extra_exts)),
             ServerConnectionData::default(),
@@ -819,14 +1269,29 @@ impl ConnectionCore<ServerConnectionData> {
 pub struct ServerConnectionData {
     pub(super) sni: Option<DnsName>,
     pub(super) received_resumption_data: Option<Vec<u8>>,
+    pub(super) resumed_psk_identity: Option<Vec<u8>>,
     pub(super) resumption_data: Vec<u8>,
     pub(super) early_data: EarlyDataState,
+    pub(super) client_authenticated: bool,
+    pub(super) client_cert_verifier: Option<Arc<dyn ClientCertVerifier>>,
 }
 
 impl ServerConnectionData {
     pub(super) fn get_sni_str(&self) -> Option<&str> {
         self.sni.as_ref().map(AsRef::as_ref)
     }
+
+    /// The client certificate verifier chosen for this connection by
+    /// [`ServerConfig::client_cert_verifier_resolver`], or else `config.verifier`.
+    pub(super) fn client_cert_verifier<'a>(
+        &'a self,
+        config: &'a ServerConfig,
+    ) -> &'a dyn ClientCertVerifier {
+        match &self.client_cert_verifier {
+            Some(verifier) => verifier.as_ref(),
+            None => config.verifier.as_ref(),
+        }
+    }
 }
 
 impl crate::conn::SideData for ServerConnectionData {}
@@ -1,7 +1,9 @@
 use crate::check::inappropriate_message;
+#[cfg(feature = "dangerous_transcript")]
+use crate::common_state::TranscriptPoint;
 use crate::common_state::{CommonState, Side, State};
 use crate::conn::ConnectionRandoms;
-use crate::crypto::ActiveKeyExchange;
+use crate::crypto::{ActiveKeyExchange, SupportedKxGroup};
 use crate::enums::ProtocolVersion;
 use crate::enums::{AlertDescription, ContentType, HandshakeType};
 use crate::error::{Error, PeerIncompatible, PeerMisbehaved};
@@ -31,7 +33,6 @@ use alloc::sync::Arc;
 pub(super) use client_hello::CompleteClientHelloHandling;
 
 mod client_hello {
-    use crate::crypto::SupportedKxGroup;
     use crate::enums::SignatureScheme;
     use crate::msgs::enums::ECPointFormat;
     use crate::msgs::enums::{ClientCertificateType, Compression};
@@ -226,6 +227,7 @@ mod client_hello {
                 sigschemes,
                 group,
                 server_key.get_key(),
+                self.config.signing_retry_policy,
                 &self.randoms,
             )?;
             let doing_client_auth = emit_certificate_req(&self.config, &mut self.transcript, cx)?;
@@ -240,6 +242,7 @@ mod client_hello {
                     suite: self.suite,
                     using_ems: self.using_ems,
                     server_kx,
+                    kx_group: group,
                     send_ticket: self.send_ticket,
                 }))
             } else {
@@ -251,6 +254,7 @@ mod client_hello {
                     suite: self.suite,
                     using_ems: self.using_ems,
                     server_kx,
+                    kx_group: group,
                     client_cert: None,
                     send_ticket: self.send_ticket,
                 }))
@@ -300,6 +304,7 @@ mod client_hello {
             );
             cx.common
                 .start_encryption_tls12(&secrets, Side::Server);
+            cx.data.client_authenticated = resumedata.client_cert_chain.is_some();
             cx.common.peer_certificates = resumedata.client_cert_chain;
 
             if self.send_ticket {
@@ -363,6 +368,9 @@ mod client_hello {
 
         trace!("sending server hello {:?}", sh);
         transcript.add_message(&sh);
+        #[cfg(feature = "dangerous_transcript")]
+        cx.common
+            .record_transcript(TranscriptPoint::ServerHello, transcript);
         cx.common.send_msg(sh, false);
         Ok(ep.send_ticket)
     }
@@ -382,6 +390,7 @@ mod client_hello {
 
         transcript.add_message(&c);
         common.send_msg(c, false);
+        common.sent_certificates = Some(cert_chain.to_owned());
     }
 
     fn emit_cert_status(transcript: &mut HandshakeHash, common: &mut CommonState, ocsp: &[u8]) {
@@ -405,6 +414,7 @@ mod client_hello {
         sigschemes: Vec<SignatureScheme>,
         selected_group: &'static dyn SupportedKxGroup,
         signing_key: &dyn sign::SigningKey,
+        retry_policy: sign::SigningRetryPolicy,
         randoms: &ConnectionRandoms,
     ) -> Result<Box<dyn ActiveKeyExchange>, Error> {
         let kx = selected_group
@@ -419,9 +429,11 @@ mod client_hello {
 
         let signer = signing_key
             .choose_scheme(&sigschemes)
+            .filter(|signer| sigschemes.contains(&signer.scheme()))
             .ok_or_else(|| Error::General("incompatible signing key".to_string()))?;
         let sigscheme = signer.scheme();
-        let sig = signer.sign(&msg)?;
+        common.record_signature_scheme(sigscheme);
+        let sig = retry_policy.sign(signer.as_ref(), &msg)?;
 
         let skx = ServerKeyExchangePayload::ECDHE(ECDHEServerKeyExchange {
             params: secdh,
@@ -446,7 +458,7 @@ mod client_hello {
         transcript: &mut HandshakeHash,
         cx: &mut ServerContext<'_>,
     ) -> Result<bool, Error> {
-        let client_auth = &config.verifier;
+        let client_auth = cx.data.client_cert_verifier(config);
 
         if !client_auth.offer_client_auth() {
             return Ok(false);
@@ -454,8 +466,7 @@ mod client_hello {
 
         let verify_schemes = client_auth.supported_verify_schemes();
 
-        let names = config
-            .verifier
+        let names = client_auth
             .client_auth_root_subjects()
             .to_vec();
 
@@ -505,6 +516,7 @@ struct ExpectCertificate {
     suite: &'static Tls12CipherSuite,
     using_ems: bool,
     server_kx: Box<dyn ActiveKeyExchange>,
+    kx_group: &'static dyn SupportedKxGroup,
     send_ticket: bool,
 }
 
@@ -518,9 +530,9 @@ impl State<ServerConnectionData> for ExpectCertificate {
         )?;
 
         // If we can't determine if the auth is mandatory, abort
-        let mandatory = self
-            .config
-            .verifier
+        let mandatory = cx
+            .data
+            .client_cert_verifier(&self.config)
             .client_auth_mandatory();
 
         trace!("certs {:?}", cert_chain);
@@ -538,9 +550,9 @@ impl State<ServerConnectionData> for ExpectCertificate {
                 None
             }
             Some((end_entity, intermediates)) => {
-                self.config
-                    .verifier
-                    .verify_client_cert(end_entity, intermediates, UnixTime::now())
+                cx.data
+                    .client_cert_verifier(&self.config)
+                    .verify_client_cert(end_entity, intermediates, cx.common.now())
                     .map_err(|err| {
                         cx.common
                             .send_cert_verify_error_alert(err)
@@ -558,6 +570,7 @@ impl State<ServerConnectionData> for ExpectCertificate {
             suite: self.suite,
             using_ems: self.using_ems,
             server_kx: self.server_kx,
+            kx_group: self.kx_group,
             client_cert,
             send_ticket: self.send_ticket,
         }))
@@ -573,6 +586,7 @@ struct ExpectClientKx {
     suite: &'static Tls12CipherSuite,
     using_ems: bool,
     server_kx: Box<dyn ActiveKeyExchange>,
+    kx_group: &'static dyn SupportedKxGroup,
     client_cert: Option<Vec<CertificateDer<'static>>>,
     send_ticket: bool,
 }
@@ -593,6 +607,14 @@ impl State<ServerConnectionData> for ExpectClientKx {
         // resulting premaster secret.
         let peer_kx_params =
             tls12::decode_ecdh_params::<ClientECDHParams>(cx.common, &client_kx.0)?;
+        if let Some(detector) = &self.config.key_share_reuse_detector {
+            detector.check(cx.common, self.server_kx.group(), &peer_kx_params.public.0)?;
+        }
+        cx.common.record_key_shares(
+            self.kx_group,
+            self.server_kx.pub_key(),
+            &peer_kx_params.public.0,
+        );
         let secrets = ConnectionSecrets::from_key_exchange(
             self.server_kx,
             &peer_kx_params.public.0,
@@ -652,23 +674,25 @@ impl State<ServerConnectionData> for ExpectCertificateVerify {
                 HandshakeType::CertificateVerify,
                 HandshakePayload::CertificateVerify
             )?;
+            cx.common
+                .record_signature_scheme(sig.scheme);
 
             match self.transcript.take_handshake_buf() {
                 Some(msgs) => {
                     let certs = &self.client_cert;
-                    self.config
-                        .verifier
+                    cx.data
+                        .client_cert_verifier(&self.config)
                         .verify_tls12_signature(&msgs, &certs[0], sig)
                 }
                 None => {
-                    // This should be unreachable; the handshake buffer was initialized with
-                    // client authentication if the verifier wants to offer it.
-                    // `transcript.abandon_client_auth()` can extract it, but its only caller in
-                    // this flow will also set `ExpectClientKx::client_cert` to `None`, making it
-                    // impossible to reach this state.
+                    // The handshake buffer was initialized with client authentication if
+                    // the verifier wants to offer it, and `transcript.abandon_client_auth()`
+                    // is only called in this flow when `ExpectClientKx::client_cert` is set
+                    // to `None`.  So the buffer is missing only if the handshake grew beyond
+                    // `MAX_CLIENT_AUTH_BUFFER`, and was discarded.
                     return Err(cx.common.send_fatal_alert(
-                        AlertDescription::AccessDenied,
-                        Error::General("client authentication not set up".into()),
+                        AlertDescription::HandshakeFailure,
+                        PeerIncompatible::HandshakeTooLargeForClientAuth,
                     ));
                 }
             }
@@ -681,6 +705,7 @@ impl State<ServerConnectionData> for ExpectCertificateVerify {
         }
 
         trace!("client CertificateVerify OK");
+        cx.data.client_authenticated = true;
         cx.common.peer_certificates = Some(self.client_cert);
 
         self.transcript.add_message(&m);
@@ -775,7 +800,7 @@ fn emit_ticket(
     ticketer: &dyn ProducesTickets,
 ) -> Result<(), Error> {
     let plain =
-        get_server_connection_value_tls12(secrets, using_ems, cx, UnixTime::now()).get_encoding();
+        get_server_connection_value_tls12(secrets, using_ems, cx, cx.common.now()).get_encoding();
 
     // If we can't produce a ticket for some reason, we can't
     // report an error. Send an empty one.
@@ -827,6 +852,8 @@ fn emit_finished(
     };
 
     transcript.add_message(&f);
+    #[cfg(feature = "dangerous_transcript")]
+    common.record_transcript(TranscriptPoint::ServerFinished, transcript);
     common.send_msg(f, true);
 }
 
@@ -859,6 +886,9 @@ impl State<ServerConnectionData> for ExpectFinished {
                     .send_fatal_alert(AlertDescription::DecryptError, Error::DecryptError));
             }
         };
+        #[cfg(feature = "dangerous_transcript")]
+        cx.common
+            .record_peer_finished(&finished.0);
 
         // Save connection, perhaps
         if !self.resuming && !self.session_id.is_empty() {
@@ -866,7 +896,7 @@ impl State<ServerConnectionData> for ExpectFinished {
                 &self.secrets,
                 self.using_ems,
                 cx,
-                UnixTime::now(),
+                cx.common.now(),
             );
 
             let worked = self
@@ -882,6 +912,9 @@ impl State<ServerConnectionData> for ExpectFinished {
 
         // Send our CCS and Finished.
         self.transcript.add_message(&m);
+        #[cfg(feature = "dangerous_transcript")]
+        cx.common
+            .record_transcript(TranscriptPoint::ClientFinished, &self.transcript);
         if !self.resuming {
             if self.send_ticket {
                 emit_ticket(
@@ -899,6 +932,8 @@ impl State<ServerConnectionData> for ExpectFinished {
             emit_finished(&self.secrets, &mut self.transcript, cx.common);
         }
 
+        cx.common
+            .record_handshake_hash(self.transcript.get_current_hash());
         cx.common.start_traffic();
         Ok(Box::new(ExpectTraffic {
             secrets: self.secrets,
@@ -938,8 +973,7 @@ impl State<ServerConnectionData> for ExpectTraffic {
         context: Option<&[u8]>,
     ) -> Result<(), Error> {
         self.secrets
-            .export_keying_material(output, label, context);
-        Ok(())
+            .export_keying_material(output, label, context)
     }
 
     fn extract_secrets(&self) -> Result<PartiallyExtractedSecrets, Error> {
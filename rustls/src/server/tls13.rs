@@ -3,6 +3,8 @@ use crate::check::inappropriate_handshake_message;
 use crate::check::inappropriate_message;
 #[cfg(feature = "quic")]
 use crate::common_state::Protocol;
+#[cfg(feature = "dangerous_transcript")]
+use crate::common_state::TranscriptPoint;
 use crate::common_state::{CommonState, Side, State};
 use crate::conn::ConnectionRandoms;
 use crate::enums::ProtocolVersion;
@@ -21,10 +23,9 @@ use crate::msgs::persist;
 use crate::rand;
 use crate::server::ServerConfig;
 use crate::suites::PartiallyExtractedSecrets;
-use crate::tls13::construct_client_verify_message;
-use crate::tls13::construct_server_verify_message;
 use crate::tls13::key_schedule::{KeyScheduleTraffic, KeyScheduleTrafficWithClientFinishedPending};
 use crate::tls13::Tls13CipherSuite;
+use crate::tls13::{client_verify_message, server_verify_message};
 use crate::verify;
 
 use super::hs::{self, HandshakeHashOrBuffer, ServerContext};
@@ -42,8 +43,8 @@ mod client_hello {
     use crate::enums::SignatureScheme;
     use crate::msgs::base::{Payload, PayloadU8};
     use crate::msgs::ccs::ChangeCipherSpecPayload;
-    use crate::msgs::enums::NamedGroup;
     use crate::msgs::enums::{Compression, PSKKeyExchangeMode};
+    use crate::msgs::enums::{ExtensionType, NamedGroup};
     use crate::msgs::handshake::CertReqExtension;
     use crate::msgs::handshake::CertificateEntry;
     use crate::msgs::handshake::CertificateExtension;
@@ -58,6 +59,7 @@ mod client_hello {
     use crate::msgs::handshake::ServerExtension;
     use crate::msgs::handshake::ServerHelloPayload;
     use crate::msgs::handshake::SessionId;
+    use crate::msgs::handshake::UnknownExtension;
     use crate::server::common::ActiveCertifiedKey;
     use crate::sign;
     use crate::tls13::key_schedule::{
@@ -125,6 +127,71 @@ mod client_hello {
             ConstantTimeEq::ct_eq(real_binder.as_ref(), binder).into()
         }
 
+        /// Having no usable key share, choose a suitable group and send a
+        /// HelloRetryRequest.
+        fn retry_for_key_share(
+            mut self,
+            cx: &mut ServerContext<'_>,
+            chm: &Message,
+            client_hello: &ClientHelloPayload,
+            groups_ext: &[NamedGroup],
+        ) -> hs::NextStateOrError {
+            let retry_group_maybe = self
+                .config
+                .kx_groups
+                .iter()
+                .find(|group| groups_ext.contains(&group.name()))
+                .cloned();
+
+            self.transcript.add_message(chm);
+
+            if let Some(group) = retry_group_maybe {
+                if self.done_retry {
+                    return Err(cx.common.send_fatal_alert(
+                        AlertDescription::IllegalParameter,
+                        PeerMisbehaved::RefusedToFollowHelloRetryRequest,
+                    ));
+                }
+
+                emit_hello_retry_request(
+                    &mut self.transcript,
+                    self.suite,
+                    client_hello.session_id,
+                    cx.common,
+                    group.name(),
+                );
+                emit_fake_ccs(cx.common);
+
+                let skip_early_data = max_early_data_size(self.config.max_early_data_size);
+
+                let next = Box::new(hs::ExpectClientHello {
+                    config: self.config,
+                    transcript: HandshakeHashOrBuffer::Hash(self.transcript),
+                    #[cfg(feature = "tls12")]
+                    session_id: SessionId::empty(),
+                    #[cfg(feature = "tls12")]
+                    using_ems: false,
+                    done_retry: true,
+                    send_tickets: self.send_tickets,
+                    extra_exts: self.extra_exts,
+                });
+
+                return if client_hello.early_data_extension_offered() {
+                    Ok(Box::new(ExpectAndSkipRejectedEarlyData {
+                        skip_data_left: skip_early_data,
+                        next,
+                    }))
+                } else {
+                    Ok(next)
+                };
+            }
+
+            Err(cx.common.send_fatal_alert(
+                AlertDescription::HandshakeFailure,
+                PeerIncompatible::NoKxGroupsInCommon,
+            ))
+        }
+
         fn attempt_tls13_ticket_decryption(
             &mut self,
             ticket: &[u8],
@@ -168,14 +235,20 @@ mod client_hello {
 
             sigschemes_ext.retain(SignatureScheme::supported_in_tls13);
 
-            let shares_ext = client_hello
-                .get_keyshare_extension()
-                .ok_or_else(|| {
-                    cx.common.send_fatal_alert(
+            // A client resuming in the psk_ke mode need not send a key share.
+            let psk_ke_offered = client_hello.get_psk().is_some()
+                && client_hello.psk_mode_offered(PSKKeyExchangeMode::PSK_KE);
+
+            let shares_ext = match client_hello.get_keyshare_extension() {
+                Some(shares_ext) => shares_ext,
+                None if psk_ke_offered => &[],
+                None => {
+                    return Err(cx.common.send_fatal_alert(
                         AlertDescription::HandshakeFailure,
                         PeerIncompatible::KeyShareExtensionRequired,
-                    )
-                })?;
+                    ));
+                }
+            };
 
             if client_hello.has_keyshare_extension_with_duplicates() {
                 return Err(cx.common.send_fatal_alert(
@@ -208,67 +281,20 @@ mod client_hello {
                         .map(|share| (share, *group))
                 });
 
-            let chosen_share_and_kxg = match chosen_share_and_kxg {
-                Some(s) => s,
-                None => {
-                    // We don't have a suitable key share.  Choose a suitable group and
-                    // send a HelloRetryRequest.
-                    let retry_group_maybe = self
-                        .config
-                        .kx_groups
-                        .iter()
-                        .find(|group| groups_ext.contains(&group.name()))
-                        .cloned();
-
-                    self.transcript.add_message(chm);
-
-                    if let Some(group) = retry_group_maybe {
-                        if self.done_retry {
-                            return Err(cx.common.send_fatal_alert(
-                                AlertDescription::IllegalParameter,
-                                PeerMisbehaved::RefusedToFollowHelloRetryRequest,
-                            ));
-                        }
-
-                        emit_hello_retry_request(
-                            &mut self.transcript,
-                            self.suite,
-                            client_hello.session_id,
-                            cx.common,
-                            group.name(),
-                        );
-                        emit_fake_ccs(cx.common);
-
-                        let skip_early_data = max_early_data_size(self.config.max_early_data_size);
-
-                        let next = Box::new(hs::ExpectClientHello {
-                            config: self.config,
-                            transcript: HandshakeHashOrBuffer::Hash(self.transcript),
-                            #[cfg(feature = "tls12")]
-                            session_id: SessionId::empty(),
-                            #[cfg(feature = "tls12")]
-                            using_ems: false,
-                            done_retry: true,
-                            send_tickets: self.send_tickets,
-                            extra_exts: self.extra_exts,
-                        });
-
-                        return if early_data_requested {
-                            Ok(Box::new(ExpectAndSkipRejectedEarlyData {
-                                skip_data_left: skip_early_data,
-                                next,
-                            }))
-                        } else {
-                            Ok(next)
-                        };
-                    }
-
-                    return Err(cx.common.send_fatal_alert(
-                        AlertDescription::HandshakeFailure,
-                        PeerIncompatible::NoKxGroupsInCommon,
-                    ));
-                }
-            };
+            // Only a psk_ke resumption can do without a key share.  A client that
+            // also allows psk_dhe_ke is asked for one, keeping forward secrecy.
+            let psk_ke_allowed = self
+                .config
+                .allow_tls13_psk_ke_resumption
+                && client_hello.psk_mode_offered(PSKKeyExchangeMode::PSK_KE);
+            let dhe_ke_offered = client_hello.psk_mode_offered(PSKKeyExchangeMode::PSK_DHE_KE);
+
+            if chosen_share_and_kxg.is_none()
+                && !(psk_ke_offered && psk_ke_allowed && !dhe_ke_offered)
+            {
+                // We don't have a suitable key share, and can't do without one.
+                return self.retry_for_key_share(cx, chm, client_hello, groups_ext);
+            }
 
             let mut chosen_psk_index = None;
             let mut resumedata = None;
@@ -310,7 +336,7 @@ mod client_hello {
                     let resume = match self
                         .attempt_tls13_ticket_decryption(&psk_id.identity.0)
                         .map(|resumedata| {
-                            resumedata.set_freshness(psk_id.obfuscated_ticket_age, UnixTime::now())
+                            resumedata.set_freshness(psk_id.obfuscated_ticket_age, cx.common.now())
                         })
                         .filter(|resumedata| {
                             hs::can_resume(self.suite.into(), &cx.data.sni, false, resumedata)
@@ -337,8 +363,8 @@ mod client_hello {
                 }
             }
 
-            if !client_hello.psk_mode_offered(PSKKeyExchangeMode::PSK_DHE_KE) {
-                debug!("Client unwilling to resume, DHE_KE not offered");
+            if !dhe_ke_offered && !psk_ke_allowed {
+                debug!("Client unwilling to resume, no supported PSK mode offered");
                 self.send_tickets = 0;
                 chosen_psk_index = None;
                 resumedata = None;
@@ -346,8 +372,29 @@ mod client_hello {
                 self.send_tickets = self.config.send_tls13_tickets;
             }
 
+            // Prefer psk_dhe_ke, for its forward secrecy, where the client allows it.
+            // Otherwise resume with psk_ke, which has none.
+            let chosen_share_and_kxg = match chosen_share_and_kxg {
+                Some(share) if dhe_ke_offered || resumedata.is_none() => Some(share),
+                _ if resumedata.is_some() && !dhe_ke_offered => {
+                    debug!("Resuming without (EC)DHE, as client offered psk_ke");
+                    None
+                }
+                _ => return self.retry_for_key_share(cx, chm, client_hello, groups_ext),
+            };
+
             if let Some(ref resume) = resumedata {
                 cx.data.received_resumption_data = Some(resume.application_data.0.clone());
+                cx.data.resumed_psk_identity = client_hello
+                    .get_psk()
+                    .zip(chosen_psk_index)
+                    .map(|(psk_offer, i)| {
+                        psk_offer.identities[i]
+                            .identity
+                            .0
+                            .clone()
+                    });
+                cx.data.client_authenticated = resume.client_cert_chain.is_some();
                 cx.common.peer_certificates = resume.client_cert_chain.clone();
             }
 
@@ -378,6 +425,7 @@ mod client_hello {
                 &mut ocsp_response,
                 client_hello,
                 resumedata.as_ref(),
+                chosen_psk_index,
                 self.extra_exts,
                 &self.config,
             )?;
@@ -390,12 +438,14 @@ mod client_hello {
                     cx.common,
                     server_key.get_cert(),
                     ocsp_response,
+                    server_key.get_tls13_certificate_extensions(),
                 );
                 emit_certificate_verify_tls13(
                     &mut self.transcript,
                     cx.common,
                     server_key.get_key(),
                     &sigschemes_ext,
+                    self.config.signing_retry_policy,
                 )?;
                 client_auth
             } else {
@@ -477,22 +527,32 @@ mod client_hello {
         suite: &'static Tls13CipherSuite,
         cx: &mut ServerContext<'_>,
         session_id: &SessionId,
-        share_and_kxgroup: (&KeyShareEntry, &'static dyn SupportedKxGroup),
+        share_and_kxgroup: Option<(&KeyShareEntry, &'static dyn SupportedKxGroup)>,
         chosen_psk_idx: Option<usize>,
         resuming_psk: Option<&[u8]>,
         config: &ServerConfig,
     ) -> Result<KeyScheduleHandshake, Error> {
         let mut extensions = Vec::new();
 
-        // Prepare key exchange; the caller already found the matching SupportedKxGroup
-        let (share, kxgroup) = share_and_kxgroup;
-        debug_assert_eq!(kxgroup.name(), share.group);
-        let kx = kxgroup
-            .start()
-            .map_err(|_| Error::FailedToGetRandomBytes)?;
+        // Prepare key exchange; the caller already found the matching SupportedKxGroup.
+        // There is none when resuming in the psk_ke mode.
+        let share_and_kx = match share_and_kxgroup {
+            Some((share, kxgroup)) => {
+                debug_assert_eq!(kxgroup.name(), share.group);
+                if let Some(detector) = &config.key_share_reuse_detector {
+                    detector.check(cx.common, share.group, &share.payload.0)?;
+                }
+
+                let kx = kxgroup
+                    .start()
+                    .map_err(|_| Error::FailedToGetRandomBytes)?;
 
-        let kse = KeyShareEntry::new(share.group, kx.pub_key());
-        extensions.push(ServerExtension::KeyShare(kse));
+                let kse = KeyShareEntry::new(share.group, kx.pub_key());
+                extensions.push(ServerExtension::KeyShare(kse));
+                Some((share, kxgroup, kx))
+            }
+            None => None,
+        };
         extensions.push(ServerExtension::SupportedVersions(ProtocolVersion::TLSv1_3));
 
         if let Some(psk_idx) = chosen_psk_idx {
@@ -520,6 +580,9 @@ mod client_hello {
 
         trace!("sending server hello {:?}", sh);
         transcript.add_message(&sh);
+        #[cfg(feature = "dangerous_transcript")]
+        cx.common
+            .record_transcript(TranscriptPoint::ServerHello, transcript);
         cx.common.send_msg(sh, false);
 
         // Start key schedule
@@ -538,8 +601,15 @@ mod client_hello {
         };
 
         // Do key exchange
-        let shared_secret = kx.complete(&share.payload.0)?;
-        let key_schedule = key_schedule_pre_handshake.into_handshake(shared_secret);
+        let key_schedule = match share_and_kx {
+            Some((share, kxgroup, kx)) => {
+                cx.common
+                    .record_key_shares(kxgroup, kx.pub_key(), &share.payload.0);
+                let shared_secret = kx.complete(&share.payload.0)?;
+                key_schedule_pre_handshake.into_handshake(shared_secret)
+            }
+            None => key_schedule_pre_handshake.into_handshake_without_dhe(),
+        };
 
         let handshake_hash = transcript.get_current_hash();
         let key_schedule = key_schedule.derive_server_handshake_secrets(
@@ -604,6 +674,7 @@ mod client_hello {
         cx: &mut ServerContext<'_>,
         client_hello: &ClientHelloPayload,
         resumedata: Option<&persist::ServerSessionValue>,
+        chosen_psk_index: Option<usize>,
         suite: &'static Tls13CipherSuite,
         config: &ServerConfig,
     ) -> EarlyDataDecision {
@@ -647,7 +718,29 @@ mod client_hello {
             && resume.cipher_suite == suite.common.suite
             && resume.alpn.as_ref().map(|x| &x.0) == cx.common.alpn_protocol.as_ref();
 
-        if early_data_configured && early_data_possible && !cx.data.early_data.was_rejected() {
+        /* "An alternative form of anti-replay is to record a unique value
+         *  derived from the ClientHello (generally either the random value or
+         *  the PSK binder) and reject duplicates." (RFC8446, 8.2)
+         *
+         * This is only consulted for otherwise acceptable early data, so only
+         * those ClientHellos are recorded. */
+        let is_replay = || match (
+            &config.anti_replay,
+            client_hello.get_psk(),
+            chosen_psk_index,
+        ) {
+            (Some(anti_replay), Some(psk_offer), Some(i)) => !anti_replay.check_and_record(
+                psk_offer.binders[i].as_ref(),
+                psk_offer.identities[i].obfuscated_ticket_age,
+            ),
+            _ => false,
+        };
+
+        if early_data_configured
+            && early_data_possible
+            && !cx.data.early_data.was_rejected()
+            && !is_replay()
+        {
             EarlyDataDecision::Accepted
         } else {
             #[cfg(feature = "quic")]
@@ -667,17 +760,26 @@ mod client_hello {
         ocsp_response: &mut Option<&[u8]>,
         hello: &ClientHelloPayload,
         resumedata: Option<&persist::ServerSessionValue>,
+        chosen_psk_index: Option<usize>,
         extra_exts: Vec<ServerExtension>,
         config: &ServerConfig,
     ) -> Result<EarlyDataDecision, Error> {
         let mut ep = hs::ExtensionProcessing::new();
         ep.process_common(config, cx, ocsp_response, hello, resumedata, extra_exts)?;
 
-        let early_data = decide_if_early_data_allowed(cx, hello, resumedata, suite, config);
+        let early_data =
+            decide_if_early_data_allowed(cx, hello, resumedata, chosen_psk_index, suite, config);
         if early_data == EarlyDataDecision::Accepted {
             ep.exts.push(ServerExtension::EarlyData);
         }
 
+        if !config.supported_groups_hint.is_empty() {
+            ep.exts
+                .push(ServerExtension::SupportedGroups(
+                    config.supported_groups_hint.clone(),
+                ));
+        }
+
         let ee = Message {
             version: ProtocolVersion::TLSv1_3,
             payload: MessagePayload::handshake(HandshakeMessagePayload {
@@ -697,7 +799,8 @@ mod client_hello {
         cx: &mut ServerContext<'_>,
         config: &ServerConfig,
     ) -> Result<bool, Error> {
-        if !config.verifier.offer_client_auth() {
+        let verifier = cx.data.client_cert_verifier(config);
+        if !verifier.offer_client_auth() {
             return Ok(false);
         }
 
@@ -706,14 +809,11 @@ mod client_hello {
             extensions: Vec::new(),
         };
 
-        let schemes = config
-            .verifier
-            .supported_verify_schemes();
+        let schemes = verifier.supported_verify_schemes();
         cr.extensions
             .push(CertReqExtension::SignatureAlgorithms(schemes.to_vec()));
 
-        let names = config
-            .verifier
+        let names = verifier
             .client_auth_root_subjects()
             .to_vec();
 
@@ -741,6 +841,7 @@ mod client_hello {
         common: &mut CommonState,
         cert_chain: &[CertificateDer<'static>],
         ocsp_response: Option<&[u8]>,
+        extra_extensions: &[(u16, Vec<u8>)],
     ) {
         let mut cert_entries = vec![];
         for cert in cert_chain {
@@ -761,6 +862,15 @@ mod client_hello {
                     .exts
                     .push(CertificateExtension::CertificateStatus(cst));
             }
+
+            for (typ, payload) in extra_extensions {
+                end_entity_cert
+                    .exts
+                    .push(CertificateExtension::Unknown(UnknownExtension {
+                        typ: ExtensionType::from(*typ),
+                        payload: Payload::new(payload.clone()),
+                    }));
+            }
         }
 
         let cert_body = CertificatePayloadTLS13::new(cert_entries);
@@ -775,6 +885,7 @@ mod client_hello {
         trace!("sending certificate {:?}", c);
         transcript.add_message(&c);
         common.send_msg(c, true);
+        common.sent_certificates = Some(cert_chain.to_owned());
     }
 
     fn emit_certificate_verify_tls13(
@@ -782,11 +893,13 @@ mod client_hello {
         common: &mut CommonState,
         signing_key: &dyn sign::SigningKey,
         schemes: &[SignatureScheme],
+        retry_policy: sign::SigningRetryPolicy,
     ) -> Result<(), Error> {
-        let message = construct_server_verify_message(&transcript.get_current_hash());
+        let message = server_verify_message(&transcript.get_current_hash());
 
         let signer = signing_key
             .choose_scheme(schemes)
+            .filter(|signer| schemes.contains(&signer.scheme()))
             .ok_or_else(|| {
                 common.send_fatal_alert(
                     AlertDescription::HandshakeFailure,
@@ -795,7 +908,8 @@ mod client_hello {
             })?;
 
         let scheme = signer.scheme();
-        let sig = signer.sign(&message)?;
+        common.record_signature_scheme(scheme);
+        let sig = retry_policy.sign(signer.as_ref(), message.as_ref())?;
 
         let cv = DigitallySignedStruct::new(scheme, sig);
 
@@ -834,6 +948,9 @@ mod client_hello {
 
         trace!("sending finished {:?}", m);
         transcript.add_message(&m);
+        #[cfg(feature = "dangerous_transcript")]
+        cx.common
+            .record_transcript(TranscriptPoint::ServerFinished, transcript);
         let hash_at_server_fin = transcript.get_current_hash();
         cx.common.send_msg(m, true);
 
@@ -895,9 +1012,9 @@ impl State<ServerConnectionData> for ExpectCertificate {
 
         let client_cert = certp.convert();
 
-        let mandatory = self
-            .config
-            .verifier
+        let mandatory = cx
+            .data
+            .client_cert_verifier(&self.config)
             .client_auth_mandatory();
 
         let (end_entity, intermediates) = match client_cert.split_first() {
@@ -922,9 +1039,9 @@ impl State<ServerConnectionData> for ExpectCertificate {
             Some(chain) => chain,
         };
 
-        self.config
-            .verifier
-            .verify_client_cert(end_entity, intermediates, UnixTime::now())
+        cx.data
+            .client_cert_verifier(&self.config)
+            .verify_client_cert(end_entity, intermediates, cx.common.now())
             .map_err(|err| {
                 cx.common
                     .send_cert_verify_error_alert(err)
@@ -958,14 +1075,16 @@ impl State<ServerConnectionData> for ExpectCertificateVerify {
                 HandshakeType::CertificateVerify,
                 HandshakePayload::CertificateVerify
             )?;
+            cx.common
+                .record_signature_scheme(sig.scheme);
             let handshake_hash = self.transcript.get_current_hash();
             self.transcript.abandon_client_auth();
             let certs = &self.client_cert;
-            let msg = construct_client_verify_message(&handshake_hash);
+            let msg = client_verify_message(&handshake_hash);
 
-            self.config
-                .verifier
-                .verify_tls13_signature(&msg, &certs[0], sig)
+            cx.data
+                .client_cert_verifier(&self.config)
+                .verify_tls13_signature(msg.as_ref(), &certs[0], sig)
         };
 
         if let Err(e) = rc {
@@ -975,6 +1094,7 @@ impl State<ServerConnectionData> for ExpectCertificateVerify {
         }
 
         trace!("client CertificateVerify OK");
+        cx.data.client_authenticated = true;
         cx.common.peer_certificates = Some(self.client_cert);
 
         self.transcript.add_message(&m);
@@ -1041,6 +1161,10 @@ impl State<ServerConnectionData> for ExpectEarlyData {
             )),
         }
     }
+
+    fn handles_early_data(&self) -> bool {
+        true
+    }
 }
 
 // --- Process client's Finished ---
@@ -1096,7 +1220,7 @@ impl ExpectFinished {
             key_schedule,
             cx,
             &nonce,
-            UnixTime::now(),
+            cx.common.now(),
             age_add,
         )
         .get_encoding();
@@ -1169,10 +1293,16 @@ impl State<ServerConnectionData> for ExpectFinished {
                     .send_fatal_alert(AlertDescription::DecryptError, Error::DecryptError));
             }
         };
+        #[cfg(feature = "dangerous_transcript")]
+        cx.common
+            .record_peer_finished(&finished.0);
 
         // nb. future derivations include Client Finished, but not the
         // main application data keying.
         self.transcript.add_message(&m);
+        #[cfg(feature = "dangerous_transcript")]
+        cx.common
+            .record_transcript(TranscriptPoint::ClientFinished, &self.transcript);
 
         cx.common.check_aligned_handshake()?;
 
@@ -1187,6 +1317,8 @@ impl State<ServerConnectionData> for ExpectFinished {
         }
 
         // Application data may now flow, even if we have client auth enabled.
+        cx.common
+            .record_handshake_hash(self.transcript.get_current_hash());
         cx.common.start_traffic();
 
         #[cfg(feature = "quic")]
@@ -1282,6 +1414,15 @@ impl State<ServerConnectionData> for ExpectTraffic {
         self.key_schedule
             .extract_secrets(Side::Server)
     }
+
+    fn send_key_update(&mut self, common: &mut CommonState) -> Result<(), Error> {
+        // Any KeyUpdate we already owe the peer goes first.
+        common.perhaps_write_key_update();
+        self.key_schedule
+            .update_encrypter_and_notify(common);
+        common.perhaps_write_key_update();
+        Ok(())
+    }
 }
 
 #[cfg(feature = "quic")]
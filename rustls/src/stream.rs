@@ -55,8 +55,9 @@ where
         // We call complete_io() in a loop since a single call may read only
         // a partial packet from the underlying transport. A full packet is
         // needed to get more plaintext, which we must do if EOF has not been
-        // hit.
-        while self.conn.wants_read() {
+        // hit.  Records left undecrypted under `incoming_plaintext_limit`
+        // need no more bytes, just processing.
+        while self.conn.wants_read() || self.conn.has_deferred_records {
             if self.conn.complete_io(self.sock)?.0 == 0 {
                 break;
             }
@@ -72,8 +73,9 @@ where
         // We call complete_io() in a loop since a single call may read only
         // a partial packet from the underlying transport. A full packet is
         // needed to get more plaintext, which we must do if EOF has not been
-        // hit.
-        while self.conn.wants_read() {
+        // hit.  Records left undecrypted under `incoming_plaintext_limit`
+        // need no more bytes, just processing.
+        while self.conn.wants_read() || self.conn.has_deferred_records {
             if self.conn.complete_io(self.sock)?.0 == 0 {
                 break;
             }
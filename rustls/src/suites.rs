@@ -67,6 +67,15 @@ impl SupportedCipherSuite {
         }
     }
 
+    /// Return `true` if every algorithm this suite uses reports being FIPS-approved.
+    pub fn fips(&self) -> bool {
+        match self {
+            #[cfg(feature = "tls12")]
+            Self::Tls12(inner) => inner.fips(),
+            Self::Tls13(inner) => inner.fips(),
+        }
+    }
+
     /// Return supported protocol version for the cipher suite.
     pub fn version(&self) -> &'static SupportedProtocolVersion {
         match self {
@@ -76,6 +85,44 @@ impl SupportedCipherSuite {
         }
     }
 
+    /// Return the length in bytes of the AEAD key used by this suite.
+    pub fn aead_key_len(&self) -> usize {
+        match self {
+            #[cfg(feature = "tls12")]
+            Self::Tls12(inner) => {
+                inner
+                    .aead_alg
+                    .key_block_shape()
+                    .enc_key_len
+            }
+            Self::Tls13(inner) => inner.aead_alg.key_len(),
+        }
+    }
+
+    /// Return the length in bytes of the AEAD nonce used by this suite.
+    ///
+    /// For TLS1.2 this is the whole nonce: the fixed part derived from
+    /// the key block together with any explicit part sent in each record.
+    pub fn aead_nonce_len(&self) -> usize {
+        match self {
+            #[cfg(feature = "tls12")]
+            Self::Tls12(inner) => {
+                let shape = inner.aead_alg.key_block_shape();
+                shape.fixed_iv_len + shape.explicit_nonce_len
+            }
+            Self::Tls13(_) => crypto::cipher::NONCE_LEN,
+        }
+    }
+
+    /// Return the length in bytes of the AEAD authentication tag used by this suite.
+    pub fn aead_tag_len(&self) -> usize {
+        match self {
+            #[cfg(feature = "tls12")]
+            Self::Tls12(inner) => inner.aead_alg.tag_len(),
+            Self::Tls13(inner) => inner.aead_alg.tag_len(),
+        }
+    }
+
     /// Return true if this suite is usable for a key only offering `sig_alg`
     /// signatures.  This resolves to true for all TLS1.3 suites.
     pub fn usable_for_signature_algorithm(&self, _sig_alg: SignatureAlgorithm) -> bool {
@@ -254,6 +301,29 @@ mod tests {
         .is_none());
     }
 
+    #[test]
+    fn test_aead_lengths() {
+        assert_eq!(TLS13_AES_256_GCM_SHA384.aead_key_len(), 32);
+        assert_eq!(TLS13_AES_256_GCM_SHA384.aead_nonce_len(), 12);
+        assert_eq!(TLS13_AES_256_GCM_SHA384.aead_tag_len(), 16);
+
+        assert_eq!(TLS13_AES_128_GCM_SHA256.aead_key_len(), 16);
+        assert_eq!(TLS13_CHACHA20_POLY1305_SHA256.aead_key_len(), 32);
+
+        #[cfg(feature = "tls12")]
+        {
+            use crate::crypto::ring::tls12::*;
+            let suite = TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384;
+            assert_eq!(suite.aead_key_len(), 32);
+            assert_eq!(suite.aead_nonce_len(), 12);
+            assert_eq!(suite.aead_tag_len(), 16);
+
+            let suite = TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256;
+            assert_eq!(suite.aead_key_len(), 32);
+            assert_eq!(suite.aead_nonce_len(), 12);
+        }
+    }
+
     #[test]
     fn test_scs_is_debug() {
         println!("{:?}", crypto::ring::ALL_CIPHER_SUITES);
@@ -0,0 +1,40 @@
+# Encrypt a DER PKCS#8 key with PBES2 (PBKDF2-HMAC-SHA256, AES-GCM), which
+# `openssl pkcs8` cannot write.  The passphrase for the test keys is "rustls":
+#
+#   python3 encrypt-pkcs8-aes-gcm.py nistp256key.pkcs8.der nistp256key.pkcs8.aes256gcm.der 32 rustls
+#   python3 encrypt-pkcs8-aes-gcm.py rsa2048key.pkcs8.der rsa2048key.pkcs8.aes128gcm.der 16 rustls
+
+import hashlib, os, sys
+from cryptography.hazmat.primitives.ciphers.aead import AESGCM
+
+def tlv(tag, body):
+    n = len(body)
+    if n < 0x80:
+        l = bytes([n])
+    else:
+        b = n.to_bytes((n.bit_length() + 7) // 8, 'big')
+        l = bytes([0x80 | len(b)]) + b
+    return bytes([tag]) + l + body
+
+seq = lambda *items: tlv(0x30, b''.join(items))
+octets = lambda b: tlv(0x04, b)
+oid = lambda b: tlv(0x06, bytes.fromhex(b))
+def integer(n):
+    b = n.to_bytes((n.bit_length() + 8) // 8, 'big')
+    return tlv(0x02, b)
+null = b'\x05\x00'
+
+PBES2 = '2a864886f70d01050d'
+PBKDF2 = '2a864886f70d01050c'
+HMAC_SHA256 = '2a864886f70d0209'
+AES_GCM = {16: '608648016503040106', 32: '60864801650304012e'}
+
+src, dst, key_len, passphrase = sys.argv[1], sys.argv[2], int(sys.argv[3]), sys.argv[4].encode()
+plaintext = open(src, 'rb').read()
+salt, nonce, iterations = os.urandom(16), os.urandom(12), 2048
+key = hashlib.pbkdf2_hmac('sha256', passphrase, salt, iterations, key_len)
+ciphertext = AESGCM(key).encrypt(nonce, plaintext, None)
+
+kdf = seq(oid(PBKDF2), seq(octets(salt), integer(iterations), seq(oid(HMAC_SHA256), null)))
+cipher = seq(oid(AES_GCM[key_len]), seq(octets(nonce), integer(16)))
+open(dst, 'wb').write(seq(seq(oid(PBES2), seq(kdf, cipher)), octets(ciphertext)))
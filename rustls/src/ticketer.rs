@@ -128,6 +128,38 @@ impl TicketSwitcher {
             Some(state)
         }
     }
+
+    /// Rotate keys now, rather than waiting for the current ticketer's
+    /// lifetime to pass.
+    ///
+    /// The `current` ticketer is demoted to `previous`, so tickets it issued
+    /// are still accepted until the next rotation, whether triggered by this
+    /// or by the passage of time.  Tickets issued by the old `previous`
+    /// ticketer are no longer accepted.  The new `current` ticketer is used
+    /// for a full `lifetime` before it is rotated automatically.
+    pub fn rotate(&self) -> Result<(), Error> {
+        // As in `maybe_roll`, generate outside the mutex.
+        let fresh = (self.generator)()?;
+        let mut state = self
+            .state
+            .lock()
+            .map_err(|_| Error::General("ticketer state poisoned".into()))?;
+
+        // If `next` is missing (because generating it failed earlier), let
+        // `maybe_roll` recover it later.
+        let new_current = match state.next.take() {
+            Some(next) => {
+                state.next = Some(fresh);
+                next
+            }
+            None => fresh,
+        };
+        state.previous = Some(mem::replace(&mut state.current, new_current));
+        state.next_switch_time = UnixTime::now()
+            .as_secs()
+            .saturating_add(u64::from(self.lifetime));
+        Ok(())
+    }
 }
 
 impl ProducesTickets for TicketSwitcher {
@@ -33,6 +33,11 @@ pub struct Tls12CipherSuite {
 }
 
 impl Tls12CipherSuite {
+    /// Does this suite use only FIPS-approved hash, HMAC and AEAD implementations?
+    pub fn fips(&self) -> bool {
+        self.common.hash_provider.fips() && self.hmac_provider.fips() && self.aead_alg.fips()
+    }
+
     /// Resolve the set of supported [`SignatureScheme`]s from the
     /// offered signature schemes.  If we return an empty
     /// set, the handshake terminates.
@@ -222,13 +227,14 @@ impl ConnectionSecrets {
         output: &mut [u8],
         label: &[u8],
         context: Option<&[u8]>,
-    ) {
+    ) -> Result<(), Error> {
         let mut randoms = Vec::new();
         randoms.extend_from_slice(&self.randoms.client);
         randoms.extend_from_slice(&self.randoms.server);
         if let Some(context) = context {
-            assert!(context.len() <= 0xffff);
-            (context.len() as u16).encode(&mut randoms);
+            let len = u16::try_from(context.len())
+                .map_err(|_| Error::General("exporter context too long".to_string()))?;
+            len.encode(&mut randoms);
             randoms.extend_from_slice(context);
         }
 
@@ -241,6 +247,7 @@ impl ConnectionSecrets {
             label,
             &randoms,
         );
+        Ok(())
     }
 
     pub(crate) fn extract_secrets(&self, side: Side) -> Result<PartiallyExtractedSecrets, Error> {
@@ -317,6 +324,7 @@ mod tests {
     use super::*;
     use crate::common_state::{CommonState, Side};
     use crate::crypto::ring::kx_group::X25519;
+    use crate::crypto::ring::tls12::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256;
     use crate::msgs::handshake::{ClientECDHParams, ServerECDHParams};
 
     #[test]
@@ -336,4 +344,44 @@ mod tests {
         let mut common = CommonState::new(Side::Server);
         assert!(decode_ecdh_params::<ClientECDHParams>(&mut common, &[34]).is_err());
     }
+
+    #[test]
+    fn exporter_test_vectors() {
+        /* These test vectors generated with OpenSSL's TLS1-PRF. */
+        let master_secret: Vec<u8> = (0..48).collect();
+        let randoms = ConnectionRandoms {
+            client: [0x11; 32],
+            server: [0x22; 32],
+        };
+        let suite = match TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256 {
+            SupportedCipherSuite::Tls12(suite) => suite,
+            _ => unreachable!(),
+        };
+        let secrets = ConnectionSecrets::new_resume(randoms, suite, &master_secret);
+
+        let without_context = [
+            0x28, 0xd5, 0x9f, 0x23, 0x3f, 0xfa, 0x65, 0xa9, 0xc0, 0xce, 0x3e, 0xb6, 0xa0, 0xae,
+            0x47, 0x9e, 0xf4, 0x81, 0x03, 0xa0, 0x62, 0x3a, 0xcc, 0x5d, 0xcd, 0x89, 0x82, 0x7b,
+            0x62, 0x75, 0x85, 0x58,
+        ];
+        let with_context = [
+            0xbc, 0xe4, 0xeb, 0xa7, 0x36, 0x78, 0x7a, 0x27, 0x6b, 0x6f, 0x3a, 0xab, 0x3e, 0x14,
+            0x72, 0x0e, 0x38, 0x02, 0xde, 0x6a, 0x06, 0xec, 0xc3, 0x09, 0x3b, 0x35, 0x79, 0x89,
+            0x4e, 0xb4, 0x8c, 0x39,
+        ];
+
+        let mut output = [0u8; 32];
+        secrets
+            .export_keying_material(&mut output, b"EXPERIMENTAL rustls", None)
+            .unwrap();
+        assert_eq!(output, without_context);
+        secrets
+            .export_keying_material(&mut output, b"EXPERIMENTAL rustls", Some(b"context"))
+            .unwrap();
+        assert_eq!(output, with_context);
+
+        assert!(secrets
+            .export_keying_material(&mut output, b"EXPERIMENTAL rustls", Some(&[0; 0x10000]))
+            .is_err());
+    }
 }
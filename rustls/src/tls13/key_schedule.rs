@@ -145,6 +145,12 @@ impl KeySchedulePreHandshake {
             .input_secret(secret.secret_bytes());
         KeyScheduleHandshakeStart { ks: self.ks }
     }
+
+    /// Continue without an (EC)DHE shared secret, for the `psk_ke` mode.
+    pub(crate) fn into_handshake_without_dhe(mut self) -> KeyScheduleHandshakeStart {
+        self.ks.input_empty();
+        KeyScheduleHandshakeStart { ks: self.ks }
+    }
 }
 
 impl From<KeyScheduleEarly> for KeySchedulePreHandshake {
@@ -560,6 +566,9 @@ impl KeySchedule {
         common
             .record_layer
             .set_message_encrypter(self.suite.aead_alg.encrypter(key, iv));
+        common
+            .record_layer
+            .set_confidentiality_limit(self.suite.confidentiality_limit);
     }
 
     fn set_decrypter(&self, secret: &hkdf::OkmBlock, common: &mut CommonState) {
@@ -692,7 +701,6 @@ impl KeySchedule {
             .hash(context.unwrap_or(&[]));
 
         let expander = hkdf::Expander::from_okm(&secret, self.suite.hmac_provider);
-        // TODO: Test what happens when this fails due to large `out.len()`
         hkdf_expand_label_slice(&expander, b"exporter", h_context.as_ref(), out)
             .map_err(|_| Error::General("exporting too much".to_string()))
     }
@@ -786,8 +794,14 @@ where
 
 #[cfg(all(test, feature = "ring"))]
 mod tests {
-    use super::{derive_traffic_iv, derive_traffic_key, KeySchedule, SecretKind};
-    use crate::crypto::ring::tls13::TLS13_CHACHA20_POLY1305_SHA256_INTERNAL;
+    use super::{
+        derive_traffic_iv, derive_traffic_key, hkdf_expand_label_block, KeySchedule, SecretKind,
+    };
+    use crate::crypto::hash;
+    use crate::crypto::ring::tls13::{
+        TLS13_AES_128_GCM_SHA256_INTERNAL, TLS13_CHACHA20_POLY1305_SHA256_INTERNAL,
+    };
+    use crate::hkdf::OkmBlock;
     use crate::KeyLog;
     use ring::aead;
 
@@ -914,6 +928,99 @@ mod tests {
         );
     }
 
+    #[test]
+    fn rfc8448_server_finished() {
+        /* From RFC8448 section 3, "Simple 1-RTT Handshake". */
+        let ecdhe_secret = [
+            0x8b, 0xd4, 0x05, 0x4f, 0xb5, 0x5b, 0x9d, 0x63, 0xfd, 0xfb, 0xac, 0xf9, 0xf0, 0x4b,
+            0x9f, 0x0d, 0x35, 0xe6, 0xd6, 0x3f, 0x53, 0x75, 0x63, 0xef, 0xd4, 0x62, 0x72, 0x90,
+            0x0f, 0x89, 0x49, 0x2d,
+        ];
+
+        let hs_start_hash = [
+            0x86, 0x0c, 0x06, 0xed, 0xc0, 0x78, 0x58, 0xee, 0x8e, 0x78, 0xf0, 0xe7, 0x42, 0x8c,
+            0x58, 0xed, 0xd6, 0xb4, 0x3f, 0x2c, 0xa3, 0xe6, 0xe9, 0x5f, 0x02, 0xed, 0x06, 0x3c,
+            0xf0, 0xe1, 0xca, 0xd8,
+        ];
+
+        let server_hts = [
+            0xb6, 0x7b, 0x7d, 0x69, 0x0c, 0xc1, 0x6c, 0x4e, 0x75, 0xe5, 0x42, 0x13, 0xcb, 0x2d,
+            0x37, 0xb4, 0xe9, 0xc9, 0x12, 0xbc, 0xde, 0xd9, 0x10, 0x5d, 0x42, 0xbe, 0xfd, 0x59,
+            0xd3, 0x91, 0xad, 0x38,
+        ];
+
+        let server_finished_key = [
+            0x00, 0x8d, 0x3b, 0x66, 0xf8, 0x16, 0xea, 0x55, 0x9f, 0x96, 0xb5, 0x37, 0xe8, 0x85,
+            0xc3, 0x1f, 0xc0, 0x68, 0xbf, 0x49, 0x2c, 0x65, 0x2f, 0x01, 0xf2, 0x88, 0xa1, 0xd8,
+            0xcd, 0xc1, 0x9f, 0xc8,
+        ];
+
+        let suite = TLS13_AES_128_GCM_SHA256_INTERNAL;
+        let mut ks = KeySchedule::new_with_empty_secret(suite);
+        ks.input_secret(&ecdhe_secret);
+
+        let traffic_secret = ks.derive(SecretKind::ServerHandshakeTrafficSecret, &hs_start_hash);
+        assert_eq!(traffic_secret.as_ref(), &server_hts[..]);
+
+        let expander = crate::hkdf::Expander::from_okm(&traffic_secret, suite.hmac_provider);
+        let finished_key = hkdf_expand_label_block(&expander, b"finished", &[]);
+        assert_eq!(finished_key.as_ref(), &server_finished_key[..]);
+
+        // The hash of the transcript from the ClientHello to the server's
+        // CertificateVerify, computed from the handshake messages there.
+        let transcript_hash = hash::Output::new(&[
+            0xed, 0xb7, 0x72, 0x5f, 0xa7, 0xa3, 0x47, 0x3b, 0x03, 0x1e, 0xc8, 0xef, 0x65, 0xa2,
+            0x48, 0x54, 0x93, 0x90, 0x01, 0x38, 0xa2, 0xb9, 0x12, 0x91, 0x40, 0x7d, 0x79, 0x51,
+            0xa0, 0x61, 0x10, 0xed,
+        ]);
+
+        let server_verify_data = [
+            0x9b, 0x9b, 0x14, 0x1d, 0x90, 0x63, 0x37, 0xfb, 0xd2, 0xcb, 0xdc, 0xe7, 0x1d, 0xf4,
+            0xde, 0xda, 0x4a, 0xb4, 0x2c, 0x30, 0x95, 0x72, 0xcb, 0x7f, 0xff, 0xee, 0x54, 0x54,
+            0xb7, 0x8f, 0x07, 0x18,
+        ];
+
+        let verify_data = ks.sign_finish(&traffic_secret, &transcript_hash);
+        assert_eq!(verify_data.as_ref(), &server_verify_data[..]);
+    }
+
+    #[test]
+    fn exporter_test_vectors() {
+        /* These test vectors generated with OpenSSL's TLS13-KDF. */
+        let exporter_secret = OkmBlock::from(&(0x40..0x60).collect::<Vec<u8>>()[..]);
+        let ks = KeySchedule::new_with_empty_secret(TLS13_AES_128_GCM_SHA256_INTERNAL);
+
+        let without_context = [
+            0xd1, 0xa3, 0x29, 0x6c, 0x2c, 0x6e, 0xc2, 0xdd, 0x87, 0xe8, 0xee, 0xcf, 0xdf, 0x15,
+            0x40, 0x71, 0xbd, 0x32, 0x9b, 0x5c, 0x9e, 0xd1, 0xc5, 0x9d, 0x40, 0x7a, 0xef, 0x52,
+            0xa7, 0x90, 0xf4, 0xea,
+        ];
+        let with_context = [
+            0x86, 0xfd, 0x59, 0xb5, 0x16, 0xf1, 0xb2, 0x31, 0x67, 0x82, 0x20, 0x21, 0x78, 0x8d,
+            0xff, 0x49, 0x4c, 0x0d, 0xc0, 0xfb, 0xb1, 0xb1, 0xc5, 0x00, 0xda, 0x27, 0xe5, 0x7c,
+            0x48, 0x04, 0xa2, 0x43,
+        ];
+
+        let mut output = [0u8; 32];
+        ks.export_keying_material(&exporter_secret, &mut output, b"EXPERIMENTAL rustls", None)
+            .unwrap();
+        assert_eq!(output, without_context);
+        ks.export_keying_material(
+            &exporter_secret,
+            &mut output,
+            b"EXPERIMENTAL rustls",
+            Some(b"context"),
+        )
+        .unwrap();
+        assert_eq!(output, with_context);
+
+        // HKDF-Expand cannot produce more than 255 blocks
+        let mut output = [0u8; 255 * 32 + 1];
+        assert!(ks
+            .export_keying_material(&exporter_secret, &mut output, b"EXPERIMENTAL rustls", None)
+            .is_err());
+    }
+
     fn assert_traffic_secret(
         ks: &KeySchedule,
         kind: SecretKind,
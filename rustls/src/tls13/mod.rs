@@ -1,5 +1,8 @@
 use crate::crypto;
 use crate::crypto::hash;
+use crate::enums::CipherSuite;
+use crate::error::Error;
+use crate::msgs::enums::HashAlgorithm;
 use crate::suites::{CipherSuiteCommon, SupportedCipherSuite};
 
 use core::fmt;
@@ -21,20 +24,97 @@ pub struct Tls13CipherSuite {
     /// [MessageEncrypter]: crate::crypto::cipher::MessageEncrypter
     pub aead_alg: &'static dyn crypto::cipher::Tls13AeadAlgorithm,
 
-    #[cfg(feature = "quic")]
-    pub(crate) confidentiality_limit: u64,
-    #[cfg(feature = "quic")]
-    pub(crate) integrity_limit: u64,
+    /// How many records may be encrypted under one key before its
+    /// confidentiality can no longer be relied upon.
+    ///
+    /// Once a connection's write key reaches this limit, it is replaced or
+    /// the connection fails, as configured by [`AeadLimitPolicy`].  Use
+    /// `u64::MAX` for AEAD algorithms that have no practical limit.
+    ///
+    /// See <https://www.rfc-editor.org/rfc/rfc8446#section-5.5>.
+    ///
+    /// [`AeadLimitPolicy`]: crate::AeadLimitPolicy
+    pub confidentiality_limit: u64,
+
+    /// How many records may fail to decrypt under one key before its
+    /// integrity can no longer be relied upon.
+    ///
+    /// This is only informative for TLS over TCP, where the first record
+    /// that fails to decrypt is fatal.  It is offered to QUIC
+    /// implementations, which discard forged packets instead.
+    pub integrity_limit: u64,
+
     #[cfg(feature = "quic")]
     pub(crate) quic: &'static dyn crate::quic::Algorithm,
 }
 
 impl Tls13CipherSuite {
+    /// Does this suite use only FIPS-approved hash, HMAC and AEAD implementations?
+    pub fn fips(&self) -> bool {
+        self.common.hash_provider.fips() && self.hmac_provider.fips() && self.aead_alg.fips()
+    }
+
+    /// Check that this suite's hash, HMAC and AEAD implementations fit together.
+    ///
+    /// The HMAC must use a hash of the same output length as `common.hash_provider`,
+    /// and for the suites defined in RFC8446 the hash algorithm and AEAD key length
+    /// must be those the suite name specifies.  A mismatch would otherwise only
+    /// show up as a key schedule that no peer agrees with.
+    pub fn validate(&self) -> Result<(), Error> {
+        let hash = self.common.hash_provider;
+        if self.hmac_provider.hash_output_len() != hash.output_len() {
+            return Err(Error::General(format!(
+                "{:?}: HMAC output length {} does not match hash output length {}",
+                self.common.suite,
+                self.hmac_provider.hash_output_len(),
+                hash.output_len()
+            )));
+        }
+
+        let expected = match self.common.suite {
+            CipherSuite::TLS13_AES_128_GCM_SHA256 => Some((HashAlgorithm::SHA256, 16)),
+            CipherSuite::TLS13_AES_256_GCM_SHA384 => Some((HashAlgorithm::SHA384, 32)),
+            CipherSuite::TLS13_CHACHA20_POLY1305_SHA256 => Some((HashAlgorithm::SHA256, 32)),
+            _ => None,
+        };
+
+        match expected {
+            Some((alg, _)) if hash.algorithm() != alg => Err(Error::General(format!(
+                "{:?}: hash algorithm {:?} does not match suite",
+                self.common.suite,
+                hash.algorithm()
+            ))),
+            Some((_, key_len)) if self.aead_alg.key_len() != key_len => {
+                Err(Error::General(format!(
+                    "{:?}: AEAD key length {} does not match suite",
+                    self.common.suite,
+                    self.aead_alg.key_len()
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
     /// Can a session using suite self resume from suite prev?
+    ///
+    /// This only needs the same hash function, as RFC 8446 section 4.6.1
+    /// permits: it does not stop a session being resumed into a weaker
+    /// suite.  See [`Tls13CipherSuite::is_at_least_as_strong_as`] for that.
     pub fn can_resume_from(&self, prev: &'static Self) -> Option<&'static Self> {
         (prev.common.hash_provider.algorithm() == self.common.hash_provider.algorithm())
             .then(|| prev)
     }
+
+    /// Is this suite's AEAD key at least as long as `other`'s, and its hash
+    /// output at least as long?
+    ///
+    /// This is a coarse measure of strength.  A client does not offer to
+    /// resume a session into a suite weaker than the one that established
+    /// it, when it knows the suite before offering.
+    pub fn is_at_least_as_strong_as(&self, other: &Self) -> bool {
+        self.aead_alg.key_len() >= other.aead_alg.key_len()
+            && self.common.hash_provider.output_len() >= other.common.hash_provider.output_len()
+    }
 }
 
 impl From<&'static Tls13CipherSuite> for SupportedCipherSuite {
@@ -57,23 +137,166 @@ impl fmt::Debug for Tls13CipherSuite {
     }
 }
 
-/// Constructs the signature message specified in section 4.4.3 of RFC8446.
-pub(crate) fn construct_client_verify_message(handshake_hash: &hash::Output) -> Vec<u8> {
-    construct_verify_message(handshake_hash, b"TLS 1.3, client CertificateVerify\x00")
+/// Constructs the client's signature message specified in section 4.4.3 of RFC8446,
+/// without allocating.
+pub(crate) fn client_verify_message(handshake_hash: &hash::Output) -> VerifyMessage {
+    VerifyMessage::new(handshake_hash, CLIENT_VERIFY_CONTEXT)
 }
 
-/// Constructs the signature message specified in section 4.4.3 of RFC8446.
-pub(crate) fn construct_server_verify_message(handshake_hash: &hash::Output) -> Vec<u8> {
-    construct_verify_message(handshake_hash, b"TLS 1.3, server CertificateVerify\x00")
+/// Constructs the server's signature message specified in section 4.4.3 of RFC8446,
+/// without allocating.
+pub(crate) fn server_verify_message(handshake_hash: &hash::Output) -> VerifyMessage {
+    VerifyMessage::new(handshake_hash, SERVER_VERIFY_CONTEXT)
 }
 
-fn construct_verify_message(
-    handshake_hash: &hash::Output,
-    context_string_with_0: &[u8],
-) -> Vec<u8> {
-    let mut msg = Vec::new();
-    msg.resize(64, 0x20u8);
-    msg.extend_from_slice(context_string_with_0);
-    msg.extend_from_slice(handshake_hash.as_ref());
-    msg
+const CLIENT_VERIFY_CONTEXT: &[u8; 34] = b"TLS 1.3, client CertificateVerify\x00";
+const SERVER_VERIFY_CONTEXT: &[u8; 34] = b"TLS 1.3, server CertificateVerify\x00";
+const VERIFY_PADDING_LEN: usize = 64;
+
+/// A CertificateVerify signature message, held on the stack.
+pub(crate) struct VerifyMessage {
+    buf: [u8; VERIFY_PADDING_LEN + CLIENT_VERIFY_CONTEXT.len() + hash::Output::MAX_LEN],
+    used: usize,
+}
+
+impl VerifyMessage {
+    fn new(handshake_hash: &hash::Output, context_string_with_0: &[u8; 34]) -> Self {
+        let mut buf =
+            [0x20u8; VERIFY_PADDING_LEN + CLIENT_VERIFY_CONTEXT.len() + hash::Output::MAX_LEN];
+        let hash = handshake_hash.as_ref();
+        let context_end = VERIFY_PADDING_LEN + context_string_with_0.len();
+        buf[VERIFY_PADDING_LEN..context_end].copy_from_slice(context_string_with_0);
+        buf[context_end..context_end + hash.len()].copy_from_slice(hash);
+        Self {
+            buf,
+            used: context_end + hash.len(),
+        }
+    }
+}
+
+impl AsRef<[u8]> for VerifyMessage {
+    fn as_ref(&self) -> &[u8] {
+        &self.buf[..self.used]
+    }
+}
+
+#[cfg(all(test, feature = "ring"))]
+mod tests {
+    use super::*;
+    use crate::crypto::ring::tls13::{
+        TLS13_AES_128_GCM_SHA256_INTERNAL, TLS13_AES_256_GCM_SHA384,
+        TLS13_CHACHA20_POLY1305_SHA256_INTERNAL,
+    };
+
+    #[test]
+    fn verify_messages() {
+        for len in [32, 48, hash::Output::MAX_LEN] {
+            let hash = hash::Output::new(&[0xa5; hash::Output::MAX_LEN][..len]);
+            for (msg, context) in [
+                (
+                    client_verify_message(&hash),
+                    &b"TLS 1.3, client CertificateVerify\x00"[..],
+                ),
+                (
+                    server_verify_message(&hash),
+                    &b"TLS 1.3, server CertificateVerify\x00"[..],
+                ),
+            ] {
+                let msg = msg.as_ref();
+                assert_eq!(msg.len(), 64 + context.len() + len);
+                assert_eq!(&msg[..64], &[0x20; 64][..]);
+                assert_eq!(&msg[64..64 + context.len()], context);
+                assert_eq!(&msg[64 + context.len()..], hash.as_ref());
+            }
+        }
+    }
+
+    #[test]
+    fn ring_suites_are_valid() {
+        for suite in [
+            TLS13_AES_128_GCM_SHA256_INTERNAL,
+            TLS13_AES_256_GCM_SHA384
+                .tls13()
+                .unwrap(),
+            TLS13_CHACHA20_POLY1305_SHA256_INTERNAL,
+        ] {
+            assert_eq!(suite.validate(), Ok(()));
+        }
+    }
+
+    #[test]
+    fn mismatched_suites_are_rejected() {
+        let sha256 = TLS13_AES_128_GCM_SHA256_INTERNAL;
+        let sha384 = TLS13_AES_256_GCM_SHA384
+            .tls13()
+            .unwrap();
+        let mixed =
+            |common: &CipherSuiteCommon,
+             hmac_provider: &'static dyn crypto::hmac::Hmac,
+             aead_alg: &'static dyn crypto::cipher::Tls13AeadAlgorithm| {
+                Tls13CipherSuite {
+                    common: CipherSuiteCommon {
+                        suite: common.suite,
+                        hash_provider: common.hash_provider,
+                    },
+                    hmac_provider,
+                    aead_alg,
+                    confidentiality_limit: sha256.confidentiality_limit,
+                    integrity_limit: sha256.integrity_limit,
+                    #[cfg(feature = "quic")]
+                    quic: sha256.quic,
+                }
+            };
+
+        // HMAC-SHA384 with a SHA256 transcript hash
+        assert!(mixed(&sha256.common, sha384.hmac_provider, sha256.aead_alg)
+            .validate()
+            .is_err());
+
+        // SHA384 under a *_SHA256 suite name, with consistent HMAC
+        let renamed = CipherSuiteCommon {
+            suite: sha256.common.suite,
+            hash_provider: sha384.common.hash_provider,
+        };
+        assert!(mixed(&renamed, sha384.hmac_provider, sha256.aead_alg)
+            .validate()
+            .is_err());
+
+        // AES-256 under TLS13_AES_128_GCM_SHA256
+        assert!(mixed(&sha256.common, sha256.hmac_provider, sha384.aead_alg)
+            .validate()
+            .is_err());
+    }
+
+    #[test]
+    fn suite_strength_and_resumability() {
+        let aes128 = TLS13_AES_128_GCM_SHA256_INTERNAL;
+        let aes256 = TLS13_AES_256_GCM_SHA384
+            .tls13()
+            .unwrap();
+        assert!(aes256.is_at_least_as_strong_as(aes128));
+        assert!(aes256.is_at_least_as_strong_as(aes256));
+        assert!(!aes128.is_at_least_as_strong_as(aes256));
+
+        // AES-256 with SHA256, so only the key size differs from `aes128`
+        let aes256_sha256: &'static Tls13CipherSuite = Box::leak(Box::new(Tls13CipherSuite {
+            common: CipherSuiteCommon {
+                suite: aes128.common.suite,
+                hash_provider: aes128.common.hash_provider,
+            },
+            hmac_provider: aes128.hmac_provider,
+            aead_alg: aes256.aead_alg,
+            confidentiality_limit: aes256.confidentiality_limit,
+            integrity_limit: aes256.integrity_limit,
+            #[cfg(feature = "quic")]
+            quic: aes256.quic,
+        }));
+        assert!(!aes128.is_at_least_as_strong_as(aes256_sha256));
+        assert!(aes256_sha256.is_at_least_as_strong_as(aes128));
+
+        // resumption itself only needs the same hash
+        assert_eq!(aes128.can_resume_from(aes256_sha256), Some(aes256_sha256));
+        assert_eq!(aes256_sha256.can_resume_from(aes128), Some(aes128));
+        assert_eq!(aes256.can_resume_from(aes128), None);
+    }
 }
@@ -88,6 +88,53 @@ pub trait ServerCertVerifier: Send + Sync {
         now: UnixTime,
     ) -> Result<ServerCertVerified, Error>;
 
+    /// Like [`ServerCertVerifier::verify_server_cert`], but also told which
+    /// application protocol was negotiated with the server.
+    ///
+    /// `alpn_protocol` is the protocol the server selected using ALPN, or
+    /// `None` if it selected none.  This lets a verifier apply a different
+    /// policy per protocol.  rustls calls this, rather than `verify_server_cert`,
+    /// to verify the server's certificate.
+    ///
+    /// The default ignores `alpn_protocol` and calls `verify_server_cert`.
+    fn verify_server_cert_with_alpn(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName,
+        ocsp_response: &[u8],
+        alpn_protocol: Option<&[u8]>,
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        let _ = alpn_protocol;
+        self.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)
+    }
+
+    /// Verify a server identified by a raw public key ([RFC 7250]) rather
+    /// than by a certificate chain.
+    ///
+    /// `spki` is the DER-encoded `SubjectPublicKeyInfo` the server presented.
+    /// rustls calls this instead of [`ServerCertVerifier::verify_server_cert_with_alpn`]
+    /// when the client was configured with
+    /// [`ConfigBuilder::with_raw_public_key_verifier`](crate::ConfigBuilder::with_raw_public_key_verifier).
+    /// The `cert` later given to [`ServerCertVerifier::verify_tls13_signature`]
+    /// then holds `spki`; see [`crate::verify_tls13_signature_with_raw_key`].
+    ///
+    /// The default rejects every raw public key.
+    ///
+    /// [RFC 7250]: https://www.rfc-editor.org/rfc/rfc7250
+    fn verify_raw_public_key(
+        &self,
+        spki: &[u8],
+        server_name: &ServerName,
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        let _ = (spki, server_name, now);
+        Err(Error::General(
+            "raw public keys are not supported by this verifier".into(),
+        ))
+    }
+
     /// Verify a signature allegedly by the given server certificate.
     ///
     /// `message` is not hashed, and needs hashing during the verification.
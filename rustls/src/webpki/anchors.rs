@@ -4,10 +4,18 @@ use webpki::extract_trust_anchor;
 use super::pki_error;
 #[cfg(feature = "logging")]
 use crate::log::{debug, trace};
+use crate::msgs::handshake::DistinguishedName;
 use crate::Error;
 
 /// A container for root certificates able to provide a root-of-trust
 /// for connection authentication.
+///
+/// Any certificate may be a trust anchor, not only a self-signed root: a
+/// private PKI can pin an intermediate CA without shipping its root.  A
+/// presented chain is then accepted once it reaches any trusted certificate.
+/// Name constraints in a trusted certificate apply to the chain beneath it,
+/// and path length is counted from it; constraints in certificates above it,
+/// such as its issuer's, are not considered.
 #[derive(Debug, Clone)]
 pub struct RootCertStore {
     /// The list of roots.
@@ -30,6 +38,27 @@ impl RootCertStore {
         self.roots.len()
     }
 
+    /// Return the DER encoded [`DistinguishedName`] of each trust anchor subject in the root
+    /// cert store.
+    ///
+    /// Each [`DistinguishedName`] will be a DER-encoded X.500 distinguished name, per
+    /// [RFC 5280 A.1], including the outer `SEQUENCE`.
+    ///
+    /// [RFC 5280 A.1]: https://www.rfc-editor.org/rfc/rfc5280#appendix-A.1
+    pub fn subjects(&self) -> Vec<DistinguishedName> {
+        self.iter_subjects().collect()
+    }
+
+    /// Iterate over the [`DistinguishedName`] of each trust anchor subject, in the
+    /// order the trust anchors were added.
+    ///
+    /// This is like [`RootCertStore::subjects`], without collecting into a `Vec`.
+    pub fn iter_subjects(&self) -> impl Iterator<Item = DistinguishedName> + '_ {
+        self.roots
+            .iter()
+            .map(|ta| DistinguishedName::in_sequence(ta.subject.as_ref()))
+    }
+
     /// Add a single DER-encoded certificate to the store.
     ///
     /// This is suitable for a small set of root certificates that are expected to parse
@@ -6,7 +6,7 @@ use webpki::BorrowedCertRevocationList;
 
 use super::verify::{AnonymousClientPolicy, WebPkiClientVerifier, WebPkiSupportedAlgorithms};
 use crate::verify::ClientCertVerifier;
-use crate::{CertRevocationListError, RootCertStore};
+use crate::{CertRevocationListError, RootCertStore, SignatureScheme};
 
 /// A builder for configuring a `webpki` client certificate verifier.
 ///
@@ -17,6 +17,10 @@ pub struct ClientCertVerifierBuilder {
     crls: Vec<CertificateRevocationListDer<'static>>,
     anon_policy: AnonymousClientPolicy,
     supported_algs: Option<WebPkiSupportedAlgorithms>,
+    signature_schemes: Option<Vec<SignatureScheme>>,
+    reject_sha1_signatures: bool,
+    allow_nonconforming_serial_numbers: bool,
+    required_policy_oids: Vec<Vec<u8>>,
 }
 
 impl ClientCertVerifierBuilder {
@@ -26,6 +30,10 @@ impl ClientCertVerifierBuilder {
             crls: Vec::new(),
             anon_policy: AnonymousClientPolicy::Deny,
             supported_algs: None,
+            signature_schemes: None,
+            reject_sha1_signatures: true,
+            allow_nonconforming_serial_numbers: false,
+            required_policy_oids: Vec::new(),
         }
     }
 
@@ -60,6 +68,70 @@ impl ClientCertVerifierBuilder {
         self
     }
 
+    /// Only accept client signatures made with one of `schemes`.
+    ///
+    /// This covers the client's `CertificateVerify` signature and the
+    /// signatures in its certificate chain, which must each use an algorithm
+    /// that [`WebPkiSupportedAlgorithms::mapping`] gives for one of `schemes`.
+    /// The schemes requested from clients are restricted to match.  This is
+    /// independent of the key exchange groups in the `ServerConfig`.  Schemes
+    /// not in the enabled signature verification algorithms are ignored.
+    ///
+    /// If this is called multiple times, the last call wins.
+    pub fn with_signature_schemes(mut self, schemes: &[SignatureScheme]) -> Self {
+        self.signature_schemes = Some(schemes.to_vec());
+        self
+    }
+
+    /// Control whether client certificate chains containing SHA-1 signatures are rejected.
+    ///
+    /// When `reject` is true, which is the default, verification fails with
+    /// [`crate::CertificateError::Sha1Signature`] if the client's certificate
+    /// or any intermediate it sends is signed using a SHA-1 based algorithm.
+    /// Only the self-signature of a trust anchor, if the client sends one,
+    /// is exempt.
+    ///
+    /// The default algorithms cannot verify SHA-1 based signatures anyway, so
+    /// with them this only makes the error specific.  With algorithms given to
+    /// [`Self::with_signature_verification_algorithms`] that include a SHA-1
+    /// based one, this is what keeps those signatures out of certificate chains.
+    pub fn reject_sha1_signatures(mut self, reject: bool) -> Self {
+        self.reject_sha1_signatures = reject;
+        self
+    }
+
+    /// Control whether client certificate chains with non-conforming serial
+    /// numbers are accepted.
+    ///
+    /// Unless `allow` is true, verification fails with
+    /// [`crate::CertificateError::NonConformingSerialNumber`] if the client's
+    /// certificate or any intermediate it sends has a serial number that is
+    /// not a positive integer of at most 20 octets, as RFC 5280 requires.
+    /// When it is true, such certificates are accepted with a warning logged.
+    /// The default is false.
+    pub fn allow_nonconforming_serial_numbers(mut self, allow: bool) -> Self {
+        self.allow_nonconforming_serial_numbers = allow;
+        self
+    }
+
+    /// Require the client's certificate to assert at least one of the
+    /// certificate policies in `oids`.
+    ///
+    /// Each policy identifier is given as the contents of its DER encoding,
+    /// without the tag and length.  Verification fails with
+    /// [`crate::CertificateError::RequiredPolicyNotAsserted`] if the client's
+    /// certificate asserts none of them.  Only the client's certificate is
+    /// checked, and `anyPolicy` only matches if listed here.
+    ///
+    /// If this is called multiple times, the last call wins.
+    pub fn with_required_policy_oids(mut self, oids: &[&[u8]]) -> Self {
+        self.required_policy_oids = oids
+            .iter()
+            .map(|oid| oid.to_vec())
+            .collect();
+        self
+    }
+
     /// Build a client certificate verifier. The built verifier will be used for the server to offer
     /// client certificate authentication, to control how offered client certificates are validated,
     /// and to determine what to do with anonymous clients that do not respond to the client
@@ -104,6 +176,10 @@ impl ClientCertVerifierBuilder {
                 .collect::<Result<Vec<_>, CertRevocationListError>>()?,
             self.anon_policy,
             supported_algs,
+            self.signature_schemes,
+            self.reject_sha1_signatures,
+            self.allow_nonconforming_serial_numbers,
+            self.required_policy_oids,
         )))
     }
 }
@@ -4,13 +4,20 @@ use crate::error::{CertRevocationListError, CertificateError, Error};
 
 mod anchors;
 mod client_verifier_builder;
+mod name_constraints;
+mod ocsp;
 mod verify;
 
 pub use anchors::RootCertStore;
 
+pub use name_constraints::{NameConstraintVerifier, NameSubtrees};
+
 pub use client_verifier_builder::{ClientCertVerifierBuilder, ClientCertVerifierBuilderError};
 
-pub use verify::{WebPkiClientVerifier, WebPkiSupportedAlgorithms};
+pub use verify::{
+    verify_tls13_certificate_verify, verify_tls13_signature_with_raw_key, WebPkiClientVerifier,
+    WebPkiSupportedAlgorithms,
+};
 
 // Conditionally exported from crate.
 #[allow(unreachable_pub)]
@@ -0,0 +1,399 @@
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use pki_types::{CertificateDer, TrustAnchor, UnixTime};
+
+use super::pki_error;
+#[cfg(feature = "ring")]
+use super::verify::SUPPORTED_SIG_ALGS;
+use super::verify::{ParsedCertificate, WebPkiSupportedAlgorithms};
+use crate::client::ServerName;
+use crate::dns_name::{DnsNameRef, InvalidDnsNameError};
+use crate::enums::SignatureScheme;
+use crate::error::{CertificateError, Error};
+#[cfg(feature = "logging")]
+use crate::log::debug;
+use crate::msgs::handshake::DistinguishedName;
+use crate::verify::{
+    ClientCertVerified, ClientCertVerifier, DigitallySignedStruct, HandshakeSignatureValid,
+    ServerCertVerified, ServerCertVerifier,
+};
+
+/// DNS name subtrees that certificates issued beneath a trust anchor must respect.
+///
+/// A subtree such as `example.com` contains that name and every name below it,
+/// as for the `dNSName` form of [RFC 5280 name constraints].
+///
+/// [RFC 5280 name constraints]: https://datatracker.ietf.org/doc/html/rfc5280#section-4.2.1.10
+#[derive(Clone, Debug, Default)]
+pub struct NameSubtrees {
+    permitted: Vec<String>,
+    excluded: Vec<String>,
+}
+
+impl NameSubtrees {
+    /// Make an empty set of subtrees, which constrains nothing.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Permit names within `subtree`.
+    ///
+    /// Once any subtree is permitted, every DNS name in a certificate must be
+    /// within one of the permitted subtrees.
+    pub fn permit(mut self, subtree: &str) -> Result<Self, InvalidDnsNameError> {
+        self.permitted
+            .push(normalize_subtree(subtree)?);
+        Ok(self)
+    }
+
+    /// Exclude names within `subtree`.
+    ///
+    /// Exclusions take precedence over permitted subtrees.
+    pub fn exclude(mut self, subtree: &str) -> Result<Self, InvalidDnsNameError> {
+        self.excluded
+            .push(normalize_subtree(subtree)?);
+        Ok(self)
+    }
+
+    fn allows(&self, name: &str) -> bool {
+        let name = name.to_ascii_lowercase();
+
+        if self
+            .excluded
+            .iter()
+            .any(|subtree| within(&name, subtree) || wildcard_covers(&name, subtree))
+        {
+            return false;
+        }
+
+        self.permitted.is_empty()
+            || self
+                .permitted
+                .iter()
+                .any(|subtree| within(&name, subtree))
+    }
+}
+
+fn normalize_subtree(subtree: &str) -> Result<String, InvalidDnsNameError> {
+    let name = DnsNameRef::try_from(subtree)?;
+    Ok(name
+        .to_lowercase_owned()
+        .as_ref()
+        .into())
+}
+
+/// Is `name` equal to `subtree`, or below it?
+fn within(name: &str, subtree: &str) -> bool {
+    match name.strip_suffix(subtree) {
+        Some("") => true,
+        Some(prefix) => prefix.ends_with('.'),
+        None => false,
+    }
+}
+
+/// Could the wildcard `name` match some name within `subtree`?
+///
+/// `*.example.com` may stand for `forbidden.example.com`, so is caught by
+/// an exclusion of the latter.
+fn wildcard_covers(name: &str, subtree: &str) -> bool {
+    let base = match name.strip_prefix("*.") {
+        Some(base) => base,
+        None => return false,
+    };
+
+    match subtree.strip_suffix(base) {
+        Some(prefix) => match prefix.strip_suffix('.') {
+            Some(label) => !label.is_empty() && !label.contains('.'),
+            None => false,
+        },
+        None => false,
+    }
+}
+
+/// A verifier that imposes extra name constraints on top of another verifier.
+///
+/// Each trust anchor can be given a [`NameSubtrees`], which is enforced against
+/// the DNS names in the subject alternative names of end-entity certificates
+/// issued beneath it.  This happens only once the inner verifier has
+/// accepted the certificate chain.
+///
+/// The constraints for an anchor apply when the presented chain validates to
+/// that anchor.  A chain that names the anchor as an issuer but cannot be
+/// validated to it here (for example, because it has expired, or uses an
+/// algorithm outside those given to [`Self::new_with_algorithms`]) is
+/// rejected, rather than being let through unconstrained.  IP address names
+/// are not constrained.
+///
+/// This implements [`ServerCertVerifier`] or [`ClientCertVerifier`],
+/// depending on what it wraps.
+#[derive(Debug)]
+pub struct NameConstraintVerifier<V: ?Sized> {
+    inner: Arc<V>,
+    supported: WebPkiSupportedAlgorithms,
+    constraints: Vec<(TrustAnchor<'static>, NameSubtrees)>,
+}
+
+impl<V: ?Sized> NameConstraintVerifier<V> {
+    /// Wrap `inner`, initially without any extra constraints.
+    #[cfg(feature = "ring")]
+    pub fn new(inner: Arc<V>) -> Self {
+        Self::new_with_algorithms(inner, SUPPORTED_SIG_ALGS)
+    }
+
+    /// Wrap `inner`, initially without any extra constraints.
+    ///
+    /// `supported` is the set of algorithms used to find which trust anchor
+    /// a chain validates to.  It should match those of the inner verifier.
+    pub fn new_with_algorithms(inner: Arc<V>, supported: WebPkiSupportedAlgorithms) -> Self {
+        Self {
+            inner,
+            supported,
+            constraints: Vec::new(),
+        }
+    }
+
+    /// Constrain the certificates issued beneath `anchor` to `subtrees`.
+    ///
+    /// `anchor` is the DER-encoded trust anchor certificate, as given to the
+    /// inner verifier.  This fails if it cannot be parsed.
+    pub fn with_constraints(
+        mut self,
+        anchor: &CertificateDer<'_>,
+        subtrees: NameSubtrees,
+    ) -> Result<Self, Error> {
+        let anchor = webpki::extract_trust_anchor(anchor).map_err(pki_error)?;
+        self.constraints
+            .push((anchor.to_owned(), subtrees));
+        Ok(self)
+    }
+
+    fn check_constraints(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        now: UnixTime,
+        usage: webpki::KeyUsage,
+    ) -> Result<(), Error> {
+        if self.constraints.is_empty() {
+            return Ok(());
+        }
+
+        let cert = ParsedCertificate::try_from(end_entity)?;
+
+        for (anchor, subtrees) in &self.constraints {
+            match cert.0.verify_for_usage(
+                self.supported.all,
+                core::slice::from_ref(anchor),
+                intermediates,
+                now,
+                usage,
+                None,
+            ) {
+                Ok(_) => {}
+                // no path from the end-entity certificate reaches this anchor
+                Err(webpki::Error::UnknownIssuer) => continue,
+                Err(err) => {
+                    debug!(
+                        "chain names {:?} but does not validate to it: {:?}",
+                        DistinguishedName::in_sequence(anchor.subject.as_ref()),
+                        err
+                    );
+                    return Err(pki_error(err));
+                }
+            }
+
+            #[cfg_attr(not(feature = "logging"), allow(unused_variables))]
+            if let Some(name) = cert
+                .0
+                .valid_dns_names()
+                .find(|name| !subtrees.allows(name))
+            {
+                debug!(
+                    "name {:?} violates constraints for {:?}",
+                    name,
+                    DistinguishedName::in_sequence(anchor.subject.as_ref())
+                );
+                return Err(CertificateError::NotValidForName.into());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<V: ServerCertVerifier + ?Sized> ServerCertVerifier for NameConstraintVerifier<V> {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )?;
+        self.check_constraints(
+            end_entity,
+            intermediates,
+            now,
+            webpki::KeyUsage::server_auth(),
+        )?;
+        Ok(verified)
+    }
+
+    fn verify_server_cert_with_alpn(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName,
+        ocsp_response: &[u8],
+        alpn_protocol: Option<&[u8]>,
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        let verified = self
+            .inner
+            .verify_server_cert_with_alpn(
+                end_entity,
+                intermediates,
+                server_name,
+                ocsp_response,
+                alpn_protocol,
+                now,
+            )?;
+        self.check_constraints(
+            end_entity,
+            intermediates,
+            now,
+            webpki::KeyUsage::server_auth(),
+        )?;
+        Ok(verified)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner
+            .verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner
+            .verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+impl<V: ClientCertVerifier + ?Sized> ClientCertVerifier for NameConstraintVerifier<V> {
+    fn offer_client_auth(&self) -> bool {
+        self.inner.offer_client_auth()
+    }
+
+    fn client_auth_mandatory(&self) -> bool {
+        self.inner.client_auth_mandatory()
+    }
+
+    fn client_auth_root_subjects(&self) -> &[DistinguishedName] {
+        self.inner.client_auth_root_subjects()
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        now: UnixTime,
+    ) -> Result<ClientCertVerified, Error> {
+        let verified = self
+            .inner
+            .verify_client_cert(end_entity, intermediates, now)?;
+        self.check_constraints(
+            end_entity,
+            intermediates,
+            now,
+            webpki::KeyUsage::client_auth(),
+        )?;
+        Ok(verified)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner
+            .verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner
+            .verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NameSubtrees;
+
+    #[test]
+    fn subtree_matching() {
+        let subtrees = NameSubtrees::new()
+            .permit("Example.com")
+            .unwrap()
+            .exclude("secret.example.com")
+            .unwrap();
+
+        assert!(subtrees.allows("example.com"));
+        assert!(subtrees.allows("www.EXAMPLE.com"));
+        assert!(subtrees.allows("a.b.example.com"));
+        assert!(!subtrees.allows("badexample.com"));
+        assert!(!subtrees.allows("example.org"));
+        assert!(!subtrees.allows("secret.example.com"));
+        assert!(!subtrees.allows("deeper.secret.example.com"));
+        assert!(!subtrees.allows("*.example.com"));
+        assert!(subtrees.allows("*.www.example.com"));
+    }
+
+    #[test]
+    fn empty_subtrees_allow_everything() {
+        assert!(NameSubtrees::new().allows("anything.test"));
+        assert!(NameSubtrees::new()
+            .exclude("other.test")
+            .unwrap()
+            .allows("anything.test"));
+    }
+
+    #[test]
+    fn invalid_subtrees_are_rejected() {
+        assert!(NameSubtrees::new()
+            .permit("not a name")
+            .is_err());
+        assert!(NameSubtrees::new().exclude("").is_err());
+    }
+}
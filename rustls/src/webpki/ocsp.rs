@@ -0,0 +1,479 @@
+//! Validation of stapled OCSP responses, as described in RFC 6960.
+
+use alloc::borrow::Cow;
+use core::time::Duration;
+
+use pki_types::{CertificateDer, UnixTime};
+
+use super::anchors::RootCertStore;
+use super::verify::WebPkiSupportedAlgorithms;
+use crate::crypto::hash::HashAlgorithm;
+use crate::crypto::CryptoProvider;
+use crate::error::{CertificateError, Error};
+use crate::x509::{
+    expect_sequence, parse_time, split_raw_tlv, split_sequence, split_subject_public_key_info,
+    split_tlv, wrap_in_sequence, Certificate, Signed, DER_EXPLICIT_VERSION_TAG, DER_INTEGER_TAG,
+    DER_OCTET_STRING_TAG, DER_OID_TAG, DER_SEQUENCE_TAG,
+};
+
+/// Check that the DER-encoded OCSP response `response` says `end_entity`
+/// is good, and is current at `now`.
+///
+/// The response must be signed by the issuer of `end_entity`, found among
+/// `intermediates` and `roots`, or by a responder certificate included in
+/// the response that the issuer has authorised for OCSP signing.  Its
+/// `responderID` must name the certificate that signed it.  It must have
+/// a `SingleResponse` whose `CertID` names `end_entity`: its serial number,
+/// and the hashes of its issuer's name and key.  These hashes, and any
+/// `responderID` key hash, are computed with `provider`'s hash functions.
+///
+/// `skew` is allowed on either side of the response's validity period,
+/// which must have an end.  A response outside it is stale, whatever the
+/// status it gives.
+pub(super) fn verify_ocsp_response(
+    response: &[u8],
+    end_entity: &CertificateDer<'_>,
+    intermediates: &[CertificateDer<'_>],
+    roots: &RootCertStore,
+    supported: &WebPkiSupportedAlgorithms,
+    provider: &dyn CryptoProvider,
+    now: UnixTime,
+    skew: Duration,
+) -> Result<(), Error> {
+    let cert = Certificate::parse(end_entity).ok_or(CertificateError::BadEncoding)?;
+    let issuer_name = expect_sequence(cert.issuer).ok_or(CertificateError::BadEncoding)?;
+    let response = BasicResponse::parse(response).ok_or(CertificateError::InvalidOcspResponse)?;
+
+    // The keys of the possible issuers, as whole `SubjectPublicKeyInfo`s.
+    let issuer_spkis = intermediates
+        .iter()
+        .filter_map(|der| Certificate::parse(der))
+        .filter(|issuer| issuer.subject == cert.issuer)
+        .map(|issuer| Cow::Borrowed(issuer.spki))
+        .chain(
+            roots
+                .roots
+                .iter()
+                .filter(|anchor| anchor.subject.as_ref() == issuer_name)
+                .map(|anchor| {
+                    let mut spki = anchor.subject_public_key_info.to_vec();
+                    wrap_in_sequence(&mut spki);
+                    Cow::Owned(spki)
+                }),
+        );
+
+    let mut single = None;
+    for issuer_spki in issuer_spkis {
+        let issuer_spki = issuer_spki.as_ref();
+        if !response.signed_by(cert.issuer, issuer_spki, supported, provider)
+            && !response
+                .delegated_responders(cert.issuer, issuer_spki, supported, now)
+                .any(|responder| {
+                    response.signed_by(responder.subject, responder.spki, supported, provider)
+                })
+        {
+            continue;
+        }
+
+        single = response.find(&cert, issuer_spki, provider);
+        if single.is_some() {
+            break;
+        }
+    }
+    let single = single.ok_or(CertificateError::InvalidOcspResponse)?;
+
+    let next_update = single
+        .next_update
+        .ok_or(CertificateError::InvalidOcspResponse)?;
+    let (earliest, latest) = (
+        now.as_secs()
+            .saturating_sub(skew.as_secs()),
+        now.as_secs()
+            .saturating_add(skew.as_secs()),
+    );
+    if latest < single.this_update.as_secs() || earliest >= next_update.as_secs() {
+        return Err(CertificateError::StaleOcspResponse.into());
+    }
+
+    match single.status {
+        CertStatus::Good => Ok(()),
+        CertStatus::Revoked => Err(CertificateError::Revoked.into()),
+        CertStatus::Unknown => Err(CertificateError::InvalidOcspResponse.into()),
+    }
+}
+
+/// The parts of a `BasicOCSPResponse` needed to validate it.
+struct BasicResponse<'a> {
+    /// The signed `ResponseData`.
+    signed: Signed<'a>,
+    /// Who says they signed it.
+    responder_id: ResponderId<'a>,
+    /// The contents of the `SEQUENCE OF SingleResponse`.
+    responses: &'a [u8],
+    /// The contents of the `SEQUENCE OF Certificate`, or empty.
+    certs: &'a [u8],
+}
+
+impl<'a> BasicResponse<'a> {
+    /// Parse an `OCSPResponse`, which must be successful and of the basic type.
+    fn parse(der: &'a [u8]) -> Option<Self> {
+        let response = match split_tlv(der)? {
+            (DER_SEQUENCE_TAG, contents, []) => contents,
+            _ => return None,
+        };
+        let bytes = match split_tlv(response)? {
+            (DER_ENUMERATED_TAG, [SUCCESSFUL], rest) => rest,
+            _ => return None,
+        };
+        let bytes = match split_tlv(bytes)? {
+            (DER_EXPLICIT_RESPONSE_BYTES_TAG, contents, []) => expect_sequence(contents)?,
+            _ => return None,
+        };
+        let basic = match split_tlv(bytes)? {
+            (DER_OID_TAG, OID_PKIX_OCSP_BASIC, rest) => match split_tlv(rest)? {
+                (DER_OCTET_STRING_TAG, basic, []) => basic,
+                _ => return None,
+            },
+            _ => return None,
+        };
+        let basic = match split_tlv(basic)? {
+            (DER_SEQUENCE_TAG, contents, []) => contents,
+            _ => return None,
+        };
+
+        let (signed, rest) = Signed::split(basic)?;
+        let certs = match rest {
+            [] => &[][..],
+            _ => match split_tlv(rest)? {
+                (DER_EXPLICIT_CERTS_TAG, certs, []) => expect_sequence(certs)?,
+                _ => return None,
+            },
+        };
+
+        // Skip the explicitly tagged version, and producedAt.
+        let mut data = expect_sequence(signed.tbs)?;
+        if data.first() == Some(&DER_EXPLICIT_VERSION_TAG) {
+            data = split_tlv(data)?.2;
+        }
+        let (responder_id, data) = match split_tlv(data)? {
+            (DER_EXPLICIT_BY_NAME_TAG, name, rest) => match split_raw_tlv(name)? {
+                (name, []) => (ResponderId::ByName(name), rest),
+                _ => return None,
+            },
+            (DER_EXPLICIT_BY_KEY_TAG, key_hash, rest) => match split_tlv(key_hash)? {
+                (DER_OCTET_STRING_TAG, key_hash, []) => (ResponderId::ByKey(key_hash), rest),
+                _ => return None,
+            },
+            _ => return None,
+        };
+        let data = split_tlv(data)?.2;
+        let responses = expect_sequence(data)?;
+
+        Some(Self {
+            signed,
+            responder_id,
+            responses,
+            certs,
+        })
+    }
+
+    /// Find the `SingleResponse` for `cert`, issued by the holder of `issuer_spki`.
+    fn find(
+        &self,
+        cert: &Certificate<'_>,
+        issuer_spki: &[u8],
+        provider: &dyn CryptoProvider,
+    ) -> Option<SingleResponse> {
+        let (_, issuer_key) = split_subject_public_key_info(issuer_spki)?;
+        let mut responses = self.responses;
+        while !responses.is_empty() {
+            let (single, rest) = split_sequence(responses)?;
+            responses = rest;
+
+            let (cert_id, single) = split_sequence(single)?;
+            let (hash_algorithm, cert_id) = split_sequence(cert_id)?;
+            let (issuer_name_hash, cert_id) = match split_tlv(cert_id)? {
+                (DER_OCTET_STRING_TAG, hash, rest) => (hash, rest),
+                _ => return None,
+            };
+            let (issuer_key_hash, cert_id) = match split_tlv(cert_id)? {
+                (DER_OCTET_STRING_TAG, hash, rest) => (hash, rest),
+                _ => return None,
+            };
+            match split_tlv(cert_id)? {
+                (DER_INTEGER_TAG, serial, []) if serial == cert.serial => {}
+                (DER_INTEGER_TAG, _, []) => continue,
+                _ => return None,
+            }
+            if !cert_id_hash_matches(provider, hash_algorithm, cert.issuer, issuer_name_hash)
+                || !cert_id_hash_matches(provider, hash_algorithm, issuer_key, issuer_key_hash)
+            {
+                continue;
+            }
+
+            let (status, single) = match split_tlv(single)? {
+                (DER_CERT_STATUS_GOOD_TAG, [], rest) => (CertStatus::Good, rest),
+                (DER_CERT_STATUS_REVOKED_TAG, _, rest) => (CertStatus::Revoked, rest),
+                (DER_CERT_STATUS_UNKNOWN_TAG, [], rest) => (CertStatus::Unknown, rest),
+                _ => return None,
+            };
+            let (tag, this_update, single) = split_tlv(single)?;
+            let this_update = parse_time(tag, this_update)?;
+            let next_update = match split_tlv(single) {
+                Some((DER_EXPLICIT_NEXT_UPDATE_TAG, next_update, _)) => {
+                    let (tag, next_update, _) = split_tlv(next_update)?;
+                    Some(parse_time(tag, next_update)?)
+                }
+                _ => None,
+            };
+
+            return Some(SingleResponse {
+                status,
+                this_update,
+                next_update,
+            });
+        }
+
+        None
+    }
+
+    /// Is the response signed by `subject`, with the key in `spki`?
+    ///
+    /// The `responderID` must name `subject` or its key.
+    fn signed_by(
+        &self,
+        subject: &[u8],
+        spki: &[u8],
+        supported: &WebPkiSupportedAlgorithms,
+        provider: &dyn CryptoProvider,
+    ) -> bool {
+        let names_signer = match self.responder_id {
+            ResponderId::ByName(name) => name == subject,
+            ResponderId::ByKey(key_hash) => match split_subject_public_key_info(spki) {
+                Some((_, key)) => hash_matches(provider, HashAlgorithm::SHA1, key, key_hash),
+                None => false,
+            },
+        };
+        names_signer && signed_by(&self.signed, spki, supported)
+    }
+
+    /// Iterate over the certificates included in the response that were
+    /// issued by `issuer` for OCSP signing, and are valid at `now`.
+    fn delegated_responders(
+        &self,
+        issuer: &'a [u8],
+        issuer_spki: &'a [u8],
+        supported: &'a WebPkiSupportedAlgorithms,
+        now: UnixTime,
+    ) -> impl Iterator<Item = Certificate<'a>> + 'a {
+        let mut certs = self.certs;
+        core::iter::from_fn(move || {
+            let (cert, rest) = split_raw_tlv(certs)?;
+            certs = rest;
+            Some(Certificate::parse(cert))
+        })
+        .flatten()
+        .filter(move |cert| {
+            cert.issuer == issuer
+                && cert
+                    .not_before()
+                    .map_or(false, |t| t.as_secs() <= now.as_secs())
+                && cert
+                    .not_after()
+                    .map_or(false, |t| now.as_secs() < t.as_secs())
+                && has_ocsp_signing_purpose(cert) == Some(true)
+                && signed_by(&cert.signed, issuer_spki, supported)
+        })
+    }
+}
+
+/// The `ResponderID` of a `ResponseData`.
+enum ResponderId<'a> {
+    /// The responder's `Name`, including its outer `SEQUENCE`.
+    ByName(&'a [u8]),
+    /// The SHA-1 hash of the responder's public key.
+    ByKey(&'a [u8]),
+}
+
+struct SingleResponse {
+    status: CertStatus,
+    this_update: UnixTime,
+    next_update: Option<UnixTime>,
+}
+
+enum CertStatus {
+    Good,
+    Revoked,
+    Unknown,
+}
+
+/// Does the extended key usage extension of `cert` include OCSP signing?
+///
+/// Returns `None` if the extensions cannot be parsed.
+fn has_ocsp_signing_purpose(cert: &Certificate<'_>) -> Option<bool> {
+    let mut purposes = match cert.extension(OID_EXTENDED_KEY_USAGE)? {
+        Some(value) => expect_sequence(value)?,
+        None => return Some(false),
+    };
+    while !purposes.is_empty() {
+        match split_tlv(purposes)? {
+            (DER_OID_TAG, OID_OCSP_SIGNING, _) => return Some(true),
+            (DER_OID_TAG, _, rest) => purposes = rest,
+            _ => return None,
+        }
+    }
+    Some(false)
+}
+
+/// Was `signed` signed by the key in the `SubjectPublicKeyInfo` `spki`,
+/// using one of the `supported` algorithms?
+fn signed_by(signed: &Signed<'_>, spki: &[u8], supported: &WebPkiSupportedAlgorithms) -> bool {
+    let (key_algorithm, key) = match split_subject_public_key_info(spki) {
+        Some(parts) => parts,
+        None => return false,
+    };
+
+    supported
+        .all
+        .iter()
+        .filter(|alg| {
+            alg.signature_alg_id().as_ref() == signed.signature_algorithm
+                && alg.public_key_alg_id().as_ref() == key_algorithm
+        })
+        .any(|alg| {
+            alg.verify_signature(key, signed.tbs, signed.signature)
+                .is_ok()
+        })
+}
+
+/// Is `expected` the hash of `data`, using the `CertID` hash algorithm
+/// whose `AlgorithmIdentifier` has the contents `algorithm`?
+fn cert_id_hash_matches(
+    provider: &dyn CryptoProvider,
+    algorithm: &[u8],
+    data: &[u8],
+    expected: &[u8],
+) -> bool {
+    let algorithm = match split_tlv(algorithm) {
+        Some((DER_OID_TAG, OID_SHA1, [] | [DER_NULL_TAG, 0])) => HashAlgorithm::SHA1,
+        Some((DER_OID_TAG, OID_SHA256, [] | [DER_NULL_TAG, 0])) => HashAlgorithm::SHA256,
+        Some((DER_OID_TAG, OID_SHA384, [] | [DER_NULL_TAG, 0])) => HashAlgorithm::SHA384,
+        Some((DER_OID_TAG, OID_SHA512, [] | [DER_NULL_TAG, 0])) => HashAlgorithm::SHA512,
+        _ => return false,
+    };
+    hash_matches(provider, algorithm, data, expected)
+}
+
+/// Is `expected` the hash of `data` with `algorithm`?
+///
+/// This is false if `provider` does not implement `algorithm`.
+fn hash_matches(
+    provider: &dyn CryptoProvider,
+    algorithm: HashAlgorithm,
+    data: &[u8],
+    expected: &[u8],
+) -> bool {
+    provider
+        .hash_provider(algorithm)
+        .map_or(false, |hash| hash.hash(data).as_ref() == expected)
+}
+
+const DER_NULL_TAG: u8 = 0x05;
+const DER_ENUMERATED_TAG: u8 = 0x0a;
+const DER_EXPLICIT_RESPONSE_BYTES_TAG: u8 = 0xa0;
+const DER_EXPLICIT_CERTS_TAG: u8 = 0xa0;
+const DER_EXPLICIT_NEXT_UPDATE_TAG: u8 = 0xa0;
+const DER_EXPLICIT_BY_NAME_TAG: u8 = 0xa1;
+const DER_EXPLICIT_BY_KEY_TAG: u8 = 0xa2;
+const DER_CERT_STATUS_GOOD_TAG: u8 = 0x80;
+const DER_CERT_STATUS_REVOKED_TAG: u8 = 0xa1;
+const DER_CERT_STATUS_UNKNOWN_TAG: u8 = 0x82;
+
+/// `OCSPResponseStatus` of a successful response.
+const SUCCESSFUL: u8 = 0;
+
+// id-pkix-ocsp-basic, 1.3.6.1.5.5.7.48.1.1
+const OID_PKIX_OCSP_BASIC: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x30, 0x01, 0x01];
+
+// id-sha1, 1.3.14.3.2.26
+const OID_SHA1: &[u8] = &[0x2b, 0x0e, 0x03, 0x02, 0x1a];
+// id-sha256, 2.16.840.1.101.3.4.2.1
+const OID_SHA256: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01];
+// id-sha384, 2.16.840.1.101.3.4.2.2
+const OID_SHA384: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x02];
+// id-sha512, 2.16.840.1.101.3.4.2.3
+const OID_SHA512: &[u8] = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x03];
+
+// id-ce-extKeyUsage, 2.5.29.37
+const OID_EXTENDED_KEY_USAGE: &[u8] = &[0x55, 0x1d, 0x25];
+
+// id-kp-OCSPSigning, 1.3.6.1.5.5.7.3.9
+const OID_OCSP_SIGNING: &[u8] = &[0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x03, 0x09];
+
+#[cfg(all(test, feature = "ring"))]
+mod tests {
+    use super::*;
+    use crate::crypto::ring::RING;
+    use crate::webpki::verify::SUPPORTED_SIG_ALGS;
+
+    use pki_types::CertificateDer;
+
+    fn der(pem: &[u8]) -> CertificateDer<'static> {
+        rustls_pemfile::certs(&mut &pem[..])
+            .next()
+            .unwrap()
+            .unwrap()
+    }
+
+    #[test]
+    fn cert_id_must_name_the_issuer() {
+        let end = der(include_bytes!("../../../test-ca/rsa/end.cert"));
+        let inter = der(include_bytes!("../../../test-ca/rsa/inter.cert"));
+        let ca = der(include_bytes!("../../../test-ca/rsa/ca.cert"));
+
+        let end = Certificate::parse(&end).unwrap();
+        let inter = Certificate::parse(&inter).unwrap();
+        let ca = Certificate::parse(&ca).unwrap();
+        for ocsp in [
+            &include_bytes!("../../../test-ca/rsa/end.ocsp.good.der")[..],
+            &include_bytes!("../../../test-ca/rsa/end.ocsp.sha256.der")[..],
+        ] {
+            let response = BasicResponse::parse(ocsp).unwrap();
+            assert!(response
+                .find(&end, inter.spki, RING)
+                .is_some());
+            // Same serial, but another issuer's key.
+            assert!(response
+                .find(&end, ca.spki, RING)
+                .is_none());
+        }
+    }
+
+    #[test]
+    fn responder_id_must_name_the_signer() {
+        let inter = der(include_bytes!("../../../test-ca/rsa/inter.cert"));
+        let ocsp = der(include_bytes!("../../../test-ca/rsa/ocsp.cert"));
+        let inter = Certificate::parse(&inter).unwrap();
+        let ocsp = Certificate::parse(&ocsp).unwrap();
+
+        // By name.
+        let response =
+            BasicResponse::parse(include_bytes!("../../../test-ca/rsa/end.ocsp.good.der")).unwrap();
+        assert!(response.signed_by(inter.subject, inter.spki, &SUPPORTED_SIG_ALGS, RING));
+        assert!(!response.signed_by(ocsp.subject, inter.spki, &SUPPORTED_SIG_ALGS, RING));
+
+        // By key.
+        let response =
+            BasicResponse::parse(include_bytes!("../../../test-ca/rsa/end.ocsp.sha256.der"))
+                .unwrap();
+        assert!(matches!(response.responder_id, ResponderId::ByKey(_)));
+        assert!(response.signed_by(inter.subject, inter.spki, &SUPPORTED_SIG_ALGS, RING));
+
+        // Signed by the issuer, but naming the delegated responder.
+        let response = BasicResponse::parse(include_bytes!(
+            "../../../test-ca/rsa/end.ocsp.wrongresponder.der"
+        ))
+        .unwrap();
+        assert!(!response.signed_by(inter.subject, inter.spki, &SUPPORTED_SIG_ALGS, RING));
+    }
+}
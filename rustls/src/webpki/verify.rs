@@ -1,5 +1,6 @@
 use alloc::sync::Arc;
 use core::fmt;
+use core::time::Duration;
 
 use pki_types::{CertificateDer, SignatureVerificationAlgorithm, UnixTime};
 #[cfg(feature = "ring")]
@@ -7,17 +8,22 @@ use webpki::ring as webpki_algs;
 
 use super::anchors::RootCertStore;
 use super::client_verifier_builder::ClientCertVerifierBuilder;
+use super::ocsp::verify_ocsp_response;
 use super::pki_error;
 use crate::client::ServerName;
+use crate::common_state::Side;
+use crate::crypto::{hash, CryptoProvider};
 use crate::enums::SignatureScheme;
 use crate::error::{CertRevocationListError, CertificateError, Error, PeerMisbehaved};
 #[cfg(feature = "logging")]
-use crate::log::trace;
+use crate::log::{trace, warn};
 use crate::msgs::handshake::DistinguishedName;
+use crate::tls13::{client_verify_message, server_verify_message};
 use crate::verify::{
     ClientCertVerified, ClientCertVerifier, DigitallySignedStruct, HandshakeSignatureValid,
     NoClientAuth, ServerCertVerified, ServerCertVerifier,
 };
+use crate::x509;
 
 /// Verify that the end-entity certificate `end_entity` is a valid server cert
 /// and chains to at least one of the trust anchors in the `roots` [RootCertStore].
@@ -46,6 +52,84 @@ pub fn verify_server_cert_signed_by_trust_anchor(
         .map(|_| ())
 }
 
+/// Check that none of `end_entity` and `intermediates` are signed with SHA-1.
+///
+/// A presented copy of one of the `roots` is not checked, because its
+/// self-signature is not relied upon.  Certificates that cannot be parsed
+/// are left for the path validation to reject.
+fn check_no_sha1_signatures(
+    end_entity: &CertificateDer<'_>,
+    intermediates: &[CertificateDer<'_>],
+    roots: &RootCertStore,
+) -> Result<(), Error> {
+    if core::iter::once(end_entity)
+        .chain(intermediates)
+        .any(|cert| x509::signed_with_sha1(cert) == Some(true) && !is_trust_anchor(cert, roots))
+    {
+        return Err(CertificateError::Sha1Signature.into());
+    }
+    Ok(())
+}
+
+/// Is `cert` one of the `roots`, by subject and public key?
+fn is_trust_anchor(cert: &CertificateDer<'_>, roots: &RootCertStore) -> bool {
+    let anchor = match webpki::extract_trust_anchor(cert) {
+        Ok(anchor) => anchor,
+        Err(_) => return false,
+    };
+
+    roots.roots.iter().any(|root| {
+        root.subject.as_ref() == anchor.subject.as_ref()
+            && root.subject_public_key_info.as_ref() == anchor.subject_public_key_info.as_ref()
+    })
+}
+
+/// Check the serial numbers of `end_entity` and `intermediates` conform to RFC 5280.
+///
+/// Non-conforming serial numbers are an error unless `allow` is true, when
+/// they are only logged.  Certificates that cannot be parsed are left to
+/// path validation.
+fn check_serial_numbers(
+    end_entity: &CertificateDer<'_>,
+    intermediates: &[CertificateDer<'_>],
+    allow: bool,
+) -> Result<(), Error> {
+    if core::iter::once(end_entity)
+        .chain(intermediates)
+        .any(|cert| x509::serial_number_conforms(cert) == Some(false))
+    {
+        if !allow {
+            return Err(CertificateError::NonConformingSerialNumber.into());
+        }
+        warn!("accepting certificate chain with non-conforming serial number");
+    }
+    Ok(())
+}
+
+/// Check that `end_entity` asserts at least one of the `required` certificate policies.
+///
+/// An empty `required` list imposes no requirement.  `anyPolicy` is not
+/// treated specially: it only satisfies the requirement if it is listed.
+fn check_required_policies(
+    end_entity: &CertificateDer<'_>,
+    required: &[Vec<u8>],
+) -> Result<(), Error> {
+    if required.is_empty() {
+        return Ok(());
+    }
+
+    match x509::certificate_policies(end_entity) {
+        Some(policies)
+            if policies
+                .iter()
+                .any(|policy| required.iter().any(|r| r == policy)) =>
+        {
+            Ok(())
+        }
+        _ => Err(CertificateError::RequiredPolicyNotAsserted.into()),
+    }
+}
+
 /// Verify that the `end_entity` has a name or alternative name matching the `server_name`
 /// note: this only verifies the name and should be used in conjuction with more verification
 /// like [verify_server_cert_signed_by_trust_anchor]
@@ -88,19 +172,58 @@ impl ServerCertVerifier for WebPkiServerVerifier {
     ) -> Result<ServerCertVerified, Error> {
         let cert = ParsedCertificate::try_from(end_entity)?;
 
-        verify_server_cert_signed_by_trust_anchor(
-            &cert,
-            &self.roots,
+        if self.reject_sha1_signatures {
+            check_no_sha1_signatures(end_entity, intermediates, &self.roots)?;
+        }
+        check_serial_numbers(
+            end_entity,
             intermediates,
-            now,
-            self.supported.all,
+            self.allow_nonconforming_serial_numbers,
         )?;
 
+        let algs = restrict_algorithms(&self.supported, &self.signature_schemes);
+        let verify_at = |now| {
+            verify_server_cert_signed_by_trust_anchor(&cert, &self.roots, intermediates, now, &algs)
+        };
+
+        // If the certificate is only invalid because of the time, allow for
+        // our clock being up to `allowed_clock_skew` fast or slow.
+        let skew = self.allowed_clock_skew;
+        match verify_at(now) {
+            Err(Error::InvalidCertificate(CertificateError::Expired)) if !skew.is_zero() => {
+                verify_at(UnixTime::since_unix_epoch(Duration::from_secs(
+                    now.as_secs()
+                        .saturating_sub(skew.as_secs()),
+                )))
+            }
+            Err(Error::InvalidCertificate(CertificateError::NotValidYet)) if !skew.is_zero() => {
+                verify_at(UnixTime::since_unix_epoch(Duration::from_secs(
+                    now.as_secs()
+                        .saturating_add(skew.as_secs()),
+                )))
+            }
+            result => result,
+        }?;
+
         if !ocsp_response.is_empty() {
-            trace!("Unvalidated OCSP response: {:?}", ocsp_response.to_vec());
+            if let Some(provider) = self.ocsp_provider {
+                verify_ocsp_response(
+                    ocsp_response,
+                    end_entity,
+                    intermediates,
+                    &self.roots,
+                    &self.supported,
+                    provider,
+                    now,
+                    skew,
+                )?;
+            } else {
+                trace!("Unvalidated OCSP response: {:?}", ocsp_response.to_vec());
+            }
         }
 
         verify_server_name(&cert, server_name)?;
+        check_required_policies(end_entity, &self.required_policy_oids)?;
         Ok(ServerCertVerified::assertion())
     }
 
@@ -110,6 +233,7 @@ impl ServerCertVerifier for WebPkiServerVerifier {
         cert: &CertificateDer<'_>,
         dss: &DigitallySignedStruct,
     ) -> Result<HandshakeSignatureValid, Error> {
+        check_scheme_allowed(dss.scheme, &self.signature_schemes)?;
         verify_signed_struct(message, cert, dss, &self.supported)
     }
 
@@ -119,11 +243,12 @@ impl ServerCertVerifier for WebPkiServerVerifier {
         cert: &CertificateDer<'_>,
         dss: &DigitallySignedStruct,
     ) -> Result<HandshakeSignatureValid, Error> {
+        check_scheme_allowed(dss.scheme, &self.signature_schemes)?;
         verify_tls13(message, cert, dss, &self.supported)
     }
 
     fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
-        self.supported.supported_schemes()
+        restrict_schemes(&self.supported, &self.signature_schemes)
     }
 }
 
@@ -132,6 +257,12 @@ impl ServerCertVerifier for WebPkiServerVerifier {
 pub struct WebPkiServerVerifier {
     roots: Arc<RootCertStore>,
     supported: WebPkiSupportedAlgorithms,
+    allowed_clock_skew: Duration,
+    signature_schemes: Option<Vec<SignatureScheme>>,
+    reject_sha1_signatures: bool,
+    allow_nonconforming_serial_numbers: bool,
+    required_policy_oids: Vec<Vec<u8>>,
+    ocsp_provider: Option<&'static dyn CryptoProvider>,
 }
 
 #[allow(unreachable_pub)]
@@ -156,9 +287,124 @@ impl WebPkiServerVerifier {
         Self {
             roots: roots.into(),
             supported,
+            allowed_clock_skew: Duration::ZERO,
+            signature_schemes: None,
+            reject_sha1_signatures: true,
+            allow_nonconforming_serial_numbers: false,
+            required_policy_oids: Vec::new(),
+            ocsp_provider: None,
         }
     }
 
+    /// Tolerate the local clock being up to `skew` ahead of or behind the
+    /// true time when checking certificate validity periods.
+    ///
+    /// Certificates are accepted if they are valid at some time within
+    /// `skew` of the current time.  The default is zero: validity periods
+    /// are checked strictly.  Only whole seconds are significant.
+    pub fn with_allowed_clock_skew(mut self, skew: Duration) -> Self {
+        self.allowed_clock_skew = skew;
+        self
+    }
+
+    /// Only accept server signatures made with one of `schemes`.
+    ///
+    /// This covers the TLS1.3 `CertificateVerify` and TLS1.2 `ServerKeyExchange`
+    /// signatures, and restricts the schemes offered to the server.  The
+    /// signatures in the server's certificate chain are restricted too: each
+    /// must use an algorithm that [`WebPkiSupportedAlgorithms::mapping`] gives
+    /// for one of `schemes`, so an RSA chain needs an RSA PKCS#1 scheme here
+    /// even if the server signs its handshake with RSA-PSS.  It is
+    /// independent of the key exchange groups in the `ClientConfig`: for example,
+    /// a client may use X25519 for key exchange but only accept
+    /// [`SignatureScheme::ECDSA_NISTP384_SHA384`] from ECDSA servers.
+    ///
+    /// Schemes not in the verifier's supported algorithms are ignored.  The
+    /// default is to accept all supported schemes.
+    pub fn with_signature_schemes(mut self, schemes: &[SignatureScheme]) -> Self {
+        self.signature_schemes = Some(schemes.to_vec());
+        self
+    }
+
+    /// Control whether server certificate chains containing SHA-1 signatures are rejected.
+    ///
+    /// When `reject` is true, which is the default, verification fails with
+    /// [`CertificateError::Sha1Signature`] if any certificate sent by the
+    /// server is signed using a SHA-1 based algorithm.  Only the
+    /// self-signature of a trust anchor, if the server sends one, is exempt.
+    ///
+    /// The default algorithms cannot verify SHA-1 based signatures anyway, so
+    /// with them this only makes the error specific: without it, such chains
+    /// fail with [`CertificateError::BadSignature`].  With algorithms given to
+    /// [`Self::new_with_algorithms`] that include a SHA-1 based one, this is
+    /// what keeps those signatures out of certificate chains.
+    pub fn reject_sha1_signatures(mut self, reject: bool) -> Self {
+        self.reject_sha1_signatures = reject;
+        self
+    }
+
+    /// Control whether server certificate chains with non-conforming serial
+    /// numbers are accepted.
+    ///
+    /// RFC 5280 requires serial numbers to be positive integers of at most
+    /// 20 octets, but some CAs issue certificates with negative, zero or
+    /// longer serial numbers.  Unless `allow` is true, verification fails
+    /// with [`CertificateError::NonConformingSerialNumber`] if the end-entity
+    /// certificate or any intermediate sent by the server has one.  When it
+    /// is true, such certificates are accepted with a warning logged.
+    ///
+    /// The default is false.
+    pub fn allow_nonconforming_serial_numbers(mut self, allow: bool) -> Self {
+        self.allow_nonconforming_serial_numbers = allow;
+        self
+    }
+
+    /// Require the server's end-entity certificate to assert at least one of
+    /// the certificate policies in `oids`.
+    ///
+    /// Each policy identifier is given as the contents of its DER encoding,
+    /// without the tag and length: 2.23.140.1.2.1 is
+    /// `&[0x67, 0x81, 0x0c, 0x01, 0x02, 0x01]`.  Verification fails with
+    /// [`CertificateError::RequiredPolicyNotAsserted`] if the certificate's
+    /// policies extension is missing or names none of them.  Only the
+    /// end-entity certificate is checked; policy mapping and constraints in
+    /// the rest of the chain are not processed.  `anyPolicy` only matches if
+    /// listed here.
+    ///
+    /// The default, an empty list, imposes no requirement.
+    pub fn with_required_policy_oids(mut self, oids: &[&[u8]]) -> Self {
+        self.required_policy_oids = oids
+            .iter()
+            .map(|oid| oid.to_vec())
+            .collect();
+        self
+    }
+
+    /// Validate OCSP responses stapled by the server, using the hash
+    /// functions of `provider`.
+    ///
+    /// A stapled response must be signed by the issuer of the server's
+    /// certificate, or by a responder certificate the issuer authorised for
+    /// OCSP signing and that is included in the response, and its
+    /// `responderID` must name that signer.  Its `CertID` must match the
+    /// certificate's serial number, and the hashes of its issuer's name and
+    /// key.  These hashes, and a `responderID` given by key hash, are checked
+    /// with [`CryptoProvider::hash_provider()`]: a response using a hash
+    /// function `provider` lacks is rejected.  The current time must be
+    /// within its `thisUpdate` and `nextUpdate` times, allowing for any
+    /// [clock skew][Self::with_allowed_clock_skew], and it must say the
+    /// certificate is good.  Verification fails with
+    /// [`CertificateError::StaleOcspResponse`] if the response is not
+    /// current, with [`CertificateError::Revoked`] if it says the certificate
+    /// is revoked, and with [`CertificateError::InvalidOcspResponse`] otherwise.
+    ///
+    /// Servers are not required to staple a response: its absence is not
+    /// an error.  By default, stapled responses are ignored.
+    pub fn validate_ocsp_responses(mut self, provider: &'static dyn CryptoProvider) -> Self {
+        self.ocsp_provider = Some(provider);
+        self
+    }
+
     /// A full implementation of `ServerCertVerifier::verify_tls12_signature` or
     /// `ClientCertVerifier::verify_tls12_signature`.
     #[cfg(feature = "ring")]
@@ -181,6 +427,19 @@ impl WebPkiServerVerifier {
         verify_tls13(message, cert, dss, &SUPPORTED_SIG_ALGS)
     }
 
+    /// A full implementation of `ServerCertVerifier::verify_tls13_signature`
+    /// for a server identified by a raw public key, where `spki` is that key.
+    ///
+    /// See [`verify_tls13_signature_with_raw_key`].
+    #[cfg(feature = "ring")]
+    pub fn default_verify_tls13_signature_with_raw_key(
+        message: &[u8],
+        spki: &[u8],
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        verify_tls13_signature_with_raw_key(message, spki, dss, &SUPPORTED_SIG_ALGS)
+    }
+
     /// A full implementation of `ServerCertVerifier::supported_verify_schemes()` or
     /// `ClientCertVerifier::supported_verify_schemes()`.
     #[cfg(feature = "ring")]
@@ -247,6 +506,10 @@ pub struct WebPkiClientVerifier {
     crls: Vec<webpki::OwnedCertRevocationList>,
     anonymous_policy: AnonymousClientPolicy,
     supported_algs: WebPkiSupportedAlgorithms,
+    signature_schemes: Option<Vec<SignatureScheme>>,
+    reject_sha1_signatures: bool,
+    allow_nonconforming_serial_numbers: bool,
+    required_policy_oids: Vec<Vec<u8>>,
 }
 
 impl WebPkiClientVerifier {
@@ -277,22 +540,34 @@ impl WebPkiClientVerifier {
     /// `anonymous_policy` controls whether client authentication is required, or if anonymous
     /// clients can connect.
     /// `supported_algs` is which signature verification algorithms should be used.
+    /// `signature_schemes`, if set, further restricts the schemes accepted for
+    /// the client's `CertificateVerify` signature and certificate chain.
+    /// `reject_sha1_signatures` controls whether client certificate chains
+    /// containing SHA-1 signatures are rejected.
+    /// `allow_nonconforming_serial_numbers` controls whether client
+    /// certificate chains with non-conforming serial numbers are accepted.
+    /// `required_policy_oids`, if not empty, lists certificate policies of
+    /// which the client's certificate must assert at least one.
     pub(crate) fn new(
         roots: Arc<RootCertStore>,
         crls: Vec<webpki::OwnedCertRevocationList>,
         anonymous_policy: AnonymousClientPolicy,
         supported_algs: WebPkiSupportedAlgorithms,
+        signature_schemes: Option<Vec<SignatureScheme>>,
+        reject_sha1_signatures: bool,
+        allow_nonconforming_serial_numbers: bool,
+        required_policy_oids: Vec<Vec<u8>>,
     ) -> Self {
         Self {
-            subjects: roots
-                .roots
-                .iter()
-                .map(|ta| DistinguishedName::in_sequence(ta.subject.as_ref()))
-                .collect(),
+            subjects: roots.subjects(),
             crls,
             roots,
             anonymous_policy,
             supported_algs,
+            signature_schemes,
+            reject_sha1_signatures,
+            allow_nonconforming_serial_numbers,
+            required_policy_oids,
         }
     }
 }
@@ -321,6 +596,15 @@ impl ClientCertVerifier for WebPkiClientVerifier {
     ) -> Result<ClientCertVerified, Error> {
         let cert = ParsedCertificate::try_from(end_entity)?;
 
+        if self.reject_sha1_signatures {
+            check_no_sha1_signatures(end_entity, intermediates, &self.roots)?;
+        }
+        check_serial_numbers(
+            end_entity,
+            intermediates,
+            self.allow_nonconforming_serial_numbers,
+        )?;
+
         #[allow(trivial_casts)] // Cast to &dyn trait is required.
         let crls = self
             .crls
@@ -341,15 +625,17 @@ impl ClientCertVerifier for WebPkiClientVerifier {
 
         cert.0
             .verify_for_usage(
-                self.supported_algs.all,
+                &restrict_algorithms(&self.supported_algs, &self.signature_schemes),
                 &self.roots.roots,
                 intermediates,
                 now,
                 webpki::KeyUsage::client_auth(),
                 revocation,
             )
-            .map_err(pki_error)
-            .map(|_| ClientCertVerified::assertion())
+            .map_err(pki_error)?;
+
+        check_required_policies(end_entity, &self.required_policy_oids)?;
+        Ok(ClientCertVerified::assertion())
     }
 
     fn verify_tls12_signature(
@@ -358,6 +644,7 @@ impl ClientCertVerifier for WebPkiClientVerifier {
         cert: &CertificateDer<'_>,
         dss: &DigitallySignedStruct,
     ) -> Result<HandshakeSignatureValid, Error> {
+        check_scheme_allowed(dss.scheme, &self.signature_schemes)?;
         verify_signed_struct(message, cert, dss, &self.supported_algs)
     }
 
@@ -367,11 +654,12 @@ impl ClientCertVerifier for WebPkiClientVerifier {
         cert: &CertificateDer<'_>,
         dss: &DigitallySignedStruct,
     ) -> Result<HandshakeSignatureValid, Error> {
+        check_scheme_allowed(dss.scheme, &self.signature_schemes)?;
         verify_tls13(message, cert, dss, &self.supported_algs)
     }
 
     fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
-        self.supported_algs.supported_schemes()
+        restrict_schemes(&self.supported_algs, &self.signature_schemes)
     }
 }
 
@@ -547,6 +835,47 @@ fn verify_sig_using_any_alg(
     Err(webpki::Error::UnsupportedSignatureAlgorithmForPublicKey)
 }
 
+/// The schemes in `supported`, in order, less any not in `allowed`.
+fn restrict_schemes(
+    supported: &WebPkiSupportedAlgorithms,
+    allowed: &Option<Vec<SignatureScheme>>,
+) -> Vec<SignatureScheme> {
+    let mut schemes = supported.supported_schemes();
+    if let Some(allowed) = allowed {
+        schemes.retain(|scheme| allowed.contains(scheme));
+    }
+    schemes
+}
+
+/// The algorithms for the schemes in `supported` that are in `allowed`, for
+/// verifying certificate chains.
+fn restrict_algorithms(
+    supported: &WebPkiSupportedAlgorithms,
+    allowed: &Option<Vec<SignatureScheme>>,
+) -> Vec<&'static dyn SignatureVerificationAlgorithm> {
+    match allowed {
+        Some(allowed) => supported
+            .mapping
+            .iter()
+            .filter(|(scheme, _)| allowed.contains(scheme))
+            .flat_map(|(_, algs)| algs.iter().copied())
+            .collect(),
+        None => supported.all.to_vec(),
+    }
+}
+
+fn check_scheme_allowed(
+    scheme: SignatureScheme,
+    allowed: &Option<Vec<SignatureScheme>>,
+) -> Result<(), Error> {
+    match allowed {
+        Some(allowed) if !allowed.contains(&scheme) => {
+            Err(PeerMisbehaved::SignedHandshakeWithUnadvertisedSigScheme.into())
+        }
+        _ => Ok(()),
+    }
+}
+
 fn verify_signed_struct(
     message: &[u8],
     cert: &CertificateDer<'_>,
@@ -580,6 +909,62 @@ fn verify_tls13(
         .map(|_| HandshakeSignatureValid::assertion())
 }
 
+/// Verify a TLS1.3 `CertificateVerify` signature outside of a connection.
+///
+/// `handshake_hash` is the transcript hash up to (but excluding) the
+/// `CertificateVerify` message, and `signer` is the side that produced the
+/// signature: this selects the context string used to construct the signed
+/// message.  The signature is checked against the public key in `cert`,
+/// using `scheme` as mapped by `supported_schemes`.
+///
+/// This does not verify `cert` itself; see [`verify_server_cert_signed_by_trust_anchor`].
+pub fn verify_tls13_certificate_verify(
+    handshake_hash: &hash::Output,
+    signer: Side,
+    cert: &CertificateDer<'_>,
+    scheme: SignatureScheme,
+    signature: &[u8],
+    supported_schemes: &WebPkiSupportedAlgorithms,
+) -> Result<HandshakeSignatureValid, Error> {
+    let message = match signer {
+        Side::Client => client_verify_message(handshake_hash),
+        Side::Server => server_verify_message(handshake_hash),
+    };
+    let dss = DigitallySignedStruct::new(scheme, signature.to_vec());
+    verify_tls13(message.as_ref(), cert, &dss, supported_schemes)
+}
+
+/// Verify a TLS1.3 signature made with a raw public key ([RFC 7250]).
+///
+/// This is the counterpart of [`ServerCertVerifier::verify_tls13_signature`]
+/// for a server identified by a raw public key: `spki` is the DER-encoded
+/// `SubjectPublicKeyInfo` it presented in place of a certificate.  The
+/// signature in `dss` is checked using its scheme as mapped by `supported_schemes`.
+///
+/// [RFC 7250]: https://www.rfc-editor.org/rfc/rfc7250
+pub fn verify_tls13_signature_with_raw_key(
+    message: &[u8],
+    spki: &[u8],
+    dss: &DigitallySignedStruct,
+    supported_schemes: &WebPkiSupportedAlgorithms,
+) -> Result<HandshakeSignatureValid, Error> {
+    if !dss.scheme.supported_in_tls13() {
+        return Err(PeerMisbehaved::SignedHandshakeWithUnadvertisedSigScheme.into());
+    }
+
+    let alg = supported_schemes.convert_scheme(dss.scheme)?[0];
+
+    let (algorithm, key) = x509::split_subject_public_key_info(spki)
+        .ok_or(Error::InvalidCertificate(CertificateError::BadEncoding))?;
+    if algorithm != alg.public_key_alg_id().as_ref() {
+        return Err(CertificateError::BadSignature.into());
+    }
+
+    alg.verify_signature(key, message, dss.signature())
+        .map_err(|_| CertificateError::BadSignature.into())
+        .map(|_| HandshakeSignatureValid::assertion())
+}
+
 /// wrapper around internal representation of a parsed certificate. This is used in order to avoid parsing twice when specifying custom verification
 pub struct ParsedCertificate<'a>(pub(crate) webpki::EndEntityCert<'a>);
 
@@ -693,4 +1078,53 @@ mod tests {
             format!("{:?}", SUPPORTED_SIG_ALGS)
         );
     }
+
+    #[cfg(feature = "ring")]
+    #[test]
+    fn detached_tls13_certificate_verify() {
+        let cert =
+            rustls_pemfile::certs(&mut &include_bytes!("../../../test-ca/ecdsa/end.cert")[..])
+                .next()
+                .unwrap()
+                .unwrap();
+        let key = rustls_pemfile::pkcs8_private_keys(
+            &mut &include_bytes!("../../../test-ca/ecdsa/end.key")[..],
+        )
+        .next()
+        .unwrap()
+        .unwrap();
+        let signer = crate::crypto::ring::sign::any_supported_type(&key.into())
+            .unwrap()
+            .choose_scheme(&[SignatureScheme::ECDSA_NISTP256_SHA256])
+            .unwrap();
+
+        let handshake_hash = hash::Output::new(&[0x5a; 32]);
+        let sig = signer
+            .sign(server_verify_message(&handshake_hash).as_ref())
+            .unwrap();
+        let verify = |signer: Side, sig: &[u8]| {
+            verify_tls13_certificate_verify(
+                &handshake_hash,
+                signer,
+                &cert,
+                SignatureScheme::ECDSA_NISTP256_SHA256,
+                sig,
+                &SUPPORTED_SIG_ALGS,
+            )
+            .map(|_| ())
+        };
+
+        assert_eq!(verify(Side::Server, &sig), Ok(()));
+        assert_eq!(
+            verify(Side::Client, &sig),
+            Err(Error::InvalidCertificate(CertificateError::BadSignature))
+        );
+
+        let mut tampered = sig.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert_eq!(
+            verify(Side::Server, &tampered),
+            Err(Error::InvalidCertificate(CertificateError::BadSignature))
+        );
+    }
 }
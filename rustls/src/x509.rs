@@ -1,5 +1,9 @@
 // Additional x509/asn1 functions to those provided in webpki/ring.
 
+use core::time::Duration;
+
+use pki_types::UnixTime;
+
 pub(crate) fn wrap_in_asn1_len(bytes: &mut Vec<u8>) {
     let len = bytes.len();
 
@@ -23,12 +27,619 @@ pub(crate) fn wrap_in_sequence(bytes: &mut Vec<u8>) {
     bytes.insert(0, DER_SEQUENCE_TAG);
 }
 
-const DER_SEQUENCE_TAG: u8 = 0x30;
+/// Split the DER TLV at the start of `input` into its tag, its contents,
+/// and whatever follows it.
+///
+/// Returns `None` if `input` doesn't start with a well-formed TLV.  Only
+/// single-byte tags are supported.
+pub(crate) fn split_tlv(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = input.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+
+    let (len, rest) = match first {
+        short if short < 0x80 => (short as usize, rest),
+        long @ 0x81..=0x84 => {
+            let octets = (long & 0x7f) as usize;
+            if rest.len() < octets {
+                return None;
+            }
+            let (octets, rest) = rest.split_at(octets);
+            let len = octets
+                .iter()
+                .fold(0usize, |len, &b| (len << 8) | b as usize);
+            (len, rest)
+        }
+        _ => return None,
+    };
+
+    if rest.len() < len {
+        return None;
+    }
+    let (contents, rest) = rest.split_at(len);
+    Some((tag, contents, rest))
+}
+
+/// The fields of a DER-encoded X.509 certificate that are looked at here.
+///
+/// Parsing only checks the outline of the `Certificate` and its
+/// `TBSCertificate`; nothing is validated, and the signature is not checked.
+pub(crate) struct Certificate<'a> {
+    /// The signed `TBSCertificate`.
+    pub(crate) signed: Signed<'a>,
+    /// The contents of the `serialNumber` INTEGER.
+    pub(crate) serial: &'a [u8],
+    /// The issuer `Name`, including its outer `SEQUENCE`.
+    pub(crate) issuer: &'a [u8],
+    /// The contents of the `Validity`.
+    validity: &'a [u8],
+    /// The subject `Name`, including its outer `SEQUENCE`.
+    pub(crate) subject: &'a [u8],
+    /// The `SubjectPublicKeyInfo`, including its outer `SEQUENCE`.
+    pub(crate) spki: &'a [u8],
+    /// The contents of the `SEQUENCE OF Extension`, or empty.
+    extensions: &'a [u8],
+}
+
+impl<'a> Certificate<'a> {
+    pub(crate) fn parse(der: &'a [u8]) -> Option<Self> {
+        let cert = match split_tlv(der)? {
+            (DER_SEQUENCE_TAG, contents, []) => contents,
+            _ => return None,
+        };
+        let (signed, rest) = Signed::split(cert)?;
+        if !rest.is_empty() {
+            return None;
+        }
+
+        // Skip the explicitly tagged version.
+        let mut fields = expect_sequence(signed.tbs)?;
+        if fields.first() == Some(&DER_EXPLICIT_VERSION_TAG) {
+            fields = split_tlv(fields)?.2;
+        }
+        let (serial, fields) = match split_tlv(fields)? {
+            (DER_INTEGER_TAG, serial, rest) => (serial, rest),
+            _ => return None,
+        };
+        // Skip the signature algorithm, which is repeated after the TBSCertificate.
+        let (_, fields) = split_sequence(fields)?;
+        let (issuer, fields) = split_raw_sequence(fields)?;
+        let (validity, fields) = split_sequence(fields)?;
+        let (subject, fields) = split_raw_sequence(fields)?;
+        let (spki, mut fields) = split_raw_sequence(fields)?;
+
+        // Skip the optional issuerUniqueID and subjectUniqueID.
+        let mut extensions = &[][..];
+        while !fields.is_empty() {
+            let (tag, contents, rest) = split_tlv(fields)?;
+            if tag == DER_EXPLICIT_EXTENSIONS_TAG {
+                extensions = expect_sequence(contents)?;
+            }
+            fields = rest;
+        }
+
+        Some(Self {
+            signed,
+            serial,
+            issuer,
+            validity,
+            subject,
+            spki,
+            extensions,
+        })
+    }
+
+    pub(crate) fn not_before(&self) -> Option<UnixTime> {
+        let (tag, not_before, _) = split_tlv(self.validity)?;
+        parse_time(tag, not_before)
+    }
+
+    pub(crate) fn not_after(&self) -> Option<UnixTime> {
+        let (_, _, validity) = split_tlv(self.validity)?;
+        let (tag, not_after, _) = split_tlv(validity)?;
+        parse_time(tag, not_after)
+    }
+
+    /// Find the extension identified by `oid`, returning the contents of
+    /// its `extnValue` OCTET STRING.
+    ///
+    /// Returns `Some(None)` if the certificate has no such extension, and
+    /// `None` if its extensions cannot be parsed.
+    pub(crate) fn extension(&self, oid: &[u8]) -> Option<Option<&'a [u8]>> {
+        let mut extensions = self.extensions;
+        while !extensions.is_empty() {
+            let (extension, rest) = split_sequence(extensions)?;
+            extensions = rest;
+
+            let (tag, this_oid, extension) = split_tlv(extension)?;
+            if tag != DER_OID_TAG {
+                return None;
+            }
+            if this_oid != oid {
+                continue;
+            }
+
+            // Skip the optional critical flag.
+            let (mut tag, mut value, mut extension) = split_tlv(extension)?;
+            if tag == DER_BOOLEAN_TAG {
+                (tag, value, extension) = split_tlv(extension)?;
+            }
+            if tag != DER_OCTET_STRING_TAG || !extension.is_empty() {
+                return None;
+            }
+            return Some(Some(value));
+        }
+
+        Some(None)
+    }
+}
+
+/// Some DER-encoded data and a signature over it.
+pub(crate) struct Signed<'a> {
+    /// The whole of the signed data, including its tag and length.
+    pub(crate) tbs: &'a [u8],
+    /// The contents of the signature's `AlgorithmIdentifier`.
+    pub(crate) signature_algorithm: &'a [u8],
+    /// The signature, without the unused-bits octet of its BIT STRING.
+    pub(crate) signature: &'a [u8],
+}
+
+impl<'a> Signed<'a> {
+    /// Split the contents of a signed `SEQUENCE` into the signed data and
+    /// whatever follows its signature.
+    pub(crate) fn split(input: &'a [u8]) -> Option<(Self, &'a [u8])> {
+        let (tbs, rest) = split_raw_tlv(input)?;
+        let (signature_algorithm, rest) = split_sequence(rest)?;
+        match split_tlv(rest)? {
+            (DER_BIT_STRING_TAG, [0, signature @ ..], rest) => Some((
+                Self {
+                    tbs,
+                    signature_algorithm,
+                    signature,
+                },
+                rest,
+            )),
+            _ => None,
+        }
+    }
+}
+
+/// Find the `notAfter` time in the validity period of the DER-encoded
+/// X.509 certificate `der`.
+///
+/// This does no other validation of the certificate.
+pub(crate) fn certificate_not_after(der: &[u8]) -> Option<UnixTime> {
+    Certificate::parse(der)?.not_after()
+}
+
+/// Does the DER-encoded X.509 certificate `der` have a serial number that
+/// conforms to RFC 5280: a positive integer of no more than 20 octets?
+///
+/// The leading zero octet needed to encode a positive number whose top bit
+/// is set is not counted.  Returns `None` if the certificate cannot be parsed.
+pub(crate) fn serial_number_conforms(der: &[u8]) -> Option<bool> {
+    serial_conforms(Certificate::parse(der)?.serial)
+}
+
+fn serial_conforms(serial: &[u8]) -> Option<bool> {
+    let magnitude = match serial {
+        [] => return None,
+        [first, ..] if first & 0x80 != 0 => return Some(false),
+        [0, rest @ ..] => rest,
+        _ => serial,
+    };
+    Some(!magnitude.is_empty() && magnitude.len() <= 20 && magnitude.iter().any(|b| *b != 0))
+}
+
+/// Find the issuer of the DER-encoded X.509 certificate `der`.
+///
+/// The issuer is returned as the DER encoding of its `Name`, including the
+/// outer `SEQUENCE`.  This does no other validation of the certificate.
+pub(crate) fn certificate_issuer(der: &[u8]) -> Option<&[u8]> {
+    Some(Certificate::parse(der)?.issuer)
+}
+
+/// Split the DER-encoded `SubjectPublicKeyInfo` `der` into the contents of
+/// its `AlgorithmIdentifier` and its `subjectPublicKey`.
+///
+/// The key is returned without the unused-bits octet of the BIT STRING,
+/// which must be zero.  This does no other validation of the key.
+pub(crate) fn split_subject_public_key_info(der: &[u8]) -> Option<(&[u8], &[u8])> {
+    let spki = match split_tlv(der)? {
+        (DER_SEQUENCE_TAG, contents, []) => contents,
+        _ => return None,
+    };
+    let (algorithm, rest) = match split_tlv(spki)? {
+        (DER_SEQUENCE_TAG, contents, rest) => (contents, rest),
+        _ => return None,
+    };
+    match split_tlv(rest)? {
+        (DER_BIT_STRING_TAG, [0, key @ ..], []) => Some((algorithm, key)),
+        _ => None,
+    }
+}
+
+/// Is the DER-encoded X.509 certificate `der` signed using a SHA-1 based
+/// signature algorithm?
+///
+/// Returns `None` if the certificate cannot be parsed.
+pub(crate) fn signed_with_sha1(der: &[u8]) -> Option<bool> {
+    is_sha1_algorithm(
+        Certificate::parse(der)?
+            .signed
+            .signature_algorithm,
+    )
+}
+
+/// List the policy identifiers in the certificate policies extension of
+/// the DER-encoded X.509 certificate `der`.
+///
+/// Each identifier is the contents of a DER OID, without its tag and length.
+/// A certificate without the extension has no policies.  Returns `None` if
+/// the certificate cannot be parsed.
+pub(crate) fn certificate_policies(der: &[u8]) -> Option<Vec<&[u8]>> {
+    let value = match Certificate::parse(der)?.extension(OID_CERTIFICATE_POLICIES)? {
+        Some(value) => value,
+        None => return Some(Vec::new()),
+    };
+
+    let mut policies = expect_sequence(value)?;
+    let mut oids = Vec::new();
+    while !policies.is_empty() {
+        let (policy, rest) = split_sequence(policies)?;
+        policies = rest;
+
+        match split_tlv(policy)? {
+            (DER_OID_TAG, oid, _) => oids.push(oid),
+            _ => return None,
+        }
+    }
+    Some(oids)
+}
+
+/// Does the contents of the `AlgorithmIdentifier` `algorithm` name a
+/// SHA-1 based signature algorithm?
+fn is_sha1_algorithm(algorithm: &[u8]) -> Option<bool> {
+    let (tag, oid, params) = split_tlv(algorithm)?;
+    if tag != DER_OID_TAG {
+        return None;
+    }
+
+    if SHA1_SIGNATURE_OIDS.contains(&oid) {
+        return Some(true);
+    }
+    if oid != OID_RSASSA_PSS {
+        return Some(false);
+    }
+
+    // RSASSA-PSS-params has an explicitly tagged hashAlgorithm, which
+    // defaults to SHA-1.
+    let params = expect_sequence(params)?;
+    match split_tlv(params) {
+        Some((0xa0, hash_algorithm, _)) => {
+            let hash_algorithm = expect_sequence(hash_algorithm)?;
+            let (_, hash_oid, _) = split_tlv(hash_algorithm)?;
+            Some(hash_oid == OID_SHA1)
+        }
+        _ => Some(true),
+    }
+}
+
+/// Like [`split_tlv`], but returns the whole of the first TLV rather than its contents.
+pub(crate) fn split_raw_tlv(input: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (_, _, rest) = split_tlv(input)?;
+    Some((&input[..input.len() - rest.len()], rest))
+}
+
+pub(crate) fn expect_sequence(input: &[u8]) -> Option<&[u8]> {
+    match split_tlv(input)? {
+        (DER_SEQUENCE_TAG, contents, _) => Some(contents),
+        _ => None,
+    }
+}
+
+pub(crate) fn split_sequence(input: &[u8]) -> Option<(&[u8], &[u8])> {
+    match split_tlv(input)? {
+        (DER_SEQUENCE_TAG, contents, rest) => Some((contents, rest)),
+        _ => None,
+    }
+}
+
+fn split_raw_sequence(input: &[u8]) -> Option<(&[u8], &[u8])> {
+    match input.first() {
+        Some(&DER_SEQUENCE_TAG) => split_raw_tlv(input),
+        _ => None,
+    }
+}
+
+/// Parse a UTCTime or GeneralizedTime, in the restricted forms that
+/// RFC 5280 section 4.1.2.5 permits.
+///
+/// Times before the Unix epoch cannot be represented, and are rejected.
+pub(crate) fn parse_time(tag: u8, value: &[u8]) -> Option<UnixTime> {
+    let (year, rest) = match (tag, value.len()) {
+        (DER_UTC_TIME_TAG, 13) => {
+            let yy = two_digits(&value[..2])?;
+            let year = if yy >= 50 { 1900 + yy } else { 2000 + yy };
+            (year, &value[2..])
+        }
+        (DER_GENERALIZED_TIME_TAG, 15) => {
+            let year = two_digits(&value[..2])? * 100 + two_digits(&value[2..4])?;
+            (year, &value[4..])
+        }
+        _ => return None,
+    };
+
+    if rest[10] != b'Z' {
+        return None;
+    }
+
+    let month = two_digits(&rest[0..2])?;
+    let day = two_digits(&rest[2..4])?;
+    let hours = two_digits(&rest[4..6])?;
+    let minutes = two_digits(&rest[6..8])?;
+    let seconds = two_digits(&rest[8..10])?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    if hours > 23 || minutes > 59 || seconds > 59 {
+        return None;
+    }
+
+    let days = days_since_unix_epoch(year, month, day)?;
+    let secs = days * 86_400 + hours * 3_600 + minutes * 60 + seconds;
+    Some(UnixTime::since_unix_epoch(Duration::from_secs(secs)))
+}
+
+fn two_digits(digits: &[u8]) -> Option<u64> {
+    match digits {
+        [hi @ b'0'..=b'9', lo @ b'0'..=b'9'] => {
+            Some(u64::from(hi - b'0') * 10 + u64::from(lo - b'0'))
+        }
+        _ => None,
+    }
+}
+
+/// Days from 1970-01-01 to the given date, or `None` for dates before 1970.
+///
+/// This is Howard Hinnant's `days_from_civil` algorithm.
+fn days_since_unix_epoch(year: u64, month: u64, day: u64) -> Option<u64> {
+    let year = if month <= 2 {
+        year.checked_sub(1)?
+    } else {
+        year
+    };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let month_from_march = (month + 9) % 12;
+    let day_of_year = (153 * month_from_march + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    (era * 146_097 + day_of_era).checked_sub(719_468)
+}
+
+pub(crate) const DER_SEQUENCE_TAG: u8 = 0x30;
+pub(crate) const DER_OID_TAG: u8 = 0x06;
+const DER_UTC_TIME_TAG: u8 = 0x17;
+const DER_GENERALIZED_TIME_TAG: u8 = 0x18;
+pub(crate) const DER_EXPLICIT_VERSION_TAG: u8 = 0xa0;
+const DER_EXPLICIT_EXTENSIONS_TAG: u8 = 0xa3;
+const DER_BOOLEAN_TAG: u8 = 0x01;
+pub(crate) const DER_INTEGER_TAG: u8 = 0x02;
+const DER_BIT_STRING_TAG: u8 = 0x03;
+pub(crate) const DER_OCTET_STRING_TAG: u8 = 0x04;
+
+const SHA1_SIGNATURE_OIDS: &[&[u8]] = &[
+    // sha1WithRSAEncryption, 1.2.840.113549.1.1.5
+    &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x05],
+    // sha1WithRSASignature (OIW), 1.3.14.3.2.29
+    &[0x2b, 0x0e, 0x03, 0x02, 0x1d],
+    // ecdsa-with-SHA1, 1.2.840.10045.4.1
+    &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x01],
+    // id-dsa-with-sha1, 1.2.840.10040.4.3
+    &[0x2a, 0x86, 0x48, 0xce, 0x38, 0x04, 0x03],
+];
+// id-RSASSA-PSS, 1.2.840.113549.1.1.10
+const OID_RSASSA_PSS: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0a];
+// id-sha1, 1.3.14.3.2.26
+const OID_SHA1: &[u8] = &[0x2b, 0x0e, 0x03, 0x02, 0x1a];
+// id-ce-certificatePolicies, 2.5.29.32
+const OID_CERTIFICATE_POLICIES: &[u8] = &[0x55, 0x1d, 0x20];
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_split_tlv() {
+        assert_eq!(
+            split_tlv(&[0x30, 0x02, 0x01, 0x02, 0x05]),
+            Some((0x30, &[0x01, 0x02][..], &[0x05][..]))
+        );
+
+        let mut val = vec![0x12; 300];
+        wrap_in_sequence(&mut val);
+        let (tag, contents, rest) = split_tlv(&val).unwrap();
+        assert_eq!(tag, 0x30);
+        assert_eq!(contents.len(), 300);
+        assert!(rest.is_empty());
+
+        assert_eq!(split_tlv(&[]), None);
+        assert_eq!(split_tlv(&[0x30]), None);
+        assert_eq!(split_tlv(&[0x30, 0x03, 0x01]), None);
+        assert_eq!(split_tlv(&[0x30, 0x82, 0x01]), None);
+        assert_eq!(split_tlv(&[0x30, 0x80]), None);
+    }
+
+    #[test]
+    fn test_split_subject_public_key_info() {
+        let spki = [
+            0x30, 0x0a, 0x30, 0x03, 0x06, 0x01, 0x2a, 0x03, 0x03, 0x00, 0xaa, 0xbb,
+        ];
+        assert_eq!(
+            split_subject_public_key_info(&spki),
+            Some((&[0x06, 0x01, 0x2a][..], &[0xaa, 0xbb][..]))
+        );
+
+        // unused bits in the key
+        let mut bad = spki;
+        bad[9] = 0x01;
+        assert_eq!(split_subject_public_key_info(&bad), None);
+
+        // trailing data
+        let mut bad = spki.to_vec();
+        bad.push(0x00);
+        assert_eq!(split_subject_public_key_info(&bad), None);
+
+        // not a BIT STRING
+        let mut bad = spki;
+        bad[7] = 0x04;
+        assert_eq!(split_subject_public_key_info(&bad), None);
+
+        assert_eq!(split_subject_public_key_info(&[]), None);
+    }
+
+    #[test]
+    fn test_serial_number_conforms() {
+        assert_eq!(serial_conforms(&[0x01]), Some(true));
+        assert_eq!(serial_conforms(&[0x7f; 20]), Some(true));
+        let mut padded = vec![0x00];
+        padded.extend_from_slice(&[0xff; 20]);
+        assert_eq!(serial_conforms(&padded), Some(true));
+
+        assert_eq!(serial_conforms(&[0x01; 21]), Some(false));
+        assert_eq!(serial_conforms(&[0x80]), Some(false));
+        assert_eq!(serial_conforms(&[0x00]), Some(false));
+        assert_eq!(serial_conforms(&[]), None);
+
+        assert_eq!(
+            serial_number_conforms(include_bytes!("testdata/cert-arstechnica.0.der")),
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn test_certificate_not_after() {
+        let check = |der: &[u8], expected: u64| {
+            assert_eq!(
+                certificate_not_after(der),
+                Some(UnixTime::since_unix_epoch(Duration::from_secs(expected)))
+            );
+        };
+        check(
+            include_bytes!("testdata/cert-rustlang.0.der"),
+            1_665_532_799,
+        );
+        check(include_bytes!("testdata/cert-github.0.der"), 1_648_684_799);
+
+        assert_eq!(certificate_not_after(&[]), None);
+        let mut trailing = include_bytes!("testdata/cert-github.0.der").to_vec();
+        trailing.push(0x00);
+        assert_eq!(certificate_not_after(&trailing), None);
+        assert_eq!(
+            certificate_not_after(include_bytes!("testdata/nistp256key.der")),
+            None
+        );
+    }
+
+    #[test]
+    fn test_signed_with_sha1() {
+        let der = |pem: &[u8]| {
+            rustls_pemfile::certs(&mut &pem[..])
+                .next()
+                .unwrap()
+                .unwrap()
+        };
+
+        let sha1_inter = der(include_bytes!("../../test-ca/rsa/inter.sha1.cert"));
+        assert_eq!(signed_with_sha1(&sha1_inter), Some(true));
+        let sha1_root = der(include_bytes!("../../test-ca/rsa/ca.sha1.cert"));
+        assert_eq!(signed_with_sha1(&sha1_root), Some(true));
+
+        let inter = der(include_bytes!("../../test-ca/rsa/inter.cert"));
+        assert_eq!(signed_with_sha1(&inter), Some(false));
+        let end = der(include_bytes!("../../test-ca/rsa/end.cert"));
+        assert_eq!(signed_with_sha1(&end), Some(false));
+
+        assert_eq!(signed_with_sha1(&[]), None);
+        assert_eq!(
+            signed_with_sha1(include_bytes!("testdata/nistp256key.der")),
+            None
+        );
+    }
+
+    #[test]
+    fn test_certificate_policies() {
+        let der = |pem: &[u8]| {
+            rustls_pemfile::certs(&mut &pem[..])
+                .next()
+                .unwrap()
+                .unwrap()
+        };
+
+        // 2.23.140.1.2.1
+        let end = der(include_bytes!("../../test-ca/rsa/end.policy.cert"));
+        assert_eq!(
+            certificate_policies(&end),
+            Some(vec![&[0x67, 0x81, 0x0c, 0x01, 0x02, 0x01][..]])
+        );
+
+        let end = der(include_bytes!("../../test-ca/rsa/end.cert"));
+        assert_eq!(certificate_policies(&end), Some(vec![]));
+
+        assert_eq!(certificate_policies(&[]), None);
+        assert_eq!(
+            certificate_policies(include_bytes!("testdata/nistp256key.der")),
+            None
+        );
+    }
+
+    #[test]
+    fn test_is_sha1_algorithm() {
+        // id-RSASSA-PSS with default parameters uses SHA-1
+        let mut pss = vec![DER_OID_TAG, OID_RSASSA_PSS.len() as u8];
+        pss.extend_from_slice(OID_RSASSA_PSS);
+        pss.extend_from_slice(&[DER_SEQUENCE_TAG, 0x00]);
+        assert_eq!(is_sha1_algorithm(&pss), Some(true));
+
+        // ... but not when it names SHA-256.
+        let mut pss_sha256 = vec![DER_OID_TAG, OID_RSASSA_PSS.len() as u8];
+        pss_sha256.extend_from_slice(OID_RSASSA_PSS);
+        pss_sha256.extend_from_slice(&[
+            0x30, 0x0f, 0xa0, 0x0d, 0x30, 0x0b, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03,
+            0x04, 0x02, 0x01,
+        ]);
+        assert_eq!(is_sha1_algorithm(&pss_sha256), Some(false));
+
+        assert_eq!(is_sha1_algorithm(&[DER_SEQUENCE_TAG, 0x00]), None);
+    }
+
+    #[test]
+    fn test_parse_time() {
+        let secs = |secs| Some(UnixTime::since_unix_epoch(Duration::from_secs(secs)));
+        assert_eq!(parse_time(DER_UTC_TIME_TAG, b"700101000000Z"), secs(0));
+        assert_eq!(
+            parse_time(DER_UTC_TIME_TAG, b"281225154046Z"),
+            secs(1_861_371_646)
+        );
+        assert_eq!(
+            parse_time(DER_GENERALIZED_TIME_TAG, b"20281225154046Z"),
+            secs(1_861_371_646)
+        );
+        assert_eq!(
+            parse_time(DER_GENERALIZED_TIME_TAG, b"20000229235959Z"),
+            secs(951_868_799)
+        );
+        assert_eq!(parse_time(DER_UTC_TIME_TAG, b"691231235959Z"), None);
+        assert_eq!(parse_time(DER_UTC_TIME_TAG, b"550615120000Z"), None);
+        assert_eq!(
+            parse_time(DER_GENERALIZED_TIME_TAG, b"00000101000000Z"),
+            None
+        );
+        assert_eq!(parse_time(DER_UTC_TIME_TAG, b"281225154046+"), None);
+        assert_eq!(parse_time(DER_UTC_TIME_TAG, b"281325154046Z"), None);
+        assert_eq!(parse_time(DER_UTC_TIME_TAG, b"2812251540Z"), None);
+        assert_eq!(parse_time(DER_GENERALIZED_TIME_TAG, b"281225154046Z"), None);
+    }
+
     #[test]
     fn test_empty() {
         let mut val = Vec::new();
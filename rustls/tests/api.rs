@@ -1,7 +1,8 @@
 #![cfg(feature = "ring")]
 #![cfg_attr(read_buf, feature(read_buf))]
 //! Assorted public API tests.
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
 use std::fmt;
 use std::io::{self, IoSlice, Read, Write};
 use std::mem;
@@ -9,20 +10,28 @@ use std::ops::{Deref, DerefMut};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::time::Duration;
 
-use pki_types::CertificateDer;
-use rustls::client::{ResolvesClientCert, Resumption};
+use pki_types::{CertificateDer, PrivateKeyDer, UnixTime};
+use rustls::client::danger::{
+    HandshakeSignatureValid, NormalizesServerName, ServerCertVerified, ServerCertVerifier,
+};
+use rustls::client::{ResolvesClientCert, Resumption, Tls13PskKeyExchangeModes};
 use rustls::crypto::ring::ALL_CIPHER_SUITES;
 use rustls::internal::msgs::base::Payload;
 use rustls::internal::msgs::codec::Codec;
 use rustls::internal::msgs::enums::AlertLevel;
 use rustls::internal::msgs::message::PlainMessage;
-use rustls::server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier};
+use rustls::server::danger::{ClientCertVerified, ClientCertVerifier};
+use rustls::server::{
+    ClientHello, ResolvesClientCertVerifier, ResolvesServerCert, WebPkiClientVerifier,
+};
 use rustls::ConnectionTrafficSecrets;
 use rustls::SupportedCipherSuite;
 use rustls::{
-    sign, AlertDescription, CertificateError, ConnectionCommon, ContentType, Error, KeyLog,
-    PeerIncompatible, PeerMisbehaved, SideData,
+    sign, Alert, AlertDescription, CertificateError, ConnectionCommon, ContentType,
+    DistinguishedName, Error, KeyLog, PeerIncompatible, PeerMisbehaved, RecordPaddingPolicy,
+    SideData, TrustedAuthority,
 };
 use rustls::{CipherSuite, ProtocolVersion, SignatureScheme};
 use rustls::{ClientConfig, ClientConnection};
@@ -98,6 +107,79 @@ fn alpn() {
     );
 }
 
+#[test]
+fn alpn_fallback_is_used_when_nothing_matches() {
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.alpn_protocols = vec![b"server-proto".to_vec()];
+    server_config.alpn_fallback = Some(b"fallback".to_vec());
+    let server_config = Arc::new(server_config);
+
+    for version in rustls::ALL_VERSIONS {
+        let mut client_config = make_client_config_with_versions(KeyType::Rsa, &[version]);
+        client_config.alpn_protocols = vec![b"client-proto".to_vec()];
+
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(server.alpn_protocol(), Some(&b"fallback"[..]));
+        // an unoffered protocol can't be sent to the client
+        assert_eq!(client.alpn_protocol(), None);
+
+        // a match is still preferred
+        let mut client_config = make_client_config_with_versions(KeyType::Rsa, &[version]);
+        client_config.alpn_protocols = vec![b"client-proto".to_vec(), b"server-proto".to_vec()];
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(server.alpn_protocol(), Some(&b"server-proto"[..]));
+        assert_eq!(client.alpn_protocol(), Some(&b"server-proto"[..]));
+    }
+}
+
+#[test]
+fn alpn_common_protocols() {
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.alpn_protocols = vec![
+        b"server-only".to_vec(),
+        b"proto-b".to_vec(),
+        b"proto-a".to_vec(),
+        b"proto-c".to_vec(),
+    ];
+    let server_config = Arc::new(server_config);
+
+    for version in rustls::ALL_VERSIONS {
+        let mut client_config = make_client_config_with_versions(KeyType::Rsa, &[version]);
+        client_config.alpn_protocols = vec![
+            b"proto-a".to_vec(),
+            b"client-only".to_vec(),
+            b"proto-c".to_vec(),
+            b"proto-b".to_vec(),
+        ];
+
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+        assert!(client
+            .alpn_common_protocols()
+            .is_empty());
+        assert!(server
+            .alpn_common_protocols()
+            .is_empty());
+        do_handshake(&mut client, &mut server);
+
+        assert_eq!(server.alpn_protocol(), Some(&b"proto-b"[..]));
+        assert_eq!(
+            server.alpn_common_protocols(),
+            vec![
+                b"proto-b".to_vec(),
+                b"proto-a".to_vec(),
+                b"proto-c".to_vec()
+            ]
+        );
+        assert_eq!(client.alpn_protocol(), Some(&b"proto-b"[..]));
+        assert_eq!(client.alpn_common_protocols(), vec![b"proto-b".to_vec()]);
+    }
+}
+
 fn version_test(
     client_versions: &[&'static rustls::SupportedProtocolVersion],
     server_versions: &[&'static rustls::SupportedProtocolVersion],
@@ -228,6 +310,49 @@ fn config_builder_for_client_rejects_empty_kx_groups() {
     );
 }
 
+#[test]
+fn config_builder_for_client_sets_alpn_protocols() {
+    let protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    let client_config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(get_client_root_store(KeyType::Rsa))
+        .with_alpn_protocols(protocols.clone())
+        .unwrap()
+        .with_no_client_auth();
+    assert_eq!(client_config.alpn_protocols, protocols);
+
+    // A rebuilt config keeps its protocols unless they are replaced.
+    let rebuilt = client_config
+        .to_builder()
+        .with_no_client_auth();
+    assert_eq!(rebuilt.alpn_protocols, protocols);
+    let rebuilt = client_config
+        .to_builder()
+        .with_alpn_protocols(vec![b"http/1.1".to_vec()])
+        .unwrap()
+        .with_no_client_auth();
+    assert_eq!(rebuilt.alpn_protocols, vec![b"http/1.1".to_vec()]);
+
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.alpn_protocol(), Some(&b"http/1.1"[..]));
+
+    for invalid in [vec![], vec![b'a'; 256]] {
+        assert_eq!(
+            ClientConfig::builder()
+                .with_safe_defaults()
+                .with_root_certificates(get_client_root_store(KeyType::Rsa))
+                .with_alpn_protocols(vec![b"h2".to_vec(), invalid])
+                .err(),
+            Some(Error::General(
+                "ALPN protocol IDs must be between 1 and 255 bytes".into()
+            ))
+        );
+    }
+}
+
 #[test]
 fn config_builder_for_client_rejects_empty_cipher_suites() {
     assert_eq!(
@@ -240,6 +365,52 @@ fn config_builder_for_client_rejects_empty_cipher_suites() {
     );
 }
 
+#[test]
+fn config_builder_for_client_rejects_denying_all_cipher_suites() {
+    let all: Vec<_> = rustls::crypto::ring::ALL_CIPHER_SUITES
+        .iter()
+        .map(|scs| scs.suite())
+        .collect();
+    assert_eq!(
+        ClientConfig::builder()
+            .with_safe_default_cipher_suites_except(&all)
+            .err(),
+        Some(Error::General(
+            "all default cipher suites were denied".into()
+        ))
+    );
+}
+
+#[test]
+fn client_hello_omits_denied_cipher_suites() {
+    use rustls::server::Acceptor;
+
+    let denied = CipherSuite::TLS13_AES_128_GCM_SHA256;
+    let client_config = ClientConfig::builder()
+        .with_safe_default_cipher_suites_except(&[denied])
+        .unwrap()
+        .with_safe_default_kx_groups()
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(get_client_root_store(KeyType::Rsa))
+        .with_no_client_auth();
+
+    let mut client =
+        ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
+    let mut buf = Vec::new();
+    client.write_tls(&mut buf).unwrap();
+
+    let mut acceptor = Acceptor::default();
+    acceptor
+        .read_tls(&mut buf.as_slice())
+        .unwrap();
+    let accepted = acceptor.accept().unwrap().unwrap();
+    let ch = accepted.client_hello();
+    let offered = ch.cipher_suites();
+    assert!(!offered.contains(&denied));
+    assert!(offered.contains(&CipherSuite::TLS13_AES_256_GCM_SHA384));
+}
+
 #[cfg(feature = "tls12")]
 #[test]
 fn config_builder_for_client_rejects_incompatible_cipher_suites() {
@@ -716,6 +887,72 @@ fn test_tls13_valid_early_plaintext_alert() {
     );
 }
 
+#[cfg(feature = "tls12")]
+fn plaintext_alert(level: AlertLevel, description: AlertDescription) -> Vec<u8> {
+    <Message as Into<PlainMessage>>::into(Message::build_alert(level, description))
+        .borrow()
+        .to_unencrypted_opaque()
+        .encode()
+}
+
+#[cfg(feature = "tls12")]
+#[test]
+fn connections_record_received_alerts() {
+    let (mut client, mut server) = make_pair_for_configs(
+        make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS12]),
+        make_server_config(KeyType::Rsa),
+    );
+    assert_eq!(client.received_alerts(), &[]);
+
+    // warnings are tolerated in TLS1.2, but still recorded
+    client
+        .read_tls(&mut io::Cursor::new(plaintext_alert(
+            AlertLevel::Warning,
+            AlertDescription::NoRenegotiation,
+        )))
+        .unwrap();
+    client.process_new_packets().unwrap();
+    do_handshake(&mut client, &mut server);
+
+    server.send_close_notify();
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+    assert_eq!(
+        client.received_alerts(),
+        &[
+            Alert {
+                level: AlertLevel::Warning,
+                description: AlertDescription::NoRenegotiation,
+            },
+            Alert {
+                level: AlertLevel::Warning,
+                description: AlertDescription::CloseNotify,
+            },
+        ]
+    );
+    assert_eq!(server.received_alerts(), &[]);
+
+    // a fatal alert remains visible after the connection fails
+    let (_, mut server) = make_pair(KeyType::Rsa);
+    server
+        .read_tls(&mut io::Cursor::new(plaintext_alert(
+            AlertLevel::Fatal,
+            AlertDescription::UnknownCA,
+        )))
+        .unwrap();
+    assert_eq!(
+        server.process_new_packets(),
+        Err(Error::AlertReceived(AlertDescription::UnknownCA)),
+    );
+    assert_eq!(
+        server.received_alerts(),
+        &[Alert {
+            level: AlertLevel::Fatal,
+            description: AlertDescription::UnknownCA,
+        }]
+    );
+}
+
 #[test]
 fn test_tls13_too_short_early_plaintext_alert() {
     let (mut client, mut server) = make_pair(KeyType::Rsa);
@@ -771,6 +1008,7 @@ struct ServerCheckCertResolve {
     expected_sigalgs: Option<Vec<SignatureScheme>>,
     expected_alpn: Option<Vec<Vec<u8>>>,
     expected_cipher_suites: Option<Vec<CipherSuite>>,
+    expected_trusted_ca_names: Option<Vec<DistinguishedName>>,
 }
 
 impl ResolvesServerCert for ServerCheckCertResolve {
@@ -821,6 +1059,23 @@ impl ResolvesServerCert for ServerCheckCertResolve {
             );
         }
 
+        if let Some(expected_names) = &self.expected_trusted_ca_names {
+            let names = client_hello
+                .trusted_ca_keys()
+                .expect("trusted_ca_keys unexpectedly absent")
+                .iter()
+                .map(|authority| match authority {
+                    TrustedAuthority::X509Name(name) => name.as_ref().to_vec(),
+                    other => panic!("unexpected authority {:?}", other),
+                })
+                .collect::<Vec<_>>();
+            let expected_names = expected_names
+                .iter()
+                .map(|name| name.as_ref().to_vec())
+                .collect::<Vec<_>>();
+            assert_eq!(names, expected_names);
+        }
+
         None
     }
 }
@@ -868,122 +1123,682 @@ fn server_cert_resolve_with_alpn() {
 }
 
 #[test]
-fn client_trims_terminating_dot() {
+fn root_cert_store_lists_trust_anchor_subjects() {
+    let mut roots = rustls::RootCertStore::empty();
     for kt in ALL_KEY_TYPES.iter() {
-        let client_config = make_client_config(*kt);
-        let mut server_config = make_server_config(*kt);
-
-        server_config.cert_resolver = Arc::new(ServerCheckCertResolve {
-            expected_sni: Some("some-host.com".into()),
-            ..Default::default()
-        });
+        roots
+            .add(kt.get_chain().pop().unwrap())
+            .unwrap();
+    }
 
-        let mut client =
-            ClientConnection::new(Arc::new(client_config), server_name("some-host.com.")).unwrap();
-        let mut server = ServerConnection::new(Arc::new(server_config)).unwrap();
+    let subjects = roots
+        .iter_subjects()
+        .collect::<Vec<_>>();
+    assert_eq!(subjects.len(), ALL_KEY_TYPES.len());
 
-        let err = do_handshake_until_error(&mut client, &mut server);
-        assert!(err.is_err());
+    for (subject, common_name) in subjects.iter().zip([
+        &b"ponytown RSA CA"[..],
+        &b"ponytown ECDSA CA"[..],
+        &b"ponytown EdDSA CA"[..],
+    ]) {
+        let der = subject.as_ref();
+        // an outer SEQUENCE, ending with the common name
+        assert_eq!(der[0], 0x30);
+        assert!(der.ends_with(common_name));
     }
-}
 
-#[cfg(feature = "tls12")]
-fn check_sigalgs_reduced_by_ciphersuite(
-    kt: KeyType,
-    suite: CipherSuite,
-    expected_sigalgs: Vec<SignatureScheme>,
-) {
-    let client_config = finish_client_config(
-        kt,
-        ClientConfig::builder()
-            .with_cipher_suites(&[find_suite(suite)])
-            .with_safe_default_kx_groups()
-            .with_safe_default_protocol_versions()
-            .unwrap(),
+    assert_eq!(
+        roots
+            .subjects()
+            .iter()
+            .map(|s| s.as_ref().to_vec())
+            .collect::<Vec<_>>(),
+        subjects
+            .iter()
+            .map(|s| s.as_ref().to_vec())
+            .collect::<Vec<_>>()
     );
+}
 
-    let mut server_config = make_server_config(kt);
-
-    server_config.cert_resolver = Arc::new(ServerCheckCertResolve {
-        expected_sigalgs: Some(expected_sigalgs),
-        expected_cipher_suites: Some(vec![suite, CipherSuite::TLS_EMPTY_RENEGOTIATION_INFO_SCSV]),
-        ..Default::default()
-    });
-
-    let mut client =
-        ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
-    let mut server = ServerConnection::new(Arc::new(server_config)).unwrap();
+fn load_single_cert(pem: &[u8]) -> CertificateDer<'static> {
+    rustls_pemfile::certs(&mut &pem[..])
+        .next()
+        .unwrap()
+        .unwrap()
+}
 
-    let err = do_handshake_until_error(&mut client, &mut server);
-    assert!(err.is_err());
+fn sha1_signed_rsa_intermediate() -> CertificateDer<'static> {
+    load_single_cert(include_bytes!("../../test-ca/rsa/inter.sha1.cert"))
 }
 
-#[cfg(feature = "tls12")]
 #[test]
-fn server_cert_resolve_reduces_sigalgs_for_rsa_ciphersuite() {
-    check_sigalgs_reduced_by_ciphersuite(
-        KeyType::Rsa,
-        CipherSuite::TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256,
-        vec![
-            SignatureScheme::RSA_PSS_SHA512,
-            SignatureScheme::RSA_PSS_SHA384,
-            SignatureScheme::RSA_PSS_SHA256,
-            SignatureScheme::RSA_PKCS1_SHA512,
-            SignatureScheme::RSA_PKCS1_SHA384,
-            SignatureScheme::RSA_PKCS1_SHA256,
-        ],
+fn server_verifier_rejects_sha1_signed_intermediate() {
+    let chain = KeyType::Rsa.get_chain();
+    let mut roots = rustls::RootCertStore::empty();
+    roots
+        .add(chain.last().unwrap().clone())
+        .unwrap();
+    let roots = Arc::new(roots);
+    let sha1_inter = sha1_signed_rsa_intermediate();
+
+    let verify = |verifier: &rustls::client::WebPkiServerVerifier,
+                  intermediate: &CertificateDer<'static>| {
+        verifier.verify_server_cert(
+            &chain[0],
+            std::slice::from_ref(intermediate),
+            &server_name("testserver.com"),
+            &[],
+            UnixTime::now(),
+        )
+    };
+
+    let verifier = rustls::client::WebPkiServerVerifier::new(roots.clone());
+    assert!(verify(&verifier, &chain[1]).is_ok());
+    assert_eq!(
+        verify(&verifier, &sha1_inter).err(),
+        Some(Error::InvalidCertificate(CertificateError::Sha1Signature))
+    );
+
+    // a copy of the trust anchor, though self-signed with SHA-1, is not relied upon
+    let sha1_root = load_single_cert(include_bytes!("../../test-ca/rsa/ca.sha1.cert"));
+    assert!(verifier
+        .verify_server_cert(
+            &chain[0],
+            &[chain[1].clone(), sha1_root.clone()],
+            &server_name("testserver.com"),
+            &[],
+            UnixTime::now(),
+        )
+        .is_ok());
+
+    // but being self-issued does not exempt a certificate that is not a trust anchor
+    let mut other_roots = rustls::RootCertStore::empty();
+    other_roots
+        .add(
+            KeyType::Ecdsa
+                .get_chain()
+                .pop()
+                .unwrap(),
+        )
+        .unwrap();
+    assert_eq!(
+        rustls::client::WebPkiServerVerifier::new(other_roots)
+            .verify_server_cert(
+                &chain[0],
+                &[chain[1].clone(), sha1_root],
+                &server_name("testserver.com"),
+                &[],
+                UnixTime::now(),
+            )
+            .err(),
+        Some(Error::InvalidCertificate(CertificateError::Sha1Signature))
+    );
+
+    // without the policy, the default algorithms still cannot verify SHA-1
+    let verifier = rustls::client::WebPkiServerVerifier::new(roots).reject_sha1_signatures(false);
+    assert!(verify(&verifier, &chain[1]).is_ok());
+    assert_eq!(
+        verify(&verifier, &sha1_inter).err(),
+        Some(Error::InvalidCertificate(CertificateError::BadSignature))
     );
 }
 
-#[cfg(feature = "tls12")]
 #[test]
-fn server_cert_resolve_reduces_sigalgs_for_ecdsa_ciphersuite() {
-    check_sigalgs_reduced_by_ciphersuite(
-        KeyType::Ecdsa,
-        CipherSuite::TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256,
-        vec![
-            SignatureScheme::ECDSA_NISTP384_SHA384,
-            SignatureScheme::ECDSA_NISTP256_SHA256,
-            SignatureScheme::ED25519,
-        ],
+fn client_verifier_rejects_sha1_signed_intermediate() {
+    let chain = KeyType::Rsa.get_client_chain();
+    let mut roots = rustls::RootCertStore::empty();
+    roots
+        .add(chain.last().unwrap().clone())
+        .unwrap();
+    let verifier = WebPkiClientVerifier::builder(Arc::new(roots))
+        .build()
+        .unwrap();
+
+    assert!(verifier
+        .verify_client_cert(&chain[0], &chain[1..2], UnixTime::now())
+        .is_ok());
+    assert_eq!(
+        verifier
+            .verify_client_cert(
+                &chain[0],
+                &[sha1_signed_rsa_intermediate()],
+                UnixTime::now()
+            )
+            .err(),
+        Some(Error::InvalidCertificate(CertificateError::Sha1Signature))
     );
 }
 
-struct ServerCheckNoSNI {}
+// 2.23.140.1.2.1, asserted by the `*.policy.cert` test certificates
+const TEST_POLICY_OID: &[u8] = &[0x67, 0x81, 0x0c, 0x01, 0x02, 0x01];
 
-impl ResolvesServerCert for ServerCheckNoSNI {
-    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<sign::CertifiedKey>> {
-        assert!(client_hello.server_name().is_none());
+#[test]
+fn server_verifier_enforces_required_policy_oids() {
+    let chain = KeyType::Rsa.get_chain();
+    let mut roots = rustls::RootCertStore::empty();
+    roots
+        .add(chain.last().unwrap().clone())
+        .unwrap();
+    let policy_end = load_single_cert(include_bytes!("../../test-ca/rsa/end.policy.cert"));
+
+    let verify = |verifier: &rustls::client::WebPkiServerVerifier,
+                  end_entity: &CertificateDer<'static>| {
+        verifier.verify_server_cert(
+            end_entity,
+            &chain[1..2],
+            &server_name("testserver.com"),
+            &[],
+            UnixTime::now(),
+        )
+    };
 
-        None
-    }
+    let verifier = rustls::client::WebPkiServerVerifier::new(roots)
+        .with_required_policy_oids(&[b"\x2a\x03", TEST_POLICY_OID]);
+    assert!(verify(&verifier, &policy_end).is_ok());
+    assert_eq!(
+        verify(&verifier, &chain[0]).err(),
+        Some(Error::InvalidCertificate(
+            CertificateError::RequiredPolicyNotAsserted
+        ))
+    );
 }
 
 #[test]
-fn client_with_sni_disabled_does_not_send_sni() {
-    for kt in ALL_KEY_TYPES.iter() {
-        let mut server_config = make_server_config(*kt);
-        server_config.cert_resolver = Arc::new(ServerCheckNoSNI {});
-        let server_config = Arc::new(server_config);
+fn server_verifier_trusts_pinned_intermediate() {
+    let chain = KeyType::Rsa.get_chain();
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add(chain[1].clone()).unwrap();
 
-        for version in rustls::ALL_VERSIONS {
-            let mut client_config = make_client_config_with_versions(*kt, &[version]);
-            client_config.enable_sni = false;
+    let verify = |roots: rustls::RootCertStore, intermediates: &[CertificateDer<'static>]| {
+        rustls::client::WebPkiServerVerifier::new(roots).verify_server_cert(
+            &chain[0],
+            intermediates,
+            &server_name("testserver.com"),
+            &[],
+            UnixTime::now(),
+        )
+    };
 
-            let mut client =
-                ClientConnection::new(Arc::new(client_config), server_name("value-not-sent"))
-                    .unwrap();
-            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+    // whether or not the server sends the pinned intermediate and its root
+    assert!(verify(roots.clone(), &[]).is_ok());
+    assert!(verify(roots.clone(), &chain[1..]).is_ok());
+
+    // name constraints on the pinned intermediate still apply beneath it:
+    // this permits only dNSName example.com
+    let mut constrained = roots.clone();
+    constrained.roots[0].name_constraints = Some(
+        b"\xa0\x0f\x30\x0d\x82\x0bexample.com"
+            .to_vec()
+            .into(),
+    );
+    assert!(matches!(
+        verify(constrained, &[]),
+        Err(Error::InvalidCertificate(CertificateError::Other(_)))
+    ));
 
-            let err = do_handshake_until_error(&mut client, &mut server);
-            assert!(err.is_err());
-        }
-    }
+    // and an unrelated anchor does not help
+    let mut unrelated = rustls::RootCertStore::empty();
+    unrelated
+        .add(KeyType::Ecdsa.get_chain()[1].clone())
+        .unwrap();
+    assert_eq!(
+        verify(unrelated, &chain[1..]).err(),
+        Some(Error::InvalidCertificate(CertificateError::UnknownIssuer))
+    );
 }
 
 #[test]
-fn client_checks_server_certificate_with_given_name() {
-    for kt in ALL_KEY_TYPES.iter() {
+fn client_trusting_only_an_intermediate_completes_handshake() {
+    let kt = KeyType::Rsa;
+    let mut roots = rustls::RootCertStore::empty();
+    roots
+        .add(kt.get_chain()[1].clone())
+        .unwrap();
+    let client_config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, make_server_config(kt));
+    do_handshake(&mut client, &mut server);
+}
+
+#[test]
+fn server_verifier_serial_number_conformance() {
+    let chain = KeyType::Rsa.get_chain();
+    let mut roots = rustls::RootCertStore::empty();
+    roots
+        .add(chain.last().unwrap().clone())
+        .unwrap();
+    let roots = Arc::new(roots);
+    let long_serial_end = load_single_cert(include_bytes!("../../test-ca/rsa/end.longserial.cert"));
+
+    let verify = |verifier: &rustls::client::WebPkiServerVerifier,
+                  end_entity: &CertificateDer<'static>| {
+        verifier.verify_server_cert(
+            end_entity,
+            &chain[1..2],
+            &server_name("testserver.com"),
+            &[],
+            UnixTime::now(),
+        )
+    };
+
+    let strict = rustls::client::WebPkiServerVerifier::new(Arc::clone(&roots));
+    assert!(verify(&strict, &chain[0]).is_ok());
+    assert_eq!(
+        verify(&strict, &long_serial_end).err(),
+        Some(Error::InvalidCertificate(
+            CertificateError::NonConformingSerialNumber
+        ))
+    );
+
+    let lenient =
+        rustls::client::WebPkiServerVerifier::new(roots).allow_nonconforming_serial_numbers(true);
+    assert!(verify(&lenient, &long_serial_end).is_ok());
+}
+
+#[test]
+fn server_verifier_validates_stapled_ocsp_responses() {
+    let chain = KeyType::Rsa.get_chain();
+    let mut roots = rustls::RootCertStore::empty();
+    roots
+        .add(chain.last().unwrap().clone())
+        .unwrap();
+    let roots = Arc::new(roots);
+
+    // test-ca/make-ocsp-responses.py makes these all valid from 2026-11-01
+    // to 2026-11-08.
+    const THIS_UPDATE: u64 = 1_793_491_200;
+    const NEXT_UPDATE: u64 = 1_794_096_000;
+    let good = include_bytes!("../../test-ca/rsa/end.ocsp.good.der");
+    let sha256 = include_bytes!("../../test-ca/rsa/end.ocsp.sha256.der");
+    let delegated = include_bytes!("../../test-ca/rsa/end.ocsp.delegated.der");
+    let revoked = include_bytes!("../../test-ca/rsa/end.ocsp.revoked.der");
+    let wrong_serial = include_bytes!("../../test-ca/rsa/end.ocsp.wrongserial.der");
+    let unauthorized = include_bytes!("../../test-ca/rsa/end.ocsp.unauthorized.der");
+    let forged = include_bytes!("../../test-ca/rsa/end.ocsp.forged.der");
+    let wrong_responder = include_bytes!("../../test-ca/rsa/end.ocsp.wrongresponder.der");
+    let no_next_update = include_bytes!("../../test-ca/rsa/end.ocsp.nonextupdate.der");
+    let at = |secs| UnixTime::since_unix_epoch(Duration::from_secs(secs));
+
+    let verify = |verifier: &rustls::client::WebPkiServerVerifier, ocsp: &[u8], now| {
+        verifier.verify_server_cert(
+            &chain[0],
+            &chain[1..2],
+            &server_name("testserver.com"),
+            ocsp,
+            now,
+        )
+    };
+
+    let ignoring = rustls::client::WebPkiServerVerifier::new(Arc::clone(&roots));
+    assert!(verify(&ignoring, revoked, at(THIS_UPDATE)).is_ok());
+
+    let validating = rustls::client::WebPkiServerVerifier::new(roots)
+        .validate_ocsp_responses(rustls::crypto::ring::RING);
+    assert!(verify(&validating, &[], at(THIS_UPDATE)).is_ok());
+    for ocsp in [&good[..], &sha256[..], &delegated[..]] {
+        assert!(verify(&validating, ocsp, at(THIS_UPDATE)).is_ok());
+        assert!(verify(&validating, ocsp, at(NEXT_UPDATE - 1)).is_ok());
+    }
+
+    for (ocsp, now, err) in [
+        (&good[..], NEXT_UPDATE, CertificateError::StaleOcspResponse),
+        (
+            &good[..],
+            THIS_UPDATE - 1,
+            CertificateError::StaleOcspResponse,
+        ),
+        (
+            &good[..good.len() - 1],
+            THIS_UPDATE,
+            CertificateError::InvalidOcspResponse,
+        ),
+        (
+            &wrong_serial[..],
+            THIS_UPDATE,
+            CertificateError::InvalidOcspResponse,
+        ),
+        (
+            &unauthorized[..],
+            THIS_UPDATE,
+            CertificateError::InvalidOcspResponse,
+        ),
+        (
+            &forged[..],
+            THIS_UPDATE,
+            CertificateError::InvalidOcspResponse,
+        ),
+        (
+            &wrong_responder[..],
+            THIS_UPDATE,
+            CertificateError::InvalidOcspResponse,
+        ),
+        (
+            &no_next_update[..],
+            THIS_UPDATE,
+            CertificateError::InvalidOcspResponse,
+        ),
+        (&revoked[..], THIS_UPDATE, CertificateError::Revoked),
+        // a stale response says nothing, not even that the certificate is revoked
+        (
+            &revoked[..],
+            NEXT_UPDATE,
+            CertificateError::StaleOcspResponse,
+        ),
+    ] {
+        assert_eq!(
+            verify(&validating, ocsp, at(now)).err(),
+            Some(Error::InvalidCertificate(err))
+        );
+    }
+}
+
+#[test]
+fn client_verifier_enforces_required_policy_oids() {
+    let chain = KeyType::Rsa.get_client_chain();
+    let mut roots = rustls::RootCertStore::empty();
+    roots
+        .add(chain.last().unwrap().clone())
+        .unwrap();
+    let verifier = WebPkiClientVerifier::builder(Arc::new(roots))
+        .with_required_policy_oids(&[TEST_POLICY_OID])
+        .build()
+        .unwrap();
+    let policy_client = load_single_cert(include_bytes!("../../test-ca/rsa/client.policy.cert"));
+
+    assert!(verifier
+        .verify_client_cert(&policy_client, &chain[1..2], UnixTime::now())
+        .is_ok());
+    assert_eq!(
+        verifier
+            .verify_client_cert(&chain[0], &chain[1..2], UnixTime::now())
+            .err(),
+        Some(Error::InvalidCertificate(
+            CertificateError::RequiredPolicyNotAsserted
+        ))
+    );
+}
+
+#[test]
+fn client_chooses_among_client_certs_by_issuer() {
+    let client_certs = || {
+        [KeyType::Ecdsa, KeyType::Ed25519, KeyType::Rsa]
+            .iter()
+            .map(|kt| (kt.get_client_chain(), kt.get_client_key()))
+            .collect::<Vec<_>>()
+    };
+
+    for version in rustls::ALL_VERSIONS {
+        for client_kt in ALL_KEY_TYPES.iter() {
+            let client_config = ClientConfig::builder()
+                .with_safe_default_cipher_suites()
+                .with_safe_default_kx_groups()
+                .with_protocol_versions(&[version])
+                .unwrap()
+                .with_root_certificates(get_client_root_store(KeyType::Rsa))
+                .with_client_auth_certs(client_certs())
+                .unwrap();
+            let server_config = ServerConfig::builder()
+                .with_safe_defaults()
+                .with_client_cert_verifier(
+                    WebPkiClientVerifier::builder(get_client_root_store(*client_kt))
+                        .build()
+                        .unwrap(),
+                )
+                .with_single_cert(KeyType::Rsa.get_chain(), KeyType::Rsa.get_key())
+                .unwrap();
+
+            let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+            do_handshake_until_error(&mut client, &mut server).unwrap();
+            assert_eq!(
+                server.peer_certificates().unwrap()[0],
+                client_kt.get_client_chain()[0],
+                "{:?} {:?}",
+                version,
+                client_kt
+            );
+        }
+    }
+}
+
+#[test]
+fn client_with_no_acceptable_client_cert_sends_none() {
+    let client_config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(get_client_root_store(KeyType::Rsa))
+        .with_client_auth_certs(vec![(
+            KeyType::Ecdsa.get_client_chain(),
+            KeyType::Ecdsa.get_client_key(),
+        )])
+        .unwrap();
+    let server_config = make_server_config_with_mandatory_client_auth(KeyType::Rsa);
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Server(Error::NoCertificatesPresented))
+    );
+}
+
+struct VerifierForServerName {
+    server_name: &'static str,
+    verifier: Arc<dyn ClientCertVerifier>,
+}
+
+impl ResolvesClientCertVerifier for VerifierForServerName {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<dyn ClientCertVerifier>> {
+        if client_hello.server_name() == Some(self.server_name) {
+            Some(Arc::clone(&self.verifier))
+        } else {
+            None
+        }
+    }
+}
+
+#[test]
+fn client_cert_verifier_resolved_per_server_name() {
+    let mut server_config = make_server_config_with_mandatory_client_auth(KeyType::Rsa);
+    server_config.client_cert_verifier_resolver = Some(Arc::new(VerifierForServerName {
+        server_name: "second.testserver.com",
+        verifier: WebPkiClientVerifier::builder(get_client_root_store(KeyType::Ecdsa))
+            .build()
+            .unwrap(),
+    }));
+    let server_config = Arc::new(server_config);
+
+    for version in rustls::ALL_VERSIONS {
+        let mut rsa_client = make_client_config_with_versions_with_auth(KeyType::Rsa, &[version]);
+        rsa_client.resumption = Resumption::disabled();
+        let mut ecdsa_client = rsa_client.clone();
+        ecdsa_client.client_auth_cert_resolver =
+            make_client_config_with_auth(KeyType::Ecdsa).client_auth_cert_resolver;
+
+        for (client_config, name, ok) in [
+            (&rsa_client, "testserver.com", true),
+            (&ecdsa_client, "testserver.com", false),
+            (&rsa_client, "second.testserver.com", false),
+            (&ecdsa_client, "second.testserver.com", true),
+        ] {
+            let mut client =
+                ClientConnection::new(Arc::new(client_config.clone()), server_name(name)).unwrap();
+            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+
+            let result = do_handshake_until_error(&mut client, &mut server);
+            if ok {
+                assert_eq!(result, Ok(()), "{:?} {}", version, name);
+            } else {
+                assert_eq!(
+                    result,
+                    Err(ErrorFromPeer::Server(Error::InvalidCertificate(
+                        CertificateError::UnknownIssuer
+                    ))),
+                    "{:?} {}",
+                    version,
+                    name
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn server_cert_resolve_with_trusted_ca_keys() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let subjects = get_client_root_store(*kt).subjects();
+        let mut client_config = make_client_config(*kt);
+        client_config.trusted_ca_keys = subjects
+            .iter()
+            .cloned()
+            .map(TrustedAuthority::X509Name)
+            .collect();
+
+        let mut server_config = make_server_config(*kt);
+        server_config.cert_resolver = Arc::new(ServerCheckCertResolve {
+            expected_trusted_ca_names: Some(subjects.clone()),
+            ..Default::default()
+        });
+
+        let client_config = Arc::new(client_config);
+        let mut client =
+            ClientConnection::new(client_config.clone(), server_name("sni-value")).unwrap();
+        let (_, exts) = client_hello_extensions(&mut client);
+        let sent = exts
+            .iter()
+            .find_map(|ext| match ext {
+                ClientExtension::TrustedCaKeys(authorities) => Some(authorities),
+                _ => None,
+            })
+            .expect("trusted_ca_keys not sent");
+        assert_eq!(sent.len(), subjects.len());
+        assert!(matches!(sent[0], TrustedAuthority::X509Name(_)));
+
+        let mut client = ClientConnection::new(client_config, server_name("sni-value")).unwrap();
+        let mut server = ServerConnection::new(Arc::new(server_config)).unwrap();
+
+        let err = do_handshake_until_error(&mut client, &mut server);
+        assert!(err.is_err());
+    }
+}
+
+#[test]
+fn client_trims_terminating_dot() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let client_config = make_client_config(*kt);
+        let mut server_config = make_server_config(*kt);
+
+        server_config.cert_resolver = Arc::new(ServerCheckCertResolve {
+            expected_sni: Some("some-host.com".into()),
+            ..Default::default()
+        });
+
+        let mut client =
+            ClientConnection::new(Arc::new(client_config), server_name("some-host.com.")).unwrap();
+        let mut server = ServerConnection::new(Arc::new(server_config)).unwrap();
+
+        let err = do_handshake_until_error(&mut client, &mut server);
+        assert!(err.is_err());
+    }
+}
+
+#[cfg(feature = "tls12")]
+fn check_sigalgs_reduced_by_ciphersuite(
+    kt: KeyType,
+    suite: CipherSuite,
+    expected_sigalgs: Vec<SignatureScheme>,
+) {
+    let client_config = finish_client_config(
+        kt,
+        ClientConfig::builder()
+            .with_cipher_suites(&[find_suite(suite)])
+            .with_safe_default_kx_groups()
+            .with_safe_default_protocol_versions()
+            .unwrap(),
+    );
+
+    let mut server_config = make_server_config(kt);
+
+    server_config.cert_resolver = Arc::new(ServerCheckCertResolve {
+        expected_sigalgs: Some(expected_sigalgs),
+        expected_cipher_suites: Some(vec![suite, CipherSuite::TLS_EMPTY_RENEGOTIATION_INFO_SCSV]),
+        ..Default::default()
+    });
+
+    let mut client =
+        ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
+    let mut server = ServerConnection::new(Arc::new(server_config)).unwrap();
+
+    let err = do_handshake_until_error(&mut client, &mut server);
+    assert!(err.is_err());
+}
+
+#[cfg(feature = "tls12")]
+#[test]
+fn server_cert_resolve_reduces_sigalgs_for_rsa_ciphersuite() {
+    check_sigalgs_reduced_by_ciphersuite(
+        KeyType::Rsa,
+        CipherSuite::TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256,
+        vec![
+            SignatureScheme::RSA_PSS_SHA512,
+            SignatureScheme::RSA_PSS_SHA384,
+            SignatureScheme::RSA_PSS_SHA256,
+            SignatureScheme::RSA_PKCS1_SHA512,
+            SignatureScheme::RSA_PKCS1_SHA384,
+            SignatureScheme::RSA_PKCS1_SHA256,
+        ],
+    );
+}
+
+#[cfg(feature = "tls12")]
+#[test]
+fn server_cert_resolve_reduces_sigalgs_for_ecdsa_ciphersuite() {
+    check_sigalgs_reduced_by_ciphersuite(
+        KeyType::Ecdsa,
+        CipherSuite::TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256,
+        vec![
+            SignatureScheme::ECDSA_NISTP384_SHA384,
+            SignatureScheme::ECDSA_NISTP256_SHA256,
+            SignatureScheme::ED25519,
+        ],
+    );
+}
+
+struct ServerCheckNoSNI {}
+
+impl ResolvesServerCert for ServerCheckNoSNI {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<sign::CertifiedKey>> {
+        assert!(client_hello.server_name().is_none());
+
+        None
+    }
+}
+
+#[test]
+fn client_with_sni_disabled_does_not_send_sni() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let mut server_config = make_server_config(*kt);
+        server_config.cert_resolver = Arc::new(ServerCheckNoSNI {});
+        let server_config = Arc::new(server_config);
+
+        for version in rustls::ALL_VERSIONS {
+            let mut client_config = make_client_config_with_versions(*kt, &[version]);
+            client_config.enable_sni = false;
+
+            let mut client =
+                ClientConnection::new(Arc::new(client_config), server_name("value-not-sent"))
+                    .unwrap();
+            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+
+            let err = do_handshake_until_error(&mut client, &mut server);
+            assert!(err.is_err());
+        }
+    }
+}
+
+#[test]
+fn client_checks_server_certificate_with_given_name() {
+    for kt in ALL_KEY_TYPES.iter() {
         let server_config = Arc::new(make_server_config(*kt));
 
         for version in rustls::ALL_VERSIONS {
@@ -1193,30 +2008,154 @@ fn client_auth_works() {
 }
 
 #[test]
-fn client_mandatory_auth_revocation_works() {
+fn server_reports_whether_client_authenticated() {
     for kt in ALL_KEY_TYPES.iter() {
-        // Create a server configuration that includes a CRL that specifies the client certificate
-        // is revoked.
-        let crls = vec![kt.client_crl()];
-        let server_config = Arc::new(make_server_config_with_mandatory_client_auth_crls(
-            *kt, crls,
-        ));
-
         for version in rustls::ALL_VERSIONS {
-            let client_config = make_client_config_with_versions_with_auth(*kt, &[version]);
-            let (mut client, mut server) =
-                make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
-            // Because the client certificate is revoked, the handshake should fail.
-            let err = do_handshake_until_error(&mut client, &mut server);
-            assert_eq!(
-                err,
-                Err(ErrorFromPeer::Server(Error::InvalidCertificate(
-                    CertificateError::Revoked
-                )))
+            // mutual TLS
+            let (mut client, mut server) = make_pair_for_configs(
+                make_client_config_with_versions_with_auth(*kt, &[version]),
+                make_server_config_with_mandatory_client_auth(*kt),
             );
-        }
-    }
-}
+            assert!(!server.client_authenticated());
+            do_handshake(&mut client, &mut server);
+            assert!(server.client_authenticated());
+
+            // optional client auth, declined by the client
+            let (mut client, mut server) = make_pair_for_configs(
+                make_client_config_with_versions(*kt, &[version]),
+                make_server_config_with_optional_client_auth(*kt, Vec::new()),
+            );
+            do_handshake(&mut client, &mut server);
+            assert!(!server.client_authenticated());
+
+            // no client auth
+            let (mut client, mut server) = make_pair_for_configs(
+                make_client_config_with_versions_with_auth(*kt, &[version]),
+                make_server_config(*kt),
+            );
+            do_handshake(&mut client, &mut server);
+            assert!(!server.client_authenticated());
+        }
+    }
+}
+
+/// A client verifier that names no acceptable issuers to clients.
+struct NoIssuerHints(Arc<dyn ClientCertVerifier>);
+
+impl ClientCertVerifier for NoIssuerHints {
+    fn client_auth_root_subjects(&self) -> &[DistinguishedName] {
+        &[]
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        now: UnixTime,
+    ) -> Result<ClientCertVerified, Error> {
+        self.0
+            .verify_client_cert(end_entity, intermediates, now)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.0
+            .verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.0
+            .verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.supported_verify_schemes()
+    }
+}
+
+/// A client cert resolver that checks it is given no acceptable issuers.
+struct ExpectNoIssuers(Arc<dyn ResolvesClientCert>);
+
+impl ResolvesClientCert for ExpectNoIssuers {
+    fn resolve(
+        &self,
+        acceptable_issuers: &[&[u8]],
+        sigschemes: &[SignatureScheme],
+    ) -> Option<Arc<sign::CertifiedKey>> {
+        assert!(acceptable_issuers.is_empty());
+        self.0
+            .resolve(acceptable_issuers, sigschemes)
+    }
+
+    fn has_certs(&self) -> bool {
+        self.0.has_certs()
+    }
+}
+
+#[test]
+fn client_auth_works_without_issuer_hints() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let verifier = WebPkiClientVerifier::builder(get_client_root_store(*kt))
+            .build()
+            .unwrap();
+        let server_config = Arc::new(
+            ServerConfig::builder()
+                .with_safe_defaults()
+                .with_client_cert_verifier(Arc::new(NoIssuerHints(verifier)))
+                .with_single_cert(kt.get_chain(), kt.get_key())
+                .unwrap(),
+        );
+
+        for version in rustls::ALL_VERSIONS {
+            let mut client_config = make_client_config_with_versions_with_auth(*kt, &[version]);
+            client_config.client_auth_cert_resolver = Arc::new(ExpectNoIssuers(
+                client_config
+                    .client_auth_cert_resolver
+                    .clone(),
+            ));
+
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+            do_handshake(&mut client, &mut server);
+            assert!(server.client_authenticated());
+        }
+    }
+}
+
+#[test]
+fn client_mandatory_auth_revocation_works() {
+    for kt in ALL_KEY_TYPES.iter() {
+        // Create a server configuration that includes a CRL that specifies the client certificate
+        // is revoked.
+        let crls = vec![kt.client_crl()];
+        let server_config = Arc::new(make_server_config_with_mandatory_client_auth_crls(
+            *kt, crls,
+        ));
+
+        for version in rustls::ALL_VERSIONS {
+            let client_config = make_client_config_with_versions_with_auth(*kt, &[version]);
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+            // Because the client certificate is revoked, the handshake should fail.
+            let err = do_handshake_until_error(&mut client, &mut server);
+            assert_eq!(
+                err,
+                Err(ErrorFromPeer::Server(Error::InvalidCertificate(
+                    CertificateError::Revoked
+                )))
+            );
+        }
+    }
+}
 
 #[test]
 fn client_optional_auth_revocation_works() {
@@ -1266,6 +2205,35 @@ fn server_error_is_sticky() {
     assert!(err.is_err());
 }
 
+#[test]
+fn application_can_send_fatal_alert() {
+    for version in rustls::ALL_VERSIONS {
+        let client_config = make_client_config_with_versions(KeyType::Rsa, &[version]);
+        let (mut client, mut server) =
+            make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+        do_handshake(&mut client, &mut server);
+
+        server.send_fatal_alert(AlertDescription::AccessDenied);
+        assert!(server.wants_write());
+        assert_eq!(
+            server.process_new_packets(),
+            Err(Error::AlertSent(AlertDescription::AccessDenied))
+        );
+
+        // a second call has no further effect
+        server.send_fatal_alert(AlertDescription::InternalError);
+        transfer(&mut server, &mut client);
+        assert_eq!(
+            client.process_new_packets(),
+            Err(Error::AlertReceived(AlertDescription::AccessDenied))
+        );
+        assert_eq!(
+            server.process_new_packets(),
+            Err(Error::AlertSent(AlertDescription::AccessDenied))
+        );
+    }
+}
+
 #[test]
 fn server_flush_does_nothing() {
     let (_, mut server) = make_pair(KeyType::Rsa);
@@ -2123,864 +3091,2041 @@ fn server_exposes_offered_sni_smashed_to_lowercase() {
     }
 }
 
-#[test]
-fn server_exposes_offered_sni_even_if_resolver_fails() {
-    let kt = KeyType::Rsa;
-    let resolver = rustls::server::ResolvesServerCertUsingSni::new();
+struct ResolvesOneName {
+    name: &'static str,
+    certified_key: Arc<sign::CertifiedKey>,
+}
 
-    let mut server_config = make_server_config(kt);
-    server_config.cert_resolver = Arc::new(resolver);
-    let server_config = Arc::new(server_config);
+impl ResolvesServerCert for ResolvesOneName {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<sign::CertifiedKey>> {
+        assert!(!client_hello
+            .signature_schemes()
+            .is_empty());
+        match client_hello.server_name() {
+            Some(name) if name == self.name => Some(Arc::clone(&self.certified_key)),
+            _ => None,
+        }
+    }
+}
 
-    for version in rustls::ALL_VERSIONS {
-        let client_config = make_client_config_with_versions(kt, &[version]);
-        let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
-        let mut client =
-            ClientConnection::new(Arc::new(client_config), server_name("thisdoesNOTexist.com"))
-                .unwrap();
+/// A key that signs by calling out to `key`, like a remote signer would.
+fn make_callback_signing_key(
+    key: PrivateKeyDer<'static>,
+    calls: Arc<AtomicUsize>,
+) -> Arc<dyn sign::SigningKey> {
+    let inner = sign::any_supported_type(&key).unwrap();
+    let schemes = inner.supported_schemes().to_vec();
+    Arc::new(sign::CallbackSigningKey::new(
+        inner.algorithm(),
+        &schemes,
+        move |scheme, message| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            inner
+                .choose_scheme(&[scheme])
+                .unwrap()
+                .sign(message)
+        },
+    ))
+}
 
-        assert_eq!(None, server.server_name());
-        transfer(&mut client, &mut server);
-        assert_eq!(
-            server.process_new_packets(),
-            Err(Error::General(
-                "no server certificate chain resolved".to_string()
-            ))
-        );
-        assert_eq!(Some("thisdoesnotexist.com"), server.server_name());
+struct AlwaysResolvesClientCert(Arc<sign::CertifiedKey>);
+
+impl ResolvesClientCert for AlwaysResolvesClientCert {
+    fn resolve(&self, _: &[&[u8]], _: &[SignatureScheme]) -> Option<Arc<sign::CertifiedKey>> {
+        Some(Arc::clone(&self.0))
+    }
+
+    fn has_certs(&self) -> bool {
+        true
     }
 }
 
 #[test]
-fn sni_resolver_works() {
-    let kt = KeyType::Rsa;
-    let mut resolver = rustls::server::ResolvesServerCertUsingSni::new();
-    let signing_key = sign::RsaSigningKey::new(&kt.get_key()).unwrap();
-    let signing_key: Arc<dyn sign::SigningKey> = Arc::new(signing_key);
-    resolver
-        .add(
-            "localhost",
-            sign::CertifiedKey::new(kt.get_chain(), signing_key.clone()),
-        )
-        .unwrap();
+fn callback_signing_key_signs_handshakes() {
+    let kt = KeyType::Ecdsa;
+    for version in rustls::ALL_VERSIONS {
+        let server_calls = Arc::new(AtomicUsize::new(0));
+        let mut server_config = make_server_config_with_mandatory_client_auth(kt);
+        server_config.cert_resolver = Arc::new(ResolvesOneName {
+            name: "localhost",
+            certified_key: Arc::new(sign::CertifiedKey::new(
+                kt.get_chain(),
+                make_callback_signing_key(kt.get_key(), Arc::clone(&server_calls)),
+            )),
+        });
 
-    let mut server_config = make_server_config(kt);
-    server_config.cert_resolver = Arc::new(resolver);
-    let server_config = Arc::new(server_config);
+        let client_calls = Arc::new(AtomicUsize::new(0));
+        let mut client_config = make_client_config_with_versions(kt, &[version]);
+        client_config.client_auth_cert_resolver =
+            Arc::new(AlwaysResolvesClientCert(Arc::new(sign::CertifiedKey::new(
+                kt.get_client_chain(),
+                make_callback_signing_key(kt.get_client_key(), Arc::clone(&client_calls)),
+            ))));
 
-    let mut server1 = ServerConnection::new(Arc::clone(&server_config)).unwrap();
-    let mut client1 =
-        ClientConnection::new(Arc::new(make_client_config(kt)), server_name("localhost")).unwrap();
-    let err = do_handshake_until_error(&mut client1, &mut server1);
-    assert_eq!(err, Ok(()));
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(server_calls.load(Ordering::SeqCst), 1);
+        assert_eq!(client_calls.load(Ordering::SeqCst), 1);
+        assert!(server.peer_certificates().is_some());
+    }
+}
 
-    let mut server2 = ServerConnection::new(Arc::clone(&server_config)).unwrap();
-    let mut client2 = ClientConnection::new(
-        Arc::new(make_client_config(kt)),
-        server_name("notlocalhost"),
-    )
-    .unwrap();
-    let err = do_handshake_until_error(&mut client2, &mut server2);
+#[test]
+fn callback_signing_key_errors_end_the_handshake() {
+    let kt = KeyType::Ecdsa;
+    let mut server_config = make_server_config(kt);
+    server_config.cert_resolver = Arc::new(ResolvesOneName {
+        name: "localhost",
+        certified_key: Arc::new(sign::CertifiedKey::new(
+            kt.get_chain(),
+            Arc::new(sign::CallbackSigningKey::new(
+                rustls::SignatureAlgorithm::ECDSA,
+                &[SignatureScheme::ECDSA_NISTP256_SHA256],
+                |_, _| Err(Error::General("signer unreachable".into())),
+            )),
+        )),
+    });
+
+    let (mut client, mut server) = make_pair_for_configs(make_client_config(kt), server_config);
     assert_eq!(
-        err,
+        do_handshake_until_error(&mut client, &mut server),
         Err(ErrorFromPeer::Server(Error::General(
-            "no server certificate chain resolved".into()
+            "signer unreachable".into()
         )))
     );
 }
 
+/// A key whose every first attempt at a signature fails.
+fn make_flaky_signing_key(
+    key: PrivateKeyDer<'static>,
+    calls: Arc<AtomicUsize>,
+) -> Arc<dyn sign::SigningKey> {
+    let inner = sign::any_supported_type(&key).unwrap();
+    let schemes = inner.supported_schemes().to_vec();
+    Arc::new(sign::CallbackSigningKey::new(
+        inner.algorithm(),
+        &schemes,
+        move |scheme, message| match calls.fetch_add(1, Ordering::SeqCst) % 2 {
+            0 => Err(Error::TransientSigningFailure("signer blipped".into())),
+            _ => inner
+                .choose_scheme(&[scheme])
+                .unwrap()
+                .sign(message),
+        },
+    ))
+}
+
 #[test]
-fn sni_resolver_rejects_wrong_names() {
-    let kt = KeyType::Rsa;
-    let mut resolver = rustls::server::ResolvesServerCertUsingSni::new();
-    let signing_key = sign::RsaSigningKey::new(&kt.get_key()).unwrap();
-    let signing_key: Arc<dyn sign::SigningKey> = Arc::new(signing_key);
+fn signing_retry_policy_retries_flaky_signers() {
+    let kt = KeyType::Ecdsa;
+    let retry_policy = sign::SigningRetryPolicy { attempts: 2 };
 
+    for version in rustls::ALL_VERSIONS {
+        let server_calls = Arc::new(AtomicUsize::new(0));
+        let mut server_config = make_server_config_with_mandatory_client_auth(kt);
+        server_config.cert_resolver = Arc::new(ResolvesOneName {
+            name: "localhost",
+            certified_key: Arc::new(sign::CertifiedKey::new(
+                kt.get_chain(),
+                make_flaky_signing_key(kt.get_key(), Arc::clone(&server_calls)),
+            )),
+        });
+        server_config.signing_retry_policy = retry_policy;
+
+        let client_calls = Arc::new(AtomicUsize::new(0));
+        let mut client_config = make_client_config_with_versions(kt, &[version]);
+        client_config.client_auth_cert_resolver =
+            Arc::new(AlwaysResolvesClientCert(Arc::new(sign::CertifiedKey::new(
+                kt.get_client_chain(),
+                make_flaky_signing_key(kt.get_client_key(), Arc::clone(&client_calls)),
+            ))));
+        client_config.signing_retry_policy = retry_policy;
+
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        do_handshake(&mut client, &mut server);
+        assert_eq!(server_calls.load(Ordering::SeqCst), 2);
+        assert_eq!(client_calls.load(Ordering::SeqCst), 2);
+    }
+
+    // by default, the first failure ends the handshake
+    let mut server_config = make_server_config(kt);
+    server_config.cert_resolver = Arc::new(ResolvesOneName {
+        name: "localhost",
+        certified_key: Arc::new(sign::CertifiedKey::new(
+            kt.get_chain(),
+            make_flaky_signing_key(kt.get_key(), Arc::new(AtomicUsize::new(0))),
+        )),
+    });
     assert_eq!(
-        Ok(()),
-        resolver.add(
-            "localhost",
-            sign::CertifiedKey::new(kt.get_chain(), signing_key.clone())
-        )
-    );
-    assert_eq!(
-        Err(Error::InvalidCertificate(CertificateError::NotValidForName)),
-        resolver.add(
-            "not-localhost",
-            sign::CertifiedKey::new(kt.get_chain(), signing_key.clone())
-        )
+        server_config.signing_retry_policy,
+        sign::SigningRetryPolicy::NONE
     );
+
+    let (mut client, mut server) = make_pair_for_configs(make_client_config(kt), server_config);
     assert_eq!(
-        Err(Error::General("Bad DNS name".into())),
-        resolver.add(
-            "not ascii 🦀",
-            sign::CertifiedKey::new(kt.get_chain(), signing_key.clone())
-        )
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Server(Error::TransientSigningFailure(
+            "signer blipped".into()
+        )))
     );
 }
 
 #[test]
-fn sni_resolver_lower_cases_configured_names() {
-    let kt = KeyType::Rsa;
-    let mut resolver = rustls::server::ResolvesServerCertUsingSni::new();
-    let signing_key = sign::RsaSigningKey::new(&kt.get_key()).unwrap();
-    let signing_key: Arc<dyn sign::SigningKey> = Arc::new(signing_key);
+fn signing_retry_policy_retries_only_transient_failures_up_to_a_cap() {
+    let kt = KeyType::Ecdsa;
+    let check = |error: Error| {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counted = Arc::clone(&calls);
+        let key = sign::CallbackSigningKey::new(
+            rustls::SignatureAlgorithm::ECDSA,
+            &[rustls::SignatureScheme::ECDSA_NISTP256_SHA256],
+            move |_, _| {
+                counted.fetch_add(1, Ordering::SeqCst);
+                Err(error.clone())
+            },
+        );
+
+        let mut server_config = make_server_config(kt);
+        server_config.cert_resolver = Arc::new(ResolvesOneName {
+            name: "localhost",
+            certified_key: Arc::new(sign::CertifiedKey::new(kt.get_chain(), Arc::new(key))),
+        });
+        server_config.signing_retry_policy = sign::SigningRetryPolicy { attempts: u32::MAX };
+
+        let (mut client, mut server) = make_pair_for_configs(make_client_config(kt), server_config);
+        let result = do_handshake_until_error(&mut client, &mut server);
+        (result, calls.load(Ordering::SeqCst))
+    };
 
+    let broken = Error::General("signer broken".into());
     assert_eq!(
-        Ok(()),
-        resolver.add(
-            "LOCALHOST",
-            sign::CertifiedKey::new(kt.get_chain(), signing_key.clone())
+        check(broken.clone()),
+        (Err(ErrorFromPeer::Server(broken)), 1)
+    );
+
+    let down = Error::TransientSigningFailure("signer down".into());
+    assert_eq!(
+        check(down.clone()),
+        (
+            Err(ErrorFromPeer::Server(down)),
+            sign::SigningRetryPolicy::MAX_ATTEMPTS as usize
         )
     );
+}
 
-    let mut server_config = make_server_config(kt);
-    server_config.cert_resolver = Arc::new(resolver);
-    let server_config = Arc::new(server_config);
+/// Records the schemes an inner key signs with, optionally insisting on one.
+struct SchemeRecordingKey {
+    inner: Arc<dyn sign::SigningKey>,
+    insist_on: Option<SignatureScheme>,
+    chosen: Arc<Mutex<Vec<SignatureScheme>>>,
+}
 
-    let mut server1 = ServerConnection::new(Arc::clone(&server_config)).unwrap();
-    let mut client1 =
-        ClientConnection::new(Arc::new(make_client_config(kt)), server_name("localhost")).unwrap();
-    let err = do_handshake_until_error(&mut client1, &mut server1);
-    assert_eq!(err, Ok(()));
+impl sign::SigningKey for SchemeRecordingKey {
+    fn choose_scheme(&self, offered: &[SignatureScheme]) -> Option<Box<dyn sign::Signer>> {
+        let signer = match self.insist_on {
+            Some(scheme) => self.inner.choose_scheme(&[scheme]),
+            None => self.inner.choose_scheme(offered),
+        }?;
+        self.chosen
+            .lock()
+            .unwrap()
+            .push(signer.scheme());
+        Some(signer)
+    }
+
+    fn algorithm(&self) -> rustls::SignatureAlgorithm {
+        self.inner.algorithm()
+    }
 }
 
-#[test]
-fn sni_resolver_lower_cases_queried_names() {
-    // actually, the handshake parser does this, but the effect is the same.
+fn make_scheme_recording_pair(
+    version: &'static rustls::SupportedProtocolVersion,
+    insist_on: Option<SignatureScheme>,
+) -> (
+    ClientConnection,
+    ServerConnection,
+    Arc<Mutex<Vec<SignatureScheme>>>,
+) {
     let kt = KeyType::Rsa;
-    let mut resolver = rustls::server::ResolvesServerCertUsingSni::new();
-    let signing_key = sign::RsaSigningKey::new(&kt.get_key()).unwrap();
-    let signing_key: Arc<dyn sign::SigningKey> = Arc::new(signing_key);
+    let chosen = Arc::new(Mutex::new(Vec::new()));
+    let recording_key = |key| {
+        Arc::new(SchemeRecordingKey {
+            inner: sign::any_supported_type(&key).unwrap(),
+            insist_on,
+            chosen: Arc::clone(&chosen),
+        })
+    };
 
-    assert_eq!(
-        Ok(()),
-        resolver.add(
-            "localhost",
-            sign::CertifiedKey::new(kt.get_chain(), signing_key.clone())
-        )
-    );
+    let mut server_config = make_server_config_with_mandatory_client_auth(kt);
+    server_config.cert_resolver = Arc::new(ResolvesOneName {
+        name: "localhost",
+        certified_key: Arc::new(sign::CertifiedKey::new(
+            kt.get_chain(),
+            recording_key(kt.get_key()),
+        )),
+    });
 
-    let mut server_config = make_server_config(kt);
-    server_config.cert_resolver = Arc::new(resolver);
-    let server_config = Arc::new(server_config);
+    let mut client_config = make_client_config_with_versions(kt, &[version]);
+    client_config.client_auth_cert_resolver = Arc::new(AlwaysResolvesClientCert(Arc::new(
+        sign::CertifiedKey::new(kt.get_client_chain(), recording_key(kt.get_client_key())),
+    )));
 
-    let mut server1 = ServerConnection::new(Arc::clone(&server_config)).unwrap();
-    let mut client1 =
-        ClientConnection::new(Arc::new(make_client_config(kt)), server_name("LOCALHOST")).unwrap();
-    let err = do_handshake_until_error(&mut client1, &mut server1);
-    assert_eq!(err, Ok(()));
+    let (client, server) = make_pair_for_configs(client_config, server_config);
+    (client, server, chosen)
 }
 
 #[test]
-fn sni_resolver_rejects_bad_certs() {
-    let kt = KeyType::Rsa;
-    let mut resolver = rustls::server::ResolvesServerCertUsingSni::new();
-    let signing_key = sign::RsaSigningKey::new(&kt.get_key()).unwrap();
-    let signing_key: Arc<dyn sign::SigningKey> = Arc::new(signing_key);
+fn tls13_rsa_keys_sign_with_pss() {
+    let (mut client, mut server, chosen) =
+        make_scheme_recording_pair(&rustls::version::TLS13, None);
+    do_handshake(&mut client, &mut server);
 
-    assert_eq!(
-        Err(Error::NoCertificatesPresented),
-        resolver.add(
-            "localhost",
-            sign::CertifiedKey::new(vec![], signing_key.clone())
-        )
-    );
+    // The server's CertificateVerify, then the client's.
+    let chosen = chosen.lock().unwrap();
+    assert_eq!(chosen.len(), 2);
+    for scheme in chosen.iter() {
+        assert!(matches!(
+            scheme,
+            SignatureScheme::RSA_PSS_SHA256
+                | SignatureScheme::RSA_PSS_SHA384
+                | SignatureScheme::RSA_PSS_SHA512
+        ));
+    }
+}
 
-    let bad_chain = vec![CertificateDer::from(vec![0xa0])];
+#[test]
+fn tls13_refuses_rsa_keys_insisting_on_pkcs1() {
+    let (mut client, mut server, _) = make_scheme_recording_pair(
+        &rustls::version::TLS13,
+        Some(SignatureScheme::RSA_PKCS1_SHA256),
+    );
     assert_eq!(
-        Err(Error::InvalidCertificate(CertificateError::BadEncoding)),
-        resolver.add(
-            "localhost",
-            sign::CertifiedKey::new(bad_chain, signing_key.clone())
-        )
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Server(Error::PeerIncompatible(
+            PeerIncompatible::NoSignatureSchemesInCommon
+        )))
     );
+
+    #[cfg(feature = "tls12")]
+    {
+        let (mut client, mut server, chosen) = make_scheme_recording_pair(
+            &rustls::version::TLS12,
+            Some(SignatureScheme::RSA_PKCS1_SHA256),
+        );
+        do_handshake(&mut client, &mut server);
+        assert_eq!(
+            *chosen.lock().unwrap(),
+            vec![
+                SignatureScheme::RSA_PKCS1_SHA256,
+                SignatureScheme::RSA_PKCS1_SHA256
+            ]
+        );
+    }
 }
 
-fn do_exporter_test(client_config: ClientConfig, server_config: ServerConfig) {
-    let mut client_secret = [0u8; 64];
-    let mut server_secret = [0u8; 64];
+#[test]
+fn server_config_probes_cert_resolver() {
+    let kt = KeyType::Rsa;
+    let signing_key = sign::any_supported_type(&kt.get_key()).unwrap();
 
-    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    let mut server_config = make_server_config(kt);
+    server_config.cert_resolver = Arc::new(ResolvesOneName {
+        name: "example.com",
+        certified_key: Arc::new(sign::CertifiedKey::new(kt.get_chain(), signing_key)),
+    });
 
-    assert_eq!(
-        Err(Error::HandshakeNotComplete),
-        client.export_keying_material(&mut client_secret, b"label", Some(b"context"))
-    );
-    assert_eq!(
-        Err(Error::HandshakeNotComplete),
-        server.export_keying_material(&mut server_secret, b"label", Some(b"context"))
-    );
-    do_handshake(&mut client, &mut server);
+    assert!(server_config.probe_resolver("example.com"));
+    assert!(!server_config.probe_resolver("other.com"));
+    assert!(!server_config.probe_resolver("not a dns name"));
+}
 
-    assert!(client
-        .export_keying_material(&mut client_secret, b"label", Some(b"context"))
-        .is_ok());
-    assert!(server
-        .export_keying_material(&mut server_secret, b"label", Some(b"context"))
-        .is_ok());
-    assert_eq!(client_secret.to_vec(), server_secret.to_vec());
+#[derive(Default)]
+struct RecordingCertificateExtensions {
+    seen: Mutex<Vec<(usize, u16, Vec<u8>)>>,
+}
 
-    assert!(client
-        .export_keying_material(&mut client_secret, b"label", None)
-        .is_ok());
-    assert_ne!(client_secret.to_vec(), server_secret.to_vec());
-    assert!(server
-        .export_keying_material(&mut server_secret, b"label", None)
-        .is_ok());
-    assert_eq!(client_secret.to_vec(), server_secret.to_vec());
+impl rustls::client::HandlesCertificateExtensions for RecordingCertificateExtensions {
+    fn handle(&self, entry_index: usize, extensions: &[(u16, &[u8])]) -> Result<(), Error> {
+        let mut seen = self.seen.lock().unwrap();
+        for (typ, payload) in extensions {
+            seen.push((entry_index, *typ, payload.to_vec()));
+        }
+        Ok(())
+    }
+}
+
+fn server_config_with_certificate_extension(kt: KeyType) -> ServerConfig {
+    let certified_key = sign::CertifiedKey::new(
+        kt.get_chain(),
+        sign::any_supported_type(&kt.get_key()).unwrap(),
+    )
+    .with_tls13_certificate_extensions(vec![(0xff42, b"hello".to_vec())]);
+
+    let mut server_config = make_server_config(kt);
+    server_config.cert_resolver = Arc::new(ResolvesOneName {
+        name: "localhost",
+        certified_key: Arc::new(certified_key),
+    });
+    server_config
 }
 
-#[cfg(feature = "tls12")]
 #[test]
-fn test_tls12_exporter() {
+fn client_certificate_extension_handler_sees_unknown_extensions() {
     for kt in ALL_KEY_TYPES.iter() {
-        let client_config = make_client_config_with_versions(*kt, &[&rustls::version::TLS12]);
-        let server_config = make_server_config(*kt);
+        let handler = Arc::new(RecordingCertificateExtensions::default());
+        let mut client_config = make_client_config_with_versions(*kt, &[&rustls::version::TLS13]);
+        client_config.certificate_extension_handler = Some(handler.clone());
 
-        do_exporter_test(client_config, server_config);
+        let (mut client, mut server) =
+            make_pair_for_configs(client_config, server_config_with_certificate_extension(*kt));
+        do_handshake(&mut client, &mut server);
+
+        assert_eq!(
+            *handler.seen.lock().unwrap(),
+            vec![(0, 0xff42, b"hello".to_vec())]
+        );
     }
 }
 
 #[test]
-fn test_tls13_exporter() {
+fn client_rejects_unknown_certificate_extensions_without_handler() {
     for kt in ALL_KEY_TYPES.iter() {
         let client_config = make_client_config_with_versions(*kt, &[&rustls::version::TLS13]);
-        let server_config = make_server_config(*kt);
 
-        do_exporter_test(client_config, server_config);
+        let (mut client, mut server) =
+            make_pair_for_configs(client_config, server_config_with_certificate_extension(*kt));
+
+        assert_eq!(
+            do_handshake_until_error(&mut client, &mut server),
+            Err(ErrorFromPeer::Client(Error::PeerMisbehaved(
+                PeerMisbehaved::BadCertChainExtensions
+            )))
+        );
     }
 }
 
-fn do_suite_test(
-    client_config: ClientConfig,
-    server_config: ServerConfig,
-    expect_suite: SupportedCipherSuite,
-    expect_version: ProtocolVersion,
-) {
-    println!(
-        "do_suite_test {:?} {:?}",
-        expect_version,
-        expect_suite.suite()
-    );
-    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+#[test]
+fn server_exposes_offered_sni_even_if_resolver_fails() {
+    let kt = KeyType::Rsa;
+    let resolver = rustls::server::ResolvesServerCertUsingSni::new();
 
-    assert_eq!(None, client.negotiated_cipher_suite());
-    assert_eq!(None, server.negotiated_cipher_suite());
-    assert_eq!(None, client.protocol_version());
-    assert_eq!(None, server.protocol_version());
-    assert!(client.is_handshaking());
-    assert!(server.is_handshaking());
+    let mut server_config = make_server_config(kt);
+    server_config.cert_resolver = Arc::new(resolver);
+    let server_config = Arc::new(server_config);
 
-    transfer(&mut client, &mut server);
-    server.process_new_packets().unwrap();
+    for version in rustls::ALL_VERSIONS {
+        let client_config = make_client_config_with_versions(kt, &[version]);
+        let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+        let mut client =
+            ClientConnection::new(Arc::new(client_config), server_name("thisdoesNOTexist.com"))
+                .unwrap();
 
-    assert!(client.is_handshaking());
-    assert!(server.is_handshaking());
-    assert_eq!(None, client.protocol_version());
-    assert_eq!(Some(expect_version), server.protocol_version());
-    assert_eq!(None, client.negotiated_cipher_suite());
-    assert_eq!(Some(expect_suite), server.negotiated_cipher_suite());
+        assert_eq!(None, server.server_name());
+        transfer(&mut client, &mut server);
+        assert_eq!(
+            server.process_new_packets(),
+            Err(Error::General(
+                "no server certificate chain resolved".to_string()
+            ))
+        );
+        assert_eq!(Some("thisdoesnotexist.com"), server.server_name());
+    }
+}
 
-    transfer(&mut server, &mut client);
-    client.process_new_packets().unwrap();
+#[test]
+fn sni_resolver_works() {
+    let kt = KeyType::Rsa;
+    let mut resolver = rustls::server::ResolvesServerCertUsingSni::new();
+    let signing_key = sign::RsaSigningKey::new(&kt.get_key()).unwrap();
+    let signing_key: Arc<dyn sign::SigningKey> = Arc::new(signing_key);
+    resolver
+        .add(
+            "localhost",
+            sign::CertifiedKey::new(kt.get_chain(), signing_key.clone()),
+        )
+        .unwrap();
 
-    assert_eq!(Some(expect_suite), client.negotiated_cipher_suite());
-    assert_eq!(Some(expect_suite), server.negotiated_cipher_suite());
+    let mut server_config = make_server_config(kt);
+    server_config.cert_resolver = Arc::new(resolver);
+    let server_config = Arc::new(server_config);
 
-    transfer(&mut client, &mut server);
-    server.process_new_packets().unwrap();
-    transfer(&mut server, &mut client);
-    client.process_new_packets().unwrap();
+    let mut server1 = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+    let mut client1 =
+        ClientConnection::new(Arc::new(make_client_config(kt)), server_name("localhost")).unwrap();
+    let err = do_handshake_until_error(&mut client1, &mut server1);
+    assert_eq!(err, Ok(()));
 
-    assert!(!client.is_handshaking());
-    assert!(!server.is_handshaking());
-    assert_eq!(Some(expect_version), client.protocol_version());
-    assert_eq!(Some(expect_version), server.protocol_version());
-    assert_eq!(Some(expect_suite), client.negotiated_cipher_suite());
-    assert_eq!(Some(expect_suite), server.negotiated_cipher_suite());
+    let mut server2 = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+    let mut client2 = ClientConnection::new(
+        Arc::new(make_client_config(kt)),
+        server_name("notlocalhost"),
+    )
+    .unwrap();
+    let err = do_handshake_until_error(&mut client2, &mut server2);
+    assert_eq!(
+        err,
+        Err(ErrorFromPeer::Server(Error::General(
+            "no server certificate chain resolved".into()
+        )))
+    );
 }
 
-fn find_suite(suite: CipherSuite) -> SupportedCipherSuite {
-    for scs in ALL_CIPHER_SUITES.iter().copied() {
-        if scs.suite() == suite {
-            return scs;
+#[test]
+fn sent_certificate_chain_matches_resolver_choice() {
+    let kt = KeyType::Rsa;
+    let full_chain = kt.get_chain();
+    let short_chain = full_chain[..2].to_vec();
+
+    let mut resolver = rustls::server::ResolvesServerCertUsingSni::new();
+    let signing_key = sign::RsaSigningKey::new(&kt.get_key()).unwrap();
+    let signing_key: Arc<dyn sign::SigningKey> = Arc::new(signing_key);
+    resolver
+        .add(
+            "localhost",
+            sign::CertifiedKey::new(full_chain.clone(), signing_key.clone()),
+        )
+        .unwrap();
+    resolver
+        .add(
+            "testserver.com",
+            sign::CertifiedKey::new(short_chain.clone(), signing_key),
+        )
+        .unwrap();
+
+    let mut server_config = make_server_config(kt);
+    server_config.cert_resolver = Arc::new(resolver);
+    let server_config = Arc::new(server_config);
+
+    for version in rustls::ALL_VERSIONS {
+        let client_config = Arc::new(make_client_config_with_versions(kt, &[version]));
+
+        for (name, expected) in [("localhost", &full_chain), ("testserver.com", &short_chain)] {
+            let mut client =
+                ClientConnection::new(Arc::clone(&client_config), server_name(name)).unwrap();
+            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+            assert_eq!(server.sent_certificate_chain(), None);
+
+            do_handshake(&mut client, &mut server);
+            assert_eq!(server.sent_certificate_chain(), Some(&expected[..]));
+            assert_eq!(client.peer_certificates(), Some(&expected[..]));
+            assert_eq!(client.sent_certificate_chain(), None);
         }
     }
+}
 
-    panic!("find_suite given unsupported suite");
+#[test]
+fn client_sent_certificate_chain_with_client_auth() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let server_config = Arc::new(make_server_config_with_mandatory_client_auth(*kt));
+
+        for version in rustls::ALL_VERSIONS {
+            let client_config = make_client_config_with_versions_with_auth(*kt, &[version]);
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+            do_handshake(&mut client, &mut server);
+
+            let client_chain = kt.get_client_chain();
+            assert_eq!(client.sent_certificate_chain(), Some(&client_chain[..]));
+            assert_eq!(server.peer_certificates(), Some(&client_chain[..]));
+        }
+    }
 }
 
-static TEST_CIPHERSUITES: &[(&rustls::SupportedProtocolVersion, KeyType, CipherSuite)] = &[
-    (
-        &rustls::version::TLS13,
-        KeyType::Rsa,
-        CipherSuite::TLS13_CHACHA20_POLY1305_SHA256,
-    ),
-    (
-        &rustls::version::TLS13,
-        KeyType::Rsa,
-        CipherSuite::TLS13_AES_256_GCM_SHA384,
-    ),
-    (
-        &rustls::version::TLS13,
-        KeyType::Rsa,
-        CipherSuite::TLS13_AES_128_GCM_SHA256,
-    ),
-    #[cfg(feature = "tls12")]
-    (
-        &rustls::version::TLS12,
-        KeyType::Ecdsa,
-        CipherSuite::TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256,
-    ),
-    #[cfg(feature = "tls12")]
-    (
-        &rustls::version::TLS12,
-        KeyType::Rsa,
-        CipherSuite::TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256,
-    ),
-    #[cfg(feature = "tls12")]
-    (
-        &rustls::version::TLS12,
-        KeyType::Ecdsa,
-        CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384,
-    ),
-    #[cfg(feature = "tls12")]
-    (
-        &rustls::version::TLS12,
-        KeyType::Ecdsa,
-        CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256,
-    ),
-    #[cfg(feature = "tls12")]
-    (
-        &rustls::version::TLS12,
-        KeyType::Rsa,
-        CipherSuite::TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384,
-    ),
-    #[cfg(feature = "tls12")]
-    (
-        &rustls::version::TLS12,
-        KeyType::Rsa,
-        CipherSuite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
-    ),
-];
-
 #[test]
-fn negotiated_ciphersuite_default() {
+fn peer_certificate_not_after_matches_fixture() {
+    // notAfter of every end-entity and client certificate in test-ca
+    let expiry = UnixTime::since_unix_epoch(Duration::from_secs(1_861_371_646));
+
     for kt in ALL_KEY_TYPES.iter() {
-        do_suite_test(
-            make_client_config(*kt),
-            make_server_config(*kt),
-            find_suite(CipherSuite::TLS13_AES_256_GCM_SHA384),
-            ProtocolVersion::TLSv1_3,
-        );
+        let server_config = Arc::new(make_server_config_with_mandatory_client_auth(*kt));
+
+        for version in rustls::ALL_VERSIONS {
+            let client_config = make_client_config_with_versions_with_auth(*kt, &[version]);
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+            assert_eq!(client.peer_certificate_not_after(), None);
+
+            do_handshake(&mut client, &mut server);
+            assert_eq!(client.peer_certificate_not_after(), Some(expiry));
+            assert_eq!(server.peer_certificate_not_after(), Some(expiry));
+        }
     }
 }
 
-#[test]
-fn all_suites_covered() {
-    assert_eq!(ALL_CIPHER_SUITES.len(), TEST_CIPHERSUITES.len());
+/// A server certificate verifier which only offers, and only accepts,
+/// `ED25519` signatures; and records the schemes the server used.
+struct Ed25519OnlyVerifier {
+    inner: rustls::client::WebPkiServerVerifier,
+    seen_schemes: Mutex<Vec<SignatureScheme>>,
 }
 
-#[test]
-fn negotiated_ciphersuite_client() {
-    for item in TEST_CIPHERSUITES.iter() {
-        let (version, kt, suite) = *item;
-        let scs = find_suite(suite);
-        let client_config = finish_client_config(
-            kt,
-            ClientConfig::builder()
-                .with_cipher_suites(&[scs])
-                .with_safe_default_kx_groups()
-                .with_protocol_versions(&[version])
-                .unwrap(),
+impl Ed25519OnlyVerifier {
+    fn new() -> Self {
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add_parsable_certificates(
+            rustls_pemfile::certs(&mut io::BufReader::new(bytes_for("eddsa", "ca.cert")))
+                .map(|result| result.unwrap()),
         );
+        Self {
+            inner: rustls::client::WebPkiServerVerifier::new(roots),
+            seen_schemes: Mutex::new(Vec::new()),
+        }
+    }
 
-        do_suite_test(client_config, make_server_config(kt), scs, version.version);
+    fn check_scheme(&self, dss: &rustls::DigitallySignedStruct) -> Result<(), Error> {
+        self.seen_schemes
+            .lock()
+            .unwrap()
+            .push(dss.scheme);
+        match dss.scheme {
+            SignatureScheme::ED25519 => Ok(()),
+            _ => Err(PeerMisbehaved::SignedHandshakeWithUnadvertisedSigScheme.into()),
+        }
     }
 }
 
-#[test]
-fn negotiated_ciphersuite_server() {
-    for item in TEST_CIPHERSUITES.iter() {
-        let (version, kt, suite) = *item;
-        let scs = find_suite(suite);
-        let server_config = finish_server_config(
-            kt,
-            ServerConfig::builder()
-                .with_cipher_suites(&[scs])
-                .with_safe_default_kx_groups()
-                .with_protocol_versions(&[version])
-                .unwrap(),
-        );
+impl ServerCertVerifier for Ed25519OnlyVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &rustls::ServerName,
+        ocsp_response: &[u8],
+        now: pki_types::UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        self.inner
+            .verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)
+    }
 
-        do_suite_test(make_client_config(kt), server_config, scs, version.version);
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.check_scheme(dss)?;
+        self.inner
+            .verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.check_scheme(dss)?;
+        self.inner
+            .verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        vec![SignatureScheme::ED25519]
     }
 }
 
-#[derive(Debug, PartialEq)]
-struct KeyLogItem {
-    label: String,
-    client_random: Vec<u8>,
-    secret: Vec<u8>,
+#[test]
+fn ed25519_certified_key_works_with_ed25519_only_client() {
+    let kt = KeyType::Ed25519;
+    let key = match kt.get_key() {
+        PrivateKeyDer::Pkcs8(key) => key,
+        _ => unreachable!(),
+    };
+    let signing_key = sign::any_eddsa_type(&key).unwrap();
+    assert_eq!(signing_key.algorithm(), rustls::SignatureAlgorithm::ED25519);
+
+    let mut resolver = rustls::server::ResolvesServerCertUsingSni::new();
+    resolver
+        .add(
+            "localhost",
+            sign::CertifiedKey::new(kt.get_chain(), signing_key),
+        )
+        .unwrap();
+
+    let mut server_config = make_server_config(kt);
+    server_config.cert_resolver = Arc::new(resolver);
+    let server_config = Arc::new(server_config);
+
+    for version in rustls::ALL_VERSIONS {
+        let verifier = Arc::new(Ed25519OnlyVerifier::new());
+        let mut client_config = make_client_config_with_versions(kt, &[version]);
+        client_config
+            .dangerous()
+            .set_certificate_verifier(verifier.clone());
+
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+        do_handshake(&mut client, &mut server);
+
+        assert_eq!(client.protocol_version(), Some(version.version));
+        assert_eq!(
+            *verifier.seen_schemes.lock().unwrap(),
+            vec![SignatureScheme::ED25519]
+        );
+    }
 }
 
-struct KeyLogToVec {
-    label: &'static str,
-    items: Mutex<Vec<KeyLogItem>>,
+/// A server certificate verifier that records the server names and ALPN
+/// protocols it is asked to verify for, then defers to the default verifier.
+struct ServerNameRecordingVerifier {
+    inner: rustls::client::WebPkiServerVerifier,
+    seen_names: Mutex<Vec<rustls::ServerName>>,
+    seen_alpn: Mutex<Vec<Option<Vec<u8>>>>,
 }
 
-impl KeyLogToVec {
-    fn new(who: &'static str) -> Self {
+impl ServerNameRecordingVerifier {
+    fn new(kt: KeyType) -> Self {
         Self {
-            label: who,
-            items: Mutex::new(vec![]),
+            inner: rustls::client::WebPkiServerVerifier::new(get_client_root_store(kt)),
+            seen_names: Mutex::new(Vec::new()),
+            seen_alpn: Mutex::new(Vec::new()),
         }
     }
+}
 
-    fn take(&self) -> Vec<KeyLogItem> {
-        std::mem::take(&mut self.items.lock().unwrap())
+impl ServerCertVerifier for ServerNameRecordingVerifier {
+    fn verify_server_cert_with_alpn(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &rustls::ServerName,
+        ocsp_response: &[u8],
+        alpn_protocol: Option<&[u8]>,
+        now: pki_types::UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        self.seen_alpn
+            .lock()
+            .unwrap()
+            .push(alpn_protocol.map(|p| p.to_vec()));
+        self.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)
     }
-}
 
-impl KeyLog for KeyLogToVec {
-    fn log(&self, label: &str, client: &[u8], secret: &[u8]) {
-        let value = KeyLogItem {
-            label: label.into(),
-            client_random: client.into(),
-            secret: secret.into(),
-        };
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &rustls::ServerName,
+        ocsp_response: &[u8],
+        now: pki_types::UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        self.seen_names
+            .lock()
+            .unwrap()
+            .push(server_name.clone());
+        self.inner
+            .verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)
+    }
 
-        println!("key log {:?}: {:?}", self.label, value);
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner
+            .verify_tls12_signature(message, cert, dss)
+    }
 
-        self.items.lock().unwrap().push(value);
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner
+            .verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
     }
 }
 
-#[cfg(feature = "tls12")]
-#[test]
-fn key_log_for_tls12() {
-    let client_key_log = Arc::new(KeyLogToVec::new("client"));
-    let server_key_log = Arc::new(KeyLogToVec::new("server"));
+struct UppercaseServerName;
 
-    let kt = KeyType::Rsa;
-    let mut client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS12]);
-    client_config.key_log = client_key_log.clone();
-    let client_config = Arc::new(client_config);
+impl NormalizesServerName for UppercaseServerName {
+    fn normalize(&self, server_name: &rustls::ServerName) -> rustls::ServerName {
+        match server_name {
+            rustls::ServerName::DnsName(dns_name) => dns_name
+                .as_ref()
+                .to_uppercase()
+                .as_str()
+                .try_into()
+                .unwrap(),
+            _ => server_name.clone(),
+        }
+    }
+}
 
+#[test]
+fn server_cert_verifier_sees_negotiated_alpn() {
+    let kt = KeyType::Rsa;
     let mut server_config = make_server_config(kt);
-    server_config.key_log = server_key_log.clone();
+    server_config.alpn_protocols = vec![b"h2".to_vec()];
     let server_config = Arc::new(server_config);
 
-    // full handshake
-    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
-    do_handshake(&mut client, &mut server);
+    for version in rustls::ALL_VERSIONS {
+        for (client_protocols, expected) in [
+            (
+                vec![b"http/1.1".to_vec(), b"h2".to_vec()],
+                Some(b"h2".to_vec()),
+            ),
+            (vec![], None),
+        ] {
+            let verifier = Arc::new(ServerNameRecordingVerifier::new(kt));
+            let mut client_config = make_client_config_with_versions(kt, &[version]);
+            client_config.alpn_protocols = client_protocols;
+            client_config
+                .dangerous()
+                .set_certificate_verifier(verifier.clone());
 
-    let client_full_log = client_key_log.take();
-    let server_full_log = server_key_log.take();
-    assert_eq!(client_full_log, server_full_log);
-    assert_eq!(1, client_full_log.len());
-    assert_eq!("CLIENT_RANDOM", client_full_log[0].label);
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+            do_handshake(&mut client, &mut server);
+            assert_eq!(*verifier.seen_alpn.lock().unwrap(), vec![expected]);
+        }
+    }
+}
 
-    // resumed
-    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
-    do_handshake(&mut client, &mut server);
+/// A server certificate verifier that only accepts a server identified by
+/// one pinned raw public key.
+struct PinnedRawPublicKeyVerifier {
+    spki: Vec<u8>,
+    seen_names: Mutex<Vec<rustls::ServerName>>,
+}
 
-    let client_resume_log = client_key_log.take();
-    let server_resume_log = server_key_log.take();
-    assert_eq!(client_resume_log, server_resume_log);
-    assert_eq!(1, client_resume_log.len());
-    assert_eq!("CLIENT_RANDOM", client_resume_log[0].label);
-    assert_eq!(client_full_log[0].secret, client_resume_log[0].secret);
+impl PinnedRawPublicKeyVerifier {
+    fn new(spki: Vec<u8>) -> Self {
+        Self {
+            spki,
+            seen_names: Mutex::new(Vec::new()),
+        }
+    }
 }
 
-#[test]
-fn key_log_for_tls13() {
-    let client_key_log = Arc::new(KeyLogToVec::new("client"));
-    let server_key_log = Arc::new(KeyLogToVec::new("server"));
+impl ServerCertVerifier for PinnedRawPublicKeyVerifier {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &rustls::ServerName,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        panic!("certificate chain given to raw public key verifier");
+    }
 
-    let kt = KeyType::Rsa;
-    let mut client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS13]);
-    client_config.key_log = client_key_log.clone();
-    let client_config = Arc::new(client_config);
+    fn verify_raw_public_key(
+        &self,
+        spki: &[u8],
+        server_name: &rustls::ServerName,
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        self.seen_names
+            .lock()
+            .unwrap()
+            .push(server_name.clone());
+        match spki == self.spki {
+            true => Ok(ServerCertVerified::assertion()),
+            false => Err(Error::InvalidCertificate(CertificateError::UnknownIssuer)),
+        }
+    }
 
-    let mut server_config = make_server_config(kt);
-    server_config.key_log = server_key_log.clone();
-    let server_config = Arc::new(server_config);
+    fn verify_tls12_signature(
+        &self,
+        _message: &[u8],
+        _cert: &CertificateDer<'_>,
+        _dss: &rustls::DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        panic!("raw public keys are not used in TLS1.2");
+    }
 
-    // full handshake
-    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
-    do_handshake(&mut client, &mut server);
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        rustls::client::WebPkiServerVerifier::default_verify_tls13_signature_with_raw_key(
+            message, cert, dss,
+        )
+    }
 
-    let client_full_log = client_key_log.take();
-    let server_full_log = server_key_log.take();
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        rustls::client::WebPkiServerVerifier::default_supported_verify_schemes()
+    }
+}
 
-    assert_eq!(5, client_full_log.len());
-    assert_eq!("CLIENT_HANDSHAKE_TRAFFIC_SECRET", client_full_log[0].label);
-    assert_eq!("SERVER_HANDSHAKE_TRAFFIC_SECRET", client_full_log[1].label);
-    assert_eq!("CLIENT_TRAFFIC_SECRET_0", client_full_log[2].label);
-    assert_eq!("SERVER_TRAFFIC_SECRET_0", client_full_log[3].label);
-    assert_eq!("EXPORTER_SECRET", client_full_log[4].label);
+fn make_raw_public_key_server_config(kt: KeyType) -> ServerConfig {
+    ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_raw_public_key(kt.get_spki(), kt.get_key())
+        .unwrap()
+}
 
-    assert_eq!(client_full_log[0], server_full_log[0]);
-    assert_eq!(client_full_log[1], server_full_log[1]);
-    assert_eq!(client_full_log[2], server_full_log[2]);
-    assert_eq!(client_full_log[3], server_full_log[3]);
-    assert_eq!(client_full_log[4], server_full_log[4]);
+fn make_raw_public_key_client_config(verifier: Arc<PinnedRawPublicKeyVerifier>) -> ClientConfig {
+    ClientConfig::builder()
+        .with_safe_defaults()
+        .with_raw_public_key_verifier(verifier)
+        .with_no_client_auth()
+}
 
-    // resumed
-    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
-    do_handshake(&mut client, &mut server);
+#[test]
+fn server_identified_by_raw_public_key() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let verifier = Arc::new(PinnedRawPublicKeyVerifier::new(kt.get_spki()));
+        let (mut client, mut server) = make_pair_for_configs(
+            make_raw_public_key_client_config(verifier.clone()),
+            make_raw_public_key_server_config(*kt),
+        );
+        do_handshake(&mut client, &mut server);
 
-    let client_resume_log = client_key_log.take();
-    let server_resume_log = server_key_log.take();
+        assert_eq!(client.protocol_version(), Some(ProtocolVersion::TLSv1_3));
+        assert_eq!(
+            *verifier.seen_names.lock().unwrap(),
+            vec![server_name("localhost")]
+        );
+        assert_eq!(
+            client.peer_certificates(),
+            Some(&[CertificateDer::from(kt.get_spki())][..])
+        );
 
-    assert_eq!(5, client_resume_log.len());
-    assert_eq!(
-        "CLIENT_HANDSHAKE_TRAFFIC_SECRET",
-        client_resume_log[0].label
+        client
+            .writer()
+            .write_all(b"hello")
+            .unwrap();
+        transfer(&mut client, &mut server);
+        server.process_new_packets().unwrap();
+        check_read(&mut server.reader(), b"hello");
+    }
+}
+
+#[test]
+fn server_raw_public_key_must_be_pinned() {
+    let verifier = Arc::new(PinnedRawPublicKeyVerifier::new(KeyType::Ecdsa.get_spki()));
+    let (mut client, mut server) = make_pair_for_configs(
+        make_raw_public_key_client_config(verifier),
+        make_raw_public_key_server_config(KeyType::Ed25519),
     );
     assert_eq!(
-        "SERVER_HANDSHAKE_TRAFFIC_SECRET",
-        client_resume_log[1].label
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Client(Error::InvalidCertificate(
+            CertificateError::UnknownIssuer
+        )))
     );
-    assert_eq!("CLIENT_TRAFFIC_SECRET_0", client_resume_log[2].label);
-    assert_eq!("SERVER_TRAFFIC_SECRET_0", client_resume_log[3].label);
-    assert_eq!("EXPORTER_SECRET", client_resume_log[4].label);
+}
 
-    assert_eq!(6, server_resume_log.len());
-    assert_eq!("CLIENT_EARLY_TRAFFIC_SECRET", server_resume_log[0].label);
+#[test]
+fn server_raw_public_key_signature_is_checked() {
+    // The pinned key is presented, but the handshake is signed by another.
+    let kt = KeyType::Ed25519;
+    let verifier = Arc::new(PinnedRawPublicKeyVerifier::new(kt.get_spki()));
+    let server_config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_raw_public_key(kt.get_spki(), kt.get_client_key())
+        .unwrap();
+    let (mut client, mut server) =
+        make_pair_for_configs(make_raw_public_key_client_config(verifier), server_config);
     assert_eq!(
-        "CLIENT_HANDSHAKE_TRAFFIC_SECRET",
-        server_resume_log[1].label
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Client(Error::InvalidCertificate(
+            CertificateError::BadSignature
+        )))
+    );
+}
+
+#[test]
+fn raw_public_key_client_refused_by_certificate_server() {
+    let kt = KeyType::Ecdsa;
+    let verifier = Arc::new(PinnedRawPublicKeyVerifier::new(kt.get_spki()));
+    let (mut client, mut server) = make_pair_for_configs(
+        make_raw_public_key_client_config(verifier),
+        make_server_config(kt),
     );
     assert_eq!(
-        "SERVER_HANDSHAKE_TRAFFIC_SECRET",
-        server_resume_log[2].label
+        do_handshake_until_both_error(&mut client, &mut server),
+        Err(vec![
+            ErrorFromPeer::Server(Error::PeerIncompatible(
+                PeerIncompatible::NoServerCertificateTypesInCommon
+            )),
+            ErrorFromPeer::Client(Error::AlertReceived(
+                AlertDescription::UnsupportedCertificate
+            )),
+        ])
     );
-    assert_eq!("CLIENT_TRAFFIC_SECRET_0", server_resume_log[3].label);
-    assert_eq!("SERVER_TRAFFIC_SECRET_0", server_resume_log[4].label);
-    assert_eq!("EXPORTER_SECRET", server_resume_log[5].label);
-
-    assert_eq!(client_resume_log[0], server_resume_log[1]);
-    assert_eq!(client_resume_log[1], server_resume_log[2]);
-    assert_eq!(client_resume_log[2], server_resume_log[3]);
-    assert_eq!(client_resume_log[3], server_resume_log[4]);
-    assert_eq!(client_resume_log[4], server_resume_log[5]);
 }
 
 #[test]
-fn vectored_write_for_server_appdata() {
-    let (mut client, mut server) = make_pair(KeyType::Rsa);
-    do_handshake(&mut client, &mut server);
-
-    server
-        .writer()
-        .write_all(b"01234567890123456789")
-        .unwrap();
-    server
-        .writer()
-        .write_all(b"01234567890123456789")
-        .unwrap();
-    {
-        let mut pipe = OtherSession::new(&mut client);
-        let wrlen = server.write_tls(&mut pipe).unwrap();
-        assert_eq!(84, wrlen);
-        assert_eq!(pipe.writevs, vec![vec![42, 42]]);
+fn certificate_client_refused_by_raw_public_key_server() {
+    let kt = KeyType::Ecdsa;
+    for version in rustls::ALL_VERSIONS {
+        let (mut client, mut server) = make_pair_for_configs(
+            make_client_config_with_versions(kt, &[version]),
+            make_raw_public_key_server_config(kt),
+        );
+        assert_eq!(
+            do_handshake_until_both_error(&mut client, &mut server),
+            Err(vec![
+                ErrorFromPeer::Server(Error::PeerIncompatible(
+                    PeerIncompatible::NoServerCertificateTypesInCommon
+                )),
+                ErrorFromPeer::Client(Error::AlertReceived(
+                    AlertDescription::UnsupportedCertificate
+                )),
+            ])
+        );
     }
-    check_read(
-        &mut client.reader(),
-        b"0123456789012345678901234567890123456789",
-    );
 }
 
+#[cfg(feature = "tls12")]
 #[test]
-fn vectored_write_for_client_appdata() {
-    let (mut client, mut server) = make_pair(KeyType::Rsa);
-    do_handshake(&mut client, &mut server);
+fn raw_public_keys_require_tls13() {
+    let verifier = Arc::new(PinnedRawPublicKeyVerifier::new(KeyType::Ecdsa.get_spki()));
+    let client_config = ClientConfig::builder()
+        .with_safe_default_cipher_suites()
+        .with_safe_default_kx_groups()
+        .with_protocol_versions(&[&rustls::version::TLS12])
+        .unwrap()
+        .with_raw_public_key_verifier(verifier)
+        .with_no_client_auth();
 
-    client
-        .writer()
-        .write_all(b"01234567890123456789")
-        .unwrap();
-    client
-        .writer()
-        .write_all(b"01234567890123456789")
-        .unwrap();
-    {
-        let mut pipe = OtherSession::new(&mut server);
-        let wrlen = client.write_tls(&mut pipe).unwrap();
-        assert_eq!(84, wrlen);
-        assert_eq!(pipe.writevs, vec![vec![42, 42]]);
-    }
-    check_read(
-        &mut server.reader(),
-        b"0123456789012345678901234567890123456789",
-    );
+    assert!(matches!(
+        ClientConnection::new(Arc::new(client_config), server_name("localhost")),
+        Err(Error::General(_))
+    ));
 }
 
 #[test]
-fn vectored_write_for_server_handshake_with_half_rtt_data() {
-    let mut server_config = make_server_config(KeyType::Rsa);
-    server_config.send_half_rtt_data = true;
-    let (mut client, mut server) =
-        make_pair_for_configs(make_client_config_with_auth(KeyType::Rsa), server_config);
+fn server_name_is_lowercased_for_verification_by_default() {
+    let kt = KeyType::Rsa;
+    let server_config = Arc::new(make_server_config(kt));
 
-    server
-        .writer()
-        .write_all(b"01234567890123456789")
-        .unwrap();
-    server
-        .writer()
-        .write_all(b"0123456789")
-        .unwrap();
+    for version in rustls::ALL_VERSIONS {
+        let verifier = Arc::new(ServerNameRecordingVerifier::new(kt));
+        let mut client_config = make_client_config_with_versions(kt, &[version]);
+        client_config
+            .dangerous()
+            .set_certificate_verifier(verifier.clone());
 
-    transfer(&mut client, &mut server);
-    server.process_new_packets().unwrap();
-    {
-        let mut pipe = OtherSession::new(&mut client);
-        let wrlen = server.write_tls(&mut pipe).unwrap();
-        // don't assert exact sizes here, to avoid a brittle test
-        assert!(wrlen > 4000); // its pretty big (contains cert chain)
-        assert_eq!(pipe.writevs.len(), 1); // only one writev
-        assert_eq!(pipe.writevs[0].len(), 8); // at least a server hello/ccs/cert/serverkx/0.5rtt data
-    }
+        let mut client =
+            ClientConnection::new(Arc::new(client_config), server_name("LocalHost")).unwrap();
+        let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+        do_handshake(&mut client, &mut server);
 
-    client.process_new_packets().unwrap();
-    transfer(&mut client, &mut server);
-    server.process_new_packets().unwrap();
-    {
-        let mut pipe = OtherSession::new(&mut client);
-        let wrlen = server.write_tls(&mut pipe).unwrap();
-        // 4 tickets
-        assert_eq!(wrlen, 103 * 4);
-        assert_eq!(pipe.writevs, vec![vec![103, 103, 103, 103]]);
+        assert_eq!(
+            *verifier.seen_names.lock().unwrap(),
+            vec![server_name("localhost")]
+        );
     }
-
-    assert!(!server.is_handshaking());
-    assert!(!client.is_handshaking());
-    check_read(&mut client.reader(), b"012345678901234567890123456789");
 }
 
-fn check_half_rtt_does_not_work(server_config: ServerConfig) {
-    let (mut client, mut server) =
-        make_pair_for_configs(make_client_config_with_auth(KeyType::Rsa), server_config);
+#[test]
+fn client_can_override_server_name_normalization() {
+    let kt = KeyType::Rsa;
+    let server_config = Arc::new(make_server_config(kt));
 
-    server
-        .writer()
-        .write_all(b"01234567890123456789")
-        .unwrap();
-    server
-        .writer()
-        .write_all(b"0123456789")
-        .unwrap();
+    for version in rustls::ALL_VERSIONS {
+        let verifier = Arc::new(ServerNameRecordingVerifier::new(kt));
+        let mut client_config = make_client_config_with_versions(kt, &[version]);
+        client_config
+            .dangerous()
+            .set_certificate_verifier(verifier.clone());
+        client_config
+            .dangerous()
+            .set_server_name_normalizer(Arc::new(UppercaseServerName));
 
-    transfer(&mut client, &mut server);
-    server.process_new_packets().unwrap();
-    {
-        let mut pipe = OtherSession::new(&mut client);
-        let wrlen = server.write_tls(&mut pipe).unwrap();
-        // don't assert exact sizes here, to avoid a brittle test
-        assert!(wrlen > 4000); // its pretty big (contains cert chain)
-        assert_eq!(pipe.writevs.len(), 1); // only one writev
-        assert!(pipe.writevs[0].len() >= 6); // at least a server hello/ccs/cert/serverkx data
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&Arc::new(client_config), &server_config);
+        do_handshake(&mut client, &mut server);
+
+        assert_eq!(
+            *verifier.seen_names.lock().unwrap(),
+            vec![server_name("LOCALHOST")]
+        );
+        // SNI is not affected
+        assert_eq!(server.server_name(), Some("localhost"));
     }
+}
 
-    // client second flight
-    client.process_new_packets().unwrap();
-    transfer(&mut client, &mut server);
+#[test]
+fn server_verifier_allows_configured_clock_skew() {
+    let kt = KeyType::Rsa;
+    let chain = kt.get_chain();
+    let verify = |skew: Duration, now: u64| {
+        rustls::client::WebPkiServerVerifier::new(get_client_root_store(kt))
+            .with_allowed_clock_skew(skew)
+            .verify_server_cert(
+                &chain[0],
+                &chain[1..],
+                &server_name("localhost"),
+                &[],
+                UnixTime::since_unix_epoch(Duration::from_secs(now)),
+            )
+            .map(|_| ())
+    };
 
-    // when client auth is enabled, we don't sent 0.5-rtt data, as we'd be sending
-    // it to an unauthenticated peer. so it happens here, in the server's second
-    // flight (42 and 32 are lengths of appdata sent above).
-    server.process_new_packets().unwrap();
-    {
-        let mut pipe = OtherSession::new(&mut client);
-        let wrlen = server.write_tls(&mut pipe).unwrap();
-        assert_eq!(wrlen, 486);
-        assert_eq!(pipe.writevs, vec![vec![103, 103, 103, 103, 42, 32]]);
-    }
+    // validity period of test-ca/rsa/end.cert (and its intermediate)
+    let not_before = 1_688_571_646; // 2023-07-05T15:40:46Z
+    let not_after = 1_861_371_646; // 2028-12-25T15:40:46Z
 
-    assert!(!server.is_handshaking());
-    assert!(!client.is_handshaking());
-    check_read(&mut client.reader(), b"012345678901234567890123456789");
+    let expired = not_after + 30;
+    assert_eq!(
+        verify(Duration::ZERO, expired),
+        Err(Error::InvalidCertificate(CertificateError::Expired))
+    );
+    assert_eq!(verify(Duration::from_secs(60), expired), Ok(()));
+    assert_eq!(
+        verify(Duration::from_secs(20), expired),
+        Err(Error::InvalidCertificate(CertificateError::Expired))
+    );
+
+    let not_yet_valid = not_before - 30;
+    assert_eq!(
+        verify(Duration::ZERO, not_yet_valid),
+        Err(Error::InvalidCertificate(CertificateError::NotValidYet))
+    );
+    assert_eq!(verify(Duration::from_secs(60), not_yet_valid), Ok(()));
+
+    // skew does not excuse other problems
+    assert_eq!(
+        rustls::client::WebPkiServerVerifier::new(get_client_root_store(kt))
+            .with_allowed_clock_skew(Duration::from_secs(60))
+            .verify_server_cert(
+                &chain[0],
+                &chain[1..],
+                &server_name("example.com"),
+                &[],
+                UnixTime::since_unix_epoch(Duration::from_secs(expired)),
+            )
+            .map(|_| ()),
+        Err(Error::InvalidCertificate(CertificateError::NotValidForName))
+    );
 }
 
 #[test]
-fn vectored_write_for_server_handshake_no_half_rtt_with_client_auth() {
-    let mut server_config = make_server_config_with_mandatory_client_auth(KeyType::Rsa);
-    server_config.send_half_rtt_data = true; // ask even though it will be ignored
-    check_half_rtt_does_not_work(server_config);
+fn server_verifier_signature_schemes_are_independent_of_kx_groups() {
+    let client_config_for = |kt: KeyType| {
+        let verifier = rustls::client::WebPkiServerVerifier::new(get_client_root_store(kt))
+            .with_signature_schemes(&[
+                SignatureScheme::ECDSA_NISTP384_SHA384,
+                SignatureScheme::RSA_PSS_SHA256,
+                // for the test-ca/rsa chain
+                SignatureScheme::RSA_PKCS1_SHA256,
+            ]);
+        let mut config =
+            make_client_config_with_kx_groups(kt, &[rustls::crypto::ring::kx_group::X25519]);
+        config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(verifier));
+        config
+    };
+
+    // test-ca/ecdsa/end.cert has a P-256 key, so its CertificateVerify would be
+    // ECDSA_NISTP256_SHA256: that is never offered, and the server cannot sign.
+    let (mut client, mut server) = make_pair_for_configs(
+        client_config_for(KeyType::Ecdsa),
+        make_server_config(KeyType::Ecdsa),
+    );
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Server(Error::PeerIncompatible(
+            PeerIncompatible::NoSignatureSchemesInCommon
+        )))
+    );
+
+    // but X25519 (the client's only kx group) is unaffected
+    let (mut client, mut server) = make_pair_for_configs(
+        client_config_for(KeyType::Rsa),
+        make_server_config(KeyType::Rsa),
+    );
+    do_handshake(&mut client, &mut server);
 }
 
 #[test]
-fn vectored_write_for_server_handshake_no_half_rtt_by_default() {
-    let server_config = make_server_config(KeyType::Rsa);
-    assert!(!server_config.send_half_rtt_data);
-    check_half_rtt_does_not_work(server_config);
+fn server_verifier_signature_schemes_restrict_certificate_chains() {
+    let kt = KeyType::Rsa;
+    let client_config_for = |schemes: &[SignatureScheme]| {
+        let verifier = rustls::client::WebPkiServerVerifier::new(get_client_root_store(kt))
+            .with_signature_schemes(schemes);
+        let mut config = make_client_config(kt);
+        config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(verifier));
+        config
+    };
+
+    // test-ca/rsa is signed with RSA PKCS#1 SHA-256, though the server signs
+    // its CertificateVerify with RSA-PSS
+    let (mut client, mut server) = make_pair_for_configs(
+        client_config_for(&[SignatureScheme::RSA_PSS_SHA256]),
+        make_server_config(kt),
+    );
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Client(Error::InvalidCertificate(
+            CertificateError::BadSignature
+        )))
+    );
+
+    let (mut client, mut server) = make_pair_for_configs(
+        client_config_for(&[
+            SignatureScheme::RSA_PSS_SHA256,
+            SignatureScheme::RSA_PKCS1_SHA256,
+        ]),
+        make_server_config(kt),
+    );
+    do_handshake(&mut client, &mut server);
 }
 
 #[test]
-fn vectored_write_for_client_handshake() {
-    let (mut client, mut server) = make_pair(KeyType::Rsa);
+fn sni_resolver_rejects_wrong_names() {
+    let kt = KeyType::Rsa;
+    let mut resolver = rustls::server::ResolvesServerCertUsingSni::new();
+    let signing_key = sign::RsaSigningKey::new(&kt.get_key()).unwrap();
+    let signing_key: Arc<dyn sign::SigningKey> = Arc::new(signing_key);
 
-    client
-        .writer()
-        .write_all(b"01234567890123456789")
-        .unwrap();
-    client
-        .writer()
-        .write_all(b"0123456789")
-        .unwrap();
-    {
-        let mut pipe = OtherSession::new(&mut server);
-        let wrlen = client.write_tls(&mut pipe).unwrap();
-        // don't assert exact sizes here, to avoid a brittle test
-        assert!(wrlen > 200); // just the client hello
-        assert_eq!(pipe.writevs.len(), 1); // only one writev
-        assert!(pipe.writevs[0].len() == 1); // only a client hello
-    }
+    assert_eq!(
+        Ok(()),
+        resolver.add(
+            "localhost",
+            sign::CertifiedKey::new(kt.get_chain(), signing_key.clone())
+        )
+    );
+    assert_eq!(
+        Err(Error::InvalidCertificate(CertificateError::NotValidForName)),
+        resolver.add(
+            "not-localhost",
+            sign::CertifiedKey::new(kt.get_chain(), signing_key.clone())
+        )
+    );
+    assert_eq!(
+        Err(Error::General("Bad DNS name".into())),
+        resolver.add(
+            "not ascii 🦀",
+            sign::CertifiedKey::new(kt.get_chain(), signing_key.clone())
+        )
+    );
+}
 
-    transfer(&mut server, &mut client);
-    client.process_new_packets().unwrap();
+#[test]
+fn sni_resolver_lower_cases_configured_names() {
+    let kt = KeyType::Rsa;
+    let mut resolver = rustls::server::ResolvesServerCertUsingSni::new();
+    let signing_key = sign::RsaSigningKey::new(&kt.get_key()).unwrap();
+    let signing_key: Arc<dyn sign::SigningKey> = Arc::new(signing_key);
 
-    {
-        let mut pipe = OtherSession::new(&mut server);
-        let wrlen = client.write_tls(&mut pipe).unwrap();
-        assert_eq!(wrlen, 154);
-        // CCS, finished, then two application datas
-        assert_eq!(pipe.writevs, vec![vec![6, 74, 42, 32]]);
-    }
+    assert_eq!(
+        Ok(()),
+        resolver.add(
+            "LOCALHOST",
+            sign::CertifiedKey::new(kt.get_chain(), signing_key.clone())
+        )
+    );
 
-    assert!(!server.is_handshaking());
-    assert!(!client.is_handshaking());
-    check_read(&mut server.reader(), b"012345678901234567890123456789");
+    let mut server_config = make_server_config(kt);
+    server_config.cert_resolver = Arc::new(resolver);
+    let server_config = Arc::new(server_config);
+
+    let mut server1 = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+    let mut client1 =
+        ClientConnection::new(Arc::new(make_client_config(kt)), server_name("localhost")).unwrap();
+    let err = do_handshake_until_error(&mut client1, &mut server1);
+    assert_eq!(err, Ok(()));
 }
 
 #[test]
-fn vectored_write_with_slow_client() {
-    let (mut client, mut server) = make_pair(KeyType::Rsa);
-
-    client.set_buffer_limit(Some(32));
+fn sni_resolver_lower_cases_queried_names() {
+    // actually, the handshake parser does this, but the effect is the same.
+    let kt = KeyType::Rsa;
+    let mut resolver = rustls::server::ResolvesServerCertUsingSni::new();
+    let signing_key = sign::RsaSigningKey::new(&kt.get_key()).unwrap();
+    let signing_key: Arc<dyn sign::SigningKey> = Arc::new(signing_key);
 
-    do_handshake(&mut client, &mut server);
-    server
-        .writer()
-        .write_all(b"01234567890123456789")
-        .unwrap();
+    assert_eq!(
+        Ok(()),
+        resolver.add(
+            "localhost",
+            sign::CertifiedKey::new(kt.get_chain(), signing_key.clone())
+        )
+    );
 
-    {
-        let mut pipe = OtherSession::new(&mut client);
-        pipe.short_writes = true;
-        let wrlen = server.write_tls(&mut pipe).unwrap()
-            + server.write_tls(&mut pipe).unwrap()
-            + server.write_tls(&mut pipe).unwrap()
-            + server.write_tls(&mut pipe).unwrap()
-            + server.write_tls(&mut pipe).unwrap()
-            + server.write_tls(&mut pipe).unwrap();
-        assert_eq!(42, wrlen);
-        assert_eq!(
-            pipe.writevs,
-            vec![vec![21], vec![10], vec![5], vec![3], vec![3]]
-        );
-    }
-    check_read(&mut client.reader(), b"01234567890123456789");
-}
+    let mut server_config = make_server_config(kt);
+    server_config.cert_resolver = Arc::new(resolver);
+    let server_config = Arc::new(server_config);
 
-struct ServerStorage {
-    storage: Arc<dyn rustls::server::StoresServerSessions>,
-    put_count: AtomicUsize,
-    get_count: AtomicUsize,
-    take_count: AtomicUsize,
+    let mut server1 = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+    let mut client1 =
+        ClientConnection::new(Arc::new(make_client_config(kt)), server_name("LOCALHOST")).unwrap();
+    let err = do_handshake_until_error(&mut client1, &mut server1);
+    assert_eq!(err, Ok(()));
 }
 
-impl ServerStorage {
-    fn new() -> Self {
-        Self {
-            storage: rustls::server::ServerSessionMemoryCache::new(1024),
-            put_count: AtomicUsize::new(0),
-            get_count: AtomicUsize::new(0),
-            take_count: AtomicUsize::new(0),
-        }
-    }
+#[test]
+fn sni_resolver_rejects_bad_certs() {
+    let kt = KeyType::Rsa;
+    let mut resolver = rustls::server::ResolvesServerCertUsingSni::new();
+    let signing_key = sign::RsaSigningKey::new(&kt.get_key()).unwrap();
+    let signing_key: Arc<dyn sign::SigningKey> = Arc::new(signing_key);
 
-    fn puts(&self) -> usize {
-        self.put_count.load(Ordering::SeqCst)
-    }
-    fn gets(&self) -> usize {
-        self.get_count.load(Ordering::SeqCst)
-    }
-    fn takes(&self) -> usize {
-        self.take_count.load(Ordering::SeqCst)
-    }
-}
+    assert_eq!(
+        Err(Error::NoCertificatesPresented),
+        resolver.add(
+            "localhost",
+            sign::CertifiedKey::new(vec![], signing_key.clone())
+        )
+    );
 
-impl fmt::Debug for ServerStorage {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "(put: {:?}, get: {:?}, take: {:?})",
-            self.put_count, self.get_count, self.take_count
+    let bad_chain = vec![CertificateDer::from(vec![0xa0])];
+    assert_eq!(
+        Err(Error::InvalidCertificate(CertificateError::BadEncoding)),
+        resolver.add(
+            "localhost",
+            sign::CertifiedKey::new(bad_chain, signing_key.clone())
         )
-    }
+    );
 }
 
-impl rustls::server::StoresServerSessions for ServerStorage {
-    fn put(&self, key: Vec<u8>, value: Vec<u8>) -> bool {
-        self.put_count
-            .fetch_add(1, Ordering::SeqCst);
-        self.storage.put(key, value)
-    }
+fn do_exporter_test(client_config: ClientConfig, server_config: ServerConfig) {
+    let mut client_secret = [0u8; 64];
+    let mut server_secret = [0u8; 64];
 
-    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
-        self.get_count
-            .fetch_add(1, Ordering::SeqCst);
-        self.storage.get(key)
-    }
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
 
-    fn take(&self, key: &[u8]) -> Option<Vec<u8>> {
-        self.take_count
-            .fetch_add(1, Ordering::SeqCst);
-        self.storage.take(key)
-    }
+    assert_eq!(
+        Err(Error::HandshakeNotComplete),
+        client.export_keying_material(&mut client_secret, b"label", Some(b"context"))
+    );
+    assert_eq!(
+        Err(Error::HandshakeNotComplete),
+        server.export_keying_material(&mut server_secret, b"label", Some(b"context"))
+    );
+    do_handshake(&mut client, &mut server);
 
-    fn can_cache(&self) -> bool {
-        true
-    }
-}
+    assert!(client
+        .export_keying_material(&mut client_secret, b"label", Some(b"context"))
+        .is_ok());
+    assert!(server
+        .export_keying_material(&mut server_secret, b"label", Some(b"context"))
+        .is_ok());
+    assert_eq!(client_secret.to_vec(), server_secret.to_vec());
 
-#[derive(Debug, Clone)]
-enum ClientStorageOp {
-    SetKxHint(rustls::ServerName, rustls::NamedGroup),
-    GetKxHint(rustls::ServerName, Option<rustls::NamedGroup>),
-    SetTls12Session(rustls::ServerName),
-    GetTls12Session(rustls::ServerName, bool),
-    RemoveTls12Session(rustls::ServerName),
-    InsertTls13Ticket(rustls::ServerName),
-    TakeTls13Ticket(rustls::ServerName, bool),
+    assert!(client
+        .export_keying_material(&mut client_secret, b"label", None)
+        .is_ok());
+    assert_ne!(client_secret.to_vec(), server_secret.to_vec());
+    assert!(server
+        .export_keying_material(&mut server_secret, b"label", None)
+        .is_ok());
+    assert_eq!(client_secret.to_vec(), server_secret.to_vec());
 }
 
-struct ClientStorage {
-    storage: Arc<dyn rustls::client::ClientSessionStore>,
-    ops: Mutex<Vec<ClientStorageOp>>,
-}
+#[cfg(feature = "tls12")]
+#[test]
+fn test_tls12_exporter() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let client_config = make_client_config_with_versions(*kt, &[&rustls::version::TLS12]);
+        let server_config = make_server_config(*kt);
 
-impl ClientStorage {
-    fn new() -> Self {
-        Self {
-            storage: Arc::new(rustls::client::ClientSessionMemoryCache::new(1024)),
-            ops: Mutex::new(Vec::new()),
-        }
+        do_exporter_test(client_config, server_config);
     }
+}
 
-    #[cfg(feature = "tls12")]
-    fn ops(&self) -> Vec<ClientStorageOp> {
-        self.ops.lock().unwrap().clone()
-    }
+#[test]
+fn test_tls13_exporter() {
+    for kt in ALL_KEY_TYPES.iter() {
+        let client_config = make_client_config_with_versions(*kt, &[&rustls::version::TLS13]);
+        let server_config = make_server_config(*kt);
 
-    #[cfg(feature = "tls12")]
-    fn ops_and_reset(&self) -> Vec<ClientStorageOp> {
-        std::mem::take(&mut self.ops.lock().unwrap())
+        do_exporter_test(client_config, server_config);
+    }
+}
+
+fn do_suite_test(
+    client_config: ClientConfig,
+    server_config: ServerConfig,
+    expect_suite: SupportedCipherSuite,
+    expect_version: ProtocolVersion,
+) {
+    println!(
+        "do_suite_test {:?} {:?}",
+        expect_version,
+        expect_suite.suite()
+    );
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+
+    assert_eq!(None, client.negotiated_cipher_suite());
+    assert_eq!(None, server.negotiated_cipher_suite());
+    assert_eq!(None, client.protocol_version());
+    assert_eq!(None, server.protocol_version());
+    assert!(client.is_handshaking());
+    assert!(server.is_handshaking());
+
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+
+    assert!(client.is_handshaking());
+    assert!(server.is_handshaking());
+    assert_eq!(None, client.protocol_version());
+    assert_eq!(Some(expect_version), server.protocol_version());
+    assert_eq!(None, client.negotiated_cipher_suite());
+    assert_eq!(Some(expect_suite), server.negotiated_cipher_suite());
+
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+
+    assert_eq!(Some(expect_suite), client.negotiated_cipher_suite());
+    assert_eq!(Some(expect_suite), server.negotiated_cipher_suite());
+
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+
+    assert!(!client.is_handshaking());
+    assert!(!server.is_handshaking());
+    assert_eq!(Some(expect_version), client.protocol_version());
+    assert_eq!(Some(expect_version), server.protocol_version());
+    assert_eq!(Some(expect_suite), client.negotiated_cipher_suite());
+    assert_eq!(Some(expect_suite), server.negotiated_cipher_suite());
+}
+
+fn find_suite(suite: CipherSuite) -> SupportedCipherSuite {
+    for scs in ALL_CIPHER_SUITES.iter().copied() {
+        if scs.suite() == suite {
+            return scs;
+        }
+    }
+
+    panic!("find_suite given unsupported suite");
+}
+
+static TEST_CIPHERSUITES: &[(&rustls::SupportedProtocolVersion, KeyType, CipherSuite)] = &[
+    (
+        &rustls::version::TLS13,
+        KeyType::Rsa,
+        CipherSuite::TLS13_CHACHA20_POLY1305_SHA256,
+    ),
+    (
+        &rustls::version::TLS13,
+        KeyType::Rsa,
+        CipherSuite::TLS13_AES_256_GCM_SHA384,
+    ),
+    (
+        &rustls::version::TLS13,
+        KeyType::Rsa,
+        CipherSuite::TLS13_AES_128_GCM_SHA256,
+    ),
+    #[cfg(feature = "tls12")]
+    (
+        &rustls::version::TLS12,
+        KeyType::Ecdsa,
+        CipherSuite::TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256,
+    ),
+    #[cfg(feature = "tls12")]
+    (
+        &rustls::version::TLS12,
+        KeyType::Rsa,
+        CipherSuite::TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256,
+    ),
+    #[cfg(feature = "tls12")]
+    (
+        &rustls::version::TLS12,
+        KeyType::Ecdsa,
+        CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384,
+    ),
+    #[cfg(feature = "tls12")]
+    (
+        &rustls::version::TLS12,
+        KeyType::Ecdsa,
+        CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256,
+    ),
+    #[cfg(feature = "tls12")]
+    (
+        &rustls::version::TLS12,
+        KeyType::Rsa,
+        CipherSuite::TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384,
+    ),
+    #[cfg(feature = "tls12")]
+    (
+        &rustls::version::TLS12,
+        KeyType::Rsa,
+        CipherSuite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
+    ),
+];
+
+#[test]
+fn negotiated_ciphersuite_default() {
+    for kt in ALL_KEY_TYPES.iter() {
+        do_suite_test(
+            make_client_config(*kt),
+            make_server_config(*kt),
+            find_suite(CipherSuite::TLS13_AES_256_GCM_SHA384),
+            ProtocolVersion::TLSv1_3,
+        );
+    }
+}
+
+#[test]
+fn all_suites_covered() {
+    assert_eq!(ALL_CIPHER_SUITES.len(), TEST_CIPHERSUITES.len());
+}
+
+#[test]
+fn negotiated_ciphersuite_client() {
+    for item in TEST_CIPHERSUITES.iter() {
+        let (version, kt, suite) = *item;
+        let scs = find_suite(suite);
+        let client_config = finish_client_config(
+            kt,
+            ClientConfig::builder()
+                .with_cipher_suites(&[scs])
+                .with_safe_default_kx_groups()
+                .with_protocol_versions(&[version])
+                .unwrap(),
+        );
+
+        do_suite_test(client_config, make_server_config(kt), scs, version.version);
+    }
+}
+
+#[test]
+fn negotiated_ciphersuite_server() {
+    for item in TEST_CIPHERSUITES.iter() {
+        let (version, kt, suite) = *item;
+        let scs = find_suite(suite);
+        let server_config = finish_server_config(
+            kt,
+            ServerConfig::builder()
+                .with_cipher_suites(&[scs])
+                .with_safe_default_kx_groups()
+                .with_protocol_versions(&[version])
+                .unwrap(),
+        );
+
+        do_suite_test(make_client_config(kt), server_config, scs, version.version);
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct KeyLogItem {
+    label: String,
+    client_random: Vec<u8>,
+    secret: Vec<u8>,
+}
+
+struct KeyLogToVec {
+    label: &'static str,
+    items: Mutex<Vec<KeyLogItem>>,
+}
+
+impl KeyLogToVec {
+    fn new(who: &'static str) -> Self {
+        Self {
+            label: who,
+            items: Mutex::new(vec![]),
+        }
+    }
+
+    fn take(&self) -> Vec<KeyLogItem> {
+        std::mem::take(&mut self.items.lock().unwrap())
+    }
+}
+
+impl KeyLog for KeyLogToVec {
+    fn log(&self, label: &str, client: &[u8], secret: &[u8]) {
+        let value = KeyLogItem {
+            label: label.into(),
+            client_random: client.into(),
+            secret: secret.into(),
+        };
+
+        println!("key log {:?}: {:?}", self.label, value);
+
+        self.items.lock().unwrap().push(value);
+    }
+}
+
+#[cfg(feature = "tls12")]
+#[test]
+fn key_log_for_tls12() {
+    let client_key_log = Arc::new(KeyLogToVec::new("client"));
+    let server_key_log = Arc::new(KeyLogToVec::new("server"));
+
+    let kt = KeyType::Rsa;
+    let mut client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS12]);
+    client_config.key_log = client_key_log.clone();
+    let client_config = Arc::new(client_config);
+
+    let mut server_config = make_server_config(kt);
+    server_config.key_log = server_key_log.clone();
+    let server_config = Arc::new(server_config);
+
+    // full handshake
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+
+    let client_full_log = client_key_log.take();
+    let server_full_log = server_key_log.take();
+    assert_eq!(client_full_log, server_full_log);
+    assert_eq!(1, client_full_log.len());
+    assert_eq!("CLIENT_RANDOM", client_full_log[0].label);
+
+    // resumed
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+
+    let client_resume_log = client_key_log.take();
+    let server_resume_log = server_key_log.take();
+    assert_eq!(client_resume_log, server_resume_log);
+    assert_eq!(1, client_resume_log.len());
+    assert_eq!("CLIENT_RANDOM", client_resume_log[0].label);
+    assert_eq!(client_full_log[0].secret, client_resume_log[0].secret);
+}
+
+#[test]
+fn key_log_for_tls13() {
+    let client_key_log = Arc::new(KeyLogToVec::new("client"));
+    let server_key_log = Arc::new(KeyLogToVec::new("server"));
+
+    let kt = KeyType::Rsa;
+    let mut client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS13]);
+    client_config.key_log = client_key_log.clone();
+    let client_config = Arc::new(client_config);
+
+    let mut server_config = make_server_config(kt);
+    server_config.key_log = server_key_log.clone();
+    let server_config = Arc::new(server_config);
+
+    // full handshake
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+
+    let client_full_log = client_key_log.take();
+    let server_full_log = server_key_log.take();
+
+    assert_eq!(5, client_full_log.len());
+    assert_eq!("CLIENT_HANDSHAKE_TRAFFIC_SECRET", client_full_log[0].label);
+    assert_eq!("SERVER_HANDSHAKE_TRAFFIC_SECRET", client_full_log[1].label);
+    assert_eq!("CLIENT_TRAFFIC_SECRET_0", client_full_log[2].label);
+    assert_eq!("SERVER_TRAFFIC_SECRET_0", client_full_log[3].label);
+    assert_eq!("EXPORTER_SECRET", client_full_log[4].label);
+
+    assert_eq!(client_full_log[0], server_full_log[0]);
+    assert_eq!(client_full_log[1], server_full_log[1]);
+    assert_eq!(client_full_log[2], server_full_log[2]);
+    assert_eq!(client_full_log[3], server_full_log[3]);
+    assert_eq!(client_full_log[4], server_full_log[4]);
+
+    // resumed
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+
+    let client_resume_log = client_key_log.take();
+    let server_resume_log = server_key_log.take();
+
+    assert_eq!(5, client_resume_log.len());
+    assert_eq!(
+        "CLIENT_HANDSHAKE_TRAFFIC_SECRET",
+        client_resume_log[0].label
+    );
+    assert_eq!(
+        "SERVER_HANDSHAKE_TRAFFIC_SECRET",
+        client_resume_log[1].label
+    );
+    assert_eq!("CLIENT_TRAFFIC_SECRET_0", client_resume_log[2].label);
+    assert_eq!("SERVER_TRAFFIC_SECRET_0", client_resume_log[3].label);
+    assert_eq!("EXPORTER_SECRET", client_resume_log[4].label);
+
+    assert_eq!(6, server_resume_log.len());
+    assert_eq!("CLIENT_EARLY_TRAFFIC_SECRET", server_resume_log[0].label);
+    assert_eq!(
+        "CLIENT_HANDSHAKE_TRAFFIC_SECRET",
+        server_resume_log[1].label
+    );
+    assert_eq!(
+        "SERVER_HANDSHAKE_TRAFFIC_SECRET",
+        server_resume_log[2].label
+    );
+    assert_eq!("CLIENT_TRAFFIC_SECRET_0", server_resume_log[3].label);
+    assert_eq!("SERVER_TRAFFIC_SECRET_0", server_resume_log[4].label);
+    assert_eq!("EXPORTER_SECRET", server_resume_log[5].label);
+
+    assert_eq!(client_resume_log[0], server_resume_log[1]);
+    assert_eq!(client_resume_log[1], server_resume_log[2]);
+    assert_eq!(client_resume_log[2], server_resume_log[3]);
+    assert_eq!(client_resume_log[3], server_resume_log[4]);
+    assert_eq!(client_resume_log[4], server_resume_log[5]);
+}
+
+#[test]
+fn write_key_log_captures_secrets() {
+    let kt = KeyType::Rsa;
+    let client_key_log = Arc::new(rustls::WriteKeyLog::new(Vec::new()));
+    let mut client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS13]);
+    client_config.key_log = client_key_log.clone();
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, make_server_config(kt));
+    do_handshake(&mut client, &mut server);
+    drop(client);
+
+    let log = Arc::try_unwrap(client_key_log)
+        .ok()
+        .unwrap()
+        .into_inner();
+    let log = String::from_utf8(log).unwrap();
+    let lines = log.lines().collect::<Vec<_>>();
+    assert_eq!(5, lines.len());
+    assert!(lines[0].starts_with("CLIENT_HANDSHAKE_TRAFFIC_SECRET "));
+    assert!(lines
+        .iter()
+        .all(|line| line.split(' ').count() == 3));
+}
+
+#[test]
+fn vectored_write_for_server_appdata() {
+    let (mut client, mut server) = make_pair(KeyType::Rsa);
+    do_handshake(&mut client, &mut server);
+
+    server
+        .writer()
+        .write_all(b"01234567890123456789")
+        .unwrap();
+    server
+        .writer()
+        .write_all(b"01234567890123456789")
+        .unwrap();
+    {
+        let mut pipe = OtherSession::new(&mut client);
+        let wrlen = server.write_tls(&mut pipe).unwrap();
+        assert_eq!(84, wrlen);
+        assert_eq!(pipe.writevs, vec![vec![42, 42]]);
+    }
+    check_read(
+        &mut client.reader(),
+        b"0123456789012345678901234567890123456789",
+    );
+}
+
+#[test]
+fn vectored_write_for_client_appdata() {
+    let (mut client, mut server) = make_pair(KeyType::Rsa);
+    do_handshake(&mut client, &mut server);
+
+    client
+        .writer()
+        .write_all(b"01234567890123456789")
+        .unwrap();
+    client
+        .writer()
+        .write_all(b"01234567890123456789")
+        .unwrap();
+    {
+        let mut pipe = OtherSession::new(&mut server);
+        let wrlen = client.write_tls(&mut pipe).unwrap();
+        assert_eq!(84, wrlen);
+        assert_eq!(pipe.writevs, vec![vec![42, 42]]);
+    }
+    check_read(
+        &mut server.reader(),
+        b"0123456789012345678901234567890123456789",
+    );
+}
+
+#[test]
+fn vectored_write_for_server_handshake_with_half_rtt_data() {
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.send_half_rtt_data = true;
+    let (mut client, mut server) =
+        make_pair_for_configs(make_client_config_with_auth(KeyType::Rsa), server_config);
+
+    server
+        .writer()
+        .write_all(b"01234567890123456789")
+        .unwrap();
+    server
+        .writer()
+        .write_all(b"0123456789")
+        .unwrap();
+
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    {
+        let mut pipe = OtherSession::new(&mut client);
+        let wrlen = server.write_tls(&mut pipe).unwrap();
+        // don't assert exact sizes here, to avoid a brittle test
+        assert!(wrlen > 4000); // its pretty big (contains cert chain)
+        assert_eq!(pipe.writevs.len(), 1); // only one writev
+        assert_eq!(pipe.writevs[0].len(), 8); // at least a server hello/ccs/cert/serverkx/0.5rtt data
+    }
+
+    client.process_new_packets().unwrap();
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    {
+        let mut pipe = OtherSession::new(&mut client);
+        let wrlen = server.write_tls(&mut pipe).unwrap();
+        // 4 tickets
+        assert_eq!(wrlen, 103 * 4);
+        assert_eq!(pipe.writevs, vec![vec![103, 103, 103, 103]]);
+    }
+
+    assert!(!server.is_handshaking());
+    assert!(!client.is_handshaking());
+    check_read(&mut client.reader(), b"012345678901234567890123456789");
+}
+
+#[test]
+fn server_writes_half_rtt_data_after_its_finished() {
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.send_half_rtt_data = true;
+    let (mut client, mut server) =
+        make_pair_for_configs(make_client_config(KeyType::Rsa), server_config);
+
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+
+    // the server has emitted its Finished, but not yet seen the client's
+    assert!(server.is_handshaking());
+    server
+        .writer()
+        .write_all(b"half-rtt")
+        .unwrap();
+
+    {
+        let mut pipe = OtherSession::new(&mut client);
+        server.write_tls(&mut pipe).unwrap();
+        assert_eq!(pipe.writevs.len(), 1);
+        // the final record in the flight is the 0.5-RTT data (8 bytes, plus
+        // content type, tag and header)
+        assert_eq!(pipe.writevs[0].last(), Some(&(8 + 1 + 16 + 5)));
+    }
+
+    client.process_new_packets().unwrap();
+    assert!(!client.is_handshaking());
+    check_read(&mut client.reader(), b"half-rtt");
+    assert!(server.is_handshaking());
+}
+
+fn check_half_rtt_does_not_work(server_config: ServerConfig) {
+    let (mut client, mut server) =
+        make_pair_for_configs(make_client_config_with_auth(KeyType::Rsa), server_config);
+
+    server
+        .writer()
+        .write_all(b"01234567890123456789")
+        .unwrap();
+    server
+        .writer()
+        .write_all(b"0123456789")
+        .unwrap();
+
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    {
+        let mut pipe = OtherSession::new(&mut client);
+        let wrlen = server.write_tls(&mut pipe).unwrap();
+        // don't assert exact sizes here, to avoid a brittle test
+        assert!(wrlen > 4000); // its pretty big (contains cert chain)
+        assert_eq!(pipe.writevs.len(), 1); // only one writev
+        assert!(pipe.writevs[0].len() >= 6); // at least a server hello/ccs/cert/serverkx data
+    }
+
+    // client second flight
+    client.process_new_packets().unwrap();
+    transfer(&mut client, &mut server);
+
+    // when client auth is enabled, we don't sent 0.5-rtt data, as we'd be sending
+    // it to an unauthenticated peer. so it happens here, in the server's second
+    // flight (42 and 32 are lengths of appdata sent above).
+    server.process_new_packets().unwrap();
+    {
+        let mut pipe = OtherSession::new(&mut client);
+        let wrlen = server.write_tls(&mut pipe).unwrap();
+        assert_eq!(wrlen, 486);
+        assert_eq!(pipe.writevs, vec![vec![103, 103, 103, 103, 42, 32]]);
+    }
+
+    assert!(!server.is_handshaking());
+    assert!(!client.is_handshaking());
+    check_read(&mut client.reader(), b"012345678901234567890123456789");
+}
+
+#[test]
+fn vectored_write_for_server_handshake_no_half_rtt_with_client_auth() {
+    let mut server_config = make_server_config_with_mandatory_client_auth(KeyType::Rsa);
+    server_config.send_half_rtt_data = true; // ask even though it will be ignored
+    check_half_rtt_does_not_work(server_config);
+}
+
+#[test]
+fn vectored_write_for_server_handshake_no_half_rtt_by_default() {
+    let server_config = make_server_config(KeyType::Rsa);
+    assert!(!server_config.send_half_rtt_data);
+    check_half_rtt_does_not_work(server_config);
+}
+
+#[test]
+fn vectored_write_for_client_handshake() {
+    let (mut client, mut server) = make_pair(KeyType::Rsa);
+
+    client
+        .writer()
+        .write_all(b"01234567890123456789")
+        .unwrap();
+    client
+        .writer()
+        .write_all(b"0123456789")
+        .unwrap();
+    {
+        let mut pipe = OtherSession::new(&mut server);
+        let wrlen = client.write_tls(&mut pipe).unwrap();
+        // don't assert exact sizes here, to avoid a brittle test
+        assert!(wrlen > 200); // just the client hello
+        assert_eq!(pipe.writevs.len(), 1); // only one writev
+        assert!(pipe.writevs[0].len() == 1); // only a client hello
+    }
+
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+
+    {
+        let mut pipe = OtherSession::new(&mut server);
+        let wrlen = client.write_tls(&mut pipe).unwrap();
+        assert_eq!(wrlen, 154);
+        // CCS, finished, then two application datas
+        assert_eq!(pipe.writevs, vec![vec![6, 74, 42, 32]]);
+    }
+
+    assert!(!server.is_handshaking());
+    assert!(!client.is_handshaking());
+    check_read(&mut server.reader(), b"012345678901234567890123456789");
+}
+
+#[test]
+fn vectored_write_with_slow_client() {
+    let (mut client, mut server) = make_pair(KeyType::Rsa);
+
+    client.set_buffer_limit(Some(32));
+
+    do_handshake(&mut client, &mut server);
+    server
+        .writer()
+        .write_all(b"01234567890123456789")
+        .unwrap();
+
+    {
+        let mut pipe = OtherSession::new(&mut client);
+        pipe.short_writes = true;
+        let wrlen = server.write_tls(&mut pipe).unwrap()
+            + server.write_tls(&mut pipe).unwrap()
+            + server.write_tls(&mut pipe).unwrap()
+            + server.write_tls(&mut pipe).unwrap()
+            + server.write_tls(&mut pipe).unwrap()
+            + server.write_tls(&mut pipe).unwrap();
+        assert_eq!(42, wrlen);
+        assert_eq!(
+            pipe.writevs,
+            vec![vec![21], vec![10], vec![5], vec![3], vec![3]]
+        );
+    }
+    check_read(&mut client.reader(), b"01234567890123456789");
+}
+
+struct ServerStorage {
+    storage: Arc<dyn rustls::server::StoresServerSessions>,
+    put_count: AtomicUsize,
+    get_count: AtomicUsize,
+    take_count: AtomicUsize,
+}
+
+impl ServerStorage {
+    fn new() -> Self {
+        Self {
+            storage: rustls::server::ServerSessionMemoryCache::new(1024),
+            put_count: AtomicUsize::new(0),
+            get_count: AtomicUsize::new(0),
+            take_count: AtomicUsize::new(0),
+        }
+    }
+
+    fn puts(&self) -> usize {
+        self.put_count.load(Ordering::SeqCst)
+    }
+    fn gets(&self) -> usize {
+        self.get_count.load(Ordering::SeqCst)
+    }
+    fn takes(&self) -> usize {
+        self.take_count.load(Ordering::SeqCst)
+    }
+}
+
+impl fmt::Debug for ServerStorage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "(put: {:?}, get: {:?}, take: {:?})",
+            self.put_count, self.get_count, self.take_count
+        )
+    }
+}
+
+impl rustls::server::StoresServerSessions for ServerStorage {
+    fn put(&self, key: Vec<u8>, value: Vec<u8>) -> bool {
+        self.put_count
+            .fetch_add(1, Ordering::SeqCst);
+        self.storage.put(key, value)
+    }
+
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.get_count
+            .fetch_add(1, Ordering::SeqCst);
+        self.storage.get(key)
+    }
+
+    fn take(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.take_count
+            .fetch_add(1, Ordering::SeqCst);
+        self.storage.take(key)
+    }
+
+    fn can_cache(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Debug, Clone)]
+enum ClientStorageOp {
+    SetKxHint(rustls::ServerName, rustls::NamedGroup),
+    GetKxHint(rustls::ServerName, Option<rustls::NamedGroup>),
+    SetTls12Session(rustls::ServerName),
+    GetTls12Session(rustls::ServerName, bool),
+    RemoveTls12Session(rustls::ServerName),
+    InsertTls13Ticket(rustls::ServerName),
+    TakeTls13Ticket(rustls::ServerName, bool),
+}
+
+struct ClientStorage {
+    storage: Arc<dyn rustls::client::ClientSessionStore>,
+    ops: Mutex<Vec<ClientStorageOp>>,
+}
+
+impl ClientStorage {
+    fn new() -> Self {
+        Self {
+            storage: Arc::new(rustls::client::ClientSessionMemoryCache::new(1024)),
+            ops: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn ops(&self) -> Vec<ClientStorageOp> {
+        self.ops.lock().unwrap().clone()
+    }
+
+    #[cfg(feature = "tls12")]
+    fn ops_and_reset(&self) -> Vec<ClientStorageOp> {
+        std::mem::take(&mut self.ops.lock().unwrap())
     }
 }
 
@@ -2990,1437 +5135,3626 @@ impl fmt::Debug for ClientStorage {
     }
 }
 
-impl rustls::client::ClientSessionStore for ClientStorage {
-    fn set_kx_hint(&self, server_name: &rustls::ServerName, group: rustls::NamedGroup) {
-        self.ops
-            .lock()
-            .unwrap()
-            .push(ClientStorageOp::SetKxHint(server_name.clone(), group));
-        self.storage
-            .set_kx_hint(server_name, group)
+impl rustls::client::ClientSessionStore for ClientStorage {
+    fn set_kx_hint(&self, server_name: &rustls::ServerName, group: rustls::NamedGroup) {
+        self.ops
+            .lock()
+            .unwrap()
+            .push(ClientStorageOp::SetKxHint(server_name.clone(), group));
+        self.storage
+            .set_kx_hint(server_name, group)
+    }
+
+    fn kx_hint(&self, server_name: &rustls::ServerName) -> Option<rustls::NamedGroup> {
+        let rc = self.storage.kx_hint(server_name);
+        self.ops
+            .lock()
+            .unwrap()
+            .push(ClientStorageOp::GetKxHint(server_name.clone(), rc));
+        rc
+    }
+
+    fn set_tls12_session(
+        &self,
+        server_name: &rustls::ServerName,
+        value: rustls::client::Tls12ClientSessionValue,
+    ) {
+        self.ops
+            .lock()
+            .unwrap()
+            .push(ClientStorageOp::SetTls12Session(server_name.clone()));
+        self.storage
+            .set_tls12_session(server_name, value)
+    }
+
+    fn tls12_session(
+        &self,
+        server_name: &rustls::ServerName,
+    ) -> Option<rustls::client::Tls12ClientSessionValue> {
+        let rc = self.storage.tls12_session(server_name);
+        self.ops
+            .lock()
+            .unwrap()
+            .push(ClientStorageOp::GetTls12Session(
+                server_name.clone(),
+                rc.is_some(),
+            ));
+        rc
+    }
+
+    fn remove_tls12_session(&self, server_name: &rustls::ServerName) {
+        self.ops
+            .lock()
+            .unwrap()
+            .push(ClientStorageOp::RemoveTls12Session(server_name.clone()));
+        self.storage
+            .remove_tls12_session(server_name);
+    }
+
+    fn insert_tls13_ticket(
+        &self,
+        server_name: &rustls::ServerName,
+        value: rustls::client::Tls13ClientSessionValue,
+    ) {
+        self.ops
+            .lock()
+            .unwrap()
+            .push(ClientStorageOp::InsertTls13Ticket(server_name.clone()));
+        self.storage
+            .insert_tls13_ticket(server_name, value);
+    }
+
+    fn take_tls13_ticket(
+        &self,
+        server_name: &rustls::ServerName,
+    ) -> Option<rustls::client::Tls13ClientSessionValue> {
+        let rc = self
+            .storage
+            .take_tls13_ticket(server_name);
+        self.ops
+            .lock()
+            .unwrap()
+            .push(ClientStorageOp::TakeTls13Ticket(
+                server_name.clone(),
+                rc.is_some(),
+            ));
+        rc
+    }
+}
+
+#[test]
+fn tls13_stateful_resumption() {
+    let kt = KeyType::Rsa;
+    let client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS13]);
+    let client_config = Arc::new(client_config);
+
+    let mut server_config = make_server_config(kt);
+    let storage = Arc::new(ServerStorage::new());
+    server_config.session_storage = storage.clone();
+    let server_config = Arc::new(server_config);
+
+    // full handshake
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    let (full_c2s, full_s2c) = do_handshake(&mut client, &mut server);
+    assert_eq!(storage.puts(), 4);
+    assert_eq!(storage.gets(), 0);
+    assert_eq!(storage.takes(), 0);
+    assert_eq!(
+        client
+            .peer_certificates()
+            .map(|certs| certs.len()),
+        Some(3)
+    );
+
+    // resumed
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    let (resume_c2s, resume_s2c) = do_handshake(&mut client, &mut server);
+    assert!(resume_c2s > full_c2s);
+    assert!(resume_s2c < full_s2c);
+    assert_eq!(storage.puts(), 8);
+    assert_eq!(storage.gets(), 0);
+    assert_eq!(storage.takes(), 1);
+    assert_eq!(
+        client
+            .peer_certificates()
+            .map(|certs| certs.len()),
+        Some(3)
+    );
+
+    // resumed again
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    let (resume2_c2s, resume2_s2c) = do_handshake(&mut client, &mut server);
+    assert_eq!(resume_s2c, resume2_s2c);
+    assert_eq!(resume_c2s, resume2_c2s);
+    assert_eq!(storage.puts(), 12);
+    assert_eq!(storage.gets(), 0);
+    assert_eq!(storage.takes(), 2);
+    assert_eq!(
+        client
+            .peer_certificates()
+            .map(|certs| certs.len()),
+        Some(3)
+    );
+}
+
+/// A stand-in for a session cache shared by a group of servers, like Redis.
+type SharedSessionCache = Arc<Mutex<HashMap<Vec<u8>, Vec<u8>>>>;
+
+/// One server's client for a `SharedSessionCache`.
+struct ClusterSessionStore {
+    cache: SharedSessionCache,
+}
+
+impl rustls::server::StoresServerSessions for ClusterSessionStore {
+    fn put(&self, key: Vec<u8>, value: Vec<u8>) -> bool {
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(key, value);
+        true
+    }
+
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.cache
+            .lock()
+            .unwrap()
+            .get(key)
+            .cloned()
+    }
+
+    fn take(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.cache.lock().unwrap().remove(key)
+    }
+
+    fn can_cache(&self) -> bool {
+        true
+    }
+}
+
+#[test]
+fn stateful_resumption_across_servers_with_shared_store() {
+    let kt = KeyType::Rsa;
+
+    for version in rustls::ALL_VERSIONS {
+        let client_config = Arc::new(make_client_config_with_versions(kt, &[version]));
+
+        // two servers, each with their own store, sharing a backing cache
+        let cache = SharedSessionCache::default();
+        let make_server = || {
+            let mut server_config = make_server_config(kt);
+            server_config.session_storage = Arc::new(ClusterSessionStore {
+                cache: cache.clone(),
+            });
+            Arc::new(server_config)
+        };
+        let server_a = make_server();
+        let server_b = make_server();
+
+        // full handshake with the first server
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_a);
+        server.set_resumption_data(b"from server a");
+        let (_, full_s2c) = do_handshake(&mut client, &mut server);
+        assert_eq!(server.received_resumption_data(), None);
+        assert!(!cache.lock().unwrap().is_empty());
+
+        // resumed with the second
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_b);
+        let (_, resume_s2c) = do_handshake(&mut client, &mut server);
+        assert!(resume_s2c < full_s2c);
+        if version.version == ProtocolVersion::TLSv1_3 {
+            assert_eq!(
+                server.received_resumption_data(),
+                Some(&b"from server a"[..])
+            );
+        }
+    }
+}
+
+#[test]
+fn server_reports_resumed_psk_identity() {
+    let kt = KeyType::Rsa;
+    let client_config = Arc::new(make_client_config_with_versions(
+        kt,
+        &[&rustls::version::TLS13],
+    ));
+
+    let cache = SharedSessionCache::default();
+    let mut server_config = make_server_config(kt);
+    server_config.session_storage = Arc::new(ClusterSessionStore {
+        cache: cache.clone(),
+    });
+    let server_config = Arc::new(server_config);
+
+    // full handshake
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(server.resumed_psk_identity(), None);
+    let issued = cache
+        .lock()
+        .unwrap()
+        .keys()
+        .cloned()
+        .collect::<Vec<_>>();
+    assert!(!issued.is_empty());
+
+    // resumed: the identity is one of the session ids handed out above,
+    // and has now been consumed from the store
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+    let identity = server
+        .resumed_psk_identity()
+        .expect("resumed")
+        .to_vec();
+    assert!(issued.contains(&identity));
+    assert!(!cache
+        .lock()
+        .unwrap()
+        .contains_key(&identity));
+}
+
+#[test]
+fn tls13_resumption_survives_one_ticket_key_rotation() {
+    let kt = KeyType::Rsa;
+    let ticketer = rustls::crypto::ring::Ticketer::new_switcher().unwrap();
+    let mut server_config = make_server_config(kt);
+    server_config.ticketer = ticketer.clone();
+    let server_config = Arc::new(server_config);
+
+    // Two clients, each holding tickets issued under the same key.
+    let clients = [(); 2].map(|_| {
+        let client_config = Arc::new(make_client_config_with_versions(
+            kt,
+            &[&rustls::version::TLS13],
+        ));
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        server.set_resumption_data(b"before rotation");
+        do_handshake(&mut client, &mut server);
+        client_config
+    });
+
+    // That key is retired, but still decrypts.
+    ticketer.rotate().unwrap();
+    let (mut client, mut server) = make_pair_for_arc_configs(&clients[0], &server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(
+        server.received_resumption_data(),
+        Some(&b"before rotation"[..])
+    );
+
+    // Now it is evicted, so the handshake is a full one.
+    ticketer.rotate().unwrap();
+    let (mut client, mut server) = make_pair_for_arc_configs(&clients[1], &server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(server.received_resumption_data(), None);
+}
+
+#[test]
+fn tls13_stateless_resumption() {
+    let kt = KeyType::Rsa;
+    let client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS13]);
+    let client_config = Arc::new(client_config);
+
+    let mut server_config = make_server_config(kt);
+    server_config.ticketer = rustls::crypto::ring::Ticketer::new().unwrap();
+    let storage = Arc::new(ServerStorage::new());
+    server_config.session_storage = storage.clone();
+    let server_config = Arc::new(server_config);
+
+    // full handshake
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    let (full_c2s, full_s2c) = do_handshake(&mut client, &mut server);
+    assert_eq!(storage.puts(), 0);
+    assert_eq!(storage.gets(), 0);
+    assert_eq!(storage.takes(), 0);
+    assert_eq!(
+        client
+            .peer_certificates()
+            .map(|certs| certs.len()),
+        Some(3)
+    );
+
+    // resumed
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    let (resume_c2s, resume_s2c) = do_handshake(&mut client, &mut server);
+    assert!(resume_c2s > full_c2s);
+    assert!(resume_s2c < full_s2c);
+    assert_eq!(storage.puts(), 0);
+    assert_eq!(storage.gets(), 0);
+    assert_eq!(storage.takes(), 0);
+    assert_eq!(
+        client
+            .peer_certificates()
+            .map(|certs| certs.len()),
+        Some(3)
+    );
+
+    // resumed again
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    let (resume2_c2s, resume2_s2c) = do_handshake(&mut client, &mut server);
+    assert_eq!(resume_s2c, resume2_s2c);
+    assert_eq!(resume_c2s, resume2_c2s);
+    assert_eq!(storage.puts(), 0);
+    assert_eq!(storage.gets(), 0);
+    assert_eq!(storage.takes(), 0);
+    assert_eq!(
+        client
+            .peer_certificates()
+            .map(|certs| certs.len()),
+        Some(3)
+    );
+}
+
+#[test]
+fn early_data_not_available() {
+    let (mut client, _) = make_pair(KeyType::Rsa);
+    assert!(client.early_data().is_none());
+}
+
+fn early_data_configs() -> (Arc<ClientConfig>, Arc<ServerConfig>) {
+    let kt = KeyType::Rsa;
+    let mut client_config = make_client_config(kt);
+    client_config.enable_early_data = true;
+    client_config.resumption = Resumption::store(Arc::new(ClientStorage::new()));
+
+    let mut server_config = make_server_config(kt);
+    server_config.max_early_data_size = 1234;
+    (Arc::new(client_config), Arc::new(server_config))
+}
+
+#[test]
+fn early_data_is_available_on_resumption() {
+    let (client_config, server_config) = early_data_configs();
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    assert!(client.early_data().is_some());
+    assert_eq!(
+        client
+            .early_data()
+            .unwrap()
+            .bytes_left(),
+        1234
+    );
+    client
+        .early_data()
+        .unwrap()
+        .flush()
+        .unwrap();
+    assert_eq!(
+        client
+            .early_data()
+            .unwrap()
+            .write(b"hello")
+            .unwrap(),
+        5
+    );
+    do_handshake(&mut client, &mut server);
+
+    let mut received_early_data = [0u8; 5];
+    assert_eq!(
+        server
+            .early_data()
+            .expect("early_data didn't happen")
+            .read(&mut received_early_data)
+            .expect("early_data failed unexpectedly"),
+        5
+    );
+    assert_eq!(&received_early_data[..], b"hello");
+}
+
+/// A session store whose sessions can be resumed any number of times.
+struct ReusableSessionStorage(Arc<rustls::server::ServerSessionMemoryCache>);
+
+impl rustls::server::StoresServerSessions for ReusableSessionStorage {
+    fn put(&self, key: Vec<u8>, value: Vec<u8>) -> bool {
+        self.0.put(key, value)
+    }
+
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.0.get(key)
+    }
+
+    fn take(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.0.get(key)
+    }
+
+    fn can_cache(&self) -> bool {
+        true
+    }
+}
+
+#[test]
+fn replayed_early_data_is_rejected_by_anti_replay() {
+    for anti_replay in [false, true] {
+        let (client_config, server_config) = early_data_configs();
+        let mut server_config = ServerConfig::clone(&server_config);
+        server_config.session_storage = Arc::new(ReusableSessionStorage(
+            rustls::server::ServerSessionMemoryCache::new(32),
+        ));
+        if anti_replay {
+            server_config.anti_replay = Some(rustls::server::AntiReplayCache::new(32));
+        }
+        let server_config = Arc::new(server_config);
+
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        do_handshake(&mut client, &mut server);
+
+        // Capture the client's first flight, with its early data.
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        client
+            .early_data()
+            .unwrap()
+            .write_all(b"hello")
+            .unwrap();
+        let mut flight = Vec::new();
+        client.write_tls(&mut flight).unwrap();
+
+        server
+            .read_tls(&mut &flight[..])
+            .unwrap();
+        server.process_new_packets().unwrap();
+        assert!(server.early_data().is_some());
+
+        // Replay it to another server.
+        let mut replayed = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+        replayed
+            .read_tls(&mut &flight[..])
+            .unwrap();
+        replayed.process_new_packets().unwrap();
+        assert_eq!(replayed.early_data().is_none(), anti_replay);
+
+        if anti_replay {
+            // The handshake carries on without the early data.
+            do_handshake(&mut client, &mut replayed);
+            assert!(!client.is_early_data_accepted());
+        }
+    }
+}
+
+#[test]
+fn early_data_can_be_enabled_by_config_builder() {
+    let kt = KeyType::Rsa;
+    let builder = || {
+        ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(get_client_root_store(kt))
+    };
+    assert!(
+        !builder()
+            .with_no_client_auth()
+            .enable_early_data
+    );
+
+    let client_config = builder()
+        .enable_early_data()
+        .with_no_client_auth();
+    assert!(client_config.enable_early_data);
+    assert!(
+        client_config
+            .to_builder()
+            .with_no_client_auth()
+            .enable_early_data
+    );
+
+    let mut server_config = make_server_config(kt);
+    server_config.max_early_data_size = 1234;
+    let server_config = Arc::new(server_config);
+
+    // The default resumption settings keep the tickets early data needs.
+    let client_config = Arc::new(client_config);
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+    let (mut client, _) = make_pair_for_arc_configs(&client_config, &server_config);
+    assert!(client.early_data().is_some());
+
+    let mut client_config = ClientConfig::clone(&client_config);
+    client_config.resumption = Resumption::disabled();
+    let client_config = Arc::new(client_config);
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+    let (mut client, _) = make_pair_for_arc_configs(&client_config, &server_config);
+    assert!(client.early_data().is_none());
+}
+
+#[test]
+fn early_data_not_available_on_server_before_client_hello() {
+    let mut server = ServerConnection::new(Arc::new(make_server_config(KeyType::Rsa))).unwrap();
+    assert!(server.early_data().is_none());
+}
+
+#[test]
+fn early_data_can_be_rejected_by_server() {
+    let (client_config, server_config) = early_data_configs();
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    assert!(client.early_data().is_some());
+    assert_eq!(
+        client
+            .early_data()
+            .unwrap()
+            .bytes_left(),
+        1234
+    );
+    client
+        .early_data()
+        .unwrap()
+        .flush()
+        .unwrap();
+    assert_eq!(
+        client
+            .early_data()
+            .unwrap()
+            .write(b"hello")
+            .unwrap(),
+        5
+    );
+    server.reject_early_data();
+    do_handshake(&mut client, &mut server);
+
+    assert!(!client.is_early_data_accepted());
+}
+
+#[cfg(feature = "quic")]
+mod test_quic {
+    use super::*;
+    use rustls::quic::{self, ConnectionCommon};
+
+    // Returns the sender's next secrets to use, or the receiver's error.
+    fn step<L: SideData, R: SideData>(
+        send: &mut ConnectionCommon<L>,
+        recv: &mut ConnectionCommon<R>,
+    ) -> Result<Option<quic::KeyChange>, Error> {
+        let mut buf = Vec::new();
+        let change = loop {
+            let prev = buf.len();
+            if let Some(x) = send.write_hs(&mut buf) {
+                break Some(x);
+            }
+            if prev == buf.len() {
+                break None;
+            }
+        };
+        if let Err(e) = recv.read_hs(&buf) {
+            return Err(e);
+        } else {
+            assert_eq!(recv.alert(), None);
+        }
+
+        Ok(change)
+    }
+
+    #[test]
+    fn test_quic_handshake() {
+        fn equal_packet_keys(x: &dyn quic::PacketKey, y: &dyn quic::PacketKey) -> bool {
+            // Check that these two sets of keys are equal.
+            let mut buf = [0; 32];
+            let (header, payload_tag) = buf.split_at_mut(8);
+            let (payload, tag_buf) = payload_tag.split_at_mut(8);
+            let tag = x
+                .encrypt_in_place(42, header, payload)
+                .unwrap();
+            tag_buf.copy_from_slice(tag.as_ref());
+
+            let result = y.decrypt_in_place(42, header, payload_tag);
+            match result {
+                Ok(payload) => payload == [0; 8],
+                Err(_) => false,
+            }
+        }
+
+        fn compatible_keys(x: &quic::KeyChange, y: &quic::KeyChange) -> bool {
+            fn keys(kc: &quic::KeyChange) -> &quic::Keys {
+                match kc {
+                    quic::KeyChange::Handshake { keys } => keys,
+                    quic::KeyChange::OneRtt { keys, .. } => keys,
+                }
+            }
+
+            let (x, y) = (keys(x), keys(y));
+            equal_packet_keys(x.local.packet.as_ref(), y.remote.packet.as_ref())
+                && equal_packet_keys(x.remote.packet.as_ref(), y.local.packet.as_ref())
+        }
+
+        let kt = KeyType::Rsa;
+        let mut client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS13]);
+        client_config.enable_early_data = true;
+        let client_config = Arc::new(client_config);
+        let mut server_config = make_server_config_with_versions(kt, &[&rustls::version::TLS13]);
+        server_config.max_early_data_size = 0xffffffff;
+        let server_config = Arc::new(server_config);
+        let client_params = &b"client params"[..];
+        let server_params = &b"server params"[..];
+
+        // full handshake
+        let mut client = quic::ClientConnection::new(
+            Arc::clone(&client_config),
+            quic::Version::V1,
+            server_name("localhost"),
+            client_params.into(),
+        )
+        .unwrap();
+
+        let mut server = quic::ServerConnection::new(
+            Arc::clone(&server_config),
+            quic::Version::V1,
+            server_params.into(),
+        )
+        .unwrap();
+
+        let client_initial = step(&mut client, &mut server).unwrap();
+        assert!(client_initial.is_none());
+        assert!(client.zero_rtt_keys().is_none());
+        assert_eq!(server.quic_transport_parameters(), Some(client_params));
+        let server_hs = step(&mut server, &mut client)
+            .unwrap()
+            .unwrap();
+        assert!(server.zero_rtt_keys().is_none());
+        let client_hs = step(&mut client, &mut server)
+            .unwrap()
+            .unwrap();
+        assert!(compatible_keys(&server_hs, &client_hs));
+        assert!(client.is_handshaking());
+        let server_1rtt = step(&mut server, &mut client)
+            .unwrap()
+            .unwrap();
+        assert!(!client.is_handshaking());
+        assert_eq!(client.quic_transport_parameters(), Some(server_params));
+        assert!(server.is_handshaking());
+        let client_1rtt = step(&mut client, &mut server)
+            .unwrap()
+            .unwrap();
+        assert!(!server.is_handshaking());
+        assert!(compatible_keys(&server_1rtt, &client_1rtt));
+        assert!(!compatible_keys(&server_hs, &server_1rtt));
+        assert!(step(&mut client, &mut server)
+            .unwrap()
+            .is_none());
+        assert!(step(&mut server, &mut client)
+            .unwrap()
+            .is_none());
+
+        // 0-RTT handshake
+        let mut client = quic::ClientConnection::new(
+            Arc::clone(&client_config),
+            quic::Version::V1,
+            server_name("localhost"),
+            client_params.into(),
+        )
+        .unwrap();
+        assert!(client
+            .negotiated_cipher_suite()
+            .is_some());
+
+        let mut server = quic::ServerConnection::new(
+            Arc::clone(&server_config),
+            quic::Version::V1,
+            server_params.into(),
+        )
+        .unwrap();
+
+        step(&mut client, &mut server).unwrap();
+        assert_eq!(client.quic_transport_parameters(), Some(server_params));
+        {
+            let client_early = client.zero_rtt_keys().unwrap();
+            let server_early = server.zero_rtt_keys().unwrap();
+            assert!(equal_packet_keys(
+                client_early.packet.as_ref(),
+                server_early.packet.as_ref()
+            ));
+        }
+        step(&mut server, &mut client)
+            .unwrap()
+            .unwrap();
+        step(&mut client, &mut server)
+            .unwrap()
+            .unwrap();
+        step(&mut server, &mut client)
+            .unwrap()
+            .unwrap();
+        assert!(client.is_early_data_accepted());
+
+        // 0-RTT rejection
+        {
+            let client_config = (*client_config).clone();
+            let mut client = quic::ClientConnection::new(
+                Arc::new(client_config),
+                quic::Version::V1,
+                server_name("localhost"),
+                client_params.into(),
+            )
+            .unwrap();
+
+            let mut server = quic::ServerConnection::new(
+                Arc::clone(&server_config),
+                quic::Version::V1,
+                server_params.into(),
+            )
+            .unwrap();
+            server.reject_early_data();
+
+            step(&mut client, &mut server).unwrap();
+            assert_eq!(client.quic_transport_parameters(), Some(server_params));
+            assert!(client.zero_rtt_keys().is_some());
+            assert!(server.zero_rtt_keys().is_none());
+            step(&mut server, &mut client)
+                .unwrap()
+                .unwrap();
+            step(&mut client, &mut server)
+                .unwrap()
+                .unwrap();
+            step(&mut server, &mut client)
+                .unwrap()
+                .unwrap();
+            assert!(!client.is_early_data_accepted());
+        }
+
+        // failed handshake
+        let mut client = quic::ClientConnection::new(
+            client_config,
+            quic::Version::V1,
+            server_name("example.com"),
+            client_params.into(),
+        )
+        .unwrap();
+
+        let mut server =
+            quic::ServerConnection::new(server_config, quic::Version::V1, server_params.into())
+                .unwrap();
+
+        step(&mut client, &mut server).unwrap();
+        step(&mut server, &mut client)
+            .unwrap()
+            .unwrap();
+        assert!(step(&mut server, &mut client).is_err());
+        assert_eq!(
+            client.alert(),
+            Some(rustls::AlertDescription::BadCertificate)
+        );
+
+        // Key updates
+
+        let (mut client_secrets, mut server_secrets) = match (client_1rtt, server_1rtt) {
+            (quic::KeyChange::OneRtt { next: c, .. }, quic::KeyChange::OneRtt { next: s, .. }) => {
+                (c, s)
+            }
+            _ => unreachable!(),
+        };
+
+        let mut client_next = client_secrets.next_packet_keys();
+        let mut server_next = server_secrets.next_packet_keys();
+        assert!(equal_packet_keys(
+            client_next.local.as_ref(),
+            server_next.remote.as_ref()
+        ));
+        assert!(equal_packet_keys(
+            server_next.local.as_ref(),
+            client_next.remote.as_ref()
+        ));
+
+        client_next = client_secrets.next_packet_keys();
+        server_next = server_secrets.next_packet_keys();
+        assert!(equal_packet_keys(
+            client_next.local.as_ref(),
+            server_next.remote.as_ref()
+        ));
+        assert!(equal_packet_keys(
+            server_next.local.as_ref(),
+            client_next.remote.as_ref()
+        ));
+    }
+
+    #[test]
+    fn test_quic_rejects_missing_alpn() {
+        let client_params = &b"client params"[..];
+        let server_params = &b"server params"[..];
+
+        for &kt in ALL_KEY_TYPES.iter() {
+            let client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS13]);
+            let client_config = Arc::new(client_config);
+
+            let mut server_config =
+                make_server_config_with_versions(kt, &[&rustls::version::TLS13]);
+            server_config.alpn_protocols = vec!["foo".into()];
+            let server_config = Arc::new(server_config);
+
+            let mut client = quic::ClientConnection::new(
+                client_config,
+                quic::Version::V1,
+                server_name("localhost"),
+                client_params.into(),
+            )
+            .unwrap();
+            let mut server =
+                quic::ServerConnection::new(server_config, quic::Version::V1, server_params.into())
+                    .unwrap();
+
+            assert_eq!(
+                step(&mut client, &mut server)
+                    .err()
+                    .unwrap(),
+                Error::NoApplicationProtocol
+            );
+
+            assert_eq!(
+                server.alert(),
+                Some(rustls::AlertDescription::NoApplicationProtocol)
+            );
+        }
+    }
+
+    #[cfg(feature = "tls12")]
+    #[test]
+    fn test_quic_no_tls13_error() {
+        let mut client_config =
+            make_client_config_with_versions(KeyType::Ed25519, &[&rustls::version::TLS12]);
+        client_config.alpn_protocols = vec!["foo".into()];
+        let client_config = Arc::new(client_config);
+
+        assert!(quic::ClientConnection::new(
+            client_config,
+            quic::Version::V1,
+            server_name("localhost"),
+            b"client params".to_vec(),
+        )
+        .is_err());
+
+        let mut server_config =
+            make_server_config_with_versions(KeyType::Ed25519, &[&rustls::version::TLS12]);
+        server_config.alpn_protocols = vec!["foo".into()];
+        let server_config = Arc::new(server_config);
+
+        assert!(quic::ServerConnection::new(
+            server_config,
+            quic::Version::V1,
+            b"server params".to_vec(),
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_quic_invalid_early_data_size() {
+        let mut server_config =
+            make_server_config_with_versions(KeyType::Ed25519, &[&rustls::version::TLS13]);
+        server_config.alpn_protocols = vec!["foo".into()];
+
+        let cases = [
+            (None, true),
+            (Some(0u32), true),
+            (Some(5), false),
+            (Some(0xffff_ffff), true),
+        ];
+
+        for &(size, ok) in cases.iter() {
+            println!("early data size case: {:?}", size);
+            if let Some(new) = size {
+                server_config.max_early_data_size = new;
+            }
+
+            let wrapped = Arc::new(server_config.clone());
+            assert_eq!(
+                quic::ServerConnection::new(wrapped, quic::Version::V1, b"server params".to_vec(),)
+                    .is_ok(),
+                ok
+            );
+        }
+    }
+
+    #[test]
+    fn test_quic_server_no_params_received() {
+        let server_config =
+            make_server_config_with_versions(KeyType::Ed25519, &[&rustls::version::TLS13]);
+        let server_config = Arc::new(server_config);
+
+        let mut server = quic::ServerConnection::new(
+            server_config,
+            quic::Version::V1,
+            b"server params".to_vec(),
+        )
+        .unwrap();
+
+        use rustls::crypto::ring::RING;
+        use rustls::internal::msgs::base::PayloadU16;
+        use rustls::internal::msgs::enums::{Compression, NamedGroup};
+        use rustls::internal::msgs::handshake::{
+            ClientHelloPayload, HandshakeMessagePayload, KeyShareEntry, Random, SessionId,
+        };
+        use rustls::{CipherSuite, HandshakeType, SignatureScheme};
+
+        let mut random = [0; 32];
+        RING.fill_random(&mut random).unwrap();
+        let random = Random::from(random);
+
+        let rng = ring::rand::SystemRandom::new();
+        let kx = ring::agreement::EphemeralPrivateKey::generate(&ring::agreement::X25519, &rng)
+            .unwrap()
+            .compute_public_key()
+            .unwrap();
+
+        let client_hello = MessagePayload::handshake(HandshakeMessagePayload {
+            typ: HandshakeType::ClientHello,
+            payload: HandshakePayload::ClientHello(ClientHelloPayload {
+                client_version: ProtocolVersion::TLSv1_3,
+                random,
+                session_id: SessionId::random(RING).unwrap(),
+                cipher_suites: vec![CipherSuite::TLS13_AES_128_GCM_SHA256],
+                compression_methods: vec![Compression::Null],
+                extensions: vec![
+                    ClientExtension::SupportedVersions(vec![ProtocolVersion::TLSv1_3]),
+                    ClientExtension::NamedGroups(vec![NamedGroup::X25519]),
+                    ClientExtension::SignatureAlgorithms(vec![SignatureScheme::ED25519]),
+                    ClientExtension::KeyShare(vec![KeyShareEntry {
+                        group: NamedGroup::X25519,
+                        payload: PayloadU16::new(kx.as_ref().to_vec()),
+                    }]),
+                ],
+            }),
+        });
+
+        let mut buf = Vec::with_capacity(512);
+        client_hello.encode(&mut buf);
+        assert_eq!(
+            server.read_hs(buf.as_slice()).err(),
+            Some(Error::PeerMisbehaved(
+                PeerMisbehaved::MissingQuicTransportParameters
+            ))
+        );
+    }
+
+    #[test]
+    fn test_quic_server_no_tls12() {
+        let mut server_config =
+            make_server_config_with_versions(KeyType::Ed25519, &[&rustls::version::TLS13]);
+        server_config.alpn_protocols = vec!["foo".into()];
+        let server_config = Arc::new(server_config);
+
+        use rustls::crypto::ring::RING;
+        use rustls::internal::msgs::base::PayloadU16;
+        use rustls::internal::msgs::enums::{Compression, NamedGroup};
+        use rustls::internal::msgs::handshake::{
+            ClientHelloPayload, HandshakeMessagePayload, KeyShareEntry, Random, SessionId,
+        };
+        use rustls::{CipherSuite, HandshakeType, SignatureScheme};
+
+        let mut random = [0; 32];
+        RING.fill_random(&mut random).unwrap();
+        let random = Random::from(random);
+
+        let rng = ring::rand::SystemRandom::new();
+        let kx = ring::agreement::EphemeralPrivateKey::generate(&ring::agreement::X25519, &rng)
+            .unwrap()
+            .compute_public_key()
+            .unwrap();
+
+        let mut server = quic::ServerConnection::new(
+            server_config,
+            quic::Version::V1,
+            b"server params".to_vec(),
+        )
+        .unwrap();
+
+        let client_hello = MessagePayload::handshake(HandshakeMessagePayload {
+            typ: HandshakeType::ClientHello,
+            payload: HandshakePayload::ClientHello(ClientHelloPayload {
+                client_version: ProtocolVersion::TLSv1_2,
+                random,
+                session_id: SessionId::random(RING).unwrap(),
+                cipher_suites: vec![CipherSuite::TLS13_AES_128_GCM_SHA256],
+                compression_methods: vec![Compression::Null],
+                extensions: vec![
+                    ClientExtension::NamedGroups(vec![NamedGroup::X25519]),
+                    ClientExtension::SignatureAlgorithms(vec![SignatureScheme::ED25519]),
+                    ClientExtension::KeyShare(vec![KeyShareEntry {
+                        group: NamedGroup::X25519,
+                        payload: PayloadU16::new(kx.as_ref().to_vec()),
+                    }]),
+                ],
+            }),
+        });
+
+        let mut buf = Vec::with_capacity(512);
+        client_hello.encode(&mut buf);
+        assert_eq!(
+            server.read_hs(buf.as_slice()).err(),
+            Some(Error::PeerIncompatible(
+                PeerIncompatible::SupportedVersionsExtensionRequired
+            )),
+        );
+    }
+
+    #[test]
+    fn packet_key_api() {
+        use rustls::cipher_suite::TLS13_AES_128_GCM_SHA256;
+        use rustls::quic::{Keys, Version};
+        use rustls::Side;
+
+        // Test vectors: https://www.rfc-editor.org/rfc/rfc9001.html#name-client-initial
+        const CONNECTION_ID: &[u8] = &[0x83, 0x94, 0xc8, 0xf0, 0x3e, 0x51, 0x57, 0x08];
+        const PACKET_NUMBER: u64 = 2;
+        const PLAIN_HEADER: &[u8] = &[
+            0xc3, 0x00, 0x00, 0x00, 0x01, 0x08, 0x83, 0x94, 0xc8, 0xf0, 0x3e, 0x51, 0x57, 0x08,
+            0x00, 0x00, 0x44, 0x9e, 0x00, 0x00, 0x00, 0x02,
+        ];
+
+        const PAYLOAD: &[u8] = &[
+            0x06, 0x00, 0x40, 0xf1, 0x01, 0x00, 0x00, 0xed, 0x03, 0x03, 0xeb, 0xf8, 0xfa, 0x56,
+            0xf1, 0x29, 0x39, 0xb9, 0x58, 0x4a, 0x38, 0x96, 0x47, 0x2e, 0xc4, 0x0b, 0xb8, 0x63,
+            0xcf, 0xd3, 0xe8, 0x68, 0x04, 0xfe, 0x3a, 0x47, 0xf0, 0x6a, 0x2b, 0x69, 0x48, 0x4c,
+            0x00, 0x00, 0x04, 0x13, 0x01, 0x13, 0x02, 0x01, 0x00, 0x00, 0xc0, 0x00, 0x00, 0x00,
+            0x10, 0x00, 0x0e, 0x00, 0x00, 0x0b, 0x65, 0x78, 0x61, 0x6d, 0x70, 0x6c, 0x65, 0x2e,
+            0x63, 0x6f, 0x6d, 0xff, 0x01, 0x00, 0x01, 0x00, 0x00, 0x0a, 0x00, 0x08, 0x00, 0x06,
+            0x00, 0x1d, 0x00, 0x17, 0x00, 0x18, 0x00, 0x10, 0x00, 0x07, 0x00, 0x05, 0x04, 0x61,
+            0x6c, 0x70, 0x6e, 0x00, 0x05, 0x00, 0x05, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x33,
+            0x00, 0x26, 0x00, 0x24, 0x00, 0x1d, 0x00, 0x20, 0x93, 0x70, 0xb2, 0xc9, 0xca, 0xa4,
+            0x7f, 0xba, 0xba, 0xf4, 0x55, 0x9f, 0xed, 0xba, 0x75, 0x3d, 0xe1, 0x71, 0xfa, 0x71,
+            0xf5, 0x0f, 0x1c, 0xe1, 0x5d, 0x43, 0xe9, 0x94, 0xec, 0x74, 0xd7, 0x48, 0x00, 0x2b,
+            0x00, 0x03, 0x02, 0x03, 0x04, 0x00, 0x0d, 0x00, 0x10, 0x00, 0x0e, 0x04, 0x03, 0x05,
+            0x03, 0x06, 0x03, 0x02, 0x03, 0x08, 0x04, 0x08, 0x05, 0x08, 0x06, 0x00, 0x2d, 0x00,
+            0x02, 0x01, 0x01, 0x00, 0x1c, 0x00, 0x02, 0x40, 0x01, 0x00, 0x39, 0x00, 0x32, 0x04,
+            0x08, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x05, 0x04, 0x80, 0x00, 0xff,
+            0xff, 0x07, 0x04, 0x80, 0x00, 0xff, 0xff, 0x08, 0x01, 0x10, 0x01, 0x04, 0x80, 0x00,
+            0x75, 0x30, 0x09, 0x01, 0x10, 0x0f, 0x08, 0x83, 0x94, 0xc8, 0xf0, 0x3e, 0x51, 0x57,
+            0x08, 0x06, 0x04, 0x80, 0x00, 0xff, 0xff,
+        ];
+
+        let client_keys = Keys::initial(
+            Version::V1,
+            TLS13_AES_128_GCM_SHA256
+                .tls13()
+                .unwrap(),
+            CONNECTION_ID,
+            Side::Client,
+        );
+        assert_eq!(
+            client_keys
+                .local
+                .packet
+                .confidentiality_limit(),
+            2u64.pow(23)
+        );
+        assert_eq!(
+            client_keys
+                .local
+                .packet
+                .integrity_limit(),
+            2u64.pow(52)
+        );
+        assert_eq!(client_keys.local.packet.tag_len(), 16);
+
+        let mut buf = Vec::new();
+        buf.extend(PLAIN_HEADER);
+        buf.extend(PAYLOAD);
+        let header_len = PLAIN_HEADER.len();
+        let tag_len = client_keys.local.packet.tag_len();
+        let padding_len = 1200 - header_len - PAYLOAD.len() - tag_len;
+        buf.extend(std::iter::repeat(0).take(padding_len));
+        let (header, payload) = buf.split_at_mut(header_len);
+        let tag = client_keys
+            .local
+            .packet
+            .encrypt_in_place(PACKET_NUMBER, header, payload)
+            .unwrap();
+
+        let sample_len = client_keys.local.header.sample_len();
+        let sample = &payload[..sample_len];
+        let (first, rest) = header.split_at_mut(1);
+        client_keys
+            .local
+            .header
+            .encrypt_in_place(sample, &mut first[0], &mut rest[17..21])
+            .unwrap();
+        buf.extend_from_slice(tag.as_ref());
+
+        const PROTECTED: &[u8] = &[
+            0xc0, 0x00, 0x00, 0x00, 0x01, 0x08, 0x83, 0x94, 0xc8, 0xf0, 0x3e, 0x51, 0x57, 0x08,
+            0x00, 0x00, 0x44, 0x9e, 0x7b, 0x9a, 0xec, 0x34, 0xd1, 0xb1, 0xc9, 0x8d, 0xd7, 0x68,
+            0x9f, 0xb8, 0xec, 0x11, 0xd2, 0x42, 0xb1, 0x23, 0xdc, 0x9b, 0xd8, 0xba, 0xb9, 0x36,
+            0xb4, 0x7d, 0x92, 0xec, 0x35, 0x6c, 0x0b, 0xab, 0x7d, 0xf5, 0x97, 0x6d, 0x27, 0xcd,
+            0x44, 0x9f, 0x63, 0x30, 0x00, 0x99, 0xf3, 0x99, 0x1c, 0x26, 0x0e, 0xc4, 0xc6, 0x0d,
+            0x17, 0xb3, 0x1f, 0x84, 0x29, 0x15, 0x7b, 0xb3, 0x5a, 0x12, 0x82, 0xa6, 0x43, 0xa8,
+            0xd2, 0x26, 0x2c, 0xad, 0x67, 0x50, 0x0c, 0xad, 0xb8, 0xe7, 0x37, 0x8c, 0x8e, 0xb7,
+            0x53, 0x9e, 0xc4, 0xd4, 0x90, 0x5f, 0xed, 0x1b, 0xee, 0x1f, 0xc8, 0xaa, 0xfb, 0xa1,
+            0x7c, 0x75, 0x0e, 0x2c, 0x7a, 0xce, 0x01, 0xe6, 0x00, 0x5f, 0x80, 0xfc, 0xb7, 0xdf,
+            0x62, 0x12, 0x30, 0xc8, 0x37, 0x11, 0xb3, 0x93, 0x43, 0xfa, 0x02, 0x8c, 0xea, 0x7f,
+            0x7f, 0xb5, 0xff, 0x89, 0xea, 0xc2, 0x30, 0x82, 0x49, 0xa0, 0x22, 0x52, 0x15, 0x5e,
+            0x23, 0x47, 0xb6, 0x3d, 0x58, 0xc5, 0x45, 0x7a, 0xfd, 0x84, 0xd0, 0x5d, 0xff, 0xfd,
+            0xb2, 0x03, 0x92, 0x84, 0x4a, 0xe8, 0x12, 0x15, 0x46, 0x82, 0xe9, 0xcf, 0x01, 0x2f,
+            0x90, 0x21, 0xa6, 0xf0, 0xbe, 0x17, 0xdd, 0xd0, 0xc2, 0x08, 0x4d, 0xce, 0x25, 0xff,
+            0x9b, 0x06, 0xcd, 0xe5, 0x35, 0xd0, 0xf9, 0x20, 0xa2, 0xdb, 0x1b, 0xf3, 0x62, 0xc2,
+            0x3e, 0x59, 0x6d, 0x11, 0xa4, 0xf5, 0xa6, 0xcf, 0x39, 0x48, 0x83, 0x8a, 0x3a, 0xec,
+            0x4e, 0x15, 0xda, 0xf8, 0x50, 0x0a, 0x6e, 0xf6, 0x9e, 0xc4, 0xe3, 0xfe, 0xb6, 0xb1,
+            0xd9, 0x8e, 0x61, 0x0a, 0xc8, 0xb7, 0xec, 0x3f, 0xaf, 0x6a, 0xd7, 0x60, 0xb7, 0xba,
+            0xd1, 0xdb, 0x4b, 0xa3, 0x48, 0x5e, 0x8a, 0x94, 0xdc, 0x25, 0x0a, 0xe3, 0xfd, 0xb4,
+            0x1e, 0xd1, 0x5f, 0xb6, 0xa8, 0xe5, 0xeb, 0xa0, 0xfc, 0x3d, 0xd6, 0x0b, 0xc8, 0xe3,
+            0x0c, 0x5c, 0x42, 0x87, 0xe5, 0x38, 0x05, 0xdb, 0x05, 0x9a, 0xe0, 0x64, 0x8d, 0xb2,
+            0xf6, 0x42, 0x64, 0xed, 0x5e, 0x39, 0xbe, 0x2e, 0x20, 0xd8, 0x2d, 0xf5, 0x66, 0xda,
+            0x8d, 0xd5, 0x99, 0x8c, 0xca, 0xbd, 0xae, 0x05, 0x30, 0x60, 0xae, 0x6c, 0x7b, 0x43,
+            0x78, 0xe8, 0x46, 0xd2, 0x9f, 0x37, 0xed, 0x7b, 0x4e, 0xa9, 0xec, 0x5d, 0x82, 0xe7,
+            0x96, 0x1b, 0x7f, 0x25, 0xa9, 0x32, 0x38, 0x51, 0xf6, 0x81, 0xd5, 0x82, 0x36, 0x3a,
+            0xa5, 0xf8, 0x99, 0x37, 0xf5, 0xa6, 0x72, 0x58, 0xbf, 0x63, 0xad, 0x6f, 0x1a, 0x0b,
+            0x1d, 0x96, 0xdb, 0xd4, 0xfa, 0xdd, 0xfc, 0xef, 0xc5, 0x26, 0x6b, 0xa6, 0x61, 0x17,
+            0x22, 0x39, 0x5c, 0x90, 0x65, 0x56, 0xbe, 0x52, 0xaf, 0xe3, 0xf5, 0x65, 0x63, 0x6a,
+            0xd1, 0xb1, 0x7d, 0x50, 0x8b, 0x73, 0xd8, 0x74, 0x3e, 0xeb, 0x52, 0x4b, 0xe2, 0x2b,
+            0x3d, 0xcb, 0xc2, 0xc7, 0x46, 0x8d, 0x54, 0x11, 0x9c, 0x74, 0x68, 0x44, 0x9a, 0x13,
+            0xd8, 0xe3, 0xb9, 0x58, 0x11, 0xa1, 0x98, 0xf3, 0x49, 0x1d, 0xe3, 0xe7, 0xfe, 0x94,
+            0x2b, 0x33, 0x04, 0x07, 0xab, 0xf8, 0x2a, 0x4e, 0xd7, 0xc1, 0xb3, 0x11, 0x66, 0x3a,
+            0xc6, 0x98, 0x90, 0xf4, 0x15, 0x70, 0x15, 0x85, 0x3d, 0x91, 0xe9, 0x23, 0x03, 0x7c,
+            0x22, 0x7a, 0x33, 0xcd, 0xd5, 0xec, 0x28, 0x1c, 0xa3, 0xf7, 0x9c, 0x44, 0x54, 0x6b,
+            0x9d, 0x90, 0xca, 0x00, 0xf0, 0x64, 0xc9, 0x9e, 0x3d, 0xd9, 0x79, 0x11, 0xd3, 0x9f,
+            0xe9, 0xc5, 0xd0, 0xb2, 0x3a, 0x22, 0x9a, 0x23, 0x4c, 0xb3, 0x61, 0x86, 0xc4, 0x81,
+            0x9e, 0x8b, 0x9c, 0x59, 0x27, 0x72, 0x66, 0x32, 0x29, 0x1d, 0x6a, 0x41, 0x82, 0x11,
+            0xcc, 0x29, 0x62, 0xe2, 0x0f, 0xe4, 0x7f, 0xeb, 0x3e, 0xdf, 0x33, 0x0f, 0x2c, 0x60,
+            0x3a, 0x9d, 0x48, 0xc0, 0xfc, 0xb5, 0x69, 0x9d, 0xbf, 0xe5, 0x89, 0x64, 0x25, 0xc5,
+            0xba, 0xc4, 0xae, 0xe8, 0x2e, 0x57, 0xa8, 0x5a, 0xaf, 0x4e, 0x25, 0x13, 0xe4, 0xf0,
+            0x57, 0x96, 0xb0, 0x7b, 0xa2, 0xee, 0x47, 0xd8, 0x05, 0x06, 0xf8, 0xd2, 0xc2, 0x5e,
+            0x50, 0xfd, 0x14, 0xde, 0x71, 0xe6, 0xc4, 0x18, 0x55, 0x93, 0x02, 0xf9, 0x39, 0xb0,
+            0xe1, 0xab, 0xd5, 0x76, 0xf2, 0x79, 0xc4, 0xb2, 0xe0, 0xfe, 0xb8, 0x5c, 0x1f, 0x28,
+            0xff, 0x18, 0xf5, 0x88, 0x91, 0xff, 0xef, 0x13, 0x2e, 0xef, 0x2f, 0xa0, 0x93, 0x46,
+            0xae, 0xe3, 0x3c, 0x28, 0xeb, 0x13, 0x0f, 0xf2, 0x8f, 0x5b, 0x76, 0x69, 0x53, 0x33,
+            0x41, 0x13, 0x21, 0x19, 0x96, 0xd2, 0x00, 0x11, 0xa1, 0x98, 0xe3, 0xfc, 0x43, 0x3f,
+            0x9f, 0x25, 0x41, 0x01, 0x0a, 0xe1, 0x7c, 0x1b, 0xf2, 0x02, 0x58, 0x0f, 0x60, 0x47,
+            0x47, 0x2f, 0xb3, 0x68, 0x57, 0xfe, 0x84, 0x3b, 0x19, 0xf5, 0x98, 0x40, 0x09, 0xdd,
+            0xc3, 0x24, 0x04, 0x4e, 0x84, 0x7a, 0x4f, 0x4a, 0x0a, 0xb3, 0x4f, 0x71, 0x95, 0x95,
+            0xde, 0x37, 0x25, 0x2d, 0x62, 0x35, 0x36, 0x5e, 0x9b, 0x84, 0x39, 0x2b, 0x06, 0x10,
+            0x85, 0x34, 0x9d, 0x73, 0x20, 0x3a, 0x4a, 0x13, 0xe9, 0x6f, 0x54, 0x32, 0xec, 0x0f,
+            0xd4, 0xa1, 0xee, 0x65, 0xac, 0xcd, 0xd5, 0xe3, 0x90, 0x4d, 0xf5, 0x4c, 0x1d, 0xa5,
+            0x10, 0xb0, 0xff, 0x20, 0xdc, 0xc0, 0xc7, 0x7f, 0xcb, 0x2c, 0x0e, 0x0e, 0xb6, 0x05,
+            0xcb, 0x05, 0x04, 0xdb, 0x87, 0x63, 0x2c, 0xf3, 0xd8, 0xb4, 0xda, 0xe6, 0xe7, 0x05,
+            0x76, 0x9d, 0x1d, 0xe3, 0x54, 0x27, 0x01, 0x23, 0xcb, 0x11, 0x45, 0x0e, 0xfc, 0x60,
+            0xac, 0x47, 0x68, 0x3d, 0x7b, 0x8d, 0x0f, 0x81, 0x13, 0x65, 0x56, 0x5f, 0xd9, 0x8c,
+            0x4c, 0x8e, 0xb9, 0x36, 0xbc, 0xab, 0x8d, 0x06, 0x9f, 0xc3, 0x3b, 0xd8, 0x01, 0xb0,
+            0x3a, 0xde, 0xa2, 0xe1, 0xfb, 0xc5, 0xaa, 0x46, 0x3d, 0x08, 0xca, 0x19, 0x89, 0x6d,
+            0x2b, 0xf5, 0x9a, 0x07, 0x1b, 0x85, 0x1e, 0x6c, 0x23, 0x90, 0x52, 0x17, 0x2f, 0x29,
+            0x6b, 0xfb, 0x5e, 0x72, 0x40, 0x47, 0x90, 0xa2, 0x18, 0x10, 0x14, 0xf3, 0xb9, 0x4a,
+            0x4e, 0x97, 0xd1, 0x17, 0xb4, 0x38, 0x13, 0x03, 0x68, 0xcc, 0x39, 0xdb, 0xb2, 0xd1,
+            0x98, 0x06, 0x5a, 0xe3, 0x98, 0x65, 0x47, 0x92, 0x6c, 0xd2, 0x16, 0x2f, 0x40, 0xa2,
+            0x9f, 0x0c, 0x3c, 0x87, 0x45, 0xc0, 0xf5, 0x0f, 0xba, 0x38, 0x52, 0xe5, 0x66, 0xd4,
+            0x45, 0x75, 0xc2, 0x9d, 0x39, 0xa0, 0x3f, 0x0c, 0xda, 0x72, 0x19, 0x84, 0xb6, 0xf4,
+            0x40, 0x59, 0x1f, 0x35, 0x5e, 0x12, 0xd4, 0x39, 0xff, 0x15, 0x0a, 0xab, 0x76, 0x13,
+            0x49, 0x9d, 0xbd, 0x49, 0xad, 0xab, 0xc8, 0x67, 0x6e, 0xef, 0x02, 0x3b, 0x15, 0xb6,
+            0x5b, 0xfc, 0x5c, 0xa0, 0x69, 0x48, 0x10, 0x9f, 0x23, 0xf3, 0x50, 0xdb, 0x82, 0x12,
+            0x35, 0x35, 0xeb, 0x8a, 0x74, 0x33, 0xbd, 0xab, 0xcb, 0x90, 0x92, 0x71, 0xa6, 0xec,
+            0xbc, 0xb5, 0x8b, 0x93, 0x6a, 0x88, 0xcd, 0x4e, 0x8f, 0x2e, 0x6f, 0xf5, 0x80, 0x01,
+            0x75, 0xf1, 0x13, 0x25, 0x3d, 0x8f, 0xa9, 0xca, 0x88, 0x85, 0xc2, 0xf5, 0x52, 0xe6,
+            0x57, 0xdc, 0x60, 0x3f, 0x25, 0x2e, 0x1a, 0x8e, 0x30, 0x8f, 0x76, 0xf0, 0xbe, 0x79,
+            0xe2, 0xfb, 0x8f, 0x5d, 0x5f, 0xbb, 0xe2, 0xe3, 0x0e, 0xca, 0xdd, 0x22, 0x07, 0x23,
+            0xc8, 0xc0, 0xae, 0xa8, 0x07, 0x8c, 0xdf, 0xcb, 0x38, 0x68, 0x26, 0x3f, 0xf8, 0xf0,
+            0x94, 0x00, 0x54, 0xda, 0x48, 0x78, 0x18, 0x93, 0xa7, 0xe4, 0x9a, 0xd5, 0xaf, 0xf4,
+            0xaf, 0x30, 0x0c, 0xd8, 0x04, 0xa6, 0xb6, 0x27, 0x9a, 0xb3, 0xff, 0x3a, 0xfb, 0x64,
+            0x49, 0x1c, 0x85, 0x19, 0x4a, 0xab, 0x76, 0x0d, 0x58, 0xa6, 0x06, 0x65, 0x4f, 0x9f,
+            0x44, 0x00, 0xe8, 0xb3, 0x85, 0x91, 0x35, 0x6f, 0xbf, 0x64, 0x25, 0xac, 0xa2, 0x6d,
+            0xc8, 0x52, 0x44, 0x25, 0x9f, 0xf2, 0xb1, 0x9c, 0x41, 0xb9, 0xf9, 0x6f, 0x3c, 0xa9,
+            0xec, 0x1d, 0xde, 0x43, 0x4d, 0xa7, 0xd2, 0xd3, 0x92, 0xb9, 0x05, 0xdd, 0xf3, 0xd1,
+            0xf9, 0xaf, 0x93, 0xd1, 0xaf, 0x59, 0x50, 0xbd, 0x49, 0x3f, 0x5a, 0xa7, 0x31, 0xb4,
+            0x05, 0x6d, 0xf3, 0x1b, 0xd2, 0x67, 0xb6, 0xb9, 0x0a, 0x07, 0x98, 0x31, 0xaa, 0xf5,
+            0x79, 0xbe, 0x0a, 0x39, 0x01, 0x31, 0x37, 0xaa, 0xc6, 0xd4, 0x04, 0xf5, 0x18, 0xcf,
+            0xd4, 0x68, 0x40, 0x64, 0x7e, 0x78, 0xbf, 0xe7, 0x06, 0xca, 0x4c, 0xf5, 0xe9, 0xc5,
+            0x45, 0x3e, 0x9f, 0x7c, 0xfd, 0x2b, 0x8b, 0x4c, 0x8d, 0x16, 0x9a, 0x44, 0xe5, 0x5c,
+            0x88, 0xd4, 0xa9, 0xa7, 0xf9, 0x47, 0x42, 0x41, 0xe2, 0x21, 0xaf, 0x44, 0x86, 0x00,
+            0x18, 0xab, 0x08, 0x56, 0x97, 0x2e, 0x19, 0x4c, 0xd9, 0x34,
+        ];
+
+        assert_eq!(&buf, PROTECTED);
+
+        let (header, payload) = buf.split_at_mut(header_len);
+        let (first, rest) = header.split_at_mut(1);
+        let sample = &payload[..sample_len];
+
+        let server_keys = Keys::initial(
+            Version::V1,
+            TLS13_AES_128_GCM_SHA256
+                .tls13()
+                .unwrap(),
+            CONNECTION_ID,
+            Side::Server,
+        );
+        server_keys
+            .remote
+            .header
+            .decrypt_in_place(sample, &mut first[0], &mut rest[17..21])
+            .unwrap();
+        let payload = server_keys
+            .remote
+            .packet
+            .decrypt_in_place(PACKET_NUMBER, header, payload)
+            .unwrap();
+
+        assert_eq!(&payload[..PAYLOAD.len()], PAYLOAD);
+        assert_eq!(payload.len(), buf.len() - header_len - tag_len);
+    }
+
+    #[test]
+    fn test_quic_exporter() {
+        for &kt in ALL_KEY_TYPES.iter() {
+            let client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS13]);
+            let server_config = make_server_config_with_versions(kt, &[&rustls::version::TLS13]);
+
+            do_exporter_test(client_config, server_config);
+        }
+    }
+} // mod test_quic
+
+#[test]
+fn test_client_does_not_offer_sha1() {
+    use rustls::internal::msgs::{
+        codec::Reader, handshake::HandshakePayload, message::MessagePayload, message::OpaqueMessage,
+    };
+    use rustls::HandshakeType;
+
+    for kt in ALL_KEY_TYPES.iter() {
+        for version in rustls::ALL_VERSIONS {
+            let client_config = make_client_config_with_versions(*kt, &[version]);
+            let (mut client, _) = make_pair_for_configs(client_config, make_server_config(*kt));
+
+            assert!(client.wants_write());
+            let mut buf = [0u8; 262144];
+            let sz = client
+                .write_tls(&mut buf.as_mut())
+                .unwrap();
+            let msg = OpaqueMessage::read(&mut Reader::init(&buf[..sz])).unwrap();
+            let msg = Message::try_from(msg.into_plain_message()).unwrap();
+            assert!(msg.is_handshake_type(HandshakeType::ClientHello));
+
+            let client_hello = match msg.payload {
+                MessagePayload::Handshake { parsed, .. } => match parsed.payload {
+                    HandshakePayload::ClientHello(ch) => ch,
+                    _ => unreachable!(),
+                },
+                _ => unreachable!(),
+            };
+
+            let sigalgs = client_hello
+                .get_sigalgs_extension()
+                .unwrap();
+            assert!(
+                !sigalgs.contains(&SignatureScheme::RSA_PKCS1_SHA1),
+                "sha1 unexpectedly offered"
+            );
+        }
+    }
+}
+
+fn client_hello_extensions(client: &mut ClientConnection) -> (Vec<u8>, Vec<ClientExtension>) {
+    use rustls::internal::msgs::{codec::Reader, message::OpaqueMessage};
+
+    let mut buf = Vec::new();
+    client.write_tls(&mut buf).unwrap();
+    let msg = OpaqueMessage::read(&mut Reader::init(&buf)).unwrap();
+    let msg = Message::try_from(msg.into_plain_message()).unwrap();
+    match msg.payload {
+        MessagePayload::Handshake { parsed, .. } => match parsed.payload {
+            HandshakePayload::ClientHello(ch) => (buf, ch.extensions),
+            _ => unreachable!(),
+        },
+        _ => unreachable!(),
+    }
+}
+
+/// Hands out the first TLS1.3 ticket it is given, every time.
+#[derive(Debug, Default)]
+struct SingleTicketStore {
+    ticket: Mutex<Option<rustls::client::Tls13ClientSessionValue>>,
+}
+
+impl rustls::client::ClientSessionStore for SingleTicketStore {
+    fn set_kx_hint(&self, _: &rustls::ServerName, _: rustls::NamedGroup) {}
+
+    fn kx_hint(&self, _: &rustls::ServerName) -> Option<rustls::NamedGroup> {
+        None
+    }
+
+    fn set_tls12_session(
+        &self,
+        _: &rustls::ServerName,
+        _: rustls::client::Tls12ClientSessionValue,
+    ) {
+    }
+
+    fn tls12_session(
+        &self,
+        _: &rustls::ServerName,
+    ) -> Option<rustls::client::Tls12ClientSessionValue> {
+        None
+    }
+
+    fn remove_tls12_session(&self, _: &rustls::ServerName) {}
+
+    fn insert_tls13_ticket(
+        &self,
+        _: &rustls::ServerName,
+        value: rustls::client::Tls13ClientSessionValue,
+    ) {
+        self.ticket
+            .lock()
+            .unwrap()
+            .get_or_insert(value);
+    }
+
+    fn take_tls13_ticket(
+        &self,
+        _: &rustls::ServerName,
+    ) -> Option<rustls::client::Tls13ClientSessionValue> {
+        self.ticket.lock().unwrap().clone()
+    }
+}
+
+#[test]
+fn test_client_ticket_age_uses_fixed_time() {
+    let kt = KeyType::Rsa;
+    let store = Arc::new(SingleTicketStore::default());
+    let client_config_at = |secs: u64| {
+        let mut config = make_client_config_with_versions(kt, &[&rustls::version::TLS13]);
+        config.resumption = Resumption::store(store.clone());
+        config.fixed_time = Some(UnixTime::since_unix_epoch(Duration::from_secs(secs)));
+        config
+    };
+    let issued = 1_700_000_000;
+
+    let (mut client, mut server) =
+        make_pair_for_configs(client_config_at(issued), make_server_config(kt));
+    do_handshake(&mut client, &mut server);
+
+    let obfuscated_ticket_age = |secs: u64| {
+        let (mut client, _) = make_pair_for_configs(client_config_at(secs), make_server_config(kt));
+        client_hello_extensions(&mut client)
+            .1
+            .iter()
+            .find_map(|ext| match ext {
+                ClientExtension::PresharedKey(offer) => {
+                    Some(offer.identities[0].obfuscated_ticket_age)
+                }
+                _ => None,
+            })
+            .unwrap()
+    };
+
+    let first = obfuscated_ticket_age(issued + 10);
+    assert_eq!(obfuscated_ticket_age(issued + 10), first);
+    assert_eq!(
+        obfuscated_ticket_age(issued + 12).wrapping_sub(first),
+        2_000
+    );
+}
+
+#[test]
+fn test_client_tls13_resumption_can_be_disabled() {
+    let kt = KeyType::Rsa;
+    let storage = Arc::new(ClientStorage::new());
+    let mut client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS13]);
+    client_config.resumption = Resumption::store(storage.clone()).tls13_resumption(false);
+    let client_config = Arc::new(client_config);
+    let server_config = Arc::new(make_server_config(kt));
+
+    for _ in 0..2 {
+        let (mut client, _) = make_pair_for_arc_configs(&client_config, &server_config);
+        let (_, exts) = client_hello_extensions(&mut client);
+        assert!(!exts
+            .iter()
+            .any(|ext| matches!(ext, ClientExtension::PresharedKey(_))));
+        assert!(!exts
+            .iter()
+            .any(|ext| matches!(ext, ClientExtension::PresharedKeyModes(_))));
+
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        do_handshake(&mut client, &mut server);
+    }
+
+    assert!(!storage.ops().iter().any(|op| matches!(
+        op,
+        ClientStorageOp::InsertTls13Ticket(_) | ClientStorageOp::TakeTls13Ticket(_, _)
+    )));
+}
+
+#[test]
+fn test_client_session_cache_survives_export_and_import() {
+    let kt = KeyType::Rsa;
+    let server_config = Arc::new(make_server_config(kt));
+
+    for version in rustls::ALL_VERSIONS {
+        let make_client_config = |cache: &Arc<rustls::client::ClientSessionMemoryCache>| {
+            let mut config = make_client_config_with_versions(kt, &[version]);
+            config.resumption = Resumption::store(cache.clone());
+            Arc::new(config)
+        };
+
+        let cache = Arc::new(rustls::client::ClientSessionMemoryCache::new(32));
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&make_client_config(&cache), &server_config);
+        let (_, full_s2c) = do_handshake(&mut client, &mut server);
+        let exported = cache.export();
+
+        let cache = Arc::new(rustls::client::ClientSessionMemoryCache::new(32));
+        cache
+            .import(&exported, ALL_CIPHER_SUITES)
+            .unwrap();
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&make_client_config(&cache), &server_config);
+        let (_, resume_s2c) = do_handshake(&mut client, &mut server);
+        assert!(resume_s2c < full_s2c);
+
+        // sessions for suites we no longer support are dropped
+        let cache = Arc::new(rustls::client::ClientSessionMemoryCache::new(32));
+        cache.import(&exported, &[]).unwrap();
+        let (mut client, mut server) =
+            make_pair_for_arc_configs(&make_client_config(&cache), &server_config);
+        let (_, dropped_s2c) = do_handshake(&mut client, &mut server);
+        assert!(dropped_s2c > resume_s2c);
+
+        assert!(matches!(
+            cache.import(&exported[..exported.len() - 1], ALL_CIPHER_SUITES),
+            Err(Error::InvalidMessage(_))
+        ));
+    }
+}
+
+#[test]
+fn test_client_resumption_can_be_suppressed_per_server() {
+    let kt = KeyType::Rsa;
+    let storage = Arc::new(ClientStorage::new());
+    let mut client_config = make_client_config(kt);
+    client_config.resumption = Resumption::store(storage.clone())
+        .offer_resumption_if(|name| *name != server_name("second.testserver.com"));
+    let client_config = Arc::new(client_config);
+    let server_config = Arc::new(make_server_config(kt));
+
+    for name in ["testserver.com", "second.testserver.com"] {
+        for _ in 0..2 {
+            let mut client =
+                ClientConnection::new(Arc::clone(&client_config), server_name(name)).unwrap();
+            let mut server = ServerConnection::new(Arc::clone(&server_config)).unwrap();
+            do_handshake(&mut client, &mut server);
+        }
+    }
+
+    let first = server_name("testserver.com");
+    let second = server_name("second.testserver.com");
+    let ops = storage.ops();
+    assert!(ops.iter().any(|op| matches!(
+        op,
+        ClientStorageOp::TakeTls13Ticket(n, true) if *n == first
+    )));
+    assert!(!ops.iter().any(|op| matches!(
+        op,
+        ClientStorageOp::TakeTls13Ticket(n, _) | ClientStorageOp::GetTls12Session(n, _)
+            if *n == second
+    )));
+
+    // the new sessions from the suppressed server were stored all the same
+    assert!(ops.iter().any(|op| matches!(
+        op,
+        ClientStorageOp::InsertTls13Ticket(n) if *n == second
+    )));
+}
+
+#[cfg(feature = "tls12")]
+#[test]
+fn test_client_tls13_resumption_disabled_keeps_tls12_resumption() {
+    let kt = KeyType::Rsa;
+    let storage = Arc::new(ClientStorage::new());
+    let mut client_config = make_client_config(kt);
+    client_config.resumption = Resumption::store(storage.clone()).tls13_resumption(false);
+    let client_config = Arc::new(client_config);
+    let server_config = Arc::new(make_server_config_with_versions(
+        kt,
+        &[&rustls::version::TLS12],
+    ));
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+    assert!(storage
+        .ops_and_reset()
+        .iter()
+        .any(|op| matches!(op, ClientStorageOp::SetTls12Session(_))));
+
+    let (mut client, _) = make_pair_for_arc_configs(&client_config, &server_config);
+    let (_, exts) = client_hello_extensions(&mut client);
+    assert!(!exts
+        .iter()
+        .any(|ext| matches!(ext, ClientExtension::PresharedKey(_))));
+
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+    let ops = storage.ops();
+    assert!(ops
+        .iter()
+        .any(|op| matches!(op, ClientStorageOp::GetTls12Session(_, true))));
+    assert!(!ops
+        .iter()
+        .any(|op| matches!(op, ClientStorageOp::TakeTls13Ticket(_, _))));
+}
+
+#[test]
+fn test_client_ec_point_formats_can_be_omitted() {
+    use rustls::internal::msgs::enums::ECPointFormat;
+
+    for send in [true, false] {
+        for version in rustls::ALL_VERSIONS {
+            let mut client_config = make_client_config_with_versions(KeyType::Ecdsa, &[version]);
+            client_config.send_ec_point_formats = send;
+            let (mut client, mut server) =
+                make_pair_for_configs(client_config, make_server_config(KeyType::Ecdsa));
+
+            let (buf, exts) = client_hello_extensions(&mut client);
+            let formats = exts.iter().find_map(|ext| match ext {
+                ClientExtension::ECPointFormats(formats) => Some(formats.clone()),
+                _ => None,
+            });
+            if send {
+                assert_eq!(formats, Some(vec![ECPointFormat::Uncompressed]));
+            } else {
+                assert_eq!(formats, None);
+            }
+
+            server.read_tls(&mut &buf[..]).unwrap();
+            if send || version.version == ProtocolVersion::TLSv1_3 {
+                do_handshake(&mut client, &mut server);
+            } else {
+                assert_eq!(
+                    server.process_new_packets().err(),
+                    Some(Error::PeerIncompatible(
+                        PeerIncompatible::EcPointsExtensionRequired
+                    ))
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn test_client_hello_record_version_is_configurable() {
+    for (version, wire) in [
+        (None, [0x03, 0x01]),
+        (Some(ProtocolVersion::TLSv1_2), [0x03, 0x03]),
+    ] {
+        let mut client_config = make_client_config(KeyType::Rsa);
+        if let Some(version) = version {
+            client_config.client_hello_record_version = version;
+        }
+        let (mut client, mut server) =
+            make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+
+        let (buf, _) = client_hello_extensions(&mut client);
+        assert_eq!(buf[0], 0x16);
+        assert_eq!(buf[1..3], wire);
+
+        server.read_tls(&mut &buf[..]).unwrap();
+        do_handshake(&mut client, &mut server);
+    }
+}
+
+#[test]
+fn name_constraint_verifier_enforces_subtrees_per_anchor() {
+    use rustls::{NameConstraintVerifier, NameSubtrees};
+
+    let check_chain = |anchor_kt: KeyType, subtrees: NameSubtrees, chain| {
+        let kt = KeyType::Rsa;
+        let anchor = anchor_kt.get_chain().pop().unwrap();
+        let verifier = NameConstraintVerifier::new(Arc::new(
+            rustls::client::WebPkiServerVerifier::new(get_client_root_store(kt)),
+        ))
+        .with_constraints(&anchor, subtrees)
+        .unwrap();
+
+        let mut client_config = make_client_config(kt);
+        client_config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(verifier));
+        let server_config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(chain, kt.get_key())
+            .unwrap();
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        do_handshake_until_error(&mut client, &mut server)
+    };
+    let check = |anchor_kt: KeyType, subtrees: NameSubtrees| {
+        check_chain(anchor_kt, subtrees, KeyType::Rsa.get_chain())
+    };
+
+    // the server's certificate names testserver.com, second.testserver.com and localhost
+    let permitted = NameSubtrees::new()
+        .permit("testserver.com")
+        .unwrap()
+        .permit("localhost")
+        .unwrap();
+    assert_eq!(check(KeyType::Rsa, permitted), Ok(()));
+
+    let excluded = NameSubtrees::new()
+        .exclude("second.testserver.com")
+        .unwrap();
+    assert_eq!(
+        check(KeyType::Rsa, excluded.clone()),
+        Err(ErrorFromPeer::Client(Error::InvalidCertificate(
+            CertificateError::NotValidForName
+        )))
+    );
+
+    let outside = NameSubtrees::new()
+        .permit("testserver.com")
+        .unwrap();
+    assert_eq!(
+        check(KeyType::Rsa, outside),
+        Err(ErrorFromPeer::Client(Error::InvalidCertificate(
+            CertificateError::NotValidForName
+        )))
+    );
+
+    // constraints on an unrelated anchor do not apply
+    assert_eq!(check(KeyType::Ecdsa, excluded.clone()), Ok(()));
+
+    // ... even if the server appends an intermediate naming that anchor
+    let mut chain = KeyType::Rsa.get_chain();
+    chain.push(KeyType::Ecdsa.get_chain()[1].clone());
+    assert_eq!(
+        check_chain(KeyType::Ecdsa, excluded.clone(), chain.clone()),
+        Ok(())
+    );
+
+    // and appending it does not dodge the constraints of the anchor the chain validates to
+    assert_eq!(
+        check_chain(KeyType::Rsa, excluded, chain),
+        Err(ErrorFromPeer::Client(Error::InvalidCertificate(
+            CertificateError::NotValidForName
+        )))
+    );
+}
+
+#[test]
+fn server_detects_reused_client_key_share() {
+    use rustls::{KeyShareReuseDetector, KeyShareReusePolicy};
+
+    for policy in [KeyShareReusePolicy::Log, KeyShareReusePolicy::Reject] {
+        let detector = KeyShareReuseDetector::new(policy, 16);
+        let mut server_config = make_server_config(KeyType::Rsa);
+        server_config.key_share_reuse_detector = Some(detector.clone());
+        let server_config = Arc::new(server_config);
+
+        let mut client = ClientConnection::new(
+            Arc::new(make_client_config(KeyType::Rsa)),
+            server_name("localhost"),
+        )
+        .unwrap();
+        let (client_hello, _) = client_hello_extensions(&mut client);
+
+        // the same ClientHello (and so the same key share) is sent to two servers
+        let mut first = ServerConnection::new(server_config.clone()).unwrap();
+        first
+            .read_tls(&mut &client_hello[..])
+            .unwrap();
+        first.process_new_packets().unwrap();
+        assert_eq!(detector.reuses_detected(), 0);
+
+        let mut second = ServerConnection::new(server_config).unwrap();
+        second
+            .read_tls(&mut &client_hello[..])
+            .unwrap();
+        let result = second.process_new_packets().map(|_| ());
+        assert_eq!(detector.reuses_detected(), 1);
+
+        match policy {
+            KeyShareReusePolicy::Log => assert_eq!(result, Ok(())),
+            KeyShareReusePolicy::Reject => assert_eq!(
+                result,
+                Err(Error::PeerMisbehaved(PeerMisbehaved::ReusedKeyShare))
+            ),
+        }
+    }
+}
+
+#[test]
+fn configs_round_trip_through_to_builder() {
+    let mut client_config =
+        make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS13]);
+    client_config.alpn_protocols = vec![b"h2".to_vec()];
+    client_config.max_fragment_size = Some(1000);
+    client_config.enable_sni = false;
+
+    let mut rebuilt = client_config
+        .to_builder()
+        .with_no_client_auth();
+    assert_eq!(format!("{:?}", rebuilt), format!("{:?}", client_config));
+    rebuilt.alpn_protocols = vec![b"http/1.1".to_vec()];
+    client_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    assert_eq!(format!("{:?}", rebuilt), format!("{:?}", client_config));
+
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.alpn_protocols = vec![b"h2".to_vec()];
+    server_config.send_tls13_tickets = 1;
+
+    let kt = KeyType::Ecdsa;
+    let mut rebuilt = server_config
+        .to_builder()
+        .with_single_cert(kt.get_chain(), kt.get_key())
+        .unwrap();
+    assert_eq!(format!("{:?}", rebuilt), format!("{:?}", server_config));
+    rebuilt.alpn_protocols = vec![b"http/1.1".to_vec()];
+    assert_eq!(rebuilt.send_tls13_tickets, 1);
+
+    // the rebuilt server uses its new certificate, and keeps other settings
+    let (mut client, mut server) = make_pair_for_configs(
+        {
+            let mut config = make_client_config(kt);
+            config.alpn_protocols = vec![b"http/1.1".to_vec()];
+            config
+        },
+        rebuilt,
+    );
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.alpn_protocol(), Some(&b"http/1.1"[..]));
+}
+
+#[test]
+fn key_shares_are_available_after_key_exchange() {
+    for version in rustls::ALL_VERSIONS {
+        let client_config = make_client_config_with_versions(KeyType::Rsa, &[version]);
+        let (mut client, mut server) =
+            make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+        assert_eq!(client.local_key_share(), None);
+        assert_eq!(client.peer_key_share(), None);
+
+        // both sides default to X25519
+        do_handshake(&mut client, &mut server);
+
+        let peer_share = client.peer_key_share().unwrap();
+        assert_eq!(peer_share.len(), 32);
+        assert_eq!(server.local_key_share(), Some(peer_share));
+        assert_eq!(server.peer_key_share(), client.local_key_share());
+        assert_eq!(server.peer_key_share().unwrap().len(), 32);
+    }
+}
+
+#[test]
+fn pending_tls_bytes_reflects_queued_records() {
+    for version in rustls::ALL_VERSIONS {
+        let client_config = make_client_config_with_versions(KeyType::Rsa, &[version]);
+        let (mut client, mut server) =
+            make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+
+        // the ClientHello is queued on creation
+        let queued = client.pending_tls_bytes();
+        assert!(queued > 0);
+        assert_eq!(client.pending_tls_bytes(), queued);
+
+        let mut buf = Vec::new();
+        assert_eq!(client.write_tls(&mut buf).unwrap(), queued);
+        assert_eq!(client.pending_tls_bytes(), 0);
+
+        server
+            .read_tls(&mut buf.as_slice())
+            .unwrap();
+        assert_eq!(server.pending_tls_bytes(), 0);
+        server.process_new_packets().unwrap();
+        let queued = server.pending_tls_bytes();
+        assert!(queued > 0);
+        assert_eq!(
+            server
+                .process_new_packets()
+                .unwrap()
+                .tls_bytes_to_write(),
+            queued
+        );
+
+        transfer(&mut server, &mut client);
+        assert_eq!(server.pending_tls_bytes(), 0);
+    }
+}
+
+#[test]
+fn to_builder_keeps_existing_cert_resolvers() {
+    let kt = KeyType::Rsa;
+    let client_config = make_client_config_with_auth(kt)
+        .to_builder()
+        .with_alpn_protocols(vec![b"h2".to_vec()])
+        .unwrap()
+        .with_existing_client_cert_resolver();
+    let mut server_config = make_server_config_with_mandatory_client_auth(kt);
+    server_config.alpn_protocols = vec![b"h2".to_vec()];
+    let server_config = server_config
+        .to_builder()
+        .with_existing_cert_resolver();
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.alpn_protocol(), Some(&b"h2"[..]));
+    assert_eq!(
+        server.peer_certificates(),
+        Some(kt.get_client_chain().as_slice())
+    );
+}
+
+#[test]
+fn test_client_sends_grease_ech_when_enabled() {
+    use rustls::internal::msgs::enums::ExtensionType;
+
+    let mut client_config = make_client_config(KeyType::Rsa);
+    client_config.enable_grease_ech = true;
+    let (mut client, mut server) =
+        make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+
+    let (buf, exts) = client_hello_extensions(&mut client);
+    let ech = exts
+        .iter()
+        .find(|ext| ext.get_type() == ExtensionType::EncryptedClientHello);
+    match ech {
+        Some(ClientExtension::Unknown(ext)) => {
+            // outer type, HKDF-SHA256, AES-128-GCM
+            assert_eq!(&ext.payload.0[..5], &[0, 0, 1, 0, 1]);
+        }
+        _ => panic!("no GREASE ECH extension in {:?}", exts),
     }
 
-    fn kx_hint(&self, server_name: &rustls::ServerName) -> Option<rustls::NamedGroup> {
-        let rc = self.storage.kx_hint(server_name);
-        self.ops
-            .lock()
-            .unwrap()
-            .push(ClientStorageOp::GetKxHint(server_name.clone(), rc));
-        rc
+    // supported_versions GREASE is configured separately
+    let versions = exts
+        .iter()
+        .find_map(|ext| match ext {
+            ClientExtension::SupportedVersions(versions) => Some(versions),
+            _ => None,
+        })
+        .unwrap();
+    assert!(!versions
+        .iter()
+        .any(|v| matches!(v, ProtocolVersion::Unknown(_))));
+
+    // the server ignores it
+    server.read_tls(&mut &buf[..]).unwrap();
+    server.process_new_packets().unwrap();
+    do_handshake(&mut client, &mut server);
+}
+
+#[test]
+fn test_client_sends_grease_supported_version_when_enabled() {
+    let mut client_config = make_client_config(KeyType::Rsa);
+    client_config.enable_grease_supported_versions = true;
+    let (mut client, mut server) =
+        make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+
+    let (buf, exts) = client_hello_extensions(&mut client);
+    let versions = exts
+        .iter()
+        .find_map(|ext| match ext {
+            ClientExtension::SupportedVersions(versions) => Some(versions),
+            _ => None,
+        })
+        .unwrap();
+    match versions[0] {
+        ProtocolVersion::Unknown(v) => {
+            assert_eq!(v & 0x0f0f, 0x0a0a);
+            assert_eq!(v >> 8, v & 0xff);
+        }
+        _ => panic!("no GREASE version in {:?}", versions),
     }
+    assert_eq!(
+        versions[1..].to_vec(),
+        rustls::DEFAULT_VERSIONS
+            .iter()
+            .map(|v| v.version)
+            .collect::<Vec<_>>()
+    );
 
-    fn set_tls12_session(
+    server.read_tls(&mut &buf[..]).unwrap();
+    server.process_new_packets().unwrap();
+    do_handshake(&mut client, &mut server);
+    assert_eq!(client.protocol_version(), Some(ProtocolVersion::TLSv1_3));
+}
+
+#[test]
+fn test_client_config_keyshare() {
+    let client_config = make_client_config_with_kx_groups(
+        KeyType::Rsa,
+        &[rustls::crypto::ring::kx_group::SECP384R1],
+    );
+    let server_config = make_server_config_with_kx_groups(
+        KeyType::Rsa,
+        &[rustls::crypto::ring::kx_group::SECP384R1],
+    );
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake_until_error(&mut client, &mut server).unwrap();
+}
+
+#[test]
+fn test_client_config_keyshare_mismatch() {
+    let client_config = make_client_config_with_kx_groups(
+        KeyType::Rsa,
+        &[rustls::crypto::ring::kx_group::SECP384R1],
+    );
+    let server_config =
+        make_server_config_with_kx_groups(KeyType::Rsa, &[rustls::crypto::ring::kx_group::X25519]);
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    assert!(do_handshake_until_error(&mut client, &mut server).is_err());
+}
+
+/// A key exchange group that claims to be FFDHE2048, but is really X25519.
+#[derive(Debug)]
+struct FakeFfdhe2048;
+
+impl rustls::crypto::SupportedKxGroup for FakeFfdhe2048 {
+    fn start(
         &self,
-        server_name: &rustls::ServerName,
-        value: rustls::client::Tls12ClientSessionValue,
-    ) {
-        self.ops
-            .lock()
-            .unwrap()
-            .push(ClientStorageOp::SetTls12Session(server_name.clone()));
-        self.storage
-            .set_tls12_session(server_name, value)
+    ) -> Result<Box<dyn rustls::crypto::ActiveKeyExchange>, rustls::crypto::GetRandomFailed> {
+        Ok(Box::new(FakeFfdhe2048Kx(
+            rustls::crypto::ring::kx_group::X25519.start()?,
+        )))
     }
 
-    fn tls12_session(
-        &self,
-        server_name: &rustls::ServerName,
-    ) -> Option<rustls::client::Tls12ClientSessionValue> {
-        let rc = self.storage.tls12_session(server_name);
-        self.ops
-            .lock()
-            .unwrap()
-            .push(ClientStorageOp::GetTls12Session(
-                server_name.clone(),
-                rc.is_some(),
-            ));
-        rc
+    fn name(&self) -> rustls::NamedGroup {
+        rustls::NamedGroup::FFDHE2048
     }
+}
 
-    fn remove_tls12_session(&self, server_name: &rustls::ServerName) {
-        self.ops
-            .lock()
-            .unwrap()
-            .push(ClientStorageOp::RemoveTls12Session(server_name.clone()));
-        self.storage
-            .remove_tls12_session(server_name);
+struct FakeFfdhe2048Kx(Box<dyn rustls::crypto::ActiveKeyExchange>);
+
+impl rustls::crypto::ActiveKeyExchange for FakeFfdhe2048Kx {
+    fn complete(
+        self: Box<Self>,
+        peer_pub_key: &[u8],
+    ) -> Result<rustls::crypto::SharedSecret, Error> {
+        self.0.complete(peer_pub_key)
     }
 
-    fn insert_tls13_ticket(
-        &self,
-        server_name: &rustls::ServerName,
-        value: rustls::client::Tls13ClientSessionValue,
-    ) {
-        self.ops
-            .lock()
-            .unwrap()
-            .push(ClientStorageOp::InsertTls13Ticket(server_name.clone()));
-        self.storage
-            .insert_tls13_ticket(server_name, value);
+    fn pub_key(&self) -> &[u8] {
+        self.0.pub_key()
     }
 
-    fn take_tls13_ticket(
-        &self,
-        server_name: &rustls::ServerName,
-    ) -> Option<rustls::client::Tls13ClientSessionValue> {
-        let rc = self
-            .storage
-            .take_tls13_ticket(server_name);
-        self.ops
-            .lock()
-            .unwrap()
-            .push(ClientStorageOp::TakeTls13Ticket(
-                server_name.clone(),
-                rc.is_some(),
-            ));
-        rc
+    fn group(&self) -> rustls::NamedGroup {
+        rustls::NamedGroup::FFDHE2048
+    }
+}
+
+fn ffdhe_test(version: &'static rustls::SupportedProtocolVersion, min_ffdhe_bits: u16) {
+    let mut client_config = ClientConfig::builder()
+        .with_safe_default_cipher_suites()
+        .with_kx_groups(&[&FakeFfdhe2048])
+        .with_protocol_versions(&[version])
+        .unwrap()
+        .with_root_certificates(get_client_root_store(KeyType::Rsa))
+        .with_no_client_auth();
+    client_config.min_ffdhe_bits = min_ffdhe_bits;
+    let server_config = make_server_config_with_kx_groups(KeyType::Rsa, &[&FakeFfdhe2048]);
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+
+    if min_ffdhe_bits <= 2048 {
+        do_handshake(&mut client, &mut server);
+        assert_eq!(client.protocol_version(), Some(version.version));
+    } else {
+        assert_eq!(
+            do_handshake_until_error(&mut client, &mut server),
+            Err(ErrorFromPeer::Client(Error::PeerIncompatible(
+                PeerIncompatible::ServerSelectedWeakFfdheGroup
+            )))
+        );
+    }
+}
+
+#[cfg(feature = "tls12")]
+#[test]
+fn tls12_client_rejects_ffdhe_group_below_minimum() {
+    ffdhe_test(&rustls::version::TLS12, 2048);
+    ffdhe_test(&rustls::version::TLS12, 3072);
+}
+
+#[test]
+fn tls13_client_rejects_ffdhe_group_below_minimum() {
+    ffdhe_test(&rustls::version::TLS13, 2048);
+    ffdhe_test(&rustls::version::TLS13, 3072);
+}
+
+#[test]
+fn server_cipher_suite_policy_can_reject_top_suite() {
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.ignore_client_order = true;
+    server_config.cipher_suite_policy = Some(Arc::new(|suite, client_hello| {
+        assert!(client_hello
+            .cipher_suites()
+            .contains(&suite.suite()));
+        suite.suite() != CipherSuite::TLS13_AES_256_GCM_SHA384
+    }));
+
+    let (mut client, mut server) =
+        make_pair_for_configs(make_client_config(KeyType::Rsa), server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(
+        server
+            .negotiated_cipher_suite()
+            .map(|scs| scs.suite()),
+        Some(CipherSuite::TLS13_AES_128_GCM_SHA256)
+    );
+}
+
+#[test]
+fn server_cipher_suite_policy_rejecting_everything_fails_handshake() {
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.cipher_suite_policy = Some(Arc::new(|_, _| false));
+
+    let (mut client, mut server) =
+        make_pair_for_configs(make_client_config(KeyType::Rsa), server_config);
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Server(Error::PeerIncompatible(
+            PeerIncompatible::NoCipherSuitesInCommon
+        )))
+    );
+}
+
+#[cfg(feature = "tls12")]
+#[test]
+fn test_client_sends_helloretryrequest() {
+    // client sends a secp384r1 key share
+    let mut client_config = make_client_config_with_kx_groups(
+        KeyType::Rsa,
+        &[
+            rustls::crypto::ring::kx_group::SECP384R1,
+            rustls::crypto::ring::kx_group::X25519,
+        ],
+    );
+
+    let storage = Arc::new(ClientStorage::new());
+    client_config.resumption = Resumption::store(storage.clone());
+
+    // but server only accepts x25519, so a HRR is required
+    let server_config =
+        make_server_config_with_kx_groups(KeyType::Rsa, &[rustls::crypto::ring::kx_group::X25519]);
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+
+    // client sends hello
+    {
+        let mut pipe = OtherSession::new(&mut server);
+        let wrlen = client.write_tls(&mut pipe).unwrap();
+        assert!(wrlen > 200);
+        assert_eq!(pipe.writevs.len(), 1);
+        assert!(pipe.writevs[0].len() == 1);
     }
+
+    // server sends HRR
+    {
+        let mut pipe = OtherSession::new(&mut client);
+        let wrlen = server.write_tls(&mut pipe).unwrap();
+        assert!(wrlen < 100); // just the hello retry request
+        assert_eq!(pipe.writevs.len(), 1); // only one writev
+        assert!(pipe.writevs[0].len() == 2); // hello retry request and CCS
+    }
+
+    // client sends fixed hello
+    {
+        let mut pipe = OtherSession::new(&mut server);
+        let wrlen = client.write_tls(&mut pipe).unwrap();
+        assert!(wrlen > 200); // just the client hello retry
+        assert_eq!(pipe.writevs.len(), 1); // only one writev
+        assert!(pipe.writevs[0].len() == 2); // only a CCS & client hello retry
+    }
+
+    // server completes handshake
+    {
+        let mut pipe = OtherSession::new(&mut client);
+        let wrlen = server.write_tls(&mut pipe).unwrap();
+        assert!(wrlen > 200);
+        assert_eq!(pipe.writevs.len(), 1);
+        assert!(pipe.writevs[0].len() == 5); // server hello / encrypted exts / cert / cert-verify / finished
+    }
+
+    do_handshake_until_error(&mut client, &mut server).unwrap();
+
+    // client only did following storage queries:
+    println!("storage {:#?}", storage.ops());
+    assert_eq!(storage.ops().len(), 9);
+    assert!(matches!(
+        storage.ops()[0],
+        ClientStorageOp::TakeTls13Ticket(_, false)
+    ));
+    assert!(matches!(
+        storage.ops()[1],
+        ClientStorageOp::GetTls12Session(_, false)
+    ));
+    assert!(matches!(
+        storage.ops()[2],
+        ClientStorageOp::GetKxHint(_, None)
+    ));
+    assert!(matches!(
+        storage.ops()[3],
+        ClientStorageOp::SetKxHint(_, rustls::NamedGroup::X25519)
+    ));
+    assert!(matches!(
+        storage.ops()[4],
+        ClientStorageOp::RemoveTls12Session(_)
+    ));
+    // server sends 4 tickets by default
+    assert!(matches!(
+        storage.ops()[5],
+        ClientStorageOp::InsertTls13Ticket(_)
+    ));
+    assert!(matches!(
+        storage.ops()[6],
+        ClientStorageOp::InsertTls13Ticket(_)
+    ));
+    assert!(matches!(
+        storage.ops()[7],
+        ClientStorageOp::InsertTls13Ticket(_)
+    ));
+    assert!(matches!(
+        storage.ops()[8],
+        ClientStorageOp::InsertTls13Ticket(_)
+    ));
 }
 
-#[test]
-fn tls13_stateful_resumption() {
-    let kt = KeyType::Rsa;
-    let client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS13]);
+/// Resume a TLS1.3 session with a server that demands a HelloRetryRequest,
+/// returning whether the server resumed it.
+fn resume_after_hello_retry(omit_psk_after_hello_retry: bool) -> bool {
+    let mut client_config = make_client_config_with_kx_groups(
+        KeyType::Rsa,
+        &[
+            rustls::crypto::ring::kx_group::SECP384R1,
+            rustls::crypto::ring::kx_group::X25519,
+        ],
+    );
+    client_config.resumption =
+        Resumption::default().omit_psk_after_hello_retry(omit_psk_after_hello_retry);
     let client_config = Arc::new(client_config);
 
-    let mut server_config = make_server_config(kt);
     let storage = Arc::new(ServerStorage::new());
-    server_config.session_storage = storage.clone();
-    let server_config = Arc::new(server_config);
+    let mut first_server_config = make_server_config_with_kx_groups(
+        KeyType::Rsa,
+        &[
+            rustls::crypto::ring::kx_group::SECP384R1,
+            rustls::crypto::ring::kx_group::X25519,
+        ],
+    );
+    first_server_config.session_storage = storage.clone();
 
-    // full handshake
-    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
-    let (full_c2s, full_s2c) = do_handshake(&mut client, &mut server);
-    assert_eq!(storage.puts(), 4);
-    assert_eq!(storage.gets(), 0);
+    // the client remembers secp384r1 for the next handshake...
+    let (mut client, mut server) =
+        make_pair_for_arc_configs(&client_config, &Arc::new(first_server_config));
+    do_handshake(&mut client, &mut server);
     assert_eq!(storage.takes(), 0);
-    assert_eq!(
-        client
-            .peer_certificates()
-            .map(|certs| certs.len()),
-        Some(3)
-    );
 
-    // resumed
-    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
-    let (resume_c2s, resume_s2c) = do_handshake(&mut client, &mut server);
-    assert!(resume_c2s > full_c2s);
-    assert!(resume_s2c < full_s2c);
-    assert_eq!(storage.puts(), 8);
-    assert_eq!(storage.gets(), 0);
-    assert_eq!(storage.takes(), 1);
-    assert_eq!(
-        client
-            .peer_certificates()
-            .map(|certs| certs.len()),
-        Some(3)
-    );
+    // ...but the server now only accepts x25519, so a HRR is required
+    let mut second_server_config =
+        make_server_config_with_kx_groups(KeyType::Rsa, &[rustls::crypto::ring::kx_group::X25519]);
+    second_server_config.session_storage = storage.clone();
 
-    // resumed again
-    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
-    let (resume2_c2s, resume2_s2c) = do_handshake(&mut client, &mut server);
-    assert_eq!(resume_s2c, resume2_s2c);
-    assert_eq!(resume_c2s, resume2_c2s);
-    assert_eq!(storage.puts(), 12);
-    assert_eq!(storage.gets(), 0);
-    assert_eq!(storage.takes(), 2);
-    assert_eq!(
-        client
-            .peer_certificates()
-            .map(|certs| certs.len()),
-        Some(3)
-    );
+    let (mut client, mut server) =
+        make_pair_for_arc_configs(&client_config, &Arc::new(second_server_config));
+    do_handshake(&mut client, &mut server);
+
+    storage.takes() == 1
 }
 
 #[test]
-fn tls13_stateless_resumption() {
-    let kt = KeyType::Rsa;
-    let client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS13]);
+fn test_client_offers_psk_after_helloretryrequest() {
+    assert!(resume_after_hello_retry(false));
+}
+
+#[test]
+fn test_client_can_omit_psk_after_helloretryrequest() {
+    assert!(!resume_after_hello_retry(true));
+}
+
+/// Resume a TLS1.3 session offering the given PSK key exchange modes,
+/// returning whether the resumed ClientHello and ServerHello carried
+/// key shares.
+fn resume_with_psk_modes(modes: Tls13PskKeyExchangeModes) -> (bool, bool) {
+    use rustls::internal::msgs::enums::PSKKeyExchangeMode;
+
+    let mut client_config =
+        make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS13]);
+    client_config.resumption = Resumption::default().tls13_psk_key_exchange_modes(modes);
     let client_config = Arc::new(client_config);
 
-    let mut server_config = make_server_config(kt);
-    server_config.ticketer = rustls::crypto::ring::Ticketer::new().unwrap();
+    let mut server_config = make_server_config(KeyType::Rsa);
     let storage = Arc::new(ServerStorage::new());
     server_config.session_storage = storage.clone();
+    server_config.allow_tls13_psk_ke_resumption = true;
     let server_config = Arc::new(server_config);
 
-    // full handshake
+    // there is nothing to resume, so this is a full handshake
     let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
-    let (full_c2s, full_s2c) = do_handshake(&mut client, &mut server);
-    assert_eq!(storage.puts(), 0);
-    assert_eq!(storage.gets(), 0);
+    do_handshake(&mut client, &mut server);
     assert_eq!(storage.takes(), 0);
-    assert_eq!(
-        client
-            .peer_certificates()
-            .map(|certs| certs.len()),
-        Some(3)
-    );
 
-    // resumed
-    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
-    let (resume_c2s, resume_s2c) = do_handshake(&mut client, &mut server);
-    assert!(resume_c2s > full_c2s);
-    assert!(resume_s2c < full_s2c);
-    assert_eq!(storage.puts(), 0);
-    assert_eq!(storage.gets(), 0);
-    assert_eq!(storage.takes(), 0);
-    assert_eq!(
-        client
-            .peer_certificates()
-            .map(|certs| certs.len()),
-        Some(3)
+    let client_key_share = Cell::new(false);
+    let server_key_share = Cell::new(false);
+    let (client, server) = make_pair_for_arc_configs(&client_config, &server_config);
+    let (mut client, mut server) = (client.into(), server.into());
+
+    transfer_altered(
+        &mut client,
+        |msg| {
+            if let MessagePayload::Handshake { parsed, .. } = &msg.payload {
+                if let HandshakePayload::ClientHello(ch) = &parsed.payload {
+                    let psk_ke = modes != Tls13PskKeyExchangeModes::PskDheKeOnly;
+                    assert_eq!(ch.psk_mode_offered(PSKKeyExchangeMode::PSK_KE), psk_ke);
+                    assert!(ch.get_psk().is_some());
+                    client_key_share.set(ch.get_keyshare_extension().is_some());
+                }
+            }
+            Altered::InPlace
+        },
+        &mut server,
+    );
+    server.process_new_packets().unwrap();
+    transfer_altered(
+        &mut server,
+        |msg| {
+            if let MessagePayload::Handshake { parsed, .. } = &msg.payload {
+                if let HandshakePayload::ServerHello(sh) = &parsed.payload {
+                    server_key_share.set(sh.get_key_share().is_some());
+                }
+            }
+            Altered::InPlace
+        },
+        &mut client,
     );
+    client.process_new_packets().unwrap();
+    transfer_altered(&mut client, |_| Altered::InPlace, &mut server);
+    server.process_new_packets().unwrap();
 
-    // resumed again
-    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
-    let (resume2_c2s, resume2_s2c) = do_handshake(&mut client, &mut server);
-    assert_eq!(resume_s2c, resume2_s2c);
-    assert_eq!(resume_c2s, resume2_c2s);
-    assert_eq!(storage.puts(), 0);
-    assert_eq!(storage.gets(), 0);
-    assert_eq!(storage.takes(), 0);
+    assert!(!client.is_handshaking());
+    assert!(!server.is_handshaking());
+    assert_eq!(storage.takes(), 1);
+    (client_key_share.get(), server_key_share.get())
+}
+
+#[test]
+fn tls13_resumption_with_psk_ke_only() {
     assert_eq!(
-        client
-            .peer_certificates()
-            .map(|certs| certs.len()),
-        Some(3)
+        resume_with_psk_modes(Tls13PskKeyExchangeModes::PskKeOnly),
+        (false, false)
     );
 }
 
 #[test]
-fn early_data_not_available() {
-    let (mut client, _) = make_pair(KeyType::Rsa);
-    assert!(client.early_data().is_none());
-}
+fn tls13_psk_ke_only_falls_back_to_full_handshake() {
+    let mut client_config =
+        make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS13]);
+    client_config.resumption =
+        Resumption::default().tls13_psk_key_exchange_modes(Tls13PskKeyExchangeModes::PskKeOnly);
+    let client_config = Arc::new(client_config);
+    let psk_ke_server_config = || {
+        let mut server_config = make_server_config(KeyType::Rsa);
+        server_config.allow_tls13_psk_ke_resumption = true;
+        Arc::new(server_config)
+    };
 
-fn early_data_configs() -> (Arc<ClientConfig>, Arc<ServerConfig>) {
-    let kt = KeyType::Rsa;
-    let mut client_config = make_client_config(kt);
-    client_config.enable_early_data = true;
-    client_config.resumption = Resumption::store(Arc::new(ClientStorage::new()));
+    let (mut client, mut server) =
+        make_pair_for_arc_configs(&client_config, &psk_ke_server_config());
+    do_handshake(&mut client, &mut server);
+    assert!(!client.did_hello_retry_request());
 
-    let mut server_config = make_server_config(kt);
-    server_config.max_early_data_size = 1234;
-    (Arc::new(client_config), Arc::new(server_config))
+    // this server cannot decrypt the ticket, so asks for a key share
+    let (mut client, mut server) =
+        make_pair_for_arc_configs(&client_config, &psk_ke_server_config());
+    do_handshake(&mut client, &mut server);
+    assert!(client.did_hello_retry_request());
+    assert_eq!(server.received_resumption_data(), None);
 }
 
 #[test]
-fn early_data_is_available_on_resumption() {
-    let (client_config, server_config) = early_data_configs();
+fn tls13_psk_ke_resumption_is_off_by_default() {
+    let mut client_config =
+        make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS13]);
+    client_config.resumption =
+        Resumption::default().tls13_psk_key_exchange_modes(Tls13PskKeyExchangeModes::PskKeOnly);
+    let client_config = Arc::new(client_config);
 
-    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
-    do_handshake(&mut client, &mut server);
+    let storage = Arc::new(ServerStorage::new());
+    let mut first_server_config = make_server_config(KeyType::Rsa);
+    first_server_config.session_storage = storage.clone();
+    first_server_config.allow_tls13_psk_ke_resumption = true;
 
-    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
-    assert!(client.early_data().is_some());
-    assert_eq!(
-        client
-            .early_data()
-            .unwrap()
-            .bytes_left(),
-        1234
-    );
-    client
-        .early_data()
-        .unwrap()
-        .flush()
-        .unwrap();
-    assert_eq!(
-        client
-            .early_data()
-            .unwrap()
-            .write(b"hello")
-            .unwrap(),
-        5
-    );
+    let (mut client, mut server) =
+        make_pair_for_arc_configs(&client_config, &Arc::new(first_server_config));
     do_handshake(&mut client, &mut server);
 
-    let mut received_early_data = [0u8; 5];
-    assert_eq!(
-        server
-            .early_data()
-            .expect("early_data didn't happen")
-            .read(&mut received_early_data)
-            .expect("early_data failed unexpectedly"),
-        5
-    );
-    assert_eq!(&received_early_data[..], b"hello");
+    // this server can find the session, but resuming it would need psk_ke
+    let mut second_server_config = make_server_config(KeyType::Rsa);
+    second_server_config.session_storage = storage.clone();
+    assert!(!second_server_config.allow_tls13_psk_ke_resumption);
+
+    let (mut client, mut server) =
+        make_pair_for_arc_configs(&client_config, &Arc::new(second_server_config));
+    do_handshake(&mut client, &mut server);
+    assert!(client.did_hello_retry_request());
+    assert_eq!(storage.takes(), 1);
+    assert_eq!(server.received_resumption_data(), None);
 }
 
 #[test]
-fn early_data_not_available_on_server_before_client_hello() {
-    let mut server = ServerConnection::new(Arc::new(make_server_config(KeyType::Rsa))).unwrap();
-    assert!(server.early_data().is_none());
-}
+fn tls13_psk_ke_allowed_still_asks_for_key_share_for_psk_dhe_ke() {
+    let mut client_config = make_client_config_with_kx_groups(
+        KeyType::Rsa,
+        &[
+            rustls::crypto::ring::kx_group::SECP384R1,
+            rustls::crypto::ring::kx_group::X25519,
+        ],
+    );
+    client_config.resumption =
+        Resumption::default().tls13_psk_key_exchange_modes(Tls13PskKeyExchangeModes::Both);
+    let client_config = Arc::new(client_config);
+
+    let storage = Arc::new(ServerStorage::new());
+    let mut first_server_config = make_server_config_with_kx_groups(
+        KeyType::Rsa,
+        &[
+            rustls::crypto::ring::kx_group::SECP384R1,
+            rustls::crypto::ring::kx_group::X25519,
+        ],
+    );
+    first_server_config.session_storage = storage.clone();
+    first_server_config.allow_tls13_psk_ke_resumption = true;
+
+    let (mut client, mut server) =
+        make_pair_for_arc_configs(&client_config, &Arc::new(first_server_config));
+    do_handshake(&mut client, &mut server);
 
-#[test]
-fn early_data_can_be_rejected_by_server() {
-    let (client_config, server_config) = early_data_configs();
+    // the resumed ClientHello has a secp384r1 key share, which this server
+    // cannot use: it asks for another rather than resuming with psk_ke
+    let mut second_server_config =
+        make_server_config_with_kx_groups(KeyType::Rsa, &[rustls::crypto::ring::kx_group::X25519]);
+    second_server_config.session_storage = storage.clone();
+    second_server_config.allow_tls13_psk_ke_resumption = true;
 
-    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    let (mut client, mut server) =
+        make_pair_for_arc_configs(&client_config, &Arc::new(second_server_config));
     do_handshake(&mut client, &mut server);
+    assert!(client.did_hello_retry_request());
+    assert_eq!(storage.takes(), 1);
+    assert!(server
+        .received_resumption_data()
+        .is_some());
+}
 
-    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
-    assert!(client.early_data().is_some());
+#[test]
+fn tls13_resumption_prefers_psk_dhe_ke() {
     assert_eq!(
-        client
-            .early_data()
-            .unwrap()
-            .bytes_left(),
-        1234
+        resume_with_psk_modes(Tls13PskKeyExchangeModes::PskDheKeOnly),
+        (true, true)
     );
-    client
-        .early_data()
-        .unwrap()
-        .flush()
-        .unwrap();
     assert_eq!(
-        client
-            .early_data()
-            .unwrap()
-            .write(b"hello")
-            .unwrap(),
-        5
+        resume_with_psk_modes(Tls13PskKeyExchangeModes::Both),
+        (true, true)
     );
-    server.reject_early_data();
-    do_handshake(&mut client, &mut server);
-
-    assert!(!client.is_early_data_accepted());
 }
 
-#[cfg(feature = "quic")]
-mod test_quic {
-    use super::*;
-    use rustls::quic::{self, ConnectionCommon};
-
-    // Returns the sender's next secrets to use, or the receiver's error.
-    fn step<L: SideData, R: SideData>(
-        send: &mut ConnectionCommon<L>,
-        recv: &mut ConnectionCommon<R>,
-    ) -> Result<Option<quic::KeyChange>, Error> {
-        let mut buf = Vec::new();
-        let change = loop {
-            let prev = buf.len();
-            if let Some(x) = send.write_hs(&mut buf) {
-                break Some(x);
-            }
-            if prev == buf.len() {
-                break None;
-            }
-        };
-        if let Err(e) = recv.read_hs(&buf) {
-            return Err(e);
-        } else {
-            assert_eq!(recv.alert(), None);
-        }
-
-        Ok(change)
-    }
+#[test]
+fn test_client_reports_hello_retry_request() {
+    let kx_groups = &[
+        rustls::crypto::ring::kx_group::X25519,
+        rustls::crypto::ring::kx_group::SECP384R1,
+    ];
 
-    #[test]
-    fn test_quic_handshake() {
-        fn equal_packet_keys(x: &dyn quic::PacketKey, y: &dyn quic::PacketKey) -> bool {
-            // Check that these two sets of keys are equal.
-            let mut buf = [0; 32];
-            let (header, payload_tag) = buf.split_at_mut(8);
-            let (payload, tag_buf) = payload_tag.split_at_mut(8);
-            let tag = x
-                .encrypt_in_place(42, header, payload)
-                .unwrap();
-            tag_buf.copy_from_slice(tag.as_ref());
+    // the client key-shares x25519, which the server accepts
+    let (mut client, mut server) = make_pair_for_configs(
+        make_client_config_with_kx_groups(KeyType::Rsa, kx_groups),
+        make_server_config_with_kx_groups(KeyType::Rsa, kx_groups),
+    );
+    do_handshake(&mut client, &mut server);
+    assert!(!client.did_hello_retry_request());
+
+    // but this server only accepts secp384r1
+    let (mut client, mut server) = make_pair_for_configs(
+        make_client_config_with_kx_groups(KeyType::Rsa, kx_groups),
+        make_server_config_with_kx_groups(
+            KeyType::Rsa,
+            &[rustls::crypto::ring::kx_group::SECP384R1],
+        ),
+    );
+    assert!(!client.did_hello_retry_request());
+    do_handshake(&mut client, &mut server);
+    assert!(client.did_hello_retry_request());
+}
 
-            let result = y.decrypt_in_place(42, header, payload_tag);
-            match result {
-                Ok(payload) => payload == [0; 8],
-                Err(_) => false,
-            }
-        }
+#[test]
+fn test_client_remembers_server_supported_groups_hint() {
+    use rustls::client::ClientSessionStore;
 
-        fn compatible_keys(x: &quic::KeyChange, y: &quic::KeyChange) -> bool {
-            fn keys(kc: &quic::KeyChange) -> &quic::Keys {
-                match kc {
-                    quic::KeyChange::Handshake { keys } => keys,
-                    quic::KeyChange::OneRtt { keys, .. } => keys,
-                }
-            }
+    let kx_groups = &[
+        rustls::crypto::ring::kx_group::X25519,
+        rustls::crypto::ring::kx_group::SECP384R1,
+    ];
+    let store = Arc::new(rustls::client::ClientSessionMemoryCache::new(4));
+    let mut client_config = make_client_config_with_kx_groups(KeyType::Rsa, kx_groups);
+    client_config.resumption = Resumption::store(store.clone());
+    let client_config = Arc::new(client_config);
 
-            let (x, y) = (keys(x), keys(y));
-            equal_packet_keys(x.local.packet.as_ref(), y.remote.packet.as_ref())
-                && equal_packet_keys(x.remote.packet.as_ref(), y.local.packet.as_ref())
-        }
+    // without a hint, the group the server chose is remembered
+    let server_config = Arc::new(make_server_config_with_kx_groups(KeyType::Rsa, kx_groups));
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(
+        store.kx_hint(&server_name("localhost")),
+        Some(rustls::NamedGroup::X25519)
+    );
 
-        let kt = KeyType::Rsa;
-        let mut client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS13]);
-        client_config.enable_early_data = true;
-        let client_config = Arc::new(client_config);
-        let mut server_config = make_server_config_with_versions(kt, &[&rustls::version::TLS13]);
-        server_config.max_early_data_size = 0xffffffff;
-        let server_config = Arc::new(server_config);
-        let client_params = &b"client params"[..];
-        let server_params = &b"server params"[..];
+    // the server accepts x25519, but says it would prefer secp384r1
+    let mut server_config = make_server_config_with_kx_groups(KeyType::Rsa, kx_groups);
+    server_config.supported_groups_hint = vec![
+        rustls::NamedGroup::FFDHE2048,
+        rustls::NamedGroup::secp384r1,
+        rustls::NamedGroup::X25519,
+    ];
+    let (mut client, mut server) =
+        make_pair_for_arc_configs(&client_config, &Arc::new(server_config));
+    do_handshake(&mut client, &mut server);
+    assert!(!client.did_hello_retry_request());
+    assert_eq!(
+        store.kx_hint(&server_name("localhost")),
+        Some(rustls::NamedGroup::secp384r1)
+    );
 
-        // full handshake
-        let mut client = quic::ClientConnection::new(
-            Arc::clone(&client_config),
-            quic::Version::V1,
-            server_name("localhost"),
-            client_params.into(),
-        )
-        .unwrap();
+    // so a server that only accepts secp384r1 gets it first time
+    let server_config = Arc::new(make_server_config_with_kx_groups(
+        KeyType::Rsa,
+        &[rustls::crypto::ring::kx_group::SECP384R1],
+    ));
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake(&mut client, &mut server);
+    assert!(!client.did_hello_retry_request());
+}
 
-        let mut server = quic::ServerConnection::new(
-            Arc::clone(&server_config),
-            quic::Version::V1,
-            server_params.into(),
-        )
-        .unwrap();
+#[cfg(feature = "dangerous_transcript")]
+#[test]
+fn connections_expose_peer_finished_verify_data() {
+    for version in rustls::ALL_VERSIONS {
+        let client_config = make_client_config_with_versions(KeyType::Rsa, &[version]);
+        let (mut client, mut server) =
+            make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+        assert_eq!(client.peer_finished_verify_data(), None);
+        assert_eq!(server.peer_finished_verify_data(), None);
 
-        let client_initial = step(&mut client, &mut server).unwrap();
-        assert!(client_initial.is_none());
-        assert!(client.zero_rtt_keys().is_none());
-        assert_eq!(server.quic_transport_parameters(), Some(client_params));
-        let server_hs = step(&mut server, &mut client)
-            .unwrap()
-            .unwrap();
-        assert!(server.zero_rtt_keys().is_none());
-        let client_hs = step(&mut client, &mut server)
-            .unwrap()
-            .unwrap();
-        assert!(compatible_keys(&server_hs, &client_hs));
-        assert!(client.is_handshaking());
-        let server_1rtt = step(&mut server, &mut client)
-            .unwrap()
-            .unwrap();
-        assert!(!client.is_handshaking());
-        assert_eq!(client.quic_transport_parameters(), Some(server_params));
-        assert!(server.is_handshaking());
-        let client_1rtt = step(&mut client, &mut server)
-            .unwrap()
-            .unwrap();
-        assert!(!server.is_handshaking());
-        assert!(compatible_keys(&server_1rtt, &client_1rtt));
-        assert!(!compatible_keys(&server_hs, &server_1rtt));
-        assert!(step(&mut client, &mut server)
-            .unwrap()
-            .is_none());
-        assert!(step(&mut server, &mut client)
-            .unwrap()
-            .is_none());
+        do_handshake(&mut client, &mut server);
 
-        // 0-RTT handshake
-        let mut client = quic::ClientConnection::new(
-            Arc::clone(&client_config),
-            quic::Version::V1,
-            server_name("localhost"),
-            client_params.into(),
-        )
-        .unwrap();
-        assert!(client
+        let expected_len = match client
             .negotiated_cipher_suite()
-            .is_some());
-
-        let mut server = quic::ServerConnection::new(
-            Arc::clone(&server_config),
-            quic::Version::V1,
-            server_params.into(),
-        )
-        .unwrap();
-
-        step(&mut client, &mut server).unwrap();
-        assert_eq!(client.quic_transport_parameters(), Some(server_params));
-        {
-            let client_early = client.zero_rtt_keys().unwrap();
-            let server_early = server.zero_rtt_keys().unwrap();
-            assert!(equal_packet_keys(
-                client_early.packet.as_ref(),
-                server_early.packet.as_ref()
-            ));
-        }
-        step(&mut server, &mut client)
             .unwrap()
-            .unwrap();
-        step(&mut client, &mut server)
-            .unwrap()
-            .unwrap();
-        step(&mut server, &mut client)
-            .unwrap()
-            .unwrap();
-        assert!(client.is_early_data_accepted());
-
-        // 0-RTT rejection
         {
-            let client_config = (*client_config).clone();
-            let mut client = quic::ClientConnection::new(
-                Arc::new(client_config),
-                quic::Version::V1,
-                server_name("localhost"),
-                client_params.into(),
-            )
+            SupportedCipherSuite::Tls13(suite) => suite.common.hash_provider.output_len(),
+            #[cfg(feature = "tls12")]
+            SupportedCipherSuite::Tls12(_) => 12,
+        };
+        let from_server = client
+            .peer_finished_verify_data()
             .unwrap();
-
-            let mut server = quic::ServerConnection::new(
-                Arc::clone(&server_config),
-                quic::Version::V1,
-                server_params.into(),
-            )
+        let from_client = server
+            .peer_finished_verify_data()
             .unwrap();
-            server.reject_early_data();
+        assert_eq!(from_server.len(), expected_len);
+        assert_eq!(from_client.len(), expected_len);
+        assert_ne!(from_server, from_client);
+    }
+}
 
-            step(&mut client, &mut server).unwrap();
-            assert_eq!(client.quic_transport_parameters(), Some(server_params));
-            assert!(client.zero_rtt_keys().is_some());
-            assert!(server.zero_rtt_keys().is_none());
-            step(&mut server, &mut client)
-                .unwrap()
-                .unwrap();
-            step(&mut client, &mut server)
-                .unwrap()
-                .unwrap();
-            step(&mut server, &mut client)
-                .unwrap()
-                .unwrap();
-            assert!(!client.is_early_data_accepted());
-        }
+#[test]
+fn connections_expose_handshake_hash() {
+    for version in rustls::ALL_VERSIONS {
+        for client_auth in [false, true] {
+            let (client_config, server_config) = match client_auth {
+                false => (
+                    make_client_config_with_versions(KeyType::Rsa, &[version]),
+                    make_server_config(KeyType::Rsa),
+                ),
+                true => (
+                    make_client_config_with_versions_with_auth(KeyType::Rsa, &[version]),
+                    make_server_config_with_mandatory_client_auth(KeyType::Rsa),
+                ),
+            };
+            let client_config = Arc::new(client_config);
+            let server_config = Arc::new(server_config);
 
-        // failed handshake
-        let mut client = quic::ClientConnection::new(
-            client_config,
-            quic::Version::V1,
-            server_name("example.com"),
-            client_params.into(),
-        )
-        .unwrap();
+            let mut hashes = Vec::new();
+            for _resumption in 0..2 {
+                let (mut client, mut server) =
+                    make_pair_for_arc_configs(&client_config, &server_config);
+                assert_eq!(client.handshake_hash(), None);
+                assert_eq!(server.handshake_hash(), None);
+
+                do_handshake(&mut client, &mut server);
+
+                let expected_len = match client
+                    .negotiated_cipher_suite()
+                    .unwrap()
+                {
+                    SupportedCipherSuite::Tls13(suite) => suite.common.hash_provider.output_len(),
+                    #[cfg(feature = "tls12")]
+                    SupportedCipherSuite::Tls12(suite) => suite.common.hash_provider.output_len(),
+                };
+                let hash = client.handshake_hash().unwrap();
+                assert_eq!(hash.len(), expected_len);
+                assert_eq!(Some(hash), server.handshake_hash());
+                hashes.push(hash.to_vec());
+            }
+            assert_ne!(hashes[0], hashes[1]);
+        }
+    }
+}
 
-        let mut server =
-            quic::ServerConnection::new(server_config, quic::Version::V1, server_params.into())
-                .unwrap();
+#[cfg(feature = "dangerous_transcript")]
+#[test]
+fn connections_expose_raw_transcript() {
+    use rustls::{HandshakeType, TranscriptPoint};
 
-        step(&mut client, &mut server).unwrap();
-        step(&mut server, &mut client)
+    fn check_transcripts(client: &ClientConnection, server: &ServerConnection) -> Vec<u8> {
+        let hash_provider = match client
+            .negotiated_cipher_suite()
             .unwrap()
+        {
+            SupportedCipherSuite::Tls13(suite) => suite.common.hash_provider,
+            #[cfg(feature = "tls12")]
+            SupportedCipherSuite::Tls12(suite) => suite.common.hash_provider,
+        };
+
+        let points = [
+            TranscriptPoint::ServerHello,
+            TranscriptPoint::ServerFinished,
+            TranscriptPoint::ClientFinished,
+        ];
+        for point in points {
+            assert_eq!(
+                client.transcript_up_to(point),
+                server.transcript_up_to(point)
+            );
+        }
+
+        let server_hello = client
+            .transcript_up_to(TranscriptPoint::ServerHello)
             .unwrap();
-        assert!(step(&mut server, &mut client).is_err());
+        let server_finished = client
+            .transcript_up_to(TranscriptPoint::ServerFinished)
+            .unwrap();
+        let client_finished = client
+            .transcript_up_to(TranscriptPoint::ClientFinished)
+            .unwrap();
+        assert!(server_finished.starts_with(&server_hello));
+        assert!(client_finished.starts_with(&server_hello));
+
+        // Whichever Finished came last completes the handshake.
+        let complete = std::cmp::max_by_key(server_finished, client_finished, Vec::len);
         assert_eq!(
-            client.alert(),
-            Some(rustls::AlertDescription::BadCertificate)
+            client.handshake_hash(),
+            Some(hash_provider.hash(&complete).as_ref())
         );
+        server_hello
+    }
 
-        // Key updates
+    for version in rustls::ALL_VERSIONS {
+        let client_config = Arc::new(make_client_config_with_versions(KeyType::Rsa, &[version]));
+        let server_config = Arc::new(make_server_config(KeyType::Rsa));
 
-        let (mut client_secrets, mut server_secrets) = match (client_1rtt, server_1rtt) {
-            (quic::KeyChange::OneRtt { next: c, .. }, quic::KeyChange::OneRtt { next: s, .. }) => {
-                (c, s)
+        for _resumption in 0..2 {
+            let (mut client, mut server) =
+                make_pair_for_arc_configs(&client_config, &server_config);
+            assert_eq!(client.transcript_up_to(TranscriptPoint::ServerHello), None);
+
+            do_handshake(&mut client, &mut server);
+            let server_hello = check_transcripts(&client, &server);
+            assert_eq!(server_hello[0], HandshakeType::ClientHello.get_u8());
+        }
+    }
+
+    // After a HelloRetryRequest, the transcript starts with the synthetic
+    // `message_hash` message rather than the first ClientHello.
+    let client_config = make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS13]);
+    let server_config = make_server_config_with_kx_groups(
+        KeyType::Rsa,
+        &[rustls::crypto::ring::kx_group::SECP384R1],
+    );
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+    let server_hello = check_transcripts(&client, &server);
+    assert_eq!(server_hello[0], HandshakeType::MessageHash.get_u8());
+}
+
+#[test]
+fn test_server_hello_retry_request_is_stateful() {
+    let saw_hrr = std::cell::Cell::new(false);
+    let assert_server_retries_without_cookie = |msg: &mut Message| -> Altered {
+        if let MessagePayload::Handshake { parsed, .. } = &mut msg.payload {
+            if let HandshakePayload::HelloRetryRequest(hrr) = &mut parsed.payload {
+                assert_eq!(
+                    hrr.get_requested_key_share_group(),
+                    Some(rustls::NamedGroup::X25519)
+                );
+                assert!(hrr.get_cookie().is_none());
+                saw_hrr.set(true);
             }
-            _ => unreachable!(),
-        };
+        }
+        Altered::InPlace
+    };
 
-        let mut client_next = client_secrets.next_packet_keys();
-        let mut server_next = server_secrets.next_packet_keys();
-        assert!(equal_packet_keys(
-            client_next.local.as_ref(),
-            server_next.remote.as_ref()
-        ));
-        assert!(equal_packet_keys(
-            server_next.local.as_ref(),
-            client_next.remote.as_ref()
-        ));
+    // client prefers a secp384r1 key share, server only accepts x25519
+    let client_config = make_client_config_with_kx_groups(
+        KeyType::Rsa,
+        &[
+            rustls::crypto::ring::kx_group::SECP384R1,
+            rustls::crypto::ring::kx_group::X25519,
+        ],
+    );
+    let server_config =
+        make_server_config_with_kx_groups(KeyType::Rsa, &[rustls::crypto::ring::kx_group::X25519]);
 
-        client_next = client_secrets.next_packet_keys();
-        server_next = server_secrets.next_packet_keys();
-        assert!(equal_packet_keys(
-            client_next.local.as_ref(),
-            server_next.remote.as_ref()
-        ));
-        assert!(equal_packet_keys(
-            server_next.local.as_ref(),
-            client_next.remote.as_ref()
-        ));
-    }
+    let (client, server) = make_pair_for_configs(client_config, server_config);
+    let (mut client, mut server) = (client.into(), server.into());
+    let unaltered = |_: &mut Message| Altered::InPlace;
+    transfer_altered(&mut client, unaltered, &mut server);
+    server.process_new_packets().unwrap();
+    transfer_altered(
+        &mut server,
+        assert_server_retries_without_cookie,
+        &mut client,
+    );
+    assert!(saw_hrr.get());
+    client.process_new_packets().unwrap();
 
-    #[test]
-    fn test_quic_rejects_missing_alpn() {
-        let client_params = &b"client params"[..];
-        let server_params = &b"server params"[..];
+    // the server kept the first ClientHello's state, and finishes the
+    // handshake from the retried ClientHello alone
+    while client.is_handshaking() || server.is_handshaking() {
+        transfer_altered(&mut client, unaltered, &mut server);
+        server.process_new_packets().unwrap();
+        transfer_altered(&mut server, unaltered, &mut client);
+        client.process_new_packets().unwrap();
+    }
+}
 
-        for &kt in ALL_KEY_TYPES.iter() {
-            let client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS13]);
-            let client_config = Arc::new(client_config);
+#[test]
+fn test_client_rejects_hrr_with_varied_session_id() {
+    use rustls::crypto::ring::RING;
+    use rustls::internal::msgs::handshake::SessionId;
+    let different_session_id = SessionId::random(RING).unwrap();
 
-            let mut server_config =
-                make_server_config_with_versions(kt, &[&rustls::version::TLS13]);
-            server_config.alpn_protocols = vec!["foo".into()];
-            let server_config = Arc::new(server_config);
+    let assert_client_sends_hello_with_secp384 = |msg: &mut Message| -> Altered {
+        if let MessagePayload::Handshake { parsed, encoded } = &mut msg.payload {
+            if let HandshakePayload::ClientHello(ch) = &mut parsed.payload {
+                let keyshares = ch
+                    .get_keyshare_extension()
+                    .expect("missing key share extension");
+                assert_eq!(keyshares.len(), 1);
+                assert_eq!(keyshares[0].group, rustls::NamedGroup::secp384r1);
 
-            let mut client = quic::ClientConnection::new(
-                client_config,
-                quic::Version::V1,
-                server_name("localhost"),
-                client_params.into(),
-            )
-            .unwrap();
-            let mut server =
-                quic::ServerConnection::new(server_config, quic::Version::V1, server_params.into())
-                    .unwrap();
+                ch.session_id = different_session_id;
+                *encoded = Payload::new(parsed.get_encoding());
+            }
+        }
+        Altered::InPlace
+    };
 
-            assert_eq!(
-                step(&mut client, &mut server)
-                    .err()
-                    .unwrap(),
-                Error::NoApplicationProtocol
-            );
+    let assert_server_requests_retry_and_echoes_session_id = |msg: &mut Message| -> Altered {
+        if let MessagePayload::Handshake { parsed, .. } = &mut msg.payload {
+            if let HandshakePayload::HelloRetryRequest(hrr) = &mut parsed.payload {
+                let group = hrr.get_requested_key_share_group();
+                assert_eq!(group, Some(rustls::NamedGroup::X25519));
 
-            assert_eq!(
-                server.alert(),
-                Some(rustls::AlertDescription::NoApplicationProtocol)
-            );
+                assert_eq!(hrr.session_id, different_session_id);
+            }
         }
-    }
+        Altered::InPlace
+    };
 
-    #[cfg(feature = "tls12")]
-    #[test]
-    fn test_quic_no_tls13_error() {
-        let mut client_config =
-            make_client_config_with_versions(KeyType::Ed25519, &[&rustls::version::TLS12]);
-        client_config.alpn_protocols = vec!["foo".into()];
-        let client_config = Arc::new(client_config);
+    // client prefers a secp384r1 key share, server only accepts x25519
+    let client_config = make_client_config_with_kx_groups(
+        KeyType::Rsa,
+        &[
+            rustls::crypto::ring::kx_group::SECP384R1,
+            rustls::crypto::ring::kx_group::X25519,
+        ],
+    );
 
-        assert!(quic::ClientConnection::new(
-            client_config,
-            quic::Version::V1,
-            server_name("localhost"),
-            b"client params".to_vec(),
-        )
-        .is_err());
+    let server_config =
+        make_server_config_with_kx_groups(KeyType::Rsa, &[rustls::crypto::ring::kx_group::X25519]);
 
-        let mut server_config =
-            make_server_config_with_versions(KeyType::Ed25519, &[&rustls::version::TLS12]);
-        server_config.alpn_protocols = vec!["foo".into()];
-        let server_config = Arc::new(server_config);
+    let (client, server) = make_pair_for_configs(client_config, server_config);
+    let (mut client, mut server) = (client.into(), server.into());
+    transfer_altered(
+        &mut client,
+        assert_client_sends_hello_with_secp384,
+        &mut server,
+    );
+    server.process_new_packets().unwrap();
+    transfer_altered(
+        &mut server,
+        assert_server_requests_retry_and_echoes_session_id,
+        &mut client,
+    );
+    assert_eq!(
+        client.process_new_packets(),
+        Err(Error::PeerMisbehaved(
+            PeerMisbehaved::IllegalHelloRetryRequestWithWrongSessionId
+        ))
+    );
+}
+
+#[cfg(feature = "tls12")]
+#[test]
+fn test_client_attempts_to_use_unsupported_kx_group() {
+    // common to both client configs
+    let shared_storage = Arc::new(ClientStorage::new());
+
+    // first, client sends a x25519 and server agrees. x25519 is inserted
+    //   into kx group cache.
+    let mut client_config_1 =
+        make_client_config_with_kx_groups(KeyType::Rsa, &[rustls::crypto::ring::kx_group::X25519]);
+    client_config_1.resumption = Resumption::store(shared_storage.clone());
+
+    // second, client only supports secp-384 and so kx group cache
+    //   contains an unusable value.
+    let mut client_config_2 = make_client_config_with_kx_groups(
+        KeyType::Rsa,
+        &[rustls::crypto::ring::kx_group::SECP384R1],
+    );
+    client_config_2.resumption = Resumption::store(shared_storage.clone());
+
+    let server_config = make_server_config(KeyType::Rsa);
+
+    // first handshake
+    let (mut client_1, mut server) = make_pair_for_configs(client_config_1, server_config.clone());
+    do_handshake_until_error(&mut client_1, &mut server).unwrap();
+
+    let ops = shared_storage.ops();
+    println!("storage {:#?}", ops);
+    assert_eq!(ops.len(), 9);
+    assert!(matches!(
+        ops[3],
+        ClientStorageOp::SetKxHint(_, rustls::NamedGroup::X25519)
+    ));
+
+    // second handshake
+    let (mut client_2, mut server) = make_pair_for_configs(client_config_2, server_config);
+    do_handshake_until_error(&mut client_2, &mut server).unwrap();
+
+    let ops = shared_storage.ops();
+    println!("storage {:?} {:#?}", ops.len(), ops);
+    assert_eq!(ops.len(), 17);
+    assert!(matches!(ops[9], ClientStorageOp::TakeTls13Ticket(_, true)));
+    assert!(matches!(
+        ops[10],
+        ClientStorageOp::GetKxHint(_, Some(rustls::NamedGroup::X25519))
+    ));
+    assert!(matches!(
+        ops[11],
+        ClientStorageOp::SetKxHint(_, rustls::NamedGroup::secp384r1)
+    ));
+}
 
-        assert!(quic::ServerConnection::new(
-            server_config,
-            quic::Version::V1,
-            b"server params".to_vec(),
-        )
-        .is_err());
-    }
+#[cfg(feature = "tls12")]
+#[test]
+fn test_tls13_client_resumption_does_not_reuse_tickets() {
+    let shared_storage = Arc::new(ClientStorage::new());
 
-    #[test]
-    fn test_quic_invalid_early_data_size() {
-        let mut server_config =
-            make_server_config_with_versions(KeyType::Ed25519, &[&rustls::version::TLS13]);
-        server_config.alpn_protocols = vec!["foo".into()];
+    let mut client_config = make_client_config(KeyType::Rsa);
+    client_config.resumption = Resumption::store(shared_storage.clone());
+    let client_config = Arc::new(client_config);
 
-        let cases = [
-            (None, true),
-            (Some(0u32), true),
-            (Some(5), false),
-            (Some(0xffff_ffff), true),
-        ];
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.send_tls13_tickets = 5;
+    let server_config = Arc::new(server_config);
 
-        for &(size, ok) in cases.iter() {
-            println!("early data size case: {:?}", size);
-            if let Some(new) = size {
-                server_config.max_early_data_size = new;
-            }
+    // first handshake: client obtains 5 tickets from server.
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    do_handshake_until_error(&mut client, &mut server).unwrap();
 
-            let wrapped = Arc::new(server_config.clone());
-            assert_eq!(
-                quic::ServerConnection::new(wrapped, quic::Version::V1, b"server params".to_vec(),)
-                    .is_ok(),
-                ok
-            );
-        }
-    }
+    let ops = shared_storage.ops_and_reset();
+    println!("storage {:#?}", ops);
+    assert_eq!(ops.len(), 10);
+    assert!(matches!(ops[5], ClientStorageOp::InsertTls13Ticket(_)));
+    assert!(matches!(ops[6], ClientStorageOp::InsertTls13Ticket(_)));
+    assert!(matches!(ops[7], ClientStorageOp::InsertTls13Ticket(_)));
+    assert!(matches!(ops[8], ClientStorageOp::InsertTls13Ticket(_)));
+    assert!(matches!(ops[9], ClientStorageOp::InsertTls13Ticket(_)));
 
-    #[test]
-    fn test_quic_server_no_params_received() {
-        let server_config =
-            make_server_config_with_versions(KeyType::Ed25519, &[&rustls::version::TLS13]);
-        let server_config = Arc::new(server_config);
+    // 5 subsequent handshakes: all are resumptions
 
-        let mut server = quic::ServerConnection::new(
-            server_config,
-            quic::Version::V1,
-            b"server params".to_vec(),
-        )
-        .unwrap();
+    // Note: we don't do complete the handshakes, because that means
+    // we get five additional tickets per connection which is unhelpful
+    // in this test.  It also acts to record a "Happy Eyeballs"-type use
+    // case, where a client speculatively makes many connection attempts
+    // in parallel without knowledge of which will work due to underlying
+    // connectivity uncertainty.
+    for _ in 0..5 {
+        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+        transfer(&mut client, &mut server);
+        server.process_new_packets().unwrap();
 
-        use rustls::crypto::ring::RING;
-        use rustls::internal::msgs::base::PayloadU16;
-        use rustls::internal::msgs::enums::{Compression, NamedGroup};
-        use rustls::internal::msgs::handshake::{
-            ClientHelloPayload, HandshakeMessagePayload, KeyShareEntry, Random, SessionId,
-        };
-        use rustls::{CipherSuite, HandshakeType, SignatureScheme};
+        let ops = shared_storage.ops_and_reset();
+        assert!(matches!(ops[0], ClientStorageOp::TakeTls13Ticket(_, true)));
+    }
 
-        let mut random = [0; 32];
-        RING.fill_random(&mut random).unwrap();
-        let random = Random::from(random);
+    // 6th subsequent handshake: cannot be resumed; we ran out of tickets
+    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
 
-        let rng = ring::rand::SystemRandom::new();
-        let kx = ring::agreement::EphemeralPrivateKey::generate(&ring::agreement::X25519, &rng)
-            .unwrap()
-            .compute_public_key()
-            .unwrap();
+    let ops = shared_storage.ops_and_reset();
+    println!("last {:?}", ops);
+    assert!(matches!(ops[0], ClientStorageOp::TakeTls13Ticket(_, false)));
+}
 
-        let client_hello = MessagePayload::handshake(HandshakeMessagePayload {
-            typ: HandshakeType::ClientHello,
-            payload: HandshakePayload::ClientHello(ClientHelloPayload {
-                client_version: ProtocolVersion::TLSv1_3,
-                random,
-                session_id: SessionId::random(RING).unwrap(),
-                cipher_suites: vec![CipherSuite::TLS13_AES_128_GCM_SHA256],
-                compression_methods: vec![Compression::Null],
-                extensions: vec![
-                    ClientExtension::SupportedVersions(vec![ProtocolVersion::TLSv1_3]),
-                    ClientExtension::NamedGroups(vec![NamedGroup::X25519]),
-                    ClientExtension::SignatureAlgorithms(vec![SignatureScheme::ED25519]),
-                    ClientExtension::KeyShare(vec![KeyShareEntry {
-                        group: NamedGroup::X25519,
-                        payload: PayloadU16::new(kx.as_ref().to_vec()),
-                    }]),
-                ],
-            }),
-        });
+#[test]
+fn test_client_mtu_reduction() {
+    struct CollectWrites {
+        writevs: Vec<Vec<usize>>,
+    }
 
-        let mut buf = Vec::with_capacity(512);
-        client_hello.encode(&mut buf);
-        assert_eq!(
-            server.read_hs(buf.as_slice()).err(),
-            Some(Error::PeerMisbehaved(
-                PeerMisbehaved::MissingQuicTransportParameters
-            ))
-        );
+    impl io::Write for CollectWrites {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            panic!()
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            panic!()
+        }
+        fn write_vectored(&mut self, b: &[io::IoSlice<'_>]) -> io::Result<usize> {
+            let writes = b
+                .iter()
+                .map(|slice| slice.len())
+                .collect::<Vec<usize>>();
+            let len = writes.iter().sum();
+            self.writevs.push(writes);
+            Ok(len)
+        }
     }
 
-    #[test]
-    fn test_quic_server_no_tls12() {
-        let mut server_config =
-            make_server_config_with_versions(KeyType::Ed25519, &[&rustls::version::TLS13]);
-        server_config.alpn_protocols = vec!["foo".into()];
-        let server_config = Arc::new(server_config);
+    fn collect_write_lengths(client: &mut ClientConnection) -> Vec<usize> {
+        let mut collector = CollectWrites { writevs: vec![] };
 
-        use rustls::crypto::ring::RING;
-        use rustls::internal::msgs::base::PayloadU16;
-        use rustls::internal::msgs::enums::{Compression, NamedGroup};
-        use rustls::internal::msgs::handshake::{
-            ClientHelloPayload, HandshakeMessagePayload, KeyShareEntry, Random, SessionId,
-        };
-        use rustls::{CipherSuite, HandshakeType, SignatureScheme};
+        client
+            .write_tls(&mut collector)
+            .unwrap();
+        assert_eq!(collector.writevs.len(), 1);
+        collector.writevs[0].clone()
+    }
 
-        let mut random = [0; 32];
-        RING.fill_random(&mut random).unwrap();
-        let random = Random::from(random);
+    for kt in ALL_KEY_TYPES.iter() {
+        let mut client_config = make_client_config(*kt);
+        client_config.max_fragment_size = Some(64);
+        let mut client =
+            ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
+        let writes = collect_write_lengths(&mut client);
+        println!("writes at mtu=64: {:?}", writes);
+        assert!(writes.iter().all(|x| *x <= 64));
+        assert!(writes.len() > 1);
+    }
+}
 
-        let rng = ring::rand::SystemRandom::new();
-        let kx = ring::agreement::EphemeralPrivateKey::generate(&ring::agreement::X25519, &rng)
-            .unwrap()
-            .compute_public_key()
-            .unwrap();
+#[test]
+fn test_server_mtu_reduction() {
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.max_fragment_size = Some(64);
+    server_config.send_half_rtt_data = true;
+    let (mut client, mut server) =
+        make_pair_for_configs(make_client_config(KeyType::Rsa), server_config);
 
-        let mut server = quic::ServerConnection::new(
-            server_config,
-            quic::Version::V1,
-            b"server params".to_vec(),
-        )
+    let big_data = [0u8; 2048];
+    server
+        .writer()
+        .write_all(&big_data)
         .unwrap();
 
-        let client_hello = MessagePayload::handshake(HandshakeMessagePayload {
-            typ: HandshakeType::ClientHello,
-            payload: HandshakePayload::ClientHello(ClientHelloPayload {
-                client_version: ProtocolVersion::TLSv1_2,
-                random,
-                session_id: SessionId::random(RING).unwrap(),
-                cipher_suites: vec![CipherSuite::TLS13_AES_128_GCM_SHA256],
-                compression_methods: vec![Compression::Null],
-                extensions: vec![
-                    ClientExtension::NamedGroups(vec![NamedGroup::X25519]),
-                    ClientExtension::SignatureAlgorithms(vec![SignatureScheme::ED25519]),
-                    ClientExtension::KeyShare(vec![KeyShareEntry {
-                        group: NamedGroup::X25519,
-                        payload: PayloadU16::new(kx.as_ref().to_vec()),
-                    }]),
-                ],
-            }),
-        });
+    let encryption_overhead = 20; // FIXME: see issue #991
 
-        let mut buf = Vec::with_capacity(512);
-        client_hello.encode(&mut buf);
-        assert_eq!(
-            server.read_hs(buf.as_slice()).err(),
-            Some(Error::PeerIncompatible(
-                PeerIncompatible::SupportedVersionsExtensionRequired
-            )),
-        );
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    {
+        let mut pipe = OtherSession::new(&mut client);
+        server.write_tls(&mut pipe).unwrap();
+
+        assert_eq!(pipe.writevs.len(), 1);
+        assert!(pipe.writevs[0]
+            .iter()
+            .all(|x| *x <= 64 + encryption_overhead));
+    }
+
+    client.process_new_packets().unwrap();
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    {
+        let mut pipe = OtherSession::new(&mut client);
+        server.write_tls(&mut pipe).unwrap();
+        assert_eq!(pipe.writevs.len(), 1);
+        assert!(pipe.writevs[0]
+            .iter()
+            .all(|x| *x <= 64 + encryption_overhead));
     }
 
-    #[test]
-    fn packet_key_api() {
-        use rustls::cipher_suite::TLS13_AES_128_GCM_SHA256;
-        use rustls::quic::{Keys, Version};
-        use rustls::Side;
+    client.process_new_packets().unwrap();
+    check_read(&mut client.reader(), &big_data);
+}
+
+#[test]
+fn records_fit_within_mtu() {
+    const MTU: usize = 200;
 
-        // Test vectors: https://www.rfc-editor.org/rfc/rfc9001.html#name-client-initial
-        const CONNECTION_ID: &[u8] = &[0x83, 0x94, 0xc8, 0xf0, 0x3e, 0x51, 0x57, 0x08];
-        const PACKET_NUMBER: u64 = 2;
-        const PLAIN_HEADER: &[u8] = &[
-            0xc3, 0x00, 0x00, 0x00, 0x01, 0x08, 0x83, 0x94, 0xc8, 0xf0, 0x3e, 0x51, 0x57, 0x08,
-            0x00, 0x00, 0x44, 0x9e, 0x00, 0x00, 0x00, 0x02,
-        ];
+    // returns the length of the largest record sent
+    fn send_all<C, S>(
+        from: &mut C,
+        to: &mut impl DerefMut<Target = ConnectionCommon<impl SideData>>,
+    ) -> usize
+    where
+        C: DerefMut + Deref<Target = ConnectionCommon<S>>,
+        S: SideData,
+    {
+        let mut largest = 0;
+        while from.wants_write() {
+            let mut pipe = OtherSession::new(to);
+            from.write_tls(&mut pipe).unwrap();
+            for len in pipe.writevs.iter().flatten() {
+                assert!(*len <= MTU, "record of {} bytes exceeds mtu", len);
+                largest = largest.max(*len);
+            }
+        }
+        largest
+    }
 
-        const PAYLOAD: &[u8] = &[
-            0x06, 0x00, 0x40, 0xf1, 0x01, 0x00, 0x00, 0xed, 0x03, 0x03, 0xeb, 0xf8, 0xfa, 0x56,
-            0xf1, 0x29, 0x39, 0xb9, 0x58, 0x4a, 0x38, 0x96, 0x47, 0x2e, 0xc4, 0x0b, 0xb8, 0x63,
-            0xcf, 0xd3, 0xe8, 0x68, 0x04, 0xfe, 0x3a, 0x47, 0xf0, 0x6a, 0x2b, 0x69, 0x48, 0x4c,
-            0x00, 0x00, 0x04, 0x13, 0x01, 0x13, 0x02, 0x01, 0x00, 0x00, 0xc0, 0x00, 0x00, 0x00,
-            0x10, 0x00, 0x0e, 0x00, 0x00, 0x0b, 0x65, 0x78, 0x61, 0x6d, 0x70, 0x6c, 0x65, 0x2e,
-            0x63, 0x6f, 0x6d, 0xff, 0x01, 0x00, 0x01, 0x00, 0x00, 0x0a, 0x00, 0x08, 0x00, 0x06,
-            0x00, 0x1d, 0x00, 0x17, 0x00, 0x18, 0x00, 0x10, 0x00, 0x07, 0x00, 0x05, 0x04, 0x61,
-            0x6c, 0x70, 0x6e, 0x00, 0x05, 0x00, 0x05, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x33,
-            0x00, 0x26, 0x00, 0x24, 0x00, 0x1d, 0x00, 0x20, 0x93, 0x70, 0xb2, 0xc9, 0xca, 0xa4,
-            0x7f, 0xba, 0xba, 0xf4, 0x55, 0x9f, 0xed, 0xba, 0x75, 0x3d, 0xe1, 0x71, 0xfa, 0x71,
-            0xf5, 0x0f, 0x1c, 0xe1, 0x5d, 0x43, 0xe9, 0x94, 0xec, 0x74, 0xd7, 0x48, 0x00, 0x2b,
-            0x00, 0x03, 0x02, 0x03, 0x04, 0x00, 0x0d, 0x00, 0x10, 0x00, 0x0e, 0x04, 0x03, 0x05,
-            0x03, 0x06, 0x03, 0x02, 0x03, 0x08, 0x04, 0x08, 0x05, 0x08, 0x06, 0x00, 0x2d, 0x00,
-            0x02, 0x01, 0x01, 0x00, 0x1c, 0x00, 0x02, 0x40, 0x01, 0x00, 0x39, 0x00, 0x32, 0x04,
-            0x08, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x05, 0x04, 0x80, 0x00, 0xff,
-            0xff, 0x07, 0x04, 0x80, 0x00, 0xff, 0xff, 0x08, 0x01, 0x10, 0x01, 0x04, 0x80, 0x00,
-            0x75, 0x30, 0x09, 0x01, 0x10, 0x0f, 0x08, 0x83, 0x94, 0xc8, 0xf0, 0x3e, 0x51, 0x57,
-            0x08, 0x06, 0x04, 0x80, 0x00, 0xff, 0xff,
-        ];
+    for version in rustls::ALL_VERSIONS {
+        let client_config = make_client_config_with_versions(KeyType::Rsa, &[version]);
+        let mut server_config = make_server_config(KeyType::Rsa);
+        server_config.send_half_rtt_data = true;
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
 
-        let client_keys = Keys::initial(
-            Version::V1,
-            TLS13_AES_128_GCM_SHA256
-                .tls13()
-                .unwrap(),
-            CONNECTION_ID,
-            Side::Client,
-        );
-        assert_eq!(
-            client_keys
-                .local
-                .packet
-                .confidentiality_limit(),
-            2u64.pow(23)
-        );
-        assert_eq!(
-            client_keys
-                .local
-                .packet
-                .integrity_limit(),
-            2u64.pow(52)
-        );
-        assert_eq!(client_keys.local.packet.tag_len(), 16);
+        // the ClientHello was queued before the limit could be set
+        transfer(&mut client, &mut server);
 
-        let mut buf = Vec::new();
-        buf.extend(PLAIN_HEADER);
-        buf.extend(PAYLOAD);
-        let header_len = PLAIN_HEADER.len();
-        let tag_len = client_keys.local.packet.tag_len();
-        let padding_len = 1200 - header_len - PAYLOAD.len() - tag_len;
-        buf.extend(std::iter::repeat(0).take(padding_len));
-        let (header, payload) = buf.split_at_mut(header_len);
-        let tag = client_keys
-            .local
-            .packet
-            .encrypt_in_place(PACKET_NUMBER, header, payload)
+        client
+            .set_record_plaintext_limit_for_mtu(MTU)
             .unwrap();
-
-        let sample_len = client_keys.local.header.sample_len();
-        let sample = &payload[..sample_len];
-        let (first, rest) = header.split_at_mut(1);
-        client_keys
-            .local
-            .header
-            .encrypt_in_place(sample, &mut first[0], &mut rest[17..21])
+        server
+            .set_record_plaintext_limit_for_mtu(MTU)
             .unwrap();
-        buf.extend_from_slice(tag.as_ref());
-
-        const PROTECTED: &[u8] = &[
-            0xc0, 0x00, 0x00, 0x00, 0x01, 0x08, 0x83, 0x94, 0xc8, 0xf0, 0x3e, 0x51, 0x57, 0x08,
-            0x00, 0x00, 0x44, 0x9e, 0x7b, 0x9a, 0xec, 0x34, 0xd1, 0xb1, 0xc9, 0x8d, 0xd7, 0x68,
-            0x9f, 0xb8, 0xec, 0x11, 0xd2, 0x42, 0xb1, 0x23, 0xdc, 0x9b, 0xd8, 0xba, 0xb9, 0x36,
-            0xb4, 0x7d, 0x92, 0xec, 0x35, 0x6c, 0x0b, 0xab, 0x7d, 0xf5, 0x97, 0x6d, 0x27, 0xcd,
-            0x44, 0x9f, 0x63, 0x30, 0x00, 0x99, 0xf3, 0x99, 0x1c, 0x26, 0x0e, 0xc4, 0xc6, 0x0d,
-            0x17, 0xb3, 0x1f, 0x84, 0x29, 0x15, 0x7b, 0xb3, 0x5a, 0x12, 0x82, 0xa6, 0x43, 0xa8,
-            0xd2, 0x26, 0x2c, 0xad, 0x67, 0x50, 0x0c, 0xad, 0xb8, 0xe7, 0x37, 0x8c, 0x8e, 0xb7,
-            0x53, 0x9e, 0xc4, 0xd4, 0x90, 0x5f, 0xed, 0x1b, 0xee, 0x1f, 0xc8, 0xaa, 0xfb, 0xa1,
-            0x7c, 0x75, 0x0e, 0x2c, 0x7a, 0xce, 0x01, 0xe6, 0x00, 0x5f, 0x80, 0xfc, 0xb7, 0xdf,
-            0x62, 0x12, 0x30, 0xc8, 0x37, 0x11, 0xb3, 0x93, 0x43, 0xfa, 0x02, 0x8c, 0xea, 0x7f,
-            0x7f, 0xb5, 0xff, 0x89, 0xea, 0xc2, 0x30, 0x82, 0x49, 0xa0, 0x22, 0x52, 0x15, 0x5e,
-            0x23, 0x47, 0xb6, 0x3d, 0x58, 0xc5, 0x45, 0x7a, 0xfd, 0x84, 0xd0, 0x5d, 0xff, 0xfd,
-            0xb2, 0x03, 0x92, 0x84, 0x4a, 0xe8, 0x12, 0x15, 0x46, 0x82, 0xe9, 0xcf, 0x01, 0x2f,
-            0x90, 0x21, 0xa6, 0xf0, 0xbe, 0x17, 0xdd, 0xd0, 0xc2, 0x08, 0x4d, 0xce, 0x25, 0xff,
-            0x9b, 0x06, 0xcd, 0xe5, 0x35, 0xd0, 0xf9, 0x20, 0xa2, 0xdb, 0x1b, 0xf3, 0x62, 0xc2,
-            0x3e, 0x59, 0x6d, 0x11, 0xa4, 0xf5, 0xa6, 0xcf, 0x39, 0x48, 0x83, 0x8a, 0x3a, 0xec,
-            0x4e, 0x15, 0xda, 0xf8, 0x50, 0x0a, 0x6e, 0xf6, 0x9e, 0xc4, 0xe3, 0xfe, 0xb6, 0xb1,
-            0xd9, 0x8e, 0x61, 0x0a, 0xc8, 0xb7, 0xec, 0x3f, 0xaf, 0x6a, 0xd7, 0x60, 0xb7, 0xba,
-            0xd1, 0xdb, 0x4b, 0xa3, 0x48, 0x5e, 0x8a, 0x94, 0xdc, 0x25, 0x0a, 0xe3, 0xfd, 0xb4,
-            0x1e, 0xd1, 0x5f, 0xb6, 0xa8, 0xe5, 0xeb, 0xa0, 0xfc, 0x3d, 0xd6, 0x0b, 0xc8, 0xe3,
-            0x0c, 0x5c, 0x42, 0x87, 0xe5, 0x38, 0x05, 0xdb, 0x05, 0x9a, 0xe0, 0x64, 0x8d, 0xb2,
-            0xf6, 0x42, 0x64, 0xed, 0x5e, 0x39, 0xbe, 0x2e, 0x20, 0xd8, 0x2d, 0xf5, 0x66, 0xda,
-            0x8d, 0xd5, 0x99, 0x8c, 0xca, 0xbd, 0xae, 0x05, 0x30, 0x60, 0xae, 0x6c, 0x7b, 0x43,
-            0x78, 0xe8, 0x46, 0xd2, 0x9f, 0x37, 0xed, 0x7b, 0x4e, 0xa9, 0xec, 0x5d, 0x82, 0xe7,
-            0x96, 0x1b, 0x7f, 0x25, 0xa9, 0x32, 0x38, 0x51, 0xf6, 0x81, 0xd5, 0x82, 0x36, 0x3a,
-            0xa5, 0xf8, 0x99, 0x37, 0xf5, 0xa6, 0x72, 0x58, 0xbf, 0x63, 0xad, 0x6f, 0x1a, 0x0b,
-            0x1d, 0x96, 0xdb, 0xd4, 0xfa, 0xdd, 0xfc, 0xef, 0xc5, 0x26, 0x6b, 0xa6, 0x61, 0x17,
-            0x22, 0x39, 0x5c, 0x90, 0x65, 0x56, 0xbe, 0x52, 0xaf, 0xe3, 0xf5, 0x65, 0x63, 0x6a,
-            0xd1, 0xb1, 0x7d, 0x50, 0x8b, 0x73, 0xd8, 0x74, 0x3e, 0xeb, 0x52, 0x4b, 0xe2, 0x2b,
-            0x3d, 0xcb, 0xc2, 0xc7, 0x46, 0x8d, 0x54, 0x11, 0x9c, 0x74, 0x68, 0x44, 0x9a, 0x13,
-            0xd8, 0xe3, 0xb9, 0x58, 0x11, 0xa1, 0x98, 0xf3, 0x49, 0x1d, 0xe3, 0xe7, 0xfe, 0x94,
-            0x2b, 0x33, 0x04, 0x07, 0xab, 0xf8, 0x2a, 0x4e, 0xd7, 0xc1, 0xb3, 0x11, 0x66, 0x3a,
-            0xc6, 0x98, 0x90, 0xf4, 0x15, 0x70, 0x15, 0x85, 0x3d, 0x91, 0xe9, 0x23, 0x03, 0x7c,
-            0x22, 0x7a, 0x33, 0xcd, 0xd5, 0xec, 0x28, 0x1c, 0xa3, 0xf7, 0x9c, 0x44, 0x54, 0x6b,
-            0x9d, 0x90, 0xca, 0x00, 0xf0, 0x64, 0xc9, 0x9e, 0x3d, 0xd9, 0x79, 0x11, 0xd3, 0x9f,
-            0xe9, 0xc5, 0xd0, 0xb2, 0x3a, 0x22, 0x9a, 0x23, 0x4c, 0xb3, 0x61, 0x86, 0xc4, 0x81,
-            0x9e, 0x8b, 0x9c, 0x59, 0x27, 0x72, 0x66, 0x32, 0x29, 0x1d, 0x6a, 0x41, 0x82, 0x11,
-            0xcc, 0x29, 0x62, 0xe2, 0x0f, 0xe4, 0x7f, 0xeb, 0x3e, 0xdf, 0x33, 0x0f, 0x2c, 0x60,
-            0x3a, 0x9d, 0x48, 0xc0, 0xfc, 0xb5, 0x69, 0x9d, 0xbf, 0xe5, 0x89, 0x64, 0x25, 0xc5,
-            0xba, 0xc4, 0xae, 0xe8, 0x2e, 0x57, 0xa8, 0x5a, 0xaf, 0x4e, 0x25, 0x13, 0xe4, 0xf0,
-            0x57, 0x96, 0xb0, 0x7b, 0xa2, 0xee, 0x47, 0xd8, 0x05, 0x06, 0xf8, 0xd2, 0xc2, 0x5e,
-            0x50, 0xfd, 0x14, 0xde, 0x71, 0xe6, 0xc4, 0x18, 0x55, 0x93, 0x02, 0xf9, 0x39, 0xb0,
-            0xe1, 0xab, 0xd5, 0x76, 0xf2, 0x79, 0xc4, 0xb2, 0xe0, 0xfe, 0xb8, 0x5c, 0x1f, 0x28,
-            0xff, 0x18, 0xf5, 0x88, 0x91, 0xff, 0xef, 0x13, 0x2e, 0xef, 0x2f, 0xa0, 0x93, 0x46,
-            0xae, 0xe3, 0x3c, 0x28, 0xeb, 0x13, 0x0f, 0xf2, 0x8f, 0x5b, 0x76, 0x69, 0x53, 0x33,
-            0x41, 0x13, 0x21, 0x19, 0x96, 0xd2, 0x00, 0x11, 0xa1, 0x98, 0xe3, 0xfc, 0x43, 0x3f,
-            0x9f, 0x25, 0x41, 0x01, 0x0a, 0xe1, 0x7c, 0x1b, 0xf2, 0x02, 0x58, 0x0f, 0x60, 0x47,
-            0x47, 0x2f, 0xb3, 0x68, 0x57, 0xfe, 0x84, 0x3b, 0x19, 0xf5, 0x98, 0x40, 0x09, 0xdd,
-            0xc3, 0x24, 0x04, 0x4e, 0x84, 0x7a, 0x4f, 0x4a, 0x0a, 0xb3, 0x4f, 0x71, 0x95, 0x95,
-            0xde, 0x37, 0x25, 0x2d, 0x62, 0x35, 0x36, 0x5e, 0x9b, 0x84, 0x39, 0x2b, 0x06, 0x10,
-            0x85, 0x34, 0x9d, 0x73, 0x20, 0x3a, 0x4a, 0x13, 0xe9, 0x6f, 0x54, 0x32, 0xec, 0x0f,
-            0xd4, 0xa1, 0xee, 0x65, 0xac, 0xcd, 0xd5, 0xe3, 0x90, 0x4d, 0xf5, 0x4c, 0x1d, 0xa5,
-            0x10, 0xb0, 0xff, 0x20, 0xdc, 0xc0, 0xc7, 0x7f, 0xcb, 0x2c, 0x0e, 0x0e, 0xb6, 0x05,
-            0xcb, 0x05, 0x04, 0xdb, 0x87, 0x63, 0x2c, 0xf3, 0xd8, 0xb4, 0xda, 0xe6, 0xe7, 0x05,
-            0x76, 0x9d, 0x1d, 0xe3, 0x54, 0x27, 0x01, 0x23, 0xcb, 0x11, 0x45, 0x0e, 0xfc, 0x60,
-            0xac, 0x47, 0x68, 0x3d, 0x7b, 0x8d, 0x0f, 0x81, 0x13, 0x65, 0x56, 0x5f, 0xd9, 0x8c,
-            0x4c, 0x8e, 0xb9, 0x36, 0xbc, 0xab, 0x8d, 0x06, 0x9f, 0xc3, 0x3b, 0xd8, 0x01, 0xb0,
-            0x3a, 0xde, 0xa2, 0xe1, 0xfb, 0xc5, 0xaa, 0x46, 0x3d, 0x08, 0xca, 0x19, 0x89, 0x6d,
-            0x2b, 0xf5, 0x9a, 0x07, 0x1b, 0x85, 0x1e, 0x6c, 0x23, 0x90, 0x52, 0x17, 0x2f, 0x29,
-            0x6b, 0xfb, 0x5e, 0x72, 0x40, 0x47, 0x90, 0xa2, 0x18, 0x10, 0x14, 0xf3, 0xb9, 0x4a,
-            0x4e, 0x97, 0xd1, 0x17, 0xb4, 0x38, 0x13, 0x03, 0x68, 0xcc, 0x39, 0xdb, 0xb2, 0xd1,
-            0x98, 0x06, 0x5a, 0xe3, 0x98, 0x65, 0x47, 0x92, 0x6c, 0xd2, 0x16, 0x2f, 0x40, 0xa2,
-            0x9f, 0x0c, 0x3c, 0x87, 0x45, 0xc0, 0xf5, 0x0f, 0xba, 0x38, 0x52, 0xe5, 0x66, 0xd4,
-            0x45, 0x75, 0xc2, 0x9d, 0x39, 0xa0, 0x3f, 0x0c, 0xda, 0x72, 0x19, 0x84, 0xb6, 0xf4,
-            0x40, 0x59, 0x1f, 0x35, 0x5e, 0x12, 0xd4, 0x39, 0xff, 0x15, 0x0a, 0xab, 0x76, 0x13,
-            0x49, 0x9d, 0xbd, 0x49, 0xad, 0xab, 0xc8, 0x67, 0x6e, 0xef, 0x02, 0x3b, 0x15, 0xb6,
-            0x5b, 0xfc, 0x5c, 0xa0, 0x69, 0x48, 0x10, 0x9f, 0x23, 0xf3, 0x50, 0xdb, 0x82, 0x12,
-            0x35, 0x35, 0xeb, 0x8a, 0x74, 0x33, 0xbd, 0xab, 0xcb, 0x90, 0x92, 0x71, 0xa6, 0xec,
-            0xbc, 0xb5, 0x8b, 0x93, 0x6a, 0x88, 0xcd, 0x4e, 0x8f, 0x2e, 0x6f, 0xf5, 0x80, 0x01,
-            0x75, 0xf1, 0x13, 0x25, 0x3d, 0x8f, 0xa9, 0xca, 0x88, 0x85, 0xc2, 0xf5, 0x52, 0xe6,
-            0x57, 0xdc, 0x60, 0x3f, 0x25, 0x2e, 0x1a, 0x8e, 0x30, 0x8f, 0x76, 0xf0, 0xbe, 0x79,
-            0xe2, 0xfb, 0x8f, 0x5d, 0x5f, 0xbb, 0xe2, 0xe3, 0x0e, 0xca, 0xdd, 0x22, 0x07, 0x23,
-            0xc8, 0xc0, 0xae, 0xa8, 0x07, 0x8c, 0xdf, 0xcb, 0x38, 0x68, 0x26, 0x3f, 0xf8, 0xf0,
-            0x94, 0x00, 0x54, 0xda, 0x48, 0x78, 0x18, 0x93, 0xa7, 0xe4, 0x9a, 0xd5, 0xaf, 0xf4,
-            0xaf, 0x30, 0x0c, 0xd8, 0x04, 0xa6, 0xb6, 0x27, 0x9a, 0xb3, 0xff, 0x3a, 0xfb, 0x64,
-            0x49, 0x1c, 0x85, 0x19, 0x4a, 0xab, 0x76, 0x0d, 0x58, 0xa6, 0x06, 0x65, 0x4f, 0x9f,
-            0x44, 0x00, 0xe8, 0xb3, 0x85, 0x91, 0x35, 0x6f, 0xbf, 0x64, 0x25, 0xac, 0xa2, 0x6d,
-            0xc8, 0x52, 0x44, 0x25, 0x9f, 0xf2, 0xb1, 0x9c, 0x41, 0xb9, 0xf9, 0x6f, 0x3c, 0xa9,
-            0xec, 0x1d, 0xde, 0x43, 0x4d, 0xa7, 0xd2, 0xd3, 0x92, 0xb9, 0x05, 0xdd, 0xf3, 0xd1,
-            0xf9, 0xaf, 0x93, 0xd1, 0xaf, 0x59, 0x50, 0xbd, 0x49, 0x3f, 0x5a, 0xa7, 0x31, 0xb4,
-            0x05, 0x6d, 0xf3, 0x1b, 0xd2, 0x67, 0xb6, 0xb9, 0x0a, 0x07, 0x98, 0x31, 0xaa, 0xf5,
-            0x79, 0xbe, 0x0a, 0x39, 0x01, 0x31, 0x37, 0xaa, 0xc6, 0xd4, 0x04, 0xf5, 0x18, 0xcf,
-            0xd4, 0x68, 0x40, 0x64, 0x7e, 0x78, 0xbf, 0xe7, 0x06, 0xca, 0x4c, 0xf5, 0xe9, 0xc5,
-            0x45, 0x3e, 0x9f, 0x7c, 0xfd, 0x2b, 0x8b, 0x4c, 0x8d, 0x16, 0x9a, 0x44, 0xe5, 0x5c,
-            0x88, 0xd4, 0xa9, 0xa7, 0xf9, 0x47, 0x42, 0x41, 0xe2, 0x21, 0xaf, 0x44, 0x86, 0x00,
-            0x18, 0xab, 0x08, 0x56, 0x97, 0x2e, 0x19, 0x4c, 0xd9, 0x34,
-        ];
-
-        assert_eq!(&buf, PROTECTED);
-
-        let (header, payload) = buf.split_at_mut(header_len);
-        let (first, rest) = header.split_at_mut(1);
-        let sample = &payload[..sample_len];
+        server.process_new_packets().unwrap();
 
-        let server_keys = Keys::initial(
-            Version::V1,
-            TLS13_AES_128_GCM_SHA256
-                .tls13()
-                .unwrap(),
-            CONNECTION_ID,
-            Side::Server,
-        );
-        server_keys
-            .remote
-            .header
-            .decrypt_in_place(sample, &mut first[0], &mut rest[17..21])
+        let big_data = [0x5au8; 4096];
+        client
+            .writer()
+            .write_all(&big_data)
             .unwrap();
-        let payload = server_keys
-            .remote
-            .packet
-            .decrypt_in_place(PACKET_NUMBER, header, payload)
+        server
+            .writer()
+            .write_all(&big_data)
             .unwrap();
 
-        assert_eq!(&payload[..PAYLOAD.len()], PAYLOAD);
-        assert_eq!(payload.len(), buf.len() - header_len - tag_len);
-    }
+        let mut largest = 0;
+        while client.is_handshaking()
+            || server.is_handshaking()
+            || client.wants_write()
+            || server.wants_write()
+        {
+            largest = largest.max(send_all(&mut client, &mut server));
+            server.process_new_packets().unwrap();
+            largest = largest.max(send_all(&mut server, &mut client));
+            client.process_new_packets().unwrap();
+        }
 
-    #[test]
-    fn test_quic_exporter() {
-        for &kt in ALL_KEY_TYPES.iter() {
-            let client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS13]);
-            let server_config = make_server_config_with_versions(kt, &[&rustls::version::TLS13]);
+        // the application data fills records exactly
+        assert_eq!(largest, MTU);
 
-            do_exporter_test(client_config, server_config);
-        }
+        check_read(&mut client.reader(), &big_data);
+        check_read(&mut server.reader(), &big_data);
     }
-} // mod test_quic
+}
 
 #[test]
-fn test_client_does_not_offer_sha1() {
-    use rustls::internal::msgs::{
-        codec::Reader, handshake::HandshakePayload, message::MessagePayload, message::OpaqueMessage,
-    };
-    use rustls::HandshakeType;
-
-    for kt in ALL_KEY_TYPES.iter() {
-        for version in rustls::ALL_VERSIONS {
-            let client_config = make_client_config_with_versions(*kt, &[version]);
-            let (mut client, _) = make_pair_for_configs(client_config, make_server_config(*kt));
-
-            assert!(client.wants_write());
-            let mut buf = [0u8; 262144];
-            let sz = client
-                .write_tls(&mut buf.as_mut())
-                .unwrap();
-            let msg = OpaqueMessage::read(&mut Reader::init(&buf[..sz])).unwrap();
-            let msg = Message::try_from(msg.into_plain_message()).unwrap();
-            assert!(msg.is_handshake_type(HandshakeType::ClientHello));
+fn large_handshake_messages_are_fragmented_within_mtu() {
+    const MTU: usize = 64;
 
-            let client_hello = match msg.payload {
-                MessagePayload::Handshake { parsed, .. } => match parsed.payload {
-                    HandshakePayload::ClientHello(ch) => ch,
-                    _ => unreachable!(),
-                },
-                _ => unreachable!(),
-            };
+    // returns the number of records sent
+    fn send_all<C, S>(
+        from: &mut C,
+        to: &mut impl DerefMut<Target = ConnectionCommon<impl SideData>>,
+    ) -> usize
+    where
+        C: DerefMut + Deref<Target = ConnectionCommon<S>>,
+        S: SideData,
+    {
+        let mut records = 0;
+        while from.wants_write() {
+            let mut pipe = OtherSession::new(to);
+            from.write_tls(&mut pipe).unwrap();
+            for len in pipe.writevs.iter().flatten() {
+                assert!(*len <= MTU, "record of {} bytes exceeds mtu", len);
+                records += 1;
+            }
+        }
+        records
+    }
 
-            let sigalgs = client_hello
-                .get_sigalgs_extension()
-                .unwrap();
-            assert!(
-                !sigalgs.contains(&SignatureScheme::RSA_PKCS1_SHA1),
-                "sha1 unexpectedly offered"
-            );
+    let kt = KeyType::Rsa;
+    for version in rustls::ALL_VERSIONS {
+        let mut client_config = make_client_config_with_versions_with_auth(kt, &[version]);
+        client_config.max_fragment_size = Some(MTU);
+        let server_config = make_server_config_with_mandatory_client_auth(kt);
+        let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+        client
+            .set_record_plaintext_limit_for_mtu(MTU)
+            .unwrap();
+        server
+            .set_record_plaintext_limit_for_mtu(MTU)
+            .unwrap();
+
+        let mut client_records = 0;
+        while client.is_handshaking() || server.is_handshaking() {
+            client_records += send_all(&mut client, &mut server);
+            server.process_new_packets().unwrap();
+            send_all(&mut server, &mut client);
+            client.process_new_packets().unwrap();
         }
+
+        // the client's Certificate message alone spans this many records
+        let chain = kt.get_client_chain();
+        let chain_len = chain
+            .iter()
+            .map(|c| c.len())
+            .sum::<usize>();
+        assert!(client_records > chain_len / MTU);
+        assert_eq!(server.peer_certificates(), Some(&chain[..]));
     }
 }
 
 #[test]
-fn test_client_config_keyshare() {
-    let client_config = make_client_config_with_kx_groups(
-        KeyType::Rsa,
-        &[rustls::crypto::ring::kx_group::SECP384R1],
-    );
-    let server_config = make_server_config_with_kx_groups(
-        KeyType::Rsa,
-        &[rustls::crypto::ring::kx_group::SECP384R1],
+fn record_mtu_must_be_at_least_32() {
+    let (mut client, _) = make_pair(KeyType::Rsa);
+    assert_eq!(
+        client.set_record_plaintext_limit_for_mtu(31),
+        Err(Error::BadMaxFragmentSize)
     );
-    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
-    do_handshake_until_error(&mut client, &mut server).unwrap();
+    assert_eq!(client.set_record_plaintext_limit_for_mtu(32), Ok(()));
 }
 
-#[test]
-fn test_client_config_keyshare_mismatch() {
-    let client_config = make_client_config_with_kx_groups(
-        KeyType::Rsa,
-        &[rustls::crypto::ring::kx_group::SECP384R1],
-    );
-    let server_config =
-        make_server_config_with_kx_groups(KeyType::Rsa, &[rustls::crypto::ring::kx_group::X25519]);
-    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
-    assert!(do_handshake_until_error(&mut client, &mut server).is_err());
+fn check_client_max_fragment_size(size: usize) -> Option<Error> {
+    let mut client_config = make_client_config(KeyType::Ed25519);
+    client_config.max_fragment_size = Some(size);
+    ClientConnection::new(Arc::new(client_config), server_name("localhost")).err()
 }
 
-#[cfg(feature = "tls12")]
 #[test]
-fn test_client_sends_helloretryrequest() {
-    // client sends a secp384r1 key share
-    let mut client_config = make_client_config_with_kx_groups(
-        KeyType::Rsa,
-        &[
-            rustls::crypto::ring::kx_group::SECP384R1,
-            rustls::crypto::ring::kx_group::X25519,
-        ],
+fn bad_client_max_fragment_sizes() {
+    assert_eq!(
+        check_client_max_fragment_size(31),
+        Some(Error::BadMaxFragmentSize)
     );
+    assert_eq!(check_client_max_fragment_size(32), None);
+    assert_eq!(check_client_max_fragment_size(64), None);
+    assert_eq!(check_client_max_fragment_size(1460), None);
+    assert_eq!(check_client_max_fragment_size(0x4000), None);
+    assert_eq!(check_client_max_fragment_size(0x4005), None);
+    assert_eq!(
+        check_client_max_fragment_size(0x4006),
+        Some(Error::BadMaxFragmentSize)
+    );
+    assert_eq!(
+        check_client_max_fragment_size(0xffff),
+        Some(Error::BadMaxFragmentSize)
+    );
+}
 
-    let storage = Arc::new(ClientStorage::new());
-    client_config.resumption = Resumption::store(storage.clone());
-
-    // but server only accepts x25519, so a HRR is required
-    let server_config =
-        make_server_config_with_kx_groups(KeyType::Rsa, &[rustls::crypto::ring::kx_group::X25519]);
+fn record_lengths(tls: &[u8]) -> Vec<usize> {
+    let mut lengths = vec![];
+    let mut rest = tls;
+    while !rest.is_empty() {
+        let len = u16::from_be_bytes([rest[3], rest[4]]) as usize;
+        lengths.push(len);
+        rest = &rest[5 + len..];
+    }
+    lengths
+}
 
-    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+#[test]
+fn tls13_records_are_padded_to_block_size() {
+    // content type byte, and AEAD tag
+    let encryption_overhead = 1 + 16;
 
-    // client sends hello
-    {
-        let mut pipe = OtherSession::new(&mut server);
-        let wrlen = client.write_tls(&mut pipe).unwrap();
-        assert!(wrlen > 200);
-        assert_eq!(pipe.writevs.len(), 1);
-        assert!(pipe.writevs[0].len() == 1);
-    }
+    let mut client_config =
+        make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS13]);
+    client_config.record_padding_policy = RecordPaddingPolicy::BlockSize(256);
+    let (mut client, mut server) =
+        make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+    do_handshake(&mut client, &mut server);
 
-    // server sends HRR
-    {
-        let mut pipe = OtherSession::new(&mut client);
-        let wrlen = server.write_tls(&mut pipe).unwrap();
-        assert!(wrlen < 100); // just the hello retry request
-        assert_eq!(pipe.writevs.len(), 1); // only one writev
-        assert!(pipe.writevs[0].len() == 2); // hello retry request and CCS
-    }
+    for (len, padded_len) in [(1, 256), (256, 256), (300, 512)] {
+        let data = vec![0x5a; len];
+        client
+            .writer()
+            .write_all(&data)
+            .unwrap();
 
-    // client sends fixed hello
-    {
-        let mut pipe = OtherSession::new(&mut server);
-        let wrlen = client.write_tls(&mut pipe).unwrap();
-        assert!(wrlen > 200); // just the client hello retry
-        assert_eq!(pipe.writevs.len(), 1); // only one writev
-        assert!(pipe.writevs[0].len() == 2); // only a CCS & client hello retry
-    }
+        let mut tls = vec![];
+        client.write_tls(&mut tls).unwrap();
+        assert_eq!(record_lengths(&tls), vec![padded_len + encryption_overhead]);
 
-    // server completes handshake
-    {
-        let mut pipe = OtherSession::new(&mut client);
-        let wrlen = server.write_tls(&mut pipe).unwrap();
-        assert!(wrlen > 200);
-        assert_eq!(pipe.writevs.len(), 1);
-        assert!(pipe.writevs[0].len() == 5); // server hello / encrypted exts / cert / cert-verify / finished
+        server.read_tls(&mut &tls[..]).unwrap();
+        server.process_new_packets().unwrap();
+        check_read(&mut server.reader(), &data);
     }
 
-    do_handshake_until_error(&mut client, &mut server).unwrap();
-
-    // client only did following storage queries:
-    println!("storage {:#?}", storage.ops());
-    assert_eq!(storage.ops().len(), 9);
-    assert!(matches!(
-        storage.ops()[0],
-        ClientStorageOp::TakeTls13Ticket(_, false)
-    ));
-    assert!(matches!(
-        storage.ops()[1],
-        ClientStorageOp::GetTls12Session(_, false)
-    ));
-    assert!(matches!(
-        storage.ops()[2],
-        ClientStorageOp::GetKxHint(_, None)
-    ));
-    assert!(matches!(
-        storage.ops()[3],
-        ClientStorageOp::SetKxHint(_, rustls::NamedGroup::X25519)
-    ));
-    assert!(matches!(
-        storage.ops()[4],
-        ClientStorageOp::RemoveTls12Session(_)
-    ));
-    // server sends 4 tickets by default
-    assert!(matches!(
-        storage.ops()[5],
-        ClientStorageOp::InsertTls13Ticket(_)
-    ));
-    assert!(matches!(
-        storage.ops()[6],
-        ClientStorageOp::InsertTls13Ticket(_)
-    ));
-    assert!(matches!(
-        storage.ops()[7],
-        ClientStorageOp::InsertTls13Ticket(_)
-    ));
-    assert!(matches!(
-        storage.ops()[8],
-        ClientStorageOp::InsertTls13Ticket(_)
-    ));
+    // the server does not pad
+    server
+        .writer()
+        .write_all(b"hello")
+        .unwrap();
+    let mut tls = vec![];
+    server.write_tls(&mut tls).unwrap();
+    assert_eq!(record_lengths(&tls), vec![5 + encryption_overhead]);
 }
 
+#[cfg(feature = "tls12")]
 #[test]
-fn test_client_rejects_hrr_with_varied_session_id() {
-    use rustls::crypto::ring::RING;
-    use rustls::internal::msgs::handshake::SessionId;
-    let different_session_id = SessionId::random(RING).unwrap();
-
-    let assert_client_sends_hello_with_secp384 = |msg: &mut Message| -> Altered {
-        if let MessagePayload::Handshake { parsed, encoded } = &mut msg.payload {
-            if let HandshakePayload::ClientHello(ch) = &mut parsed.payload {
-                let keyshares = ch
-                    .get_keyshare_extension()
-                    .expect("missing key share extension");
-                assert_eq!(keyshares.len(), 1);
-                assert_eq!(keyshares[0].group, rustls::NamedGroup::secp384r1);
+fn tls12_records_are_not_padded() {
+    let mut client_config =
+        make_client_config_with_versions(KeyType::Rsa, &[&rustls::version::TLS12]);
+    client_config.record_padding_policy = RecordPaddingPolicy::BlockSize(256);
+    let (mut client, mut server) =
+        make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+    do_handshake(&mut client, &mut server);
 
-                ch.session_id = different_session_id;
-                *encoded = Payload::new(parsed.get_encoding());
-            }
-        }
-        Altered::InPlace
-    };
+    client
+        .writer()
+        .write_all(b"hello")
+        .unwrap();
+    let mut tls = vec![];
+    client.write_tls(&mut tls).unwrap();
+    let lengths = record_lengths(&tls);
+    assert_eq!(lengths.len(), 1);
+    assert!(lengths[0] < 64);
 
-    let assert_server_requests_retry_and_echoes_session_id = |msg: &mut Message| -> Altered {
-        if let MessagePayload::Handshake { parsed, .. } = &mut msg.payload {
-            if let HandshakePayload::HelloRetryRequest(hrr) = &mut parsed.payload {
-                let group = hrr.get_requested_key_share_group();
-                assert_eq!(group, Some(rustls::NamedGroup::X25519));
+    server.read_tls(&mut &tls[..]).unwrap();
+    server.process_new_packets().unwrap();
+    check_read(&mut server.reader(), b"hello");
+}
 
-                assert_eq!(hrr.session_id, different_session_id);
-            }
-        }
-        Altered::InPlace
+/// A copy of TLS13_AES_128_GCM_SHA256 whose keys run out after `limit` records.
+///
+/// (A `Tls13CipherSuite` cannot be built outside rustls with the `quic` feature.)
+#[cfg(not(feature = "quic"))]
+fn tls13_suite_with_confidentiality_limit(limit: u64) -> SupportedCipherSuite {
+    let base = match rustls::cipher_suite::TLS13_AES_128_GCM_SHA256 {
+        SupportedCipherSuite::Tls13(base) => base,
+        _ => unreachable!(),
     };
+    SupportedCipherSuite::Tls13(Box::leak(Box::new(rustls::Tls13CipherSuite {
+        common: rustls::cipher_suite::CipherSuiteCommon {
+            suite: base.common.suite,
+            hash_provider: base.common.hash_provider,
+        },
+        hmac_provider: base.hmac_provider,
+        aead_alg: base.aead_alg,
+        confidentiality_limit: limit,
+        integrity_limit: base.integrity_limit,
+    })))
+}
+
+#[cfg(not(feature = "quic"))]
+#[derive(Default)]
+struct CountingAeadLimitCallback {
+    calls: AtomicUsize,
+}
 
-    // client prefers a secp384r1 key share, server only accepts x25519
-    let client_config = make_client_config_with_kx_groups(
-        KeyType::Rsa,
-        &[
-            rustls::crypto::ring::kx_group::SECP384R1,
-            rustls::crypto::ring::kx_group::X25519,
-        ],
-    );
-
-    let server_config =
-        make_server_config_with_kx_groups(KeyType::Rsa, &[rustls::crypto::ring::kx_group::X25519]);
+#[cfg(not(feature = "quic"))]
+impl rustls::AeadLimitCallback for CountingAeadLimitCallback {
+    fn limit_reached(&self, suite: CipherSuite, records: u64) {
+        assert_eq!(suite, CipherSuite::TLS13_AES_128_GCM_SHA256);
+        assert_eq!(records, 7);
+        self.calls
+            .fetch_add(1, Ordering::SeqCst);
+    }
+}
 
-    let (client, server) = make_pair_for_configs(client_config, server_config);
-    let (mut client, mut server) = (client.into(), server.into());
-    transfer_altered(
-        &mut client,
-        assert_client_sends_hello_with_secp384,
-        &mut server,
-    );
-    server.process_new_packets().unwrap();
-    transfer_altered(
-        &mut server,
-        assert_server_requests_retry_and_echoes_session_id,
-        &mut client,
+#[cfg(not(feature = "quic"))]
+fn make_pair_with_confidentiality_limit(
+    policy: rustls::AeadLimitPolicy,
+    callback: Arc<CountingAeadLimitCallback>,
+) -> (ClientConnection, ServerConnection) {
+    let kt = KeyType::Rsa;
+    let suite = tls13_suite_with_confidentiality_limit(8);
+    let mut client_config = finish_client_config(
+        kt,
+        ClientConfig::builder()
+            .with_cipher_suites(&[suite])
+            .with_safe_default_kx_groups()
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap(),
     );
-    assert_eq!(
-        client.process_new_packets(),
-        Err(Error::PeerMisbehaved(
-            PeerMisbehaved::IllegalHelloRetryRequestWithWrongSessionId
-        ))
+    client_config.max_fragment_size = Some(512);
+    client_config.aead_limit_policy = policy;
+    client_config.aead_limit_callback = Some(callback);
+    let server_config = finish_server_config(
+        kt,
+        ServerConfig::builder()
+            .with_cipher_suites(&[suite])
+            .with_safe_default_kx_groups()
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap(),
     );
+
+    let (mut client, mut server) = make_pair_for_configs(client_config, server_config);
+    do_handshake(&mut client, &mut server);
+    (client, server)
 }
 
-#[cfg(feature = "tls12")]
+#[cfg(not(feature = "quic"))]
 #[test]
-fn test_client_attempts_to_use_unsupported_kx_group() {
-    // common to both client configs
-    let shared_storage = Arc::new(ClientStorage::new());
+fn tls13_write_key_is_updated_at_confidentiality_limit() {
+    let callback = Arc::new(CountingAeadLimitCallback::default());
+    let (mut client, mut server) =
+        make_pair_with_confidentiality_limit(rustls::AeadLimitPolicy::KeyUpdate, callback.clone());
 
-    // first, client sends a x25519 and server agrees. x25519 is inserted
-    //   into kx group cache.
-    let mut client_config_1 =
-        make_client_config_with_kx_groups(KeyType::Rsa, &[rustls::crypto::ring::kx_group::X25519]);
-    client_config_1.resumption = Resumption::store(shared_storage.clone());
+    // one record per write, then one write needing ten records
+    let mut sent = vec![];
+    for i in 0..20u8 {
+        let data = [i; 100];
+        client
+            .writer()
+            .write_all(&data)
+            .unwrap();
+        sent.extend_from_slice(&data);
+    }
+    let data = [0xaa; 5000];
+    client
+        .writer()
+        .write_all(&data)
+        .unwrap();
+    sent.extend_from_slice(&data);
 
-    // second, client only supports secp-384 and so kx group cache
-    //   contains an unusable value.
-    let mut client_config_2 = make_client_config_with_kx_groups(
-        KeyType::Rsa,
-        &[rustls::crypto::ring::kx_group::SECP384R1],
-    );
-    client_config_2.resumption = Resumption::store(shared_storage.clone());
+    // seven application data records fit under each key
+    assert_eq!(callback.calls.load(Ordering::SeqCst), 4);
 
-    let server_config = make_server_config(KeyType::Rsa);
+    transfer(&mut client, &mut server);
+    server.process_new_packets().unwrap();
+    check_read(&mut server.reader(), &sent);
+    client.process_new_packets().unwrap();
+}
 
-    // first handshake
-    let (mut client_1, mut server) = make_pair_for_configs(client_config_1, server_config.clone());
-    do_handshake_until_error(&mut client_1, &mut server).unwrap();
+#[cfg(not(feature = "quic"))]
+#[test]
+fn tls13_connection_fails_at_confidentiality_limit() {
+    let callback = Arc::new(CountingAeadLimitCallback::default());
+    let (mut client, mut server) =
+        make_pair_with_confidentiality_limit(rustls::AeadLimitPolicy::Fail, callback.clone());
 
-    let ops = shared_storage.ops();
-    println!("storage {:#?}", ops);
-    assert_eq!(ops.len(), 9);
-    assert!(matches!(
-        ops[3],
-        ClientStorageOp::SetKxHint(_, rustls::NamedGroup::X25519)
-    ));
+    for _ in 0..7 {
+        client
+            .writer()
+            .write_all(b"hello")
+            .unwrap();
+    }
+    assert_eq!(callback.calls.load(Ordering::SeqCst), 1);
 
-    // second handshake
-    let (mut client_2, mut server) = make_pair_for_configs(client_config_2, server_config);
-    do_handshake_until_error(&mut client_2, &mut server).unwrap();
+    let err = client
+        .writer()
+        .write(b"hello")
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::Other);
+    assert_eq!(err.to_string(), Error::AeadLimitReached.to_string());
+    assert_eq!(client.process_new_packets(), Err(Error::AeadLimitReached));
 
-    let ops = shared_storage.ops();
-    println!("storage {:?} {:#?}", ops.len(), ops);
-    assert_eq!(ops.len(), 17);
-    assert!(matches!(ops[9], ClientStorageOp::TakeTls13Ticket(_, true)));
-    assert!(matches!(
-        ops[10],
-        ClientStorageOp::GetKxHint(_, Some(rustls::NamedGroup::X25519))
-    ));
-    assert!(matches!(
-        ops[11],
-        ClientStorageOp::SetKxHint(_, rustls::NamedGroup::secp384r1)
-    ));
+    // everything sent under the key arrives, followed by a close_notify
+    transfer(&mut client, &mut server);
+    let io_state = server.process_new_packets().unwrap();
+    assert!(io_state.peer_has_closed());
+    check_read(&mut server.reader(), &b"hello".repeat(7));
+    assert_eq!(callback.calls.load(Ordering::SeqCst), 1);
 }
 
-#[cfg(feature = "tls12")]
 #[test]
-fn test_tls13_client_resumption_does_not_reuse_tickets() {
-    let shared_storage = Arc::new(ClientStorage::new());
-
-    let mut client_config = make_client_config(KeyType::Rsa);
-    client_config.resumption = Resumption::store(shared_storage.clone());
-    let client_config = Arc::new(client_config);
+fn corked_small_writes_are_coalesced() {
+    let (mut client, mut server) = make_pair(KeyType::Rsa);
+    do_handshake(&mut client, &mut server);
 
-    let mut server_config = make_server_config(KeyType::Rsa);
-    server_config.send_tls13_tickets = 5;
-    let server_config = Arc::new(server_config);
+    for _ in 0..100 {
+        client
+            .writer()
+            .write_all(b"0123456789")
+            .unwrap();
+    }
+    let mut tls = vec![];
+    while client.wants_write() {
+        client.write_tls(&mut tls).unwrap();
+    }
+    assert_eq!(record_lengths(&tls).len(), 100);
 
-    // first handshake: client obtains 5 tickets from server.
-    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
-    do_handshake_until_error(&mut client, &mut server).unwrap();
+    client.cork();
+    for _ in 0..100 {
+        client
+            .writer()
+            .write_all(b"0123456789")
+            .unwrap();
+    }
+    assert!(!client.wants_write());
+    client.writer().flush().unwrap();
+    let mut corked = vec![];
+    client.write_tls(&mut corked).unwrap();
+    assert_eq!(record_lengths(&corked).len(), 1);
 
-    let ops = shared_storage.ops_and_reset();
-    println!("storage {:#?}", ops);
-    assert_eq!(ops.len(), 10);
-    assert!(matches!(ops[5], ClientStorageOp::InsertTls13Ticket(_)));
-    assert!(matches!(ops[6], ClientStorageOp::InsertTls13Ticket(_)));
-    assert!(matches!(ops[7], ClientStorageOp::InsertTls13Ticket(_)));
-    assert!(matches!(ops[8], ClientStorageOp::InsertTls13Ticket(_)));
-    assert!(matches!(ops[9], ClientStorageOp::InsertTls13Ticket(_)));
+    // full records are sent without waiting for a flush
+    client
+        .writer()
+        .write_all(&[0u8; 20000])
+        .unwrap();
+    let mut full = vec![];
+    client.write_tls(&mut full).unwrap();
+    assert_eq!(record_lengths(&full).len(), 1);
+
+    client.uncork();
+    let mut rest = vec![];
+    client.write_tls(&mut rest).unwrap();
+    assert_eq!(record_lengths(&rest).len(), 1);
+
+    let mut received = vec![];
+    for buf in [tls, corked, full, rest] {
+        let mut buf = &buf[..];
+        while !buf.is_empty() {
+            server.read_tls(&mut buf).unwrap();
+            server.process_new_packets().unwrap();
+            server
+                .reader()
+                .read_to_end(&mut received)
+                .unwrap_err();
+        }
+    }
+    assert_eq!(received.len(), 2000 + 20000);
+}
 
-    // 5 subsequent handshakes: all are resumptions
+#[test]
+fn probe_captures_server_parameters() {
+    for version in rustls::ALL_VERSIONS {
+        let mut client_config = make_client_config_with_versions(KeyType::Rsa, &[version]);
+        client_config
+            .dangerous()
+            .set_probe_only(true);
+        // verification is skipped, so a verifier that would reject this server is fine
+        client_config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(rustls::client::WebPkiServerVerifier::new(
+                get_client_root_store(KeyType::Ecdsa),
+            )));
+        let (mut client, mut server) =
+            make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
 
-    // Note: we don't do complete the handshakes, because that means
-    // we get five additional tickets per connection which is unhelpful
-    // in this test.  It also acts to record a "Happy Eyeballs"-type use
-    // case, where a client speculatively makes many connection attempts
-    // in parallel without knowledge of which will work due to underlying
-    // connectivity uncertainty.
-    for _ in 0..5 {
-        let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
         transfer(&mut client, &mut server);
         server.process_new_packets().unwrap();
+        transfer(&mut server, &mut client);
+        // the rest of the server's flight is discarded
+        client.process_new_packets().unwrap();
 
-        let ops = shared_storage.ops_and_reset();
-        assert!(matches!(ops[0], ClientStorageOp::TakeTls13Ticket(_, true)));
+        let probe = client.server_probe().unwrap();
+        assert_eq!(probe.version, version.version);
+        assert_eq!(Some(probe.suite), server.negotiated_cipher_suite());
+        assert_eq!(probe.kx_group, rustls::NamedGroup::X25519);
+        assert_eq!(probe.cert_chain, KeyType::Rsa.get_chain());
+
+        assert!(client.is_handshaking());
+        assert!(client.peer_certificates().is_none());
+
+        // the server only sees the client's close_notify
+        transfer(&mut client, &mut server);
+        let io_state = server.process_new_packets().unwrap();
+        assert!(io_state.peer_has_closed());
+        assert!(!client.wants_write());
     }
+}
 
-    // 6th subsequent handshake: cannot be resumed; we ran out of tickets
-    let (mut client, mut server) = make_pair_for_arc_configs(&client_config, &server_config);
+#[test]
+fn record_layer_byte_counters() {
+    let (mut client, mut server) = make_pair(KeyType::Rsa);
+    assert_eq!((client.bytes_sent(), client.bytes_received()), (0, 0));
+
+    do_handshake(&mut client, &mut server);
+    assert!(client.bytes_sent() > 0);
+    assert!(client.bytes_received() > client.bytes_sent()); // the server sends its chain
+    assert_eq!(client.bytes_sent(), server.bytes_received());
+    assert_eq!(client.bytes_received(), server.bytes_sent());
+    assert_eq!(
+        client.bytes_transferred(),
+        client.bytes_sent() + client.bytes_received()
+    );
+
+    let (sent, received) = (client.bytes_sent(), client.bytes_received());
+    client
+        .writer()
+        .write_all(&[0u8; 100])
+        .unwrap();
     transfer(&mut client, &mut server);
     server.process_new_packets().unwrap();
 
-    let ops = shared_storage.ops_and_reset();
-    println!("last {:?}", ops);
-    assert!(matches!(ops[0], ClientStorageOp::TakeTls13Ticket(_, false)));
+    // one record: the 100 bytes of plaintext plus header and AEAD overhead
+    assert!(client.bytes_sent() > sent + 100);
+    assert_eq!(client.bytes_sent(), server.bytes_received());
+    assert_eq!(client.bytes_received(), received);
 }
 
 #[test]
-fn test_client_mtu_reduction() {
-    struct CollectWrites {
-        writevs: Vec<Vec<usize>>,
-    }
-
-    impl io::Write for CollectWrites {
-        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
-            panic!()
-        }
-        fn flush(&mut self) -> io::Result<()> {
-            panic!()
-        }
-        fn write_vectored(&mut self, b: &[io::IoSlice<'_>]) -> io::Result<usize> {
-            let writes = b
-                .iter()
-                .map(|slice| slice.len())
-                .collect::<Vec<usize>>();
-            let len = writes.iter().sum();
-            self.writevs.push(writes);
-            Ok(len)
-        }
-    }
+fn connection_fails_once_byte_limit_is_exceeded() {
+    let (mut client, mut server) = make_pair(KeyType::Rsa);
+    do_handshake(&mut client, &mut server);
+    let handshake_bytes = server.bytes_transferred();
+    assert!(handshake_bytes > 0);
 
-    fn collect_write_lengths(client: &mut ClientConnection) -> Vec<usize> {
-        let mut collector = CollectWrites { writevs: vec![] };
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.max_bytes_transferred = Some(handshake_bytes + 100);
+    let (mut client, mut server) =
+        make_pair_for_configs(make_client_config(KeyType::Rsa), server_config);
+    do_handshake(&mut client, &mut server);
+    assert_eq!(server.bytes_transferred(), handshake_bytes);
 
-        client
-            .write_tls(&mut collector)
-            .unwrap();
-        assert_eq!(collector.writevs.len(), 1);
-        collector.writevs[0].clone()
-    }
+    client
+        .writer()
+        .write_all(&[0u8; 1000])
+        .unwrap();
+    transfer(&mut client, &mut server);
+    assert!(server.bytes_transferred() > handshake_bytes + 100);
+    assert_eq!(
+        server.process_new_packets(),
+        Err(Error::BytesTransferredLimitExceeded)
+    );
 
-    for kt in ALL_KEY_TYPES.iter() {
-        let mut client_config = make_client_config(*kt);
-        client_config.max_fragment_size = Some(64);
-        let mut client =
-            ClientConnection::new(Arc::new(client_config), server_name("localhost")).unwrap();
-        let writes = collect_write_lengths(&mut client);
-        println!("writes at mtu=64: {:?}", writes);
-        assert!(writes.iter().all(|x| *x <= 64));
-        assert!(writes.len() > 1);
-    }
+    // the error is sticky
+    assert_eq!(
+        server.process_new_packets(),
+        Err(Error::BytesTransferredLimitExceeded)
+    );
 }
 
 #[test]
-fn test_server_mtu_reduction() {
+fn outgoing_plaintext_limit_makes_writer_would_block() {
     let mut server_config = make_server_config(KeyType::Rsa);
-    server_config.max_fragment_size = Some(64);
-    server_config.send_half_rtt_data = true;
+    server_config.outgoing_plaintext_limit = Some(1000);
     let (mut client, mut server) =
         make_pair_for_configs(make_client_config(KeyType::Rsa), server_config);
+    do_handshake(&mut client, &mut server);
 
-    let big_data = [0u8; 2048];
-    server
+    assert_eq!(
+        server
+            .writer()
+            .write(&[0u8; 800])
+            .unwrap(),
+        800
+    );
+    let second = server
         .writer()
-        .write_all(&big_data)
+        .write(&[0u8; 800])
         .unwrap();
+    assert!(second > 0 && second < 200);
+    assert_eq!(
+        server
+            .writer()
+            .write(&[0u8; 800])
+            .unwrap_err()
+            .kind(),
+        io::ErrorKind::WouldBlock
+    );
 
-    let encryption_overhead = 20; // FIXME: see issue #991
+    // the client's (unlimited) side is unaffected
+    assert_eq!(
+        client
+            .writer()
+            .write(&[0u8; 2000])
+            .unwrap(),
+        2000
+    );
 
-    transfer(&mut client, &mut server);
-    server.process_new_packets().unwrap();
-    {
-        let mut pipe = OtherSession::new(&mut client);
-        server.write_tls(&mut pipe).unwrap();
+    transfer(&mut server, &mut client);
+    assert_eq!(
+        server
+            .writer()
+            .write(&[0u8; 800])
+            .unwrap(),
+        800
+    );
+}
 
-        assert_eq!(pipe.writevs.len(), 1);
-        assert!(pipe.writevs[0]
-            .iter()
-            .all(|x| *x <= 64 + encryption_overhead));
+#[test]
+fn incoming_plaintext_limit_pauses_decryption() {
+    let mut client_config = make_client_config(KeyType::Rsa);
+    client_config.incoming_plaintext_limit = Some(100);
+    let (mut client, mut server) =
+        make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+    do_handshake(&mut client, &mut server);
+
+    for _ in 0..3 {
+        server
+            .writer()
+            .write_all(&[0u8; 100])
+            .unwrap();
+    }
+    transfer(&mut server, &mut client);
+
+    for i in 0..3 {
+        let io_state = client.process_new_packets().unwrap();
+        assert_eq!(io_state.plaintext_bytes_to_read(), 100);
+        check_read(&mut client.reader(), &[0u8; 100]);
+        // there is nothing to read while records are still undecrypted
+        assert_eq!(client.wants_read(), i == 2);
     }
+    let io_state = client.process_new_packets().unwrap();
+    assert_eq!(io_state.plaintext_bytes_to_read(), 0);
 
-    client.process_new_packets().unwrap();
+    // the server's (unlimited) side is unaffected
+    client
+        .writer()
+        .write_all(&[0u8; 300])
+        .unwrap();
     transfer(&mut client, &mut server);
-    server.process_new_packets().unwrap();
-    {
-        let mut pipe = OtherSession::new(&mut client);
-        server.write_tls(&mut pipe).unwrap();
-        assert_eq!(pipe.writevs.len(), 1);
-        assert!(pipe.writevs[0]
-            .iter()
-            .all(|x| *x <= 64 + encryption_overhead));
+    let io_state = server.process_new_packets().unwrap();
+    assert_eq!(io_state.plaintext_bytes_to_read(), 300);
+}
+
+#[test]
+fn stream_reads_records_deferred_by_incoming_plaintext_limit() {
+    /// Serves `tls`, then blocks: there is nothing more from the peer.
+    struct Exhausted(io::Cursor<Vec<u8>>);
+
+    impl io::Read for Exhausted {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.read(buf)? {
+                0 => Err(io::ErrorKind::WouldBlock.into()),
+                n => Ok(n),
+            }
+        }
     }
 
-    client.process_new_packets().unwrap();
-    check_read(&mut client.reader(), &big_data);
-}
+    impl io::Write for Exhausted {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
 
-fn check_client_max_fragment_size(size: usize) -> Option<Error> {
-    let mut client_config = make_client_config(KeyType::Ed25519);
-    client_config.max_fragment_size = Some(size);
-    ClientConnection::new(Arc::new(client_config), server_name("localhost")).err()
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut client_config = make_client_config(KeyType::Rsa);
+    client_config.incoming_plaintext_limit = Some(100);
+    let (mut client, mut server) =
+        make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+    do_handshake(&mut client, &mut server);
+
+    for byte in 1..=3 {
+        server
+            .writer()
+            .write_all(&[byte; 100])
+            .unwrap();
+    }
+    let mut tls = vec![];
+    while server.wants_write() {
+        server.write_tls(&mut tls).unwrap();
+    }
+
+    let mut sock = Exhausted(io::Cursor::new(tls));
+    let mut stream = Stream::new(&mut client, &mut sock);
+    for byte in 1..=3 {
+        let mut buf = [0u8; 100];
+        stream.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [byte; 100]);
+    }
+    assert!(!client.wants_write());
+    assert!(client.wants_read());
 }
 
 #[test]
-fn bad_client_max_fragment_sizes() {
-    assert_eq!(
-        check_client_max_fragment_size(31),
-        Some(Error::BadMaxFragmentSize)
-    );
-    assert_eq!(check_client_max_fragment_size(32), None);
-    assert_eq!(check_client_max_fragment_size(64), None);
-    assert_eq!(check_client_max_fragment_size(1460), None);
-    assert_eq!(check_client_max_fragment_size(0x4000), None);
-    assert_eq!(check_client_max_fragment_size(0x4005), None);
-    assert_eq!(
-        check_client_max_fragment_size(0x4006),
-        Some(Error::BadMaxFragmentSize)
-    );
+fn connection_fails_once_age_limit_is_exceeded() {
+    let mut client_config = make_client_config(KeyType::Rsa);
+    client_config.max_connection_age = Some(Duration::from_secs(60));
+    let (mut client, mut server) =
+        make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+
+    let start = UnixTime::since_unix_epoch(Duration::from_secs(1_700_000_000));
+    client.set_current_time(start);
+    do_handshake(&mut client, &mut server);
+
+    client.set_current_time(UnixTime::since_unix_epoch(Duration::from_secs(
+        1_700_000_060,
+    )));
+    server
+        .writer()
+        .write_all(b"hello")
+        .unwrap();
+    transfer(&mut server, &mut client);
+    client.process_new_packets().unwrap();
+    check_read(&mut client.reader(), b"hello");
+
+    client.set_current_time(UnixTime::since_unix_epoch(Duration::from_secs(
+        1_700_000_061,
+    )));
     assert_eq!(
-        check_client_max_fragment_size(0xffff),
-        Some(Error::BadMaxFragmentSize)
+        client.process_new_packets(),
+        Err(Error::ConnectionAgeLimitExceeded)
     );
 }
 
@@ -4442,6 +8776,52 @@ use rustls::internal::msgs::{
     message::MessagePayload,
 };
 
+#[test]
+fn test_client_rejects_non_null_compression() {
+    use rustls::internal::msgs::enums::Compression;
+
+    fn client_offers_only_null_compression(msg: &mut Message) -> Altered {
+        if let MessagePayload::Handshake { parsed, .. } = &mut msg.payload {
+            if let HandshakePayload::ClientHello(ch) = &mut parsed.payload {
+                assert_eq!(ch.compression_methods, vec![Compression::Null]);
+            }
+        }
+        Altered::InPlace
+    }
+
+    fn server_selects_deflate(msg: &mut Message) -> Altered {
+        if let MessagePayload::Handshake { parsed, encoded } = &mut msg.payload {
+            if let HandshakePayload::ServerHello(sh) = &mut parsed.payload {
+                sh.compression_method = Compression::Deflate;
+            }
+
+            *encoded = Payload::new(parsed.get_encoding());
+        }
+        Altered::InPlace
+    }
+
+    for version in rustls::ALL_VERSIONS {
+        let client_config = make_client_config_with_versions(KeyType::Rsa, &[version]);
+        let (client, server) =
+            make_pair_for_configs(client_config, make_server_config(KeyType::Rsa));
+        let (mut client, mut server) = (client.into(), server.into());
+
+        transfer_altered(
+            &mut client,
+            client_offers_only_null_compression,
+            &mut server,
+        );
+        server.process_new_packets().unwrap();
+        transfer_altered(&mut server, server_selects_deflate, &mut client);
+        assert_eq!(
+            client.process_new_packets(),
+            Err(Error::PeerMisbehaved(
+                PeerMisbehaved::SelectedUnofferedCompression
+            ))
+        );
+    }
+}
+
 #[test]
 fn test_server_rejects_duplicate_sni_names() {
     fn duplicate_sni_payload(msg: &mut Message) -> Altered {
@@ -4530,6 +8910,145 @@ fn test_server_rejects_clients_without_any_kx_group_overlap() {
     );
 }
 
+/// A webpki server verifier which offers only `schemes`, though it will
+/// verify signatures made with any scheme it supports.
+#[cfg(feature = "tls12")]
+struct NarrowlyAdvertisingVerifier {
+    inner: rustls::client::WebPkiServerVerifier,
+    schemes: Vec<SignatureScheme>,
+}
+
+#[cfg(feature = "tls12")]
+impl ServerCertVerifier for NarrowlyAdvertisingVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &rustls::ServerName,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        self.inner
+            .verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner
+            .verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner
+            .verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.schemes.clone()
+    }
+}
+
+#[cfg(feature = "tls12")]
+#[test]
+fn client_refuses_server_signing_with_unadvertised_scheme() {
+    // The client offers only RSA_PSS_SHA256, but the server is led to believe
+    // it offered only RSA_PSS_SHA512.  (In TLS1.3 the altered transcript already
+    // prevents the client decrypting the server's CertificateVerify; in TLS1.2 the
+    // ServerKeyExchange signature does not cover it.)
+    fn offer_pss_sha512(msg: &mut Message) -> Altered {
+        if let MessagePayload::Handshake { parsed, encoded } = &mut msg.payload {
+            if let HandshakePayload::ClientHello(ch) = &mut parsed.payload {
+                for ext in ch.extensions.iter_mut() {
+                    if let ClientExtension::SignatureAlgorithms(schemes) = ext {
+                        *schemes = vec![SignatureScheme::RSA_PSS_SHA512];
+                    }
+                }
+            }
+
+            *encoded = Payload::new(parsed.get_encoding());
+        }
+        Altered::InPlace
+    }
+
+    let kt = KeyType::Rsa;
+    for enforce in [true, false] {
+        let mut client_config = make_client_config_with_versions(kt, &[&rustls::version::TLS12]);
+        client_config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(NarrowlyAdvertisingVerifier {
+                inner: rustls::client::WebPkiServerVerifier::new(get_client_root_store(kt)),
+                schemes: vec![SignatureScheme::RSA_PSS_SHA256],
+            }));
+        client_config.enforce_advertised_signature_schemes = enforce;
+
+        let (client, server) = make_pair_for_configs(client_config, make_server_config(kt));
+        let (mut client, mut server) = (client.into(), server.into());
+        transfer_altered(&mut client, offer_pss_sha512, &mut server);
+        server.process_new_packets().unwrap();
+        transfer_altered(&mut server, |_| Altered::InPlace, &mut client);
+
+        // Otherwise the verifier accepts the signature, leaving the
+        // tampering to be caught by the Finished messages.
+        let result = client.process_new_packets().map(|_| ());
+        match enforce {
+            true => assert_eq!(
+                result,
+                Err(Error::PeerMisbehaved(
+                    PeerMisbehaved::SignedHandshakeWithUnadvertisedSigScheme
+                ))
+            ),
+            false => assert_eq!(result, Ok(())),
+        }
+    }
+}
+
+#[test]
+fn test_server_rejects_oversized_client_hello_lists() {
+    fn repeat_cipher_suites(msg: &mut Message) -> Altered {
+        if let MessagePayload::Handshake { parsed, encoded } = &mut msg.payload {
+            if let HandshakePayload::ClientHello(ch) = &mut parsed.payload {
+                let suite = ch.cipher_suites[0];
+                ch.cipher_suites
+                    .extend(std::iter::repeat(suite).take(1000));
+            }
+
+            *encoded = Payload::new(parsed.get_encoding());
+        }
+        Altered::InPlace
+    }
+
+    let (client, server) = make_pair(KeyType::Rsa);
+    let (mut client, mut server) = (client.into(), server.into());
+    transfer_altered(&mut client, repeat_cipher_suites, &mut server);
+    assert_eq!(
+        server.process_new_packets(),
+        Err(Error::PeerMisbehaved(
+            PeerMisbehaved::TooManyClientHelloEntries
+        ))
+    );
+
+    // Unaltered hellos are refused too once the cap is below their size.
+    let mut server_config = make_server_config(KeyType::Rsa);
+    server_config.max_client_hello_entries = 2;
+    let (mut client, mut server) =
+        make_pair_for_configs(make_client_config(KeyType::Rsa), server_config);
+    assert_eq!(
+        do_handshake_until_error(&mut client, &mut server),
+        Err(ErrorFromPeer::Server(Error::PeerMisbehaved(
+            PeerMisbehaved::TooManyClientHelloEntries
+        )))
+    );
+}
+
 #[test]
 fn test_client_rejects_illegal_tls13_ccs() {
     fn corrupt_ccs(msg: &mut Message) -> Altered {
@@ -5128,3 +9647,291 @@ fn test_client_construction_requires_64_bytes_of_random_material() {
     ClientConnection::new(Arc::new(client_config), server_name("localhost"))
         .expect("check how much random material ClientConnection::new consumes");
 }
+
+/// A provider that delegates to ring but claims to be FIPS-approved.
+#[cfg(not(feature = "quic"))]
+#[derive(Debug)]
+struct FipsClaimingProvider;
+
+#[cfg(not(feature = "quic"))]
+impl rustls::crypto::CryptoProvider for FipsClaimingProvider {
+    fn fill_random(&self, output: &mut [u8]) -> Result<(), rustls::crypto::GetRandomFailed> {
+        rustls::crypto::ring::RING.fill_random(output)
+    }
+
+    fn default_cipher_suites(&self) -> &'static [SupportedCipherSuite] {
+        rustls::crypto::ring::RING.default_cipher_suites()
+    }
+
+    fn default_kx_groups(&self) -> &'static [&'static dyn rustls::crypto::SupportedKxGroup] {
+        rustls::crypto::ring::RING.default_kx_groups()
+    }
+
+    fn fips(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(not(feature = "quic"))]
+#[derive(Debug)]
+struct FipsClaimingKxGroup(&'static dyn rustls::crypto::SupportedKxGroup);
+
+#[cfg(not(feature = "quic"))]
+impl rustls::crypto::SupportedKxGroup for FipsClaimingKxGroup {
+    fn start(
+        &self,
+    ) -> Result<Box<dyn rustls::crypto::ActiveKeyExchange>, rustls::crypto::GetRandomFailed> {
+        self.0.start()
+    }
+
+    fn name(&self) -> rustls::NamedGroup {
+        self.0.name()
+    }
+
+    fn fips(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(not(feature = "quic"))]
+static FIPS_CLAIMING_SECP256R1: FipsClaimingKxGroup =
+    FipsClaimingKxGroup(rustls::crypto::ring::kx_group::SECP256R1);
+
+#[cfg(not(feature = "quic"))]
+fn ring_tls13_aes_128_gcm_sha256() -> &'static rustls::Tls13CipherSuite {
+    match rustls::cipher_suite::TLS13_AES_128_GCM_SHA256 {
+        SupportedCipherSuite::Tls13(suite) => suite,
+        _ => unreachable!(),
+    }
+}
+
+#[cfg(not(feature = "quic"))]
+struct FipsClaimingHash;
+
+#[cfg(not(feature = "quic"))]
+impl rustls::crypto::hash::Hash for FipsClaimingHash {
+    fn start(&self) -> Box<dyn rustls::crypto::hash::Context> {
+        ring_tls13_aes_128_gcm_sha256()
+            .common
+            .hash_provider
+            .start()
+    }
+
+    fn hash(&self, data: &[u8]) -> rustls::crypto::hash::Output {
+        ring_tls13_aes_128_gcm_sha256()
+            .common
+            .hash_provider
+            .hash(data)
+    }
+
+    fn output_len(&self) -> usize {
+        ring_tls13_aes_128_gcm_sha256()
+            .common
+            .hash_provider
+            .output_len()
+    }
+
+    fn algorithm(&self) -> rustls::crypto::hash::HashAlgorithm {
+        ring_tls13_aes_128_gcm_sha256()
+            .common
+            .hash_provider
+            .algorithm()
+    }
+
+    fn fips(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(not(feature = "quic"))]
+struct FipsClaimingHmac;
+
+#[cfg(not(feature = "quic"))]
+impl rustls::crypto::hmac::Hmac for FipsClaimingHmac {
+    fn with_key(&self, key: &[u8]) -> Box<dyn rustls::crypto::hmac::Key> {
+        ring_tls13_aes_128_gcm_sha256()
+            .hmac_provider
+            .with_key(key)
+    }
+
+    fn hash_output_len(&self) -> usize {
+        ring_tls13_aes_128_gcm_sha256()
+            .hmac_provider
+            .hash_output_len()
+    }
+
+    fn fips(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(not(feature = "quic"))]
+struct FipsClaimingAead;
+
+#[cfg(not(feature = "quic"))]
+impl rustls::crypto::cipher::Tls13AeadAlgorithm for FipsClaimingAead {
+    fn encrypter(
+        &self,
+        key: rustls::crypto::cipher::AeadKey,
+        iv: rustls::crypto::cipher::Iv,
+    ) -> Box<dyn rustls::crypto::cipher::MessageEncrypter> {
+        ring_tls13_aes_128_gcm_sha256()
+            .aead_alg
+            .encrypter(key, iv)
+    }
+
+    fn decrypter(
+        &self,
+        key: rustls::crypto::cipher::AeadKey,
+        iv: rustls::crypto::cipher::Iv,
+    ) -> Box<dyn rustls::crypto::cipher::MessageDecrypter> {
+        ring_tls13_aes_128_gcm_sha256()
+            .aead_alg
+            .decrypter(key, iv)
+    }
+
+    fn key_len(&self) -> usize {
+        ring_tls13_aes_128_gcm_sha256()
+            .aead_alg
+            .key_len()
+    }
+
+    fn extract_keys(
+        &self,
+        key: rustls::crypto::cipher::AeadKey,
+        iv: rustls::crypto::cipher::Iv,
+    ) -> Result<ConnectionTrafficSecrets, rustls::crypto::cipher::UnsupportedOperationError> {
+        ring_tls13_aes_128_gcm_sha256()
+            .aead_alg
+            .extract_keys(key, iv)
+    }
+
+    fn fips(&self) -> bool {
+        true
+    }
+}
+
+/// A copy of TLS13_AES_128_GCM_SHA256 whose algorithms claim to be FIPS-approved.
+///
+/// (A `Tls13CipherSuite` cannot be built outside rustls with the `quic` feature.)
+#[cfg(not(feature = "quic"))]
+static FIPS_CLAIMING_TLS13_AES_128_GCM_SHA256: rustls::Tls13CipherSuite =
+    rustls::Tls13CipherSuite {
+        common: rustls::cipher_suite::CipherSuiteCommon {
+            suite: CipherSuite::TLS13_AES_128_GCM_SHA256,
+            hash_provider: &FipsClaimingHash,
+        },
+        hmac_provider: &FipsClaimingHmac,
+        aead_alg: &FipsClaimingAead,
+        confidentiality_limit: 1 << 23,
+        integrity_limit: 1 << 52,
+    };
+
+#[cfg(not(feature = "quic"))]
+fn fips_claiming_pair(
+    suite: SupportedCipherSuite,
+    kx_group: &'static dyn rustls::crypto::SupportedKxGroup,
+) -> (ClientConnection, ServerConnection) {
+    let client_config = finish_client_config(
+        KeyType::Ecdsa,
+        ClientConfig::builder_with_provider(&FipsClaimingProvider)
+            .with_cipher_suites(&[suite])
+            .with_kx_groups(&[kx_group])
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap(),
+    );
+    let server_config = finish_server_config(
+        KeyType::Ecdsa,
+        ServerConfig::builder_with_provider(&FipsClaimingProvider)
+            .with_cipher_suites(&[suite])
+            .with_kx_groups(&[kx_group])
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap(),
+    );
+    make_pair_for_configs(client_config, server_config)
+}
+
+#[cfg(not(feature = "quic"))]
+#[test]
+fn config_fips_requires_every_suite_and_group() {
+    fn config(
+        provider: &'static dyn rustls::crypto::CryptoProvider,
+        suites: &[SupportedCipherSuite],
+        kx_groups: &[&'static dyn rustls::crypto::SupportedKxGroup],
+    ) -> ClientConfig {
+        ClientConfig::builder_with_provider(provider)
+            .with_cipher_suites(suites)
+            .with_kx_groups(kx_groups)
+            .with_protocol_versions(&[&rustls::version::TLS13])
+            .unwrap()
+            .with_root_certificates(rustls::RootCertStore::empty())
+            .with_no_client_auth()
+    }
+
+    let fips_suite = SupportedCipherSuite::Tls13(&FIPS_CLAIMING_TLS13_AES_128_GCM_SHA256);
+    let fips_kx: &'static dyn rustls::crypto::SupportedKxGroup = &FIPS_CLAIMING_SECP256R1;
+    assert!(fips_suite.fips());
+    assert!(config(&FipsClaimingProvider, &[fips_suite], &[fips_kx]).fips());
+
+    // a non-FIPS suite slipped into the list
+    assert!(!config(
+        &FipsClaimingProvider,
+        &[fips_suite, rustls::cipher_suite::TLS13_AES_256_GCM_SHA384],
+        &[fips_kx]
+    )
+    .fips());
+
+    // likewise a non-FIPS key exchange group
+    assert!(!config(
+        &FipsClaimingProvider,
+        &[fips_suite],
+        &[fips_kx, rustls::crypto::ring::kx_group::X25519]
+    )
+    .fips());
+
+    // and the provider itself must claim FIPS
+    assert!(!config(rustls::crypto::ring::RING, &[fips_suite], &[fips_kx]).fips());
+}
+
+#[cfg(not(feature = "quic"))]
+#[test]
+fn composite_provider_rejects_non_fips_parts_of_a_fips_claim() {
+    use rustls::crypto::CompositeCryptoProvider;
+
+    // FipsClaimingProvider claims FIPS, but hands out ring's suites and kx groups
+    assert!(CompositeCryptoProvider::new(&FipsClaimingProvider, &FipsClaimingProvider).is_err());
+
+    // without the claim, ring's parts are fine
+    assert!(
+        CompositeCryptoProvider::new(rustls::crypto::ring::RING, &FipsClaimingProvider).is_ok()
+    );
+}
+
+#[cfg(not(feature = "quic"))]
+#[test]
+fn connection_fips_compliance_reflects_negotiated_group() {
+    let fips_suite = SupportedCipherSuite::Tls13(&FIPS_CLAIMING_TLS13_AES_128_GCM_SHA256);
+
+    let (mut client, mut server) = fips_claiming_pair(fips_suite, &FIPS_CLAIMING_SECP256R1);
+    assert!(!client.is_fips_compliant());
+    assert!(!server.is_fips_compliant());
+    do_handshake(&mut client, &mut server);
+    assert!(client.is_fips_compliant());
+    assert!(server.is_fips_compliant());
+
+    // the provider and suite claim FIPS, but the group does not
+    let (mut client, mut server) =
+        fips_claiming_pair(fips_suite, rustls::crypto::ring::kx_group::X25519);
+    do_handshake(&mut client, &mut server);
+    assert!(!client.is_fips_compliant());
+    assert!(!server.is_fips_compliant());
+
+    // likewise a non-FIPS suite
+    let (mut client, mut server) = fips_claiming_pair(
+        rustls::cipher_suite::TLS13_AES_128_GCM_SHA256,
+        &FIPS_CLAIMING_SECP256R1,
+    );
+    do_handshake(&mut client, &mut server);
+    assert!(!client.is_fips_compliant());
+    assert!(!server.is_fips_compliant());
+}
@@ -53,6 +53,7 @@ embed_files! {
     (ECDSA_END_FULLCHAIN, "ecdsa", "end.fullchain");
     (ECDSA_END_KEY, "ecdsa", "end.key");
     (ECDSA_END_REQ, "ecdsa", "end.req");
+    (ECDSA_END_SPKI, "ecdsa", "end.spki.der");
     (ECDSA_INTER_CERT, "ecdsa", "inter.cert");
     (ECDSA_INTER_KEY, "ecdsa", "inter.key");
     (ECDSA_INTER_REQ, "ecdsa", "inter.req");
@@ -73,6 +74,7 @@ embed_files! {
     (EDDSA_END_FULLCHAIN, "eddsa", "end.fullchain");
     (EDDSA_END_KEY, "eddsa", "end.key");
     (EDDSA_END_REQ, "eddsa", "end.req");
+    (EDDSA_END_SPKI, "eddsa", "end.spki.der");
     (EDDSA_INTER_CERT, "eddsa", "inter.cert");
     (EDDSA_INTER_KEY, "eddsa", "inter.key");
     (EDDSA_INTER_REQ, "eddsa", "inter.req");
@@ -92,6 +94,7 @@ embed_files! {
     (RSA_END_FULLCHAIN, "rsa", "end.fullchain");
     (RSA_END_KEY, "rsa", "end.key");
     (RSA_END_REQ, "rsa", "end.req");
+    (RSA_END_SPKI, "rsa", "end.spki.der");
     (RSA_END_RSA, "rsa", "end.rsa");
     (RSA_INTER_CERT, "rsa", "inter.cert");
     (RSA_INTER_KEY, "rsa", "inter.key");
@@ -205,6 +208,10 @@ impl KeyType {
             .collect()
     }
 
+    pub fn get_spki(&self) -> Vec<u8> {
+        self.bytes_for("end.spki.der").to_vec()
+    }
+
     pub fn get_key(&self) -> PrivateKeyDer<'static> {
         PrivateKeyDer::Pkcs8(
             rustls_pemfile::pkcs8_private_keys(&mut io::BufReader::new(self.bytes_for("end.key")))
@@ -229,7 +236,7 @@ impl KeyType {
         .unwrap()
     }
 
-    fn get_client_key(&self) -> PrivateKeyDer<'static> {
+    pub fn get_client_key(&self) -> PrivateKeyDer<'static> {
         PrivateKeyDer::Pkcs8(
             rustls_pemfile::pkcs8_private_keys(&mut io::BufReader::new(
                 self.bytes_for("client.key"),
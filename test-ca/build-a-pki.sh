@@ -200,8 +200,87 @@ for kt in rsa ecdsa eddsa ; do
   cat $kt/client.cert $kt/inter.cert $kt/ca.cert > $kt/client.fullchain
 
   openssl asn1parse -in $kt/ca.cert -out $kt/ca.der > /dev/null
+
+  openssl pkey -in $kt/end.key -pubout -outform DER -out $kt/end.spki.der
 done
 
+# SHA-1 signed variants of the RSA CA and intermediate, for testing that
+# such signatures are rejected.
+openssl req -x509 \
+          -new \
+          -key rsa/ca.key \
+          -out rsa/ca.sha1.cert \
+          -sha1 \
+          -days 3650 \
+          -batch \
+          -subj "/CN=ponytown RSA CA"
+
+openssl x509 -req \
+          -in rsa/inter.req \
+          -out rsa/inter.sha1.cert \
+          -CA rsa/ca.cert \
+          -CAkey rsa/ca.key \
+          -sha1 \
+          -days 3650 \
+          -set_serial 124 \
+          -extensions v3_inter -extfile openssl.cnf
+
+# Variants of the RSA end-entity and client certificates asserting a
+# certificate policy, for testing required policy enforcement.
+openssl x509 -req \
+          -in rsa/end.req \
+          -out rsa/end.policy.cert \
+          -CA rsa/inter.cert \
+          -CAkey rsa/inter.key \
+          -sha256 \
+          -days 2000 \
+          -set_serial 457 \
+          -extensions v3_end_policy -extfile openssl.cnf
+
+openssl x509 -req \
+          -in rsa/client.req \
+          -out rsa/client.policy.cert \
+          -CA rsa/inter.cert \
+          -CAkey rsa/inter.key \
+          -sha256 \
+          -days 2000 \
+          -set_serial 790 \
+          -extensions v3_client_policy -extfile openssl.cnf
+
+# A variant of the RSA end-entity certificate with a 21-octet serial
+# number, for testing serial number conformance checks.
+openssl x509 -req \
+          -in rsa/end.req \
+          -out rsa/end.longserial.cert \
+          -CA rsa/inter.cert \
+          -CAkey rsa/inter.key \
+          -sha256 \
+          -days 2000 \
+          -set_serial 0x0102030405060708090a0b0c0d0e0f101112131415 \
+          -extensions v3_end -extfile openssl.cnf
+
+# A delegated OCSP responder for the RSA intermediate, and OCSP responses
+# about the RSA end-entity certificate, for testing stapled OCSP validation.
+openssl req -nodes \
+          -newkey rsa:2048 \
+          -keyout rsa/ocsp.key \
+          -out rsa/ocsp.req \
+          -sha256 \
+          -batch \
+          -subj "/CN=ponytown RSA OCSP responder"
+
+openssl x509 -req \
+          -in rsa/ocsp.req \
+          -out rsa/ocsp.cert \
+          -CA rsa/inter.cert \
+          -CAkey rsa/inter.key \
+          -sha256 \
+          -days 2000 \
+          -set_serial 901 \
+          -extensions v3_ocsp -extfile openssl.cnf
+
+python3 make-ocsp-responses.py
+
 # Tidy up openssl CA state.
 rm index.txt* || true
 rm crlnumber* || true
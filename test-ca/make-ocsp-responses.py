@@ -0,0 +1,97 @@
+# Make OCSP responses about the RSA end-entity certificate, for testing
+# stapled OCSP validation.  `openssl ocsp` cannot set thisUpdate and
+# nextUpdate, so the tests could not know them: these responses are all
+# valid from THIS_UPDATE to NEXT_UPDATE.  Run from build-a-pki.sh, once the
+# RSA chain and OCSP responder certificates exist.  The responder certificate
+# must be valid at THIS_UPDATE.
+
+import datetime
+from cryptography import x509
+from cryptography.hazmat.primitives import hashes, serialization
+from cryptography.x509 import ocsp
+
+THIS_UPDATE = datetime.datetime(2026, 11, 1, tzinfo=datetime.timezone.utc)
+NEXT_UPDATE = THIS_UPDATE + datetime.timedelta(days=7)
+REVOKED_AT = THIS_UPDATE - datetime.timedelta(days=1)
+
+
+def cert(name):
+    with open("rsa/%s.cert" % name, "rb") as f:
+        return x509.load_pem_x509_certificate(f.read())
+
+
+def key(name):
+    with open("rsa/%s.key" % name, "rb") as f:
+        return serialization.load_pem_private_key(f.read(), password=None)
+
+
+inter = cert("inter")
+
+
+def named(name, signer):
+    """A throwaway certificate with the subject of `name` and the key of
+    `signer`: `cryptography` will only name the signer as the responder."""
+    if name == signer:
+        return cert(name)
+    signer_key = key(signer)
+    return (
+        x509.CertificateBuilder()
+        .subject_name(cert(name).subject)
+        .issuer_name(cert(name).subject)
+        .public_key(signer_key.public_key())
+        .serial_number(1)
+        .not_valid_before(THIS_UPDATE)
+        .not_valid_after(NEXT_UPDATE)
+        .sign(signer_key, hashes.SHA256())
+    )
+
+
+def respond(
+    out,
+    subject="end",
+    signer="inter",
+    responder=None,
+    by=ocsp.OCSPResponderEncoding.NAME,
+    include_signer=False,
+    cert_id_hash=hashes.SHA1(),
+    status=ocsp.OCSPCertStatus.GOOD,
+    next_update=NEXT_UPDATE,
+):
+    revoked = status == ocsp.OCSPCertStatus.REVOKED
+    builder = (
+        ocsp.OCSPResponseBuilder()
+        .add_response(
+            cert=cert(subject),
+            issuer=inter,
+            algorithm=cert_id_hash,
+            cert_status=status,
+            this_update=THIS_UPDATE,
+            next_update=next_update,
+            revocation_time=REVOKED_AT if revoked else None,
+            revocation_reason=x509.ReasonFlags.key_compromise if revoked else None,
+        )
+        .responder_id(by, named(responder or signer, signer))
+    )
+    if include_signer:
+        builder = builder.certificates([cert(signer)])
+    response = builder.sign(key(signer), hashes.SHA256())
+    with open("rsa/end.ocsp.%s.der" % out, "wb") as f:
+        f.write(response.public_bytes(serialization.Encoding.DER))
+
+
+# Signed by the issuer, or by a delegated responder it authorised.
+respond("good")
+respond("sha256", cert_id_hash=hashes.SHA256(), by=ocsp.OCSPResponderEncoding.HASH)
+respond("delegated", signer="ocsp", include_signer=True)
+respond("revoked", status=ocsp.OCSPCertStatus.REVOKED)
+
+# About another certificate from the same issuer.
+respond("wrongserial", subject="client")
+# A delegated responder must have the OCSPSigning extended key usage: the
+# end-entity certificate, vouching for itself, does not.
+respond("unauthorized", signer="end", include_signer=True)
+# Naming the issuer, but signed by the responder's key.
+respond("forged", signer="ocsp", responder="inter")
+# Signed by the issuer, but naming the responder.
+respond("wrongresponder", responder="ocsp")
+respond("nonextupdate", next_update=None)